@@ -0,0 +1,5 @@
+-- 既存の todo には所有者がいないので NULL を許容する
+-- user_id が NULL の todo はどのユーザーからも見えない扱いにする
+ALTER TABLE todos ADD COLUMN user_id INTEGER REFERENCES users (id);
+
+CREATE INDEX todos_user_id_idx ON todos (user_id);
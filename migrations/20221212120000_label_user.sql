@@ -0,0 +1,4 @@
+-- 既存のラベルには所有者がいないので NULL を許容する (共有ラベル扱い)
+ALTER TABLE labels ADD COLUMN user_id INTEGER REFERENCES users (id);
+
+CREATE INDEX labels_user_id_idx ON labels (user_id);
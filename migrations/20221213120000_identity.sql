@@ -0,0 +1,12 @@
+-- 外部 IdP (Google, GitHub) でログインするユーザーはパスワードを持たない
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+CREATE TABLE identities (
+    id         SERIAL PRIMARY KEY,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider   TEXT NOT NULL,
+    -- IdP 側のユーザー識別子 (Google の sub, GitHub の user id)
+    subject    TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, subject)
+);
@@ -0,0 +1,24 @@
+CREATE TYPE membership_role AS ENUM ('owner', 'member');
+
+CREATE TABLE workspaces (
+    id         SERIAL PRIMARY KEY,
+    name       TEXT NOT NULL,
+    owner_id   INTEGER NOT NULL REFERENCES users (id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE memberships (
+    id           SERIAL PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    user_id      INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role         membership_role NOT NULL DEFAULT 'member',
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (workspace_id, user_id)
+);
+
+-- workspace_id が NULL の todo / label は個人のもの
+ALTER TABLE todos ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id);
+ALTER TABLE labels ADD COLUMN workspace_id INTEGER REFERENCES workspaces (id);
+
+CREATE INDEX todos_workspace_id_idx ON todos (workspace_id);
+CREATE INDEX labels_workspace_id_idx ON labels (workspace_id);
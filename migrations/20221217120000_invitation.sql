@@ -0,0 +1,13 @@
+CREATE TABLE invitations (
+    id           SERIAL PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    email        TEXT NOT NULL,
+    invited_by   INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- トークンそのものではなく sha256 のハッシュを保存する
+    token_hash   TEXT NOT NULL UNIQUE,
+    expires_at   TIMESTAMPTZ NOT NULL,
+    accepted_at  TIMESTAMPTZ,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX invitations_workspace_id_idx ON invitations (workspace_id);
@@ -0,0 +1,12 @@
+CREATE TYPE share_permission AS ENUM ('read', 'write');
+
+CREATE TABLE todo_shares (
+    id         SERIAL PRIMARY KEY,
+    todo_id    INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    permission share_permission NOT NULL DEFAULT 'read',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (todo_id, user_id)
+);
+
+CREATE INDEX todo_shares_user_id_idx ON todo_shares (user_id);
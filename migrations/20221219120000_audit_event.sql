@@ -0,0 +1,17 @@
+CREATE TYPE audit_action AS ENUM ('create', 'update', 'delete');
+CREATE TYPE audit_entity AS ENUM ('todo', 'label');
+
+CREATE TABLE audit_events (
+    id         SERIAL PRIMARY KEY,
+    -- ユーザーが削除されても記録は残す
+    actor_id   INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    action     audit_action NOT NULL,
+    entity     audit_entity NOT NULL,
+    entity_id  INTEGER NOT NULL,
+    before     JSONB,
+    after      JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_entity_idx ON audit_events (entity, entity_id);
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
@@ -0,0 +1,4 @@
+-- 管理者が無効化したユーザーはログインできない
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ;
+-- true のユーザーはパスワードを変更するまでパスワードでログインできない
+ALTER TABLE users ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT false;
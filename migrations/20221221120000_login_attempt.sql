@@ -0,0 +1,10 @@
+-- ログインの失敗を記録する. 存在しないメールアドレスへの試行も数えるので users は参照しない
+CREATE TABLE login_attempts (
+    id           SERIAL PRIMARY KEY,
+    email        TEXT NOT NULL,
+    ip           TEXT,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_attempts_email_idx ON login_attempts (email, attempted_at);
+CREATE INDEX login_attempts_ip_idx ON login_attempts (ip, attempted_at);
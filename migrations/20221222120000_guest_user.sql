@@ -0,0 +1,3 @@
+-- ゲストユーザーはパスワードを持たず、端末に保存したトークンで識別する
+ALTER TABLE users ADD COLUMN guest BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN device_token_hash TEXT UNIQUE;
@@ -0,0 +1,4 @@
+ALTER TABLE todos ADD COLUMN due_at TIMESTAMPTZ;
+
+-- 期限付きの todo だけを対象にした期限順の検索用
+CREATE INDEX todos_due_at_idx ON todos (due_at) WHERE due_at IS NOT NULL;
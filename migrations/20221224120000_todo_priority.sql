@@ -0,0 +1,2 @@
+ALTER TABLE todos ADD COLUMN priority TEXT NOT NULL DEFAULT 'medium'
+    CONSTRAINT todos_priority_check CHECK (priority IN ('low', 'medium', 'high', 'urgent'));
@@ -0,0 +1,4 @@
+-- 親の todo が削除されたら、子はトップレベルの todo として残す
+ALTER TABLE todos ADD COLUMN parent_id INTEGER REFERENCES todos (id) ON DELETE SET NULL;
+
+CREATE INDEX todos_parent_id_idx ON todos (parent_id);
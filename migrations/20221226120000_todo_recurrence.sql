@@ -0,0 +1,13 @@
+-- recurrence_freq が NULL なら繰り返さない
+ALTER TABLE todos
+    ADD COLUMN recurrence_freq TEXT
+        CONSTRAINT todos_recurrence_freq_check CHECK (recurrence_freq IN ('daily', 'weekly', 'monthly')),
+    ADD COLUMN recurrence_interval INTEGER NOT NULL DEFAULT 1
+        CONSTRAINT todos_recurrence_interval_check CHECK (recurrence_interval > 0),
+    ADD COLUMN recurrence_until TIMESTAMPTZ,
+    -- 完了後に次の todo を作成した日時. 同じ todo から二度作らないための印
+    ADD COLUMN recurred_at TIMESTAMPTZ;
+
+-- scheduler が次の todo を作る対象
+CREATE INDEX todos_recurrence_pending_idx ON todos (id)
+    WHERE recurrence_freq IS NOT NULL AND completed AND recurred_at IS NULL;
@@ -0,0 +1,17 @@
+-- todo の通知. 通知先は設定したユーザー自身
+CREATE TABLE reminders (
+    id         SERIAL PRIMARY KEY,
+    todo_id    INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    remind_at  TIMESTAMPTZ NOT NULL,
+    channel    TEXT NOT NULL DEFAULT 'log'
+        CONSTRAINT reminders_channel_check CHECK (channel IN ('log', 'email', 'webhook')),
+    -- channel が webhook のときの送信先
+    url        TEXT,
+    sent_at    TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX reminders_todo_id_idx ON reminders (todo_id);
+-- worker が送信する対象
+CREATE INDEX reminders_pending_idx ON reminders (remind_at) WHERE sent_at IS NULL;
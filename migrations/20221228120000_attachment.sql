@@ -0,0 +1,14 @@
+-- 添付ファイルのメタデータ. 中身は BlobStore の storage_key に保存する
+CREATE TABLE attachments (
+    id           SERIAL PRIMARY KEY,
+    todo_id      INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    -- アップロードしたユーザー
+    user_id      INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    file_name    TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size         BIGINT NOT NULL,
+    storage_key  TEXT NOT NULL UNIQUE,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX attachments_todo_id_idx ON attachments (todo_id);
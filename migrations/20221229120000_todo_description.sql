@@ -0,0 +1,2 @@
+-- markdown の詳細説明. 表示用の HTML は保存せず、取得時に変換する
+ALTER TABLE todos ADD COLUMN description TEXT;
@@ -0,0 +1 @@
+ALTER TABLE todos ADD COLUMN archived_at TIMESTAMPTZ;
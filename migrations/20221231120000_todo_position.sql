@@ -0,0 +1,3 @@
+-- 手動での並び順. 新しい todo ほど後ろになるよう sequence から採番し、間に入れられるよう間隔を空ける
+CREATE SEQUENCE todos_position_seq;
+ALTER TABLE todos ADD COLUMN position BIGINT NOT NULL DEFAULT nextval('todos_position_seq') * 1024;
@@ -0,0 +1,12 @@
+-- completed を status に置き換える. 完了済みの todo は done, それ以外は backlog にする
+ALTER TABLE todos
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'backlog'
+        CONSTRAINT todos_status_check CHECK (status IN ('backlog', 'in_progress', 'done', 'cancelled'));
+
+UPDATE todos SET status = 'done' WHERE completed;
+
+-- completed を使っている todos_recurrence_pending_idx も一緒に消えるので作り直す
+ALTER TABLE todos DROP COLUMN completed;
+
+CREATE INDEX todos_recurrence_pending_idx ON todos (id)
+    WHERE recurrence_freq IS NOT NULL AND status IN ('done', 'cancelled') AND recurred_at IS NULL;
@@ -0,0 +1,18 @@
+-- todo をまとめるプロジェクト (ボード). workspace_id が NULL なら個人のもの
+CREATE TABLE projects (
+    id           SERIAL PRIMARY KEY,
+    name         TEXT NOT NULL,
+    user_id      INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    workspace_id INTEGER REFERENCES workspaces (id),
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX projects_user_id_idx ON projects (user_id);
+CREATE INDEX projects_workspace_id_idx ON projects (workspace_id);
+
+-- プロジェクトを消しても todo は残す. todo ごと消すかどうかは handler で選ぶ
+ALTER TABLE todos ADD COLUMN project_id INTEGER REFERENCES projects (id) ON DELETE SET NULL;
+
+CREATE INDEX todos_project_id_idx ON todos (project_id);
+
+ALTER TYPE audit_entity ADD VALUE 'project';
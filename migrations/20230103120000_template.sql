@@ -0,0 +1,26 @@
+-- todo のひな形. workspace_id が NULL なら個人のもの
+-- subtasks はテンプレートから作るときにサブタスクにする text の一覧
+CREATE TABLE templates (
+    id           SERIAL PRIMARY KEY,
+    name         TEXT NOT NULL,
+    text         TEXT NOT NULL,
+    description  TEXT,
+    priority     TEXT NOT NULL DEFAULT 'medium'
+        CHECK (priority IN ('low', 'medium', 'high', 'urgent')),
+    subtasks     TEXT[] NOT NULL DEFAULT '{}',
+    user_id      INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    workspace_id INTEGER REFERENCES workspaces (id),
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX templates_user_id_idx ON templates (user_id);
+CREATE INDEX templates_workspace_id_idx ON templates (workspace_id);
+
+-- ラベルを消したらテンプレートからも外す
+CREATE TABLE template_labels (
+    template_id INTEGER NOT NULL REFERENCES templates (id) ON DELETE CASCADE,
+    label_id    INTEGER NOT NULL REFERENCES labels (id) ON DELETE CASCADE,
+    PRIMARY KEY (template_id, label_id)
+);
+
+ALTER TYPE audit_entity ADD VALUE 'template';
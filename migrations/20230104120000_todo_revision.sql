@@ -0,0 +1,12 @@
+-- todo を更新するたびに、変わったフィールドの前後の値を残す
+-- changes は { "text": { "before": "old", "after": "new" }, ... } の形
+CREATE TABLE todo_revisions (
+    id         SERIAL PRIMARY KEY,
+    todo_id    INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+    -- ユーザーが削除されても履歴は残す
+    actor_id   INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    changes    JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX todo_revisions_todo_id_idx ON todo_revisions (todo_id);
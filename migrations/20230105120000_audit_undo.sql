@@ -0,0 +1,6 @@
+-- undo したときの記録に、取り消した記録の id を残す
+-- undo_of を持つ記録と、他の記録の undo_of から参照されている記録はもう undo できない
+ALTER TABLE audit_events ADD COLUMN undo_of INTEGER REFERENCES audit_events (id) ON DELETE SET NULL;
+
+CREATE INDEX audit_events_undo_of_idx ON audit_events (undo_of);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, created_at);
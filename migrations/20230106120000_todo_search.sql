@@ -0,0 +1,8 @@
+-- 全文検索用. text の一致を description の一致より上位にする
+-- 語形変化のない言語でもそのまま検索できるように simple 設定を使う
+ALTER TABLE todos ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', text), 'A')
+        || setweight(to_tsvector('simple', COALESCE(description, '')), 'B')
+) STORED;
+
+CREATE INDEX todos_search_vector_idx ON todos USING GIN (search_vector);
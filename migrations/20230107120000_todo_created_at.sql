@@ -0,0 +1,4 @@
+-- 作成日時での絞り込み・並び替え用. 既存の todo は追加した時点の日時になる
+ALTER TABLE todos ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX todos_created_at_idx ON todos (created_at);
@@ -0,0 +1,14 @@
+-- Idempotency-Key を付けたリクエストの記録. 期限内に同じ key で再送されたら保存したレスポンスを返す
+-- response_status が NULL の間は最初のリクエストを処理中
+CREATE TABLE idempotency_keys (
+    user_id         INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    key             TEXT NOT NULL,
+    fingerprint     TEXT NOT NULL,
+    response_status INTEGER,
+    response_body   JSONB,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at      TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX idempotency_keys_expires_at_idx ON idempotency_keys (expires_at);
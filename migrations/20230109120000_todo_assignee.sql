@@ -0,0 +1,4 @@
+-- todo の担当者. ユーザーを消したら担当を外す
+ALTER TABLE todos ADD COLUMN assignee_id INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX todos_assignee_id_idx ON todos (assignee_id);
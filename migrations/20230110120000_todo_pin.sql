@@ -0,0 +1,2 @@
+-- ピン留めした todo は並び順を指定しない一覧で先頭に並ぶ
+ALTER TABLE todos ADD COLUMN is_pinned BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- スヌーズした todo は snoozed_until を過ぎるまで既定の一覧に出さない
+ALTER TABLE todos ADD COLUMN snoozed_until TIMESTAMPTZ;
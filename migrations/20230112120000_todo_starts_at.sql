@@ -0,0 +1,2 @@
+-- 開始日時が未来の todo は既定の一覧に出さない
+ALTER TABLE todos ADD COLUMN starts_at TIMESTAMPTZ;
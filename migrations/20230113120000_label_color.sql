@@ -0,0 +1,6 @@
+-- ラベルの色 (#rrggbb). 既存のラベルには id ごとにパレットの色を順に割り当てる
+ALTER TABLE labels ADD COLUMN color TEXT NOT NULL DEFAULT '#4f86f7'
+    CHECK (color ~ '^#[0-9A-Fa-f]{6}$');
+UPDATE labels SET color = (ARRAY[
+    '#4f86f7', '#e5484d', '#30a46c', '#f5a524', '#8e4ec6', '#12a594', '#d6409f', '#6e56cf'
+])[id % 8 + 1];
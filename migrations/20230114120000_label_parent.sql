@@ -0,0 +1,3 @@
+-- 入れ子のラベル. 親を削除したら子はトップレベルのラベルとして残す
+ALTER TABLE labels ADD COLUMN parent_id INTEGER REFERENCES labels (id) ON DELETE SET NULL;
+CREATE INDEX labels_parent_id_idx ON labels (parent_id);
@@ -0,0 +1,3 @@
+-- 手動での並び順. 一覧 (個人 or workspace) の中で小さい順に並べる. 既存のラベルは作成順のまま
+ALTER TABLE labels ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+UPDATE labels SET position = id;
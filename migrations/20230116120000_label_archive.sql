@@ -0,0 +1 @@
+ALTER TABLE labels ADD COLUMN archived_at TIMESTAMPTZ;
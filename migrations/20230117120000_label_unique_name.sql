@@ -0,0 +1,8 @@
+-- ラベル名は user ごとに大文字と小文字を区別せず一意にする
+-- 既に重なっている名前は、最も古いラベル以外の名前に id を付けて区別する
+UPDATE labels SET name = name || ' (' || id || ')'
+WHERE EXISTS (
+    SELECT 1 FROM labels older
+    WHERE older.user_id = labels.user_id AND lower(older.name) = lower(labels.name) AND older.id < labels.id
+);
+CREATE UNIQUE INDEX labels_user_id_name_idx ON labels (user_id, lower(name));
@@ -0,0 +1,2 @@
+-- ラベルのアイコン. 絵文字 1 文字か、フロントエンドのアイコン名 (briefcase など)
+ALTER TABLE labels ADD COLUMN icon TEXT;
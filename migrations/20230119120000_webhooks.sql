@@ -0,0 +1,36 @@
+-- todo / label の変更を外部に POST する購読. 見られるのは登録したユーザーだけ
+CREATE TABLE webhooks (
+    id            SERIAL PRIMARY KEY,
+    user_id       INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- NULL なら個人の todo / label の変更を送る
+    workspace_id  INTEGER REFERENCES workspaces (id) ON DELETE CASCADE,
+    url           TEXT NOT NULL,
+    -- 署名に使う鍵. 受け取る側でも検証するので平文で持つ
+    secret        TEXT NOT NULL,
+    -- 空なら全ての event を送る
+    events        TEXT[] NOT NULL DEFAULT '{}',
+    -- この id までの audit_events は配送待ちに展開済み
+    last_event_id INTEGER NOT NULL DEFAULT 0,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+CREATE TABLE webhook_deliveries (
+    id              SERIAL PRIMARY KEY,
+    webhook_id      INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event           TEXT NOT NULL,
+    payload         JSONB NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at    TIMESTAMPTZ,
+    -- 再試行を諦めた日時
+    failed_at       TIMESTAMPTZ,
+    last_error      TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id);
+-- worker が送信する対象
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
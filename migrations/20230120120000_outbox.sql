@@ -0,0 +1,49 @@
+-- todo / label の変更を、変更と同じトランザクションで記録する (transactional outbox)
+-- trigger で書くので、handler を通らない変更 (scheduler の繰り返しや一括操作) も漏れない
+-- relay が event bus に送り終えたら消す
+CREATE TABLE outbox_events (
+    id            BIGSERIAL PRIMARY KEY,
+    -- todo.created, label.deleted など. webhook の event と同じ名前
+    event         TEXT NOT NULL,
+    entity_id     INTEGER NOT NULL,
+    user_id       INTEGER,
+    workspace_id  INTEGER,
+    -- 変更後の行. 削除なら削除前の行
+    data          JSONB NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- relay が取り出した. 送り終える前に落ちたら、この時刻を過ぎてから別の relay が送り直す
+    claimed_until TIMESTAMPTZ
+);
+
+CREATE FUNCTION record_outbox_event() RETURNS trigger AS $$
+DECLARE
+    row_data JSONB;
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD IS NOT DISTINCT FROM NEW THEN
+        RETURN NULL;
+    END IF;
+    IF TG_OP = 'DELETE' THEN
+        row_data := to_jsonb(OLD);
+    ELSE
+        row_data := to_jsonb(NEW);
+    END IF;
+    INSERT INTO outbox_events (event, entity_id, user_id, workspace_id, data)
+    VALUES (
+        TG_ARGV[0] || '.' || CASE TG_OP
+            WHEN 'INSERT' THEN 'created'
+            WHEN 'UPDATE' THEN 'updated'
+            ELSE 'deleted'
+        END,
+        (row_data ->> 'id')::INTEGER,
+        (row_data ->> 'user_id')::INTEGER,
+        (row_data ->> 'workspace_id')::INTEGER,
+        row_data
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER todos_outbox AFTER INSERT OR UPDATE OR DELETE ON todos
+    FOR EACH ROW EXECUTE FUNCTION record_outbox_event('todo');
+CREATE TRIGGER labels_outbox AFTER INSERT OR UPDATE OR DELETE ON labels
+    FOR EACH ROW EXECUTE FUNCTION record_outbox_event('label');
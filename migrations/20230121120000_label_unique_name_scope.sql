@@ -0,0 +1,10 @@
+-- ラベル名の一意性を一覧ごとにする. 個人のラベルは user ごと、workspace のラベルは workspace ごと
+-- workspace の中で既に重なっている名前は、最も古いラベル以外の名前に id を付けて区別する
+DROP INDEX labels_user_id_name_idx;
+UPDATE labels SET name = name || ' (' || id || ')'
+WHERE workspace_id IS NOT NULL AND EXISTS (
+    SELECT 1 FROM labels older
+    WHERE older.workspace_id = labels.workspace_id AND lower(older.name) = lower(labels.name) AND older.id < labels.id
+);
+CREATE UNIQUE INDEX labels_user_id_name_idx ON labels (user_id, lower(name)) WHERE workspace_id IS NULL;
+CREATE UNIQUE INDEX labels_workspace_id_name_idx ON labels (workspace_id, lower(name)) WHERE workspace_id IS NOT NULL;
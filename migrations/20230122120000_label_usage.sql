@@ -0,0 +1,16 @@
+-- GET /labels/stats の件数. リクエストごとに数えず、jobs.refresh_label_stats の時刻に数え直す
+-- 一覧 (個人 or workspace) ごとに引けるよう、todo の user_id と workspace_id ごとに数える
+CREATE MATERIALIZED VIEW label_usage AS
+SELECT todo_labels.label_id,
+    todos.user_id,
+    todos.workspace_id,
+    COUNT(*) FILTER (WHERE todos.status NOT IN ('done', 'cancelled')) AS open,
+    COUNT(*) FILTER (WHERE todos.status = 'done') AS completed
+FROM todo_labels
+    INNER JOIN todos ON todos.id = todo_labels.todo_id
+WHERE todos.archived_at IS NULL
+GROUP BY todo_labels.label_id, todos.user_id, todos.workspace_id;
+
+-- REFRESH MATERIALIZED VIEW CONCURRENTLY には一意なインデックスが要る
+CREATE UNIQUE INDEX label_usage_label_id_user_id_workspace_id_idx
+    ON label_usage (label_id, user_id, workspace_id);
@@ -0,0 +1,45 @@
+-- webhook の配送は audit_events の id の範囲ではなく、outbox の relay が渡した event から作る
+-- id の範囲だと、先に採番されて後からコミットされた変更を取りこぼす
+ALTER TABLE webhooks DROP COLUMN last_event_id;
+
+-- 配送の元になった outbox の event. relay が同じ event を何度渡しても配送は 1 つ
+-- outbox の行は送り終えたら消えるので外部キーにはしない
+ALTER TABLE webhook_deliveries ADD COLUMN outbox_event_id BIGINT;
+CREATE UNIQUE INDEX webhook_deliveries_webhook_id_outbox_event_id_idx
+    ON webhook_deliveries (webhook_id, outbox_event_id);
+
+-- 更新なら変更前の行. webhook の previous に入れる
+ALTER TABLE outbox_events ADD COLUMN previous JSONB;
+
+CREATE OR REPLACE FUNCTION record_outbox_event() RETURNS trigger AS $$
+DECLARE
+    row_data JSONB;
+    previous JSONB;
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD IS NOT DISTINCT FROM NEW THEN
+        RETURN NULL;
+    END IF;
+    IF TG_OP = 'DELETE' THEN
+        row_data := to_jsonb(OLD);
+    ELSE
+        row_data := to_jsonb(NEW);
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        previous := to_jsonb(OLD);
+    END IF;
+    INSERT INTO outbox_events (event, entity_id, user_id, workspace_id, data, previous)
+    VALUES (
+        TG_ARGV[0] || '.' || CASE TG_OP
+            WHEN 'INSERT' THEN 'created'
+            WHEN 'UPDATE' THEN 'updated'
+            ELSE 'deleted'
+        END,
+        (row_data ->> 'id')::INTEGER,
+        (row_data ->> 'user_id')::INTEGER,
+        (row_data ->> 'workspace_id')::INTEGER,
+        row_data,
+        previous
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+-- 購読用トークンに載せる版. 上げるとそれまでに発行したトークンは全て使えなくなる
+ALTER TABLE users ADD COLUMN feed_token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,152 @@
+use axum::{
+    body::Body,
+    http::{
+        header::{ACCEPT, VARY},
+        HeaderMap, HeaderValue, Request, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+use tower::ServiceExt;
+
+// バージョンを付けずに呼ばれたときの版. 付けない呼び方は廃止予定
+pub const DEFAULT_VERSION: u32 = 1;
+// 提供している版. /v1, /v2 のようにパスの先頭に付ける
+pub const VERSIONS: [u32; 2] = [1, 2];
+// バージョンを付けずに呼んだレスポンスに付ける. 移行先はパスの先頭に /v1 を付けたもの
+// Link ヘッダーはページ送りに使っているので、移行先は載せない
+pub const DEPRECATION_HEADER: &str = "deprecation";
+// Accept: application/vnd.rust-web.v2+json のように版を指定する
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.rust-web.v";
+const MEDIA_TYPE_SUFFIX: &str = "+json";
+
+// リクエストの版. パスの /v1, /v2 か Accept で決まる. negotiate_version が request の extensions に入れる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion(pub u32);
+
+impl ApiVersion {
+    pub fn prefix(&self) -> String {
+        format!("/v{}", self.0)
+    }
+}
+
+// パスと Accept から ApiVersion を決める. 提供していない版は route_unversioned が 406 にするので、ここでは DEFAULT_VERSION
+pub async fn negotiate_version<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let version = version_in_path(req.uri().path())
+        .or_else(|| requested_version(req.headers()).ok().flatten())
+        .unwrap_or(DEFAULT_VERSION);
+    req.extensions_mut().insert(ApiVersion(version));
+    next.run(req).await
+}
+
+// /v2/todos の 2. 版が付いていなければ None
+fn version_in_path(path: &str) -> Option<u32> {
+    let rest = path.strip_prefix("/v")?;
+    let (version, _) = rest.split_once('/').unwrap_or((rest, ""));
+    version
+        .parse()
+        .ok()
+        .filter(|version| VERSIONS.contains(version))
+}
+
+// 版を除いたパス. /v2/todos なら /todos、/todos はそのまま
+pub fn unversioned_path(path: &str) -> &str {
+    match version_in_path(path) {
+        Some(version) => &path[format!("/v{}", version).len()..],
+        None => path,
+    }
+}
+
+// Accept で指定された版. 指定がなければ None、提供していない版なら 406
+pub fn requested_version(headers: &HeaderMap) -> Result<Option<u32>, StatusCode> {
+    let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+        return Ok(None);
+    };
+    for range in accept.split(',') {
+        let media_type = range.split(';').next().unwrap_or_default().trim();
+        let Some(version) = media_type
+            .strip_prefix(MEDIA_TYPE_PREFIX)
+            .and_then(|rest| rest.strip_suffix(MEDIA_TYPE_SUFFIX))
+        else {
+            continue;
+        };
+        return match version.parse::<u32>() {
+            Ok(version) if VERSIONS.contains(&version) => Ok(Some(version)),
+            _ => Err(StatusCode::NOT_ACCEPTABLE),
+        };
+    }
+    Ok(None)
+}
+
+// バージョンの付いていないパスを、Accept で指定された版か DEFAULT_VERSION のパスとして処理する
+// versioned には /v1, /v2 を nest した router を渡す
+pub async fn route_unversioned(versioned: Router, mut req: Request<Body>) -> Response {
+    let version = match requested_version(req.headers()) {
+        Ok(version) => version,
+        Err(status) => return status.into_response(),
+    };
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    let uri = format!("/v{}{}", version.unwrap_or(DEFAULT_VERSION), path_and_query);
+    let Ok(uri) = uri.parse::<Uri>() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    *req.uri_mut() = uri;
+
+    let mut res = versioned.oneshot(req).await.unwrap_or_else(|e| match e {});
+    let headers = res.headers_mut();
+    headers.append(VARY, HeaderValue::from_static("accept"));
+    if version.is_none() {
+        headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_read_version_from_accept() {
+        let headers = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_str(accept).unwrap());
+            headers
+        };
+        assert_eq!(requested_version(&HeaderMap::new()), Ok(None));
+        assert_eq!(
+            requested_version(&headers("application/json, */*")),
+            Ok(None)
+        );
+        assert_eq!(
+            requested_version(&headers("application/vnd.rust-web.v2+json")),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            requested_version(&headers(
+                "text/html, application/vnd.rust-web.v1+json; q=0.9"
+            )),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            requested_version(&headers("application/vnd.rust-web.v3+json")),
+            Err(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            requested_version(&headers("application/vnd.rust-web.vx+json")),
+            Err(StatusCode::NOT_ACCEPTABLE)
+        );
+    }
+
+    #[test]
+    fn should_strip_version_from_path() {
+        assert_eq!(version_in_path("/v2/todos"), Some(2));
+        assert_eq!(version_in_path("/v9/todos"), None);
+        assert_eq!(version_in_path("/todos"), None);
+        assert_eq!(unversioned_path("/v1/todos/1"), "/todos/1");
+        assert_eq!(unversioned_path("/videos"), "/videos");
+    }
+}
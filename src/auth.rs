@@ -0,0 +1,367 @@
+use crate::repositories::{user::Role, Scope};
+use crate::state::AppState;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequest, Path, RequestParts},
+    http::{header::AUTHORIZATION, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// アクセストークンの有効期限 (秒)
+// 無効化やロールの変更は require_auth が毎回反映する. 延長はリフレッシュトークンで行う
+const TOKEN_LIFETIME_SECS: u64 = 60 * 15;
+// リフレッシュトークンの有効期限 (日)
+pub const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 30;
+// アクティブな workspace を指定するヘッダー
+pub const WORKSPACE_HEADER: &str = "x-workspace-id";
+// カレンダーやフィードの購読用トークンの aud. アクセストークンとしては使えない
+const FEED_AUDIENCE: &str = "feed";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Claims {
+    pub sub: i32,
+    pub role: Role,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+// カレンダーアプリやフィードリーダーは Authorization ヘッダーを送れないので、URL に載せる購読用トークンの claims
+// 購読し続けられるように有効期限は付けない. ver がユーザーの feed_token_version と違えば使えない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FeedClaims {
+    pub sub: i32,
+    // workspace のカレンダーやフィードなら workspace の id
+    pub workspace_id: Option<i32>,
+    pub aud: String,
+    pub iat: u64,
+    // 版を入れる前に発行したトークンは 0 として扱う
+    #[serde(default)]
+    pub ver: i32,
+}
+
+// 認証済みのリクエストに middleware が差し込むユーザー情報
+// handler 側では Extension<CurrentUser> として受け取る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentUser {
+    pub id: i32,
+    pub role: Role,
+}
+
+impl CurrentUser {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+// resolve_workspace が差し込むアクティブな workspace. None なら個人のデータを扱う
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActiveWorkspace(pub Option<i32>);
+
+impl ActiveWorkspace {
+    pub fn scope(&self, user: CurrentUser) -> Scope {
+        Scope::new(user.id, self.0)
+    }
+}
+
+// handler の引数に RequireRole<Admin> のように書くと、そのロールを持たないユーザーは 403 になる
+pub trait RoleRequirement {
+    fn allows(role: Role) -> bool;
+}
+
+pub struct Admin;
+
+impl RoleRequirement for Admin {
+    fn allows(role: Role) -> bool {
+        role == Role::Admin
+    }
+}
+
+pub struct RequireRole<R: RoleRequirement>(pub CurrentUser, pub PhantomData<R>);
+
+#[async_trait]
+impl<R, B> FromRequest<B> for RequireRole<R>
+where
+    R: RoleRequirement,
+    B: Send,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        // require_auth が差し込んだ CurrentUser を見る
+        let user = req
+            .extensions()
+            .get::<CurrentUser>()
+            .copied()
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if !R::allows(user.role) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(RequireRole(user, PhantomData))
+    }
+}
+
+#[derive(Clone)]
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn issue(&self, user_id: i32, role: Role) -> anyhow::Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = Claims {
+            sub: user_id,
+            role,
+            iat: now,
+            exp: now + TOKEN_LIFETIME_SECS,
+        };
+        Ok(encode(&Header::default(), &claims, &self.encoding)?)
+    }
+
+    pub fn verify(&self, token: &str) -> anyhow::Result<Claims> {
+        let data = decode::<Claims>(token, &self.decoding, &Validation::default())?;
+        Ok(data.claims)
+    }
+
+    pub fn issue_feed(
+        &self,
+        user_id: i32,
+        workspace_id: Option<i32>,
+        version: i32,
+    ) -> anyhow::Result<String> {
+        let claims = FeedClaims {
+            sub: user_id,
+            workspace_id,
+            aud: FEED_AUDIENCE.to_string(),
+            iat: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            ver: version,
+        };
+        Ok(encode(&Header::default(), &claims, &self.encoding)?)
+    }
+
+    pub fn verify_feed(&self, token: &str) -> anyhow::Result<FeedClaims> {
+        let mut validation = Validation::default();
+        validation.validate_exp = false;
+        validation.set_required_spec_claims(&["aud"]);
+        validation.set_audience(&[FEED_AUDIENCE]);
+        let data = decode::<FeedClaims>(token, &self.decoding, &validation)?;
+        Ok(data.claims)
+    }
+}
+
+// リフレッシュトークンや招待トークンは推測できない十分な長さのランダム文字列にする
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+// DB にはトークンそのものではなくハッシュを保存する
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// Authorization: Bearer <token> を検証して CurrentUser をリクエストに差し込む
+// 発行した後の無効化、削除、ロールの変更を次のリクエストから反映するので、ロールは claims ではなく users から読む
+// JwtKeys と AppState は create_app で Extension として登録されている前提
+pub async fn require_auth<B>(mut req: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    let keys = req
+        .extensions()
+        .get::<JwtKeys>()
+        .cloned()
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let state = req
+        .extensions()
+        .get::<AppState>()
+        .cloned()
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = keys.verify(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let user = state
+        .user
+        .find(claims.sub)
+        .await
+        .or(Err(StatusCode::UNAUTHORIZED))?;
+    if user.is_disabled() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    req.extensions_mut().insert(CurrentUser {
+        id: user.id,
+        role: user.role,
+    });
+    Ok(next.run(req).await)
+}
+
+fn workspace_id_from_header(headers: &HeaderMap) -> Result<Option<i32>, StatusCode> {
+    headers
+        .get(WORKSPACE_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or(StatusCode::BAD_REQUEST)
+        })
+        .transpose()
+}
+
+// X-Workspace-Id ヘッダーか /workspaces/:workspace_id のパスからアクティブな workspace を決める
+// 両方指定されて食い違う場合は 400、メンバーでなければ 403
+// require_auth の後に実行される前提
+pub async fn resolve_workspace<B: Send>(
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let mut parts = RequestParts::new(req);
+    let user = parts
+        .extensions()
+        .get::<CurrentUser>()
+        .copied()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let from_header = workspace_id_from_header(parts.headers())?;
+    let from_path = match parts
+        .extract::<Option<Path<HashMap<String, String>>>>()
+        .await
+    {
+        Ok(Some(Path(params))) => params
+            .get("workspace_id")
+            .map(|value| value.parse::<i32>().or(Err(StatusCode::BAD_REQUEST)))
+            .transpose()?,
+        _ => None,
+    };
+    let workspace_id = match (from_header, from_path) {
+        (Some(header), Some(path)) if header != path => return Err(StatusCode::BAD_REQUEST),
+        (header, path) => path.or(header),
+    };
+
+    if let Some(workspace_id) = workspace_id {
+        let state = parts
+            .extensions()
+            .get::<AppState>()
+            .cloned()
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+        state
+            .workspace
+            .find_membership(workspace_id, user.id)
+            .await
+            .or(Err(StatusCode::FORBIDDEN))?;
+    }
+
+    parts.extensions_mut().insert(ActiveWorkspace(workspace_id));
+    let req = parts
+        .try_into_request()
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn issue_and_verify_token() {
+        let keys = JwtKeys::new(b"secret");
+        let token = keys.issue(1, Role::Admin).expect("failed issue token");
+        let claims = keys.verify(&token).expect("failed verify token");
+        assert_eq!(claims.sub, 1);
+        assert_eq!(claims.role, Role::Admin);
+
+        let other_keys = JwtKeys::new(b"other secret");
+        assert!(other_keys.verify(&token).is_err());
+    }
+
+    #[test]
+    fn issue_and_verify_feed_token() {
+        let keys = JwtKeys::new(b"secret");
+        let token = keys
+            .issue_feed(1, Some(2), 3)
+            .expect("failed issue feed token");
+        let claims = keys.verify_feed(&token).expect("failed verify feed token");
+        assert_eq!(
+            (claims.sub, claims.workspace_id, claims.ver),
+            (1, Some(2), 3)
+        );
+
+        // 購読用トークンとアクセストークンは取り違えられない
+        assert!(keys.verify(&token).is_err());
+        let access_token = keys.issue(1, Role::Admin).unwrap();
+        assert!(keys.verify_feed(&access_token).is_err());
+        assert!(JwtKeys::new(b"other secret").verify_feed(&token).is_err());
+    }
+
+    #[test]
+    fn generate_and_hash_token() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+
+    #[test]
+    fn read_workspace_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(workspace_id_from_header(&headers), Ok(None));
+        headers.insert(WORKSPACE_HEADER, "3".parse().unwrap());
+        assert_eq!(workspace_id_from_header(&headers), Ok(Some(3)));
+        headers.insert(WORKSPACE_HEADER, "abc".parse().unwrap());
+        assert_eq!(
+            workspace_id_from_header(&headers),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn hash_and_verify_password() {
+        let hash = hash_password("password").expect("failed hash password");
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong password", &hash));
+    }
+}
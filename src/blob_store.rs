@@ -0,0 +1,311 @@
+use crate::config::{BlobBackend, BlobStoreConfig};
+use crate::health::HealthCheck;
+use axum::async_trait;
+use hyper::body::Bytes;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::{path::PathBuf, time::Duration};
+
+// 添付ファイルの中身の保存先. メタデータは attachments テーブルに、中身はここに保存する
+#[async_trait]
+pub trait BlobStore: std::marker::Send + std::marker::Sync + 'static {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()>;
+    async fn get(&self, key: &str) -> anyhow::Result<Bytes>;
+    // 存在しない key を消してもエラーにしない
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+// ローカルのディレクトリに key をそのままパスとして保存する
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, _content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        let path = self.path(key);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Bytes> {
+        Ok(tokio::fs::read(self.path(key)).await?.into())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// 保存先のディレクトリを作れること. マウントし忘れや権限の誤りをここで見つける
+#[async_trait]
+impl HealthCheck for LocalBlobStore {
+    async fn check(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        Ok(())
+    }
+}
+
+// 署名付き URL を発行して S3 (互換のストレージ) に直接リクエストする
+#[derive(Debug, Clone)]
+pub struct S3BlobStore {
+    bucket: Bucket,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+// 署名付き URL はすぐに使うので短くてよい
+const SIGN_EXPIRES_IN: Duration = Duration::from_secs(60);
+
+impl S3BlobStore {
+    pub fn new(bucket: Bucket, credentials: Credentials) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .build()?;
+        Ok(Self {
+            bucket,
+            credentials,
+            client,
+        })
+    }
+
+    // AppConfig::validate で s3_* が揃っていることを確かめた後に呼ぶ
+    pub fn from_config(config: &BlobStoreConfig) -> anyhow::Result<Self> {
+        let endpoint = reqwest::Url::parse(&config.s3_endpoint)?;
+        let bucket = Bucket::new(
+            endpoint,
+            UrlStyle::Path,
+            config.s3_bucket.clone(),
+            config.s3_region.clone(),
+        )?;
+        let credentials = Credentials::new(
+            config.s3_access_key_id.clone(),
+            config.s3_secret_access_key.clone(),
+        );
+        Self::new(bucket, credentials)
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGN_EXPIRES_IN);
+        self.client
+            .put(url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(bytes)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Bytes> {
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), key)
+            .sign(SIGN_EXPIRES_IN);
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(bytes)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let url = self
+            .bucket
+            .delete_object(Some(&self.credentials), key)
+            .sign(SIGN_EXPIRES_IN);
+        self.client.delete(url).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+// バケットが存在し、認証情報で参照できること
+#[async_trait]
+impl HealthCheck for S3BlobStore {
+    async fn check(&self) -> anyhow::Result<()> {
+        let url = self
+            .bucket
+            .head_bucket(Some(&self.credentials))
+            .sign(SIGN_EXPIRES_IN);
+        self.client.head(url).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+// blob_store.backend で選んだ保存先. 省略したら local
+#[derive(Debug, Clone)]
+pub enum ConfiguredBlobStore {
+    Local(LocalBlobStore),
+    S3(S3BlobStore),
+}
+
+impl ConfiguredBlobStore {
+    pub fn from_config(config: &BlobStoreConfig) -> anyhow::Result<Self> {
+        match config.backend {
+            BlobBackend::S3 => Ok(Self::S3(S3BlobStore::from_config(config)?)),
+            BlobBackend::Local => Ok(Self::Local(LocalBlobStore::new(config.dir.clone().into()))),
+        }
+    }
+}
+
+#[async_trait]
+impl BlobStore for ConfiguredBlobStore {
+    async fn put(&self, key: &str, content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+        match self {
+            Self::Local(store) => store.put(key, content_type, bytes).await,
+            Self::S3(store) => store.put(key, content_type, bytes).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Bytes> {
+        match self {
+            Self::Local(store) => store.get(key).await,
+            Self::S3(store) => store.get(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Local(store) => store.delete(key).await,
+            Self::S3(store) => store.delete(key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ConfiguredBlobStore {
+    async fn check(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Local(store) => store.check().await,
+            Self::S3(store) => store.check().await,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    pub struct BlobStoreForMemory {
+        store: Arc<RwLock<HashMap<String, Bytes>>>,
+    }
+
+    impl BlobStoreForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn count(&self) -> usize {
+            self.store.read().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl BlobStore for BlobStoreForMemory {
+        async fn put(&self, key: &str, _content_type: &str, bytes: Bytes) -> anyhow::Result<()> {
+            self.store.write().unwrap().insert(key.to_string(), bytes);
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> anyhow::Result<Bytes> {
+            self.store
+                .read()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("blob not found: {}", key))
+        }
+
+        async fn delete(&self, key: &str) -> anyhow::Result<()> {
+            self.store.write().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl HealthCheck for BlobStoreForMemory {
+        async fn check(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_blob_store_scenario() {
+        let root = std::env::temp_dir().join(format!("rust_web_blob_store_{}", std::process::id()));
+        let store = LocalBlobStore::new(root.clone());
+
+        store
+            .put("todos/1/a", "text/plain", Bytes::from_static(b"hello"))
+            .await
+            .expect("failed put blob");
+        assert_eq!(
+            store.get("todos/1/a").await.unwrap(),
+            Bytes::from_static(b"hello")
+        );
+
+        store.delete("todos/1/a").await.expect("failed delete blob");
+        assert!(store.get("todos/1/a").await.is_err());
+        // 二度目の削除もエラーにしない
+        store.delete("todos/1/a").await.expect("failed delete blob");
+
+        tokio::fs::remove_dir_all(root).await.ok();
+    }
+
+    #[test]
+    fn should_sign_s3_urls() {
+        let bucket = Bucket::new(
+            reqwest::Url::parse("http://localhost:9000").unwrap(),
+            UrlStyle::Path,
+            "attachments",
+            "us-east-1",
+        )
+        .unwrap();
+        let store = S3BlobStore::new(bucket, Credentials::new("key", "secret")).unwrap();
+        let url = store
+            .bucket
+            .get_object(Some(&store.credentials), "todos/1/a")
+            .sign(SIGN_EXPIRES_IN);
+        assert_eq!(url.path(), "/attachments/todos/1/a");
+        assert!(url
+            .query()
+            .is_some_and(|query| query.contains("X-Amz-Signature=")));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::request_id::RequestId;
+use crate::routes::RouteError;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::Route,
+    Json,
+};
+use futures_util::stream;
+use http_body::{Body as _, LengthLimitError, Limited};
+use std::{convert::Infallible, error::Error, future::Future, pin::Pin};
+use tower::{Layer, Service, ServiceBuilder};
+use tower_http::{limit::RequestBodyLimitLayer, map_request_body::MapRequestBodyLayer};
+
+// 本文を limit バイトまでに制限する route layer. 制限は tower-http の RequestBodyLimitLayer に任せる
+// Content-Length が上限を超えていれば handler を呼ばずに 413 を返す. ない場合は上限を超えた時点で
+// 本文の読み込みが失敗するので、本文を読む extractor が 413 を返す. どちらも RouteError の JSON にする
+pub fn limit_body(
+    limit: usize,
+) -> impl Layer<
+    Route,
+    Service = impl Service<
+        Request<Body>,
+        Response = Response,
+        Error = Infallible,
+        Future = impl Future<Output = Result<Response, Infallible>> + Send + 'static,
+    > + Clone
+                  + Send
+                  + 'static,
+> + Clone {
+    ServiceBuilder::new()
+        .layer(middleware::from_fn(move |req, next| {
+            describe_payload_too_large(limit, req, next)
+        }))
+        .layer(RequestBodyLimitLayer::new(limit))
+        .layer(MapRequestBodyLayer::new(into_body))
+        .into_inner()
+}
+
+// ルータは hyper の Body しか受け取らないので、Limited を Body に包み直す
+fn into_body(mut body: Limited<Body>) -> Body {
+    Body::wrap_stream(stream::poll_fn(move |cx| Pin::new(&mut body).poll_data(cx)))
+}
+
+// RequestBodyLimitLayer の空の 413 や extractor の 413 を、上限を書いた JSON にする
+async fn describe_payload_too_large(
+    limit: usize,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let res = next.run(req).await;
+    if res.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return res;
+    }
+    let message = format!("request body must be at most {} bytes", limit);
+    let error = RouteError::new(StatusCode::PAYLOAD_TOO_LARGE, message, request_id);
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(error)).into_response()
+}
+
+// 本文の読み込みが上限で打ち切られたか. hyper の Error などに包まれているので source を辿る
+pub fn exceeded(error: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(inner) = error {
+        if inner.is::<LengthLimitError>() {
+            return true;
+        }
+        error = inner.source();
+    }
+    false
+}
@@ -0,0 +1,127 @@
+use crate::request_id::RequestId;
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::Route,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{any::Any, convert::Infallible, future::Future};
+use tower::{Layer, Service, ServiceBuilder};
+use tower_http::catch_panic::CatchPanicLayer;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+// RFC 7807 の problem details. panic の内容はログにだけ出し、クライアントには返さない
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// CatchPanicLayer の応答に付けて、panic したことを describe_panic に伝える. 中身は panic の文字列
+#[derive(Debug, Clone)]
+struct Panicked(String);
+
+// handler や内側の layer が panic しても接続を切らずに 500 を返す. panic は tower-http の
+// CatchPanicLayer が捕まえる. trace_request の内側に置くので、ログはリクエストの span に紐付く
+pub fn catch_panic() -> impl Layer<
+    Route,
+    Service = impl Service<
+        Request<Body>,
+        Response = Response,
+        Error = Infallible,
+        Future = impl Future<Output = Result<Response, Infallible>> + Send + 'static,
+    > + Clone
+                  + Send
+                  + 'static,
+> + Clone {
+    ServiceBuilder::new()
+        .layer(middleware::from_fn(describe_panic))
+        .layer(CatchPanicLayer::custom(mark_panic))
+        .into_inner()
+}
+
+// panic の payload はリクエストの ID を知らないので、本文は describe_panic が作る
+fn mark_panic(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let mut res = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    res.extensions_mut()
+        .insert(Panicked(panic_message(panic.as_ref()).to_string()));
+    res
+}
+
+async fn describe_panic(req: Request<Body>, next: Next<Body>) -> Response {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let route = format!("{} {}", req.method(), req.uri().path());
+    let res = next.run(req).await;
+    let Some(Panicked(message)) = res.extensions().get::<Panicked>() else {
+        return res;
+    };
+    tracing::error!("{} panicked: {}", route, message);
+    let problem = Problem {
+        problem_type: "about:blank".to_string(),
+        title: "Internal Server Error".to_string(),
+        status: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+        detail: "the server failed to handle the request".to_string(),
+        request_id: request_id.map(|request_id| request_id.0),
+    };
+    let mut res = (StatusCode::INTERNAL_SERVER_ERROR, Json(problem)).into_response();
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    res
+}
+
+// panic! に渡した文字列. それ以外の値で panic した場合は中身がわからない
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn should_turn_panics_into_problems() {
+        let app = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route(
+                "/panic",
+                get(|| async {
+                    let todos: Vec<i32> = Vec::new();
+                    todos[0].to_string()
+                }),
+            )
+            .layer(catch_panic());
+        let req = |path: &str| {
+            let mut req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            req.extensions_mut().insert(RequestId("req-1".to_string()));
+            req
+        };
+
+        let res = app.clone().oneshot(req("/panic")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(res.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let problem: Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.request_id.as_deref(), Some("req-1"));
+        // the index out of bounds message stays in the logs
+        assert!(!problem.detail.contains("index"));
+
+        let res = app.oneshot(req("/ok")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::seed::SeedOptions;
+
+// コマンドラインの引数. 何も書かなければ serve なので、今までの起動方法はそのまま使える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    // 未適用の migration を流して終わる. デプロイのときにサーバーより先に流す
+    Migrate,
+    // 動作確認用のユーザーとラベルと todo を作る
+    Seed(SeedOptions),
+    // 設定を読んで検証するだけ. 秘密情報の参照も取得する
+    CheckConfig,
+    Help,
+}
+
+pub const USAGE: &str = "usage: rust_web [COMMAND]
+
+commands:
+  serve                 start the API server (default)
+  migrate               apply pending database migrations and exit
+  seed [OPTIONS]        create demo users with labels and todos
+    --users N           number of users (default 1)
+    --labels N          labels per user (default 3)
+    --count N           todos per user (default 20)
+    --rng-seed N        generate the same data on every run
+  check-config          load and validate the config, then exit
+  help                  print this message";
+
+impl Command {
+    // 実行ファイルの名前を除いた引数
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut command = match args.next().as_deref() {
+            None | Some("serve") => Command::Serve,
+            Some("migrate") => Command::Migrate,
+            Some("seed") => Command::Seed(SeedOptions::default()),
+            Some("check-config") => Command::CheckConfig,
+            Some("help" | "--help" | "-h") => return Ok(Command::Help),
+            Some(other) => return Err(format!("unknown command: {}", other)),
+        };
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            match (&mut command, name) {
+                (_, "--help" | "-h") => return Ok(Command::Help),
+                (Command::Seed(options), "--users" | "--labels" | "--count" | "--rng-seed") => {
+                    let value = inline
+                        .or_else(|| args.next())
+                        .ok_or_else(|| format!("{} needs a number", name))?;
+                    let number: u64 = value
+                        .parse()
+                        .map_err(|_| format!("{} is not a number: {}", name, value))?;
+                    match name {
+                        "--users" => options.users = number as usize,
+                        "--labels" => options.labels = number as usize,
+                        "--count" => options.todos = number as usize,
+                        _ => options.rng_seed = Some(number),
+                    }
+                }
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn should_parse_commands() {
+        assert_eq!(parse(&[]), Ok(Command::Serve));
+        assert_eq!(parse(&["migrate"]), Ok(Command::Migrate));
+        assert_eq!(parse(&["check-config"]), Ok(Command::CheckConfig));
+        assert_eq!(parse(&["seed"]), Ok(Command::Seed(SeedOptions::default())));
+        assert_eq!(
+            parse(&[
+                "seed",
+                "--count",
+                "100",
+                "--users=3",
+                "--labels",
+                "5",
+                "--rng-seed",
+                "7"
+            ]),
+            Ok(Command::Seed(SeedOptions {
+                users: 3,
+                labels: 5,
+                todos: 100,
+                rng_seed: Some(7),
+            }))
+        );
+        assert_eq!(parse(&["migrate", "--help"]), Ok(Command::Help));
+
+        assert_eq!(
+            parse(&["deploy"]),
+            Err("unknown command: deploy".to_string())
+        );
+        assert_eq!(
+            parse(&["seed", "--count"]),
+            Err("--count needs a number".to_string())
+        );
+        assert_eq!(
+            parse(&["seed", "--count", "many"]),
+            Err("--count is not a number: many".to_string())
+        );
+        assert_eq!(
+            parse(&["serve", "--count", "1"]),
+            Err("unexpected argument: --count".to_string())
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::handlers::auth::{AuthBody, Credentials};
+use crate::repositories::label::{CreateLabel, Label, UpdateLabel};
+use crate::repositories::todo::{CreateTodo, Todo, UpdateTodo};
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    response::Response,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{convert::Infallible, sync::Mutex};
+use thiserror::Error;
+use tower::{Service, ServiceExt};
+
+// クライアントが呼ぶ API の版
+const API_PREFIX: &str = "/v1";
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    // 2xx 以外のレスポンス. body はサーバーが返したまま
+    #[error("Status Error: [{status}] {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("Transport Error: [{0}]")]
+    Transport(#[from] anyhow::Error),
+    #[error("Json Error: [{0}]")]
+    Json(#[from] serde_json::Error),
+}
+
+// リクエストを送ってステータスと本文を返す. HTTP で送るか、同じプロセスの Router に渡すかを差し替える
+#[async_trait]
+pub trait Transport: std::marker::Send + std::marker::Sync + 'static {
+    async fn send(&self, req: Request<Vec<u8>>) -> anyhow::Result<(StatusCode, Bytes)>;
+}
+
+// base_url は http://localhost:3000 のようにパスを含めない
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpTransport {
+    pub fn new(base_url: &str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .build()?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, req: Request<Vec<u8>>) -> anyhow::Result<(StatusCode, Bytes)> {
+        let (parts, body) = req.into_parts();
+        let url = format!("{}{}", self.base_url, parts.uri);
+        let res = self
+            .client
+            .request(parts.method, url)
+            .headers(parts.headers)
+            .body(body)
+            .send()
+            .await?;
+        Ok((res.status(), res.bytes().await?))
+    }
+}
+
+// create_app の Router のような tower の Service にそのまま渡す. テストやサーバーに組み込むときに使う
+pub struct ServiceTransport<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ServiceTransport<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Mutex::new(service),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> Transport for ServiceTransport<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>
+        + Clone
+        + std::marker::Send
+        + 'static,
+    S::Future: std::marker::Send,
+{
+    async fn send(&self, req: Request<Vec<u8>>) -> anyhow::Result<(StatusCode, Bytes)> {
+        let service = self.service.lock().expect("service lock poisoned").clone();
+        let res = service.oneshot(req.map(Body::from)).await?;
+        let status = res.status();
+        Ok((status, hyper::body::to_bytes(res.into_body()).await?))
+    }
+}
+
+// API の型付きクライアント. リクエストとレスポンスはサーバーと同じ構造体を使う
+// register / login で受け取ったアクセストークンを set_token してから todo や label を呼ぶ
+pub struct TodoClient<T: Transport = HttpTransport> {
+    transport: T,
+    access_token: Option<String>,
+}
+
+impl TodoClient<HttpTransport> {
+    pub fn from_base_url(base_url: &str) -> anyhow::Result<Self> {
+        Ok(Self::new(HttpTransport::new(base_url)?))
+    }
+}
+
+impl<T: Transport> TodoClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            access_token: None,
+        }
+    }
+
+    pub fn set_token(&mut self, access_token: String) {
+        self.access_token = Some(access_token);
+    }
+
+    pub async fn register(&self, payload: &Credentials) -> Result<AuthBody, ClientError> {
+        self.json(Method::POST, "/auth/register", Some(payload))
+            .await
+    }
+
+    pub async fn login(&self, payload: &Credentials) -> Result<AuthBody, ClientError> {
+        self.json(Method::POST, "/auth/login", Some(payload)).await
+    }
+
+    pub async fn create_todo(&self, payload: &CreateTodo) -> Result<Todo, ClientError> {
+        self.json(Method::POST, "/todos", Some(payload)).await
+    }
+
+    pub async fn all_todos(&self) -> Result<Vec<Todo>, ClientError> {
+        self.json(Method::GET, "/todos", None::<&()>).await
+    }
+
+    pub async fn find_todo(&self, id: i32) -> Result<Todo, ClientError> {
+        self.json(Method::GET, &format!("/todos/{}", id), None::<&()>)
+            .await
+    }
+
+    pub async fn update_todo(&self, id: i32, payload: &UpdateTodo) -> Result<Todo, ClientError> {
+        self.json(Method::PATCH, &format!("/todos/{}", id), Some(payload))
+            .await
+    }
+
+    pub async fn delete_todo(&self, id: i32) -> Result<(), ClientError> {
+        self.send(Method::DELETE, &format!("/todos/{}", id), None::<&()>)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn create_label(&self, payload: &CreateLabel) -> Result<Label, ClientError> {
+        self.json(Method::POST, "/labels", Some(payload)).await
+    }
+
+    pub async fn all_labels(&self) -> Result<Vec<Label>, ClientError> {
+        self.json(Method::GET, "/labels", None::<&()>).await
+    }
+
+    pub async fn find_label(&self, id: i32) -> Result<Label, ClientError> {
+        self.json(Method::GET, &format!("/labels/{}", id), None::<&()>)
+            .await
+    }
+
+    pub async fn update_label(&self, id: i32, payload: &UpdateLabel) -> Result<Label, ClientError> {
+        self.json(Method::PATCH, &format!("/labels/{}", id), Some(payload))
+            .await
+    }
+
+    pub async fn delete_label(&self, id: i32) -> Result<(), ClientError> {
+        self.send(Method::DELETE, &format!("/labels/{}", id), None::<&()>)
+            .await?;
+        Ok(())
+    }
+
+    async fn json<B: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        payload: Option<&B>,
+    ) -> Result<R, ClientError> {
+        let body = self.send(method, path, payload).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        payload: Option<&B>,
+    ) -> Result<Bytes, ClientError> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", API_PREFIX, path))
+            .header(header::ACCEPT, mime::APPLICATION_JSON.as_ref());
+        if let Some(token) = &self.access_token {
+            let value =
+                HeaderValue::from_str(&format!("Bearer {}", token)).map_err(anyhow::Error::from)?;
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        let body = match payload {
+            Some(payload) => {
+                req = req.header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+                serde_json::to_vec(payload)?
+            }
+            None => Vec::new(),
+        };
+        let req = req.body(body).map_err(anyhow::Error::from)?;
+
+        let (status, body) = self.transport.send(req).await?;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(ClientError::Status { status, body });
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_trim_base_url() {
+        let client = TodoClient::from_base_url("http://localhost:3000/").unwrap();
+        assert_eq!(client.transport.base_url, "http://localhost:3000");
+        assert_eq!(client.access_token, None);
+    }
+}
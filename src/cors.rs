@@ -0,0 +1,147 @@
+use crate::config::CorsConfig;
+use axum::http::HeaderValue;
+use regex::Regex;
+use tower_http::cors::{AllowMethods, AllowOrigin, Any, CorsLayer};
+
+// cors.allow_origin にカンマ区切りで並べる 1 つ分
+//   https://app.example.com       完全一致
+//   *.example.com                 サブドメイン. https://*.example.com と書けば scheme も見る
+//   regex:^https://pr-\d+\.example\.com$   origin 全体に一致する正規表現. カンマは書けない
+// サブドメインの指定はポートを含めて比べるので、ポート付きの origin は *.example.com:8443 のように書く
+#[derive(Debug, Clone)]
+pub enum OriginPattern {
+    Exact(String),
+    Subdomain {
+        scheme: Option<String>,
+        suffix: String,
+    },
+    Regex(Regex),
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(regex) = pattern.strip_prefix("regex:") {
+            // 部分一致で通ってしまわないように origin 全体にかける
+            return Regex::new(&format!("^(?:{})$", regex))
+                .map(OriginPattern::Regex)
+                .map_err(|e| format!("{}: {}", pattern, e));
+        }
+        let (scheme, host) = match pattern.split_once("://") {
+            Some((scheme, host)) => (Some(scheme.to_ascii_lowercase()), host),
+            None => (None, pattern),
+        };
+        if let Some(suffix) = host.strip_prefix("*.") {
+            if suffix.is_empty() || suffix.contains(['*', '/']) {
+                return Err(format!("{}: expected *.domain", pattern));
+            }
+            return Ok(OriginPattern::Subdomain {
+                scheme,
+                suffix: format!(".{}", suffix.to_ascii_lowercase()),
+            });
+        }
+        if scheme.is_none() || host.is_empty() || host.contains(['*', '/']) {
+            return Err(format!(
+                "{}: expected an origin like https://app.example.com",
+                pattern
+            ));
+        }
+        if pattern.parse::<HeaderValue>().is_err() {
+            return Err(format!("{}: not a valid header value", pattern));
+        }
+        Ok(OriginPattern::Exact(pattern.to_ascii_lowercase()))
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            OriginPattern::Exact(exact) => origin == *exact,
+            OriginPattern::Subdomain { scheme, suffix } => {
+                let Some((origin_scheme, host)) = origin.split_once("://") else {
+                    return false;
+                };
+                scheme
+                    .as_deref()
+                    .is_none_or(|scheme| scheme == origin_scheme)
+                    && host.len() > suffix.len()
+                    && host.ends_with(suffix.as_str())
+                    && !host.contains('/')
+            }
+            OriginPattern::Regex(regex) => regex.is_match(&origin),
+        }
+    }
+}
+
+// 空の要素は読み飛ばす. 末尾のカンマや "a, b" のような空白は許す
+pub fn parse_origins(list: &str) -> Result<Vec<OriginPattern>, Vec<String>> {
+    let (patterns, errors): (Vec<_>, Vec<_>) = list
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(OriginPattern::parse)
+        .partition(Result::is_ok);
+    if errors.is_empty() {
+        Ok(patterns.into_iter().map(Result::unwrap).collect())
+    } else {
+        Err(errors.into_iter().map(Result::unwrap_err).collect())
+    }
+}
+
+// origin と method と credentials だけを決めた CorsLayer. ヘッダーは呼び出し側で足す
+// credentials を許可するとブラウザが * を受け付けないので、リクエストの値をそのまま返す
+pub fn cors_layer(config: &CorsConfig) -> Result<CorsLayer, Vec<String>> {
+    let origin = if config.permissive {
+        if config.allow_credentials {
+            AllowOrigin::mirror_request()
+        } else {
+            AllowOrigin::any()
+        }
+    } else {
+        let patterns = parse_origins(&config.allow_origin)?;
+        match patterns.as_slice() {
+            [OriginPattern::Exact(origin)] => AllowOrigin::exact(origin.parse().unwrap()),
+            _ => AllowOrigin::predicate(move |origin, _| {
+                origin
+                    .to_str()
+                    .is_ok_and(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+            }),
+        }
+    };
+    let layer = CorsLayer::new().allow_origin(origin);
+    Ok(if config.allow_credentials {
+        layer
+            .allow_credentials(true)
+            .allow_methods(AllowMethods::mirror_request())
+    } else {
+        layer.allow_methods(Any)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_match_origin_patterns() {
+        let patterns = parse_origins(
+            "http://localhost:3001, https://*.example.com,*.example.org:8443, regex:https://pr-\\d+\\.preview\\.dev",
+        )
+        .unwrap();
+        let allowed = |origin: &str| patterns.iter().any(|pattern| pattern.matches(origin));
+        assert!(allowed("http://localhost:3001"));
+        assert!(!allowed("http://localhost:3002"));
+        assert!(allowed("https://app.example.com"));
+        assert!(allowed("https://a.b.example.com"));
+        assert!(!allowed("http://app.example.com"));
+        assert!(!allowed("https://example.com"));
+        assert!(!allowed("https://evil-example.com"));
+        assert!(!allowed("https://app.example.com:8443"));
+        assert!(allowed("http://app.example.org:8443"));
+        assert!(!allowed("http://app.example.org"));
+        assert!(allowed("https://pr-42.preview.dev"));
+        // the regex has to match the whole origin
+        assert!(!allowed("https://pr-42.preview.dev.evil.com"));
+
+        let errors = parse_origins("localhost:3001,*.,regex:(").unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+    }
+}
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+
+// cron の式. 「分 時 日 月 曜日」の 5 つか、先頭に秒を足した 6 つ. 時刻は UTC
+// 各項目は *, 5, 1-5, */15, 10-50/20 と、それらのカンマ区切り. 曜日は 0 と 7 が日曜日
+// 日と曜日を両方指定したら、どちらかに合えば実行する (crontab と同じ)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// うるう年の 2 月 29 日も見つかるだけ探す. それでも見つからなければ 2 月 30 日のような来ない日付とみなす
+const SEARCH_LIMIT: usize = 4 * 366 * 24 * 60;
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let (seconds, fields) = match fields.len() {
+            5 => (1, &fields[..]),
+            6 => (parse_field(fields[0], 0, 59)?, &fields[1..]),
+            n => return Err(format!("expected 5 or 6 fields, got {}", n)),
+        };
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            seconds,
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    // after より後で最初に当てはまる時刻. 来ない日付しか指定していなければ None
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_nanosecond(0)? + Duration::seconds(1);
+        for _ in 0..SEARCH_LIMIT {
+            if !has(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.matches_day(time) {
+                time = start_of_day(time)? + Duration::days(1);
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)?.with_second(0)? + Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time = time.with_second(0)? + Duration::minutes(1);
+            } else if !has(self.seconds, time.second()) {
+                time += Duration::seconds(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn start_of_day(time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    time.with_hour(0)?.with_minute(0)?.with_second(0)
+}
+
+// 当てはまる値のビットを立てる
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0;
+    for item in field.split(',') {
+        let invalid = || format!("invalid field: {}", field);
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (
+                start.parse().map_err(|_| invalid())?,
+                end.parse().map_err(|_| invalid())?,
+            ),
+            // 5/15 は 5 から最後まで 15 おき
+            None if item.contains('/') => (range.parse().map_err(|_| invalid())?, max),
+            None => {
+                let value = range.parse().map_err(|_| invalid())?;
+                (value, value)
+            }
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(format!("out of range {}-{}: {}", min, max, item));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+        CronSchedule::parse(expression)
+            .unwrap()
+            .next_after(at(after))
+    }
+
+    #[test]
+    fn should_find_the_next_run() {
+        let after = "2023-01-31T10:17:30Z";
+        assert_eq!(next("* * * * *", after), Some(at("2023-01-31T10:18:00Z")));
+        assert_eq!(
+            next("*/15 * * * *", after),
+            Some(at("2023-01-31T10:30:00Z"))
+        );
+        assert_eq!(
+            next("5/20 * * * *", after),
+            Some(at("2023-01-31T10:25:00Z"))
+        );
+        assert_eq!(next("@hourly", after), Some(at("2023-01-31T11:00:00Z")));
+        assert_eq!(next("30 3 * * *", after), Some(at("2023-02-01T03:30:00Z")));
+        assert_eq!(
+            next("0 9 * * 1-5", "2023-02-03T09:00:00Z"),
+            Some(at("2023-02-06T09:00:00Z"))
+        );
+        assert_eq!(next("0 0 * * 7", after), Some(at("2023-02-05T00:00:00Z")));
+        assert_eq!(
+            next("0 0 1,15 * *", after),
+            Some(at("2023-02-01T00:00:00Z"))
+        );
+        assert_eq!(next("@yearly", after), Some(at("2024-01-01T00:00:00Z")));
+        assert_eq!(next("0 0 29 2 *", after), Some(at("2024-02-29T00:00:00Z")));
+        // either the day of the month or the weekday
+        assert_eq!(next("0 0 13 * 5", after), Some(at("2023-02-03T00:00:00Z")));
+        assert_eq!(
+            next("*/10 * * * * *", after),
+            Some(at("2023-01-31T10:17:40Z"))
+        );
+        assert_eq!(next("0 0 30 2 *", after), None);
+    }
+
+    #[test]
+    fn should_reject_invalid_expressions() {
+        for expression in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(CronSchedule::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
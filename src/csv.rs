@@ -0,0 +1,172 @@
+use serde_json::Value;
+
+// GET /todos の CSV の列. Todo の JSON のフィールドと同じ順
+pub const TODO_COLUMNS: [&str; 19] = [
+    "id",
+    "text",
+    "description",
+    "status",
+    "user_id",
+    "workspace_id",
+    "due_at",
+    "starts_at",
+    "priority",
+    "parent_id",
+    "recurrence",
+    "archived_at",
+    "position",
+    "project_id",
+    "labels",
+    "created_at",
+    "assignee_id",
+    "is_pinned",
+    "snoozed_until",
+];
+// GET /labels の CSV の列. Label の JSON のフィールドと同じ順
+pub const LABEL_COLUMNS: [&str; 9] = [
+    "id",
+    "name",
+    "user_id",
+    "workspace_id",
+    "color",
+    "parent_id",
+    "position",
+    "archived_at",
+    "icon",
+];
+
+// JSON にした一覧を RFC 4180 の CSV にする. 1 行目は列名で、行末は CRLF
+pub fn render(columns: &[&str], rows: &[Value]) -> String {
+    let mut csv = String::new();
+    push_record(&mut csv, columns.iter().map(|column| column.to_string()));
+    for row in rows {
+        push_record(&mut csv, columns.iter().map(|column| cell(&row[column])));
+    }
+    csv
+}
+
+fn push_record(csv: &mut String, fields: impl Iterator<Item = String>) {
+    let fields: Vec<String> = fields.map(|field| escape(&field)).collect();
+    csv.push_str(&fields.join(","));
+    csv.push_str("\r\n");
+}
+
+// null は空にする. todo のラベルのようなオブジェクトの配列は name を ; でつなぐ
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => neutralize_formula(text),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item.get("name") {
+                Some(name) => cell(name),
+                None => cell(item),
+            })
+            .collect::<Vec<_>>()
+            .join(";"),
+        value => value.to_string(),
+    }
+}
+
+// 表計算ソフトで開いたときに数式として実行されないよう、先頭に ' を付ける
+fn neutralize_formula(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::label::Label;
+    use crate::repositories::todo::{Priority, Status, Todo};
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    #[test]
+    fn should_render_csv() {
+        let rows = vec![
+            json!({
+                "id": 1,
+                "text": "say \"hi\", then leave",
+                "description": null,
+                "labels": [{ "id": 1, "name": "home" }, { "id": 2, "name": "work" }],
+                "is_pinned": true,
+            }),
+            json!({ "id": 2, "text": "=HYPERLINK(\"x\")", "description": "line 1\nline 2" }),
+        ];
+        let csv = render(&["id", "text", "description", "labels", "is_pinned"], &rows);
+        assert_eq!(
+            csv,
+            concat!(
+                "id,text,description,labels,is_pinned\r\n",
+                "1,\"say \"\"hi\"\", then leave\",,home;work,true\r\n",
+                "2,\"'=HYPERLINK(\"\"x\"\")\",\"line 1\nline 2\",,\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn should_cover_all_fields() {
+        let keys = |value: Value| -> Vec<String> {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let sorted = |columns: &[&str]| -> Vec<String> {
+            let mut columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+            columns.sort();
+            columns
+        };
+        let label = Label {
+            id: 1,
+            name: "home".to_string(),
+            user_id: Some(1),
+            workspace_id: None,
+            color: "#4f86f7".to_string(),
+            parent_id: None,
+            position: 1,
+            archived_at: None,
+            icon: None,
+        };
+        let todo = Todo {
+            id: 1,
+            text: "todo".to_string(),
+            description: None,
+            status: Status::Backlog,
+            user_id: 1,
+            workspace_id: None,
+            due_at: None,
+            starts_at: None,
+            priority: Priority::Medium,
+            parent_id: None,
+            recurrence: None,
+            archived_at: None,
+            position: 0,
+            project_id: None,
+            labels: vec![label.clone()],
+            created_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            assignee_id: None,
+            is_pinned: false,
+            snoozed_until: None,
+        };
+        assert_eq!(
+            keys(serde_json::to_value(todo).unwrap()),
+            sorted(&TODO_COLUMNS)
+        );
+        assert_eq!(
+            keys(serde_json::to_value(label).unwrap()),
+            sorted(&LABEL_COLUMNS)
+        );
+    }
+}
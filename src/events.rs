@@ -0,0 +1,162 @@
+use crate::config::{AppConfig, EventBus};
+use crate::redis::RedisClient;
+use crate::repositories::outbox::OutboxEvent;
+use axum::async_trait;
+use tokio::sync::broadcast;
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "nats")]
+mod nats;
+
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaPublisher;
+#[cfg(feature = "nats")]
+pub use nats::NatsPublisher;
+
+// todo と label の作成、更新、削除を他のサービスに知らせる. event は outbox の relay が順に渡す
+// 失敗したら relay が同じ event を送り直す
+#[async_trait]
+pub trait EventPublisher: std::marker::Send + std::marker::Sync + 'static {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()>;
+}
+
+// 同じプロセスの購読者にだけ流す. インスタンスが 1 つのときに使う
+// 購読者がいなくても、追いつけない購読者がいても失敗にしない
+#[derive(Debug, Clone)]
+pub struct LocalPublisher {
+    sender: broadcast::Sender<OutboxEvent>,
+}
+
+impl LocalPublisher {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn subscribe(&self) -> broadcast::Receiver<OutboxEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for LocalPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        let _ = self.sender.send(event.clone());
+        Ok(())
+    }
+}
+
+// Redis の channel に JSON で PUBLISH する. どのインスタンスの購読者にも届く
+#[derive(Clone)]
+pub struct RedisPublisher {
+    client: RedisClient,
+    channel: String,
+}
+
+impl RedisPublisher {
+    pub fn new(client: RedisClient, channel: String) -> Self {
+        Self { client, channel }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for RedisPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        let message = serde_json::to_vec(event)?;
+        self.client.publish(&self.channel, &message).await?;
+        Ok(())
+    }
+}
+
+pub enum ConfiguredPublisher {
+    Local(LocalPublisher),
+    Redis(RedisPublisher),
+    #[cfg(feature = "nats")]
+    Nats(NatsPublisher),
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaPublisher),
+}
+
+impl ConfiguredPublisher {
+    // redis の URL は events.redis_url がなければ cache.redis_url を使うので、AppConfig ごと受け取る
+    pub fn from_config(config: &AppConfig) -> anyhow::Result<Self> {
+        let channel = config.events.channel.clone();
+        match config.events.bus {
+            EventBus::Redis => Ok(Self::Redis(RedisPublisher::new(
+                RedisClient::open(config.events_redis_url())?,
+                channel,
+            ))),
+            #[cfg(feature = "nats")]
+            EventBus::Nats => Ok(Self::Nats(NatsPublisher::open(
+                &config.events.nats_url,
+                channel,
+            )?)),
+            #[cfg(feature = "kafka")]
+            EventBus::Kafka => Ok(Self::Kafka(KafkaPublisher::new(
+                &config.events.kafka_rest_url,
+                channel,
+            )?)),
+            #[cfg(not(feature = "nats"))]
+            EventBus::Nats => unreachable!("rejected by AppConfig::validate"),
+            #[cfg(not(feature = "kafka"))]
+            EventBus::Kafka => unreachable!("rejected by AppConfig::validate"),
+            EventBus::Local => Ok(Self::Local(LocalPublisher::new(1024))),
+        }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for ConfiguredPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        match self {
+            Self::Local(bus) => bus.publish(event).await,
+            Self::Redis(bus) => bus.publish(event).await,
+            #[cfg(feature = "nats")]
+            Self::Nats(bus) => bus.publish(event).await,
+            #[cfg(feature = "kafka")]
+            Self::Kafka(bus) => bus.publish(event).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::redis::test_server;
+    use chrono::Utc;
+    use serde_json::json;
+
+    pub(super) fn event() -> OutboxEvent {
+        OutboxEvent {
+            id: 1,
+            event: "todo.created".to_string(),
+            entity_id: 1,
+            user_id: Some(1),
+            workspace_id: None,
+            data: json!({ "id": 1, "text": "todo" }),
+            previous: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_publish_events() {
+        let event = event();
+
+        let bus = LocalPublisher::new(8);
+        // nobody is listening yet
+        bus.publish(&event).await.unwrap();
+        let mut receiver = bus.subscribe();
+        bus.publish(&event).await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap(), event);
+
+        let client = RedisClient::open(&test_server::spawn().await).unwrap();
+        let bus = RedisPublisher::new(client, "events".to_string());
+        bus.publish(&event).await.unwrap();
+        let client = RedisClient::open("redis://127.0.0.1:1").unwrap();
+        let bus = RedisPublisher::new(client, "events".to_string());
+        assert!(bus.publish(&event).await.is_err());
+    }
+}
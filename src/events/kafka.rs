@@ -0,0 +1,129 @@
+use super::EventPublisher;
+use crate::repositories::outbox::OutboxEvent;
+use axum::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const CONTENT_TYPE: &str = "application/vnd.kafka.json.v2+json";
+
+// Kafka REST Proxy (v2 API) 越しに topic へ書く. broker に直接つなぐクライアントは持たない
+// key は "todo:1" のように entity ごとにするので、同じ todo の event は同じ partition に順番どおり入る
+#[derive(Debug, Clone)]
+pub struct KafkaPublisher {
+    client: reqwest::Client,
+    endpoint: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProduceResponse {
+    offsets: Vec<Offset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Offset {
+    error_code: Option<i64>,
+    error: Option<String>,
+}
+
+impl KafkaPublisher {
+    pub fn new(rest_url: &str, topic: String) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+        let mut endpoint =
+            Url::parse(rest_url).map_err(|e| anyhow::anyhow!("invalid kafka rest url: {}", e))?;
+        endpoint
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("invalid kafka rest url: {}", rest_url))?
+            .pop_if_empty()
+            .extend(["topics", topic.as_str()]);
+        Ok(Self { client, endpoint })
+    }
+}
+
+fn records(event: &OutboxEvent) -> Value {
+    let entity = event.event.split('.').next().unwrap_or_default();
+    json!({
+        "records": [{
+            "key": format!("{}:{}", entity, event.entity_id),
+            "value": event,
+        }]
+    })
+}
+
+// HTTP は 200 でも、record ごとに書き込みの失敗が返ることがある
+fn check_offsets(response: ProduceResponse) -> anyhow::Result<()> {
+    match response
+        .offsets
+        .into_iter()
+        .find(|offset| offset.error_code.is_some())
+    {
+        Some(offset) => Err(anyhow::anyhow!(
+            "kafka error {}: {}",
+            offset.error_code.unwrap_or_default(),
+            offset.error.unwrap_or_default()
+        )),
+        None => Ok(()),
+    }
+}
+
+#[async_trait]
+impl EventPublisher for KafkaPublisher {
+    async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(self.endpoint.clone())
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(reqwest::header::ACCEPT, "application/vnd.kafka.v2+json")
+            .body(serde_json::to_vec(&records(event))?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ProduceResponse>()
+            .await?;
+        check_offsets(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::test::event;
+
+    #[test]
+    fn should_build_the_produce_request() {
+        let publisher =
+            KafkaPublisher::new("http://kafka-rest:8082/", "rust_web.events".to_string()).unwrap();
+        assert_eq!(
+            publisher.endpoint.as_str(),
+            "http://kafka-rest:8082/topics/rust_web.events"
+        );
+
+        let event = event();
+        let body = records(&event);
+        assert_eq!(body["records"][0]["key"], "todo:1");
+        assert_eq!(
+            body["records"][0]["value"],
+            serde_json::to_value(&event).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_fail_on_record_errors() {
+        let parse = |body: Value| serde_json::from_value::<ProduceResponse>(body).unwrap();
+        let ok = parse(json!({
+            "key_schema_id": null,
+            "offsets": [{ "partition": 2, "offset": 100, "error_code": null, "error": null }]
+        }));
+        assert!(check_offsets(ok).is_ok());
+        let failed = parse(json!({
+            "offsets": [{ "partition": null, "offset": null, "error_code": 40403, "error": "Topic not found." }]
+        }));
+        assert_eq!(
+            check_offsets(failed).unwrap_err().to_string(),
+            "kafka error 40403: Topic not found."
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::repositories::todo::{TodoActivity, TodoActivityKind};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+// フィードの内容. Atom と RSS 2.0 のどちらでも出力できる
+pub struct Feed<'a> {
+    // Atom の feed の id. 同じフィードなら常に同じ値にする
+    pub id: String,
+    pub title: &'a str,
+    // フィード自身のパス. RSS の channel の link に使う
+    pub link: &'a str,
+    // 新しい順
+    pub activity: &'a [TodoActivity],
+    // エントリーがなければフィードの更新日時にする
+    pub generated_at: DateTime<Utc>,
+}
+
+impl Feed<'_> {
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.activity
+            .first()
+            .map_or(self.generated_at, |activity| activity.at)
+    }
+}
+
+pub fn render_atom(feed: &Feed) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape_xml(&feed.id)));
+    xml.push_str(&format!("  <title>{}</title>\n", escape_xml(feed.title)));
+    xml.push_str(&format!(
+        "  <updated>{}</updated>\n",
+        rfc3339(feed.updated_at())
+    ));
+    xml.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}\"/>\n",
+        escape_xml(feed.link)
+    ));
+    xml.push_str("  <author><name>rust-web</name></author>\n");
+    for activity in feed.activity {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", entry_id(activity)));
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            escape_xml(&entry_title(activity))
+        ));
+        xml.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            rfc3339(activity.at)
+        ));
+        xml.push_str(&format!(
+            "    <category term=\"{}\"/>\n",
+            kind(activity.kind)
+        ));
+        if let Some(description) = &activity.todo.description {
+            xml.push_str(&format!(
+                "    <summary>{}</summary>\n",
+                escape_xml(description)
+            ));
+        }
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+pub fn render_rss(feed: &Feed) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n");
+    xml.push_str("  <channel>\n");
+    xml.push_str(&format!("    <title>{}</title>\n", escape_xml(feed.title)));
+    xml.push_str(&format!("    <link>{}</link>\n", escape_xml(feed.link)));
+    xml.push_str("    <description>Recently created and completed todos</description>\n");
+    xml.push_str(&format!(
+        "    <lastBuildDate>{}</lastBuildDate>\n",
+        feed.updated_at().to_rfc2822()
+    ));
+    for activity in feed.activity {
+        xml.push_str("    <item>\n");
+        xml.push_str(&format!(
+            "      <title>{}</title>\n",
+            escape_xml(&entry_title(activity))
+        ));
+        xml.push_str(&format!(
+            "      <guid isPermaLink=\"false\">{}</guid>\n",
+            entry_id(activity)
+        ));
+        xml.push_str(&format!(
+            "      <pubDate>{}</pubDate>\n",
+            activity.at.to_rfc2822()
+        ));
+        xml.push_str(&format!(
+            "      <category>{}</category>\n",
+            kind(activity.kind)
+        ));
+        if let Some(description) = &activity.todo.description {
+            xml.push_str(&format!(
+                "      <description>{}</description>\n",
+                escape_xml(description)
+            ));
+        }
+        xml.push_str("    </item>\n");
+    }
+    xml.push_str("  </channel>\n");
+    xml.push_str("</rss>\n");
+    xml
+}
+
+// 完了は差し戻してからもう一度完了できるので、日時も含めて一意にする
+fn entry_id(activity: &TodoActivity) -> String {
+    match activity.kind {
+        TodoActivityKind::Created => format!("urn:rust-web:todo:{}:created", activity.todo.id),
+        TodoActivityKind::Completed => format!(
+            "urn:rust-web:todo:{}:completed:{}",
+            activity.todo.id,
+            activity.at.timestamp()
+        ),
+    }
+}
+
+fn entry_title(activity: &TodoActivity) -> String {
+    match activity.kind {
+        TodoActivityKind::Created => format!("Created: {}", activity.todo.text),
+        TodoActivityKind::Completed => format!("Completed: {}", activity.todo.text),
+    }
+}
+
+fn kind(kind: TodoActivityKind) -> &'static str {
+    match kind {
+        TodoActivityKind::Created => "created",
+        TodoActivityKind::Completed => "completed",
+    }
+}
+
+fn rfc3339(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 で使えない制御文字は落とす
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::todo::{Priority, Status, Todo};
+    use chrono::TimeZone;
+
+    fn activity(kind: TodoActivityKind, at: DateTime<Utc>) -> TodoActivity {
+        TodoActivity {
+            kind,
+            at,
+            todo: Todo {
+                id: 1,
+                text: "fish & chips".to_string(),
+                description: Some("<b>now</b>".to_string()),
+                status: Status::Done,
+                user_id: 1,
+                workspace_id: None,
+                due_at: None,
+                starts_at: None,
+                priority: Priority::Medium,
+                parent_id: None,
+                recurrence: None,
+                archived_at: None,
+                position: 0,
+                project_id: None,
+                labels: vec![],
+                created_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+                assignee_id: None,
+                is_pinned: false,
+                snoozed_until: None,
+            },
+        }
+    }
+
+    #[test]
+    fn should_render_feeds() {
+        let completed_at = Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap();
+        let activity = vec![
+            activity(TodoActivityKind::Completed, completed_at),
+            activity(
+                TodoActivityKind::Created,
+                Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            ),
+        ];
+        let feed = Feed {
+            id: "urn:rust-web:feed:user:1".to_string(),
+            title: "Todos",
+            link: "/todos/feed.atom",
+            activity: &activity,
+            generated_at: Utc.with_ymd_and_hms(2023, 1, 3, 0, 0, 0).unwrap(),
+        };
+
+        let atom = render_atom(&feed);
+        assert!(atom.contains("<updated>2023-01-02T00:00:00Z</updated>\n  <link"));
+        assert!(atom.contains("<id>urn:rust-web:todo:1:completed:1672617600</id>"));
+        assert!(atom.contains("<title>Completed: fish &amp; chips</title>"));
+        assert!(atom.contains("<summary>&lt;b&gt;now&lt;/b&gt;</summary>"));
+        assert_eq!(atom.matches("<entry>").count(), 2);
+
+        let rss = render_rss(&feed);
+        assert!(rss.contains("<lastBuildDate>Mon, 2 Jan 2023 00:00:00 +0000</lastBuildDate>"));
+        assert!(rss.contains("<guid isPermaLink=\"false\">urn:rust-web:todo:1:created</guid>"));
+        assert_eq!(rss.matches("<item>").count(), 2);
+
+        // エントリーがなければ生成した日時
+        let empty = Feed {
+            activity: &[],
+            ..feed
+        };
+        assert!(render_atom(&empty).contains("<updated>2023-01-03T00:00:00Z</updated>"));
+    }
+}
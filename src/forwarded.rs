@@ -0,0 +1,184 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{header::HOST, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+// 前に置いたリバースプロキシ. ここから来たリクエストだけ X-Forwarded-For と X-Forwarded-Proto を信じる
+// 何も書かなければヘッダーは無視し、接続元のアドレスをそのまま使う
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+    // プロキシを通らないときの scheme. 自分で TLS を終端していれば https
+    scheme: &'static str,
+}
+
+impl Default for TrustedProxies {
+    fn default() -> Self {
+        Self::new(Vec::new(), false)
+    }
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>, tls: bool) -> Self {
+        Self {
+            networks,
+            scheme: if tls { "https" } else { "http" },
+        }
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    // X-Forwarded-For を右 (自分に近い側) から辿り、信頼していないアドレスが最初に現れたところを接続元とする
+    // それより左はクライアントが自由に書けるので見ない. 読めない値があればそこで止める
+    // X-Forwarded-Proto は一番前のプロキシが上書きしている前提で、先頭の値を使う
+    pub fn resolve<B>(&self, req: &Request<B>) -> ClientInfo {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let Some(peer) = peer.filter(|ip| self.trusts(*ip)) else {
+            return ClientInfo {
+                ip: peer,
+                scheme: self.scheme.to_string(),
+            };
+        };
+
+        let mut ip = peer;
+        let forwarded: Vec<&str> = header_values(req.headers(), X_FORWARDED_FOR).collect();
+        for value in forwarded.into_iter().rev() {
+            let Ok(forwarded) = value.parse::<IpAddr>() else {
+                break;
+            };
+            ip = forwarded;
+            if !self.trusts(forwarded) {
+                break;
+            }
+        }
+        let scheme = header_values(req.headers(), X_FORWARDED_PROTO)
+            .next()
+            .map(str::to_ascii_lowercase)
+            .filter(|scheme| scheme == "http" || scheme == "https")
+            .unwrap_or_else(|| self.scheme.to_string());
+        ClientInfo {
+            ip: Some(ip),
+            scheme,
+        }
+    }
+}
+
+// 同じヘッダーが何度あってもカンマ区切りの 1 つのリストとして読む
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+// 空の要素は読み飛ばす. 10.0.0.1 のように範囲を書かなければそのアドレスだけ
+pub fn parse_networks(list: &str) -> Result<Vec<IpNet>, Vec<String>> {
+    let (networks, errors): (Vec<_>, Vec<_>) = list
+        .split(',')
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| network.to_string())
+        })
+        .partition(Result::is_ok);
+    if errors.is_empty() {
+        Ok(networks.into_iter().map(Result::unwrap).collect())
+    } else {
+        Err(errors.into_iter().map(Result::unwrap_err).collect())
+    }
+}
+
+// プロキシの後ろにいるクライアント. レート制限やログイン失敗の記録、リダイレクト先の URL はこれを使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub scheme: String,
+}
+
+impl ClientInfo {
+    // クライアントから見たこのサーバーの origin. Host がなければ None
+    pub fn origin(&self, headers: &HeaderMap) -> Option<String> {
+        let host = headers.get(HOST)?.to_str().ok()?;
+        Some(format!("{}://{}", self.scheme, host))
+    }
+
+    pub fn is_https(&self) -> bool {
+        self.scheme == "https"
+    }
+}
+
+// TrustedProxies は create_app で Extension として登録されている前提
+// POST /batch のサブリクエストは元のリクエストで決めたものをそのまま使う
+pub async fn resolve_client<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    if req.extensions().get::<ClientInfo>().is_none() {
+        let proxies = req
+            .extensions()
+            .get::<TrustedProxies>()
+            .cloned()
+            .unwrap_or_default();
+        let client = proxies.resolve(&req);
+        req.extensions_mut().insert(client);
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_trust_forwarded_headers_from_proxies_only() {
+        let proxies =
+            TrustedProxies::new(parse_networks("10.0.0.0/8, 192.168.0.1").unwrap(), false);
+        let request = |peer: [u8; 4], forwarded_for: &str, proto: &str| {
+            let mut req = Request::builder()
+                .header(X_FORWARDED_FOR, forwarded_for)
+                .header(X_FORWARDED_PROTO, proto)
+                .body(())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+            req
+        };
+        let client = |ip: [u8; 4], scheme: &str| ClientInfo {
+            ip: Some(IpAddr::from(ip)),
+            scheme: scheme.to_string(),
+        };
+
+        // the client can put anything on the left, only the hop in front of the proxies counts
+        let req = request([10, 0, 0, 1], "1.1.1.1, 203.0.113.7, 10.0.0.2", "https");
+        assert_eq!(proxies.resolve(&req), client([203, 0, 113, 7], "https"));
+        // every hop is a proxy
+        let req = request([192, 168, 0, 1], "10.0.0.3", "HTTPS");
+        assert_eq!(proxies.resolve(&req), client([10, 0, 0, 3], "https"));
+        // garbage stops the walk at the last address that could be read
+        let req = request([10, 0, 0, 1], "203.0.113.7, unknown", "gopher");
+        assert_eq!(proxies.resolve(&req), client([10, 0, 0, 1], "http"));
+        // not a proxy, the headers are ignored
+        let req = request([198, 51, 100, 4], "203.0.113.7", "https");
+        assert_eq!(proxies.resolve(&req), client([198, 51, 100, 4], "http"));
+
+        assert_eq!(
+            parse_networks("10.0.0.0/33, ::1, proxy").unwrap_err().len(),
+            2
+        );
+    }
+}
@@ -1,15 +1,59 @@
+pub mod account;
+pub mod admin;
+pub mod attachment;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod batch;
+pub mod feed;
+pub mod health;
+pub mod idempotency;
+pub mod invitation;
 pub mod label;
+pub mod oauth;
+pub mod project;
+pub mod reminder;
+pub mod template;
 pub mod todo;
+pub mod webhook;
+pub mod workspace;
 
+use crate::body_limit;
+use crate::csv;
+use crate::msgpack::{self, CONTENT_TYPE_MSGPACK};
+use crate::repositories::{Page, PatchOperation, RepositoryError};
+use crate::services::ServiceError;
 use axum::{
     async_trait,
+    body::Bytes,
     extract::{FromRequest, RequestParts},
-    http::StatusCode,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, LINK, VARY},
+        HeaderMap, HeaderValue, Request, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
     BoxError, Json,
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
 use validator::Validate;
 
+const JSON_CONTENT_TYPE: &str = "application/json";
+// PATCH をこの Content-Type で送ると RFC 7396 の JSON Merge Patch として扱う
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+// PATCH をこの Content-Type で送ると RFC 6902 の JSON Patch として扱う
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+const CSV_CONTENT_TYPE: &str = "text/csv";
+// 一覧の総件数を返すレスポンスヘッダー
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+// per_page を省略したときの件数
+const DEFAULT_PER_PAGE: i64 = 50;
+// per_page の上限. これより大きい値は上限に切り詰める
+const MAX_PER_PAGE: i64 = 100;
+
+// Content-Type が application/msgpack なら MessagePack のボディも受け付ける
 #[derive(Debug)]
 pub struct ValidatedJson<T>(T);
 
@@ -26,10 +70,24 @@ where
     type Rejection = (StatusCode, String);
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+        let value = if content_type(req.headers()).as_deref() == Some(CONTENT_TYPE_MSGPACK) {
+            // MessagePack も一度 JSON の値にしてから T にするので、検証は JSON と同じ
+            let body = Bytes::from_request(req)
+                .await
+                .map_err(|rejection| (rejection_status(&rejection), rejection.to_string()))?;
+            msgpack::decode(&body)
+                .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    let message = format!("MessagePack parse error: [{}]", e);
+                    (StatusCode::BAD_REQUEST, message)
+                })?
+        } else {
+            let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
+                let message = format!("Json parse error: [{}]", rejection);
+                (rejection_status(&rejection), message)
+            })?;
+            value
+        };
         value.validate().map_err(|rejection| {
             let message = format!("Validation error: [{}]", rejection).replace('\n', ", ");
             (StatusCode::BAD_REQUEST, message)
@@ -37,3 +95,366 @@ where
         Ok(ValidatedJson(value))
     }
 }
+
+// PATCH のボディ. Content-Type が MERGE_PATCH_CONTENT_TYPE か JSON_PATCH_CONTENT_TYPE なら、
+// 当てる前の値がないと更新内容が決まらないので、そのまま受け取って handler で当てる
+#[derive(Debug)]
+pub enum PatchBody<T> {
+    Json(T),
+    MergePatch(Map<String, Value>),
+    JsonPatch(Vec<PatchOperation>),
+}
+
+#[async_trait]
+impl<T, B> FromRequest<B> for PatchBody<T>
+where
+    T: DeserializeOwned + Validate,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let bad_request = |rejection: axum::extract::rejection::JsonRejection| {
+            let message = format!("Json parse error: [{}]", rejection);
+            (rejection_status(&rejection), message)
+        };
+        match content_type(req.headers()).as_deref() {
+            Some(MERGE_PATCH_CONTENT_TYPE) => {
+                let Json(patch) = Json::from_request(req).await.map_err(bad_request)?;
+                Ok(PatchBody::MergePatch(patch))
+            }
+            Some(JSON_PATCH_CONTENT_TYPE) => {
+                let Json(operations) = Json::from_request(req).await.map_err(bad_request)?;
+                Ok(PatchBody::JsonPatch(operations))
+            }
+            _ => {
+                let ValidatedJson(value) = ValidatedJson::<T>::from_request(req).await?;
+                Ok(PatchBody::Json(value))
+            }
+        }
+    }
+}
+
+// 本文が上限を超えて読めなかったときだけ 413. それ以外の読めない本文は 400
+fn rejection_status(rejection: &(dyn std::error::Error + 'static)) -> StatusCode {
+    if body_limit::exceeded(rejection) {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+// パラメータを除いた小文字の Content-Type
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+}
+
+// 応答のボディの形式. negotiate_body が Accept から選び、Negotiated がその形式で書く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    MessagePack,
+}
+
+tokio::task_local! {
+    static BODY_FORMAT: BodyFormat;
+}
+
+// Accept で application/msgpack を JSON より優先したら、Negotiated の応答を MessagePack で書く
+pub async fn negotiate_body<B>(req: Request<B>, next: Next<B>) -> Response {
+    let offered = [mime::APPLICATION_JSON.as_ref(), CONTENT_TYPE_MSGPACK];
+    let format = match negotiate(req.headers(), &offered) {
+        Some(CONTENT_TYPE_MSGPACK) => BodyFormat::MessagePack,
+        _ => BodyFormat::Json,
+    };
+    let mut res = BODY_FORMAT.scope(format, next.run(req)).await;
+    let varies = res
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .any(|value| value == "accept");
+    if !varies {
+        res.headers_mut()
+            .append(VARY, HeaderValue::from_static("accept"));
+    }
+    res
+}
+
+// todo と label の handler が返すボディ. Json と同じように使え、negotiate_body が選んだ形式で書く
+// negotiate_body の外で作られたら JSON
+#[derive(Debug)]
+pub struct Negotiated<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let format = BODY_FORMAT
+            .try_with(|format| *format)
+            .unwrap_or(BodyFormat::Json);
+        match format {
+            BodyFormat::Json => Json(self.0).into_response(),
+            BodyFormat::MessagePack => match serde_json::to_value(&self.0) {
+                Ok(value) => (
+                    [(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_MSGPACK))],
+                    msgpack::encode(&value),
+                )
+                    .into_response(),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            },
+        }
+    }
+}
+
+// 一覧の ?page=&per_page= または ?cursor=&limit= のクエリパラメータ. page は 1 始まり
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Pagination {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    // 前のレスポンスの next_cursor. 省略したら先頭から
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// 一覧の取り出し方. cursor か limit を指定したら cursor ページング
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Paging {
+    Offset(Page),
+    Cursor { cursor: Option<String>, limit: i64 },
+}
+
+impl Pagination {
+    // page / per_page と cursor / limit を混ぜた場合は 400
+    fn paging(&self) -> Result<Paging, StatusCode> {
+        if self.cursor.is_none() && self.limit.is_none() {
+            return self.page().map(Paging::Offset);
+        }
+        if self.page.is_some() || self.per_page.is_some() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let limit = self.limit.unwrap_or(DEFAULT_PER_PAGE);
+        if limit < 1 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Paging::Cursor {
+            cursor: self.cursor.clone(),
+            limit: limit.min(MAX_PER_PAGE),
+        })
+    }
+
+    // 1 未満の page / per_page は 400
+    fn page(&self) -> Result<Page, StatusCode> {
+        let page = self.page.unwrap_or(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page < 1 || per_page < 1 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let limit = per_page.min(MAX_PER_PAGE);
+        Ok(Page {
+            limit,
+            offset: (page - 1).saturating_mul(limit),
+        })
+    }
+}
+
+// 一覧の ?fields=id,text のクエリパラメータ. 省略したら全てのフィールドを返す
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct FieldSelection {
+    pub fields: Option<String>,
+}
+
+impl FieldSelection {
+    // 各要素を JSON にしてから、指定したフィールドだけを残す. 知らないフィールド名は無視する
+    // 要素ごとに専用の struct を作らなくても、Serialize できる一覧ならそのまま絞り込める
+    fn select<T: Serialize>(&self, items: &[T]) -> Result<Vec<Value>, StatusCode> {
+        let fields: Option<Vec<&str>> = self.fields.as_ref().map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .collect()
+        });
+        if fields.as_ref().is_some_and(|fields| fields.is_empty()) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        items
+            .iter()
+            .map(|item| {
+                let mut value =
+                    serde_json::to_value(item).or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+                if let (Some(fields), Value::Object(object)) = (&fields, &mut value) {
+                    object.retain(|key, _| fields.contains(&key.as_str()));
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+}
+
+impl FieldSelection {
+    // CSV の列. fields を指定したらその順に並べ、all にないフィールド名は無視する
+    fn columns(&self, all: &[&'static str]) -> Vec<&'static str> {
+        let Some(fields) = &self.fields else {
+            return all.to_vec();
+        };
+        fields
+            .split(',')
+            .map(str::trim)
+            .filter_map(|field| all.iter().copied().find(|column| *column == field))
+            .collect()
+    }
+}
+
+// Accept で text/csv を JSON より優先したときだけ CSV で返す. それ以外は今まで通り JSON
+fn wants_csv(headers: &HeaderMap) -> bool {
+    negotiate(headers, &[JSON_CONTENT_TYPE, CSV_CONTENT_TYPE]) == Some(CSV_CONTENT_TYPE)
+}
+
+// 一覧のレスポンス. ページ送りのヘッダーは JSON と同じものを付ける
+fn list_response(
+    csv_columns: Option<Vec<&str>>,
+    rows: Vec<Value>,
+    mut headers: HeaderMap,
+) -> Response {
+    headers.insert(VARY, HeaderValue::from_static("accept"));
+    match csv_columns {
+        Some(columns) => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/csv; charset=utf-8"),
+            );
+            (StatusCode::OK, headers, csv::render(&columns, &rows)).into_response()
+        }
+        None => (StatusCode::OK, headers, Negotiated(rows)).into_response(),
+    }
+}
+
+// X-Total-Count と、前後のページがあればそのページへの Link ヘッダー
+// Link の URL には uri の page / per_page 以外のクエリパラメータをそのまま残す
+fn pagination_headers(uri: &Uri, page: Page, total: i64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+
+    let query: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "page" && key != "per_page"
+        })
+        .collect();
+    let link = |number: i64, rel: &str| {
+        let pagination = format!("page={}&per_page={}", number, page.limit);
+        let query: Vec<&str> = query.iter().copied().chain([pagination.as_str()]).collect();
+        format!("<{}?{}>; rel=\"{}\"", uri.path(), query.join("&"), rel)
+    };
+    let current = page.offset / page.limit + 1;
+    let mut links = vec![];
+    if page.offset + page.limit < total {
+        links.push(link(current + 1, "next"));
+    }
+    if current > 1 {
+        links.push(link(current - 1, "prev"));
+    }
+    if !links.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+            headers.insert(LINK, value);
+        }
+    }
+    headers
+}
+
+// JSON にした内容の SHA-256 から作る strong な ETag. どのフィールドが変わっても値が変わる
+fn entity_tag<T: Serialize>(value: &T) -> String {
+    let json = serde_json::to_vec(value).unwrap_or_default();
+    format!("\"{:x}\"", Sha256::digest(json))
+}
+
+// If-None-Match / If-Match のどれかが etag に一致するか. * はどの etag にも一致する
+// weak なら W/ 付きの ETag も一致とみなす (If-None-Match の比較)
+fn etag_matches(value: &HeaderValue, etag: &str, weak: bool) -> bool {
+    value.to_str().is_ok_and(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag == etag || (weak && tag.strip_prefix("W/") == Some(etag)))
+    })
+}
+
+// Accept ヘッダーから、offered のうち q の最も高い media type を選ぶ. 同じ q なら offered の順
+// Accept がなければ offered の先頭を、どれも受け付けられなければ None を返すので 406 にする
+fn negotiate<'a>(headers: &HeaderMap, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = headers.get(ACCEPT) else {
+        return offered.first().copied();
+    };
+    let ranges: Vec<(String, f32)> = accept
+        .to_str()
+        .ok()?
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_range = params
+                .next()
+                .filter(|media_range| !media_range.is_empty())?;
+            let q = match params.find_map(|param| param.strip_prefix("q=")) {
+                Some(q) => q.parse().ok()?,
+                None => 1.0,
+            };
+            Some((media_range.to_ascii_lowercase(), q))
+        })
+        .collect();
+
+    let mut best: Option<(&str, f32)> = None;
+    for &offer in offered {
+        let (main_type, _) = offer.split_once('/').unwrap_or((offer, ""));
+        // */* より type/*、type/* より完全に一致する範囲の q を優先する
+        let q = ranges
+            .iter()
+            .filter_map(|(range, q)| {
+                let specificity = match range.as_str() {
+                    range if range == offer => 2,
+                    range if range.strip_suffix("/*") == Some(main_type) => 1,
+                    "*/*" => 0,
+                    _ => return None,
+                };
+                Some((specificity, *q))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, q)| q);
+        match (q, best) {
+            (Some(q), Some((_, best_q))) if q > best_q => best = Some((offer, q)),
+            (Some(q), None) if q > 0.0 => best = Some((offer, q)),
+            _ => {}
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+// repository や service から返ってきたエラーをレスポンスのステータスコードに変換する
+fn error_status(e: anyhow::Error) -> StatusCode {
+    if let Some(e) = e.downcast_ref::<ServiceError>() {
+        return match e {
+            ServiceError::InvalidTransition(..)
+            | ServiceError::TooManyPinned(_)
+            | ServiceError::UndoConflict(_) => StatusCode::CONFLICT,
+            ServiceError::UnavailableProject(_)
+            | ServiceError::UnavailableLabel(_)
+            | ServiceError::UnassignableUser(_) => StatusCode::BAD_REQUEST,
+            ServiceError::NothingToUndo => StatusCode::NOT_FOUND,
+            ServiceError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+        };
+    }
+    match e.downcast_ref::<RepositoryError>() {
+        Some(RepositoryError::NotFound(_)) => StatusCode::NOT_FOUND,
+        Some(RepositoryError::Forbidden(_)) => StatusCode::FORBIDDEN,
+        Some(RepositoryError::Duplicate(_)) => StatusCode::CONFLICT,
+        Some(RepositoryError::InUse(_)) => StatusCode::CONFLICT,
+        Some(RepositoryError::Invalid(_)) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -0,0 +1,81 @@
+use super::{attachment::remove_blobs, error_status};
+use crate::auth::CurrentUser;
+use crate::repositories::{audit::AuditEvent, label::Label, todo::Todo, user::User};
+use crate::state::AppState;
+use axum::{
+    extract::Extension,
+    http::{header::CONTENT_DISPOSITION, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// 本人が保持しているデータ一式. 共有されただけの他人の todo は含めない
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_at: DateTime<Utc>,
+    pub user: User,
+    pub todos: Vec<Todo>,
+    pub labels: Vec<Label>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn export_account(
+    Extension(current_user): Extension<CurrentUser>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = state
+        .user
+        .find(current_user.id)
+        .await
+        .map_err(error_status)?;
+    let todos = state
+        .todo
+        .all_by_user(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let labels = state
+        .label
+        .find_by_user(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let audit_events = state
+        .audit
+        .all_by_actor(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let export = AccountExport {
+        exported_at: Utc::now(),
+        user,
+        todos,
+        labels,
+        audit_events,
+    };
+    Ok((
+        StatusCode::OK,
+        [(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"account-{}.json\"", current_user.id),
+        )],
+        Json(export),
+    ))
+}
+
+// アカウントとそのデータをすべて削除する. 取り消しはできない
+#[tracing::instrument(skip_all)]
+pub async fn delete_account(
+    Extension(current_user): Extension<CurrentUser>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let storage_keys = state
+        .user
+        .delete(current_user.id)
+        .await
+        .map_err(error_status)?;
+    remove_blobs(state.blob_store.as_ref(), &storage_keys).await;
+    tracing::info!("user {} deleted their account", current_user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
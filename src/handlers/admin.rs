@@ -0,0 +1,155 @@
+use super::{attachment::remove_blobs, error_status, label::DeleteLabelQuery};
+use crate::auth::{Admin, RequireRole};
+use crate::repositories::audit::{AuditAction, AuditEntity};
+use crate::services::audit::record_event;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+#[tracing::instrument(skip_all)]
+pub async fn all_users_todo(
+    _: RequireRole<Admin>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todos = state
+        .todo
+        .all_unscoped()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_any_label(
+    RequireRole(admin, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Query(query): Query<DeleteLabelQuery>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mut tx = state.label.begin().await.map_err(error_status)?;
+    let label = state
+        .label
+        .find_for_update_in(&mut tx, id)
+        .await
+        .map_err(error_status)?;
+    state
+        .label
+        .delete_in(&mut tx, id, query.mode)
+        .await
+        .map_err(error_status)?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        admin.id,
+        AuditAction::Delete,
+        AuditEntity::Label,
+        id,
+        Some(&label),
+        None,
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    tracing::info!("label {} deleted by admin {}", id, admin.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_users(
+    _: RequireRole<Admin>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let users = state
+        .user
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(users)))
+}
+
+// 管理者が自分自身を締め出さないよう、自分への操作は受け付けない
+fn check_not_self(admin_id: i32, id: i32) -> Result<(), StatusCode> {
+    if admin_id == id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn disable_user(
+    RequireRole(admin, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_not_self(admin.id, id)?;
+    let user = state
+        .user
+        .set_disabled(id, true)
+        .await
+        .map_err(error_status)?;
+    // 発行済みのアクセストークンは期限切れまで使えるが、リフレッシュはさせない
+    state
+        .refresh_token
+        .revoke_all(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    tracing::info!("user {} disabled by admin {}", id, admin.id);
+    Ok((StatusCode::OK, Json(user)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn enable_user(
+    RequireRole(admin, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = state
+        .user
+        .set_disabled(id, false)
+        .await
+        .map_err(error_status)?;
+    tracing::info!("user {} enabled by admin {}", id, admin.id);
+    Ok((StatusCode::OK, Json(user)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn require_password_reset(
+    RequireRole(admin, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_not_self(admin.id, id)?;
+    let user = state
+        .user
+        .require_password_reset(id)
+        .await
+        .map_err(error_status)?;
+    state
+        .refresh_token
+        .revoke_all(id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    tracing::info!(
+        "password reset of user {} required by admin {}",
+        id,
+        admin.id
+    );
+    Ok((StatusCode::OK, Json(user)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_user(
+    RequireRole(admin, _): RequireRole<Admin>,
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_not_self(admin.id, id)?;
+    let storage_keys = state.user.delete(id).await.map_err(error_status)?;
+    remove_blobs(state.blob_store.as_ref(), &storage_keys).await;
+    tracing::info!("user {} deleted by admin {}", id, admin.id);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,221 @@
+use super::error_status;
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::blob_store::BlobStore;
+use crate::body_limit;
+use crate::repositories::attachment::{storage_key, NewAttachment};
+use crate::repositories::todo::{Todo, TodoRepository};
+use crate::repositories::Scope;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Multipart, Path},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        StatusCode,
+    },
+    response::IntoResponse,
+    Json,
+};
+
+// 1 ファイルあたりの上限
+const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+
+// 追加と削除は todo の所有者 (workspace ならメンバー) だけ. 共有されたユーザーは閲覧とダウンロードだけできる
+async fn find_owned_todo(
+    repo: &dyn TodoRepository,
+    scope: Scope,
+    id: i32,
+) -> Result<Todo, StatusCode> {
+    let todo = repo.find(scope, id).await.map_err(error_status)?;
+    if !todo.is_visible_in(scope) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(todo)
+}
+
+// パスの区切りや制御文字を取り除いたファイル名. 空になったら "file"
+fn sanitize_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect();
+    match name.trim() {
+        "" => "file".to_string(),
+        name => name.to_string(),
+    }
+}
+
+// multipart の "file" フィールドを 1 つ受け取る
+#[tracing::instrument(skip_all)]
+pub async fn upload_attachment(
+    Path(todo_id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, StatusCode> {
+    find_owned_todo(state.todo.as_ref(), workspace.scope(user), todo_id).await?;
+
+    let mut field = loop {
+        match multipart
+            .next_field()
+            .await
+            .or(Err(StatusCode::BAD_REQUEST))?
+        {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => return Err(StatusCode::BAD_REQUEST),
+        }
+    };
+    let file_name = sanitize_file_name(field.file_name().unwrap_or_default());
+    let content_type = field
+        .content_type()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM.as_ref())
+        .to_string();
+    // 上限を超えた時点で読むのをやめる
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        if body_limit::exceeded(&e) {
+            StatusCode::PAYLOAD_TOO_LARGE
+        } else {
+            StatusCode::BAD_REQUEST
+        }
+    })? {
+        if bytes.len() + chunk.len() > MAX_ATTACHMENT_BYTES {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let storage_key = storage_key(todo_id);
+    let size = bytes.len() as i64;
+    state
+        .blob_store
+        .put(&storage_key, &content_type, bytes.into())
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to store attachment: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let res = state
+        .attachment
+        .create(NewAttachment {
+            todo_id,
+            user_id: user.id,
+            file_name,
+            content_type,
+            size,
+            storage_key: storage_key.clone(),
+        })
+        .await;
+    match res {
+        Ok(attachment) => Ok((StatusCode::CREATED, Json(attachment))),
+        Err(_) => {
+            // メタデータのない中身は参照できないので消しておく
+            state.blob_store.delete(&storage_key).await.ok();
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_attachments(
+    Path(todo_id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .todo
+        .find(workspace.scope(user), todo_id)
+        .await
+        .map_err(error_status)?;
+    let attachments = state
+        .attachment
+        .all_by_todo(todo_id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(attachments)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn download_attachment(
+    Path((todo_id, id)): Path<(i32, i32)>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .todo
+        .find(workspace.scope(user), todo_id)
+        .await
+        .map_err(error_status)?;
+    let attachment = state
+        .attachment
+        .find(todo_id, id)
+        .await
+        .map_err(error_status)?;
+    let bytes = state
+        .blob_store
+        .get(&attachment.storage_key)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to read attachment {}: {}", attachment.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // アップロードされた content type のままブラウザに解釈させないよう、必ずダウンロードさせる
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, attachment.content_type),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", attachment.file_name),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        bytes,
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_attachment(
+    Path((todo_id, id)): Path<(i32, i32)>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    find_owned_todo(state.todo.as_ref(), workspace.scope(user), todo_id).await?;
+    let attachment = state
+        .attachment
+        .delete(todo_id, id)
+        .await
+        .map_err(error_status)?;
+    remove_blobs(state.blob_store.as_ref(), &[attachment.storage_key]).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// メタデータはすでに消えているので、中身の削除に失敗してもログに残すだけにする
+#[tracing::instrument(skip_all)]
+pub async fn remove_blobs(blob_store: &dyn BlobStore, storage_keys: &[String]) {
+    for key in storage_keys {
+        if let Err(e) = blob_store.delete(key).await {
+            tracing::error!("failed to delete attachment blob {}: {}", key, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("memo.txt"), "memo.txt");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("C:\\Users\\me\\a\"b.txt"), "ab.txt");
+        assert_eq!(sanitize_file_name("dir/"), "file");
+        assert_eq!(sanitize_file_name(""), "file");
+    }
+}
@@ -0,0 +1,16 @@
+use crate::auth::{Admin, RequireRole};
+use crate::state::AppState;
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+
+#[tracing::instrument(skip_all)]
+pub async fn all_audit_events(
+    _: RequireRole<Admin>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let events = state
+        .audit
+        .all()
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(events)))
+}
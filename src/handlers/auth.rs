@@ -0,0 +1,401 @@
+use super::{attachment::remove_blobs, error_status, ValidatedJson};
+use crate::auth::{
+    generate_token, hash_password, hash_token, verify_password, CurrentUser, JwtKeys,
+    REFRESH_TOKEN_LIFETIME_DAYS,
+};
+use crate::forwarded::ClientInfo;
+use crate::lockout::{Locked, LoginLockout};
+use crate::repositories::{
+    login_attempt::LoginAttemptRepository,
+    refresh_token::{CreateRefreshToken, RefreshTokenRepository},
+    user::{CreateUser, User, UserRepository},
+};
+use crate::state::AppState;
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use validator::Validate;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct Credentials {
+    #[validate(email(message = "Invalid email"))]
+    pub email: String,
+    #[validate(length(min = 8, message = "Too short password"))]
+    #[validate(length(max = 128, message = "Over password length"))]
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct ChangePassword {
+    #[validate(email(message = "Invalid email"))]
+    email: String,
+    current_password: String,
+    #[validate(length(min = 8, message = "Too short password"))]
+    #[validate(length(max = 128, message = "Over password length"))]
+    new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuthBody {
+    pub access_token: String,
+    pub token_type: String,
+    pub refresh_token: String,
+}
+
+impl AuthBody {
+    pub fn new(access_token: String, refresh_token: String) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer".to_string(),
+            refresh_token,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RefreshPayload {
+    refresh_token: String,
+}
+
+// ゲストには通常のトークンに加えて、アカウントに引き継ぐときに使う端末のトークンを返す
+// 端末のトークンは再発行できないので、クライアントが保存しておく
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct GuestBody {
+    pub device_token: String,
+    #[serde(flatten)]
+    pub auth: AuthBody,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClaimPayload {
+    device_token: String,
+}
+
+// パスワードで認証する handler のエラー. ロック中は 423 を返す
+#[derive(Debug)]
+pub enum LoginError {
+    Status(StatusCode),
+    Locked(Locked),
+}
+
+impl From<StatusCode> for LoginError {
+    fn from(status: StatusCode) -> Self {
+        LoginError::Status(status)
+    }
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+        match self {
+            LoginError::Status(status) => status.into_response(),
+            LoginError::Locked(locked) => locked.into_response(),
+        }
+    }
+}
+
+async fn check_lockout(
+    attempts: &dyn LoginAttemptRepository,
+    lockout: &LoginLockout,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), LoginError> {
+    let now = Utc::now();
+    let since = lockout.since(now);
+    let failures = attempts
+        .account_failures(email, since)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let mut locked_until = lockout.locked_until(&failures, lockout.threshold, now);
+    if let Some(ip) = ip {
+        let failures = attempts
+            .ip_failures(ip, since)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        locked_until = locked_until.max(lockout.locked_until(&failures, lockout.ip_threshold, now));
+    }
+
+    match locked_until {
+        Some(unlock_after) => {
+            tracing::warn!("login locked: {} from {:?}", email, ip);
+            Err(LoginError::Locked(Locked(unlock_after)))
+        }
+        None => Ok(()),
+    }
+}
+
+// メールアドレスとパスワードで本人確認をする
+// 失敗はアカウントと IP ごとに記録し、続いた場合はロックする
+async fn authenticate(
+    repo: &dyn UserRepository,
+    attempts: &dyn LoginAttemptRepository,
+    lockout: &LoginLockout,
+    email: &str,
+    password: &str,
+    ip: Option<IpAddr>,
+) -> Result<User, LoginError> {
+    check_lockout(attempts, lockout, email, ip).await?;
+    // ユーザーが存在しない場合とパスワード違いを区別させないため、どちらも 401 を返す
+    // 外部 IdP でのみ登録されたユーザーはパスワードでログインできない
+    let verified = repo.find_by_email(email).await.ok().filter(|user| {
+        user.password_hash
+            .as_deref()
+            .is_some_and(|hash| verify_password(password, hash))
+    });
+
+    match verified {
+        Some(user) => {
+            attempts
+                .clear(email)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            Ok(user)
+        }
+        None => {
+            attempts
+                .record_failure(email, ip)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            // この失敗でロックされた場合はそれを伝える
+            check_lockout(attempts, lockout, email, ip).await?;
+            Err(StatusCode::UNAUTHORIZED.into())
+        }
+    }
+}
+
+// ログインに成功したユーザーにアクセストークンと新しい family のリフレッシュトークンを発行する
+#[tracing::instrument(skip_all)]
+pub async fn issue_tokens(
+    refresh_repo: &dyn RefreshTokenRepository,
+    keys: &JwtKeys,
+    user: &User,
+) -> Result<AuthBody, StatusCode> {
+    // 管理者に無効化されたユーザーにはトークンを発行しない
+    if user.is_disabled() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let access_token = keys
+        .issue(user.id, user.role)
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let refresh_token = generate_token();
+    refresh_repo
+        .create(user.id, new_refresh_token(&refresh_token))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok(AuthBody::new(access_token, refresh_token))
+}
+
+fn new_refresh_token(token: &str) -> CreateRefreshToken {
+    CreateRefreshToken {
+        token_hash: hash_token(token),
+        expires_at: Utc::now() + Duration::days(REFRESH_TOKEN_LIFETIME_DAYS),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn register(
+    ValidatedJson(payload): ValidatedJson<Credentials>,
+    Extension(state): Extension<AppState>,
+    Extension(keys): Extension<JwtKeys>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let password_hash =
+        hash_password(&payload.password).or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let user = state
+        .user
+        .create(CreateUser {
+            email: payload.email,
+            password_hash,
+        })
+        .await
+        .map_err(error_status)?;
+    let body = issue_tokens(state.refresh_token.as_ref(), &keys, &user).await?;
+
+    Ok((StatusCode::CREATED, Json(body)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn login(
+    ValidatedJson(payload): ValidatedJson<Credentials>,
+    Extension(state): Extension<AppState>,
+    Extension(lockout): Extension<LoginLockout>,
+    Extension(keys): Extension<JwtKeys>,
+    Extension(client): Extension<ClientInfo>,
+) -> Result<impl IntoResponse, LoginError> {
+    let user = authenticate(
+        state.user.as_ref(),
+        state.login_attempt.as_ref(),
+        &lockout,
+        &payload.email,
+        &payload.password,
+        client.ip,
+    )
+    .await?;
+    // パスワードの変更を強制されている場合は /auth/password で変更してもらう
+    if user.password_reset_required {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+    let body = issue_tokens(state.refresh_token.as_ref(), &keys, &user).await?;
+
+    Ok((StatusCode::OK, Json(body)))
+}
+
+// 現在のパスワードで本人確認をしてからパスワードを変更する
+// パスワードの変更を強制されたユーザーはログインできないので、認証なしで受け付ける
+#[tracing::instrument(skip_all)]
+pub async fn change_password(
+    ValidatedJson(payload): ValidatedJson<ChangePassword>,
+    Extension(state): Extension<AppState>,
+    Extension(lockout): Extension<LoginLockout>,
+    Extension(keys): Extension<JwtKeys>,
+    Extension(client): Extension<ClientInfo>,
+) -> Result<impl IntoResponse, LoginError> {
+    // ログインと同じく、総当たりされないよう失敗を数える
+    let user = authenticate(
+        state.user.as_ref(),
+        state.login_attempt.as_ref(),
+        &lockout,
+        &payload.email,
+        &payload.current_password,
+        client.ip,
+    )
+    .await?;
+    if user.is_disabled() {
+        return Err(StatusCode::FORBIDDEN.into());
+    }
+    let password_hash =
+        hash_password(&payload.new_password).or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let user = state
+        .user
+        .update_password(user.id, password_hash)
+        .await
+        .map_err(error_status)?;
+    // 古いパスワードで発行されたセッションはすべて失効させる
+    state
+        .refresh_token
+        .revoke_all(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let body = issue_tokens(state.refresh_token.as_ref(), &keys, &user).await?;
+
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn refresh(
+    Json(payload): Json<RefreshPayload>,
+    Extension(state): Extension<AppState>,
+    Extension(keys): Extension<JwtKeys>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // 使ったトークンは失効させ、同じ family で新しいトークンを返す
+    let refresh_token = generate_token();
+    let rotated = state
+        .refresh_token
+        .rotate(
+            &hash_token(&payload.refresh_token),
+            new_refresh_token(&refresh_token),
+        )
+        .await
+        .map_err(|e| match error_status(e) {
+            StatusCode::INTERNAL_SERVER_ERROR => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        })?;
+    // ロールが変わっている可能性があるので、最新のユーザー情報からアクセストークンを作る
+    let user = state
+        .user
+        .find(rotated.user_id)
+        .await
+        .or(Err(StatusCode::UNAUTHORIZED))?;
+    if user.is_disabled() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let access_token = keys
+        .issue(user.id, user.role)
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(AuthBody::new(access_token, refresh_token)),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn logout_all(
+    Extension(current_user): Extension<CurrentUser>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .refresh_token
+        .revoke_all(current_user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn me(
+    Extension(current_user): Extension<CurrentUser>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = state
+        .user
+        .find(current_user.id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    Ok((StatusCode::OK, Json(user)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_guest(
+    Extension(state): Extension<AppState>,
+    Extension(keys): Extension<JwtKeys>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let device_token = generate_token();
+    let user = state
+        .user
+        .create_guest(hash_token(&device_token))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let auth = issue_tokens(state.refresh_token.as_ref(), &keys, &user).await?;
+
+    Ok((StatusCode::CREATED, Json(GuestBody { device_token, auth })))
+}
+
+// ゲストとして作った todo をログイン中のアカウントに引き継ぎ、ゲストユーザーは削除する
+// workspace のデータは引き継がない
+#[tracing::instrument(skip_all)]
+pub async fn claim(
+    Extension(current_user): Extension<CurrentUser>,
+    Json(payload): Json<ClaimPayload>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = state
+        .user
+        .find(current_user.id)
+        .await
+        .map_err(error_status)?;
+    if user.guest {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let guest = state
+        .user
+        .find_guest(&hash_token(&payload.device_token))
+        .await
+        .map_err(error_status)?;
+    let todos = state
+        .todo
+        .merge(guest.id, user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    // 添付ファイルは todo と一緒に移っているので、残っていた分だけ消す
+    let storage_keys = state.user.delete(guest.id).await.map_err(error_status)?;
+    remove_blobs(state.blob_store.as_ref(), &storage_keys).await;
+    tracing::info!("guest {} claimed by user {}", guest.id, user.id);
+
+    Ok((StatusCode::OK, Json(todos)))
+}
@@ -0,0 +1,59 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::repositories::backup::{Backup, ConflictStrategy};
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Query},
+    http::{header::CONTENT_DISPOSITION, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+// POST /import のクエリパラメータ. 省略したら skip
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn export_backup(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let backup = state
+        .backup
+        .export(workspace.scope(user))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((
+        StatusCode::OK,
+        [(
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"backup-{}.json\"",
+                backup.exported_at.format("%Y%m%d%H%M%S")
+            ),
+        )],
+        Json(backup),
+    ))
+}
+
+// 作った todo / ラベルと上書きした todo は、repository が同じ tx で監査ログに残す
+#[tracing::instrument(skip_all)]
+pub async fn import_backup(
+    Query(query): Query<ImportQuery>,
+    ValidatedJson(backup): ValidatedJson<Backup>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let summary = state
+        .backup
+        .import(workspace.scope(user), backup, query.strategy)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(summary)))
+}
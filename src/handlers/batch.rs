@@ -0,0 +1,141 @@
+use super::ValidatedJson;
+use crate::auth::WORKSPACE_HEADER;
+use crate::client::{ServiceTransport, Transport};
+use crate::forwarded::ClientInfo;
+use axum::{
+    extract::Extension,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    response::IntoResponse,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{str::FromStr, sync::Arc};
+use validator::{Validate, ValidationError};
+
+// サブリクエストに引き継ぐヘッダー. 認証とアクティブな workspace はまとめたリクエストと同じ
+const FORWARDED_HEADERS: [&str; 2] = ["authorization", WORKSPACE_HEADER];
+
+// サブリクエストを流す先. create_app の全ての layer を通した Router を持つ
+pub type Dispatcher = Arc<ServiceTransport<Router>>;
+
+// POST /batch のリクエスト. 書いた順に 1 件ずつ処理する
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(transparent)]
+pub struct BatchBody {
+    #[validate]
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 20, message = "Over requests length"))]
+    pub requests: Vec<SubRequest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+pub struct SubRequest {
+    #[validate(custom = "validate_method")]
+    pub method: String,
+    // /v1/todos?status=done のようにクエリも含める. バージョンを付けなければ v1
+    #[validate(custom = "validate_path")]
+    pub path: String,
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+// body は JSON ならそのまま、それ以外は文字列、空なら null
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubResponse {
+    pub status: u16,
+    pub body: Option<Value>,
+}
+
+fn validate_method(method: &str) -> Result<(), ValidationError> {
+    Method::from_str(&method.to_ascii_uppercase())
+        .map(|_| ())
+        .or(Err(ValidationError::new("invalid method")))
+}
+
+// /batch の中で /batch を呼ぶことはできない
+fn validate_path(path: &str) -> Result<(), ValidationError> {
+    if !path.starts_with('/') {
+        return Err(ValidationError::new("path must start with /"));
+    }
+    let route = path.split(['?', '#']).next().unwrap_or_default();
+    if ["/batch", "/v1/batch", "/v2/batch"].contains(&route.trim_end_matches('/')) {
+        return Err(ValidationError::new("batch can not be nested"));
+    }
+    Ok(())
+}
+
+// それぞれのサブリクエストは個別に送ったときと同じく認証やレート制限を受ける
+// 途中で失敗しても残りは処理し、結果はリクエストと同じ順に返す
+#[tracing::instrument(skip_all)]
+pub async fn batch(
+    ValidatedJson(payload): ValidatedJson<BatchBody>,
+    Extension(dispatcher): Extension<Dispatcher>,
+    headers: HeaderMap,
+    Extension(client): Extension<ClientInfo>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mut responses = Vec::with_capacity(payload.requests.len());
+    for sub in payload.requests {
+        let mut req = Request::builder()
+            .method(sub.method.to_ascii_uppercase().as_str())
+            .uri(&sub.path);
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = headers.get(name) {
+                req = req.header(name, value);
+            }
+        }
+        let body = match &sub.body {
+            Some(body) => {
+                req = req.header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+                serde_json::to_vec(body).or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+            }
+            None => Vec::new(),
+        };
+        let Ok(mut req) = req.body(body) else {
+            responses.push(SubResponse {
+                status: StatusCode::BAD_REQUEST.as_u16(),
+                body: None,
+            });
+            continue;
+        };
+        req.extensions_mut().insert(client.clone());
+
+        let (status, body) = dispatcher
+            .send(req)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+        let body = if body.is_empty() {
+            None
+        } else {
+            serde_json::from_slice(&body)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()))
+                .into()
+        };
+        responses.push(SubResponse {
+            status: status.as_u16(),
+            body,
+        });
+    }
+    Ok((StatusCode::OK, Json(responses)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_validate_sub_requests() {
+        let sub = |method: &str, path: &str| SubRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: None,
+        };
+        assert!(sub("GET", "/v1/todos?status=done").validate().is_ok());
+        assert!(sub("patch", "/todos/1").validate().is_ok());
+        assert!(sub("GET", "todos").validate().is_err());
+        assert!(sub("G ET", "/todos").validate().is_err());
+        assert!(sub("POST", "/batch").validate().is_err());
+        assert!(sub("POST", "/v1/batch/?x=1").validate().is_err());
+        assert!(BatchBody { requests: vec![] }.validate().is_err());
+    }
+}
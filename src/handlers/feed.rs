@@ -0,0 +1,205 @@
+use super::negotiate;
+use crate::auth::{ActiveWorkspace, CurrentUser, JwtKeys};
+use crate::feed::{self, Feed};
+use crate::ical::{self, Component};
+use crate::repositories::todo::TodoFilter;
+use crate::repositories::user::UserRepository;
+use crate::repositories::workspace::WorkspaceRepository;
+use crate::repositories::Scope;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Query},
+    http::{
+        header::{CONTENT_TYPE, VARY},
+        HeaderMap, StatusCode,
+    },
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+const CALENDAR_PATH: &str = "/todos/calendar.ics";
+const ATOM_PATH: &str = "/todos/feed.atom";
+const ATOM_CONTENT_TYPE: &str = "application/atom+xml";
+const RSS_CONTENT_TYPE: &str = "application/rss+xml";
+// フィードに載せる出来事の数
+const FEED_ENTRIES: usize = 50;
+
+// GET /todos/calendar.ics のクエリパラメータ
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalendarQuery {
+    pub token: String,
+    #[serde(default)]
+    pub component: Component,
+}
+
+// GET /todos/feed.atom のクエリパラメータ
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeedQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedBody {
+    pub token: String,
+    // カレンダーアプリやフィードリーダーに登録するパス. ホスト名はクライアント側で付ける
+    pub calendar_url: String,
+    pub atom_url: String,
+}
+
+// アクティブな workspace の購読用トークンを発行する. 何度発行しても以前のトークンは DELETE するまで使える
+#[tracing::instrument(skip_all)]
+pub async fn feed_token(
+    Extension(keys): Extension<JwtKeys>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = state
+        .user
+        .find(user.id)
+        .await
+        .or(Err(StatusCode::NOT_FOUND))?;
+    let token = keys
+        .issue_feed(user.id, workspace.0, user.feed_token_version)
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let calendar_url = format!("{}?token={}", CALENDAR_PATH, token);
+    let atom_url = format!("{}?token={}", ATOM_PATH, token);
+    Ok((
+        StatusCode::OK,
+        Json(FeedBody {
+            token,
+            calendar_url,
+            atom_url,
+        }),
+    ))
+}
+
+// DELETE /me/feeds. URL が漏れたときのために、それまでに発行した購読用トークンを全て使えなくする
+#[tracing::instrument(skip_all)]
+pub async fn revoke_feed_tokens(
+    Extension(user): Extension<CurrentUser>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .user
+        .rotate_feed_token(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Authorization ヘッダーの代わりにクエリパラメータのトークンで認証する
+// 無効化されたユーザーや、workspace から外れたユーザーのトークン、取り消したトークンは使えない
+async fn authorize_feed(
+    keys: &JwtKeys,
+    token: &str,
+    user_repo: &dyn UserRepository,
+    workspace_repo: &dyn WorkspaceRepository,
+) -> Result<Scope, StatusCode> {
+    let claims = keys.verify_feed(token).or(Err(StatusCode::UNAUTHORIZED))?;
+    let user = user_repo
+        .find(claims.sub)
+        .await
+        .or(Err(StatusCode::UNAUTHORIZED))?;
+    if claims.ver != user.feed_token_version {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    if user.is_disabled() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Some(workspace_id) = claims.workspace_id {
+        workspace_repo
+            .find_membership(workspace_id, user.id)
+            .await
+            .or(Err(StatusCode::FORBIDDEN))?;
+    }
+    Ok(Scope::new(user.id, claims.workspace_id))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn calendar_feed(
+    Query(query): Query<CalendarQuery>,
+    Extension(keys): Extension<JwtKeys>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let scope = authorize_feed(
+        &keys,
+        &query.token,
+        state.user.as_ref(),
+        state.workspace.as_ref(),
+    )
+    .await?;
+    // 開始前の todo も予定としては載せる
+    let mut todos = state
+        .todo
+        .all(scope, &TodoFilter::default())
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let upcoming = TodoFilter {
+        upcoming: Some(true),
+        ..Default::default()
+    };
+    todos.extend(
+        state
+            .todo
+            .all(scope, &upcoming)
+            .await
+            .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?,
+    );
+    let calendar = ical::render_calendar("Todos", &todos, query.component, Utc::now());
+    Ok((
+        StatusCode::OK,
+        [(CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        calendar,
+    ))
+}
+
+// 最近作成・完了した todo のフィード. Accept で RSS を優先したときだけ RSS 2.0 で返す
+#[tracing::instrument(skip_all)]
+pub async fn atom_feed(
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+    Extension(keys): Extension<JwtKeys>,
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let content_type = negotiate(&headers, &[ATOM_CONTENT_TYPE, RSS_CONTENT_TYPE])
+        .ok_or(StatusCode::NOT_ACCEPTABLE)?;
+    let scope = authorize_feed(
+        &keys,
+        &query.token,
+        state.user.as_ref(),
+        state.workspace.as_ref(),
+    )
+    .await?;
+    let activity = state
+        .todo
+        .activity(scope, FEED_ENTRIES)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let feed = Feed {
+        id: match scope.workspace_id {
+            Some(workspace_id) => format!("urn:rust-web:feed:workspace:{}", workspace_id),
+            None => format!("urn:rust-web:feed:user:{}", scope.user_id),
+        },
+        title: "Todos",
+        link: ATOM_PATH,
+        activity: &activity,
+        generated_at: Utc::now(),
+    };
+    let body = if content_type == RSS_CONTENT_TYPE {
+        feed::render_rss(&feed)
+    } else {
+        feed::render_atom(&feed)
+    };
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, format!("{}; charset=utf-8", content_type)),
+            (VARY, "accept".to_string()),
+        ],
+        body,
+    ))
+}
@@ -0,0 +1,113 @@
+use crate::health::HealthCheck;
+use crate::state::AppState;
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+// 依存先が応答しないときに待つ時間. プローブのタイムアウトより短くする
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+const OK: &str = "ok";
+const UNAVAILABLE: &str = "unavailable";
+
+// checks は依存先ごとの結果. エラーの詳細は外に出さずログに残す
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthBody {
+    pub status: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<String, String>,
+}
+
+// liveness. プロセスが動いていれば依存先に関係なく 200
+#[tracing::instrument(skip_all)]
+pub async fn healthz() -> impl IntoResponse {
+    let body = HealthBody {
+        status: OK.to_string(),
+        checks: BTreeMap::new(),
+    };
+    (StatusCode::OK, Json(body))
+}
+
+// readiness. 全ての依存先が使えれば 200、1 つでも使えなければ 503
+#[tracing::instrument(skip_all)]
+pub async fn readyz(Extension(state): Extension<AppState>) -> impl IntoResponse {
+    let (code, body) = readiness(&state.health_checks).await;
+    (code, Json(body))
+}
+
+async fn readiness(targets: &[(&'static str, Arc<dyn HealthCheck>)]) -> (StatusCode, HealthBody) {
+    // 遅い依存先があっても待つのは CHECK_TIMEOUT までにするため、並べて確認する
+    let pending: Vec<_> = targets
+        .iter()
+        .map(|(name, target)| tokio::spawn(check(name, target.clone())))
+        .collect();
+    let mut checks = BTreeMap::new();
+    for (handle, (name, _)) in pending.into_iter().zip(targets) {
+        let status = handle.await.unwrap_or_else(|_| UNAVAILABLE.to_string());
+        checks.insert(name.to_string(), status);
+    }
+    let ready = checks.values().all(|status| status == OK);
+    let (code, status) = if ready {
+        (StatusCode::OK, OK)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, UNAVAILABLE)
+    };
+    let body = HealthBody {
+        status: status.to_string(),
+        checks,
+    };
+    (code, body)
+}
+
+async fn check(name: &'static str, target: Arc<dyn HealthCheck>) -> String {
+    let status = match tokio::time::timeout(CHECK_TIMEOUT, target.check()).await {
+        Ok(Ok(())) => OK,
+        Ok(Err(e)) => {
+            tracing::warn!("health check failed: {}: {}", name, e);
+            UNAVAILABLE
+        }
+        Err(_) => {
+            tracing::warn!("health check timed out: {}", name);
+            UNAVAILABLE
+        }
+    };
+    status.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::async_trait;
+
+    #[derive(Debug, Clone)]
+    struct Unreachable;
+
+    #[async_trait]
+    impl HealthCheck for Unreachable {
+        async fn check(&self) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("connection refused"))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Reachable;
+
+    #[async_trait]
+    impl HealthCheck for Reachable {
+        async fn check(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_report_unavailable_dependencies() {
+        let targets: [(&str, Arc<dyn HealthCheck>); 2] = [
+            ("database", Arc::new(Unreachable)),
+            ("blob_store", Arc::new(Reachable)),
+        ];
+        let (code, body) = readiness(&targets).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.status, UNAVAILABLE);
+        assert_eq!(body.checks["database"], UNAVAILABLE);
+        assert_eq!(body.checks["blob_store"], OK);
+    }
+}
@@ -0,0 +1,92 @@
+use super::Negotiated;
+use crate::repositories::idempotency::IdempotencyRepository;
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// 保存したレスポンスを返したときに付けるヘッダー
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+// 同じ key の再送を受け付ける期間
+const IDEMPOTENCY_TTL_HOURS: i64 = 24;
+const MAX_IDEMPOTENCY_KEY_CHARS: usize = 255;
+
+// Idempotency-Key があれば key を押さえて返す. ヘッダーがなければ None
+// 同じ key のリクエストを処理済みなら、保存したレスポンスを Err で返すのでそのまま返す
+// request には key と一緒に送られた内容を渡す. 同じ key で内容が違えば 422、処理中なら 409
+#[tracing::instrument(skip_all)]
+pub async fn begin<T: Serialize>(
+    repo: &dyn IdempotencyRepository,
+    user_id: i32,
+    headers: &HeaderMap,
+    request: &T,
+) -> Result<Option<String>, Response> {
+    let Some(key) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.chars().count() <= MAX_IDEMPOTENCY_KEY_CHARS)
+        .ok_or_else(|| StatusCode::BAD_REQUEST.into_response())?;
+    let fingerprint = format!(
+        "{:x}",
+        Sha256::digest(serde_json::to_vec(request).unwrap_or_default())
+    );
+    let expires_at = Utc::now() + Duration::hours(IDEMPOTENCY_TTL_HOURS);
+    let existing = repo
+        .reserve(user_id, key, &fingerprint, expires_at)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    let Some(existing) = existing else {
+        return Ok(Some(key.to_string()));
+    };
+
+    if existing.fingerprint != fingerprint {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+    }
+    let status = existing
+        .response_status
+        .and_then(|status| u16::try_from(status).ok())
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .ok_or_else(|| StatusCode::CONFLICT.into_response())?;
+    let mut response = (status, Negotiated(existing.response_body)).into_response();
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    Err(response)
+}
+
+// begin で押さえた key に結果を残す. 成功したレスポンスは保存し、失敗したら key を外して再送を受け付ける
+// 保存に失敗してもリクエスト自体は成功しているので、ログに残すだけにする
+#[tracing::instrument(skip_all)]
+pub async fn finish<T: Serialize>(
+    repo: &dyn IdempotencyRepository,
+    user_id: i32,
+    key: Option<String>,
+    status: StatusCode,
+    result: Result<T, StatusCode>,
+) -> Result<Response, StatusCode> {
+    let Some(key) = key else {
+        return result.map(|body| (status, Negotiated(body)).into_response());
+    };
+    match result {
+        Ok(body) => {
+            let value = serde_json::to_value(&body).unwrap_or_default();
+            if let Err(e) = repo.complete(user_id, &key, status.as_u16(), value).await {
+                tracing::error!("failed to save idempotent response for {}: {}", key, e);
+            }
+            Ok((status, Negotiated(body)).into_response())
+        }
+        Err(error) => {
+            if let Err(e) = repo.release(user_id, &key).await {
+                tracing::error!("failed to release idempotency key {}: {}", key, e);
+            }
+            Err(error)
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::{generate_token, hash_token, CurrentUser};
+use crate::mailer::Mailer;
+use crate::repositories::invitation::{CreateInvitation, Invitation, NewInvitation};
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+// 招待の有効期限 (日)
+const INVITATION_LIFETIME_DAYS: i64 = 7;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct InvitationBody {
+    #[serde(flatten)]
+    pub invitation: Invitation,
+    // 受け取れるのは招待されたメールアドレスのユーザーだけなので、招待した本人には返してよい
+    pub token: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_invitation(
+    Path(workspace_id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<CreateInvitation>,
+    Extension(state): Extension<AppState>,
+    Extension(mailer): Extension<Mailer>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // 招待できるのは owner だけ
+    let workspace = state
+        .workspace
+        .find(user.id, workspace_id)
+        .await
+        .map_err(error_status)?;
+    if workspace.owner_id != user.id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let token = generate_token();
+    let invitation = state
+        .invitation
+        .create(NewInvitation {
+            workspace_id,
+            email: payload.email,
+            invited_by: user.id,
+            token_hash: hash_token(&token),
+            expires_at: Utc::now() + Duration::days(INVITATION_LIFETIME_DAYS),
+        })
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    let body = format!(
+        "You are invited to the workspace \"{}\".\nPOST {} to join within {} days.",
+        workspace.name,
+        mailer.url(&format!("/invitations/{}/accept", token)),
+        INVITATION_LIFETIME_DAYS
+    );
+    mailer
+        .send(&invitation.email, "Workspace invitation", &body)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(InvitationBody { invitation, token }),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn accept_invitation(
+    Path(token): Path<String>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let token_hash = hash_token(&token);
+    let invitation = state
+        .invitation
+        .find_by_token(&token_hash)
+        .await
+        .map_err(error_status)?;
+    // 招待されたメールアドレスのユーザー以外は使えない
+    let current = state
+        .user
+        .find(user.id)
+        .await
+        .or(Err(StatusCode::UNAUTHORIZED))?;
+    if !current.email.eq_ignore_ascii_case(&invitation.email) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    state
+        .invitation
+        .accept(&token_hash)
+        .await
+        .map_err(error_status)?;
+    let membership = state
+        .workspace
+        .add_member(invitation.workspace_id, user.id)
+        .await
+        .map_err(error_status)?;
+
+    Ok((StatusCode::CREATED, Json(membership)))
+}
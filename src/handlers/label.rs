@@ -1,70 +1,292 @@
-use axum::{
-    extract::{Extension, Path},
-    response::IntoResponse,
-    http::StatusCode,
-    Json,
+use super::{
+    error_status, list_response, pagination_headers, wants_csv, FieldSelection, Negotiated,
+    Pagination, ValidatedJson,
 };
-use std::sync::Arc;
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::csv;
 use crate::repositories::label::{
-    LabelRepository,
-    CreateLabel,
-    UpdateLabel,
+    build_tree, CreateLabel, Label, LabelDeleteMode, LabelQuery, ReorderLabels, UpdateLabel,
+};
+use crate::services::label::LabelService;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
-use super::ValidatedJson;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// POST /labels のクエリパラメータ. 省略したら同じ名前のラベルがあれば 409
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CreateLabelQuery {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    #[default]
+    Error,
+    // 同じ名前のラベルを 200 で返す
+    ReturnExisting,
+}
+
+// DELETE /labels/:id のクエリパラメータ. 省略したら todo から外して消す
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct DeleteLabelQuery {
+    #[serde(default)]
+    pub mode: LabelDeleteMode,
+}
 
-pub async fn create_label<T: LabelRepository>(
+#[tracing::instrument(skip_all)]
+pub async fn create_label(
+    Query(query): Query<CreateLabelQuery>,
     ValidatedJson(payload): ValidatedJson<CreateLabel>,
-    Extension(repo): Extension<Arc<T>>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let label = repo
-        .create(payload)
+    let service = LabelService::from_state(&state);
+    let (label, created) = match query.on_conflict {
+        OnConflict::Error => service
+            .create(user, workspace.0, payload)
+            .await
+            .map(|label| (label, true)),
+        OnConflict::ReturnExisting => service.create_or_get(user, workspace.0, payload).await,
+    }
+    .map_err(error_status)?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Negotiated(label)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_label(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let label = LabelService::from_state(&state)
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(label)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_by_user(
+    Path(user_id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // 他のユーザーのラベル一覧は見せない
+    if user_id != user.id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let mut labels = state
+        .label
+        .find_by_user(user_id)
         .await
         .or(Err(StatusCode::NOT_FOUND))?;
+    // ラベルを選ぶための一覧なので、アーカイブしたラベルは出さない
+    labels.retain(|label| label.archived_at.is_none());
+    Ok((StatusCode::OK, Negotiated(labels)))
+}
 
-    Ok((StatusCode::CREATED, Json(label)))
+// ?q= で名前を絞り込み、?page=&per_page= で切り出す. cursor ページングはしない
+// Accept: text/csv なら CSV で返す
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all)]
+pub async fn all_label(
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Query(query): Query<LabelQuery>,
+    Query(pagination): Query<Pagination>,
+    Query(selection): Query<FieldSelection>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<Response, StatusCode> {
+    let page = pagination.page()?;
+    let (labels, total) = state
+        .label
+        .page(workspace.scope(user), &query, page)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let csv_columns = wants_csv(&headers).then(|| selection.columns(&csv::LABEL_COLUMNS));
+    Ok(list_response(
+        csv_columns,
+        selection.select(&labels)?,
+        pagination_headers(&uri, page, total),
+    ))
 }
 
-pub async fn find_label<T: LabelRepository>(
+// ラベルを付けた todo を GET /todos と同じ既定の順で返す. cursor ページングはしない
+#[tracing::instrument(skip_all)]
+pub async fn label_todos(
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
+    OriginalUri(uri): OriginalUri,
+    Query(pagination): Query<Pagination>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let label = repo.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(label)))
+    LabelService::from_state(&state)
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    let page = pagination.page()?;
+    let (todos, total) = state
+        .todo
+        .page_by_label(workspace.scope(user), id, page)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((
+        StatusCode::OK,
+        pagination_headers(&uri, page, total),
+        Negotiated(todos),
+    ))
 }
 
-pub async fn find_by_user<T: LabelRepository>(
-    Path(user_id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
+// 親子関係の木にする. 兄弟は GET /labels と同じ並び順
+#[tracing::instrument(skip_all)]
+pub async fn label_tree(
+    Query(query): Query<LabelQuery>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repo.find_by_user(user_id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(labels)))
+    let labels = state
+        .label
+        .all(workspace.scope(user), &query)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Negotiated(build_tree(labels))))
+}
+
+// GET /labels/stats の要素. ラベルのフィールドに件数を足した形
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelStats {
+    #[serde(flatten)]
+    pub label: Label,
+    pub open: i64,
+    pub completed: i64,
 }
 
-pub async fn all_label<T: LabelRepository>(
-    Extension(repo): Extension<Arc<T>>,
+// GET /labels と同じ並び順で、ラベルごとの未完了と done の todo の数を返す
+// DB の件数は jobs.refresh_label_stats の時刻に数え直すので、それまでの変更はまだ数に入らない
+#[tracing::instrument(skip_all)]
+pub async fn label_stats(
+    Query(query): Query<LabelQuery>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repo.all().await.unwrap();
-    Ok((StatusCode::OK, Json(labels)))
+    let labels = state
+        .label
+        .all(workspace.scope(user), &query)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let usage: HashMap<i32, _> = state
+        .todo
+        .label_usage(workspace.scope(user))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?
+        .into_iter()
+        .map(|usage| (usage.label_id, usage))
+        .collect();
+    let stats: Vec<LabelStats> = labels
+        .into_iter()
+        .map(|label| {
+            let (open, completed) = usage
+                .get(&label.id)
+                .map_or((0, 0), |usage| (usage.open, usage.completed));
+            LabelStats {
+                label,
+                open,
+                completed,
+            }
+        })
+        .collect();
+    Ok((StatusCode::OK, Negotiated(stats)))
 }
 
-pub async fn update_label<T: LabelRepository>(
+#[tracing::instrument(skip_all)]
+pub async fn update_label(
     Path(id): Path<i32>,
     ValidatedJson(payload): ValidatedJson<UpdateLabel>,
-    Extension(repo): Extension<Arc<T>>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let label = repo
-        .update(id, payload)
+    let label = LabelService::from_state(&state)
+        .update(user, workspace.0, id, payload)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(label)))
+        .map_err(error_status)?;
+    Ok((StatusCode::CREATED, Negotiated(label)))
+}
+
+// 並べ替えた後の一覧を返す. 一覧のラベルを過不足なく指定しなければ 400
+#[tracing::instrument(skip_all)]
+pub async fn reorder_labels(
+    ValidatedJson(payload): ValidatedJson<ReorderLabels>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let labels = state
+        .label
+        .reorder(workspace.0, user.id, &payload.ids)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(labels)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn archive_label(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let label = LabelService::from_state(&state)
+        .archive(user, workspace.0, id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(label)))
 }
 
-pub async fn delete_label<T: LabelRepository>(
+#[tracing::instrument(skip_all)]
+pub async fn unarchive_label(
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
-) -> impl IntoResponse {
-    repo.delete(id)
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let label = LabelService::from_state(&state)
+        .unarchive(user, workspace.0, id)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
-}
\ No newline at end of file
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(label)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_label(
+    Path(id): Path<i32>,
+    Query(query): Query<DeleteLabelQuery>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    LabelService::from_state(&state)
+        .delete(user, workspace.0, id, query.mode)
+        .await
+        .map_err(error_status)?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,438 @@
+use super::auth::issue_tokens;
+use crate::auth::JwtKeys;
+use crate::config::OAuthConfig;
+use crate::forwarded::ClientInfo;
+use crate::repositories::user::ExternalIdentity;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    Json,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+
+// CSRF 対策の state を保持する cookie
+const STATE_COOKIE: &str = "oauth_state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    Google,
+    Github,
+}
+
+impl ProviderKind {
+    fn from_path(name: &str) -> Option<Self> {
+        match name {
+            "google" => Some(Self::Google),
+            "github" => Some(Self::Github),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Google => "google",
+            Self::Github => "github",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OAuthProvider {
+    kind: ProviderKind,
+    client_id: String,
+    client_secret: String,
+}
+
+impl OAuthProvider {
+    pub fn new(kind: ProviderKind, client_id: String, client_secret: String) -> Self {
+        Self {
+            kind,
+            client_id,
+            client_secret,
+        }
+    }
+
+    fn authorize_endpoint(&self) -> &'static str {
+        match self.kind {
+            ProviderKind::Google => "https://accounts.google.com/o/oauth2/v2/auth",
+            ProviderKind::Github => "https://github.com/login/oauth/authorize",
+        }
+    }
+
+    fn token_endpoint(&self) -> &'static str {
+        match self.kind {
+            ProviderKind::Google => "https://oauth2.googleapis.com/token",
+            ProviderKind::Github => "https://github.com/login/oauth/access_token",
+        }
+    }
+
+    fn scope(&self) -> &'static str {
+        match self.kind {
+            ProviderKind::Google => "openid email",
+            ProviderKind::Github => "read:user user:email",
+        }
+    }
+
+    pub fn authorize_url(&self, state: &str, redirect_uri: &str) -> String {
+        let mut url = reqwest::Url::parse(self.authorize_endpoint()).unwrap();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", self.scope())
+            .append_pair("state", state);
+        url.to_string()
+    }
+
+    // authorization code をアクセストークンに交換し、IdP のアカウント情報を取得する
+    async fn fetch_identity(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+    ) -> anyhow::Result<ExternalIdentity> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .build()?;
+        let token: TokenResponse = client
+            .post(self.token_endpoint())
+            .header(header::ACCEPT, mime::APPLICATION_JSON.to_string())
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("redirect_uri", redirect_uri),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match self.kind {
+            ProviderKind::Google => {
+                let info: GoogleUserInfo = client
+                    .get("https://openidconnect.googleapis.com/v1/userinfo")
+                    .bearer_auth(&token.access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                info.into_identity()
+            }
+            ProviderKind::Github => {
+                let user: GithubUser = client
+                    .get("https://api.github.com/user")
+                    .bearer_auth(&token.access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                let emails: Vec<GithubEmail> = client
+                    .get("https://api.github.com/user/emails")
+                    .bearer_auth(&token.access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                user.into_identity(emails)
+            }
+        }
+    }
+}
+
+// 設定された IdP だけを有効にする
+// redirect_base_url がなければ、クライアントから見た scheme と Host からコールバックの URL を作る
+// Host は書き換えられるが、IdP が登録済みのコールバック以外を受け付けないので code が他所に渡ることはない
+#[derive(Debug, Clone, Default)]
+pub struct OAuthProviders {
+    google: Option<OAuthProvider>,
+    github: Option<OAuthProvider>,
+    redirect_base: Option<String>,
+}
+
+impl OAuthProviders {
+    pub fn from_config(config: &OAuthConfig) -> Self {
+        let load = |kind: ProviderKind, client_id: &str, client_secret: &str| {
+            (!client_id.is_empty() && !client_secret.is_empty())
+                .then(|| OAuthProvider::new(kind, client_id.to_string(), client_secret.to_string()))
+        };
+
+        Self {
+            google: load(
+                ProviderKind::Google,
+                &config.google_client_id,
+                &config.google_client_secret,
+            ),
+            github: load(
+                ProviderKind::Github,
+                &config.github_client_id,
+                &config.github_client_secret,
+            ),
+            redirect_base: Some(config.redirect_base_url.clone()).filter(|base| !base.is_empty()),
+        }
+    }
+
+    fn redirect_uri(&self, kind: ProviderKind, client: &ClientInfo, headers: &HeaderMap) -> String {
+        let base = self
+            .redirect_base
+            .clone()
+            .or_else(|| client.origin(headers))
+            .unwrap_or_else(|| "http://localhost:3000".to_string());
+        format!(
+            "{}/auth/{}/callback",
+            base.trim_end_matches('/'),
+            kind.name()
+        )
+    }
+
+    fn get(&self, kind: ProviderKind) -> Option<&OAuthProvider> {
+        match kind {
+            ProviderKind::Google => self.google.as_ref(),
+            ProviderKind::Github => self.github.as_ref(),
+        }
+    }
+
+    fn find(&self, name: &str) -> Result<&OAuthProvider, StatusCode> {
+        ProviderKind::from_path(name)
+            .and_then(|kind| self.get(kind))
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleUserInfo {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+impl GoogleUserInfo {
+    fn into_identity(self) -> anyhow::Result<ExternalIdentity> {
+        let email = match (self.email, self.email_verified) {
+            (Some(email), Some(true)) => email,
+            _ => anyhow::bail!("google account has no verified email"),
+        };
+        Ok(ExternalIdentity {
+            provider: ProviderKind::Google.name().to_string(),
+            subject: self.sub,
+            email,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+impl GithubUser {
+    fn into_identity(self, emails: Vec<GithubEmail>) -> anyhow::Result<ExternalIdentity> {
+        let email = emails
+            .into_iter()
+            .find(|email| email.primary && email.verified)
+            .ok_or_else(|| anyhow::anyhow!("github account has no verified primary email"))?;
+        Ok(ExternalIdentity {
+            provider: ProviderKind::Github.name().to_string(),
+            subject: self.id.to_string(),
+            email: email.email,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+fn state_from_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == STATE_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn authorize(
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Extension(providers): Extension<OAuthProviders>,
+    Extension(client): Extension<ClientInfo>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let provider = providers.find(&provider)?;
+    let redirect_uri = providers.redirect_uri(provider.kind, &client, &headers);
+    let state: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let mut cookie = format!(
+        "{}={}; Path=/auth; Max-Age=600; HttpOnly; SameSite=Lax",
+        STATE_COOKIE, state
+    );
+    if client.is_https() {
+        cookie.push_str("; Secure");
+    }
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&provider.authorize_url(&state, &redirect_uri)),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn callback(
+    Path(provider): Path<String>,
+    Query(params): Query<CallbackParams>,
+    headers: HeaderMap,
+    Extension(providers): Extension<OAuthProviders>,
+    Extension(client): Extension<ClientInfo>,
+    Extension(state): Extension<AppState>,
+    Extension(keys): Extension<JwtKeys>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let provider = providers.find(&provider)?;
+    if state_from_cookie(&headers).as_deref() != Some(params.state.as_str()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // 認可のときと同じ redirect_uri でないと IdP が code を受け付けない
+    let redirect_uri = providers.redirect_uri(provider.kind, &client, &headers);
+    let identity = provider
+        .fetch_identity(&params.code, &redirect_uri)
+        .await
+        .map_err(|e| {
+            tracing::warn!(
+                "failed to fetch identity from {}: {}",
+                provider.kind.name(),
+                e
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+    let user = state
+        .user
+        .find_or_create_by_identity(identity)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let body = issue_tokens(state.refresh_token.as_ref(), &keys, &user).await?;
+
+    Ok((StatusCode::OK, Json(body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_build_authorize_url() {
+        let provider = OAuthProvider::new(
+            ProviderKind::Github,
+            "client".to_string(),
+            "secret".to_string(),
+        );
+        let url = reqwest::Url::parse(
+            &provider.authorize_url("state", "http://localhost:3000/auth/github/callback"),
+        )
+        .unwrap();
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+        assert_eq!(url.host_str(), Some("github.com"));
+        assert!(pairs.contains(&("client_id".to_string(), "client".to_string())));
+        assert!(pairs.contains(&("state".to_string(), "state".to_string())));
+        assert!(pairs.contains(&(
+            "redirect_uri".to_string(),
+            "http://localhost:3000/auth/github/callback".to_string()
+        )));
+        // client_secret がブラウザに渡ることはない
+        assert!(pairs.iter().all(|(key, _)| key != "client_secret"));
+    }
+
+    #[test]
+    fn should_build_redirect_uri_for_the_client() {
+        let client = ClientInfo {
+            ip: None,
+            scheme: "https".to_string(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "todo.example.com".parse().unwrap());
+        let providers = OAuthProviders::default();
+        assert_eq!(
+            providers.redirect_uri(ProviderKind::Google, &client, &headers),
+            "https://todo.example.com/auth/google/callback"
+        );
+        assert_eq!(
+            providers.redirect_uri(ProviderKind::Google, &client, &HeaderMap::new()),
+            "http://localhost:3000/auth/google/callback"
+        );
+        // the configured base wins over the request
+        let providers = OAuthProviders {
+            redirect_base: Some("https://api.example.com/".to_string()),
+            ..OAuthProviders::default()
+        };
+        assert_eq!(
+            providers.redirect_uri(ProviderKind::Github, &client, &headers),
+            "https://api.example.com/auth/github/callback"
+        );
+    }
+
+    #[test]
+    fn should_read_state_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            "theme=dark; oauth_state=abc".parse().unwrap(),
+        );
+        assert_eq!(state_from_cookie(&headers), Some("abc".to_string()));
+        assert_eq!(state_from_cookie(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn should_require_verified_email() {
+        let info = GoogleUserInfo {
+            sub: "sub".to_string(),
+            email: Some("user@example.com".to_string()),
+            email_verified: Some(false),
+        };
+        assert!(info.into_identity().is_err());
+
+        let user = GithubUser { id: 1 };
+        let identity = user
+            .into_identity(vec![
+                GithubEmail {
+                    email: "secondary@example.com".to_string(),
+                    primary: false,
+                    verified: true,
+                },
+                GithubEmail {
+                    email: "primary@example.com".to_string(),
+                    primary: true,
+                    verified: true,
+                },
+            ])
+            .unwrap();
+        assert_eq!(identity.subject, "1");
+        assert_eq!(identity.email, "primary@example.com");
+    }
+}
@@ -0,0 +1,243 @@
+use super::{attachment::remove_blobs, error_status, ValidatedJson};
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::repositories::audit::{AuditAction, AuditEntity};
+use crate::repositories::project::{CreateProject, UpdateProject};
+use crate::repositories::todo::TodoFilter;
+use crate::services::audit::record_event;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+
+// DELETE /projects/:id のクエリパラメータ
+// todos=delete と cascade=true はどちらも todo を消す. どちらもなければ detach
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct DeleteProjectQuery {
+    #[serde(default)]
+    pub todos: TodoDeletion,
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+impl DeleteProjectQuery {
+    fn deletion(&self) -> TodoDeletion {
+        if self.cascade {
+            TodoDeletion::Delete
+        } else {
+            self.todos
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoDeletion {
+    // todo は残してプロジェクトから外す
+    #[default]
+    Detach,
+    // プロジェクトの todo も消す
+    Delete,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_project(
+    ValidatedJson(payload): ValidatedJson<CreateProject>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mut tx = state.project.begin().await.map_err(error_status)?;
+    let project = state
+        .project
+        .create_in(&mut tx, workspace.scope(user), payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Create,
+        AuditEntity::Project,
+        project.id,
+        None,
+        Some(&project),
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    Ok((StatusCode::CREATED, Json(project)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_projects(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let projects = state
+        .project
+        .all(workspace.scope(user))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(projects)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_project(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let project = state
+        .project
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(project)))
+}
+
+// GET /todos と同じ絞り込みができる
+#[tracing::instrument(skip_all)]
+pub async fn project_todos(
+    Path(id): Path<i32>,
+    Query(filter): Query<TodoFilter>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let scope = workspace.scope(user);
+    state.project.find(scope, id).await.map_err(error_status)?;
+    let filter = TodoFilter {
+        project_id: Some(id),
+        ..filter
+    };
+    let todos = state
+        .todo
+        .all(scope, &filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(todos)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_project(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateProject>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let before = state
+        .project
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    let mut tx = state.project.begin().await.map_err(error_status)?;
+    let project = state
+        .project
+        .update_in(&mut tx, workspace.scope(user), id, payload)
+        .await
+        .map_err(error_status)?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Update,
+        AuditEntity::Project,
+        id,
+        Some(&before),
+        Some(&project),
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    Ok((StatusCode::OK, Json(project)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_project(
+    Path(id): Path<i32>,
+    Query(query): Query<DeleteProjectQuery>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let scope = workspace.scope(user);
+    let project = state.project.find(scope, id).await.map_err(error_status)?;
+
+    // todo の削除とプロジェクトの削除は 1 つの tx で行い、添付ファイルの中身は commit したあとに消す
+    let mut tx = state.project.begin().await.map_err(error_status)?;
+    let mut storage_keys = vec![];
+    // detach はプロジェクトを消せば DB が project_id を外すので、delete のときだけ先に todo を消す
+    if query.deletion() == TodoDeletion::Delete {
+        let mut todos = vec![];
+        for archived in [false, true] {
+            let filter = TodoFilter {
+                project_id: Some(id),
+                archived: Some(archived),
+                ..Default::default()
+            };
+            let found = state
+                .todo
+                .all(scope, &filter)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            todos.extend(found);
+        }
+        for todo in todos {
+            let attachments = state
+                .attachment
+                .all_by_todo(todo.id)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            storage_keys.extend(
+                attachments
+                    .into_iter()
+                    .map(|attachment| attachment.storage_key),
+            );
+            state
+                .todo
+                .delete_in(&mut tx, scope, todo.id)
+                .await
+                .map_err(error_status)?;
+            record_event(
+                state.audit.as_ref(),
+                &mut tx,
+                user.id,
+                AuditAction::Delete,
+                AuditEntity::Todo,
+                todo.id,
+                Some(&todo),
+                None,
+            )
+            .await
+            .map_err(error_status)?;
+        }
+    }
+
+    state
+        .project
+        .delete_in(&mut tx, scope, id)
+        .await
+        .map_err(error_status)?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Delete,
+        AuditEntity::Project,
+        id,
+        Some(&project),
+        None,
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    remove_blobs(state.blob_store.as_ref(), &storage_keys).await;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,66 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::repositories::reminder::CreateReminder;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+// 見られる todo なら、共有されたものにも自分宛ての通知を設定できる
+#[tracing::instrument(skip_all)]
+pub async fn create_reminder(
+    Path(todo_id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<CreateReminder>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .todo
+        .find(workspace.scope(user), todo_id)
+        .await
+        .map_err(error_status)?;
+    let reminder = state
+        .reminder
+        .create(user.id, todo_id, payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::CREATED, Json(reminder)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_reminders(
+    Path(todo_id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .todo
+        .find(workspace.scope(user), todo_id)
+        .await
+        .map_err(error_status)?;
+    let reminders = state
+        .reminder
+        .all_by_todo(user.id, todo_id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(reminders)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_reminder(
+    Path((todo_id, id)): Path<(i32, i32)>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .reminder
+        .delete(user.id, todo_id, id)
+        .await
+        .map_err(error_status)?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,215 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::repositories::audit::{AuditAction, AuditEntity};
+use crate::repositories::template::{CreateTemplate, UpdateTemplate};
+use crate::repositories::todo::{CreateTodo, Todo};
+use crate::services::audit::record_event;
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+// POST /todos/from-template/:id のレスポンス. 作った todo とそのサブタスク
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TemplateTodoBody {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub subtasks: Vec<Todo>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_template(
+    ValidatedJson(payload): ValidatedJson<CreateTemplate>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // 存在しないラベルを指定した場合などの DB エラーは 404 として扱う
+    let mut tx = state.template.begin().await.map_err(error_status)?;
+    let template = state
+        .template
+        .create_in(&mut tx, workspace.scope(user), payload)
+        .await
+        .map_err(|e| match error_status(e) {
+            StatusCode::INTERNAL_SERVER_ERROR => StatusCode::NOT_FOUND,
+            status => status,
+        })?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Create,
+        AuditEntity::Template,
+        template.id,
+        None,
+        Some(&template),
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_templates(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let templates = state
+        .template
+        .all(workspace.scope(user))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(templates)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_template(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let template = state
+        .template
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(template)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_template(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateTemplate>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let before = state
+        .template
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    let mut tx = state.template.begin().await.map_err(error_status)?;
+    let template = state
+        .template
+        .update_in(&mut tx, workspace.scope(user), id, payload)
+        .await
+        .map_err(|e| match error_status(e) {
+            StatusCode::INTERNAL_SERVER_ERROR => StatusCode::NOT_FOUND,
+            status => status,
+        })?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Update,
+        AuditEntity::Template,
+        id,
+        Some(&before),
+        Some(&template),
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    Ok((StatusCode::OK, Json(template)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_template(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let before = state
+        .template
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    let mut tx = state.template.begin().await.map_err(error_status)?;
+    state
+        .template
+        .delete_in(&mut tx, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Delete,
+        AuditEntity::Template,
+        id,
+        Some(&before),
+        None,
+    )
+    .await
+    .map_err(error_status)?;
+    tx.commit().await.map_err(error_status)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// テンプレートの初期値で todo を作り、続けてサブタスクをテンプレートの順に作る
+#[tracing::instrument(skip_all)]
+pub async fn create_todo_from_template(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let scope = workspace.scope(user);
+    let template = state.template.find(scope, id).await.map_err(error_status)?;
+
+    // todo とサブタスクは 1 つの tx で作る. 途中で失敗したら何も残らない
+    let mut tx = state.todo.begin().await.map_err(error_status)?;
+    let todo = state
+        .todo
+        .create_in(&mut tx, scope, CreateTodo::from_template(&template))
+        .await
+        .map_err(error_status)?;
+    record_event(
+        state.audit.as_ref(),
+        &mut tx,
+        user.id,
+        AuditAction::Create,
+        AuditEntity::Todo,
+        todo.id,
+        None,
+        Some(&todo),
+    )
+    .await
+    .map_err(error_status)?;
+    let mut subtasks = vec![];
+    for text in template.subtasks {
+        let subtask = state
+            .todo
+            .create_in(&mut tx, scope, CreateTodo::from_subtask(todo.id, text))
+            .await
+            .map_err(error_status)?;
+        record_event(
+            state.audit.as_ref(),
+            &mut tx,
+            user.id,
+            AuditAction::Create,
+            AuditEntity::Todo,
+            subtask.id,
+            None,
+            Some(&subtask),
+        )
+        .await
+        .map_err(error_status)?;
+        subtasks.push(subtask);
+    }
+    tx.commit().await.map_err(error_status)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(TemplateTodoBody { todo, subtasks }),
+    ))
+}
@@ -1,62 +1,547 @@
-use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
+use super::{
+    entity_tag, error_status, etag_matches, idempotency, list_response, pagination_headers,
+    wants_csv, FieldSelection, Negotiated, Pagination, Paging, PatchBody, ValidatedJson,
 };
-use std::sync::Arc;
+use crate::auth::{ActiveWorkspace, CurrentUser};
+use crate::csv;
+use crate::markdown;
+use crate::repositories::audit::AuditAction;
 use crate::repositories::todo::{
-    CreateTodo,
-    TodoRepository,
-    UpdateTodo,
+    AssignTodo, CreateTodo, CreateTodos, MoveTodo, RelabelTodos, ShareTodo, SnoozeTodo, Todo,
+    TodoCursor, TodoFilter, TodoSelection, UpdateTodo,
+};
+use crate::services::{todo::TodoService, ServiceError};
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, OriginalUri, Path, Query},
+    http::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
 };
-use super::ValidatedJson;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+// 検索語の最大文字数
+const MAX_SEARCH_QUERY_CHARS: usize = 200;
+
+// GET /todos/:id のクエリパラメータ
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct FindTodoQuery {
+    pub render: Option<Render>,
+}
+
+// GET /todos/search のクエリパラメータ. "" で囲んだ語句や -除外 も書ける
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Render {
+    Html,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoBody {
+    #[serde(flatten)]
+    pub todo: Todo,
+    // render=html のときだけ返す. sanitize 済みなのでそのまま埋め込んでよい
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description_html: Option<String>,
+}
+
+// GET /todos?cursor=&limit= のレスポンス. next_cursor が None なら最後まで取得した
+// fields を指定したときは items が絞り込んだ JSON になる
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TodoCursorPage<T = Todo> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
 
-pub async fn create_todo<T: TodoRepository>(
+// POST /todos/undo のレスポンス. todo は取り消した後の状態で、作成を取り消した場合は None
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UndoBody {
+    pub event_id: i32,
+    pub action: AuditAction,
+    pub todo: Option<Todo>,
+}
+
+// POST /todos/complete・DELETE /todos・POST /todos/labels のレスポンス. 実際に完了・削除・ラベルを変更した件数
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct BulkBody {
+    pub affected: usize,
+}
+
+// Idempotency-Key を付けて再送された場合は、作り直さずに最初のレスポンスを返す
+#[tracing::instrument(skip_all)]
+pub async fn create_todo(
+    headers: HeaderMap,
     ValidatedJson(payload): ValidatedJson<CreateTodo>,
-    Extension(repo): Extension<Arc<T>>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<Response, StatusCode> {
+    let scope = workspace.scope(user);
+    // 別の workspace に同じ内容を送った場合は別のリクエストとして扱う
+    let request = (scope.workspace_id, &payload);
+    let key =
+        match idempotency::begin(state.idempotency.as_ref(), user.id, &headers, &request).await {
+            Ok(key) => key,
+            Err(replayed) => return Ok(replayed),
+        };
+    let result = TodoService::from_state(&state)
+        .create(user, scope, payload)
+        .await
+        .map_err(not_found_on_db_error);
+    idempotency::finish(
+        state.idempotency.as_ref(),
+        user.id,
+        key,
+        StatusCode::CREATED,
+        result,
+    )
+    .await
+}
+
+// 存在しないラベルを指定した場合などの DB エラーは 404 として扱う
+fn not_found_on_db_error(e: anyhow::Error) -> StatusCode {
+    match error_status(e) {
+        StatusCode::INTERNAL_SERVER_ERROR => StatusCode::NOT_FOUND,
+        status => status,
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn create_todos(
+    ValidatedJson(payload): ValidatedJson<CreateTodos>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo
-        .create(payload)
+    let todos = TodoService::from_state(&state)
+        .create_many(user, workspace.scope(user), payload.todos)
+        .await
+        .map_err(not_found_on_db_error)?;
+    Ok((StatusCode::CREATED, Negotiated(todos)))
+}
+
+// ETag は todo の内容から作るので、render=html でも同じ値になる
+#[tracing::instrument(skip_all)]
+pub async fn find_todo(
+    Path(id): Path<i32>,
+    Query(query): Query<FindTodoQuery>,
+    headers: HeaderMap,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<Response, StatusCode> {
+    let todo = state
+        .todo
+        .find(workspace.scope(user), id)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
+        .map_err(error_status)?;
+    let etag = entity_tag(&todo);
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|value| etag_matches(value, &etag, true))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+    let description_html = match query.render {
+        Some(Render::Html) => Some(markdown::render_html(
+            todo.description.as_deref().unwrap_or_default(),
+        )),
+        None => None,
+    };
+    let body = TodoBody {
+        todo,
+        description_html,
+    };
+    Ok((StatusCode::OK, [(ETAG, etag)], Negotiated(body)).into_response())
+}
 
-    Ok((StatusCode::CREATED, Json(todo)))
+// Accept: text/csv なら CSV で返す. CSV では next_cursor を返せないので cursor ページングは 406
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all)]
+pub async fn all_todo(
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    Query(filter): Query<TodoFilter>,
+    Query(pagination): Query<Pagination>,
+    Query(selection): Query<FieldSelection>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<Response, StatusCode> {
+    let scope = workspace.scope(user);
+    let csv_columns = wants_csv(&headers).then(|| selection.columns(&csv::TODO_COLUMNS));
+    match pagination.paging()? {
+        Paging::Offset(page) => {
+            let (todos, total) = state
+                .todo
+                .page(scope, &filter, page)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            let todos = selection.select(&todos)?;
+            Ok(list_response(
+                csv_columns,
+                todos,
+                pagination_headers(&uri, page, total),
+            ))
+        }
+        Paging::Cursor { .. } if csv_columns.is_some() => Err(StatusCode::NOT_ACCEPTABLE),
+        Paging::Cursor { cursor, limit } => {
+            let cursor = cursor
+                .map(|cursor| TodoCursor::decode(&filter, &cursor))
+                .transpose()
+                .or(Err(StatusCode::BAD_REQUEST))?;
+            let (items, next) = state
+                .todo
+                .page_after(scope, &filter, cursor.as_ref(), limit)
+                .await
+                .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+            let page = TodoCursorPage {
+                items: selection.select(&items)?,
+                next_cursor: next.map(|cursor| cursor.encode()),
+            };
+            Ok((StatusCode::OK, Negotiated(page)).into_response())
+        }
+    }
 }
 
-pub async fn find_todo<T: TodoRepository>(
+#[tracing::instrument(skip_all)]
+pub async fn search_todos(
+    Query(query): Query<SearchQuery>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let q = query.q.trim();
+    if q.is_empty() || q.chars().count() > MAX_SEARCH_QUERY_CHARS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let hits = state
+        .todo
+        .search(workspace.scope(user), q)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Negotiated(hits)))
+}
+
+// 直下のサブタスクだけを返す. 孫以下は各サブタスクに対して取得する
+#[tracing::instrument(skip_all)]
+pub async fn find_subtasks(
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(todo)))
+    let scope = workspace.scope(user);
+    state.todo.find(scope, id).await.map_err(error_status)?;
+    let filter = TodoFilter {
+        parent_id: Some(id),
+        ..Default::default()
+    };
+    let todos = state
+        .todo
+        .all(scope, &filter)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Negotiated(todos)))
 }
 
-pub async fn all_todo<T: TodoRepository>(
-    Extension(repo): Extension<Arc<T>>,
+// If-Match を付けた場合は、取得してから他の誰かが変更していれば 412 を返して更新しない
+#[tracing::instrument(skip_all)]
+pub async fn update_todo(
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+    payload: PatchBody<UpdateTodo>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let todos = repo.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todos)))
+    // If-Match と patch は、service がロックして読んだ変更前の todo に対して確かめる
+    let todo = TodoService::from_state(&state)
+        .update(user, workspace.scope(user), id, |before| {
+            if let Some(value) = headers.get(IF_MATCH) {
+                if !etag_matches(value, &entity_tag(before), false) {
+                    return Err(ServiceError::PreconditionFailed(id).into());
+                }
+            }
+            match payload {
+                PatchBody::Json(payload) => Ok(payload),
+                PatchBody::MergePatch(patch) => UpdateTodo::merge(before, patch),
+                PatchBody::JsonPatch(operations) => UpdateTodo::apply(before, operations),
+            }
+        })
+        .await
+        .map_err(error_status)?;
+    Ok((
+        StatusCode::CREATED,
+        [(ETAG, entity_tag(&todo))],
+        Negotiated(todo),
+    ))
 }
 
-pub async fn update_todo<T: TodoRepository>(
+#[tracing::instrument(skip_all)]
+pub async fn todo_history(
     Path(id): Path<i32>,
-    ValidatedJson(payload): ValidatedJson<UpdateTodo>,
-    Extension(repo): Extension<Arc<T>>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repo
-        .update(id, payload)
+    let revisions = state
+        .todo
+        .history(workspace.scope(user), id)
         .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(revisions)))
 }
 
-pub async fn delete_todo<T: TodoRepository>(
+// 戻したこと自体も新しい履歴になる
+#[tracing::instrument(skip_all)]
+pub async fn revert_todo(
+    Path((id, revision_id)): Path<(i32, i32)>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .revert(user, workspace.scope(user), id, revision_id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_todo(
     Path(id): Path<i32>,
-    Extension(repo): Extension<Arc<T>>,
-) -> impl IntoResponse {
-    repo.delete(id)
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    TodoService::from_state(&state)
+        .delete(user, workspace.scope(user), id)
         .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
-}
\ No newline at end of file
+        .map_err(error_status)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn complete_todos(
+    ValidatedJson(selection): ValidatedJson<TodoSelection>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let affected = TodoService::from_state(&state)
+        .complete_many(user, workspace.scope(user), &selection)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(BulkBody { affected })))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_todos(
+    ValidatedJson(selection): ValidatedJson<TodoSelection>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let affected = TodoService::from_state(&state)
+        .delete_many(user, workspace.scope(user), &selection)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(BulkBody { affected })))
+}
+
+// 複数の todo にまとめてラベルを付け外しする. ラベルが変わった todo の数を返す
+#[tracing::instrument(skip_all)]
+pub async fn relabel_todos(
+    ValidatedJson(payload): ValidatedJson<RelabelTodos>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let affected = TodoService::from_state(&state)
+        .relabel_many(user, workspace.scope(user), &payload)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(BulkBody { affected })))
+}
+
+// 取り消せる変更がなければ 404、変更の後に他の操作が入っていたら 409
+#[tracing::instrument(skip_all)]
+pub async fn undo_todo(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (event, todo) = TodoService::from_state(&state)
+        .undo(user, workspace.scope(user))
+        .await
+        .map_err(error_status)?;
+    Ok((
+        StatusCode::OK,
+        Negotiated(UndoBody {
+            event_id: event.id,
+            action: event.action,
+            todo,
+        }),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn archive_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .archive(user, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn unarchive_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .unarchive(user, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+// ピン留めの上限を超えるなら 409
+#[tracing::instrument(skip_all)]
+pub async fn pin_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .pin(user, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn unpin_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .unpin(user, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn snooze_todo(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<SnoozeTodo>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let until = payload.until(Utc::now());
+    let todo = TodoService::from_state(&state)
+        .snooze(user, workspace.scope(user), id, Some(until))
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn unsnooze_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .snooze(user, workspace.scope(user), id, None)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+// 割り当てられないユーザーなら 400
+#[tracing::instrument(skip_all)]
+pub async fn assign_todo(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<AssignTodo>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .assign(user, workspace.scope(user), id, payload.assignee_id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn move_todo(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<MoveTodo>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .move_to(user, workspace.scope(user), id, payload)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn duplicate_todo(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let todo = TodoService::from_state(&state)
+        .duplicate(user, workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::CREATED, Negotiated(todo)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn share_todo(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<ShareTodo>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // 自分自身への共有は意味がない
+    if payload.user_id == user.id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let share = state
+        .todo
+        .share(workspace.scope(user), id, payload)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::CREATED, Negotiated(share)))
+}
@@ -0,0 +1,99 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::{generate_token, ActiveWorkspace, CurrentUser};
+use crate::repositories::webhook::{CreateWebhook, UpdateWebhook, Webhook};
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+// 署名の検証に使う secret は登録したときにしか返さない
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+// アクティブな workspace の todo / label の変更を送る. 個人の場合は自分のものだけ
+#[tracing::instrument(skip_all)]
+pub async fn create_webhook(
+    ValidatedJson(payload): ValidatedJson<CreateWebhook>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let webhook = state
+        .webhook
+        .create(workspace.scope(user), payload, generate_token())
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    let secret = webhook.secret.clone();
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook { webhook, secret }),
+    ))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_webhooks(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let webhooks = state
+        .webhook
+        .all(workspace.scope(user))
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(webhooks)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_webhook(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let webhook = state
+        .webhook
+        .find(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(webhook)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_webhook(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateWebhook>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let webhook = state
+        .webhook
+        .update(workspace.scope(user), id, payload)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(webhook)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_webhook(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Extension(workspace): Extension<ActiveWorkspace>,
+) -> Result<impl IntoResponse, StatusCode> {
+    state
+        .webhook
+        .delete(workspace.scope(user), id)
+        .await
+        .map_err(error_status)?;
+    Ok(StatusCode::NO_CONTENT)
+}
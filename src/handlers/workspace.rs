@@ -0,0 +1,81 @@
+use super::{error_status, ValidatedJson};
+use crate::auth::CurrentUser;
+use crate::repositories::workspace::{CreateWorkspace, UpdateWorkspace};
+use crate::state::AppState;
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+
+#[tracing::instrument(skip_all)]
+pub async fn create_workspace(
+    ValidatedJson(payload): ValidatedJson<CreateWorkspace>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let workspace = state
+        .workspace
+        .create(user.id, payload)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+
+    Ok((StatusCode::CREATED, Json(workspace)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn find_workspace(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let workspace = state
+        .workspace
+        .find(user.id, id)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::OK, Json(workspace)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn all_workspace(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let workspaces = state
+        .workspace
+        .all(user.id)
+        .await
+        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+    Ok((StatusCode::OK, Json(workspaces)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_workspace(
+    Path(id): Path<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateWorkspace>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let workspace = state
+        .workspace
+        .update(user.id, id, payload)
+        .await
+        .map_err(error_status)?;
+    Ok((StatusCode::CREATED, Json(workspace)))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn delete_workspace(
+    Path(id): Path<i32>,
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    state
+        .workspace
+        .delete(user.id, id)
+        .await
+        .map(|_| StatusCode::NO_CONTENT)
+        .unwrap_or_else(error_status)
+}
@@ -0,0 +1,8 @@
+use axum::async_trait;
+
+// GET /readyz で確認する依存先. リクエストを受け付けられない状態なら Err を返す
+// DB の repository は同じ接続プールを共有しているので、todo の repository で代表して確認する
+#[async_trait]
+pub trait HealthCheck: std::marker::Send + std::marker::Sync + 'static {
+    async fn check(&self) -> anyhow::Result<()>;
+}
@@ -0,0 +1,234 @@
+use crate::repositories::todo::{Priority, Status, Todo};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+const PRODID: &str = "-//rust-web//todos//EN";
+// RFC 5545 では 1 行を 75 オクテットまでにし、超える分は空白で始まる行に折り返す
+const MAX_LINE_OCTETS: usize = 75;
+
+// todo を VEVENT と VTODO のどちらで出力するか
+// Google カレンダーは VTODO を表示しないので、省略したら VEVENT にする
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    #[default]
+    Vevent,
+    Vtodo,
+}
+
+// 期限のある todo を iCalendar 形式にする. 期限のない todo は出力しない
+pub fn render_calendar(
+    name: &str,
+    todos: &[Todo],
+    component: Component,
+    now: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+    ];
+    for todo in todos {
+        let Some(due_at) = todo.due_at else {
+            continue;
+        };
+        let (begin, end) = match component {
+            Component::Vevent => ("BEGIN:VEVENT", "END:VEVENT"),
+            Component::Vtodo => ("BEGIN:VTODO", "END:VTODO"),
+        };
+        lines.push(begin.to_string());
+        lines.push(format!("UID:todo-{}@rust-web", todo.id));
+        lines.push(format!("DTSTAMP:{}", format_time(now)));
+        lines.push(format!("CREATED:{}", format_time(todo.created_at)));
+        lines.push(format!("SUMMARY:{}", escape_text(&todo.text)));
+        if let Some(description) = &todo.description {
+            lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+        }
+        // VEVENT は DTEND を省略すると期限の時刻だけの予定になる
+        match component {
+            Component::Vevent => lines.push(format!("DTSTART:{}", format_time(due_at))),
+            Component::Vtodo => {
+                if let Some(starts_at) = todo.starts_at {
+                    lines.push(format!("DTSTART:{}", format_time(starts_at)));
+                }
+                lines.push(format!("DUE:{}", format_time(due_at)));
+            }
+        }
+        lines.push(format!("STATUS:{}", status(todo.status, component)));
+        lines.push(format!("PRIORITY:{}", priority(todo.priority)));
+        if !todo.labels.is_empty() {
+            let categories = todo
+                .labels
+                .iter()
+                .map(|label| escape_text(&label.name))
+                .collect::<Vec<_>>()
+                .join(",");
+            lines.push(format!("CATEGORIES:{}", categories));
+        }
+        lines.push(end.to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// VEVENT の STATUS には完了を表す値がないので、cancelled 以外は CONFIRMED にする
+fn status(status: Status, component: Component) -> &'static str {
+    match (component, status) {
+        (_, Status::Cancelled) => "CANCELLED",
+        (Component::Vevent, _) => "CONFIRMED",
+        (Component::Vtodo, Status::Backlog) => "NEEDS-ACTION",
+        (Component::Vtodo, Status::InProgress) => "IN-PROCESS",
+        (Component::Vtodo, Status::Done) => "COMPLETED",
+    }
+}
+
+// 1 が最も高く 9 が最も低い
+fn priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::Urgent => 1,
+        Priority::High => 3,
+        Priority::Medium => 5,
+        Priority::Low => 9,
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// マルチバイト文字の途中では折り返さない. 行末は CRLF
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            // 先頭の空白も 1 オクテットに数える
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::label::Label;
+    use chrono::TimeZone;
+
+    fn todo(id: i32, text: &str, due_at: Option<DateTime<Utc>>) -> Todo {
+        Todo {
+            id,
+            text: text.to_string(),
+            description: None,
+            status: Status::Backlog,
+            user_id: 1,
+            workspace_id: None,
+            due_at,
+            starts_at: None,
+            priority: Priority::High,
+            parent_id: None,
+            recurrence: None,
+            archived_at: None,
+            position: 0,
+            project_id: None,
+            labels: vec![],
+            created_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            assignee_id: None,
+            is_pinned: false,
+            snoozed_until: None,
+        }
+    }
+
+    #[test]
+    fn should_render_calendar() {
+        let now = Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 5).unwrap();
+        let due_at = Utc.with_ymd_and_hms(2023, 1, 10, 9, 0, 0).unwrap();
+        let todos = vec![
+            Todo {
+                description: Some("line 1\nline 2".to_string()),
+                labels: vec![Label {
+                    id: 1,
+                    name: "home, office".to_string(),
+                    user_id: Some(1),
+                    workspace_id: None,
+                    color: "#4f86f7".to_string(),
+                    parent_id: None,
+                    position: 1,
+                    archived_at: None,
+                    icon: None,
+                }],
+                ..todo(1, "buy milk; eggs", Some(due_at))
+            },
+            todo(2, "no due date", None),
+        ];
+
+        let event = render_calendar("Todos", &todos, Component::Vevent, now);
+        assert_eq!(
+            event,
+            [
+                "BEGIN:VCALENDAR",
+                "VERSION:2.0",
+                "PRODID:-//rust-web//todos//EN",
+                "CALSCALE:GREGORIAN",
+                "X-WR-CALNAME:Todos",
+                "BEGIN:VEVENT",
+                "UID:todo-1@rust-web",
+                "DTSTAMP:20230102T030405Z",
+                "CREATED:20230101T000000Z",
+                "SUMMARY:buy milk\\; eggs",
+                "DESCRIPTION:line 1\\nline 2",
+                "DTSTART:20230110T090000Z",
+                "STATUS:CONFIRMED",
+                "PRIORITY:3",
+                "CATEGORIES:home\\, office",
+                "END:VEVENT",
+                "END:VCALENDAR",
+                "",
+            ]
+            .join("\r\n")
+        );
+
+        let todos = vec![Todo {
+            status: Status::Done,
+            starts_at: Some(Utc.with_ymd_and_hms(2023, 1, 9, 9, 0, 0).unwrap()),
+            ..todo(1, "done", Some(due_at))
+        }];
+        let vtodo = render_calendar("Todos", &todos, Component::Vtodo, now);
+        assert!(vtodo.contains("BEGIN:VTODO\r\n"));
+        assert!(vtodo.contains("DTSTART:20230109T090000Z\r\nDUE:20230110T090000Z\r\n"));
+        assert!(vtodo.contains("STATUS:COMPLETED\r\n"));
+    }
+
+    #[test]
+    fn should_fold_long_lines() {
+        let line = format!("SUMMARY:{}", "あ".repeat(30));
+        let folded = fold_line(&line);
+        let lines: Vec<&str> = folded.trim_end_matches("\r\n").split("\r\n").collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_OCTETS));
+        assert!(lines[1].starts_with(' '));
+        assert_eq!(lines.concat().replacen(' ', "", 1), line);
+    }
+}
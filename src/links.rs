@@ -0,0 +1,273 @@
+use crate::api_version::{unversioned_path, ApiVersion, DEFAULT_VERSION};
+use crate::msgpack::{self, CONTENT_TYPE_MSGPACK};
+use crate::routes::matched_route;
+use axum::{
+    body::{boxed, Full},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        Request, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Map, Value};
+
+// todo を返すルート. 一覧、1 件、cursor ページ、POST /todos/undo の {todo} のどれでもよい
+const TODO_ROUTES: [&str; 21] = [
+    "/todos",
+    "/todos/:id",
+    "/todos/bulk",
+    "/todos/search",
+    "/todos/undo",
+    "/todos/from-template/:id",
+    "/todos/:id/archive",
+    "/todos/:id/unarchive",
+    "/todos/:id/pin",
+    "/todos/:id/unpin",
+    "/todos/:id/snooze",
+    "/todos/:id/unsnooze",
+    "/todos/:id/move",
+    "/todos/:id/assign",
+    "/todos/:id/duplicate",
+    "/todos/:id/revert/:revision_id",
+    "/todos/:id/subtasks",
+    "/projects/:id/todos",
+    "/labels/:id/todos",
+    "/admin/todos",
+    "/workspaces/:workspace_id/todos",
+];
+// label を返すルート. GET /labels/tree の children も辿る
+const LABEL_ROUTES: [&str; 9] = [
+    "/labels",
+    "/labels/:id",
+    "/labels/user/:user_id",
+    "/labels/stats",
+    "/labels/tree",
+    "/labels/reorder",
+    "/labels/:id/archive",
+    "/labels/:id/unarchive",
+    "/workspaces/:workspace_id/labels",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resource {
+    Todo,
+    Label,
+}
+
+// todo と label の JSON に、辿れる URL を _links として加える. MessagePack で返す応答にも同じように加える
+// page / per_page の一覧は配列のままなので、次のページは今まで通り Link ヘッダーで返す
+// ?fields= でフィールドを選んだ場合は、選んだフィールドだけを返す
+pub async fn add_links<B>(req: Request<B>, next: Next<B>) -> Response {
+    let uri = req.uri().clone();
+    let version = req
+        .extensions()
+        .get::<ApiVersion>()
+        .copied()
+        .unwrap_or(ApiVersion(DEFAULT_VERSION));
+    let res = next.run(req).await;
+    let selects_fields = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .any(|pair| pair.split('=').next() == Some("fields"));
+    if selects_fields {
+        return res;
+    }
+    let resource = match matched_route(uri.path()) {
+        Some((route, _)) if TODO_ROUTES.contains(&route) => Resource::Todo,
+        Some((route, _)) if LABEL_ROUTES.contains(&route) => Resource::Label,
+        _ => return res,
+    };
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let is_msgpack = content_type.starts_with(CONTENT_TYPE_MSGPACK);
+    let is_json = content_type.starts_with(mime::APPLICATION_JSON.as_ref());
+    if !res.status().is_success() || !(is_json || is_msgpack) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let Ok(bytes) = hyper::body::to_bytes(body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let value = if is_msgpack {
+        msgpack::decode(&bytes).ok()
+    } else {
+        serde_json::from_slice::<Value>(&bytes).ok()
+    };
+    let Some(mut value) = value else {
+        return Response::from_parts(parts, boxed(Full::from(bytes)));
+    };
+    let links = Links::new(&uri, version);
+    links.annotate(&mut value, resource);
+    let body = if is_msgpack {
+        msgpack::encode(&value)
+    } else {
+        let Ok(body) = serde_json::to_vec(&value) else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        body
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+struct Links<'a> {
+    // リクエストの版. 版を付けずに呼ばれたら Accept で選んだ版か /v1
+    prefix: String,
+    uri: &'a Uri,
+}
+
+impl<'a> Links<'a> {
+    fn new(uri: &'a Uri, version: ApiVersion) -> Self {
+        Self {
+            prefix: version.prefix(),
+            uri,
+        }
+    }
+
+    fn href(&self, path: String) -> Value {
+        json!({ "href": format!("{}{}", self.prefix, path) })
+    }
+
+    fn annotate(&self, value: &mut Value, resource: Resource) {
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    self.annotate(item, resource);
+                }
+            }
+            Value::Object(object) => self.annotate_object(object, resource),
+            _ => {}
+        }
+    }
+
+    fn annotate_object(&self, object: &mut Map<String, Value>, resource: Resource) {
+        // cursor ページ. next_cursor があれば同じクエリで cursor だけ変えた URL
+        if let Some(items) = object.get_mut("items") {
+            self.annotate(items, resource);
+            if let Some(cursor) = object.get("next_cursor").and_then(Value::as_str) {
+                let next = json!({ "href": self.next_page(cursor) });
+                object.insert("_links".to_string(), json!({ "next": next }));
+            }
+            return;
+        }
+        // POST /todos/undo
+        if let Some(todo) = object.get_mut("todo") {
+            self.annotate(todo, resource);
+            return;
+        }
+        let Some(id) = object.get("id").and_then(Value::as_i64) else {
+            return;
+        };
+        let mut links = Map::new();
+        match resource {
+            Resource::Todo => {
+                links.insert("self".to_string(), self.href(format!("/todos/{}", id)));
+                let subtasks = self.href(format!("/todos/{}/subtasks", id));
+                links.insert("subtasks".to_string(), subtasks);
+                if let Some(Value::Array(labels)) = object.get_mut("labels") {
+                    let mut hrefs = Vec::new();
+                    for label in labels.iter_mut() {
+                        if let Some(id) = label.get("id").and_then(Value::as_i64) {
+                            hrefs.push(self.href(format!("/labels/{}", id)));
+                        }
+                        self.annotate(label, Resource::Label);
+                    }
+                    links.insert("labels".to_string(), Value::Array(hrefs));
+                }
+                if let Some(parent_id) = object.get("parent_id").and_then(Value::as_i64) {
+                    let parent = self.href(format!("/todos/{}", parent_id));
+                    links.insert("parent".to_string(), parent);
+                }
+            }
+            Resource::Label => {
+                links.insert("self".to_string(), self.href(format!("/labels/{}", id)));
+                links.insert(
+                    "todos".to_string(),
+                    self.href(format!("/labels/{}/todos", id)),
+                );
+                if let Some(parent_id) = object.get("parent_id").and_then(Value::as_i64) {
+                    let parent = self.href(format!("/labels/{}", parent_id));
+                    links.insert("parent".to_string(), parent);
+                }
+                if let Some(children) = object.get_mut("children") {
+                    self.annotate(children, Resource::Label);
+                }
+            }
+        }
+        object.insert("_links".to_string(), Value::Object(links));
+    }
+
+    fn next_page(&self, cursor: &str) -> String {
+        let mut query: Vec<String> = self
+            .uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some("cursor"))
+            .map(str::to_string)
+            .collect();
+        query.push(format!("cursor={}", cursor));
+        let path = unversioned_path(self.uri.path());
+        format!("{}{}?{}", self.prefix, path, query.join("&"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_link_todos_and_labels() {
+        let uri: Uri = "/v2/todos?limit=1&cursor=00".parse().unwrap();
+        let links = Links::new(&uri, ApiVersion(2));
+        let mut page = json!({
+            "items": [{
+                "id": 2,
+                "parent_id": 1,
+                "labels": [{ "id": 3, "parent_id": null }],
+            }],
+            "next_cursor": "7b7d",
+        });
+        links.annotate(&mut page, Resource::Todo);
+        assert_eq!(
+            page["_links"],
+            json!({ "next": { "href": "/v2/todos?limit=1&cursor=7b7d" } })
+        );
+        assert_eq!(
+            page["items"][0]["_links"],
+            json!({
+                "self": { "href": "/v2/todos/2" },
+                "subtasks": { "href": "/v2/todos/2/subtasks" },
+                "labels": [{ "href": "/v2/labels/3" }],
+                "parent": { "href": "/v2/todos/1" },
+            })
+        );
+        assert_eq!(
+            page["items"][0]["labels"][0]["_links"],
+            json!({
+                "self": { "href": "/v2/labels/3" },
+                "todos": { "href": "/v2/labels/3/todos" },
+            })
+        );
+
+        // 版を付けずに呼ばれたら negotiate_version が選んだ版の URL. 木の子ラベルにも付ける
+        let uri: Uri = "/labels/tree".parse().unwrap();
+        let mut tree = json!([{
+            "id": 1,
+            "parent_id": null,
+            "children": [{ "id": 2, "parent_id": 1, "children": [] }],
+        }]);
+        Links::new(&uri, ApiVersion(1)).annotate(&mut tree, Resource::Label);
+        assert_eq!(tree[0]["_links"]["self"], json!({ "href": "/v1/labels/1" }));
+        assert_eq!(
+            tree[0]["children"][0]["_links"]["parent"],
+            json!({ "href": "/v1/labels/1" })
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::config::ServerConfig;
+use crate::request_id::RequestId;
+use crate::routes::RouteError;
+use axum::{
+    http::{header::RETRY_AFTER, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+// 同時に処理するリクエストの上限. None なら制限しない
+// 上限に達したら待たせずに 503 を返す. 待たせても DB の接続を取れずにタイムアウトするだけなので
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            permits: (max > 0).then(|| Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self::new(config.max_concurrent_requests)
+    }
+}
+
+// /healthz は混んでいても答える. 落とすと過負荷のたびに再起動されてしまう
+// /readyz は数えるので、混んでいる間はロードバランサーから外れる
+// tower の LoadShedLayer と ConcurrencyLimitLayer は使わない. 空きの判定が poll_ready で
+// リクエストを見る前に行われるので /healthz を外せない. また axum 0.5 の Router::layer は
+// ルートとメソッドごとに layer を掛けるので、ConcurrencyLimitLayer だと上限もルートごとになる
+pub async fn shed_load<B>(req: Request<B>, next: Next<B>) -> Response {
+    let permits = req
+        .extensions()
+        .get::<ConcurrencyLimit>()
+        .and_then(|limit| limit.permits.clone());
+    let Some(permits) = permits.filter(|_| req.uri().path() != "/healthz") else {
+        return next.run(req).await;
+    };
+    let Ok(_permit) = permits.try_acquire_owned() else {
+        tracing::warn!("shedding {} {}", req.method(), req.uri().path());
+        let request_id = req.extensions().get::<RequestId>().cloned();
+        let message = "the server is busy, try again later".to_string();
+        let error = RouteError::new(StatusCode::SERVICE_UNAVAILABLE, message, request_id);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, "1")],
+            Json(error),
+        )
+            .into_response();
+    };
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, extract::Extension, middleware, routing::get, Router};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn should_shed_requests_over_the_limit() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        };
+        let app = Router::new()
+            .route("/todos", get(slow))
+            .route("/healthz", get(slow))
+            .layer(middleware::from_fn(shed_load))
+            .layer(Extension(ConcurrencyLimit::new(1)));
+        let req = |path: &str| Request::builder().uri(path).body(Body::empty()).unwrap();
+
+        let first = tokio::spawn(app.clone().oneshot(req("/todos")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let res = app.clone().oneshot(req("/todos")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[RETRY_AFTER], "1");
+        let res = app.clone().oneshot(req("/healthz")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // the permit is back once the first request is done
+        let res = app.oneshot(req("/todos")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
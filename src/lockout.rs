@@ -0,0 +1,98 @@
+use crate::config::LockoutConfig;
+use axum::{
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+
+// ログインの総当たり対策. window の間に threshold 回失敗したアカウントと IP をロックする
+#[derive(Debug, Clone)]
+pub struct LoginLockout {
+    pub threshold: usize,
+    // 1 つの IP から複数のアカウントを試す場合に備えて、IP 単位では別の閾値を使う
+    pub ip_threshold: usize,
+    pub window: Duration,
+}
+
+impl LoginLockout {
+    pub fn new(threshold: usize, ip_threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            ip_threshold: ip_threshold.max(1),
+            window,
+        }
+    }
+
+    pub fn from_config(config: &LockoutConfig) -> Self {
+        Self::new(
+            config.threshold,
+            config.ip_threshold,
+            Duration::seconds(config.window_secs as i64),
+        )
+    }
+
+    // この時刻より後の失敗だけを数える
+    pub fn since(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - self.window
+    }
+
+    // failures は新しい順. ロック中なら解除される時刻を返す
+    // threshold 回目に新しい失敗が window の外に出た時点で解除される
+    pub fn locked_until(
+        &self,
+        failures: &[DateTime<Utc>],
+        threshold: usize,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        failures
+            .get(threshold - 1)
+            .map(|failed_at| *failed_at + self.window)
+            .filter(|unlock_after| *unlock_after > now)
+    }
+}
+
+// 423 Locked. いつ再試行できるかを body と Retry-After で返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locked(pub DateTime<Utc>);
+
+impl IntoResponse for Locked {
+    fn into_response(self) -> Response {
+        let Locked(unlock_after) = self;
+        let retry_after = (unlock_after - Utc::now()).num_seconds().max(1);
+        (
+            StatusCode::LOCKED,
+            [(RETRY_AFTER, retry_after.to_string())],
+            Json(json!({ "unlock_after": unlock_after })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_lock_after_threshold() {
+        let lockout = LoginLockout::new(3, 10, Duration::minutes(15));
+        let now = Utc::now();
+        let failures = vec![
+            now - Duration::minutes(1),
+            now - Duration::minutes(2),
+            now - Duration::minutes(5),
+        ];
+
+        assert_eq!(lockout.locked_until(&failures[..2], 3, now), None);
+        assert_eq!(
+            lockout.locked_until(&failures, 3, now),
+            Some(now + Duration::minutes(10))
+        );
+        // 3 回目に新しい失敗が window の外に出れば解除される
+        assert_eq!(
+            lockout.locked_until(&failures, 3, now + Duration::minutes(10)),
+            None
+        );
+    }
+}
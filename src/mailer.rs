@@ -0,0 +1,45 @@
+use crate::config::MailConfig;
+
+// メール送信. SMTP などの送信手段をつなぐまではログに出力するだけ
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    from: String,
+    app_url: String,
+}
+
+impl Mailer {
+    pub fn new(from: String, app_url: String) -> Self {
+        Self { from, app_url }
+    }
+
+    pub fn from_config(config: &MailConfig) -> Self {
+        Self::new(config.from.clone(), config.app_url.clone())
+    }
+
+    // メール本文に載せるアプリの URL
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.app_url.trim_end_matches('/'), path)
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: &str) -> anyhow::Result<()> {
+        tracing::info!("mail from {} to {}: [{}]\n{}", self.from, to, subject, body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_build_url() {
+        let mailer = Mailer::new(
+            "noreply@example.com".to_string(),
+            "http://localhost:3000/".to_string(),
+        );
+        assert_eq!(
+            mailer.url("/invitations/abc/accept"),
+            "http://localhost:3000/invitations/abc/accept"
+        );
+    }
+}
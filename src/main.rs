@@ -1,97 +1,759 @@
+mod api_version;
+mod auth;
+mod blob_store;
+mod body_limit;
+mod catch_panic;
+mod cli;
+// TodoClient is only called from the tests for now, it is meant for Rust consumers of the API
+#[cfg_attr(not(test), allow(dead_code))]
+mod client;
+mod config;
+mod cors;
+mod cron;
+mod csv;
+mod events;
+mod feed;
+mod forwarded;
 mod handlers;
+mod health;
+mod ical;
+mod links;
+mod load_shed;
+mod lockout;
+mod mailer;
+mod markdown;
+mod migrations;
+mod msgpack;
+mod notifier;
+mod rate_limit;
+mod redis;
 mod repositories;
+mod request_id;
+mod routes;
+mod scheduler;
+mod secrets;
+mod seed;
+mod services;
+#[cfg(any(test, feature = "memory"))]
+mod snapshot;
+mod state;
+mod telemetry;
+mod timeout;
+mod tls;
+mod webhook;
 
+use crate::api_version::{negotiate_version, route_unversioned, DEPRECATION_HEADER};
+use crate::auth::{require_auth, resolve_workspace, JwtKeys, WORKSPACE_HEADER};
+use crate::blob_store::ConfiguredBlobStore;
+use crate::body_limit::limit_body;
+use crate::catch_panic::catch_panic;
+use crate::cli::{Command, USAGE};
+use crate::client::ServiceTransport;
+use crate::config::{AppConfig, Backend};
+use crate::cors::cors_layer;
+use crate::events::ConfiguredPublisher;
+use crate::forwarded::{parse_networks, resolve_client, TrustedProxies};
+use crate::links::add_links;
+use crate::load_shed::{shed_load, ConcurrencyLimit};
+use crate::lockout::LoginLockout;
+use crate::mailer::Mailer;
+use crate::migrations::prepare_database;
+use crate::notifier::ChannelNotifier;
+use crate::rate_limit::{rate_limit, RateLimiter};
 use crate::repositories::{
-    label::{LabelRepository, LabelRepositoryForDb},
-    todo::{TodoRepository, TodoRepositoryForDb},
+    attachment::AttachmentRepositoryForDb,
+    audit::AuditRepositoryForDb,
+    backup::BackupRepositoryForDb,
+    cache::{CachedLabelRepository, CachedTodoRepository, RepositoryCache},
+    idempotency::IdempotencyRepositoryForDb,
+    invitation::InvitationRepositoryForDb,
+    label::LabelRepositoryForDb,
+    login_attempt::LoginAttemptRepositoryForDb,
+    outbox::OutboxRepositoryForDb,
+    project::ProjectRepositoryForDb,
+    refresh_token::RefreshTokenRepositoryForDb,
+    reminder::ReminderRepositoryForDb,
+    template::TemplateRepositoryForDb,
+    todo::TodoRepositoryForDb,
+    user::UserRepositoryForDb,
+    webhook::WebhookRepositoryForDb,
+    workspace::WorkspaceRepositoryForDb,
 };
+use crate::request_id::{assign_request_id, REQUEST_ID_HEADER};
+use crate::routes::{answer_head_and_options, describe_method_not_allowed, not_found};
+use crate::scheduler::{MaintenanceJobs, Scheduler};
+use crate::secrets::SecretProviders;
+use crate::seed::{seed, SeedOptions, SEED_PASSWORD};
+use crate::state::AppState;
+use crate::telemetry::{trace_request, OtlpLayer, TRACEPARENT_HEADER};
+use crate::timeout::{request_timeout, timeout};
+use crate::tls::{ReloadableTlsConfig, TlsAcceptorIncoming};
+use crate::webhook::HttpWebhookSender;
 use axum::{
+    body::Body,
     extract::Extension,
-    routing::{delete, get, post},
+    handler::Handler,
+    http::Request,
+    middleware,
+    routing::{delete, get, patch, post},
     Router,
 };
 use dotenv::dotenv;
 use handlers::{
-    label::{all_label, create_label, delete_label, find_by_user, find_label, update_label},
-    todo::{all_todo, create_todo, delete_todo, find_todo, update_todo},
+    account::{delete_account, export_account},
+    admin::{
+        all_users, all_users_todo, delete_any_label, delete_user, disable_user, enable_user,
+        require_password_reset,
+    },
+    attachment::{all_attachments, delete_attachment, download_attachment, upload_attachment},
+    audit::all_audit_events,
+    auth::{change_password, claim, create_guest, login, logout_all, me, refresh, register},
+    backup::{export_backup, import_backup},
+    batch::batch,
+    feed::{atom_feed, calendar_feed, feed_token, revoke_feed_tokens},
+    health::{healthz, readyz},
+    idempotency::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER},
+    invitation::{accept_invitation, create_invitation},
+    label::{
+        all_label, archive_label, create_label, delete_label, find_by_user, find_label,
+        label_stats, label_todos, label_tree, reorder_labels, unarchive_label, update_label,
+    },
+    negotiate_body,
+    oauth::{authorize, callback, OAuthProviders},
+    project::{
+        all_projects, create_project, delete_project, find_project, project_todos, update_project,
+    },
+    reminder::{all_reminders, create_reminder, delete_reminder},
+    template::{
+        all_templates, create_template, create_todo_from_template, delete_template, find_template,
+        update_template,
+    },
+    todo::{
+        all_todo, archive_todo, assign_todo, complete_todos, create_todo, create_todos,
+        delete_todo, delete_todos, duplicate_todo, find_subtasks, find_todo, move_todo, pin_todo,
+        relabel_todos, revert_todo, search_todos, share_todo, snooze_todo, todo_history,
+        unarchive_todo, undo_todo, unpin_todo, unsnooze_todo, update_todo,
+    },
+    webhook::{all_webhooks, create_webhook, delete_webhook, find_webhook, update_webhook},
+    workspace::{
+        all_workspace, create_workspace, delete_workspace, find_workspace, update_workspace,
+    },
+    TOTAL_COUNT_HEADER,
 };
-use hyper::header::CONTENT_TYPE;
+use hyper::header::{HeaderName, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK};
 use sqlx::PgPool;
 use std::net::SocketAddr;
+use std::time::Duration;
 use std::{env, sync::Arc};
-use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 #[tokio::main]
 async fn main() {
-    // read .env file
-    let app_env = env::var("APP_ENV").unwrap_or("local".to_string());
-    dotenv::from_filename(".config/.env.".to_string() + app_env.as_str()).ok();
+    // no subcommand means serve, so `cargo run` keeps starting the server
+    let command = Command::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    if command == Command::Help {
+        println!("{}", USAGE);
+        return;
+    }
 
-    // set log level
-    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
+    // read .env file
+    dotenv::from_filename(".config/.env.".to_string() + config::app_env().as_str()).ok();
     dotenv().ok();
 
-    // set database
-    // let repo = TodoRepositoryForMemory::new();
-    let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+    // config file merged with env overrides, with secret references fetched from Vault or AWS.
+    // every problem is listed before giving up
+    let secrets = SecretProviders::from_env().unwrap_or_else(|e| {
+        eprintln!("invalid secret store: {}", e);
+        std::process::exit(1);
+    });
+    let config = AppConfig::load(&secrets).await.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if command == Command::CheckConfig {
+        println!(
+            "config is valid: {:?} backend, listening on {}:{}",
+            config.repository.backend, config.server.host, config.server.port
+        );
+        return;
+    }
+
+    // spans are also exported over OTLP when telemetry.otlp_endpoint is set
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(&config.logging.level))
+        .with(tracing_subscriber::fmt::layer())
+        .with(OtlpLayer::from_config(&config.telemetry))
+        .init();
+    if config.cors.permissive {
+        tracing::warn!(
+            "cors.permissive is on, any origin can call the API. do not use it in production"
+        );
+    }
+
+    match (command, config.repository.backend) {
+        (Command::Serve, Backend::Postgres) => run_with_database(config).await,
+        #[cfg(feature = "memory")]
+        (Command::Serve, Backend::Memory) => run_in_memory(config).await,
+        (Command::Migrate, Backend::Postgres) => migrate(config).await,
+        (Command::Seed(options), Backend::Postgres) => seed_database(config, options).await,
+        (Command::Migrate | Command::Seed(_), Backend::Memory) => {
+            eprintln!("migrate and seed need REPO_BACKEND=postgres");
+            std::process::exit(1);
+        }
+        #[cfg(not(feature = "memory"))]
+        (Command::Serve, Backend::Memory) => unreachable!("rejected by AppConfig::validate"),
+        (Command::Help | Command::CheckConfig, _) => unreachable!("handled before connecting"),
+    }
+}
+
+// shared by serve, migrate and seed
+async fn connect_database(config: &AppConfig) -> PgPool {
     tracing::debug!("startconnect database...");
-    let pool = PgPool::connect(database_url.as_str())
+    let connect_options = config
+        .database
+        .connect_options()
+        .unwrap_or_else(|e| panic!("invalid database url: [{}]: {}", config.database.url, e));
+    config
+        .database
+        .pool_options()
+        .connect_with(connect_options)
         .await
-        .expect(&format!("cannot connect to database: [{}]", database_url));
+        .unwrap_or_else(|e| {
+            panic!(
+                "cannot connect to database: [{}]: {}",
+                config.database.url, e
+            )
+        })
+}
 
-    // build app
-    let app = create_app(
-        TodoRepositoryForDb::new(pool.clone()),
-        LabelRepositoryForDb::new(pool.clone()),
+// reads of todos and labels go to the replica when DATABASE_READ_URL is set
+async fn connect_read_replica(config: &AppConfig, pool: &PgPool) -> PgPool {
+    match config.database.read_connect_options() {
+        Some(options) => {
+            let options = options.unwrap_or_else(|e| {
+                panic!(
+                    "invalid database read url: [{}]: {}",
+                    config.database.read_url, e
+                )
+            });
+            config
+                .database
+                .pool_options()
+                .connect_with(options)
+                .await
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "cannot connect to database: [{}]: {}",
+                        config.database.read_url, e
+                    )
+                })
+        }
+        None => pool.clone(),
+    }
+}
+
+// `rust_web migrate`, run before rolling out servers that start with RUN_MIGRATIONS=false
+async fn migrate(config: AppConfig) {
+    let pool = connect_database(&config).await;
+    if let Err(e) = prepare_database(&pool, true).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+    tracing::info!("database migrations are up to date");
+    pool.close().await;
+}
+
+// `rust_web seed --users N --count N`. writes go through the same repositories as the API
+async fn seed_database(config: AppConfig, options: SeedOptions) {
+    let pool = connect_database(&config).await;
+    if let Err(e) = prepare_database(&pool, config.database.run_migrations).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+    let seeded = seed(
+        &UserRepositoryForDb::new(pool.clone()),
+        &LabelRepositoryForDb::new(pool.clone()),
+        &TodoRepositoryForDb::new(pool.clone()),
+        &options,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("cannot seed the database: {}", e);
+        std::process::exit(1);
+    });
+    for user in &seeded.users {
+        println!("{} (password: {})", user.email, SEED_PASSWORD);
+    }
+    println!(
+        "{} users, {} labels and {} new todos",
+        seeded.users.len(),
+        seeded.labels.len(),
+        seeded.todos.len()
+    );
+    pool.close().await;
+}
+
+async fn run_with_database(config: AppConfig) {
+    // set database
+    let pool = connect_database(&config).await;
+    let read_pool = connect_read_replica(&config, &pool).await;
+    // fail here rather than with "relation does not exist" on every request
+    if let Err(e) = prepare_database(&pool, config.database.run_migrations).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+
+    // todo and label reads are cached in Redis when REDIS_URL is set
+    let cache = config.cache.client().map(|client| {
+        let client = client.expect("invalid redis url");
+        RepositoryCache::new(client, Duration::from_secs(config.cache.ttl_secs))
+    });
+    let todo_repository = CachedTodoRepository::new(
+        TodoRepositoryForDb::new(pool.clone()).with_read_pool(read_pool.clone()),
+        cache.clone(),
+    );
+    let label_repository = CachedLabelRepository::new(
+        LabelRepositoryForDb::new(pool.clone()).with_read_pool(read_pool.clone()),
+        cache.clone(),
+    );
+
+    // background tasks, stopped before the pool is closed on shutdown
+    let scheduler = Scheduler::from_config(&config.jobs);
+    let notifier =
+        ChannelNotifier::new(Mailer::from_config(&config.mail)).expect("cannot build notifier");
+    let sender = HttpWebhookSender::new().expect("cannot build webhook sender");
+    let event_bus = ConfiguredPublisher::from_config(&config).expect("cannot configure event bus");
+    let blob_store = Arc::new(
+        ConfiguredBlobStore::from_config(&config.blob_store).expect("cannot configure blob store"),
     );
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let mut background_tasks = vec![
+        scheduler.spawn_recurrences(
+            todo_repository.clone(),
+            AuditRepositoryForDb::new(pool.clone()),
+        ),
+        scheduler.spawn_reminders(ReminderRepositoryForDb::new(pool.clone()), notifier),
+        scheduler.spawn_webhooks(WebhookRepositoryForDb::new(pool.clone()), sender),
+        scheduler.spawn_outbox_relay(
+            OutboxRepositoryForDb::new(pool.clone()),
+            event_bus,
+            WebhookRepositoryForDb::new(pool.clone()),
+        ),
+    ];
+    background_tasks.extend(MaintenanceJobs::from_config(&config.jobs).spawn(
+        todo_repository.clone(),
+        AuditRepositoryForDb::new(pool.clone()),
+        IdempotencyRepositoryForDb::new(pool.clone()),
+        blob_store.clone(),
+    ));
 
+    // build app
+    let todo = Arc::new(todo_repository);
+    let state = AppState {
+        todo: todo.clone(),
+        label: Arc::new(label_repository),
+        user: Arc::new(UserRepositoryForDb::new(pool.clone()).with_cache(cache.clone())),
+        refresh_token: Arc::new(RefreshTokenRepositoryForDb::new(pool.clone())),
+        workspace: Arc::new(WorkspaceRepositoryForDb::new(pool.clone()).with_cache(cache.clone())),
+        invitation: Arc::new(InvitationRepositoryForDb::new(pool.clone())),
+        audit: Arc::new(AuditRepositoryForDb::new(pool.clone())),
+        login_attempt: Arc::new(LoginAttemptRepositoryForDb::new(pool.clone())),
+        reminder: Arc::new(ReminderRepositoryForDb::new(pool.clone())),
+        attachment: Arc::new(AttachmentRepositoryForDb::new(pool.clone())),
+        blob_store: blob_store.clone(),
+        project: Arc::new(ProjectRepositoryForDb::new(pool.clone()).with_cache(cache.clone())),
+        template: Arc::new(TemplateRepositoryForDb::new(pool.clone())),
+        idempotency: Arc::new(IdempotencyRepositoryForDb::new(pool.clone())),
+        backup: Arc::new(BackupRepositoryForDb::new(pool.clone()).with_cache(cache.clone())),
+        webhook: Arc::new(WebhookRepositoryForDb::new(pool.clone())),
+        health_checks: vec![("database", todo), ("blob_store", blob_store)],
+    };
+    let app = create_app(&config, state);
+    serve(&config, app).await;
+
+    tracing::info!("server stopped, closing the database pool");
+    for task in background_tasks {
+        task.abort();
+    }
+    pool.close().await;
+    read_pool.close().await;
+}
+
+// REPO_BACKEND=memory. everything lives in this process, only todos and labels are saved
+// to MEMORY_SNAPSHOT_PATH on shutdown
+#[cfg(feature = "memory")]
+async fn run_in_memory(config: AppConfig) {
+    use crate::blob_store::memory::BlobStoreForMemory;
+    use crate::repositories::{
+        attachment::memory::AttachmentRepositoryForMemory, audit::memory::AuditRepositoryForMemory,
+        backup::memory::BackupRepositoryForMemory,
+        idempotency::memory::IdempotencyRepositoryForMemory,
+        invitation::memory::InvitationRepositoryForMemory,
+        login_attempt::memory::LoginAttemptRepositoryForMemory,
+        project::memory::ProjectRepositoryForMemory,
+        refresh_token::memory::RefreshTokenRepositoryForMemory,
+        reminder::memory::ReminderRepositoryForMemory,
+        template::memory::TemplateRepositoryForMemory, user::memory::UserRepositoryForMemory,
+        webhook::memory::WebhookRepositoryForMemory,
+        workspace::memory::WorkspaceRepositoryForMemory,
+    };
+    use crate::snapshot::{load_snapshot, save_snapshot};
+
+    let snapshot_path = &config.repository.snapshot_path;
+    let (todo, label) = load_snapshot(snapshot_path).unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+    tracing::warn!("using the in-memory backend. users and everything but todos and labels are lost on restart");
+
+    // the scheduler shares the repositories with the app
+    let reminder = ReminderRepositoryForMemory::new();
+    let idempotency = IdempotencyRepositoryForMemory::new();
+    let audit = AuditRepositoryForMemory::new();
+    // webhook deliveries are made from the outbox, which only the database has. webhooks
+    // can be registered but nothing is sent
+    let webhook = WebhookRepositoryForMemory::new();
+    let scheduler = Scheduler::from_config(&config.jobs);
+    let notifier =
+        ChannelNotifier::new(Mailer::from_config(&config.mail)).expect("cannot build notifier");
+    let blob_store = Arc::new(BlobStoreForMemory::new());
+    let mut background_tasks = vec![
+        scheduler.spawn_recurrences(todo.clone(), audit.clone()),
+        scheduler.spawn_reminders(reminder.clone(), notifier),
+    ];
+    background_tasks.extend(MaintenanceJobs::from_config(&config.jobs).spawn(
+        todo.clone(),
+        audit.clone(),
+        idempotency.clone(),
+        blob_store.clone(),
+    ));
+
+    let todo_repository = Arc::new(todo.clone());
+    let state = AppState {
+        todo: todo_repository.clone(),
+        label: Arc::new(label.clone()),
+        user: Arc::new(UserRepositoryForMemory::new()),
+        refresh_token: Arc::new(RefreshTokenRepositoryForMemory::new()),
+        workspace: Arc::new(WorkspaceRepositoryForMemory::new()),
+        invitation: Arc::new(InvitationRepositoryForMemory::new()),
+        audit: Arc::new(audit.clone()),
+        login_attempt: Arc::new(LoginAttemptRepositoryForMemory::new()),
+        reminder: Arc::new(reminder),
+        attachment: Arc::new(AttachmentRepositoryForMemory::new()),
+        blob_store: blob_store.clone(),
+        project: Arc::new(ProjectRepositoryForMemory::new()),
+        template: Arc::new(TemplateRepositoryForMemory::new()),
+        idempotency: Arc::new(idempotency),
+        backup: Arc::new(BackupRepositoryForMemory::new(
+            todo.clone(),
+            label.clone(),
+            audit.clone(),
+        )),
+        webhook: Arc::new(webhook),
+        health_checks: vec![("database", todo_repository), ("blob_store", blob_store)],
+    };
+    let app = create_app(&config, state);
+    serve(&config, app).await;
+
+    for task in background_tasks {
+        task.abort();
+    }
+    if snapshot_path.is_empty() {
+        return;
+    }
+    match save_snapshot(snapshot_path, &todo, &label) {
+        Ok(()) => tracing::info!("saved todos and labels to [{}]", snapshot_path),
+        Err(e) => tracing::error!("{}", e),
+    }
+}
+
+// serve until SIGTERM or Ctrl+C. in-flight requests are finished before returning
+// HTTPS is terminated here only when [tls] is configured, otherwise a proxy in front does it
+async fn serve(config: &AppConfig, app: Router) {
+    let addr = config.addr();
     tracing::debug!("listening on {}", addr);
 
-    // serve
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    if config.tls.enabled() {
+        let tls = ReloadableTlsConfig::load(&config.tls).expect("cannot load TLS certificate");
+        if config.tls.reload_secs > 0 {
+            tls.spawn_reload(Duration::from_secs(config.tls.reload_secs));
+        }
+        let incoming = TlsAcceptorIncoming::bind(addr, tls)
+            .await
+            .unwrap_or_else(|e| panic!("cannot listen on {}: {}", addr, e));
+        axum::Server::builder(incoming)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .unwrap();
+    } else {
+        axum::Server::bind(&addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .unwrap();
+    }
+}
+
+// resolves on the first SIGTERM (sent by rolling deploys) or SIGINT
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("cannot install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("cannot install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("shutdown signal received, waiting for in-flight requests");
 }
 
 // create app with repositories. return Router
-fn create_app<Todo: TodoRepository, Label: LabelRepository>(
-    todo_repository: Todo,
-    label_repository: Label,
-) -> Router {
-    let allow_origin_url: std::string::String =
-        env::var("ALLOW_ORIGIN_URL").expect("ALLOW_ORIGIN_URL must be set");
-
-    Router::new()
-        .route("/", get(root))
-        .route("/todos", post(create_todo::<Todo>).get(all_todo::<Todo>))
+// handlers read the repositories from AppState, so adding one does not change this signature
+fn create_app(config: &AppConfig, state: AppState) -> Router {
+    // full buckets are dropped off the request path. the task ends with the runtime
+    let rate_limiter = RateLimiter::from_config(&config.rate_limit);
+    rate_limiter.spawn_sweep(Duration::from_secs(config.rate_limit.window_secs));
+
+    // attachment uploads and backup imports are allowed larger bodies than the other routes
+    let upload_limit = limit_body(config.limits.max_upload_bytes);
+
+    // routes that require a valid access token
+    let protected = Router::new()
+        .route("/auth/me", get(me))
+        .route("/auth/logout-all", post(logout_all))
+        .route("/auth/claim", post(claim))
+        .route("/me", delete(delete_account))
+        .route("/me/export", get(export_account))
+        .route("/me/feeds", get(feed_token).delete(revoke_feed_tokens))
+        .route("/export", get(export_backup))
+        .route(
+            "/todos",
+            post(create_todo).get(all_todo).delete(delete_todos),
+        )
         .route(
             "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>),
+            get(find_todo).delete(delete_todo).patch(update_todo),
         )
+        .route("/todos/bulk", post(create_todos))
+        .route("/todos/complete", post(complete_todos))
+        .route("/todos/labels", post(relabel_todos))
+        .route("/todos/search", get(search_todos))
+        .route("/todos/undo", post(undo_todo))
+        .route("/todos/from-template/:id", post(create_todo_from_template))
+        .route("/todos/:id/archive", post(archive_todo))
+        .route("/todos/:id/unarchive", post(unarchive_todo))
+        .route("/todos/:id/pin", post(pin_todo))
+        .route("/todos/:id/unpin", post(unpin_todo))
+        .route("/todos/:id/snooze", post(snooze_todo))
+        .route("/todos/:id/unsnooze", post(unsnooze_todo))
+        .route("/todos/:id/move", patch(move_todo))
+        .route("/todos/:id/assign", patch(assign_todo))
+        .route("/todos/:id/duplicate", post(duplicate_todo))
+        .route("/todos/:id/history", get(todo_history))
+        .route("/todos/:id/revert/:revision_id", post(revert_todo))
+        .route("/todos/:id/share", post(share_todo))
+        .route("/todos/:id/subtasks", get(find_subtasks))
         .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>),
+            "/todos/:id/reminders",
+            post(create_reminder).get(all_reminders),
+        )
+        .route("/todos/:id/reminders/:reminder_id", delete(delete_reminder))
+        .route("/todos/:id/attachments", get(all_attachments))
+        .route(
+            "/todos/:id/attachments/:attachment_id",
+            get(download_attachment).delete(delete_attachment),
+        )
+        .route("/projects", post(create_project).get(all_projects))
+        .route(
+            "/projects/:id",
+            get(find_project)
+                .patch(update_project)
+                .delete(delete_project),
+        )
+        .route("/projects/:id/todos", get(project_todos))
+        .route("/templates", post(create_template).get(all_templates))
+        .route(
+            "/templates/:id",
+            get(find_template)
+                .patch(update_template)
+                .delete(delete_template),
+        )
+        .route("/labels", post(create_label).get(all_label))
+        .route("/labels/:id", delete(delete_label))
+        .route("/labels/:id", get(find_label).patch(update_label))
+        .route("/labels/user/:user_id", get(find_by_user))
+        .route("/labels/stats", get(label_stats))
+        .route("/labels/tree", get(label_tree))
+        .route("/labels/reorder", patch(reorder_labels))
+        .route("/labels/:id/todos", get(label_todos))
+        .route("/labels/:id/archive", post(archive_label))
+        .route("/labels/:id/unarchive", post(unarchive_label))
+        .route("/admin/todos", get(all_users_todo))
+        .route("/admin/labels/:id", delete(delete_any_label))
+        .route("/admin/users", get(all_users))
+        .route("/admin/users/:id", delete(delete_user))
+        .route("/admin/users/:id/disable", post(disable_user))
+        .route("/admin/users/:id/enable", post(enable_user))
+        .route(
+            "/admin/users/:id/password-reset",
+            post(require_password_reset),
+        )
+        .route("/audit", get(all_audit_events))
+        .route("/webhooks", post(create_webhook).get(all_webhooks))
+        .route(
+            "/webhooks/:id",
+            get(find_webhook)
+                .patch(update_webhook)
+                .delete(delete_webhook),
         )
-        .route("/labels/:id", delete(delete_label::<Label>))
+        .route("/workspaces", post(create_workspace).get(all_workspace))
         .route(
-            "/labels/:id",
-            get(find_label::<Label>).patch(update_label::<Label>),
+            "/workspaces/:workspace_id",
+            get(find_workspace)
+                .patch(update_workspace)
+                .delete(delete_workspace),
         )
-        .route("/labels/user/:user_id", get(find_by_user::<Label>))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
+        // same handlers as /todos and /labels, scoped to the workspace in the path
+        .route(
+            "/workspaces/:workspace_id/todos",
+            post(create_todo).get(all_todo),
+        )
+        .route(
+            "/workspaces/:workspace_id/labels",
+            post(create_label).get(all_label),
+        )
+        .route(
+            "/workspaces/:workspace_id/invitations",
+            post(create_invitation),
+        )
+        .route("/invitations/:token/accept", post(accept_invitation))
+        .route_layer(limit_body(config.limits.max_body_bytes))
+        // uploads are added after the default limit, so that only their own larger limit applies
+        .route("/import", post(import_backup).layer(upload_limit.clone()))
+        .route(
+            "/todos/:id/attachments",
+            post(upload_attachment).layer(upload_limit),
+        )
+        // route_layer runs the last added layer first: authenticate, then resolve the workspace
+        .route_layer(middleware::from_fn(resolve_workspace))
+        .route_layer(middleware::from_fn(require_auth));
+
+    // every route of the v1 API
+    let v1 = Router::new()
+        .route("/auth/register", post(register))
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/guest", post(create_guest))
+        .route("/auth/password", post(change_password))
+        .route("/auth/:provider", get(authorize))
+        .route("/auth/:provider/callback", get(callback))
+        // calendar apps and feed readers cannot send an Authorization header, so feeds carry their own token
+        .route("/todos/calendar.ics", get(calendar_feed))
+        .route("/todos/feed.atom", get(atom_feed))
+        // sub-requests authenticate on their own, so the batch itself is public
+        .route("/batch", post(batch))
+        .route_layer(limit_body(config.limits.max_body_bytes))
+        .merge(protected);
+    // v2 starts out identical to v1. breaking changes replace routes here only, so v1 clients keep working
+    let v2 = v1.clone();
+    let versioned = Router::new()
+        .nest("/v1", v1)
+        .nest("/v2", v2)
+        // nested routers cannot have their own fallback, so unknown /v1 and /v2 paths end up here
+        .fallback(not_found.into_service());
+
+    let app = Router::new()
+        .route("/", get(root))
+        // probes for Kubernetes, outside of the versioned API. readyz checks the database and blob store
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .merge(versioned.clone())
+        // unversioned paths are deprecated aliases of /v1, or of the version asked for in Accept
+        .fallback((move |req: Request<Body>| route_unversioned(versioned, req)).into_service());
+    // innermost, so that only the handlers are timed. 0 turns the timeout off
+    let app = match request_timeout(&config.server) {
+        Some(duration) => app.layer(timeout(duration)),
+        None => app,
+    };
+    let app = app
+        // added before the extensions below so that it can read JwtKeys and RateLimiter
+        .layer(middleware::from_fn(rate_limit))
+        .layer(Extension(state))
+        .layer(Extension(JwtKeys::new(config.auth.jwt_secret.as_bytes())))
+        .layer(Extension(OAuthProviders::from_config(&config.oauth)))
+        .layer(Extension(Mailer::from_config(&config.mail)))
+        .layer(Extension(rate_limiter))
+        .layer(Extension(LoginLockout::from_config(&config.lockout)))
         .layer(
-            CorsLayer::new()
-                .allow_origin(AllowOrigin::exact(allow_origin_url.parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
+            // config.validate() has already checked allow_origin
+            cors_layer(&config.cors)
+                .expect("invalid cors config")
+                .allow_headers(vec![
+                    CONTENT_TYPE,
+                    AUTHORIZATION,
+                    IF_MATCH,
+                    IF_NONE_MATCH,
+                    HeaderName::from_static(WORKSPACE_HEADER),
+                    HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+                    HeaderName::from_static(TRACEPARENT_HEADER),
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                ])
+                .expose_headers(vec![
+                    ETAG,
+                    LINK,
+                    HeaderName::from_static(DEPRECATION_HEADER),
+                    HeaderName::from_static(TOTAL_COUNT_HEADER),
+                    HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+                    HeaderName::from_static(TRACEPARENT_HEADER),
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                ]),
         )
+        .layer(middleware::from_fn(add_links))
+        // todo and label bodies are written as JSON or MessagePack, whichever Accept prefers.
+        // outside CorsLayer, which replaces the Vary header
+        .layer(middleware::from_fn(negotiate_body))
+        // the version of the path, or of Accept for unversioned paths, for add_links
+        .layer(middleware::from_fn(negotiate_version))
+        // axum answers wrong methods with an empty 405, turn it into JSON with an Allow header
+        .layer(middleware::from_fn(describe_method_not_allowed))
+        // outermost, so that HEAD bodies are stripped after every other layer and plain OPTIONS
+        // never reach the handlers. CORS preflights are passed through to CorsLayer
+        .layer(middleware::from_fn(answer_head_and_options))
+        // a panic anywhere inside becomes a problem+json 500 instead of a dropped connection
+        .layer(catch_panic())
+        // one span per request, continuing the trace from the incoming traceparent
+        .layer(middleware::from_fn(trace_request))
+        // the client behind the reverse proxies, for rate limiting, login failures and the span
+        .layer(middleware::from_fn(resolve_client))
+        // config.validate() has already checked trusted_proxies
+        .layer(Extension(TrustedProxies::new(
+            parse_networks(&config.server.trusted_proxies).expect("invalid trusted proxies"),
+            config.tls.enabled(),
+        )))
+        // cheap 503s before any other work when too many requests are in flight
+        .layer(middleware::from_fn(shed_load))
+        .layer(Extension(ConcurrencyLimit::from_config(&config.server)))
+        // X-Request-Id on every response. outside of trace_request so that the span records it
+        .layer(middleware::from_fn(assign_request_id));
+
+    // POST /batch dispatches its sub-requests through every layer above, as if they were sent one
+    // by one. the clone is taken before this layer, so the app does not hold a reference to itself
+    let dispatcher = Arc::new(ServiceTransport::new(app.clone()));
+    app.layer(Extension(dispatcher))
 }
 
 async fn root() -> &'static str {
@@ -101,20 +763,174 @@ async fn root() -> &'static str {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::repositories::label::test_utils::LabelRepositoryForMemory;
-    use crate::repositories::todo::{test_utils::TodoRepositoryForMemory, CreateTodo, Todo};
+    use crate::blob_store::memory::BlobStoreForMemory;
+    use crate::client::{ClientError, ServiceTransport, TodoClient};
+    use crate::repositories::attachment::memory::AttachmentRepositoryForMemory;
+    use crate::repositories::audit::{
+        memory::AuditRepositoryForMemory, AuditAction, AuditEvent, AuditRepository,
+    };
+    use crate::repositories::backup::{memory::BackupRepositoryForMemory, Backup, ImportSummary};
+    use crate::repositories::idempotency::memory::IdempotencyRepositoryForMemory;
+    use crate::repositories::invitation::memory::InvitationRepositoryForMemory;
+    use crate::repositories::label::{
+        memory::LabelRepositoryForMemory, CreateLabel, Label, LabelNode, LabelQuery,
+        LabelRepository, UpdateLabel,
+    };
+    use crate::repositories::login_attempt::memory::LoginAttemptRepositoryForMemory;
+    use crate::repositories::outbox::memory::OutboxRepositoryForMemory;
+    use crate::repositories::project::{
+        memory::ProjectRepositoryForMemory, CreateProject, ProjectRepository,
+    };
+    use crate::repositories::refresh_token::memory::RefreshTokenRepositoryForMemory;
+    use crate::repositories::reminder::{memory::ReminderRepositoryForMemory, Reminder};
+    use crate::repositories::template::memory::TemplateRepositoryForMemory;
+    use crate::repositories::todo::{
+        memory::TodoRepositoryForMemory, CreateTodo, Priority, SharePermission, Status, Todo,
+        TodoRepository, TodoRevision, TodoSearchHit, TodoShare, UpdateTodo,
+    };
+    use crate::repositories::user::{
+        memory::UserRepositoryForMemory, CreateUser, Role, UserRepository,
+    };
+    use crate::repositories::webhook::{
+        memory::WebhookRepositoryForMemory, Webhook, WebhookRepository,
+    };
+    use crate::repositories::workspace::Membership;
+    use crate::repositories::workspace::{
+        memory::WorkspaceRepositoryForMemory, CreateWorkspace, Workspace, WorkspaceRepository,
+    };
+    use crate::repositories::Scope;
+    use crate::routes::RouteError;
     use axum::response::Response;
     use axum::{
         body::Body,
         http::{header, Method, Request, StatusCode},
     };
+    use handlers::auth::{AuthBody, Credentials, GuestBody};
+    use handlers::batch::SubResponse;
+    use handlers::feed::FeedBody;
+    use handlers::health::HealthBody;
+    use handlers::invitation::InvitationBody;
+    use handlers::label::LabelStats;
+    use handlers::template::TemplateTodoBody;
+    use handlers::todo::{BulkBody, TodoCursorPage, UndoBody};
+    use handlers::webhook::CreatedWebhook;
+    use handlers::{JSON_PATCH_CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE};
     use tower::ServiceExt;
 
+    // in-memory repositories shared between the requests of a test
+    #[derive(Clone)]
+    struct TestRepos {
+        todo: TodoRepositoryForMemory,
+        label: LabelRepositoryForMemory,
+        user: UserRepositoryForMemory,
+        refresh_token: RefreshTokenRepositoryForMemory,
+        workspace: WorkspaceRepositoryForMemory,
+        invitation: InvitationRepositoryForMemory,
+        audit: AuditRepositoryForMemory,
+        login_attempt: LoginAttemptRepositoryForMemory,
+        reminder: ReminderRepositoryForMemory,
+        attachment: AttachmentRepositoryForMemory,
+        blob_store: BlobStoreForMemory,
+        project: ProjectRepositoryForMemory,
+        template: TemplateRepositoryForMemory,
+        idempotency: IdempotencyRepositoryForMemory,
+        backup: BackupRepositoryForMemory,
+        webhook: WebhookRepositoryForMemory,
+    }
+
+    impl TestRepos {
+        fn new() -> Self {
+            let todo = TodoRepositoryForMemory::new();
+            let label = LabelRepositoryForMemory::with_todos(todo.clone());
+            let audit = AuditRepositoryForMemory::new();
+            // require_auth reads the user behind every token, so the ids the tests sign for exist
+            let user = UserRepositoryForMemory::new();
+            for id in [1, 2, 3] {
+                user.insert(id, Role::Member);
+            }
+            user.insert(99, Role::Admin);
+            Self {
+                todo: todo.clone(),
+                label: label.clone(),
+                user,
+                refresh_token: RefreshTokenRepositoryForMemory::new(),
+                workspace: WorkspaceRepositoryForMemory::new(),
+                invitation: InvitationRepositoryForMemory::new(),
+                audit: audit.clone(),
+                login_attempt: LoginAttemptRepositoryForMemory::new(),
+                reminder: ReminderRepositoryForMemory::new(),
+                attachment: AttachmentRepositoryForMemory::new(),
+                blob_store: BlobStoreForMemory::new(),
+                project: ProjectRepositoryForMemory::new(),
+                template: TemplateRepositoryForMemory::new(),
+                idempotency: IdempotencyRepositoryForMemory::new(),
+                backup: BackupRepositoryForMemory::new(todo, label, audit),
+                webhook: WebhookRepositoryForMemory::new(),
+            }
+        }
+
+        fn app(&self) -> Router {
+            self.app_with(&test_config())
+        }
+
+        fn app_with(&self, config: &AppConfig) -> Router {
+            let todo = Arc::new(self.todo.clone());
+            let blob_store = Arc::new(self.blob_store.clone());
+            let state = AppState {
+                todo: todo.clone(),
+                label: Arc::new(self.label.clone()),
+                user: Arc::new(self.user.clone()),
+                refresh_token: Arc::new(self.refresh_token.clone()),
+                workspace: Arc::new(self.workspace.clone()),
+                invitation: Arc::new(self.invitation.clone()),
+                audit: Arc::new(self.audit.clone()),
+                login_attempt: Arc::new(self.login_attempt.clone()),
+                reminder: Arc::new(self.reminder.clone()),
+                attachment: Arc::new(self.attachment.clone()),
+                blob_store: blob_store.clone(),
+                project: Arc::new(self.project.clone()),
+                template: Arc::new(self.template.clone()),
+                idempotency: Arc::new(self.idempotency.clone()),
+                backup: Arc::new(self.backup.clone()),
+                webhook: Arc::new(self.webhook.clone()),
+                health_checks: vec![("database", todo), ("blob_store", blob_store)],
+            };
+            create_app(config, state)
+        }
+    }
+
+    // the app in the tests does not read the config file or the environment
+    fn test_config() -> AppConfig {
+        let mut config = AppConfig::default();
+        config.database.url = "postgres://localhost/todos".to_string();
+        config.cors.allow_origin = "http://localhost:3001".to_string();
+        config.auth.jwt_secret = "test-secret".to_string();
+        config
+    }
+
+    // access token signed with the same secret as create_app
+    fn bearer_token_with_role(user_id: i32, role: Role) -> String {
+        let secret = test_config().auth.jwt_secret;
+        let token = JwtKeys::new(secret.as_bytes())
+            .issue(user_id, role)
+            .unwrap();
+        format!("Bearer {}", token)
+    }
+
+    fn bearer_token_for(user_id: i32) -> String {
+        bearer_token_with_role(user_id, Role::Member)
+    }
+
+    fn bearer_token() -> String {
+        bearer_token_for(1)
+    }
+
     fn build_todo_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
         Request::builder()
             .uri(path)
             .method(method)
             .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::AUTHORIZATION, bearer_token())
             .body(Body::from(json_body))
             .unwrap()
     }
@@ -123,36 +939,117 @@ mod test {
         Request::builder()
             .uri(path)
             .method(method)
+            .header(header::AUTHORIZATION, bearer_token())
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn build_req_with_token(method: Method, path: &str, token: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::AUTHORIZATION, token)
             .body(Body::empty())
             .unwrap()
     }
 
+    fn build_req_without_token(path: &str, method: Method, json_body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .body(Body::from(json_body))
+            .unwrap()
+    }
+
     async fn res_to_todo(res: Response) -> Todo {
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body: String = String::from_utf8(bytes.to_vec()).unwrap();
         let todo: Todo = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {}", body));
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
         todo
     }
 
+    async fn res_to_json<T: serde::de::DeserializeOwned>(res: Response) -> T {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| panic!("cannot convert response body: {:?}", bytes))
+    }
+
     #[tokio::test]
     async fn should_return_hello_world() {
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
         let req = Request::builder().uri("/").body(Body::empty()).unwrap();
-        let router = create_app(todo_repo, label_repo);
-        let res = router.oneshot(req).await.unwrap();
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
         let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
         let body = String::from_utf8(bytes.to_vec()).unwrap();
         assert_eq!(body, "hello world");
     }
 
+    #[tokio::test]
+    async fn should_answer_health_probes() {
+        for path in ["/healthz", "/readyz"] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let res = TestRepos::new().app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status(), "{}", path);
+            let body: HealthBody = res_to_json(res).await;
+            assert_eq!(body.status, "ok");
+            if path == "/readyz" {
+                assert_eq!(body.checks["database"], "ok");
+                assert_eq!(body.checks["blob_store"], "ok");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_continue_incoming_trace() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let req = Request::builder()
+            .uri("/healthz")
+            .header(
+                TRACEPARENT_HEADER,
+                format!("00-{}-00f067aa0ba902b7-01", trace_id),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        let traceparent = res.headers()[TRACEPARENT_HEADER].to_str().unwrap();
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts[1], trace_id);
+        assert_ne!(parts[2], "00f067aa0ba902b7");
+
+        // without traceparent a new trace is started
+        let req = Request::builder()
+            .uri("/healthz")
+            .body(Body::empty())
+            .unwrap();
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        let traceparent = res.headers()[TRACEPARENT_HEADER].to_str().unwrap();
+        assert!(!traceparent.contains(trace_id));
+    }
+
+    #[tokio::test]
+    async fn should_return_request_id() {
+        let req = Request::builder()
+            .uri("/v1/nothing")
+            .header(REQUEST_ID_HEADER, "support-1234")
+            .body(Body::empty())
+            .unwrap();
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "support-1234");
+        let error: RouteError = res_to_json(res).await;
+        assert_eq!(error.request_id.as_deref(), Some("support-1234"));
+
+        // generated when the client did not send one, also on error responses of the handlers
+        let req = build_todo_req_with_empty(Method::GET, "/v1/todos/99");
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert!(!res.headers()[REQUEST_ID_HEADER].is_empty());
+    }
+
     #[tokio::test]
     async fn should_created_todo() {
-        let expected = Todo::new(1, "should_return_created_todo".to_string());
+        let expected = Todo::new(1, 1, "should_return_created_todo".to_string());
 
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
         let req = build_todo_req_with_json(
             "/todos",
             Method::POST,
@@ -163,96 +1060,4625 @@ mod test {
             }"#
             .to_string(),
         );
-        let res = create_app(todo_repo, label_repo)
+        let res = TestRepos::new()
+            .app()
             .oneshot(req)
             .await
             .expect("failed create todo");
 
         let todo = res_to_todo(res).await;
+        let expected = Todo {
+            created_at: todo.created_at,
+            ..expected
+        };
         assert_eq!(expected, todo);
     }
 
     #[tokio::test]
     async fn should_find_todo() {
-        let expected = Todo::new(1, "should_find_todo".to_string());
+        let expected = Todo::new(1, 1, "should_find_todo".to_string());
 
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo
-            .create(CreateTodo::new("should_find_todo".to_string(), vec![]))
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("should_find_todo".to_string(), vec![]),
+            )
             .await
             .expect("cannot create todo");
         let req = build_todo_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(todo_repo, label_repo)
-            .oneshot(req)
-            .await
-            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
         let todo = res_to_todo(res).await;
+        let expected = Todo {
+            created_at: todo.created_at,
+            ..expected
+        };
         assert_eq!(expected, todo);
     }
 
+    #[tokio::test]
+    async fn should_render_todo_description() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "todo", "labels": [], "description": "**bold** <script>alert(1)</script>" }"#
+                .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // the markdown itself is returned as is
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: serde_json::Value = res_to_json(res).await;
+        assert_eq!(todo["description"], "**bold** <script>alert(1)</script>");
+        assert!(todo.get("description_html").is_none());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1?render=html");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: serde_json::Value = res_to_json(res).await;
+        assert_eq!(
+            todo["description_html"],
+            "<p><strong>bold</strong> &lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+
+        // descriptions are limited to 10000 characters
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            format!(r#"{{ "description": "{}" }}"#, "a".repeat(10001)),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // null clears the description
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "description": null }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: serde_json::Value = res_to_json(res).await;
+        assert_eq!(todo["description"], serde_json::Value::Null);
+    }
+
     #[tokio::test]
     async fn should_get_all_todos() {
-        let expected = Todo::new(1, "should_get_all_todos".to_string());
+        let expected = Todo::new(1, 1, "should_get_all_todos".to_string());
 
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo
-            .create(CreateTodo::new("should_get_all_todos".to_string(), vec![]))
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("should_get_all_todos".to_string(), vec![]),
+            )
             .await
             .expect("cannot create todo");
         let req = build_todo_req_with_empty(Method::GET, "/todos");
-        let res = create_app(todo_repo, label_repo)
-            .oneshot(req)
-            .await
-            .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: Vec<Todo> = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {:?}", body));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: Vec<Todo> = res_to_json(res).await;
+        let expected = Todo {
+            created_at: todo[0].created_at,
+            ..expected
+        };
         assert_eq!(vec![expected], todo);
     }
 
     #[tokio::test]
-    async fn should_update_todo() {
-        let expected = Todo::new(1, "should_update_todo".to_string());
+    async fn should_filter_overdue_todos() {
+        let repos = TestRepos::new();
+        for (text, due_at) in [
+            ("overdue", "2000-01-01T00:00:00Z"),
+            ("upcoming", "2999-01-01T00:00:00Z"),
+        ] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "labels": [], "due_at": "{}" }}"#,
+                    text, due_at
+                ),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?overdue=true");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].text, "overdue");
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos?due_before=2500-01-01T00:00:00Z&overdue=false",
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert!(todos.is_empty());
+
+        // malformed timestamps are rejected by the Query extractor
+        let req = build_todo_req_with_empty(Method::GET, "/todos?due_before=yesterday");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_sort_todos_by_priority() {
+        let repos = TestRepos::new();
+        for priority in ["low", "urgent", "medium"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "labels": [], "priority": "{}" }}"#,
+                    priority, priority
+                ),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // unknown priorities are rejected
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "todo", "labels": [], "priority": "someday" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
 
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo
-            .create(CreateTodo::new("before_update_todo".to_string(), vec![]))
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=priority");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["urgent", "medium", "low"]);
+    }
+
+    #[tokio::test]
+    async fn should_replay_idempotent_create() {
+        let repos = TestRepos::new();
+        let create = |key: &str, text: &str| {
+            let mut req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            req.headers_mut()
+                .insert(IDEMPOTENCY_KEY_HEADER, key.parse().unwrap());
+            req
+        };
+
+        let res = repos.app().oneshot(create("key-1", "once")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        let created = res_to_todo(res).await;
+
+        // a retry returns the stored response without creating another todo
+        let res = repos.app().oneshot(create("key-1", "once")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!(res.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(res_to_todo(res).await, created);
+
+        // the same key with a different body is rejected
+        let res = repos.app().oneshot(create("key-1", "twice")).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // a failed request releases its key so that it can be retried
+        let mut req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "in project", "labels": [], "project_id": 99 }"#.to_string(),
+        );
+        req.headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, "key-2".parse().unwrap());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let res = repos
+            .app()
+            .oneshot(create("key-2", "retried"))
             .await
-            .expect("cannot create todo");
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let res = repos.app().oneshot(create("", "empty key")).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_create_todos_in_bulk() {
+        let repos = TestRepos::new();
         let req = build_todo_req_with_json(
-            "/todos/1",
-            Method::PATCH,
-            r#"{
-                "text": "should_update_todo",
-                "completed": false
-            }"#
+            "/todos/bulk",
+            Method::POST,
+            r#"[
+                { "text": "first", "labels": [] },
+                { "text": "second", "labels": [], "priority": "high" }
+            ]"#
             .to_string(),
         );
-        let res = create_app(todo_repo, label_repo)
-            .oneshot(req)
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let created: Vec<(i32, &str)> = todos
+            .iter()
+            .map(|todo| (todo.id, todo.text.as_str()))
+            .collect();
+        assert_eq!(created, vec![(1, "first"), (2, "second")]);
+        assert_eq!(todos[1].priority, Priority::High);
+
+        // nothing is created when one of the todos is invalid
+        for body in [
+            r#"[]"#,
+            r#"[{ "text": "fourth", "labels": [] }, { "text": "", "labels": [] }]"#,
+            r#"{ "text": "fourth", "labels": [] }"#,
+            r#"[{ "text": "fourth", "labels": [] }, { "text": "fifth", "labels": [], "parent_id": 99 }]"#,
+        ] {
+            let req = build_todo_req_with_json("/todos/bulk", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert!(res.status().is_client_error(), "{}", body);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_complete_and_delete_todos_in_bulk() {
+        let repos = TestRepos::new();
+        for body in [
+            r#"{ "text": "overdue", "labels": [], "due_at": "2000-01-01T00:00:00Z" }"#,
+            r#"{ "text": "upcoming", "labels": [], "due_at": "2999-01-01T00:00:00Z" }"#,
+            r#"{ "text": "plain", "labels": [] }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let others = repos
+            .todo
+            .create(
+                Scope::personal(2),
+                CreateTodo::new("others".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        let bulk = |method: Method, path: &str, body: &str| {
+            build_todo_req_with_json(path, method, body.to_string())
+        };
+        let affected = |res: Response| async {
+            assert_eq!(StatusCode::OK, res.status());
+            let body: BulkBody = res_to_json(res).await;
+            body.affected
+        };
+
+        let req = bulk(
+            Method::POST,
+            "/todos/complete",
+            r#"{ "filter": { "overdue": true } }"#,
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(affected(res).await, 1);
+        // done todos are not counted again
+        let body = format!(r#"{{ "ids": [1, 2, {}] }}"#, others.id);
+        let res = repos
+            .app()
+            .oneshot(bulk(Method::POST, "/todos/complete", &body))
             .await
             .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
+        assert_eq!(affected(res).await, 1);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/2/history");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let revisions: Vec<TodoRevision> = res_to_json(res).await;
+        assert_eq!(revisions[0].changes["status"]["after"], "done");
+        assert_eq!(repos.audit.all().await.unwrap().len(), 5);
+
+        let req = bulk(
+            Method::DELETE,
+            "/todos",
+            r#"{ "filter": { "completed": true, "text_contains": "up" } }"#,
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(affected(res).await, 1);
+        let body = format!(r#"{{ "ids": [1, {}] }}"#, others.id);
+        let res = repos
+            .app()
+            .oneshot(bulk(Method::DELETE, "/todos", &body))
+            .await
+            .unwrap();
+        assert_eq!(affected(res).await, 1);
+
+        for body in [
+            r#"{ "ids": [] }"#,
+            r#"{ "ids": [3], "filter": {} }"#,
+            r#"{}"#,
+        ] {
+            let res = repos
+                .app()
+                .oneshot(bulk(Method::DELETE, "/todos", body))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", body);
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(
+            todos
+                .iter()
+                .map(|todo| todo.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["plain"]
+        );
+        assert!(repos.todo.find(Scope::personal(2), others.id).await.is_ok());
     }
 
     #[tokio::test]
-    async fn should_delete_todo() {
-        let todo_repo = TodoRepositoryForMemory::new();
-        let label_repo = LabelRepositoryForMemory::new();
-        todo_repo
-            .create(CreateTodo::new("should_delete_todo".to_string(), vec![]))
+    async fn should_relabel_todos_in_bulk() {
+        let repos = TestRepos::new();
+        for name in ["home", "work"] {
+            repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+        }
+        for text in ["first", "second"] {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(text.to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let others = repos
+            .todo
+            .create(
+                Scope::personal(2),
+                CreateTodo::new("others".to_string(), vec![]),
+            )
             .await
             .expect("cannot create todo");
-        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(todo_repo, label_repo)
-            .oneshot(req)
+        let relabel = |body: String| build_todo_req_with_json("/todos/labels", Method::POST, body);
+        let label_ids = |todo: Todo| todo.labels.iter().map(|label| label.id).collect::<Vec<_>>();
+
+        // other users' todos are skipped
+        let body = format!(r#"{{ "todo_ids": [1, 2, {}], "add": [1, 2] }}"#, others.id);
+        let res = repos.app().oneshot(relabel(body)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: BulkBody = res_to_json(res).await;
+        assert_eq!(body.affected, 2);
+        let todo = repos.todo.find(Scope::personal(1), 2).await.unwrap();
+        assert_eq!(label_ids(todo), vec![1, 2]);
+        assert!(repos
+            .todo
+            .find(Scope::personal(2), others.id)
             .await
-            .unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
+            .unwrap()
+            .labels
+            .is_empty());
+
+        // only todos whose labels change are counted
+        let body = r#"{ "todo_ids": [1, 2], "add": [1], "remove": [2] }"#.to_string();
+        let res = repos.app().oneshot(relabel(body)).await.unwrap();
+        let body: BulkBody = res_to_json(res).await;
+        assert_eq!(body.affected, 2);
+        let todo = repos.todo.find(Scope::personal(1), 1).await.unwrap();
+        assert_eq!(label_ids(todo), vec![1]);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/history");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let revisions: Vec<TodoRevision> = res_to_json(res).await;
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(
+            revisions[1].changes["labels"]["after"],
+            serde_json::json!([1])
+        );
+        assert_eq!(repos.audit.all().await.unwrap().len(), 4);
+
+        for (body, status) in [
+            (r#"{ "todo_ids": [1] }"#, StatusCode::BAD_REQUEST),
+            (r#"{ "todo_ids": [], "add": [1] }"#, StatusCode::BAD_REQUEST),
+            (
+                r#"{ "todo_ids": [1], "add": [1], "remove": [1] }"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (r#"{ "todo_ids": [1], "add": [99] }"#, StatusCode::NOT_FOUND),
+        ] {
+            let res = repos
+                .app()
+                .oneshot(relabel(body.to_string()))
+                .await
+                .unwrap();
+            assert_eq!(status, res.status(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_create_recurring_todo() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "weekly", "labels": [], "recurrence": { "freq": "weekly" } }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo: serde_json::Value = res_to_json(res).await;
+        assert_eq!(
+            todo["recurrence"],
+            serde_json::json!({ "freq": "weekly", "interval": 1, "until": null })
+        );
+
+        // the interval must be positive
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", todo["id"]),
+            Method::PATCH,
+            r#"{ "recurrence": { "freq": "daily", "interval": 0 } }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // null stops the recurrence
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", todo["id"]),
+            Method::PATCH,
+            r#"{ "recurrence": null }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(res_to_todo(res).await.recurrence, None);
+    }
+
+    #[tokio::test]
+    async fn should_manage_reminders() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "remind me", "labels": [] }"#.to_string(),
+        );
+        let todo = res_to_todo(repos.app().oneshot(req).await.unwrap()).await;
+        let path = format!("/todos/{}/reminders", todo.id);
+
+        let req = build_todo_req_with_json(
+            &path,
+            Method::POST,
+            r#"{ "remind_at": "2022-12-31T09:00:00Z", "channel": "email" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let reminder: Reminder = res_to_json(res).await;
+        assert_eq!(reminder.todo_id, todo.id);
+
+        // webhook reminders need a url
+        let req = build_todo_req_with_json(
+            &path,
+            Method::POST,
+            r#"{ "remind_at": "2022-12-31T09:00:00Z", "channel": "webhook" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // reminders can not be set on todos the user can not see
+        let req = build_req_with_token(Method::GET, &path, bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let reminders: Vec<Reminder> = res_to_json(repos.app().oneshot(req).await.unwrap()).await;
+        assert_eq!(reminders, vec![reminder.clone()]);
+
+        let req = build_todo_req_with_empty(Method::DELETE, &format!("{}/{}", path, reminder.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let reminders: Vec<Reminder> = res_to_json(repos.app().oneshot(req).await.unwrap()).await;
+        assert!(reminders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_manage_webhooks() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/webhooks",
+            Method::POST,
+            r#"{ "url": "https://example.com/hook", "events": ["todo.created"] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let created: CreatedWebhook = res_to_json(res).await;
+        let webhook = created.webhook;
+        assert_eq!(created.secret.len(), 64);
+        assert_eq!(webhook.events, vec!["todo.created"]);
+
+        // unknown events and invalid urls are rejected
+        for body in [
+            r#"{ "url": "https://example.com/hook", "events": ["todo.archived"] }"#,
+            r#"{ "url": "not a url" }"#,
+        ] {
+            let req = build_todo_req_with_json("/webhooks", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+
+        // the secret is only returned on creation
+        let path = format!("/webhooks/{}", webhook.id);
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let res = repos.app().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["url"], "https://example.com/hook");
+        assert!(body.get("secret").is_none());
+
+        // other users can not see the webhook
+        let req = build_req_with_token(Method::GET, &path, bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_req_with_token(Method::GET, "/webhooks", bearer_token_for(2));
+        let webhooks: Vec<Webhook> = res_to_json(repos.app().oneshot(req).await.unwrap()).await;
+        assert!(webhooks.is_empty());
+
+        // changes made after registration are queued for delivery when the outbox is relayed.
+        // the database writes the outbox from a trigger, here it is recorded by hand
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "hooked", "labels": [] }"#.to_string(),
+        );
+        let todo = res_to_todo(repos.app().oneshot(req).await.unwrap()).await;
+        let event = OutboxRepositoryForMemory::new().record(
+            "todo.created",
+            todo.id,
+            serde_json::to_value(&todo).unwrap(),
+        );
+        assert_eq!(repos.webhook.enqueue(&[event]).await.unwrap(), 1);
+        let deliveries = repos.webhook.take_due(chrono::Utc::now()).await.unwrap();
+        assert_eq!(deliveries[0].event, "todo.created");
+        assert_eq!(deliveries[0].secret, created.secret);
+        assert_eq!(deliveries[0].payload["data"]["id"], todo.id);
+
+        let req = build_todo_req_with_json(
+            &path,
+            Method::PATCH,
+            r#"{ "events": ["label.created", "label.deleted"] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let updated: Webhook = res_to_json(res).await;
+        assert_eq!(updated.url, webhook.url);
+        assert_eq!(updated.events, vec!["label.created", "label.deleted"]);
+
+        let req = build_todo_req_with_empty(Method::DELETE, &path);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    fn build_upload_req(
+        path: &str,
+        file_name: &str,
+        content: &str,
+        token: String,
+    ) -> Request<Body> {
+        let boundary = "test-boundary";
+        let body = format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             {content}\r\n\
+             --{boundary}--\r\n"
+        );
+        Request::builder()
+            .uri(path)
+            .method(Method::POST)
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .header(header::AUTHORIZATION, token)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_manage_attachments() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "with attachments", "labels": [] }"#.to_string(),
+        );
+        let todo = res_to_todo(repos.app().oneshot(req).await.unwrap()).await;
+        let path = format!("/todos/{}/attachments", todo.id);
+
+        let req = build_upload_req(&path, "memo.txt", "hello", bearer_token());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let attachment: serde_json::Value = res_to_json(res).await;
+        assert_eq!(attachment["file_name"], "memo.txt");
+        assert_eq!(attachment["size"], 5);
+        // the storage key is internal
+        assert!(attachment.get("storage_key").is_none());
+        assert_eq!(repos.blob_store.count(), 1);
+
+        // users who can not see the todo can not upload to it
+        let req = build_upload_req(&path, "memo.txt", "hello", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let attachments: Vec<serde_json::Value> =
+            res_to_json(repos.app().oneshot(req).await.unwrap()).await;
+        assert_eq!(attachments.len(), 1);
+
+        let file_path = format!("{}/{}", path, attachment["id"]);
+        let req = build_todo_req_with_empty(Method::GET, &file_path);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"memo.txt\""
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"hello");
+
+        let req = build_todo_req_with_empty(Method::DELETE, &file_path);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert_eq!(repos.blob_store.count(), 0);
+
+        // deleting the todo removes the stored files as well
+        let req = build_upload_req(&path, "memo.txt", "hello", bearer_token());
+        repos.app().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_empty(Method::DELETE, &format!("/todos/{}", todo.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert_eq!(repos.blob_store.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn should_manage_subtasks() {
+        let repos = TestRepos::new();
+        let create =
+            |body: &str| build_todo_req_with_json("/todos", Method::POST, body.to_string());
+        let res = repos
+            .app()
+            .oneshot(create(r#"{ "text": "parent", "labels": [] }"#))
+            .await
+            .unwrap();
+        let parent = res_to_todo(res).await;
+        let res = repos
+            .app()
+            .oneshot(create(&format!(
+                r#"{{ "text": "child", "labels": [], "parent_id": {} }}"#,
+                parent.id
+            )))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let child = res_to_todo(res).await;
+
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}/subtasks", parent.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let subtasks: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(subtasks, vec![child.clone()]);
+
+        // a todo can not become a subtask of its own subtask
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", parent.id),
+            Method::PATCH,
+            format!(r#"{{ "parent_id": {} }}"#, child.id),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // completing the parent can complete its subtasks too
+        let req = build_todo_req_with_json(
+            &format!("/todos/{}", parent.id),
+            Method::PATCH,
+            r#"{ "status": "done", "complete_subtasks": true }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", child.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(res_to_todo(res).await.status, Status::Done);
+
+        // other users can not read the subtasks
+        let req = build_req_with_token(
+            Method::GET,
+            &format!("/todos/{}/subtasks", parent.id),
+            bearer_token_for(2),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_update_todo() {
+        let expected = Todo::new(1, 1, "should_update_todo".to_string());
+
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("before_update_todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{
+                "text": "should_update_todo",
+                "status": "backlog"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        let expected = Todo {
+            created_at: todo.created_at,
+            ..expected
+        };
+        assert_eq!(expected, todo);
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("should_delete_todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_change_todo_status() {
+        let repos = TestRepos::new();
+        for text in ["first", "second"] {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(text.to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let patch_status = |status: &str| {
+            build_todo_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                format!(r#"{{ "status": "{}" }}"#, status),
+            )
+        };
+
+        for status in ["in_progress", "done", "in_progress", "cancelled"] {
+            let res = repos.app().oneshot(patch_status(status)).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        // cancelled todos have to go back to the backlog first
+        let res = repos.app().oneshot(patch_status("done")).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let res = repos.app().oneshot(patch_status("backlog")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // unknown statuses are rejected
+        let res = repos.app().oneshot(patch_status("someday")).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let res = repos
+            .app()
+            .oneshot(patch_status("in_progress"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos?status=in_progress");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn should_group_todos_by_project() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/projects",
+            Method::POST,
+            r#"{ "name": "board" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let other = repos
+            .project
+            .create(Scope::personal(2), CreateProject::new("other board"))
+            .await
+            .expect("cannot create project");
+
+        for (text, project_id) in [("in project", "1"), ("loose", "null")] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "labels": [], "project_id": {} }}"#,
+                    text, project_id
+                ),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        // todos can not be put into other users' projects
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            format!(r#"{{ "project_id": {} }}"#, other.id),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/projects/1/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["in project"]);
+        let req = build_todo_req_with_empty(Method::GET, &format!("/projects/{}/todos", other.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // deleting with todos=delete removes the project's todos too
+        let req = build_todo_req_with_empty(Method::DELETE, "/projects/1?todos=delete");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["loose"]);
+        let req = build_todo_req_with_empty(Method::GET, "/projects/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // cascade=true is the same as todos=delete
+        let req = build_todo_req_with_json(
+            "/projects",
+            Method::POST,
+            r#"{ "name": "second board" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let project: serde_json::Value = res_to_json(res).await;
+        let project_id = project["id"].as_i64().unwrap();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(
+                r#"{{ "text": "cascaded", "labels": [], "project_id": {} }}"#,
+                project_id
+            ),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_todo_req_with_empty(
+            Method::DELETE,
+            &format!("/projects/{}?cascade=true", project_id),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["loose"]);
+    }
+
+    #[tokio::test]
+    async fn should_create_todo_from_template() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/templates",
+            Method::POST,
+            r#"{ "name": "release", "text": "release v1", "priority": "high", "subtasks": ["tag", "publish"] }"#
+                .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/from-template/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let body: TemplateTodoBody = res_to_json(res).await;
+        assert_eq!(body.todo.text, "release v1");
+        assert_eq!(body.todo.priority, Priority::High);
+        let texts: Vec<&str> = body
+            .subtasks
+            .iter()
+            .map(|todo| todo.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["tag", "publish"]);
+        assert!(body
+            .subtasks
+            .iter()
+            .all(|subtask| subtask.parent_id == Some(body.todo.id)));
+
+        // templates of other users can not be used
+        let req = Request::builder()
+            .uri("/todos/from-template/1")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, bearer_token_for(2))
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // editing the template does not change the todos made from it
+        let req = build_todo_req_with_json(
+            "/templates/1",
+            Method::PATCH,
+            r#"{ "text": "release v2", "subtasks": [] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_todo_req_with_empty(Method::POST, "/todos/from-template/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let body: TemplateTodoBody = res_to_json(res).await;
+        assert_eq!(body.todo.text, "release v2");
+        assert!(body.subtasks.is_empty());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: Todo = res_to_json(res).await;
+        assert_eq!(todo.text, "release v1");
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/templates/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::POST, "/todos/from-template/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_duplicate_todo() {
+        let repos = TestRepos::new();
+        let create = |body: String| build_todo_req_with_json("/todos", Method::POST, body);
+        for body in [
+            r#"{ "text": "parent", "labels": [], "priority": "high" }"#.to_string(),
+            r#"{ "text": "child", "labels": [], "parent_id": 1 }"#.to_string(),
+            r#"{ "text": "grandchild", "labels": [], "parent_id": 2 }"#.to_string(),
+        ] {
+            let res = repos.app().oneshot(create(body)).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/archive");
+        repos.app().oneshot(req).await.unwrap();
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/duplicate");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let copy: Todo = res_to_json(res).await;
+        assert_eq!(copy.id, 4);
+        assert_eq!(copy.text, "parent");
+        assert_eq!(copy.priority, Priority::High);
+        assert_eq!(copy.archived_at, None);
+
+        // the whole subtask tree is copied under the copy
+        let req = build_todo_req_with_empty(Method::GET, "/todos/4/subtasks");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let children: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].text, "child");
+        let req =
+            build_todo_req_with_empty(Method::GET, &format!("/todos/{}/subtasks", children[0].id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let grandchildren: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = grandchildren
+            .iter()
+            .map(|todo| todo.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["grandchild"]);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let original: Todo = res_to_json(res).await;
+        assert_eq!(original.parent_id, Some(1));
+
+        // other users can not duplicate the todo
+        let req = Request::builder()
+            .uri("/todos/1/duplicate")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, bearer_token_for(2))
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_revert_todo_to_previous_revision() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("draft".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        for body in [
+            r#"{ "text": "final", "priority": "high" }"#,
+            r#"{ "status": "done" }"#,
+            // nothing changes, so no revision is written
+            r#"{ "text": "final" }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos/1", Method::PATCH, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/history");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let revisions: Vec<TodoRevision> = res_to_json(res).await;
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].actor_id, Some(1));
+        assert_eq!(
+            revisions[0].changes,
+            serde_json::json!({
+                "text": { "before": "draft", "after": "final" },
+                "priority": { "before": "medium", "after": "high" },
+            })
+        );
+
+        // reverting the first revision restores text and priority but keeps the status
+        let req = build_todo_req_with_empty(
+            Method::POST,
+            &format!("/todos/1/revert/{}", revisions[0].id),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo: Todo = res_to_json(res).await;
+        assert_eq!(todo.text, "draft");
+        assert_eq!(todo.priority, Priority::Medium);
+        assert_eq!(todo.status, Status::Done);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/history");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let revisions: Vec<TodoRevision> = res_to_json(res).await;
+        assert_eq!(revisions.len(), 3);
+
+        // revisions of other todos can not be used
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("other".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        let req = build_todo_req_with_empty(
+            Method::POST,
+            &format!("/todos/2/revert/{}", revisions[0].id),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos() {
+        let repos = TestRepos::new();
+        for text in ["Buy milk", "Write report", "buy bread"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        for (query, expected) in [
+            ("completed=true", vec![1]),
+            ("completed=false", vec![3, 2]),
+            ("text_contains=BUY", vec![3, 1]),
+            ("text_contains=buy&completed=false", vec![3]),
+            ("created_after=2000-01-01T00:00:00Z", vec![3, 2, 1]),
+            ("created_after=2999-01-01T00:00:00Z", vec![]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos?{}", query));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let todos: Vec<Todo> = res_to_json(res).await;
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, expected, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_sort_todos_and_labels() {
+        let repos = TestRepos::new();
+        for (text, due_at) in [
+            ("banana", Some("2030-01-01T00:00:00Z")),
+            ("apple", None),
+            ("cherry", Some("2020-01-01T00:00:00Z")),
+            ("apple", Some("2025-01-01T00:00:00Z")),
+        ] {
+            let due_at = due_at.map_or("null".to_string(), |due_at| format!(r#""{}""#, due_at));
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "labels": [], "due_at": {} }}"#,
+                    text, due_at
+                ),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        for (query, expected) in [
+            ("", vec![4, 3, 2, 1]),
+            ("order=asc", vec![1, 2, 3, 4]),
+            ("sort=created_at&order=asc", vec![1, 2, 3, 4]),
+            // ties are broken by id in the same direction
+            ("sort=text", vec![2, 4, 1, 3]),
+            ("sort=text&order=desc", vec![3, 1, 4, 2]),
+            // todos without a due date always come last
+            ("sort=due_at", vec![3, 4, 1, 2]),
+            ("sort=due_at&order=desc", vec![1, 4, 3, 2]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos?{}", query));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let todos: Vec<Todo> = res_to_json(res).await;
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, expected, "{}", query);
+        }
+        for query in ["sort=id%3BDROP%20TABLE%20todos", "order=sideways"] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos?{}", query));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert!(res.status().is_client_error(), "{}", query);
+        }
+
+        for name in ["work", "home", "errand"] {
+            repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+        }
+        for (query, expected) in [
+            ("", vec!["work", "home", "errand"]),
+            ("sort=name", vec!["errand", "home", "work"]),
+            ("sort=created_at&order=desc", vec!["errand", "home", "work"]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/labels?{}", query));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let labels: Vec<Label> = res_to_json(res).await;
+            let names: Vec<&str> = labels.iter().map(|label| label.name.as_str()).collect();
+            assert_eq!(names, expected, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_route_api_versions() {
+        let repos = TestRepos::new();
+        for i in 1..=2 {
+            let req = build_todo_req_with_json(
+                "/v1/todos",
+                Method::POST,
+                format!(r#"{{ "text": "todo {}", "labels": [] }}"#, i),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // versioned paths keep the prefix in pagination links
+        for version in ["v1", "v2"] {
+            let path = format!("/{}/todos?per_page=1", version);
+            let req = build_todo_req_with_empty(Method::GET, &path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert!(res.headers().get(DEPRECATION_HEADER).is_none());
+            let next = format!(r#"</{}/todos?page=2&per_page=1>; rel="next""#, version);
+            assert_eq!(res.headers()[LINK], next);
+        }
+
+        // unversioned paths are deprecated aliases of v1
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[DEPRECATION_HEADER], "true");
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+
+        // unless the version is asked for in Accept
+        let accept = |version: &str| {
+            let mut req = build_todo_req_with_empty(Method::GET, "/todos");
+            let media_type = format!("application/vnd.rust-web.{}+json", version);
+            req.headers_mut()
+                .insert(header::ACCEPT, media_type.parse().unwrap());
+            req
+        };
+        let res = repos.app().oneshot(accept("v2")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().get(DEPRECATION_HEADER).is_none());
+        let res = repos.app().oneshot(accept("v9")).await.unwrap();
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/v9/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_list_as_csv() {
+        let repos = TestRepos::new();
+        let label = repos
+            .label
+            .create(CreateLabel::new("home, sweet".to_string(), 1))
+            .await
+            .expect("cannot create label");
+        for text in ["first", "second, last"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let csv_req = |path: &str, accept: &str| {
+            let mut req = build_todo_req_with_empty(Method::GET, path);
+            req.headers_mut()
+                .insert(header::ACCEPT, accept.parse().unwrap());
+            req
+        };
+        let csv_body = |res: Response| async move {
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        // same filters and pagination as the JSON listing
+        let req = csv_req(
+            "/todos?sort=created_at&per_page=1&fields=text,id",
+            "text/csv",
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "2");
+        assert!(res.headers()[LINK]
+            .to_str()
+            .unwrap()
+            .contains(r#"rel="next""#));
+        assert_eq!(csv_body(res).await, "text,id\r\n\"second, last\",2\r\n");
+
+        let req = csv_req("/todos", "text/csv");
+        let csv = csv_body(repos.app().oneshot(req).await.unwrap()).await;
+        assert!(csv.starts_with("id,text,description,status,"));
+        assert_eq!(csv.lines().count(), 3);
+
+        // JSON stays the default unless CSV is preferred
+        let req = csv_req("/todos", "application/json, text/csv;q=0.5");
+        let todos: Vec<Todo> = res_to_json(repos.app().oneshot(req).await.unwrap()).await;
+        assert_eq!(todos.len(), 2);
+
+        // CSV has nowhere to put the next cursor
+        let req = csv_req("/todos?limit=1", "text/csv");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, res.status());
+
+        let req = csv_req("/labels?q=home", "text/csv");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            csv_body(res).await,
+            format!(
+                "id,name,user_id,workspace_id,color,parent_id,position,archived_at,icon\r\n\
+                 {},\"home, sweet\",1,,{},,{},,\r\n",
+                label.id, label.color, label.position
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn should_answer_head_and_options() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/v1/todos",
+            Method::POST,
+            r#"{ "text": "should_answer_head_and_options", "labels": [] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // HEAD has the same headers as GET, without the body
+        for path in ["/v1/todos", "/todos", "/"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            let length = hyper::body::to_bytes(res.into_body()).await.unwrap().len();
+
+            let req = build_todo_req_with_empty(Method::HEAD, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!(res.headers()[header::CONTENT_LENGTH], length.to_string());
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert!(body.is_empty());
+        }
+
+        // OPTIONS lists the methods of the route without running a handler
+        let req = build_todo_req_with_empty(Method::OPTIONS, "/v1/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert_eq!(
+            res.headers()[header::ALLOW],
+            "GET, HEAD, PATCH, DELETE, OPTIONS"
+        );
+        let req = build_todo_req_with_empty(Method::OPTIONS, "/todos/search");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+        let req = build_todo_req_with_empty(Method::OPTIONS, "/v1/nothing");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // CORS preflights are still answered by CorsLayer
+        let origin = test_config().cors.allow_origin;
+        let req = Request::builder()
+            .uri("/v1/todos")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, &origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert!(res.headers().get(header::ALLOW).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_allow_configured_origins() {
+        let repos = TestRepos::new();
+        let preflight = |origin: &str| {
+            Request::builder()
+                .uri("/v1/todos")
+                .method(Method::OPTIONS)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let mut config = test_config();
+        config.cors.allow_origin = "http://localhost:3001,https://*.example.com".to_string();
+        config.cors.allow_credentials = true;
+        for origin in ["http://localhost:3001", "https://app.example.com"] {
+            let res = repos
+                .app_with(&config)
+                .oneshot(preflight(origin))
+                .await
+                .unwrap();
+            assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+            assert_eq!(
+                res.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+                "true"
+            );
+            assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_METHODS], "POST");
+        }
+        let res = repos
+            .app_with(&config)
+            .oneshot(preflight("https://example.com.evil.dev"))
+            .await
+            .unwrap();
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        // the permissive mode lets any origin in
+        let mut config = test_config();
+        config.cors.permissive = true;
+        let res = repos
+            .app_with(&config)
+            .oneshot(preflight("http://192.168.0.10:8080"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_reject_oversized_bodies() {
+        let repos = TestRepos::new();
+        let mut config = test_config();
+        config.limits.max_body_bytes = 64;
+        let text = "a".repeat(100);
+        let req = build_todo_req_with_json(
+            "/v1/todos",
+            Method::POST,
+            format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+        );
+        let res = repos.app_with(&config).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let request_id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let error: RouteError = res_to_json(res).await;
+        assert_eq!(error.status, 413);
+        assert_eq!(error.message, "request body must be at most 64 bytes");
+        assert_eq!(error.request_id, Some(request_id));
+
+        // without Content-Length the body is read up to the limit
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..10 {
+                if sender
+                    .send_data(axum::body::Bytes::from(text.clone()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let req = Request::builder()
+            .uri("/v1/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::AUTHORIZATION, bearer_token())
+            .body(body)
+            .unwrap();
+        let res = repos.app_with(&config).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let json = r#"{ "text": "small", "labels": [] }"#.to_string();
+        let req = build_todo_req_with_json("/v1/todos", Method::POST, json);
+        let res = repos.app_with(&config).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // imports get max_upload_bytes, so the same body gets as far as the handler
+        let json = format!(r#"{{ "text": "{}" }}"#, "a".repeat(100));
+        let req = build_todo_req_with_json("/v1/import", Method::POST, json);
+        let res = repos.app_with(&config).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn should_match_route_table() {
+        // every method listed for a route is routed, every other one is rejected with 405
+        for (route, methods) in routes::ROUTES {
+            // wrong methods still pass through the route layers, so workspace 1 has to exist
+            let repos = TestRepos::new();
+            let req = build_todo_req_with_json(
+                "/v1/workspaces",
+                Method::POST,
+                r#"{"name": "team"}"#.to_string(),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let path: Vec<&str> = route
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with(':') {
+                        "1"
+                    } else {
+                        segment
+                    }
+                })
+                .collect();
+            let path = match route {
+                "/" | "/healthz" | "/readyz" => route.to_string(),
+                _ => format!("/v1{}", path.join("/")),
+            };
+            for method in [
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ] {
+                let req = build_todo_req_with_empty(method.clone(), &path);
+                let res = repos.app().oneshot(req).await.unwrap();
+                assert_eq!(
+                    res.status() == StatusCode::METHOD_NOT_ALLOWED,
+                    !methods.contains(&method.as_str()),
+                    "{} {}",
+                    method,
+                    path
+                );
+                if res.status() == StatusCode::METHOD_NOT_ALLOWED {
+                    let allow = routes::allowed_methods(&path).unwrap();
+                    assert_eq!(res.headers()[header::ALLOW], allow.as_str());
+                }
+            }
+        }
+
+        // and every route of create_app is in the table. axum cannot list the routes of a
+        // Router, so they are read from the source of create_app
+        let source = include_str!("main.rs");
+        let start = source.find("fn create_app(").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+        let mut rest = &source[start..end];
+        while let Some(index) = rest.find(".route(") {
+            rest = rest[index + ".route(".len()..].trim_start();
+            let path = rest
+                .strip_prefix('"')
+                .and_then(|rest| rest.split('"').next())
+                .unwrap();
+            assert!(
+                routes::ROUTES.iter().any(|(route, _)| *route == path),
+                "{} is missing from routes::ROUTES",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_describe_unknown_routes() {
+        let repos = TestRepos::new();
+        for path in ["/v1/nothing", "/nothing", "/v3/todos", "/v1"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+            let request_id = res.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let error: RouteError = res_to_json(res).await;
+            assert_eq!(
+                error,
+                RouteError {
+                    status: 404,
+                    error: "Not Found".to_string(),
+                    message: format!("no route for GET {}", path),
+                    request_id: Some(request_id),
+                }
+            );
+        }
+
+        // not found resources keep the empty 404 of the handlers
+        let req = build_todo_req_with_empty(Method::GET, "/v1/todos/99");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        for path in ["/v1/todos", "/todos"] {
+            let req = build_todo_req_with_empty(Method::PUT, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+            assert_eq!(
+                res.headers()[header::ALLOW],
+                "GET, HEAD, POST, DELETE, OPTIONS"
+            );
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+            let error: RouteError = res_to_json(res).await;
+            assert_eq!(error.status, 405);
+            assert_eq!(error.error, "Method Not Allowed");
+            assert_eq!(error.message, format!("PUT is not allowed for {}", path));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_call_api_with_client() {
+        let repos = TestRepos::new();
+        let mut client = TodoClient::new(ServiceTransport::new(repos.app()));
+        let credentials = Credentials {
+            email: "client@example.com".to_string(),
+            password: "password123".to_string(),
+        };
+        let auth = client.register(&credentials).await.unwrap();
+        assert_eq!(auth.token_type, "Bearer");
+
+        // the token is only sent once it is set
+        match client.all_todos().await {
+            Err(ClientError::Status { status, .. }) => assert_eq!(status, StatusCode::UNAUTHORIZED),
+            res => panic!("unexpected response: {:?}", res),
+        }
+        let auth = client.login(&credentials).await.unwrap();
+        client.set_token(auth.access_token);
+
+        let label = client
+            .create_label(&CreateLabel::new("client".to_string(), 0))
+            .await
+            .unwrap();
+        assert_eq!(client.all_labels().await.unwrap(), vec![label.clone()]);
+        let update: UpdateLabel =
+            serde_json::from_value(serde_json::json!({ "color": "#000000" })).unwrap();
+        let label = client.update_label(label.id, &update).await.unwrap();
+        assert_eq!(label.color, "#000000");
+        assert_eq!(client.find_label(label.id).await.unwrap(), label);
+
+        let todo = client
+            .create_todo(&CreateTodo::new(
+                "should_call_api_with_client".to_string(),
+                vec![],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(client.all_todos().await.unwrap(), vec![todo.clone()]);
+        let update: UpdateTodo =
+            serde_json::from_value(serde_json::json!({ "text": "updated" })).unwrap();
+        let todo = client.update_todo(todo.id, &update).await.unwrap();
+        assert_eq!(todo.text, "updated");
+        assert_eq!(client.find_todo(todo.id).await.unwrap(), todo);
+
+        client.delete_todo(todo.id).await.unwrap();
+        client.delete_label(label.id).await.unwrap();
+        match client.find_todo(todo.id).await {
+            Err(ClientError::Status { status, .. }) => assert_eq!(status, StatusCode::NOT_FOUND),
+            res => panic!("unexpected response: {:?}", res),
+        }
+        assert!(client.all_labels().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_batch_requests() {
+        let repos = TestRepos::new();
+        let batch = serde_json::json!([
+            { "method": "POST", "path": "/v1/todos", "body": { "text": "batched", "labels": [] } },
+            { "method": "get", "path": "/todos?status=backlog" },
+            { "method": "GET", "path": "/v1/todos/999" },
+            { "method": "POST", "path": "/v1/todos", "body": { "text": "" } },
+            { "method": "PUT", "path": "/v1/todos" },
+        ]);
+        let req = build_todo_req_with_json("/v1/batch", Method::POST, batch.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let responses: Vec<SubResponse> = res_to_json(res).await;
+        let statuses: Vec<u16> = responses.iter().map(|res| res.status).collect();
+        assert_eq!(statuses, vec![201, 200, 404, 400, 405]);
+        let todo: Todo = serde_json::from_value(responses[0].body.clone().unwrap()).unwrap();
+        assert_eq!(todo.text, "batched");
+        let todos: Vec<Todo> = serde_json::from_value(responses[1].body.clone().unwrap()).unwrap();
+        assert_eq!(todos, vec![todo]);
+        assert_eq!(responses[2].body, None);
+        // bodies that are not JSON come back as strings
+        assert!(responses[3].body.as_ref().unwrap().is_string());
+
+        // every sub-request is authenticated on its own
+        let batch = r#"[{ "method": "GET", "path": "/todos" }]"#;
+        let req = build_req_without_token("/batch", Method::POST, batch.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let responses: Vec<SubResponse> = res_to_json(res).await;
+        assert_eq!(responses[0].status, 401);
+
+        let nested = r#"[{ "method": "POST", "path": "/v1/batch", "body": [] }]"#;
+        let req = build_todo_req_with_json("/v1/batch", Method::POST, nested.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_link_todos_and_labels() {
+        let repos = TestRepos::new();
+        for i in 1..=2 {
+            let req = build_todo_req_with_json(
+                "/v1/todos",
+                Method::POST,
+                format!(r#"{{ "text": "todo {}", "labels": [] }}"#, i),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_todo_req_with_json(
+            "/v2/labels",
+            Method::POST,
+            r#"{ "name": "should_link_todos_and_labels" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: serde_json::Value = res_to_json(res).await;
+        assert_eq!(
+            label["_links"],
+            serde_json::json!({
+                "self": { "href": format!("/v2/labels/{}", label["id"]) },
+                "todos": { "href": format!("/v2/labels/{}/todos", label["id"]) },
+            })
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/v1/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: serde_json::Value = res_to_json(res).await;
+        assert_eq!(
+            todo["_links"],
+            serde_json::json!({
+                "self": { "href": "/v1/todos/1" },
+                "subtasks": { "href": "/v1/todos/1/subtasks" },
+                "labels": [],
+            })
+        );
+
+        // the next link of a cursor page can be followed as is
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let page: serde_json::Value = res_to_json(res).await;
+        assert_eq!(page["items"][0]["_links"]["self"]["href"], "/v1/todos/2");
+        let next = page["_links"]["next"]["href"].as_str().unwrap();
+        assert!(next.starts_with("/v1/todos?limit=1&cursor="));
+        let req = build_todo_req_with_empty(Method::GET, next);
+        let res = repos.app().oneshot(req).await.unwrap();
+        let page: serde_json::Value = res_to_json(res).await;
+        assert_eq!(page["items"][0]["_links"]["self"]["href"], "/v1/todos/1");
+        assert!(page.get("_links").is_none());
+
+        // an unversioned path links to the version negotiated with Accept
+        let mut req = build_todo_req_with_empty(Method::GET, "/todos?limit=1");
+        req.headers_mut().insert(
+            header::ACCEPT,
+            "application/vnd.rust-web.v2+json".parse().unwrap(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let page: serde_json::Value = res_to_json(res).await;
+        assert_eq!(page["items"][0]["_links"]["self"]["href"], "/v2/todos/2");
+        assert_eq!(
+            page["items"][0]["_links"]["subtasks"]["href"],
+            "/v2/todos/2/subtasks"
+        );
+        let next = page["_links"]["next"]["href"].as_str().unwrap();
+        assert!(next.starts_with("/v2/todos?limit=1&cursor="), "{}", next);
+    }
+
+    #[tokio::test]
+    async fn should_merge_patch_todo() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{
+                "text": "should_merge_patch_todo",
+                "description": "keep me",
+                "labels": [],
+                "due_at": "2030-01-01T00:00:00Z",
+                "recurrence": { "freq": "weekly", "interval": 1 }
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: Todo = res_to_json(res).await;
+        assert!(todo.due_at.is_some());
+
+        let merge_patch = |body: &str| {
+            let path = format!("/todos/{}", todo.id);
+            let mut req = build_todo_req_with_json(&path, Method::PATCH, body.to_string());
+            req.headers_mut().insert(
+                header::CONTENT_TYPE,
+                MERGE_PATCH_CONTENT_TYPE.parse().unwrap(),
+            );
+            req
+        };
+        // null clears the field, absent fields are left alone, objects are merged field by field
+        let req = merge_patch(r#"{ "due_at": null, "recurrence": { "interval": 2 } }"#);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo: Todo = res_to_json(res).await;
+        assert_eq!(todo.due_at, None);
+        assert_eq!(todo.description, Some("keep me".to_string()));
+        assert_eq!(todo.text, "should_merge_patch_todo");
+        let recurrence = todo.recurrence.unwrap();
+        assert_eq!(serde_json::to_value(recurrence.freq).unwrap(), "weekly");
+        assert_eq!(recurrence.interval, 2);
+
+        // fields that always have a value can not be removed
+        for body in [r#"{ "text": null }"#, r#"[]"#, r#"{ "text": "" }"#] {
+            let res = repos.app().oneshot(merge_patch(body)).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_json_patch_todo() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{
+                "text": "should_json_patch_todo",
+                "description": "keep me",
+                "labels": [],
+                "due_at": "2030-01-01T00:00:00Z"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todo: Todo = res_to_json(res).await;
+
+        let json_patch = |body: &str| {
+            let path = format!("/todos/{}", todo.id);
+            let mut req = build_todo_req_with_json(&path, Method::PATCH, body.to_string());
+            req.headers_mut().insert(
+                header::CONTENT_TYPE,
+                JSON_PATCH_CONTENT_TYPE.parse().unwrap(),
+            );
+            req
+        };
+        // operations are applied in order, and only the touched fields change
+        let req = json_patch(
+            r#"[
+                { "op": "replace", "path": "/text", "value": "patched" },
+                { "op": "remove", "path": "/due_at" }
+            ]"#,
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo: Todo = res_to_json(res).await;
+        assert_eq!(todo.text, "patched");
+        assert_eq!(todo.due_at, None);
+        assert_eq!(todo.description, Some("keep me".to_string()));
+
+        // paths outside the allowlist, unsupported operations and bad indexes are rejected
+        for body in [
+            r#"[{ "op": "replace", "path": "/user_id", "value": 2 }]"#,
+            r#"[{ "op": "remove", "path": "/text" }]"#,
+            r#"[{ "op": "remove", "path": "/labels/5" }]"#,
+            r#"[{ "op": "test", "path": "/text", "value": "patched" }]"#,
+            r#"{ "text": "patched" }"#,
+        ] {
+            let res = repos.app().oneshot(json_patch(body)).await.unwrap();
+            assert!(res.status().is_client_error(), "{}", body);
+        }
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", todo.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let after: Todo = res_to_json(res).await;
+        assert_eq!(after.text, "patched");
+    }
+
+    #[tokio::test]
+    async fn should_paginate_todos() {
+        let repos = TestRepos::new();
+        for i in 1..=5 {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "todo {}", "labels": [] }}"#, i),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos?sort=created_at&per_page=2&page=2&text_contains=todo",
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "5");
+        assert_eq!(
+            res.headers()[LINK],
+            concat!(
+                r#"</todos?sort=created_at&text_contains=todo&page=3&per_page=2>; rel="next", "#,
+                r#"</todos?sort=created_at&text_contains=todo&page=1&per_page=2>; rel="prev""#,
+            )
+        );
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+
+        // the last page has no next link and per_page is capped
+        let req = build_todo_req_with_empty(Method::GET, "/todos?per_page=1000");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "5");
+        assert!(res.headers().get(LINK).is_none());
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 5);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?page=5&per_page=2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers()[LINK],
+            r#"</todos?page=4&per_page=2>; rel="prev""#
+        );
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert!(todos.is_empty());
+
+        for query in ["page=0", "per_page=0", "page=-1"] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos?{}", query));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_paginate_todos_with_cursor() {
+        let repos = TestRepos::new();
+        for (text, due_at) in [
+            ("banana", Some("2030-01-01T00:00:00Z")),
+            ("apple", None),
+            ("cherry", Some("2020-01-01T00:00:00Z")),
+            ("apple", Some("2025-01-01T00:00:00Z")),
+            ("durian", None),
+        ] {
+            let due_at = due_at.map_or("null".to_string(), |due_at| format!(r#""{}""#, due_at));
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(
+                    r#"{{ "text": "{}", "labels": [], "due_at": {} }}"#,
+                    text, due_at
+                ),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // following next_cursor walks the same order as the unpaginated list
+        for (query, expected) in [
+            ("", vec![5, 4, 3, 2, 1]),
+            ("sort=text", vec![2, 4, 1, 3, 5]),
+            ("sort=due_at", vec![3, 4, 1, 2, 5]),
+            ("sort=due_at&order=desc", vec![1, 4, 3, 5, 2]),
+        ] {
+            let mut ids = vec![];
+            let mut cursor = None;
+            loop {
+                let path = match &cursor {
+                    Some(cursor) => format!("/todos?{}&limit=2&cursor={}", query, cursor),
+                    None => format!("/todos?{}&limit=2", query),
+                };
+                let req = build_todo_req_with_empty(Method::GET, &path);
+                let res = repos.app().oneshot(req).await.unwrap();
+                assert_eq!(StatusCode::OK, res.status(), "{}", path);
+                let page: TodoCursorPage = res_to_json(res).await;
+                assert!(page.items.len() <= 2);
+                ids.extend(page.items.iter().map(|todo| todo.id));
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(ids, expected, "{}", query);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=due_at&limit=2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let page: TodoCursorPage = res_to_json(res).await;
+        let cursor = page.next_cursor.expect("next_cursor is missing");
+        for path in [
+            // the cursor belongs to a different sort order
+            format!("/todos?sort=text&cursor={}", cursor),
+            format!("/todos?sort=due_at&order=desc&cursor={}", cursor),
+            "/todos?cursor=not-a-cursor".to_string(),
+            "/todos?limit=0".to_string(),
+            "/todos?page=2&limit=2".to_string(),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_select_todo_and_label_fields() {
+        let repos = TestRepos::new();
+        for text in ["first", "second"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        repos
+            .label
+            .create(CreateLabel::new("work".to_string(), 1))
+            .await
+            .expect("cannot create label");
+
+        // unknown field names are ignored
+        let req = build_todo_req_with_empty(Method::GET, "/todos?fields=id,text,unknown");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todos: Vec<serde_json::Value> = res_to_json(res).await;
+        assert_eq!(
+            todos,
+            vec![
+                serde_json::json!({ "id": 2, "text": "second" }),
+                serde_json::json!({ "id": 1, "text": "first" }),
+            ]
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=1&fields=id");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let page: TodoCursorPage<serde_json::Value> = res_to_json(res).await;
+        assert_eq!(page.items, vec![serde_json::json!({ "id": 2 })]);
+        assert!(page.next_cursor.is_some());
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?fields=name");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let labels: Vec<serde_json::Value> = res_to_json(res).await;
+        assert_eq!(labels, vec![serde_json::json!({ "name": "work" })]);
+
+        for path in ["/todos?fields=", "/labels?fields=,"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_handle_conditional_requests() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "conditional", "labels": [] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let etag = res.headers()[ETAG].clone();
+
+        // If-None-Match uses the weak comparison and accepts a list of tags
+        for value in [
+            etag.to_str().unwrap().to_string(),
+            format!(r#""other", W/{}"#, etag.to_str().unwrap()),
+        ] {
+            let mut req = build_todo_req_with_empty(Method::GET, "/todos/1");
+            req.headers_mut()
+                .insert(IF_NONE_MATCH, value.parse().unwrap());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_MODIFIED, res.status(), "{}", value);
+            assert_eq!(res.headers()[ETAG], etag);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert!(body.is_empty());
+        }
+
+        let update = |if_match: &str| {
+            let mut req = build_todo_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                r#"{ "text": "updated" }"#.to_string(),
+            );
+            req.headers_mut()
+                .insert(IF_MATCH, if_match.parse().unwrap());
+            req
+        };
+        let res = repos
+            .app()
+            .oneshot(update(etag.to_str().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let updated_etag = res.headers()[ETAG].clone();
+        assert_ne!(updated_etag, etag);
+
+        // the first etag is stale now
+        let res = repos
+            .app()
+            .oneshot(update(etag.to_str().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let weak = format!("W/{}", updated_etag.to_str().unwrap());
+        let res = repos.app().oneshot(update(&weak)).await.unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+
+        let mut req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        req.headers_mut().insert(IF_NONE_MATCH, etag);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[ETAG], updated_etag);
+        let todo = res_to_todo(res).await;
+        assert_eq!(todo.text, "updated");
+    }
+
+    #[tokio::test]
+    async fn should_search_todos() {
+        let repos = TestRepos::new();
+        for body in [
+            r#"{ "text": "Write report", "description": "include milk prices", "labels": [] }"#,
+            r#"{ "text": "Buy MILK", "labels": [] }"#,
+            r#"{ "text": "<b>milk</b> tea", "labels": [] }"#,
+            r#"{ "text": "Walk the dog", "labels": [] }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/search?q=milk");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let hits: Vec<TodoSearchHit> = res_to_json(res).await;
+        // matches in text rank above matches in the description
+        let ids: Vec<i32> = hits.iter().map(|hit| hit.todo.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(hits[1].snippet, "Buy <mark>MILK</mark>");
+        // user input is escaped, only the highlight is markup
+        assert_eq!(hits[0].snippet, "&lt;b&gt;<mark>milk</mark>&lt;/b&gt; tea");
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/search?q=%20");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_undo_last_operation() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "draft", "labels": [] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "text": "final" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        // undo the delete, then the update, then the create
+        let req = build_todo_req_with_empty(Method::POST, "/todos/undo");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: UndoBody = res_to_json(res).await;
+        assert_eq!(body.action, AuditAction::Delete);
+        assert_eq!(body.todo.map(|todo| todo.text), Some("final".to_string()));
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/undo");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: UndoBody = res_to_json(res).await;
+        assert_eq!(body.action, AuditAction::Update);
+        assert_eq!(body.todo.map(|todo| todo.text), Some("draft".to_string()));
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/undo");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: UndoBody = res_to_json(res).await;
+        assert_eq!(body.action, AuditAction::Create);
+        assert_eq!(body.todo, None);
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // undoing is itself recorded, but can not be undone
+        let req = build_todo_req_with_empty(Method::POST, "/todos/undo");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let events = repos.audit.all().await.unwrap();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0].undo_of, Some(events[5].id));
+    }
+
+    #[tokio::test]
+    async fn should_not_undo_todo_changed_by_others() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "draft", "labels": [] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        // a change that did not go through the API, e.g. by another session
+        repos
+            .todo
+            .update(
+                Scope::personal(1),
+                1,
+                serde_json::from_str(r#"{ "text": "edited" }"#).unwrap(),
+            )
+            .await
+            .expect("cannot update todo");
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/undo");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_archive_todo() {
+        let repos = TestRepos::new();
+        for text in ["kept", "archived"] {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(text.to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/archive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert!(todo.archived_at.is_some());
+
+        // archived todos are hidden from the default list
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["kept"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?archived=true");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["archived"]);
+
+        // only the owner can archive
+        let req = build_req_with_token(Method::POST, "/todos/1/archive", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/unarchive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_pin_todo() {
+        let repos = TestRepos::new();
+        for i in 1..=12 {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(format!("todo {}", i), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let req = build_todo_req_with_empty(Method::POST, "/todos/3/pin");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res_to_todo(res).await.is_pinned);
+
+        // pinned todos come first in the default order, whichever the direction
+        for (path, expected) in [
+            ("/todos?per_page=3", vec![3, 12, 11]),
+            ("/todos?per_page=3&order=asc", vec![3, 1, 2]),
+            ("/todos?limit=3", vec![3, 12, 11]),
+            ("/todos?pinned=true", vec![3]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            let ids: Vec<i32> = match path.contains("limit") {
+                true => res_to_json::<TodoCursorPage>(res).await.items,
+                false => res_to_json::<Vec<Todo>>(res).await,
+            }
+            .iter()
+            .map(|todo| todo.id)
+            .collect();
+            assert_eq!(ids, expected, "{}", path);
+        }
+        // the cursor continues after the pinned todos
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let page: TodoCursorPage = res_to_json(res).await;
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            &format!("/todos?limit=2&cursor={}", page.next_cursor.unwrap()),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let page: TodoCursorPage = res_to_json(res).await;
+        let ids: Vec<i32> = page.items.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![12, 11]);
+
+        // pinning again does not count against the limit
+        let req = build_todo_req_with_empty(Method::POST, "/todos/3/pin");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        for id in 4..=12 {
+            let req = build_todo_req_with_empty(Method::POST, &format!("/todos/{}/pin", id));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/pin");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        // only the owner can pin
+        let req = build_req_with_token(Method::POST, "/todos/2/pin", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/3/unpin");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert!(!res_to_todo(res).await.is_pinned);
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/pin");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_snooze_todo() {
+        let repos = TestRepos::new();
+        for i in 1..=3 {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(format!("todo {}", i), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let req = build_todo_req_with_json(
+            "/todos/2/snooze",
+            Method::POST,
+            r#"{ "minutes": 60 }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res_to_todo(res).await.snoozed_until.is_some());
+        let req = build_todo_req_with_json(
+            "/todos/3/snooze",
+            Method::POST,
+            r#"{ "until": "2999-01-01T00:00:00Z" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // snoozed todos are hidden from the default list
+        for (path, expected) in [("/todos", vec![1]), ("/todos?snoozed=true", vec![3, 2])] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            let ids: Vec<i32> = res_to_json::<Vec<Todo>>(res)
+                .await
+                .iter()
+                .map(|todo| todo.id)
+                .collect();
+            assert_eq!(ids, expected, "{}", path);
+        }
+
+        for body in [
+            r#"{}"#,
+            r#"{ "minutes": 0 }"#,
+            r#"{ "until": "2000-01-01T00:00:00Z" }"#,
+            r#"{ "until": "2999-01-01T00:00:00Z", "minutes": 60 }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos/1/snooze", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", body);
+        }
+
+        // only the owner can snooze
+        let req = build_req_with_token(Method::POST, "/todos/2/unsnooze", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/unsnooze");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res_to_todo(res).await.snoozed_until, None);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_schedule_start_date() {
+        let repos = TestRepos::new();
+        for body in [
+            r#"{ "text": "started", "labels": [], "starts_at": "2000-01-01T00:00:00Z" }"#,
+            r#"{ "text": "upcoming", "labels": [], "starts_at": "2999-01-01T00:00:00Z",
+                "due_at": "2999-01-02T00:00:00Z" }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // todos that have not started are hidden from the default list
+        for (path, expected) in [("/todos", vec![1]), ("/todos?upcoming=true", vec![2])] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            let ids: Vec<i32> = res_to_json::<Vec<Todo>>(res)
+                .await
+                .iter()
+                .map(|todo| todo.id)
+                .collect();
+            assert_eq!(ids, expected, "{}", path);
+        }
+
+        // starts_at must not be after due_at
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "backwards", "labels": [], "starts_at": "2999-01-02T00:00:00Z",
+                "due_at": "2999-01-01T00:00:00Z" }"#
+                .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            r#"{ "due_at": "2998-12-31T00:00:00Z" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_json(
+            "/todos/2",
+            Method::PATCH,
+            r#"{ "starts_at": null }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!(res_to_todo(res).await.starts_at, None);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_assign_todo() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("personal".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        let workspace = repos
+            .workspace
+            .create(1, CreateWorkspace::new("team".to_string()))
+            .await
+            .expect("cannot create workspace");
+        repos
+            .workspace
+            .add_member(workspace.id, 2)
+            .await
+            .expect("cannot add member");
+        for text in ["mine", "theirs"] {
+            repos
+                .todo
+                .create(
+                    Scope::new(1, Some(workspace.id)),
+                    CreateTodo::new(text.to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let assign = |id: i32, body: &str| {
+            Request::builder()
+                .uri(format!("/todos/{}/assign", id))
+                .method(Method::PATCH)
+                .header(header::AUTHORIZATION, bearer_token())
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+                .header(WORKSPACE_HEADER, "1")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let res = repos
+            .app()
+            .oneshot(assign(2, r#"{ "assignee_id": 1 }"#))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res_to_todo(res).await.assignee_id, Some(1));
+        let res = repos
+            .app()
+            .oneshot(assign(3, r#"{ "assignee_id": 2 }"#))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // the assignee must be a member of the workspace
+        let res = repos
+            .app()
+            .oneshot(assign(3, r#"{ "assignee_id": 3 }"#))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // assignee=me lists the todos assigned to the current user
+        let req = Request::builder()
+            .uri("/todos?assignee=me")
+            .header(header::AUTHORIZATION, bearer_token_for(2))
+            .header(WORKSPACE_HEADER, "1")
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["theirs"]);
+        let req = build_todo_req_with_empty(Method::GET, "/todos?assignee=abc");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // null unassigns
+        let res = repos
+            .app()
+            .oneshot(assign(3, r#"{ "assignee_id": null }"#))
+            .await
+            .unwrap();
+        assert_eq!(res_to_todo(res).await.assignee_id, None);
+
+        // personal todos can only be assigned to their owner
+        let req = build_todo_req_with_json(
+            "/todos/1/assign",
+            Method::PATCH,
+            r#"{ "assignee_id": 2 }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/1/assign",
+            Method::PATCH,
+            r#"{ "assignee_id": 1 }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos?assignee=1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(
+            todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_move_todo() {
+        let repos = TestRepos::new();
+        for text in ["a", "b", "c"] {
+            repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new(text.to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+        }
+        let req = build_todo_req_with_json(
+            "/todos/3/move",
+            Method::PATCH,
+            r#"{ "before": 1 }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=position");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(texts, vec!["c", "a", "b"]);
+
+        // exactly one target is required
+        for body in [r#"{}"#, r#"{ "index": 0, "after": 2 }"#] {
+            let req = build_todo_req_with_json("/todos/3/move", Method::PATCH, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_forbid_other_users_todo() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("should_forbid_other_users_todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        for method in [Method::GET, Method::DELETE] {
+            let req = build_req_with_token(method, "/todos/1", bearer_token_for(2));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::FORBIDDEN, res.status());
+        }
+
+        // the todo is not listed for the other user either
+        let req = build_req_with_token(Method::GET, "/todos", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert!(todos.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_find_labels_by_user() {
+        let repos = TestRepos::new();
+
+        // the label is owned by the authenticated user, not by the request body
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{
+                "name": "should_find_labels_by_user",
+                "user_id": 2
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/user/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(
+            vec![Label::new(1, "should_find_labels_by_user".to_string(), 1)],
+            labels
+        );
+
+        // other users' labels are forbidden
+        let req = build_todo_req_with_empty(Method::GET, "/labels/user/2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_duplicate_label_names() {
+        let repos = TestRepos::new();
+        for name in ["home", "work"] {
+            let body = format!(r#"{{ "name": "{}" }}"#, name);
+            let req = build_todo_req_with_json("/labels", Method::POST, body);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // names are unique per user regardless of case
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "Home" }"#.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let req = build_todo_req_with_json(
+            "/labels/2",
+            Method::PATCH,
+            r#"{ "name": "HOME" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        // renaming a label to its own name with another case is allowed
+        let req = build_todo_req_with_json(
+            "/labels/2",
+            Method::PATCH,
+            r#"{ "name": "Work" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // other users may use the same name
+        repos
+            .label
+            .create(CreateLabel::new("home".to_string(), 2))
+            .await
+            .expect("cannot create label");
+    }
+
+    #[tokio::test]
+    async fn should_search_and_paginate_labels() {
+        let repos = TestRepos::new();
+        for name in ["alpha", "beta", "Alphabet", "gamma", "50%"] {
+            repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+        }
+        let names = |labels: Vec<Label>| {
+            labels
+                .into_iter()
+                .map(|label| label.name)
+                .collect::<Vec<_>>()
+        };
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?q=ALPHA");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "2");
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["alpha", "Alphabet"]);
+        // % matches itself, not any string
+        let req = build_todo_req_with_empty(Method::GET, "/labels?q=%25");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["50%"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?sort=name&per_page=2&page=2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "5");
+        assert_eq!(
+            res.headers()[LINK],
+            concat!(
+                r#"</labels?sort=name&page=3&per_page=2>; rel="next", "#,
+                r#"</labels?sort=name&page=1&per_page=2>; rel="prev""#,
+            )
+        );
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["alpha", "beta"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?page=0");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_return_existing_label_on_conflict() {
+        let repos = TestRepos::new();
+        let upsert = |name: &str| {
+            build_todo_req_with_json(
+                "/labels?on_conflict=return_existing",
+                Method::POST,
+                format!(r#"{{ "name": "{}" }}"#, name),
+            )
+        };
+
+        let res = repos.app().oneshot(upsert("home")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let created: Label = res_to_json(res).await;
+        let res = repos.app().oneshot(upsert("Home")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let existing: Label = res_to_json(res).await;
+        assert_eq!(existing, created);
+        assert_eq!(
+            repos
+                .label
+                .all(Scope::personal(1), &LabelQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let req = build_todo_req_with_json(
+            "/labels?on_conflict=overwrite",
+            Method::POST,
+            r#"{ "name": "home" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_color_labels() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "default" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.color, "#4f86f7");
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r##"{ "name": "urgent", "color": "#E5484D" }"##.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.color, "#E5484D");
+
+        // only #rrggbb is accepted
+        for color in ["red", "#fff", "#12345g", "4f86f7a", "#ｆｆｆｆｆ"] {
+            for (path, method) in [("/labels", Method::POST), ("/labels/1", Method::PATCH)] {
+                let req = build_todo_req_with_json(
+                    path,
+                    method,
+                    format!(r#"{{ "name": "invalid", "color": "{}" }}"#, color),
+                );
+                let res = repos.app().oneshot(req).await.unwrap();
+                assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", color);
+            }
+        }
+
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r##"{ "color": "#30a46c" }"##.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(
+            (label.name.as_str(), label.color.as_str()),
+            ("default", "#30a46c")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_set_label_icons() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "home", "icon": "🏠" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.icon.as_deref(), Some("🏠"));
+
+        // single emoji, including ZWJ sequences, flags and skin tones, or an icon name
+        for icon in ["👩‍💻", "🇯🇵", "👍🏽", "❤️", "shopping-cart", "briefcase"]
+        {
+            let req = build_todo_req_with_json(
+                "/labels/1",
+                Method::PATCH,
+                format!(r#"{{ "icon": "{}" }}"#, icon),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status(), "{}", icon);
+            let label: Label = res_to_json(res).await;
+            assert_eq!(label.icon.as_deref(), Some(icon));
+        }
+        for icon in [
+            "",
+            "Briefcase",
+            "shopping cart",
+            "🏠home",
+            "<svg>",
+            "-x",
+            "🏠🏠🏠🏠🏠🏠🏠🏠🏠🏠🏠",
+        ] {
+            let req = build_todo_req_with_json(
+                "/labels/1",
+                Method::PATCH,
+                format!(r#"{{ "icon": "{}" }}"#, icon),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", icon);
+        }
+
+        // omitting icon keeps it, null removes it
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r#"{ "name": "house" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.icon.as_deref(), Some("briefcase"));
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r#"{ "icon": null }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.icon, None);
+    }
+
+    #[tokio::test]
+    async fn should_count_label_usage() {
+        let repos = TestRepos::new();
+        let mut labels = vec![];
+        for name in ["home", "work", "unused"] {
+            let label = repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+            labels.push(label);
+        }
+        for (status, label_ids) in [
+            (Status::Backlog, vec![0, 1]),
+            (Status::InProgress, vec![0]),
+            (Status::Done, vec![0, 1]),
+            (Status::Cancelled, vec![1]),
+        ] {
+            let todo = repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new("todo".to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+            let labels = label_ids.iter().map(|i| labels[*i].clone()).collect();
+            repos
+                .todo
+                .restore(
+                    Scope::personal(1),
+                    Todo {
+                        status,
+                        labels,
+                        ..todo
+                    },
+                )
+                .await
+                .expect("cannot restore todo");
+        }
+        // todos in other lists are not counted
+        let todo = repos
+            .todo
+            .create(
+                Scope::personal(2),
+                CreateTodo::new("other".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        repos
+            .todo
+            .restore(
+                Scope::personal(2),
+                Todo {
+                    labels: vec![labels[0].clone()],
+                    ..todo
+                },
+            )
+            .await
+            .expect("cannot restore todo");
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/stats?sort=name");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let stats: Vec<LabelStats> = res_to_json(res).await;
+        let counts: Vec<(&str, i64, i64)> = stats
+            .iter()
+            .map(|stats| (stats.label.name.as_str(), stats.open, stats.completed))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("home", 2, 1), ("unused", 0, 0), ("work", 1, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_list_todos_by_label() {
+        let repos = TestRepos::new();
+        let mut labels = vec![];
+        for name in ["home", "work"] {
+            let label = repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+            labels.push(label);
+        }
+        for (label, archived) in [(0, false), (0, false), (1, false), (0, false), (0, true)] {
+            let todo = repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new("todo".to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+            let labels = vec![labels[label].clone()];
+            let todo = repos
+                .todo
+                .restore(Scope::personal(1), Todo { labels, ..todo })
+                .await
+                .expect("cannot restore todo");
+            // archived todos are not listed
+            if archived {
+                repos
+                    .todo
+                    .archive(Scope::personal(1), todo.id)
+                    .await
+                    .expect("cannot archive todo");
+            }
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/1/todos?per_page=2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(res.headers()[TOTAL_COUNT_HEADER], "3");
+        assert_eq!(
+            res.headers()[LINK],
+            r#"</labels/1/todos?page=2&per_page=2>; rel="next""#
+        );
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![4, 2]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/1/todos?per_page=2&page=2");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(ids, vec![1]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/99/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reorder_labels() {
+        let repos = TestRepos::new();
+        for name in ["home", "work", "errands"] {
+            let body = format!(r#"{{ "name": "{}" }}"#, name);
+            let req = build_todo_req_with_json("/labels", Method::POST, body);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let names = |labels: Vec<Label>| {
+            labels
+                .into_iter()
+                .map(|label| label.name)
+                .collect::<Vec<_>>()
+        };
+
+        let req = build_todo_req_with_json(
+            "/labels/reorder",
+            Method::PATCH,
+            r#"{ "ids": [3, 1, 2] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["errands", "home", "work"]);
+
+        // GET /labels follows the custom order unless another sort is given
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["errands", "home", "work"]);
+        let req = build_todo_req_with_empty(Method::GET, "/labels?sort=created_at");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["home", "work", "errands"]);
+
+        // every label must be listed exactly once
+        for body in [
+            r#"{ "ids": [3, 1] }"#,
+            r#"{ "ids": [3, 1, 2, 2] }"#,
+            r#"{ "ids": [3, 1, 99] }"#,
+        ] {
+            let req = build_todo_req_with_json("/labels/reorder", Method::PATCH, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", body);
+        }
+
+        // new labels go to the end
+        let req = build_todo_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "later" }"#.to_string(),
+        );
+        repos.app().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["errands", "home", "work", "later"]);
+    }
+
+    #[tokio::test]
+    async fn should_archive_labels() {
+        let repos = TestRepos::new();
+        let mut labels = vec![];
+        for name in ["home", "work"] {
+            let label = repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+            labels.push(label);
+        }
+        let todo = repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        repos
+            .todo
+            .restore(
+                Scope::personal(1),
+                Todo {
+                    labels: labels.clone(),
+                    ..todo.clone()
+                },
+            )
+            .await
+            .expect("cannot restore todo");
+        let names = |labels: Vec<Label>| {
+            labels
+                .into_iter()
+                .map(|label| label.name)
+                .collect::<Vec<_>>()
+        };
+
+        let req = build_todo_req_with_empty(Method::POST, "/labels/1/archive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let archived: Label = res_to_json(res).await;
+        assert!(archived.archived_at.is_some());
+
+        // archived labels are hidden from the list and the picker
+        for uri in ["/labels", "/labels/user/1"] {
+            let req = build_todo_req_with_empty(Method::GET, uri);
+            let res = repos.app().oneshot(req).await.unwrap();
+            let labels: Vec<Label> = res_to_json(res).await;
+            assert_eq!(names(labels), vec!["work"], "{}", uri);
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/labels?archived=true");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(labels, vec![archived.clone()]);
+
+        // existing todos keep the label, but it can not be attached anew
+        let req = build_todo_req_with_empty(Method::GET, &format!("/todos/{}", todo.id));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let found: Todo = res_to_json(res).await;
+        assert_eq!(
+            found
+                .labels
+                .iter()
+                .map(|label| label.id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let body = format!(r#"{{ "todo_ids": [{}], "add": [1] }}"#, todo.id);
+        let req = build_todo_req_with_json("/todos/labels", Method::POST, body);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // archiving again keeps the original date
+        let req = build_todo_req_with_empty(Method::POST, "/labels/1/archive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let again: Label = res_to_json(res).await;
+        assert_eq!(again.archived_at, archived.archived_at);
+
+        let req = build_todo_req_with_empty(Method::POST, "/labels/1/unarchive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.archived_at, None);
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(names(labels), vec!["home", "work"]);
+
+        let req = build_todo_req_with_empty(Method::POST, "/labels/99/archive");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_nest_labels() {
+        let repos = TestRepos::new();
+        for body in [
+            r#"{ "name": "work" }"#,
+            r#"{ "name": "project-a", "parent_id": 1 }"#,
+            r#"{ "name": "home" }"#,
+        ] {
+            let req = build_todo_req_with_json("/labels", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // the parent must exist and must not be the label itself or one of its descendants
+        for (path, method, body) in [
+            (
+                "/labels",
+                Method::POST,
+                r#"{ "name": "orphan", "parent_id": 99 }"#,
+            ),
+            ("/labels/1", Method::PATCH, r#"{ "parent_id": 2 }"#),
+            ("/labels/1", Method::PATCH, r#"{ "parent_id": 1 }"#),
+        ] {
+            let req = build_todo_req_with_json(path, method, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            let expected = match body.contains("99") {
+                true => StatusCode::NOT_FOUND,
+                false => StatusCode::BAD_REQUEST,
+            };
+            assert_eq!(expected, res.status(), "{}", body);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels/tree?sort=name");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let tree: Vec<LabelNode> = res_to_json(res).await;
+        let names: Vec<(&str, Vec<&str>)> = tree
+            .iter()
+            .map(|node| {
+                let children = node.children.iter().map(|child| child.label.name.as_str());
+                (node.label.name.as_str(), children.collect())
+            })
+            .collect();
+        assert_eq!(names, vec![("home", vec![]), ("work", vec!["project-a"])]);
+
+        // filter by a label, optionally including its sublabels
+        let labels = repos
+            .label
+            .all(Scope::personal(1), &Default::default())
+            .await
+            .unwrap();
+        let label = |id: i32| labels.iter().find(|label| label.id == id).unwrap().clone();
+        for label_id in [1, 2] {
+            let todo = repos
+                .todo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new("todo".to_string(), vec![]),
+                )
+                .await
+                .expect("cannot create todo");
+            repos
+                .todo
+                .restore(
+                    Scope::personal(1),
+                    Todo {
+                        labels: vec![label(label_id)],
+                        ..todo
+                    },
+                )
+                .await
+                .expect("cannot restore todo");
+        }
+        for (query, expected) in [
+            ("label_id=1", vec![1]),
+            ("label_id=1&sublabels=true", vec![1, 2]),
+        ] {
+            let req = build_todo_req_with_empty(
+                Method::GET,
+                &format!("/todos?{}&sort=created_at&order=asc", query),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            let todos: Vec<Todo> = res_to_json(res).await;
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(ids, expected, "{}", query);
+        }
+
+        // moving a label back to the top level
+        let req = build_todo_req_with_json(
+            "/labels/2",
+            Method::PATCH,
+            r#"{ "parent_id": null }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.parent_id, None);
+    }
+
+    #[tokio::test]
+    async fn should_restrict_admin_routes_to_admins() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("todo of user 1".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        repos
+            .todo
+            .create(
+                Scope::personal(2),
+                CreateTodo::new("todo of user 2".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        // members are forbidden
+        let req = build_req_with_token(Method::GET, "/admin/todos", bearer_token_for(1));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // admins see every user's todos
+        let req = build_req_with_token(
+            Method::GET,
+            "/admin/todos",
+            bearer_token_with_role(99, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_only_delete_own_label_unless_admin() {
+        let repos = TestRepos::new();
+        repos
+            .label
+            .create(CreateLabel::new("label of user 2".to_string(), 2))
+            .await
+            .expect("cannot create label");
+
+        for path in ["/labels/1", "/admin/labels/1"] {
+            let req = build_req_with_token(Method::DELETE, path, bearer_token_for(1));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::FORBIDDEN, res.status());
+        }
+
+        let req = build_req_with_token(
+            Method::DELETE,
+            "/admin/labels/1",
+            bearer_token_with_role(99, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_keep_personal_labels_private() {
+        let repos = TestRepos::new();
+        repos
+            .label
+            .create(CreateLabel::new("private".to_string(), 2))
+            .await
+            .expect("cannot create label");
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("mine".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        // user 1 neither lists nor touches the personal label of user 2
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert!(labels.is_empty());
+        for (method, path) in [
+            (Method::GET, "/labels/1"),
+            (Method::GET, "/labels/1/todos"),
+            (Method::POST, "/labels/1/archive"),
+            (Method::POST, "/labels/1/unarchive"),
+        ] {
+            let req = build_todo_req_with_empty(method, path);
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::FORBIDDEN, res.status(), "{}", path);
+        }
+        let req = build_todo_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r#"{ "name": "renamed" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_todo_req_with_json(
+            "/todos/labels",
+            Method::POST,
+            r#"{ "todo_ids": [1], "add": [1] }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        assert_eq!(repos.label.find(1).await.unwrap().name, "private");
+
+        // positions are counted per owner
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "mine" }"#.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.position, 1);
+
+        // the owner still sees it
+        let req = build_req_with_token(Method::GET, "/labels", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        let labels: Vec<Label> = res_to_json(res).await;
+        assert_eq!(
+            labels.iter().map(|label| label.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_delete_labels_in_use_by_mode() {
+        let repos = TestRepos::new();
+        let mut labels = vec![];
+        for name in ["home", "work"] {
+            let label = repos
+                .label
+                .create(CreateLabel::new(name.to_string(), 1))
+                .await
+                .expect("cannot create label");
+            labels.push(label);
+        }
+        let todo = repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        repos
+            .todo
+            .restore(
+                Scope::personal(1),
+                Todo {
+                    labels,
+                    ..todo.clone()
+                },
+            )
+            .await
+            .expect("cannot restore todo");
+
+        // forbid keeps labels that are still attached to todos
+        let req = build_todo_req_with_empty(Method::DELETE, "/labels/1?mode=forbid");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert!(repos.label.find(1).await.is_ok());
+        let req = build_todo_req_with_empty(Method::DELETE, "/labels/1?mode=cascade");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // detach (the default) removes the label from todos first
+        let req = build_todo_req_with_empty(Method::DELETE, "/labels/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let todo = repos.todo.find(Scope::personal(1), todo.id).await.unwrap();
+        assert_eq!(
+            todo.labels.iter().map(|label| label.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        let req = build_req_with_token(
+            Method::DELETE,
+            "/admin/labels/2?mode=forbid",
+            bearer_token_with_role(99, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let req = build_req_with_token(
+            Method::DELETE,
+            "/admin/labels/2?mode=detach",
+            bearer_token_with_role(99, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert!(repos
+            .todo
+            .find(Scope::personal(1), todo.id)
+            .await
+            .unwrap()
+            .labels
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_return_not_found_for_unknown_oauth_provider() {
+        let req = build_req_without_token("/auth/unknown", Method::GET, String::new());
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_request_without_token() {
+        let req = build_req_without_token("/todos", Method::GET, String::new());
+        let res = TestRepos::new().app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_register_and_login() {
+        let repos = TestRepos::new();
+        let credentials = r#"{
+            "email": "user@example.com",
+            "password": "password"
+        }"#;
+
+        // register
+        let req = build_req_without_token("/auth/register", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // register with the same email
+        let req = build_req_without_token("/auth/register", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        // login with wrong password
+        let req = build_req_without_token(
+            "/auth/login",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "password": "wrong password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // login
+        let req = build_req_without_token("/auth/login", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body: AuthBody = res_to_json(res).await;
+
+        // the issued token is accepted by protected routes
+        let req = build_req_with_token(
+            Method::GET,
+            "/auth/me",
+            format!("Bearer {}", body.access_token),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_rotate_and_revoke_refresh_tokens() {
+        let repos = TestRepos::new();
+        let req = build_req_without_token(
+            "/auth/register",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "password": "password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let registered: AuthBody = res_to_json(res).await;
+
+        let refresh_req = |refresh_token: &str| {
+            build_req_without_token(
+                "/auth/refresh",
+                Method::POST,
+                format!(r#"{{"refresh_token": "{}"}}"#, refresh_token),
+            )
+        };
+
+        // refresh rotates the token
+        let res = repos
+            .app()
+            .oneshot(refresh_req(&registered.refresh_token))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let rotated: AuthBody = res_to_json(res).await;
+        assert_ne!(rotated.refresh_token, registered.refresh_token);
+
+        // the used token can not be replayed
+        let res = repos
+            .app()
+            .oneshot(refresh_req(&registered.refresh_token))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        // logout-all revokes every refresh token of the user
+        let req = build_req_with_token(
+            Method::POST,
+            "/auth/logout-all",
+            format!("Bearer {}", rotated.access_token),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = repos
+            .app()
+            .oneshot(refresh_req(&rotated.refresh_token))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_claim_guest_todos() {
+        let repos = TestRepos::new();
+        let req = build_req_without_token("/auth/guest", Method::POST, String::new());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let guest: GuestBody = res_to_json(res).await;
+        let guest_token = format!("Bearer {}", guest.auth.access_token);
+
+        // guests use the same routes as registered users
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::AUTHORIZATION, guest_token.clone())
+            .body(Body::from(r#"{ "text": "guest todo", "labels": [] }"#))
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+
+        let req = build_req_without_token(
+            "/auth/register",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "password": "password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let registered: AuthBody = res_to_json(res).await;
+        let claim_req = |token: String| {
+            Request::builder()
+                .uri("/auth/claim")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+                .header(header::AUTHORIZATION, token)
+                .body(Body::from(format!(
+                    r#"{{"device_token": "{}"}}"#,
+                    guest.device_token
+                )))
+                .unwrap()
+        };
+
+        // a guest can not claim into another guest
+        let res = repos.app().oneshot(claim_req(guest_token)).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let user_token = format!("Bearer {}", registered.access_token);
+        let res = repos
+            .app()
+            .oneshot(claim_req(user_token.clone()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].id, todo.id);
+
+        let req = build_req_with_token(Method::GET, "/todos", user_token.clone());
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(
+            todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![todo.id]
+        );
+
+        // the device token can only be claimed once
+        let res = repos.app().oneshot(claim_req(user_token)).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_export_and_delete_account() {
+        let repos = TestRepos::new();
+        let req = build_req_without_token(
+            "/auth/register",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "password": "password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let registered: AuthBody = res_to_json(res).await;
+        let token = format!("Bearer {}", registered.access_token);
+        let user_id = repos
+            .user
+            .find_by_email("user@example.com")
+            .await
+            .unwrap()
+            .id;
+        let todo = repos
+            .todo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo::new("my todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        repos
+            .todo
+            .create(
+                Scope::personal(2),
+                CreateTodo::new("other todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        // export contains only the user's own data
+        let req = build_req_with_token(Method::GET, "/me/export", token.clone());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().contains_key(header::CONTENT_DISPOSITION));
+        let export: serde_json::Value = res_to_json(res).await;
+        assert_eq!(export["user"]["email"], "user@example.com");
+        let todos: Vec<Todo> = serde_json::from_value(export["todos"].clone()).unwrap();
+        assert_eq!(todos, vec![todo]);
+
+        // delete removes the account
+        let req = build_req_with_token(Method::DELETE, "/me", token.clone());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        // the access token stops working with the account, not when it expires
+        let req = build_req_with_token(Method::GET, "/me/export", token);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_export_and_import_backup() {
+        let repos = TestRepos::new();
+        let label = repos
+            .label
+            .create(CreateLabel::new("home".to_string(), 1))
+            .await
+            .expect("cannot create label");
+        let parent = repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("parent".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+        // the in-memory create does not link labels, so attach it through restore
+        let parent = repos
+            .todo
+            .restore(
+                Scope::personal(1),
+                Todo {
+                    labels: vec![label],
+                    ..parent
+                },
+            )
+            .await
+            .expect("cannot restore todo");
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::from_subtask(parent.id, "child".to_string()),
+            )
+            .await
+            .expect("cannot create todo");
+
+        let req = build_todo_req_with_empty(Method::GET, "/export");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().contains_key(header::CONTENT_DISPOSITION));
+        let backup: Backup = res_to_json(res).await;
+        assert_eq!(backup.todos.len(), 2);
+        assert_eq!(backup.labels.len(), 1);
+        let body = serde_json::to_string(&backup).unwrap();
+        let import = |path: &str, user_id: i32, body: String| {
+            Request::builder()
+                .uri(path)
+                .method(Method::POST)
+                .header(header::AUTHORIZATION, bearer_token_for(user_id))
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        // another user gets copies linked to their own label
+        let res = repos
+            .app()
+            .oneshot(import("/import", 2, body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let summary: ImportSummary = res_to_json(res).await;
+        assert_eq!((summary.todos.created, summary.labels.created), (2, 1));
+        let req = build_req_with_token(
+            Method::GET,
+            "/todos?sort=created_at&order=asc",
+            bearer_token_for(2),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(todos[0].text, "parent");
+        assert_eq!(todos[0].labels[0].user_id, Some(2));
+        assert_eq!(todos[1].parent_id, Some(todos[0].id));
+
+        // the same user skips the existing todos unless a strategy is given
+        let res = repos
+            .app()
+            .oneshot(import("/import", 1, body.clone()))
+            .await
+            .unwrap();
+        let summary: ImportSummary = res_to_json(res).await;
+        assert_eq!((summary.todos.skipped, summary.labels.skipped), (2, 1));
+        let res = repos
+            .app()
+            .oneshot(import("/import?strategy=duplicate", 1, body.clone()))
+            .await
+            .unwrap();
+        let summary: ImportSummary = res_to_json(res).await;
+        assert_eq!(summary.todos.created, 2);
+
+        let res = repos
+            .app()
+            .oneshot(import("/import?strategy=merge", 1, body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let unsupported = Backup {
+            version: 0,
+            ..backup
+        };
+        let body = serde_json::to_string(&unsupported).unwrap();
+        let res = repos
+            .app()
+            .oneshot(import("/import", 1, body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_serve_calendar_feed() {
+        let repos = TestRepos::new();
+        repos
+            .user
+            .create(CreateUser {
+                email: "calendar@example.com".to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await
+            .expect("cannot create user");
+        for body in [
+            r#"{ "text": "dentist, 10am", "labels": [], "due_at": "2023-01-10T09:00:00Z" }"#,
+            r#"{ "text": "someday", "labels": [] }"#,
+            r#"{ "text": "conference", "labels": [], "starts_at": "2999-01-01T00:00:00Z",
+                "due_at": "2999-01-02T00:00:00Z" }"#,
+        ] {
+            let req = build_todo_req_with_json("/todos", Method::POST, body.to_string());
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/me/feeds");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let feed: FeedBody = res_to_json(res).await;
+        let subscribe = |path: &str| Request::builder().uri(path).body(Body::empty()).unwrap();
+
+        // the feed is fetched without an Authorization header
+        let res = repos
+            .app()
+            .oneshot(subscribe(&feed.calendar_url))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "text/calendar; charset=utf-8"
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let calendar = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.contains("SUMMARY:dentist\\, 10am\r\nDTSTART:20230110T090000Z\r\n"));
+        assert!(!calendar.contains("someday"));
+        // todos that have not started yet are still on the calendar
+        assert!(calendar.contains("SUMMARY:conference\r\n"));
+        let path = format!("{}&component=vtodo", feed.calendar_url);
+        let res = repos.app().oneshot(subscribe(&path)).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("BEGIN:VTODO\r\n"));
+
+        // access tokens and forged tokens cannot be used as feed tokens
+        let access_token = bearer_token().trim_start_matches("Bearer ").to_string();
+        for token in [access_token, format!("{}x", feed.token)] {
+            let path = format!("/todos/calendar.ics?token={}", token);
+            let res = repos.app().oneshot(subscribe(&path)).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+        let res = repos
+            .app()
+            .oneshot(subscribe("/todos/calendar.ics"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        // revoking stops every token issued so far, a new one works again
+        let req = build_todo_req_with_empty(Method::DELETE, "/me/feeds");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = repos
+            .app()
+            .oneshot(subscribe(&feed.calendar_url))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/me/feeds");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let feed: FeedBody = res_to_json(res).await;
+        let res = repos
+            .app()
+            .oneshot(subscribe(&feed.calendar_url))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // disabling the user stops the feed
+        repos.user.set_disabled(1, true).await.unwrap();
+        let res = repos
+            .app()
+            .oneshot(subscribe(&feed.calendar_url))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_serve_atom_feed() {
+        let repos = TestRepos::new();
+        repos
+            .user
+            .create(CreateUser {
+                email: "feed@example.com".to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await
+            .expect("cannot create user");
+        for text in ["first", "second"] {
+            let req = build_todo_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "status": "done" }"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/me/feeds");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let feed: FeedBody = res_to_json(res).await;
+        let subscribe = |accept: &str| {
+            Request::builder()
+                .uri(&feed.atom_url)
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // newest first: the completion, then the creations
+        let res = repos.app().oneshot(subscribe("*/*")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/atom+xml; charset=utf-8"
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let atom = String::from_utf8(bytes.to_vec()).unwrap();
+        let titles: Vec<&str> = atom
+            .match_indices("<title>")
+            .map(|(index, _)| &atom[index + 7..])
+            .map(|rest| &rest[..rest.find("</title>").unwrap()])
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Todos",
+                "Completed: first",
+                "Created: second",
+                "Created: first"
+            ]
+        );
+
+        // content negotiation picks RSS only when it is preferred
+        for (accept, expected) in [
+            (
+                "application/rss+xml, application/atom+xml;q=0.9",
+                Some("application/rss+xml; charset=utf-8"),
+            ),
+            ("application/*", Some("application/atom+xml; charset=utf-8")),
+            (
+                "application/atom+xml;q=0, */*;q=0.1",
+                Some("application/rss+xml; charset=utf-8"),
+            ),
+            ("text/html", None),
+        ] {
+            let res = repos.app().oneshot(subscribe(accept)).await.unwrap();
+            match expected {
+                Some(content_type) => {
+                    assert_eq!(StatusCode::OK, res.status());
+                    assert_eq!(res.headers()[header::CONTENT_TYPE], content_type);
+                }
+                None => assert_eq!(StatusCode::NOT_ACCEPTABLE, res.status()),
+            }
+        }
+
+        let req = Request::builder()
+            .uri("/todos/feed.atom?token=invalid")
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_lock_out_after_failed_logins() {
+        let repos = TestRepos::new();
+        let credentials = r#"{
+            "email": "user@example.com",
+            "password": "password"
+        }"#;
+        let req = build_req_without_token("/auth/register", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let wrong_login = || {
+            build_req_without_token(
+                "/auth/login",
+                Method::POST,
+                r#"{
+                    "email": "user@example.com",
+                    "password": "wrong password"
+                }"#
+                .to_string(),
+            )
+        };
+
+        // the default threshold is 5 failures; the 5th one locks the account
+        for _ in 0..4 {
+            let res = repos.app().oneshot(wrong_login()).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        }
+        let res = repos.app().oneshot(wrong_login()).await.unwrap();
+        assert_eq!(StatusCode::LOCKED, res.status());
+        assert!(res.headers().contains_key(header::RETRY_AFTER));
+        let body: serde_json::Value = res_to_json(res).await;
+        assert!(body["unlock_after"].is_string());
+
+        // even the right password is rejected while locked
+        let req = build_req_without_token("/auth/login", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::LOCKED, res.status());
+
+        // other accounts are not affected
+        let req = build_req_without_token(
+            "/auth/login",
+            Method::POST,
+            r#"{
+                "email": "other@example.com",
+                "password": "password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_manage_users_as_admin() {
+        let repos = TestRepos::new();
+        let credentials = r#"{
+            "email": "user@example.com",
+            "password": "password"
+        }"#;
+        let req = build_req_without_token("/auth/register", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        let registered: AuthBody = res_to_json(res).await;
+        let admin_token = || bearer_token_with_role(99, Role::Admin);
+        let admin_req =
+            |method: Method, path: &str| build_req_with_token(method, path, admin_token());
+
+        // members can not manage users
+        let req = build_req_with_token(Method::GET, "/admin/users", bearer_token_for(1));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        // the role is read from the user, not from a token issued before it changed
+        let req = build_req_with_token(
+            Method::GET,
+            "/admin/users",
+            bearer_token_with_role(1, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let res = repos
+            .app()
+            .oneshot(admin_req(Method::GET, "/admin/users"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let users: Vec<serde_json::Value> = res_to_json(res).await;
+        // the users the tests sign tokens for, and the one registered above
+        assert_eq!(users.len(), 5);
+        let user_id = users
+            .iter()
+            .find(|user| user["email"] == "user@example.com")
+            .and_then(|user| user["id"].as_i64())
+            .unwrap();
+
+        // admins can not act on themselves
+        let res = repos
+            .app()
+            .oneshot(admin_req(Method::POST, "/admin/users/99/disable"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        // disabled users can neither log in nor refresh
+        let res = repos
+            .app()
+            .oneshot(admin_req(
+                Method::POST,
+                &format!("/admin/users/{}/disable", user_id),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_req_without_token("/auth/login", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_req_without_token(
+            "/auth/refresh",
+            Method::POST,
+            format!(r#"{{"refresh_token": "{}"}}"#, registered.refresh_token),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        // nor keep using an access token issued before
+        let req = build_req_with_token(
+            Method::GET,
+            "/auth/me",
+            format!("Bearer {}", registered.access_token),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let res = repos
+            .app()
+            .oneshot(admin_req(
+                Method::POST,
+                &format!("/admin/users/{}/enable", user_id),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // a forced reset blocks login until the password is changed
+        let res = repos
+            .app()
+            .oneshot(admin_req(
+                Method::POST,
+                &format!("/admin/users/{}/password-reset", user_id),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_req_without_token("/auth/login", Method::POST, credentials.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_req_without_token(
+            "/auth/password",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "current_password": "password",
+                "new_password": "new password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_req_without_token(
+            "/auth/login",
+            Method::POST,
+            r#"{
+                "email": "user@example.com",
+                "password": "new password"
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // delete
+        let res = repos
+            .app()
+            .oneshot(admin_req(
+                Method::DELETE,
+                &format!("/admin/users/{}", user_id),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = repos
+            .app()
+            .oneshot(admin_req(
+                Method::DELETE,
+                &format!("/admin/users/{}", user_id),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_isolate_workspace_data() {
+        let repos = TestRepos::new();
+        let workspace = repos
+            .workspace
+            .create(1, CreateWorkspace::new("team".to_string()))
+            .await
+            .expect("cannot create workspace");
+        repos
+            .workspace
+            .add_member(workspace.id, 2)
+            .await
+            .expect("cannot add member");
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("personal todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        // create a todo in the workspace through the path
+        let req = build_todo_req_with_json(
+            "/workspaces/1/todos",
+            Method::POST,
+            r#"{
+                "text": "team todo",
+                "labels": []
+            }"#
+            .to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let created = res_to_todo(res).await;
+        assert_eq!(created.workspace_id, Some(workspace.id));
+
+        // another member sees it through the header, but not the personal todo
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::AUTHORIZATION, bearer_token_for(2))
+            .header(WORKSPACE_HEADER, "1")
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert_eq!(vec![created.clone()], todos);
+
+        // the personal list does not include workspace todos
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let todos: Vec<Todo> = res_to_json(res).await;
+        assert!(todos.iter().all(|todo| todo.workspace_id.is_none()));
+        assert_eq!(todos.len(), 1);
+
+        // non members are forbidden
+        for path in ["/workspaces/1", "/workspaces/1/todos"] {
+            let req = build_req_with_token(Method::GET, path, bearer_token_for(3));
+            let res = repos.app().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::FORBIDDEN, res.status());
+        }
+
+        // header and path must agree
+        let req = Request::builder()
+            .uri("/workspaces/1/todos")
+            .header(header::AUTHORIZATION, bearer_token())
+            .header(WORKSPACE_HEADER, "2")
+            .body(Body::empty())
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_manage_workspaces() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/workspaces",
+            Method::POST,
+            r#"{"name": "team"}"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let workspace: Workspace = res_to_json(res).await;
+        assert_eq!(workspace.owner_id, 1);
+
+        let req = build_todo_req_with_empty(Method::GET, "/workspaces");
+        let res = repos.app().oneshot(req).await.unwrap();
+        let workspaces: Vec<Workspace> = res_to_json(res).await;
+        assert_eq!(vec![workspace], workspaces);
+
+        // only the owner can delete it
+        repos
+            .workspace
+            .add_member(1, 2)
+            .await
+            .expect("cannot add member");
+        let req = build_req_with_token(Method::DELETE, "/workspaces/1", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_todo_req_with_empty(Method::DELETE, "/workspaces/1");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_invite_and_accept() {
+        let repos = TestRepos::new();
+        repos
+            .workspace
+            .create(1, CreateWorkspace::new("team".to_string()))
+            .await
+            .expect("cannot create workspace");
+        let invite = |token: String| {
+            Request::builder()
+                .uri("/workspaces/1/invitations")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+                .header(header::AUTHORIZATION, token)
+                .body(Body::from(r#"{"email": "user2@example.com"}"#))
+                .unwrap()
+        };
+
+        // only the owner can invite
+        repos
+            .workspace
+            .add_member(1, 3)
+            .await
+            .expect("cannot add member");
+        let res = repos
+            .app()
+            .oneshot(invite(bearer_token_for(3)))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let res = repos
+            .app()
+            .oneshot(invite(bearer_token_for(1)))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let body: InvitationBody = res_to_json(res).await;
+        let accept_path = format!("/invitations/{}/accept", body.token);
+
+        // the invitation is bound to the invited email
+        let req = build_req_with_token(Method::POST, &accept_path, bearer_token_for(3));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_req_with_token(Method::POST, &accept_path, bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let membership: Membership = res_to_json(res).await;
+        assert_eq!((membership.workspace_id, membership.user_id), (1, 2));
+
+        // the invitee is now a member, and the token can not be reused
+        let req = build_req_with_token(Method::GET, "/workspaces/1/todos", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_req_with_token(Method::POST, &accept_path, bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_share_todo() {
+        let repos = TestRepos::new();
+        repos
+            .todo
+            .create(
+                Scope::personal(1),
+                CreateTodo::new("shared todo".to_string(), vec![]),
+            )
+            .await
+            .expect("cannot create todo");
+
+        let req = build_todo_req_with_json(
+            "/todos/1/share",
+            Method::POST,
+            r#"{"user_id": 2, "permission": "read"}"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let share: TodoShare = res_to_json(res).await;
+        assert_eq!(share.permission, SharePermission::Read);
+
+        // the reader can see the todo but can not edit or delete it
+        let req = build_req_with_token(Method::GET, "/todos/1", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = Request::builder()
+            .uri("/todos/1")
+            .method(Method::PATCH)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::AUTHORIZATION, bearer_token_for(2))
+            .body(Body::from(r#"{"status": "done"}"#))
+            .unwrap();
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+        let req = build_req_with_token(Method::DELETE, "/todos/1", bearer_token_for(2));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        // sharing with yourself is rejected
+        let req = build_todo_req_with_json(
+            "/todos/1/share",
+            Method::POST,
+            r#"{"user_id": 1, "permission": "write"}"#.to_string(),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_requests() {
+        let app = TestRepos::new().app();
+        let mut limited = None;
+        for _ in 0..100 {
+            let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                limited = Some(res);
+                break;
+            }
+        }
+        let res = limited.expect("requests were never rate limited");
+        assert!(res.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn should_speak_msgpack() {
+        let repos = TestRepos::new();
+        let msgpack_req = |method: Method, path: &str, body: Option<serde_json::Value>| {
+            let mut req = build_todo_req_with_empty(method, path);
+            req.headers_mut()
+                .insert(header::ACCEPT, "application/msgpack".parse().unwrap());
+            if let Some(body) = body {
+                req.headers_mut()
+                    .insert(header::CONTENT_TYPE, "application/msgpack".parse().unwrap());
+                *req.body_mut() = Body::from(msgpack::encode(&body));
+            }
+            req
+        };
+        let msgpack_body = |res: Response| async move {
+            assert_eq!(res.headers()[header::CONTENT_TYPE], "application/msgpack");
+            assert!(res
+                .headers()
+                .get_all(header::VARY)
+                .iter()
+                .any(|v| v == "accept"));
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            msgpack::decode(&bytes).unwrap()
+        };
+
+        let body = serde_json::json!({ "name": "home" });
+        let req = msgpack_req(Method::POST, "/labels", Some(body));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label = msgpack_body(res).await;
+        assert_eq!(label["name"], "home");
+
+        let body = serde_json::json!({ "text": "msgpack", "labels": [] });
+        let req = msgpack_req(Method::POST, "/todos", Some(body));
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = msgpack_body(res).await;
+        assert_eq!(todo["text"], "msgpack");
+        assert_eq!(
+            todo["_links"]["self"]["href"],
+            format!("/v1/todos/{}", todo["id"])
+        );
+
+        let req = msgpack_req(Method::GET, "/todos", None);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(msgpack_body(res).await[0]["id"], todo["id"]);
+
+        // the same request body as JSON still works, and JSON stays the default
+        let req =
+            build_todo_req_with_json("/labels", Method::POST, r#"{ "name": "work" }"#.to_string());
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label: Label = res_to_json(res).await;
+        assert_eq!(label.name, "work");
+
+        // validation and parse errors are the same 400 as for JSON
+        let req = msgpack_req(
+            Method::POST,
+            "/todos",
+            Some(serde_json::json!({ "text": "" })),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let mut req = msgpack_req(Method::POST, "/todos", Some(serde_json::json!({})));
+        *req.body_mut() = Body::from(vec![0xc1]);
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_record_audit_events() {
+        let repos = TestRepos::new();
+        let req = build_todo_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{"text": "audited todo", "labels": []}"#.to_string(),
+        );
+        repos.app().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"text": "audited todo updated"}"#.to_string(),
+        );
+        repos.app().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        repos.app().oneshot(req).await.unwrap();
+
+        // members can not read the audit log
+        let req = build_todo_req_with_empty(Method::GET, "/audit");
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        let req = build_req_with_token(
+            Method::GET,
+            "/audit",
+            bearer_token_with_role(99, Role::Admin),
+        );
+        let res = repos.app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let events: Vec<AuditEvent> = res_to_json(res).await;
+        let actions: Vec<AuditAction> = events.iter().map(|event| event.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                AuditAction::Delete,
+                AuditAction::Update,
+                AuditAction::Create
+            ]
+        );
+        assert!(events.iter().all(|event| event.actor_id == Some(1)));
+        let update = &events[1];
+        assert_eq!(update.before.as_ref().unwrap()["text"], "audited todo");
+        assert_eq!(
+            update.after.as_ref().unwrap()["text"],
+            "audited todo updated"
+        );
     }
 }
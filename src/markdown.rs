@@ -0,0 +1,74 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+// リンクや画像に使ってよいスキーム. スキームのない相対 URL も許可する
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+// markdown を HTML に変換する. ユーザーが書いた markdown には生の HTML も書けるので、
+// 生の HTML はタグとして出力せずにエスケープし、javascript: などのリンクは空にする
+pub fn render_html(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(tag) => Event::Start(sanitize_tag(tag)),
+        Event::End(tag) => Event::End(sanitize_tag(tag)),
+        event => event,
+    });
+    let mut html = String::new();
+    html::push_html(&mut html, events);
+    html
+}
+
+fn sanitize_tag(tag: Tag) -> Tag {
+    match tag {
+        Tag::Link(link_type, url, title) => Tag::Link(link_type, sanitize_url(url), title),
+        Tag::Image(link_type, url, title) => Tag::Image(link_type, sanitize_url(url), title),
+        tag => tag,
+    }
+}
+
+fn sanitize_url(url: CowStr) -> CowStr {
+    // ブラウザは空白や制御文字を無視してスキームを解釈するので、取り除いてから判定する
+    let normalized: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    let scheme = normalized
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme {
+        Some(scheme) if !SAFE_SCHEMES.contains(&scheme) => CowStr::Borrowed(""),
+        _ => url,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_render_markdown() {
+        assert_eq!(
+            render_html("# Title\n\n**bold** and ~~done~~"),
+            "<h1>Title</h1>\n<p><strong>bold</strong> and <del>done</del></p>\n"
+        );
+        assert_eq!(
+            render_html("[docs](https://example.com/a?b=c#d) [memo](./memo.md)"),
+            "<p><a href=\"https://example.com/a?b=c#d\">docs</a> <a href=\"./memo.md\">memo</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn should_sanitize_html() {
+        let html = render_html(
+            "<script>alert(1)</script>\n\n[link](javascript:alert(1)) [tab](java\tscript:alert(1)) <img src=x onerror=alert(1)>",
+        );
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("<a href=\"\">link</a>"));
+    }
+}
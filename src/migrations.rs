@@ -0,0 +1,73 @@
+use sqlx::{migrate::Migrator, PgPool};
+
+// migrations/ をビルド時に埋め込む. sqlx migrate run と同じ _sqlx_migrations に記録するので、どちらで流してもよい
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+// run なら未適用の migration を流す. そうでなければ未適用のものがないかだけ確かめる
+// 未適用のまま起動すると、リクエストのたびに "relation does not exist" になるので起動を止める
+pub async fn prepare_database(pool: &PgPool, run: bool) -> anyhow::Result<()> {
+    if run {
+        MIGRATOR
+            .run(pool)
+            .await
+            .map_err(|e| anyhow::anyhow!("cannot run database migrations: {}", e))?;
+        return Ok(());
+    }
+
+    let table_exists: bool =
+        sqlx::query_scalar("select to_regclass('_sqlx_migrations') is not null")
+            .fetch_one(pool)
+            .await?;
+    let applied: Vec<i64> = if table_exists {
+        sqlx::query_scalar("select version from _sqlx_migrations where success")
+            .fetch_all(pool)
+            .await?
+    } else {
+        Vec::new()
+    };
+    let embedded: Vec<(i64, &str)> = MIGRATOR
+        .iter()
+        .map(|migration| (migration.version, migration.description.as_ref()))
+        .collect();
+    let pending = pending_migrations(&embedded, &applied);
+    if pending.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "the database is missing {} migration(s): {}. run `sqlx migrate run` or start with RUN_MIGRATIONS=true",
+        pending.len(),
+        pending.join(", ")
+    ))
+}
+
+fn pending_migrations(embedded: &[(i64, &str)], applied: &[i64]) -> Vec<String> {
+    embedded
+        .iter()
+        .filter(|(version, _)| !applied.contains(version))
+        .map(|(version, description)| format!("{}_{}", version, description))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_list_pending_migrations() {
+        let embedded = [(1, "init"), (2, "label"), (3, "user")];
+        assert_eq!(
+            pending_migrations(&embedded, &[1, 2, 3]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            pending_migrations(&embedded, &[1]),
+            vec!["2_label", "3_user"]
+        );
+        assert_eq!(pending_migrations(&embedded, &[]).len(), 3);
+        // every file in migrations/ is embedded
+        assert_eq!(
+            MIGRATOR.iter().count(),
+            std::fs::read_dir("migrations").unwrap().count()
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use serde_json::{Map, Number, Value};
+
+// MessagePack の符号化と復号. ビルド環境のレジストリに rmp-serde がないので、serde_json::Value との変換だけを持つ
+// 応答は serde_json::to_value で作った Value から直接符号化するので、JSON の文字列は経由しない
+pub const CONTENT_TYPE_MSGPACK: &str = "application/msgpack";
+// serde_json と同じ入れ子の上限. 深すぎる入力でスタックを使い切らないようにする
+const MAX_DEPTH: usize = 128;
+
+// JSON の値を MessagePack にする. 数値は入る一番小さい形式を使う
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_value(&mut buf, value);
+    buf
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buf.push(0xc0),
+        Value::Bool(false) => buf.push(0xc2),
+        Value::Bool(true) => buf.push(0xc3),
+        Value::Number(number) => write_number(buf, number),
+        Value::String(text) => {
+            write_len(buf, text.len(), 0xa0, 32, [0xd9, 0xda, 0xdb]);
+            buf.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            write_len(buf, items.len(), 0x90, 16, [0, 0xdc, 0xdd]);
+            for item in items {
+                write_value(buf, item);
+            }
+        }
+        Value::Object(object) => {
+            write_len(buf, object.len(), 0x80, 16, [0, 0xde, 0xdf]);
+            for (key, value) in object {
+                write_value(buf, &Value::String(key.clone()));
+                write_value(buf, value);
+            }
+        }
+    }
+}
+
+fn write_number(buf: &mut Vec<u8>, number: &Number) {
+    if let Some(n) = number.as_u64() {
+        match n {
+            0..=0x7f => buf.push(n as u8),
+            0x80..=0xff => buf.extend_from_slice(&[0xcc, n as u8]),
+            0x100..=0xffff => {
+                buf.push(0xcd);
+                buf.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                buf.push(0xce);
+                buf.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            _ => {
+                buf.push(0xcf);
+                buf.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+    } else if let Some(n) = number.as_i64() {
+        // as_u64 が None なので負の数
+        if n >= -32 {
+            buf.push(n as i8 as u8);
+        } else if n >= i8::MIN as i64 {
+            buf.extend_from_slice(&[0xd0, n as i8 as u8]);
+        } else if n >= i16::MIN as i64 {
+            buf.push(0xd1);
+            buf.extend_from_slice(&(n as i16).to_be_bytes());
+        } else if n >= i32::MIN as i64 {
+            buf.push(0xd2);
+            buf.extend_from_slice(&(n as i32).to_be_bytes());
+        } else {
+            buf.push(0xd3);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    } else {
+        buf.push(0xcb);
+        let n = number.as_f64().unwrap_or_default();
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+// fix 形式に収まらない長さは 8 / 16 / 32 bit の形式. 0 の marker は使わない (配列とマップに 8 bit の形式はない)
+fn write_len(buf: &mut Vec<u8>, len: usize, fix: u8, fix_limit: usize, markers: [u8; 3]) {
+    if len < fix_limit {
+        buf.push(fix | len as u8);
+    } else if len <= 0xff && markers[0] != 0 {
+        buf.extend_from_slice(&[markers[0], len as u8]);
+    } else if len <= 0xffff {
+        buf.push(markers[1]);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(markers[2]);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+// MessagePack を JSON の値にする. JSON にない bin と ext、文字列以外のキーはエラー
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let value = reader.read_value(0)?;
+    if reader.pos != bytes.len() {
+        return Err(format!("trailing bytes at {}", reader.pos));
+    }
+    Ok(value)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| "unexpected end of input".to_string())?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn read_len(&mut self, width: usize) -> Result<usize, String> {
+        Ok(match width {
+            1 => self.take_array::<1>()?[0] as usize,
+            2 => u16::from_be_bytes(self.take_array()?) as usize,
+            _ => u32::from_be_bytes(self.take_array()?) as usize,
+        })
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("nested too deeply".to_string());
+        }
+        let marker = self.take_array::<1>()?[0];
+        let value = match marker {
+            0x00..=0x7f => Value::from(marker),
+            0x80..=0x8f => self.read_map((marker & 0x0f) as usize, depth)?,
+            0x90..=0x9f => self.read_array((marker & 0x0f) as usize, depth)?,
+            0xa0..=0xbf => self.read_str((marker & 0x1f) as usize)?,
+            0xc0 => Value::Null,
+            0xc2 => Value::Bool(false),
+            0xc3 => Value::Bool(true),
+            0xca => float(f32::from_be_bytes(self.take_array()?) as f64)?,
+            0xcb => float(f64::from_be_bytes(self.take_array()?))?,
+            0xcc => Value::from(self.take_array::<1>()?[0]),
+            0xcd => Value::from(u16::from_be_bytes(self.take_array()?)),
+            0xce => Value::from(u32::from_be_bytes(self.take_array()?)),
+            0xcf => Value::from(u64::from_be_bytes(self.take_array()?)),
+            0xd0 => Value::from(i8::from_be_bytes(self.take_array()?)),
+            0xd1 => Value::from(i16::from_be_bytes(self.take_array()?)),
+            0xd2 => Value::from(i32::from_be_bytes(self.take_array()?)),
+            0xd3 => Value::from(i64::from_be_bytes(self.take_array()?)),
+            0xd9 => {
+                let len = self.read_len(1)?;
+                self.read_str(len)?
+            }
+            0xda => {
+                let len = self.read_len(2)?;
+                self.read_str(len)?
+            }
+            0xdb => {
+                let len = self.read_len(4)?;
+                self.read_str(len)?
+            }
+            0xdc => {
+                let len = self.read_len(2)?;
+                self.read_array(len, depth)?
+            }
+            0xdd => {
+                let len = self.read_len(4)?;
+                self.read_array(len, depth)?
+            }
+            0xde => {
+                let len = self.read_len(2)?;
+                self.read_map(len, depth)?
+            }
+            0xdf => {
+                let len = self.read_len(4)?;
+                self.read_map(len, depth)?
+            }
+            0xe0..=0xff => Value::from(marker as i8),
+            0xc4..=0xc6 => return Err("bin is not supported".to_string()),
+            0xc7..=0xc9 | 0xd4..=0xd8 => return Err("ext is not supported".to_string()),
+            0xc1 => return Err("invalid marker 0xc1".to_string()),
+        };
+        Ok(value)
+    }
+
+    fn read_str(&mut self, len: usize) -> Result<Value, String> {
+        let bytes = self.take(len)?;
+        let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+        Ok(Value::String(text.to_string()))
+    }
+
+    // 長さは入力から読むので、残りのバイト数より大きな領域は先に確保しない
+    fn read_array(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        let mut items = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+        for _ in 0..len {
+            items.push(self.read_value(depth + 1)?);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn read_map(&mut self, len: usize, depth: usize) -> Result<Value, String> {
+        let mut object = Map::new();
+        for _ in 0..len {
+            let Value::String(key) = self.read_value(depth + 1)? else {
+                return Err("map keys must be strings".to_string());
+            };
+            let value = self.read_value(depth + 1)?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+fn float(n: f64) -> Result<Value, String> {
+    Number::from_f64(n)
+        .map(Value::Number)
+        .ok_or_else(|| "NaN and infinity are not supported".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_encode_in_the_smallest_format() {
+        assert_eq!(encode(&json!(null)), [0xc0]);
+        assert_eq!(encode(&json!(true)), [0xc3]);
+        assert_eq!(encode(&json!(5)), [0x05]);
+        assert_eq!(encode(&json!(200)), [0xcc, 200]);
+        assert_eq!(encode(&json!(-1)), [0xff]);
+        assert_eq!(encode(&json!(-200)), [0xd1, 0xff, 0x38]);
+        assert_eq!(encode(&json!(70000)), [0xce, 0x00, 0x01, 0x11, 0x70]);
+        assert_eq!(encode(&json!(1.5)), [0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode(&json!("abc")), [0xa3, b'a', b'b', b'c']);
+        assert_eq!(encode(&json!([1, 2])), [0x92, 0x01, 0x02]);
+        assert_eq!(encode(&json!({ "id": 1 })), [0x81, 0xa2, b'i', b'd', 0x01]);
+        assert_eq!(encode(&json!("a".repeat(40)))[..2], [0xd9, 40]);
+        assert_eq!(encode(&json!(vec![0; 20]))[..3], [0xdc, 0, 20]);
+    }
+
+    #[test]
+    fn should_round_trip() {
+        let value = json!({
+            "id": 1,
+            "text": "todo ✓",
+            "description": null,
+            "labels": [{ "id": 2, "name": "a".repeat(300) }],
+            "position": -40000,
+            "is_pinned": false,
+            "score": 0.25,
+            "big": u64::MAX,
+            "small": i64::MIN,
+        });
+        assert_eq!(decode(&encode(&value)), Ok(value));
+    }
+
+    #[test]
+    fn should_reject_what_json_cannot_hold() {
+        assert!(decode(&[0xc4, 0x01, 0x00]).is_err());
+        assert!(decode(&[0xd4, 0x01, 0x00]).is_err());
+        assert!(decode(&[0x81, 0x01, 0x01]).is_err());
+        assert!(decode(&[0xcb, 0x7f, 0xf8, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(decode(&[0xa3, b'a']).is_err());
+        assert!(decode(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode(&[0x01, 0x02]).is_err());
+        assert!(decode(&[0x91; 200]).is_err());
+    }
+}
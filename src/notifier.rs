@@ -0,0 +1,116 @@
+use crate::mailer::Mailer;
+use crate::repositories::reminder::{Channel, DueReminder};
+use axum::async_trait;
+use serde_json::json;
+
+// 期限の来た通知を送る. 送信手段ごとに実装を差し替えられるようにしておく
+#[async_trait]
+pub trait Notifier: std::marker::Send + std::marker::Sync + 'static {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+        tracing::info!(
+            "reminder {} for todo {}: {}",
+            reminder.id,
+            reminder.todo_id,
+            reminder.text
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailNotifier {
+    mailer: Mailer,
+}
+
+impl EmailNotifier {
+    pub fn new(mailer: Mailer) -> Self {
+        Self { mailer }
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+        let body = format!(
+            "{}\n\n{}",
+            reminder.text,
+            self.mailer.url(&format!("/todos/{}", reminder.todo_id))
+        );
+        self.mailer.send(&reminder.email, "Reminder", &body).await
+    }
+}
+
+// 通知に設定された url に JSON を POST する. 受け取る側にはメールアドレスを渡さない
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new() -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+        let url = reminder
+            .url
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("reminder {} has no url", reminder.id))?;
+        self.client
+            .post(url)
+            .json(&json!({
+                "reminder_id": reminder.id,
+                "todo_id": reminder.todo_id,
+                "text": reminder.text,
+                "remind_at": reminder.remind_at,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// 通知ごとの channel で送信手段を振り分ける
+#[derive(Debug, Clone)]
+pub struct ChannelNotifier {
+    log: LogNotifier,
+    email: EmailNotifier,
+    webhook: WebhookNotifier,
+}
+
+impl ChannelNotifier {
+    pub fn new(mailer: Mailer) -> anyhow::Result<Self> {
+        Ok(Self {
+            log: LogNotifier,
+            email: EmailNotifier::new(mailer),
+            webhook: WebhookNotifier::new()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for ChannelNotifier {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+        match reminder.channel {
+            Channel::Log => self.log.notify(reminder).await,
+            Channel::Email => self.email.notify(reminder).await,
+            Channel::Webhook => self.webhook.notify(reminder).await,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::auth::JwtKeys;
+use crate::config::RateLimitConfig;
+use crate::forwarded::ClientInfo;
+use axum::{
+    http::{
+        header::{AUTHORIZATION, RETRY_AFTER},
+        Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// 覚えておくクライアント数の既定の上限
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// 認証済みならユーザー単位、そうでなければ接続元 IP 単位で数える
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    User(i32),
+    Ip(Option<IpAddr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+// トークンバケット. burst 回まで連続で受け付け、window ごとに max_requests 回分回復する
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    burst: f64,
+    // 1 秒あたりに回復するトークン数
+    refill_per_sec: f64,
+    max_clients: usize,
+    buckets: Arc<Mutex<HashMap<ClientKey, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration, burst: u32) -> Self {
+        Self {
+            burst: f64::from(burst.max(1)),
+            refill_per_sec: f64::from(max_requests.max(1)) / window.as_secs_f64().max(1.0),
+            max_clients: MAX_TRACKED_CLIENTS,
+            buckets: Arc::default(),
+        }
+    }
+
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients.max(1);
+        self
+    }
+
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        Self::new(
+            config.max_requests,
+            Duration::from_secs(config.window_secs),
+            config.burst,
+        )
+        .with_max_clients(config.max_clients)
+    }
+
+    // 受け付けられるなら Ok、超過していれば次に受け付けられるまでの秒数を返す
+    pub fn check(&self, key: ClientKey, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        // 上限に達したら最も長く来ていないクライアントを忘れる. 満タンのバケツの掃除は spawn_sweep に任せる
+        if buckets.len() >= self.max_clients && !buckets.contains_key(&key) {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, bucket)| bucket.updated_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refill(*bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.refill_per_sec).ceil() as u64)
+        }
+    }
+
+    // 満タンに戻ったバケツは新しく作るのと同じなので捨てる
+    pub fn sweep(&self, now: Instant) {
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| self.refill(*bucket, now) < self.burst);
+    }
+
+    // リクエストを待たせないように、掃除は別のタスクで一定間隔ごとに行う
+    pub fn spawn_sweep(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                this.sweep(Instant::now());
+            }
+        })
+    }
+
+    fn refill(&self, bucket: Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.burst)
+    }
+}
+
+fn client_key<B>(req: &Request<B>) -> ClientKey {
+    // 署名の検証まで通ったトークンだけをユーザーとして扱う. 偽のトークンで他人の枠を使わせない
+    let user_id = req
+        .extensions()
+        .get::<JwtKeys>()
+        .zip(
+            req.headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer ")),
+        )
+        .and_then(|(keys, token)| keys.verify(token).ok())
+        .map(|claims| claims.sub);
+
+    match user_id {
+        Some(user_id) => ClientKey::User(user_id),
+        None => ClientKey::Ip(
+            req.extensions()
+                .get::<ClientInfo>()
+                .and_then(|client| client.ip),
+        ),
+    }
+}
+
+// RateLimiter と JwtKeys は create_app で Extension として登録されている前提
+pub async fn rate_limit<B>(req: Request<B>, next: Next<B>) -> Response {
+    let limiter = match req.extensions().get::<RateLimiter>() {
+        Some(limiter) => limiter.clone(),
+        None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let key = client_key(&req);
+
+    match limiter.check(key, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            tracing::warn!("rate limit exceeded: {:?}", key);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::user::Role;
+    use axum::body::Body;
+
+    #[test]
+    fn should_limit_after_burst() {
+        // 10 秒ごとに 5 回 = 2 秒に 1 回回復
+        let limiter = RateLimiter::new(5, Duration::from_secs(10), 2);
+        let key = ClientKey::User(1);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(key, now), Ok(()));
+        assert_eq!(limiter.check(key, now), Ok(()));
+        assert_eq!(limiter.check(key, now), Err(2));
+        // 他のクライアントには影響しない
+        assert_eq!(limiter.check(ClientKey::User(2), now), Ok(()));
+
+        // 回復すれば再び受け付ける
+        let later = now + Duration::from_secs(2);
+        assert_eq!(limiter.check(key, later), Ok(()));
+        assert_eq!(limiter.check(key, later), Err(2));
+    }
+
+    #[test]
+    fn should_forget_the_oldest_client_at_the_cap() {
+        let limiter = RateLimiter::new(5, Duration::from_secs(10), 1).with_max_clients(2);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(ClientKey::User(1), now), Ok(()));
+        assert_eq!(
+            limiter.check(ClientKey::User(2), now + Duration::from_millis(1)),
+            Ok(())
+        );
+        // a third client replaces the one seen least recently, so the map never grows past the cap
+        let later = now + Duration::from_millis(2);
+        assert_eq!(limiter.check(ClientKey::User(3), later), Ok(()));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+        assert_eq!(limiter.check(ClientKey::User(2), later), Err(2));
+        assert_eq!(limiter.check(ClientKey::User(1), later), Ok(()));
+    }
+
+    #[test]
+    fn should_sweep_full_buckets() {
+        let limiter = RateLimiter::new(5, Duration::from_secs(10), 2);
+        let now = Instant::now();
+        assert_eq!(limiter.check(ClientKey::User(1), now), Ok(()));
+        assert_eq!(
+            limiter.check(ClientKey::User(2), now + Duration::from_secs(1)),
+            Ok(())
+        );
+
+        // user 1 has refilled by now, user 2 has not
+        limiter.sweep(now + Duration::from_secs(2));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(
+            buckets.keys().copied().collect::<Vec<_>>(),
+            vec![ClientKey::User(2)]
+        );
+    }
+
+    #[test]
+    fn should_key_by_verified_user() {
+        let keys = JwtKeys::new(b"secret");
+        let token = keys.issue(7, Role::Member).unwrap();
+        let build = |token: &str| {
+            let mut req = Request::builder()
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut().insert(keys.clone());
+            req.extensions_mut().insert(ClientInfo {
+                ip: Some(IpAddr::from([10, 0, 0, 1])),
+                scheme: "http".to_string(),
+            });
+            req
+        };
+
+        assert_eq!(client_key(&build(&token)), ClientKey::User(7));
+        assert_eq!(
+            client_key(&build("invalid")),
+            ClientKey::Ip(Some(IpAddr::from([10, 0, 0, 1])))
+        );
+    }
+}
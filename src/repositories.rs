@@ -1,14 +1,176 @@
+pub mod attachment;
+pub mod audit;
+pub mod backup;
+pub mod cache;
+pub mod idempotency;
+pub mod invitation;
 pub mod label;
+pub mod login_attempt;
+pub mod outbox;
+pub mod project;
+pub mod refresh_token;
+pub mod reminder;
+pub mod template;
 pub mod todo;
+pub mod user;
+pub mod webhook;
+pub mod workspace;
 
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::future::Future;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
     Unexpected(String),
     #[error("NotFound, id is {0}")]
     NotFound(i32),
     #[error("Duplicated Error: [{0}]")]
     Duplicate(i32),
+    #[error("Forbidden, id is {0}")]
+    Forbidden(i32),
+    #[error("Invalid: [{0}]")]
+    Invalid(String),
+    #[error("InUse, id is {0}")]
+    InUse(i32),
+}
+
+// todo / label を誰の範囲で扱うか
+// workspace_id が None なら user_id の個人データ、Some ならその workspace のデータを対象にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scope {
+    pub user_id: i32,
+    pub workspace_id: Option<i32>,
+}
+
+impl Scope {
+    pub fn new(user_id: i32, workspace_id: Option<i32>) -> Self {
+        Self {
+            user_id,
+            workspace_id,
+        }
+    }
+
+    pub fn personal(user_id: i32) -> Self {
+        Self::new(user_id, None)
+    }
+}
+
+// service が始めて、1 つの変更に関わる書き込みと監査ログをまとめて commit するトランザクション
+// commit せずに捨てれば DB のトランザクションは rollback される
+// メモリの repository は書き込みをその場で反映するので、DB のトランザクションを持たない
+pub struct Tx {
+    db: Option<Transaction<'static, Postgres>>,
+    // commit した後に行うこと. commit より前にキャッシュを捨てると、commit 前の値がまたキャッシュされる
+    after_commit: Vec<BoxFuture<'static, ()>>,
+}
+
+impl Tx {
+    pub async fn begin(pool: &PgPool) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: Some(pool.begin().await?),
+            after_commit: vec![],
+        })
+    }
+
+    #[cfg(any(test, feature = "memory"))]
+    pub fn memory() -> Self {
+        Self {
+            db: None,
+            after_commit: vec![],
+        }
+    }
+
+    // DB の repository が書き込みと、書き込んだ後の読み直しに使う
+    pub fn conn(&mut self) -> anyhow::Result<&mut PgConnection> {
+        match &mut self.db {
+            Some(tx) => Ok(tx),
+            None => {
+                Err(RepositoryError::Unexpected("not in a database transaction".to_string()).into())
+            }
+        }
+    }
+
+    pub fn after_commit(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.after_commit.push(Box::pin(task));
+    }
+
+    pub async fn commit(self) -> anyhow::Result<()> {
+        if let Some(tx) = self.db {
+            tx.commit().await?;
+        }
+        for task in self.after_commit {
+            task.await;
+        }
+        Ok(())
+    }
+}
+
+// 一覧の並び順の向き
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+// LIKE のパターンで特別な意味を持つ文字をエスケープする
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// RFC 6902 の JSON Patch の操作. move / copy / test は受け付けない
+// path は RFC 6901 の JSON Pointer. どの path を変更できるかはリソースごとに決める
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add {
+        path: String,
+        value: serde_json::Value,
+    },
+    Remove {
+        path: String,
+    },
+    Replace {
+        path: String,
+        value: serde_json::Value,
+    },
+}
+
+impl PatchOperation {
+    // path を参照トークンに分ける. ~1 は /、~0 は ~ に戻す
+    pub fn tokens(&self) -> Result<Vec<String>, RepositoryError> {
+        let path = match self {
+            PatchOperation::Add { path, .. }
+            | PatchOperation::Remove { path }
+            | PatchOperation::Replace { path, .. } => path,
+        };
+        let invalid = || RepositoryError::Invalid(format!("invalid path: {}", path));
+        let rest = path.strip_prefix('/').ok_or_else(invalid)?;
+        Ok(rest
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect())
+    }
+}
+
+// 一覧のうち offset 件目から limit 件を取り出す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
 }
@@ -0,0 +1,321 @@
+use super::RepositoryError;
+use crate::auth::generate_token;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+
+// メタデータだけを扱う. 中身の保存と削除は handler から BlobStore に対して行う
+#[async_trait]
+pub trait AttachmentRepository: std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, payload: NewAttachment) -> anyhow::Result<Attachment>;
+    // 古い順
+    async fn all_by_todo(&self, todo_id: i32) -> anyhow::Result<Vec<Attachment>>;
+    // 別の todo の添付ファイルは RepositoryError::NotFound
+    async fn find(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment>;
+    // 削除したメタデータを返す. 中身の削除に storage_key を使う
+    async fn delete(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct Attachment {
+    pub id: i32,
+    pub todo_id: i32,
+    pub user_id: Option<i32>,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewAttachment {
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub storage_key: String,
+}
+
+// 中身を保存する key. todo ごとにまとめ、推測されないようランダムな値を付ける
+pub fn storage_key(todo_id: i32) -> String {
+    format!("todos/{}/{}", todo_id, generate_token())
+}
+
+#[derive(Debug, Clone)]
+pub struct AttachmentRepositoryForDb {
+    pool: PgPool,
+}
+
+impl AttachmentRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AttachmentRepository for AttachmentRepositoryForDb {
+    #[tracing::instrument(name = "AttachmentRepository::create", skip_all)]
+    async fn create(&self, payload: NewAttachment) -> anyhow::Result<Attachment> {
+        let attachment = sqlx::query_as::<_, Attachment>(
+            r#"
+            INSERT INTO attachments (todo_id, user_id, file_name, content_type, size, storage_key)
+            VALUES ( $1, $2, $3, $4, $5, $6 )
+            RETURNING *
+            "#,
+        )
+        .bind(payload.todo_id)
+        .bind(payload.user_id)
+        .bind(payload.file_name)
+        .bind(payload.content_type)
+        .bind(payload.size)
+        .bind(payload.storage_key)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(attachment)
+    }
+
+    #[tracing::instrument(name = "AttachmentRepository::all_by_todo", skip_all)]
+    async fn all_by_todo(&self, todo_id: i32) -> anyhow::Result<Vec<Attachment>> {
+        let attachments = sqlx::query_as::<_, Attachment>(
+            r#"
+            SELECT * FROM attachments
+            WHERE todo_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(todo_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(attachments)
+    }
+
+    #[tracing::instrument(name = "AttachmentRepository::find", skip_all)]
+    async fn find(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment> {
+        let attachment = sqlx::query_as::<_, Attachment>(
+            r#"
+            SELECT * FROM attachments
+            WHERE id = $1 AND todo_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(todo_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(attachment)
+    }
+
+    #[tracing::instrument(name = "AttachmentRepository::delete", skip_all)]
+    async fn delete(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment> {
+        let attachment = sqlx::query_as::<_, Attachment>(
+            r#"
+            DELETE FROM attachments
+            WHERE id = $1 AND todo_id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(todo_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(attachment)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = AttachmentRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'attachment_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        let (todo_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO todos (text, user_id)
+            VALUES ( '[attachment_crud_scenario] text', $1 )
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+
+        // create
+        let attachment = repo
+            .create(NewAttachment {
+                todo_id,
+                user_id,
+                file_name: "memo.txt".to_string(),
+                content_type: "text/plain".to_string(),
+                size: 5,
+                storage_key: format!("todos/{}/attachment_crud_scenario", todo_id),
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(attachment.user_id, Some(user_id));
+
+        // find / all_by_todo
+        let found = repo
+            .find(todo_id, attachment.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(found, attachment);
+        assert!(repo.find(todo_id + 1, attachment.id).await.is_err());
+        let attachments = repo
+            .all_by_todo(todo_id)
+            .await
+            .expect("[all_by_todo] returned Err");
+        assert_eq!(attachments, vec![attachment.clone()]);
+
+        // delete
+        let deleted = repo
+            .delete(todo_id, attachment.id)
+            .await
+            .expect("[delete] returned Err");
+        assert_eq!(deleted.storage_key, attachment.storage_key);
+        assert!(repo.delete(todo_id, attachment.id).await.is_err());
+
+        sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up todo");
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub struct AttachmentRepositoryForMemory {
+        store: Arc<RwLock<Vec<Attachment>>>,
+    }
+
+    impl AttachmentRepositoryForMemory {
+        pub fn new() -> Self {
+            AttachmentRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AttachmentRepository for AttachmentRepositoryForMemory {
+        async fn create(&self, payload: NewAttachment) -> anyhow::Result<Attachment> {
+            let mut store = self.store.write().unwrap();
+            let attachment = Attachment {
+                id: store
+                    .iter()
+                    .map(|attachment| attachment.id)
+                    .max()
+                    .unwrap_or(0)
+                    + 1,
+                todo_id: payload.todo_id,
+                user_id: Some(payload.user_id),
+                file_name: payload.file_name,
+                content_type: payload.content_type,
+                size: payload.size,
+                storage_key: payload.storage_key,
+                created_at: Utc::now(),
+            };
+            store.push(attachment.clone());
+            Ok(attachment)
+        }
+
+        async fn all_by_todo(&self, todo_id: i32) -> anyhow::Result<Vec<Attachment>> {
+            let store = self.store.read().unwrap();
+            Ok(store
+                .iter()
+                .filter(|attachment| attachment.todo_id == todo_id)
+                .cloned()
+                .collect())
+        }
+
+        async fn find(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment> {
+            let store = self.store.read().unwrap();
+            store
+                .iter()
+                .find(|attachment| attachment.id == id && attachment.todo_id == todo_id)
+                .cloned()
+                .ok_or_else(|| RepositoryError::NotFound(id).into())
+        }
+
+        async fn delete(&self, todo_id: i32, id: i32) -> anyhow::Result<Attachment> {
+            let mut store = self.store.write().unwrap();
+            let index = store
+                .iter()
+                .position(|attachment| attachment.id == id && attachment.todo_id == todo_id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            Ok(store.remove(index))
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn attachment_scenario() {
+            let repo = AttachmentRepositoryForMemory::new();
+            let new_attachment = |todo_id: i32, storage_key: &str| NewAttachment {
+                todo_id,
+                user_id: 1,
+                file_name: "memo.txt".to_string(),
+                content_type: "text/plain".to_string(),
+                size: 5,
+                storage_key: storage_key.to_string(),
+            };
+            let attachment = repo
+                .create(new_attachment(1, "a"))
+                .await
+                .expect("failed create attachment");
+            repo.create(new_attachment(2, "b"))
+                .await
+                .expect("failed create attachment");
+
+            assert_eq!(repo.all_by_todo(1).await.unwrap(), vec![attachment.clone()]);
+            assert_eq!(repo.find(1, attachment.id).await.unwrap(), attachment);
+            // 別の todo からは見えない
+            assert!(repo.find(2, attachment.id).await.is_err());
+            assert!(repo.delete(2, attachment.id).await.is_err());
+
+            let deleted = repo.delete(1, attachment.id).await.unwrap();
+            assert_eq!(deleted.storage_key, "a");
+            assert!(repo.all_by_todo(1).await.unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgConnection, PgPool};
+
+use super::Tx;
+
+#[async_trait]
+pub trait AuditRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 変更と同じ tx で記録する. 変更が rollback されれば記録も残らない
+    async fn record(&self, tx: &mut Tx, payload: CreateAuditEvent) -> anyhow::Result<AuditEvent>;
+    // 新しい順
+    async fn all(&self) -> anyhow::Result<Vec<AuditEvent>>;
+    async fn all_by_actor(&self, actor_id: i32) -> anyhow::Result<Vec<AuditEvent>>;
+    // actor_id が since 以降に entity を変更した記録のうち、まだ undo していないものを新しい順に返す
+    // undo したときの記録自体は含めない
+    async fn undoable(
+        &self,
+        actor_id: i32,
+        entity: AuditEntity,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<AuditEvent>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "audit_action", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "audit_entity", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEntity {
+    Todo,
+    Label,
+    Project,
+    Template,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct AuditEvent {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: i32,
+    // 作成時は before が、削除時は after が None
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    // undo したときの記録なら、取り消した記録の id
+    pub undo_of: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateAuditEvent {
+    // scheduler など、ユーザーの操作でない変更は None
+    pub actor_id: Option<i32>,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: i32,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub undo_of: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditRepositoryForDb {
+    pool: PgPool,
+}
+
+impl AuditRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+// repository が自分で始めたトランザクションの中で記録するときにも使う (バックアップの取り込みなど)
+pub async fn insert_event(
+    conn: &mut PgConnection,
+    payload: CreateAuditEvent,
+) -> anyhow::Result<AuditEvent> {
+    let event = sqlx::query_as::<_, AuditEvent>(
+        r#"
+        INSERT INTO audit_events (actor_id, action, entity, entity_id, before, after, undo_of)
+        VALUES ( $1, $2, $3, $4, $5, $6, $7 )
+        RETURNING *
+        "#,
+    )
+    .bind(payload.actor_id)
+    .bind(payload.action)
+    .bind(payload.entity)
+    .bind(payload.entity_id)
+    .bind(payload.before)
+    .bind(payload.after)
+    .bind(payload.undo_of)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(event)
+}
+
+#[async_trait]
+impl AuditRepository for AuditRepositoryForDb {
+    #[tracing::instrument(name = "AuditRepository::record", skip_all)]
+    async fn record(&self, tx: &mut Tx, payload: CreateAuditEvent) -> anyhow::Result<AuditEvent> {
+        insert_event(tx.conn()?, payload).await
+    }
+
+    #[tracing::instrument(name = "AuditRepository::all", skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            r#"
+            SELECT * FROM audit_events
+            ORDER BY id DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    #[tracing::instrument(name = "AuditRepository::all_by_actor", skip_all)]
+    async fn all_by_actor(&self, actor_id: i32) -> anyhow::Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            r#"
+            SELECT * FROM audit_events
+            WHERE actor_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(actor_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    #[tracing::instrument(name = "AuditRepository::undoable", skip_all)]
+    async fn undoable(
+        &self,
+        actor_id: i32,
+        entity: AuditEntity,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            r#"
+            SELECT * FROM audit_events
+            WHERE actor_id = $1 AND entity = $2 AND created_at >= $3 AND undo_of IS NULL
+                AND id NOT IN (SELECT undo_of FROM audit_events WHERE undo_of IS NOT NULL)
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(actor_id)
+        .bind(entity)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = AuditRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'audit_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+
+        // record
+        let mut tx = Tx::begin(&pool).await.unwrap();
+        let event = repo
+            .record(
+                &mut tx,
+                CreateAuditEvent {
+                    actor_id: Some(user_id),
+                    action: AuditAction::Update,
+                    entity: AuditEntity::Todo,
+                    entity_id: 1,
+                    before: Some(json!({ "text": "before" })),
+                    after: Some(json!({ "text": "after" })),
+                    undo_of: None,
+                },
+            )
+            .await
+            .expect("[record] returned Err");
+        tx.commit().await.unwrap();
+        assert_eq!(event.actor_id, Some(user_id));
+        assert_eq!(event.after, Some(json!({ "text": "after" })));
+
+        // all
+        let events = repo.all().await.expect("[all] returned Err");
+        assert!(events.contains(&event));
+
+        // all_by_actor
+        let events = repo
+            .all_by_actor(user_id)
+            .await
+            .expect("[all_by_actor] returned Err");
+        assert!(events.contains(&event));
+        assert!(events.iter().all(|event| event.actor_id == Some(user_id)));
+
+        // undoable
+        let since = event.created_at;
+        let events = repo
+            .undoable(user_id, AuditEntity::Todo, since)
+            .await
+            .expect("[undoable] returned Err");
+        assert_eq!(events.first(), Some(&event));
+        let mut tx = Tx::begin(&pool).await.unwrap();
+        let undo = repo
+            .record(
+                &mut tx,
+                CreateAuditEvent {
+                    actor_id: Some(user_id),
+                    action: AuditAction::Update,
+                    entity: AuditEntity::Todo,
+                    entity_id: 1,
+                    before: Some(json!({ "text": "after" })),
+                    after: Some(json!({ "text": "before" })),
+                    undo_of: Some(event.id),
+                },
+            )
+            .await
+            .expect("[record] returned Err");
+        tx.commit().await.unwrap();
+        let events = repo
+            .undoable(user_id, AuditEntity::Todo, since)
+            .await
+            .expect("[undoable] returned Err");
+        assert!(!events.contains(&event));
+        assert!(!events.contains(&undo));
+
+        // commit しなかった tx の記録は残らない
+        let mut tx = Tx::begin(&pool).await.unwrap();
+        let rolled_back = repo
+            .record(
+                &mut tx,
+                CreateAuditEvent {
+                    actor_id: Some(user_id),
+                    action: AuditAction::Delete,
+                    entity: AuditEntity::Todo,
+                    entity_id: 1,
+                    before: Some(json!({ "text": "before" })),
+                    after: None,
+                    undo_of: None,
+                },
+            )
+            .await
+            .expect("[record] returned Err");
+        drop(tx);
+        let events = repo.all().await.expect("[all] returned Err");
+        assert!(events.iter().all(|event| event.id != rolled_back.id));
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub struct AuditRepositoryForMemory {
+        store: Arc<RwLock<Vec<AuditEvent>>>,
+    }
+
+    impl AuditRepositoryForMemory {
+        pub fn new() -> Self {
+            AuditRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AuditRepository for AuditRepositoryForMemory {
+        async fn record(
+            &self,
+            _tx: &mut Tx,
+            payload: CreateAuditEvent,
+        ) -> anyhow::Result<AuditEvent> {
+            let mut store = self.store.write().unwrap();
+            let event = AuditEvent {
+                id: (store.len() + 1) as i32,
+                actor_id: payload.actor_id,
+                action: payload.action,
+                entity: payload.entity,
+                entity_id: payload.entity_id,
+                before: payload.before,
+                after: payload.after,
+                undo_of: payload.undo_of,
+                created_at: Utc::now(),
+            };
+            store.push(event.clone());
+            Ok(event)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<AuditEvent>> {
+            let mut events = self.store.read().unwrap().clone();
+            events.reverse();
+            Ok(events)
+        }
+
+        async fn all_by_actor(&self, actor_id: i32) -> anyhow::Result<Vec<AuditEvent>> {
+            let mut events = self.all().await?;
+            events.retain(|event| event.actor_id == Some(actor_id));
+            Ok(events)
+        }
+
+        async fn undoable(
+            &self,
+            actor_id: i32,
+            entity: AuditEntity,
+            since: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<AuditEvent>> {
+            let events = self.all_by_actor(actor_id).await?;
+            let undone: Vec<i32> = events.iter().filter_map(|event| event.undo_of).collect();
+            Ok(events
+                .into_iter()
+                .filter(|event| {
+                    event.entity == entity
+                        && event.created_at >= since
+                        && event.undo_of.is_none()
+                        && !undone.contains(&event.id)
+                })
+                .collect())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn audit_scenario() {
+            let repo = AuditRepositoryForMemory::new();
+            for action in [AuditAction::Create, AuditAction::Delete] {
+                repo.record(
+                    &mut Tx::memory(),
+                    CreateAuditEvent {
+                        actor_id: Some(1),
+                        action,
+                        entity: AuditEntity::Label,
+                        entity_id: 1,
+                        before: None,
+                        after: None,
+                        undo_of: None,
+                    },
+                )
+                .await
+                .expect("failed record audit event");
+            }
+
+            let events = repo.all().await.expect("failed get all audit events");
+            let actions: Vec<AuditAction> = events.iter().map(|event| event.action).collect();
+            assert_eq!(actions, vec![AuditAction::Delete, AuditAction::Create]);
+            assert_eq!(repo.all_by_actor(1).await.unwrap(), events);
+            assert!(repo.all_by_actor(2).await.unwrap().is_empty());
+
+            // undo した記録と、undo したときの記録は対象にならない
+            let since = events[1].created_at;
+            let undo = repo
+                .record(
+                    &mut Tx::memory(),
+                    CreateAuditEvent {
+                        actor_id: Some(1),
+                        action: AuditAction::Create,
+                        entity: AuditEntity::Label,
+                        entity_id: 1,
+                        before: None,
+                        after: None,
+                        undo_of: Some(events[0].id),
+                    },
+                )
+                .await
+                .expect("failed record audit event");
+            assert_eq!(undo.undo_of, Some(events[0].id));
+            let undoable = repo.undoable(1, AuditEntity::Label, since).await.unwrap();
+            assert_eq!(undoable, vec![events[1].clone()]);
+            assert!(repo
+                .undoable(1, AuditEntity::Todo, since)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+    }
+}
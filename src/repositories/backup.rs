@@ -0,0 +1,921 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::collections::{HashMap, HashSet};
+use validator::{Validate, ValidationError};
+
+use super::{
+    audit::{insert_event, AuditAction, AuditEntity, CreateAuditEvent},
+    cache::RepositoryCache,
+    label::{validate_color, validate_icon, Label, DEFAULT_COLOR},
+    todo::{
+        fold_entities, is_valid_schedule, validate_recurrence, Priority, Recurrence, Status, Todo,
+        TodoWithLabelFromRow,
+    },
+    RepositoryError, Scope,
+};
+
+// バックアップの形式を変えたら上げる. 違う版のバックアップはインポートしない
+pub const BACKUP_VERSION: i32 = 1;
+
+#[async_trait]
+pub trait BackupRepository: std::marker::Send + std::marker::Sync + 'static {
+    // scope の todo (アーカイブ済みも含む) とラベル. 共有されただけの他人の todo は含めない
+    async fn export(&self, scope: Scope) -> anyhow::Result<Backup>;
+    // 1 つのトランザクションで scope に戻す. 1 件でも失敗したら何も戻さない
+    // scope に同じ id の todo があれば strategy に従う. ラベルは scope に同じ名前のものがあれば使い回す
+    // 作った todo / ラベルと上書きした todo は、scope のユーザーの操作として同じトランザクションで監査ログに残す
+    async fn import(
+        &self,
+        scope: Scope,
+        backup: Backup,
+        strategy: ConflictStrategy,
+    ) -> anyhow::Result<ImportSummary>;
+}
+
+// GET /export のレスポンスで、POST /import のボディ
+// id はエクスポート元での id. インポート先では親子関係とラベルの関連付けにだけ使う
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct Backup {
+    pub version: i32,
+    pub exported_at: DateTime<Utc>,
+    #[validate]
+    pub labels: Vec<BackupLabel>,
+    #[validate]
+    pub todos: Vec<BackupTodo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct BackupLabel {
+    pub id: i32,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    pub name: String,
+    // 色を追加する前のバックアップにはないので省略できる. 省略したら DEFAULT_COLOR
+    #[serde(default)]
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+    // アイコンを追加する前のバックアップにはないので省略できる
+    #[serde(default)]
+    #[validate(custom = "validate_icon")]
+    pub icon: Option<String>,
+    // labels の id. 入れ子を追加する前のバックアップにはないので省略できる
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    // アーカイブを追加する前のバックアップにはないので省略できる
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+// 所有者・プロジェクト・担当者はエクスポート元でしか意味がないので含めない
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[validate(schema(function = "validate_schedule"))]
+pub struct BackupTodo {
+    pub id: i32,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub text: String,
+    #[validate(length(max = 10000, message = "Over description length"))]
+    pub description: Option<String>,
+    pub status: Status,
+    pub due_at: Option<DateTime<Utc>>,
+    // 開始日時を追加する前のバックアップにはないので省略できる
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    pub priority: Priority,
+    pub parent_id: Option<i32>,
+    #[validate(custom = "validate_recurrence")]
+    pub recurrence: Option<Recurrence>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub position: i64,
+    pub is_pinned: bool,
+    pub created_at: DateTime<Utc>,
+    // labels の id
+    pub labels: Vec<i32>,
+}
+
+impl From<&Todo> for BackupTodo {
+    fn from(todo: &Todo) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text.clone(),
+            description: todo.description.clone(),
+            status: todo.status,
+            due_at: todo.due_at,
+            starts_at: todo.starts_at,
+            priority: todo.priority,
+            parent_id: todo.parent_id,
+            recurrence: todo.recurrence,
+            archived_at: todo.archived_at,
+            position: todo.position,
+            is_pinned: todo.is_pinned,
+            created_at: todo.created_at,
+            labels: todo.labels.iter().map(|label| label.id).collect(),
+        }
+    }
+}
+
+fn validate_schedule(todo: &BackupTodo) -> Result<(), ValidationError> {
+    is_valid_schedule(todo.starts_at, todo.due_at)
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("starts_at must not be after due_at"))
+}
+
+impl Backup {
+    fn new(todos: Vec<Todo>, mut labels: Vec<Label>) -> Self {
+        // scope の外のラベルが付いていても関連付けを戻せるように含める
+        for label in todos.iter().flat_map(|todo| &todo.labels) {
+            if !labels.iter().any(|known| known.id == label.id) {
+                labels.push(label.clone());
+            }
+        }
+        Self {
+            version: BACKUP_VERSION,
+            exported_at: Utc::now(),
+            labels: labels
+                .into_iter()
+                .map(|label| BackupLabel {
+                    id: label.id,
+                    name: label.name,
+                    color: Some(label.color),
+                    icon: label.icon,
+                    parent_id: label.parent_id,
+                    archived_at: label.archived_at,
+                })
+                .collect(),
+            todos: todos.iter().map(BackupTodo::from).collect(),
+        }
+    }
+
+    // 版・id の重複・親子関係の循環を確かめる. 中にない親やラベルを指していても関連付けを外すだけにする
+    fn check(&self) -> Result<(), RepositoryError> {
+        if self.version != BACKUP_VERSION {
+            return Err(RepositoryError::Invalid(format!(
+                "unsupported backup version: {}",
+                self.version
+            )));
+        }
+        let mut label_ids = HashSet::new();
+        if let Some(label) = self.labels.iter().find(|label| !label_ids.insert(label.id)) {
+            return Err(RepositoryError::Invalid(format!(
+                "duplicated label id: {}",
+                label.id
+            )));
+        }
+        let mut todo_ids = HashSet::new();
+        if let Some(todo) = self.todos.iter().find(|todo| !todo_ids.insert(todo.id)) {
+            return Err(RepositoryError::Invalid(format!(
+                "duplicated todo id: {}",
+                todo.id
+            )));
+        }
+        let parents = self
+            .labels
+            .iter()
+            .filter_map(|label| label.parent_id.map(|parent_id| (label.id, parent_id)))
+            .collect();
+        if let Some(id) = find_cycle(&parents) {
+            return Err(RepositoryError::Invalid(format!(
+                "circular label parent: {}",
+                id
+            )));
+        }
+        let parents = self
+            .todos
+            .iter()
+            .filter_map(|todo| todo.parent_id.map(|parent_id| (todo.id, parent_id)))
+            .collect();
+        if let Some(id) = find_cycle(&parents) {
+            return Err(RepositoryError::Invalid(format!("circular parent: {}", id)));
+        }
+        Ok(())
+    }
+}
+
+// 子の id -> 親の id をたどって循環していれば、循環に入る id を返す
+fn find_cycle(parents: &HashMap<i32, i32>) -> Option<i32> {
+    let mut ids: Vec<&i32> = parents.keys().collect();
+    ids.sort();
+    ids.into_iter().copied().find(|&id| {
+        let mut visited = HashSet::new();
+        let mut current = id;
+        while let Some(&parent_id) = parents.get(&current) {
+            if !visited.insert(current) {
+                return true;
+            }
+            current = parent_id;
+        }
+        false
+    })
+}
+
+// scope に同じ id の todo があったときの扱い
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    // 今の todo を残し、バックアップの todo は戻さない
+    #[default]
+    Skip,
+    // 今の todo をバックアップの内容で上書きする
+    Overwrite,
+    // 今の todo を残し、バックアップの todo を別の id で作る
+    Duplicate,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ImportCount {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+// 使い回したラベルは skipped に数える
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub todos: ImportCount,
+    pub labels: ImportCount,
+}
+
+// scope の todo だけを残す条件. $1 に user_id、$2 に workspace_id を渡す
+const TODOS_IN_SCOPE: &str = r#"
+    (($2::INTEGER IS NULL AND todos.workspace_id IS NULL AND todos.user_id = $1)
+        OR todos.workspace_id = $2)
+"#;
+
+// TODOS_IN_SCOPE のラベル版
+const LABELS_IN_SCOPE: &str = r#"
+    (($2::INTEGER IS NULL AND labels.workspace_id IS NULL AND labels.user_id = $1)
+        OR labels.workspace_id = $2)
+"#;
+
+#[derive(Debug, Clone)]
+pub struct BackupRepositoryForDb {
+    pool: PgPool,
+    // import したら todo / label のキャッシュを捨てる
+    cache: Option<RepositoryCache>,
+}
+
+impl BackupRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool, cache: None }
+    }
+
+    pub fn with_cache(self, cache: Option<RepositoryCache>) -> Self {
+        Self { cache, ..self }
+    }
+
+    // 監査ログに残すために、ラベルも含めて読み直す
+    async fn todos_by_id(conn: &mut PgConnection, ids: &[i32]) -> anyhow::Result<Vec<Todo>> {
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.id = ANY($1)
+            ORDER BY todos.id
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(fold_entities(rows))
+    }
+}
+
+// import で変えた todo / ラベルの監査ログ
+fn import_event<T: Serialize>(
+    scope: Scope,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i32,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> CreateAuditEvent {
+    let to_json = |value: Option<&T>| value.and_then(|value| serde_json::to_value(value).ok());
+    CreateAuditEvent {
+        actor_id: Some(scope.user_id),
+        action,
+        entity,
+        entity_id,
+        before: to_json(before),
+        after: to_json(after),
+        undo_of: None,
+    }
+}
+
+#[async_trait]
+impl BackupRepository for BackupRepositoryForDb {
+    #[tracing::instrument(name = "BackupRepository::export", skip_all)]
+    async fn export(&self, scope: Scope) -> anyhow::Result<Backup> {
+        let sql = format!(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE {}
+            ORDER BY todos.id
+            "#,
+            TODOS_IN_SCOPE
+        );
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .fetch_all(&self.pool)
+            .await?;
+        let sql = format!("SELECT * FROM labels WHERE {} ORDER BY id", LABELS_IN_SCOPE);
+        let labels = sqlx::query_as::<_, Label>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(Backup::new(fold_entities(rows), labels))
+    }
+
+    #[tracing::instrument(name = "BackupRepository::import", skip_all)]
+    async fn import(
+        &self,
+        scope: Scope,
+        backup: Backup,
+        strategy: ConflictStrategy,
+    ) -> anyhow::Result<ImportSummary> {
+        backup.check()?;
+        let mut summary = ImportSummary::default();
+        let mut tx = self.pool.begin().await?;
+
+        // バックアップのラベルの id -> インポート先のラベルの id
+        let sql = format!("SELECT * FROM labels WHERE {}", LABELS_IN_SCOPE);
+        let existing_labels = sqlx::query_as::<_, Label>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .fetch_all(&mut tx)
+            .await?;
+        let mut label_ids = HashMap::new();
+        let mut created_labels = vec![];
+        for label in &backup.labels {
+            // 名前は一意制約と同じく大文字と小文字を区別せずに比べる
+            let id = match existing_labels
+                .iter()
+                .find(|existing| existing.name.to_lowercase() == label.name.to_lowercase())
+            {
+                Some(existing) => {
+                    summary.labels.skipped += 1;
+                    existing.id
+                }
+                None => {
+                    summary.labels.created += 1;
+                    let id = sqlx::query_scalar::<_, i32>(
+                        r#"
+                        INSERT INTO labels (name, user_id, workspace_id, color, icon, archived_at)
+                        VALUES ( $1, $2, $3, $4, $5, $6 )
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(&label.name)
+                    .bind(scope.user_id)
+                    .bind(scope.workspace_id)
+                    .bind(label.color.as_deref().unwrap_or(DEFAULT_COLOR))
+                    .bind(&label.icon)
+                    .bind(label.archived_at)
+                    .fetch_one(&mut tx)
+                    .await?;
+                    created_labels.push((label, id));
+                    id
+                }
+            };
+            label_ids.insert(label.id, id);
+        }
+        // 使い回したラベルの親は変えない
+        let created_label_ids: Vec<i32> = created_labels.iter().map(|(_, id)| *id).collect();
+        for (label, id) in created_labels {
+            let parent_id = label
+                .parent_id
+                .and_then(|parent_id| label_ids.get(&parent_id).copied());
+            sqlx::query("UPDATE labels SET parent_id = $2 WHERE id = $1")
+                .bind(id)
+                .bind(parent_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        // バックアップの todo の id -> インポート先の todo の id
+        let sql = format!(
+            "SELECT id FROM todos WHERE id = ANY($3) AND {}",
+            TODOS_IN_SCOPE
+        );
+        let conflicts = sqlx::query_scalar::<_, i32>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .bind(
+                backup
+                    .todos
+                    .iter()
+                    .map(|todo| todo.id)
+                    .collect::<Vec<i32>>(),
+            )
+            .fetch_all(&mut tx)
+            .await?;
+        let overwritten = match strategy {
+            ConflictStrategy::Overwrite => Self::todos_by_id(&mut tx, &conflicts).await?,
+            _ => vec![],
+        };
+        let mut todo_ids = HashMap::new();
+        let mut imported = vec![];
+        for todo in &backup.todos {
+            let conflicted = conflicts.contains(&todo.id);
+            if conflicted && strategy == ConflictStrategy::Skip {
+                summary.todos.skipped += 1;
+                todo_ids.insert(todo.id, todo.id);
+                continue;
+            }
+            let id = if conflicted && strategy == ConflictStrategy::Overwrite {
+                summary.todos.updated += 1;
+                sqlx::query(
+                    r#"
+                    UPDATE todos SET text = $2, description = $3, status = $4, due_at = $5, priority = $6,
+                        recurrence_freq = $7, recurrence_interval = $8, recurrence_until = $9,
+                        archived_at = $10, position = $11, is_pinned = $12, starts_at = $13
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(todo.id)
+                .bind(&todo.text)
+                .bind(&todo.description)
+                .bind(todo.status)
+                .bind(todo.due_at)
+                .bind(todo.priority)
+                .bind(todo.recurrence.map(|recurrence| recurrence.freq))
+                .bind(todo.recurrence.map_or(1, |recurrence| recurrence.interval))
+                .bind(todo.recurrence.and_then(|recurrence| recurrence.until))
+                .bind(todo.archived_at)
+                .bind(todo.position)
+                .bind(todo.is_pinned)
+                .bind(todo.starts_at)
+                .execute(&mut tx)
+                .await?;
+                todo.id
+            } else {
+                summary.todos.created += 1;
+                sqlx::query_scalar::<_, i32>(
+                    r#"
+                    INSERT INTO todos (text, description, status, user_id, workspace_id, due_at, priority,
+                        recurrence_freq, recurrence_interval, recurrence_until, archived_at, position,
+                        is_pinned, created_at, starts_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                    RETURNING id
+                    "#,
+                )
+                .bind(&todo.text)
+                .bind(&todo.description)
+                .bind(todo.status)
+                .bind(scope.user_id)
+                .bind(scope.workspace_id)
+                .bind(todo.due_at)
+                .bind(todo.priority)
+                .bind(todo.recurrence.map(|recurrence| recurrence.freq))
+                .bind(todo.recurrence.map_or(1, |recurrence| recurrence.interval))
+                .bind(todo.recurrence.and_then(|recurrence| recurrence.until))
+                .bind(todo.archived_at)
+                .bind(todo.position)
+                .bind(todo.is_pinned)
+                .bind(todo.created_at)
+                .bind(todo.starts_at)
+                .fetch_one(&mut tx)
+                .await?
+            };
+            todo_ids.insert(todo.id, id);
+            imported.push((todo, id));
+        }
+
+        // 親が先に作られているとは限らないので、全部作ってから関連付ける
+        let imported_ids: Vec<i32> = imported.iter().map(|(_, id)| *id).collect();
+        for (todo, id) in imported {
+            let parent_id = todo
+                .parent_id
+                .and_then(|parent_id| todo_ids.get(&parent_id).copied());
+            sqlx::query("UPDATE todos SET parent_id = $2 WHERE id = $1")
+                .bind(id)
+                .bind(parent_id)
+                .execute(&mut tx)
+                .await?;
+            let labels: Vec<i32> = todo
+                .labels
+                .iter()
+                .filter_map(|label_id| label_ids.get(label_id).copied())
+                .collect();
+            sqlx::query("DELETE FROM todo_labels WHERE todo_id = $1")
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO todo_labels (todo_id, label_id)
+                SELECT $1, id
+                FROM unnest($2) as t(id);
+                "#,
+            )
+            .bind(id)
+            .bind(labels)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        let labels = sqlx::query_as::<_, Label>("SELECT * FROM labels WHERE id = ANY($1)")
+            .bind(&created_label_ids)
+            .fetch_all(&mut tx)
+            .await?;
+        for label in &labels {
+            let event = import_event(
+                scope,
+                AuditAction::Create,
+                AuditEntity::Label,
+                label.id,
+                None,
+                Some(label),
+            );
+            insert_event(&mut tx, event).await?;
+        }
+        for todo in Self::todos_by_id(&mut tx, &imported_ids).await? {
+            let before = overwritten.iter().find(|before| before.id == todo.id);
+            let action = match before {
+                Some(_) => AuditAction::Update,
+                None => AuditAction::Create,
+            };
+            let event = import_event(
+                scope,
+                action,
+                AuditEntity::Todo,
+                todo.id,
+                before,
+                Some(&todo),
+            );
+            insert_event(&mut tx, event).await?;
+        }
+
+        tx.commit().await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all().await;
+        }
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use crate::repositories::todo::{CreateTodo, TodoRepository, TodoRepositoryForDb};
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn export_import_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = BackupRepositoryForDb::new(pool.clone());
+        let todo_repo = TodoRepositoryForDb::new(pool.clone());
+        let mut user_ids = vec![];
+        for email in [
+            "backup_scenario@example.com",
+            "backup_scenario_other@example.com",
+        ] {
+            let (user_id,): (i32,) = sqlx::query_as(
+                r#"
+                INSERT INTO users (email, password_hash)
+                VALUES ( $1, 'hash' )
+                ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+                RETURNING id
+                "#,
+            )
+            .bind(email)
+            .fetch_one(&pool)
+            .await
+            .expect("failed to prepare user data.");
+            // 前回のテストのデータを消しておく
+            sqlx::query(
+                "DELETE FROM todo_labels WHERE todo_id IN (SELECT id FROM todos WHERE user_id = $1)",
+            )
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up todo labels");
+            sqlx::query("DELETE FROM todos WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&pool)
+                .await
+                .expect("failed to clean up todos");
+            sqlx::query("DELETE FROM labels WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&pool)
+                .await
+                .expect("failed to clean up labels");
+            user_ids.push(user_id);
+        }
+        let scope = Scope::personal(user_ids[0]);
+        let (label_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name, user_id) VALUES ( '[backup_scenario] label', $1 ) RETURNING id
+            "#,
+        )
+        .bind(user_ids[0])
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare label data.");
+        let parent = todo_repo
+            .create(
+                scope,
+                CreateTodo::new("[backup_scenario] parent".to_string(), vec![label_id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        todo_repo
+            .create(
+                scope,
+                CreateTodo::from_subtask(parent.id, "[backup_scenario] child".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+
+        // export
+        let backup = repo.export(scope).await.expect("[export] returned Err");
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_eq!(backup.todos.len(), 2);
+        assert_eq!(backup.todos[0].labels, vec![label_id]);
+        assert_eq!(backup.todos[1].parent_id, Some(parent.id));
+        assert_eq!(backup.labels.len(), 1);
+
+        // 別のユーザーに戻すと、ラベルも含めて作り直して関連付ける
+        let other = Scope::personal(user_ids[1]);
+        let summary = repo
+            .import(other, backup.clone(), ConflictStrategy::Skip)
+            .await
+            .expect("[import] returned Err");
+        assert_eq!(summary.todos.created, 2);
+        assert_eq!(summary.labels.created, 1);
+        let restored = repo.export(other).await.expect("[export] returned Err");
+        assert_eq!(restored.todos[0].text, backup.todos[0].text);
+        assert_eq!(restored.todos[0].labels, vec![restored.labels[0].id]);
+        assert_eq!(restored.todos[1].parent_id, Some(restored.todos[0].id));
+        // 作った todo とラベルは、インポートしたユーザーの操作として残る
+        let (recorded,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM audit_events WHERE actor_id = $1 AND action = 'create'",
+        )
+        .bind(other.user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(recorded, 3);
+
+        // 同じユーザーに戻すと strategy に従う
+        let mut edited = backup.clone();
+        edited.todos[0].text = "[backup_scenario] overwritten".to_string();
+        let summary = repo
+            .import(scope, edited.clone(), ConflictStrategy::Skip)
+            .await
+            .expect("[import] returned Err");
+        assert_eq!((summary.todos.skipped, summary.labels.skipped), (2, 1));
+        assert_eq!(
+            todo_repo.find(scope, parent.id).await.unwrap().text,
+            parent.text
+        );
+        let summary = repo
+            .import(scope, edited.clone(), ConflictStrategy::Overwrite)
+            .await
+            .expect("[import] returned Err");
+        assert_eq!(summary.todos.updated, 2);
+        let todo = todo_repo.find(scope, parent.id).await.unwrap();
+        assert_eq!(todo.text, "[backup_scenario] overwritten");
+        assert_eq!(todo.labels.len(), 1);
+        let summary = repo
+            .import(scope, edited, ConflictStrategy::Duplicate)
+            .await
+            .expect("[import] returned Err");
+        assert_eq!(summary.todos.created, 2);
+        assert_eq!(repo.export(scope).await.unwrap().todos.len(), 4);
+
+        // 循環した親子関係は何も戻さない
+        let mut circular = backup;
+        circular.todos[0].parent_id = Some(circular.todos[1].id);
+        let res = repo
+            .import(other, circular, ConflictStrategy::Duplicate)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Invalid(_))
+        ));
+        assert_eq!(repo.export(other).await.unwrap().todos.len(), 2);
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use super::*;
+    use crate::repositories::{
+        audit::{memory::AuditRepositoryForMemory, AuditRepository},
+        label::{
+            memory::LabelRepositoryForMemory, CreateLabel, LabelQuery, LabelRepository, UpdateLabel,
+        },
+        todo::{memory::TodoRepositoryForMemory, CreateTodo, TodoRepository},
+    };
+
+    // 他のレポジトリと同じデータを読み書きできるように、同じ store を持つレポジトリを受け取る
+    // トランザクションはないので、途中で失敗するとそこまでの変更が残る
+    #[derive(Debug, Clone)]
+    pub struct BackupRepositoryForMemory {
+        todo: TodoRepositoryForMemory,
+        label: LabelRepositoryForMemory,
+        audit: AuditRepositoryForMemory,
+    }
+
+    impl BackupRepositoryForMemory {
+        pub fn new(
+            todo: TodoRepositoryForMemory,
+            label: LabelRepositoryForMemory,
+            audit: AuditRepositoryForMemory,
+        ) -> Self {
+            Self { todo, label, audit }
+        }
+
+        // アーカイブしたラベルも含める
+        async fn labels_in(&self, scope: Scope) -> anyhow::Result<Vec<Label>> {
+            let mut labels = self.label.all(scope, &LabelQuery::default()).await?;
+            let archived = LabelQuery {
+                archived: Some(true),
+                ..Default::default()
+            };
+            labels.extend(self.label.all(scope, &archived).await?);
+            labels.sort_by_key(|label| label.id);
+            Ok(labels)
+        }
+
+        async fn todos_in(&self, scope: Scope) -> anyhow::Result<Vec<Todo>> {
+            let mut todos: Vec<Todo> = self
+                .todo
+                .all_unscoped()
+                .await?
+                .into_iter()
+                .filter(|todo| todo.is_visible_in(scope))
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            Ok(todos)
+        }
+    }
+
+    #[async_trait]
+    impl BackupRepository for BackupRepositoryForMemory {
+        async fn export(&self, scope: Scope) -> anyhow::Result<Backup> {
+            Ok(Backup::new(
+                self.todos_in(scope).await?,
+                self.labels_in(scope).await?,
+            ))
+        }
+
+        async fn import(
+            &self,
+            scope: Scope,
+            backup: Backup,
+            strategy: ConflictStrategy,
+        ) -> anyhow::Result<ImportSummary> {
+            backup.check()?;
+            let mut summary = ImportSummary::default();
+            let mut tx = self.todo.begin().await?;
+
+            let existing_labels = self.labels_in(scope).await?;
+            let mut labels = HashMap::new();
+            let mut created_labels = vec![];
+            for label in &backup.labels {
+                let imported = match existing_labels
+                    .iter()
+                    .find(|existing| existing.name.to_lowercase() == label.name.to_lowercase())
+                {
+                    Some(existing) => {
+                        summary.labels.skipped += 1;
+                        existing.clone()
+                    }
+                    None => {
+                        summary.labels.created += 1;
+                        let mut payload = CreateLabel::new(label.name.clone(), scope.user_id);
+                        payload.workspace_id = scope.workspace_id;
+                        payload.color = label.color.clone();
+                        payload.icon = label.icon.clone();
+                        let mut created = self.label.create_in(&mut tx, payload).await?;
+                        if label.archived_at.is_some() {
+                            created = self.label.archive_in(&mut tx, created.id).await?;
+                        }
+                        created_labels.push((label, created.id));
+                        created
+                    }
+                };
+                labels.insert(label.id, imported);
+            }
+            for (label, id) in created_labels {
+                let parent_id = label
+                    .parent_id
+                    .and_then(|parent_id| labels.get(&parent_id).map(|parent| parent.id));
+                let updated = self
+                    .label
+                    .update_in(&mut tx, id, UpdateLabel::parent(parent_id))
+                    .await?;
+                let event = import_event(
+                    scope,
+                    AuditAction::Create,
+                    AuditEntity::Label,
+                    id,
+                    None,
+                    Some(&updated),
+                );
+                self.audit.record(&mut tx, event).await?;
+                if let Some(imported) = labels.get_mut(&label.id) {
+                    *imported = updated;
+                }
+            }
+
+            let existing_todos = self.todos_in(scope).await?;
+            let mut todo_ids = HashMap::new();
+            let mut imported = vec![];
+            for todo in &backup.todos {
+                let existing = existing_todos
+                    .iter()
+                    .find(|existing| existing.id == todo.id);
+                let (base, created) = match (existing, strategy) {
+                    (Some(_), ConflictStrategy::Skip) => {
+                        summary.todos.skipped += 1;
+                        todo_ids.insert(todo.id, todo.id);
+                        continue;
+                    }
+                    (Some(existing), ConflictStrategy::Overwrite) => {
+                        summary.todos.updated += 1;
+                        (existing.clone(), false)
+                    }
+                    _ => {
+                        summary.todos.created += 1;
+                        let base = self
+                            .todo
+                            .create_in(&mut tx, scope, CreateTodo::new(todo.text.clone(), vec![]))
+                            .await?;
+                        (base, true)
+                    }
+                };
+                todo_ids.insert(todo.id, base.id);
+                imported.push((todo, base, created));
+            }
+
+            // 親が先に作られているとは限らないので、全部作ってから関連付ける
+            for (todo, base, created) in imported {
+                let snapshot = Todo {
+                    text: todo.text.clone(),
+                    description: todo.description.clone(),
+                    status: todo.status,
+                    due_at: todo.due_at,
+                    starts_at: todo.starts_at,
+                    priority: todo.priority,
+                    parent_id: todo
+                        .parent_id
+                        .and_then(|parent_id| todo_ids.get(&parent_id).copied()),
+                    recurrence: todo.recurrence,
+                    archived_at: todo.archived_at,
+                    position: todo.position,
+                    is_pinned: todo.is_pinned,
+                    // 上書きした todo の作成日時は変えない
+                    created_at: match created {
+                        true => todo.created_at,
+                        false => base.created_at,
+                    },
+                    labels: todo
+                        .labels
+                        .iter()
+                        .filter_map(|label_id| labels.get(label_id).cloned())
+                        .collect(),
+                    ..base.clone()
+                };
+                let after = self.todo.restore_in(&mut tx, scope, snapshot).await?;
+                let (action, before) = match created {
+                    true => (AuditAction::Create, None),
+                    false => (AuditAction::Update, Some(&base)),
+                };
+                let event = import_event(
+                    scope,
+                    action,
+                    AuditEntity::Todo,
+                    after.id,
+                    before,
+                    Some(&after),
+                );
+                self.audit.record(&mut tx, event).await?;
+            }
+
+            tx.commit().await?;
+            Ok(summary)
+        }
+    }
+}
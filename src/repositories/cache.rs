@@ -0,0 +1,663 @@
+use super::{
+    label::{CreateLabel, Label, LabelDeleteMode, LabelQuery, LabelRepository, UpdateLabel},
+    todo::{
+        CreateTodo, DeletedTodos, DuplicatedTodo, LabelUsage, MoveTodo, RelabeledTodo, ShareTodo,
+        Todo, TodoActivity, TodoCursor, TodoFilter, TodoRepository, TodoRevision, TodoSearchHit,
+        TodoSelection, TodoShare, UpdateTodo,
+    },
+    Page, Scope, Tx,
+};
+use crate::health::HealthCheck;
+use crate::redis::RedisClient;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{future::Future, time::Duration};
+
+const KEY_PREFIX: &str = "rust_web";
+const TODOS: &str = "todos";
+const LABELS: &str = "labels";
+
+// 読み込みの結果を Redis に置く. キーには書き込みのたびに INCR する世代を入れるので、
+// 書き込んだ後は新しいキーを読みにいき、古い世代のキーは TTL で消える
+// Redis に届かなければキャッシュを使わずにそのまま DB を読む
+#[derive(Clone)]
+pub struct RepositoryCache {
+    client: RedisClient,
+    ttl: Duration,
+}
+
+impl RepositoryCache {
+    pub fn new(client: RedisClient, ttl: Duration) -> Self {
+        Self { client, ttl }
+    }
+
+    async fn cached<T, F>(&self, name: &str, key: String, load: F) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        let generation = match self.client.get(&generation_key(name)).await {
+            Ok(generation) => generation.map_or_else(String::new, |generation| {
+                String::from_utf8_lossy(&generation).into_owned()
+            }),
+            Err(e) => {
+                tracing::warn!("cannot read the {} cache: {}", name, e);
+                return load.await;
+            }
+        };
+        let key = format!("{}:{}:{}:{}", KEY_PREFIX, name, generation, key);
+        if let Ok(Some(value)) = self.client.get(&key).await {
+            if let Ok(value) = serde_json::from_slice(&value) {
+                return Ok(value);
+            }
+        }
+        let value = load.await?;
+        if let Ok(bytes) = serde_json::to_vec(&value) {
+            if let Err(e) = self.client.set_ex(&key, &bytes, self.ttl).await {
+                tracing::warn!("cannot write the {} cache: {}", name, e);
+            }
+        }
+        Ok(value)
+    }
+
+    // todo と label の世代をまとめて進める. CachedTodoRepository / CachedLabelRepository を通らずに
+    // todo や label を変える書き込み (外部キーの CASCADE / SET NULL や import) の後に呼ぶ
+    pub async fn invalidate_all(&self) {
+        self.invalidate(&[LABELS, TODOS]).await
+    }
+
+    // tx の中での書き込みなら、commit してから進める
+    pub fn invalidate_all_on_commit(&self, tx: &mut Tx) {
+        let cache = self.clone();
+        tx.after_commit(async move { cache.invalidate_all().await });
+    }
+
+    // 世代を進められなければ、古いキャッシュが TTL の間は返ることになる
+    async fn invalidate(&self, names: &[&str]) {
+        for name in names {
+            if let Err(e) = self.client.incr(&generation_key(name)).await {
+                tracing::warn!("cannot invalidate the {} cache: {}", name, e);
+            }
+        }
+    }
+}
+
+// 接続先の認証情報は出さない
+impl std::fmt::Debug for RepositoryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RepositoryCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+fn generation_key(name: &str) -> String {
+    format!("{}:{}:generation", KEY_PREFIX, name)
+}
+
+// 絞り込みの条件はそのままだとキーが長くなるのでハッシュにする
+fn digest(value: impl std::fmt::Debug) -> String {
+    format!("{:x}", Sha256::digest(format!("{:?}", value)))
+}
+
+// find, all, page の結果をキャッシュする. 書き込みは inner に任せてから todo のキャッシュを捨てる
+// 開始日時やスヌーズで一覧から外れる todo は、TTL の間は一覧に残ることがある
+// cache が None なら inner をそのまま呼ぶ
+#[derive(Clone)]
+pub struct CachedTodoRepository<T> {
+    inner: T,
+    cache: Option<RepositoryCache>,
+}
+
+impl<T: TodoRepository> CachedTodoRepository<T> {
+    pub fn new(inner: T, cache: Option<RepositoryCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    async fn invalidate<R>(&self, result: anyhow::Result<R>) -> anyhow::Result<R> {
+        if let (Some(cache), Ok(_)) = (&self.cache, &result) {
+            cache.invalidate(&[TODOS]).await;
+        }
+        result
+    }
+
+    // tx の中での書き込みは、commit してから捨てる
+    fn invalidate_on_commit<R>(&self, tx: &mut Tx, result: anyhow::Result<R>) -> anyhow::Result<R> {
+        if let (Some(cache), Ok(_)) = (&self.cache, &result) {
+            let cache = cache.clone();
+            tx.after_commit(async move { cache.invalidate(&[TODOS]).await });
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<T: TodoRepository> TodoRepository for CachedTodoRepository<T> {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        self.inner.begin().await
+    }
+
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateTodo,
+    ) -> anyhow::Result<Todo> {
+        let result = self.inner.create_in(tx, scope, payload).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn create_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let result = self.inner.create_many_in(tx, scope, payloads).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let Some(cache) = &self.cache else {
+            return self.inner.find(scope, id).await;
+        };
+        let key = format!("find:{:?}:{:?}:{}", scope.user_id, scope.workspace_id, id);
+        cache.cached(TODOS, key, self.inner.find(scope, id)).await
+    }
+
+    // 変更前の todo はキャッシュを通さずに読む
+    async fn find_for_update_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        self.inner.find_for_update_in(tx, scope, id).await
+    }
+
+    async fn count_pinned_in(&self, tx: &mut Tx, scope: Scope) -> anyhow::Result<i64> {
+        self.inner.count_pinned_in(tx, scope).await
+    }
+
+    async fn all(&self, scope: Scope, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let Some(cache) = &self.cache else {
+            return self.inner.all(scope, filter).await;
+        };
+        let key = format!("all:{}", digest((scope, filter)));
+        cache
+            .cached(TODOS, key, self.inner.all(scope, filter))
+            .await
+    }
+
+    async fn page(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)> {
+        let Some(cache) = &self.cache else {
+            return self.inner.page(scope, filter, page).await;
+        };
+        let key = format!("page:{}", digest((scope, filter, page)));
+        cache
+            .cached(TODOS, key, self.inner.page(scope, filter, page))
+            .await
+    }
+
+    async fn page_by_label(
+        &self,
+        scope: Scope,
+        label_id: i32,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)> {
+        self.inner.page_by_label(scope, label_id, page).await
+    }
+
+    async fn page_after(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        cursor: Option<&TodoCursor>,
+        limit: i64,
+    ) -> anyhow::Result<(Vec<Todo>, Option<TodoCursor>)> {
+        self.inner.page_after(scope, filter, cursor, limit).await
+    }
+
+    async fn search(&self, scope: Scope, query: &str) -> anyhow::Result<Vec<TodoSearchHit>> {
+        self.inner.search(scope, query).await
+    }
+
+    async fn all_unscoped(&self) -> anyhow::Result<Vec<Todo>> {
+        self.inner.all_unscoped().await
+    }
+
+    async fn all_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Todo>> {
+        self.inner.all_by_user(user_id).await
+    }
+
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let result = self.inner.update_in(tx, scope, id, payload).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn history(&self, scope: Scope, id: i32) -> anyhow::Result<Vec<TodoRevision>> {
+        self.inner.history(scope, id).await
+    }
+
+    async fn revision(
+        &self,
+        scope: Scope,
+        id: i32,
+        revision_id: i32,
+    ) -> anyhow::Result<TodoRevision> {
+        self.inner.revision(scope, id, revision_id).await
+    }
+
+    async fn activity(&self, scope: Scope, limit: usize) -> anyhow::Result<Vec<TodoActivity>> {
+        self.inner.activity(scope, limit).await
+    }
+
+    async fn label_usage(&self, scope: Scope) -> anyhow::Result<Vec<LabelUsage>> {
+        self.inner.label_usage(scope).await
+    }
+
+    async fn refresh_label_usage(&self) -> anyhow::Result<()> {
+        self.inner.refresh_label_usage().await
+    }
+
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let result = self.inner.delete_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn complete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let result = self.inner.complete_many_in(tx, scope, selection).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn delete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<DeletedTodos> {
+        let result = self.inner.delete_many_in(tx, scope, selection).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn relabel_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        ids: &[i32],
+        add: &[Label],
+        remove: &[i32],
+    ) -> anyhow::Result<Vec<RelabeledTodo>> {
+        let result = self
+            .inner
+            .relabel_many_in(tx, scope, ids, add, remove)
+            .await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn restore_in(&self, tx: &mut Tx, scope: Scope, snapshot: Todo) -> anyhow::Result<Todo> {
+        let result = self.inner.restore_in(tx, scope, snapshot).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn archive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let result = self.inner.archive_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn unarchive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let result = self.inner.unarchive_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn pin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let result = self.inner.pin_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn unpin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let result = self.inner.unpin_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn snooze_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Todo> {
+        let result = self.inner.snooze_in(tx, scope, id, until).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn assign_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        assignee_id: Option<i32>,
+    ) -> anyhow::Result<Todo> {
+        let result = self.inner.assign_in(tx, scope, id, assignee_id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn move_to_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        target: MoveTodo,
+    ) -> anyhow::Result<Todo> {
+        let result = self.inner.move_to_in(tx, scope, id, target).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn duplicate_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<DuplicatedTodo> {
+        let result = self.inner.duplicate_in(tx, scope, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn share(&self, scope: Scope, id: i32, payload: ShareTodo) -> anyhow::Result<TodoShare> {
+        self.invalidate(self.inner.share(scope, id, payload).await)
+            .await
+    }
+
+    async fn merge(&self, from_user_id: i32, into_user_id: i32) -> anyhow::Result<Vec<Todo>> {
+        self.invalidate(self.inner.merge(from_user_id, into_user_id).await)
+            .await
+    }
+
+    // scheduler から毎回呼ばれるので、作った todo がなければキャッシュはそのまま
+    async fn materialize_recurrences_in(
+        &self,
+        tx: &mut Tx,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let created = self.inner.materialize_recurrences_in(tx, now).await?;
+        if created.is_empty() {
+            return Ok(created);
+        }
+        self.invalidate_on_commit(tx, Ok(created))
+    }
+
+    async fn purge_archived_in(
+        &self,
+        tx: &mut Tx,
+        before: DateTime<Utc>,
+    ) -> anyhow::Result<DeletedTodos> {
+        let purged = self.inner.purge_archived_in(tx, before).await?;
+        if purged.todos.is_empty() {
+            return Ok(purged);
+        }
+        self.invalidate_on_commit(tx, Ok(purged))
+    }
+}
+
+#[async_trait]
+impl<T: TodoRepository + HealthCheck> HealthCheck for CachedTodoRepository<T> {
+    async fn check(&self) -> anyhow::Result<()> {
+        self.inner.check().await
+    }
+}
+
+// find, find_by_user, all, page の結果をキャッシュする
+// todo にはラベルの名前や色も入っているので、書き込んだら todo のキャッシュも捨てる
+#[derive(Clone)]
+pub struct CachedLabelRepository<L> {
+    inner: L,
+    cache: Option<RepositoryCache>,
+}
+
+impl<L: LabelRepository> CachedLabelRepository<L> {
+    pub fn new(inner: L, cache: Option<RepositoryCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    async fn invalidate<R>(&self, result: anyhow::Result<R>) -> anyhow::Result<R> {
+        if let (Some(cache), Ok(_)) = (&self.cache, &result) {
+            cache.invalidate(&[LABELS, TODOS]).await;
+        }
+        result
+    }
+
+    fn invalidate_on_commit<R>(&self, tx: &mut Tx, result: anyhow::Result<R>) -> anyhow::Result<R> {
+        if let (Some(cache), Ok(_)) = (&self.cache, &result) {
+            let cache = cache.clone();
+            tx.after_commit(async move { cache.invalidate(&[LABELS, TODOS]).await });
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<L: LabelRepository> LabelRepository for CachedLabelRepository<L> {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        self.inner.begin().await
+    }
+
+    async fn create_in(&self, tx: &mut Tx, payload: CreateLabel) -> anyhow::Result<Label> {
+        let result = self.inner.create_in(tx, payload).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn create_or_get_in(
+        &self,
+        tx: &mut Tx,
+        payload: CreateLabel,
+    ) -> anyhow::Result<(Label, bool)> {
+        let (label, created) = self.inner.create_or_get_in(tx, payload).await?;
+        if !created {
+            return Ok((label, created));
+        }
+        self.invalidate_on_commit(tx, Ok((label, created)))
+    }
+
+    async fn find(&self, id: i32) -> anyhow::Result<Label> {
+        let Some(cache) = &self.cache else {
+            return self.inner.find(id).await;
+        };
+        cache
+            .cached(LABELS, format!("find:{}", id), self.inner.find(id))
+            .await
+    }
+
+    async fn find_for_update_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        self.inner.find_for_update_in(tx, id).await
+    }
+
+    async fn find_by_user(&self, id: i32) -> anyhow::Result<Vec<Label>> {
+        let Some(cache) = &self.cache else {
+            return self.inner.find_by_user(id).await;
+        };
+        let key = format!("find_by_user:{}", id);
+        cache.cached(LABELS, key, self.inner.find_by_user(id)).await
+    }
+
+    async fn all(&self, scope: Scope, query: &LabelQuery) -> anyhow::Result<Vec<Label>> {
+        let Some(cache) = &self.cache else {
+            return self.inner.all(scope, query).await;
+        };
+        let key = format!("all:{}", digest((scope, query)));
+        cache
+            .cached(LABELS, key, self.inner.all(scope, query))
+            .await
+    }
+
+    async fn page(
+        &self,
+        scope: Scope,
+        query: &LabelQuery,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Label>, i64)> {
+        let Some(cache) = &self.cache else {
+            return self.inner.page(scope, query, page).await;
+        };
+        let key = format!("page:{}", digest((scope, query, page)));
+        cache
+            .cached(LABELS, key, self.inner.page(scope, query, page))
+            .await
+    }
+
+    async fn update_in(&self, tx: &mut Tx, id: i32, payload: UpdateLabel) -> anyhow::Result<Label> {
+        let result = self.inner.update_in(tx, id, payload).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn delete_in(&self, tx: &mut Tx, id: i32, mode: LabelDeleteMode) -> anyhow::Result<()> {
+        let result = self.inner.delete_in(tx, id, mode).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn reorder(
+        &self,
+        workspace_id: Option<i32>,
+        user_id: i32,
+        ids: &[i32],
+    ) -> anyhow::Result<Vec<Label>> {
+        self.invalidate(self.inner.reorder(workspace_id, user_id, ids).await)
+            .await
+    }
+
+    async fn archive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        let result = self.inner.archive_in(tx, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+
+    async fn unarchive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        let result = self.inner.unarchive_in(tx, id).await;
+        self.invalidate_on_commit(tx, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::redis::test_server;
+    use crate::repositories::{
+        label::memory::LabelRepositoryForMemory, todo::memory::TodoRepositoryForMemory,
+    };
+
+    #[tokio::test]
+    async fn should_serve_reads_from_cache_until_written() {
+        let client = RedisClient::open(&test_server::spawn().await).unwrap();
+        let cache = RepositoryCache::new(client, Duration::from_secs(60));
+        let inner = TodoRepositoryForMemory::new();
+        let label_inner = LabelRepositoryForMemory::with_todos(inner.clone());
+        let todo = CachedTodoRepository::new(inner.clone(), Some(cache.clone()));
+        let label = CachedLabelRepository::new(label_inner.clone(), Some(cache.clone()));
+        let scope = Scope::personal(1);
+
+        let created = todo
+            .create(scope, CreateTodo::new("cached".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(todo.find(scope, created.id).await.unwrap(), created);
+
+        // a write that skips the cache is not seen until the cache is invalidated
+        let payload: UpdateTodo =
+            serde_json::from_value(serde_json::json!({ "text": "changed" })).unwrap();
+        let updated = inner.update(scope, created.id, payload).await.unwrap();
+        assert_eq!(todo.find(scope, created.id).await.unwrap(), created);
+        assert_eq!(
+            todo.all(scope, &TodoFilter::default()).await.unwrap(),
+            vec![updated.clone()]
+        );
+        todo.pin(scope, created.id).await.unwrap();
+        let found = todo.find(scope, created.id).await.unwrap();
+        assert_eq!(found.text, "changed");
+        assert!(found.is_pinned);
+
+        // same for labels
+        let work = label
+            .create(CreateLabel::new("work".to_string(), 1))
+            .await
+            .unwrap();
+        let labels = label
+            .all(Scope::personal(1), &LabelQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(labels, vec![work.clone()]);
+        label_inner
+            .delete(work.id, LabelDeleteMode::Detach)
+            .await
+            .unwrap();
+        assert_eq!(
+            label
+                .all(Scope::personal(1), &LabelQuery::default())
+                .await
+                .unwrap(),
+            labels
+        );
+        label
+            .create(CreateLabel::new("home".to_string(), 1))
+            .await
+            .unwrap();
+        let labels = label
+            .all(Scope::personal(1), &LabelQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            labels
+                .iter()
+                .map(|label| label.name.as_str())
+                .collect::<Vec<_>>(),
+            ["home"]
+        );
+
+        // writes that cascade outside the cached repositories bump both generations
+        label_inner
+            .create(CreateLabel::new("cascaded".to_string(), 1))
+            .await
+            .unwrap();
+        inner.delete(scope, created.id).await.unwrap();
+        assert_eq!(
+            label
+                .all(Scope::personal(1), &LabelQuery::default())
+                .await
+                .unwrap(),
+            labels
+        );
+        cache.invalidate_all().await;
+        assert_eq!(
+            label
+                .all(Scope::personal(1), &LabelQuery::default())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(todo.find(scope, created.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_read_through_when_redis_is_down() {
+        let client = RedisClient::open("redis://127.0.0.1:1").unwrap();
+        let cache = RepositoryCache::new(client, Duration::from_secs(60));
+        let todo = CachedTodoRepository::new(TodoRepositoryForMemory::new(), Some(cache));
+        let scope = Scope::personal(1);
+        let created = todo
+            .create(scope, CreateTodo::new("uncached".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(todo.find(scope, created.id).await.unwrap(), created);
+    }
+}
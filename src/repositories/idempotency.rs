@@ -0,0 +1,382 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+
+// key はユーザーごとに別. 他のユーザーが同じ key を使っても衝突しない
+#[async_trait]
+pub trait IdempotencyRepository: std::marker::Send + std::marker::Sync + 'static {
+    // key を処理中として押さえる. 期限内の記録がすでにあれば押さえずにその記録を返す
+    async fn reserve(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<IdempotencyKey>>;
+    // 押さえた key に処理したレスポンスを保存する
+    async fn complete(
+        &self,
+        user_id: i32,
+        key: &str,
+        status: u16,
+        body: Value,
+    ) -> anyhow::Result<()>;
+    // 処理に失敗したら key を外し、再送されたリクエストを最初から処理できるようにする
+    async fn release(&self, user_id: i32, key: &str) -> anyhow::Result<()>;
+    // 期限切れの記録を消し、消した件数を返す
+    async fn purge_expired(&self, now: DateTime<Utc>) -> anyhow::Result<u64>;
+}
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct IdempotencyKey {
+    pub user_id: i32,
+    pub key: String,
+    // リクエストの内容のハッシュ. 同じ key で内容の違うリクエストは受け付けない
+    pub fingerprint: String,
+    // 最初のリクエストを処理中なら None
+    pub response_status: Option<i32>,
+    pub response_body: Option<Value>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdempotencyRepositoryForDb {
+    pool: PgPool,
+}
+
+impl IdempotencyRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for IdempotencyRepositoryForDb {
+    #[tracing::instrument(name = "IdempotencyRepository::reserve", skip_all)]
+    async fn reserve(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<IdempotencyKey>> {
+        // 期限切れの記録は上書きして押さえ直す. 押さえられなかったときだけ既存の記録を返す
+        let existing = sqlx::query_as::<_, IdempotencyKey>(
+            r#"
+            WITH reserved AS (
+                INSERT INTO idempotency_keys (user_id, key, fingerprint, expires_at)
+                VALUES ( $1, $2, $3, $4 )
+                ON CONFLICT (user_id, key) DO UPDATE
+                SET fingerprint = EXCLUDED.fingerprint,
+                    response_status = NULL,
+                    response_body = NULL,
+                    created_at = now(),
+                    expires_at = EXCLUDED.expires_at
+                WHERE idempotency_keys.expires_at <= now()
+                RETURNING user_id
+            )
+            SELECT * FROM idempotency_keys
+            WHERE user_id = $1 AND key = $2 AND NOT EXISTS (SELECT 1 FROM reserved)
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(fingerprint)
+        .bind(expires_at)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(existing)
+    }
+
+    #[tracing::instrument(name = "IdempotencyRepository::complete", skip_all)]
+    async fn complete(
+        &self,
+        user_id: i32,
+        key: &str,
+        status: u16,
+        body: Value,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE idempotency_keys SET response_status = $3, response_body = $4
+            WHERE user_id = $1 AND key = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(i32::from(status))
+        .bind(body)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "IdempotencyRepository::release", skip_all)]
+    async fn release(&self, user_id: i32, key: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM idempotency_keys
+            WHERE user_id = $1 AND key = $2 AND response_status IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "IdempotencyRepository::purge_expired", skip_all)]
+    async fn purge_expired(&self, now: DateTime<Utc>) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use dotenv::dotenv;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = IdempotencyRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'idempotency_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        // 前回のテストの記録を消しておく
+        sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up idempotency keys");
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        // reserve
+        let existing = repo
+            .reserve(user_id, "key", "fingerprint", expires_at)
+            .await
+            .expect("[reserve] returned Err");
+        assert_eq!(existing, None);
+        let existing = repo
+            .reserve(user_id, "key", "other", expires_at)
+            .await
+            .expect("[reserve] returned Err")
+            .expect("reserved twice");
+        assert_eq!(existing.fingerprint, "fingerprint");
+        assert_eq!(existing.response_status, None);
+
+        // complete した記録は release で消えない
+        repo.complete(user_id, "key", 201, json!({ "id": 1 }))
+            .await
+            .expect("[complete] returned Err");
+        repo.release(user_id, "key")
+            .await
+            .expect("[release] returned Err");
+        let existing = repo
+            .reserve(user_id, "key", "fingerprint", expires_at)
+            .await
+            .unwrap()
+            .expect("completed key was released");
+        assert_eq!(existing.response_status, Some(201));
+        assert_eq!(existing.response_body, Some(json!({ "id": 1 })));
+
+        // 処理中の key は release すれば押さえ直せる
+        assert_eq!(
+            repo.reserve(user_id, "pending", "fingerprint", expires_at)
+                .await
+                .unwrap(),
+            None
+        );
+        repo.release(user_id, "pending").await.unwrap();
+        assert_eq!(
+            repo.reserve(user_id, "pending", "fingerprint", expires_at)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // 期限切れの記録は上書きできる
+        assert_eq!(
+            repo.reserve(user_id, "expired", "old", Utc::now() - Duration::seconds(1))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            repo.reserve(user_id, "expired", "new", expires_at)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // purge_expired
+        repo.reserve(user_id, "purged", "old", Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        let purged = repo
+            .purge_expired(Utc::now())
+            .await
+            .expect("[purge_expired] returned Err");
+        assert!(purged >= 1);
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM idempotency_keys WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(count, 3);
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub struct IdempotencyRepositoryForMemory {
+        store: Arc<RwLock<Vec<IdempotencyKey>>>,
+    }
+
+    impl IdempotencyRepositoryForMemory {
+        pub fn new() -> Self {
+            IdempotencyRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IdempotencyRepository for IdempotencyRepositoryForMemory {
+        async fn reserve(
+            &self,
+            user_id: i32,
+            key: &str,
+            fingerprint: &str,
+            expires_at: DateTime<Utc>,
+        ) -> anyhow::Result<Option<IdempotencyKey>> {
+            let mut store = self.store.write().unwrap();
+            let now = Utc::now();
+            if let Some(existing) = store
+                .iter()
+                .find(|record| record.user_id == user_id && record.key == key)
+            {
+                if existing.expires_at > now {
+                    return Ok(Some(existing.clone()));
+                }
+            }
+            store.retain(|record| !(record.user_id == user_id && record.key == key));
+            store.push(IdempotencyKey {
+                user_id,
+                key: key.to_string(),
+                fingerprint: fingerprint.to_string(),
+                response_status: None,
+                response_body: None,
+                created_at: now,
+                expires_at,
+            });
+            Ok(None)
+        }
+
+        async fn complete(
+            &self,
+            user_id: i32,
+            key: &str,
+            status: u16,
+            body: Value,
+        ) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            if let Some(record) = store
+                .iter_mut()
+                .find(|record| record.user_id == user_id && record.key == key)
+            {
+                record.response_status = Some(i32::from(status));
+                record.response_body = Some(body);
+            }
+            Ok(())
+        }
+
+        async fn release(&self, user_id: i32, key: &str) -> anyhow::Result<()> {
+            self.store.write().unwrap().retain(|record| {
+                !(record.user_id == user_id
+                    && record.key == key
+                    && record.response_status.is_none())
+            });
+            Ok(())
+        }
+
+        async fn purge_expired(&self, now: DateTime<Utc>) -> anyhow::Result<u64> {
+            let mut store = self.store.write().unwrap();
+            let before = store.len();
+            store.retain(|record| record.expires_at > now);
+            Ok((before - store.len()) as u64)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use chrono::Duration;
+        use serde_json::json;
+
+        #[tokio::test]
+        async fn idempotency_scenario() {
+            let repo = IdempotencyRepositoryForMemory::new();
+            let expires_at = Utc::now() + Duration::hours(1);
+
+            assert_eq!(repo.reserve(1, "key", "a", expires_at).await.unwrap(), None);
+            // key はユーザーごとに別
+            assert_eq!(repo.reserve(2, "key", "a", expires_at).await.unwrap(), None);
+            let existing = repo.reserve(1, "key", "b", expires_at).await.unwrap();
+            assert_eq!(
+                existing.map(|record| record.fingerprint),
+                Some("a".to_string())
+            );
+
+            repo.complete(1, "key", 201, json!({ "id": 1 }))
+                .await
+                .unwrap();
+            repo.release(1, "key").await.unwrap();
+            repo.release(2, "key").await.unwrap();
+            let existing = repo.reserve(1, "key", "a", expires_at).await.unwrap();
+            assert_eq!(
+                existing.and_then(|record| record.response_status),
+                Some(201)
+            );
+            assert_eq!(repo.reserve(2, "key", "a", expires_at).await.unwrap(), None);
+
+            assert_eq!(repo.purge_expired(expires_at).await.unwrap(), 2);
+            assert_eq!(repo.reserve(1, "key", "b", expires_at).await.unwrap(), None);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use super::RepositoryError;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use validator::Validate;
+
+#[async_trait]
+pub trait InvitationRepository: std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, payload: NewInvitation) -> anyhow::Result<Invitation>;
+    // 未使用かつ期限内の招待だけを返す. それ以外は RepositoryError::Forbidden
+    async fn find_by_token(&self, token_hash: &str) -> anyhow::Result<Invitation>;
+    // 招待を使用済みにする. 同じ招待で二度メンバーにはなれない
+    async fn accept(&self, token_hash: &str) -> anyhow::Result<Invitation>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Invitation {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub email: String,
+    pub invited_by: i32,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+}
+
+impl Invitation {
+    fn is_usable(&self) -> bool {
+        self.accepted_at.is_none() && self.expires_at > Utc::now()
+    }
+}
+
+// クライアントから受け取るのは招待先のメールアドレスだけ
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateInvitation {
+    #[validate(email(message = "Invalid email"))]
+    pub email: String,
+}
+
+// トークンそのものは handler 側で生成し、ハッシュだけを渡す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewInvitation {
+    pub workspace_id: i32,
+    pub email: String,
+    pub invited_by: i32,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvitationRepositoryForDb {
+    pool: PgPool,
+}
+
+impl InvitationRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl InvitationRepository for InvitationRepositoryForDb {
+    #[tracing::instrument(name = "InvitationRepository::create", skip_all)]
+    async fn create(&self, payload: NewInvitation) -> anyhow::Result<Invitation> {
+        let invitation = sqlx::query_as::<_, Invitation>(
+            r#"
+            INSERT INTO invitations (workspace_id, email, invited_by, token_hash, expires_at)
+            VALUES ( $1, $2, $3, $4, $5 )
+            RETURNING id, workspace_id, email, invited_by, expires_at, accepted_at
+            "#,
+        )
+        .bind(payload.workspace_id)
+        .bind(payload.email)
+        .bind(payload.invited_by)
+        .bind(payload.token_hash)
+        .bind(payload.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    #[tracing::instrument(name = "InvitationRepository::find_by_token", skip_all)]
+    async fn find_by_token(&self, token_hash: &str) -> anyhow::Result<Invitation> {
+        let invitation = sqlx::query_as::<_, Invitation>(
+            r#"
+            SELECT id, workspace_id, email, invited_by, expires_at, accepted_at
+            FROM invitations
+            WHERE token_hash = $1
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(0))?;
+        if !invitation.is_usable() {
+            return Err(RepositoryError::Forbidden(invitation.id).into());
+        }
+
+        Ok(invitation)
+    }
+
+    #[tracing::instrument(name = "InvitationRepository::accept", skip_all)]
+    async fn accept(&self, token_hash: &str) -> anyhow::Result<Invitation> {
+        // 同時に accept された場合も 1 回しか成功しないよう、条件付きの UPDATE で使用済みにする
+        let accepted = sqlx::query_as::<_, Invitation>(
+            r#"
+            UPDATE invitations SET accepted_at = now()
+            WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+            RETURNING id, workspace_id, email, invited_by, expires_at, accepted_at
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match accepted {
+            Some(invitation) => Ok(invitation),
+            // 存在しないのか使えないのかを区別する
+            None => self.find_by_token(token_hash).await,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = InvitationRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'invitation_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        let (workspace_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO workspaces (name, owner_id)
+            VALUES ( 'invitation_crud_scenario', $1 )
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare workspace data.");
+        // ハッシュは unique なので前回のテストのデータを消しておく
+        sqlx::query("DELETE FROM invitations WHERE token_hash LIKE 'db-invitation-%'")
+            .execute(&pool)
+            .await
+            .expect("failed to clean up invitations");
+        let new_invitation = |token_hash: &str, expires_at: DateTime<Utc>| NewInvitation {
+            workspace_id,
+            email: "invitee@example.com".to_string(),
+            invited_by: user_id,
+            token_hash: token_hash.to_string(),
+            expires_at,
+        };
+
+        // create
+        let invitation = repo
+            .create(new_invitation(
+                "db-invitation-1",
+                Utc::now() + Duration::days(1),
+            ))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(invitation.workspace_id, workspace_id);
+
+        // find_by_token
+        let found = repo
+            .find_by_token("db-invitation-1")
+            .await
+            .expect("[find_by_token] returned Err");
+        assert_eq!(found, invitation);
+
+        // accept は一度だけ
+        let accepted = repo
+            .accept("db-invitation-1")
+            .await
+            .expect("[accept] returned Err");
+        assert!(accepted.accepted_at.is_some());
+        assert!(repo.accept("db-invitation-1").await.is_err());
+
+        // 期限切れ
+        repo.create(new_invitation(
+            "db-invitation-expired",
+            Utc::now() - Duration::days(1),
+        ))
+        .await
+        .expect("[create] returned Err");
+        assert!(repo.accept("db-invitation-expired").await.is_err());
+        assert!(repo.accept("db-invitation-unknown").await.is_err());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    use super::*;
+
+    // token_hash -> Invitation
+    type InvitationDatas = HashMap<String, Invitation>;
+
+    #[derive(Debug, Clone)]
+    pub struct InvitationRepositoryForMemory {
+        store: Arc<RwLock<InvitationDatas>>,
+    }
+
+    impl InvitationRepositoryForMemory {
+        pub fn new() -> Self {
+            InvitationRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, InvitationDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, InvitationDatas> {
+            self.store.read().unwrap()
+        }
+    }
+
+    fn usable(invitation: Option<&Invitation>) -> anyhow::Result<Invitation> {
+        let invitation = invitation.ok_or(RepositoryError::NotFound(0))?;
+        if !invitation.is_usable() {
+            return Err(RepositoryError::Forbidden(invitation.id).into());
+        }
+        Ok(invitation.clone())
+    }
+
+    #[async_trait]
+    impl InvitationRepository for InvitationRepositoryForMemory {
+        async fn create(&self, payload: NewInvitation) -> anyhow::Result<Invitation> {
+            let mut store = self.write_store_ref();
+            let invitation = Invitation {
+                id: (store.len() + 1) as i32,
+                workspace_id: payload.workspace_id,
+                email: payload.email,
+                invited_by: payload.invited_by,
+                expires_at: payload.expires_at,
+                accepted_at: None,
+            };
+            store.insert(payload.token_hash, invitation.clone());
+            Ok(invitation)
+        }
+
+        async fn find_by_token(&self, token_hash: &str) -> anyhow::Result<Invitation> {
+            usable(self.read_store_ref().get(token_hash))
+        }
+
+        async fn accept(&self, token_hash: &str) -> anyhow::Result<Invitation> {
+            let mut store = self.write_store_ref();
+            usable(store.get(token_hash))?;
+            let invitation = store.get_mut(token_hash).unwrap();
+            invitation.accepted_at = Some(Utc::now());
+            Ok(invitation.clone())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use chrono::Duration;
+
+        fn new_invitation(token_hash: &str, expires_at: DateTime<Utc>) -> NewInvitation {
+            NewInvitation {
+                workspace_id: 1,
+                email: "invitee@example.com".to_string(),
+                invited_by: 1,
+                token_hash: token_hash.to_string(),
+                expires_at,
+            }
+        }
+
+        #[tokio::test]
+        async fn invitation_scenario() {
+            let repo = InvitationRepositoryForMemory::new();
+
+            // create
+            let invitation = repo
+                .create(new_invitation("token", Utc::now() + Duration::days(1)))
+                .await
+                .expect("failed create invitation");
+            assert_eq!(repo.find_by_token("token").await.unwrap(), invitation);
+
+            // accept は一度だけ
+            let accepted = repo
+                .accept("token")
+                .await
+                .expect("failed accept invitation");
+            assert!(accepted.accepted_at.is_some());
+            assert!(repo.accept("token").await.is_err());
+            assert!(repo.find_by_token("token").await.is_err());
+
+            // 期限切れ・存在しないトークン
+            repo.create(new_invitation("expired", Utc::now() - Duration::days(1)))
+                .await
+                .expect("failed create invitation");
+            assert!(repo.accept("expired").await.is_err());
+            assert!(repo.accept("unknown").await.is_err());
+        }
+    }
+}
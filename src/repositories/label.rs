@@ -1,23 +1,202 @@
-use super::RepositoryError;
+use super::{escape_like, Page, RepositoryError, Scope, SortOrder, Tx};
 use axum::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use validator::Validate;
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use validator::{Validate, ValidationError};
 
 #[async_trait]
-pub trait LabelRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label>;
+pub trait LabelRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 書き込みをまとめるトランザクションを始める. *_in は service が始めた tx の中で書き込み、監査ログも同じ tx で残す
+    // *_in のない方はテスト用で、その場で tx を始めて commit する
+    async fn begin(&self) -> anyhow::Result<Tx>;
+    // 名前は一覧 (個人のラベルは user ごと、workspace のラベルは workspace ごと) の中で大文字と小文字を区別せず一意
+    // 重なれば既存のラベルの id で RepositoryError::Duplicate を返す
+    async fn create_in(&self, tx: &mut Tx, payload: CreateLabel) -> anyhow::Result<Label>;
+    #[cfg(test)]
+    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
+        let mut tx = self.begin().await?;
+        let result = self.create_in(&mut tx, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 同じ名前のラベルがあればエラーにせずにそれを返す. 作成したかどうかも返す
+    async fn create_or_get_in(
+        &self,
+        tx: &mut Tx,
+        payload: CreateLabel,
+    ) -> anyhow::Result<(Label, bool)>;
+    #[cfg(test)]
+    async fn create_or_get(&self, payload: CreateLabel) -> anyhow::Result<(Label, bool)> {
+        let mut tx = self.begin().await?;
+        let result = self.create_or_get_in(&mut tx, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
     async fn find(&self, id: i32) -> anyhow::Result<Label>;
+    // 変更前のラベルを tx の中で primary から読み、commit まで行をロックする
+    async fn find_for_update_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label>;
     async fn find_by_user(&self, id: i32) -> anyhow::Result<Vec<Label>>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn update(&self, id: i32, payload: UpdateLabel) -> anyhow::Result<Label>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    // workspace_id が None なら scope の user の個人のラベル、Some ならその workspace のラベルを返す
+    async fn all(&self, scope: Scope, query: &LabelQuery) -> anyhow::Result<Vec<Label>>;
+    // all のうち page の範囲と、範囲を切り出す前の件数を返す
+    async fn page(
+        &self,
+        scope: Scope,
+        query: &LabelQuery,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Label>, i64)>;
+    // 親を自分自身や子孫にする場合と、親が別の一覧 (個人 or workspace) のラベルの場合は RepositoryError::Invalid を返す
+    // 名前が重なる場合は create と同じく RepositoryError::Duplicate を返す
+    async fn update_in(&self, tx: &mut Tx, id: i32, payload: UpdateLabel) -> anyhow::Result<Label>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn update(&self, id: i32, payload: UpdateLabel) -> anyhow::Result<Label> {
+        let mut tx = self.begin().await?;
+        let result = self.update_in(&mut tx, id, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 子のラベルはトップレベルのラベルとして残す. テンプレートからは外す
+    // todo に付いている場合、Detach なら外してから消し、Forbid なら RepositoryError::InUse を返す
+    async fn delete_in(&self, tx: &mut Tx, id: i32, mode: LabelDeleteMode) -> anyhow::Result<()>;
+    #[cfg(test)]
+    async fn delete(&self, id: i32, mode: LabelDeleteMode) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        let result = self.delete_in(&mut tx, id, mode).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // workspace_id の一覧 (None なら user_id の個人のラベル) を ids の順に並べ替え、並べ替えた後の一覧を返す
+    // ids が一覧のラベルをちょうど 1 回ずつ含んでいなければ RepositoryError::Invalid を返す
+    async fn reorder(
+        &self,
+        workspace_id: Option<i32>,
+        user_id: i32,
+        ids: &[i32],
+    ) -> anyhow::Result<Vec<Label>>;
+    // 一覧と選択肢から外す. todo に付いたラベルはそのまま残す
+    // アーカイブ済みのラベルをアーカイブしても日時は変えない
+    async fn archive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label>;
+    #[cfg(test)]
+    async fn archive(&self, id: i32) -> anyhow::Result<Label> {
+        let mut tx = self.begin().await?;
+        let result = self.archive_in(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn unarchive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label>;
+    #[cfg(test)]
+    async fn unarchive(&self, id: i32) -> anyhow::Result<Label> {
+        let mut tx = self.begin().await?;
+        let result = self.unarchive_in(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 }
 
+// 色を省略したラベルの色. migration の DEFAULT と同じ値
+pub const DEFAULT_COLOR: &str = "#4f86f7";
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
 pub struct Label {
     pub id: i32,
     pub name: String,
+    // 所有者のいないラベル (user_id 導入前に作られたもの) は None
+    pub user_id: Option<i32>,
+    // workspace に属さない個人のラベルは None
+    pub workspace_id: Option<i32>,
+    // #rrggbb
+    pub color: String,
+    // 入れ子にしたラベルなら親のラベルの id
+    pub parent_id: Option<i32>,
+    // 一覧 (個人 or workspace) の中での並び順. 新しいラベルは末尾
+    pub position: i32,
+    // アーカイブしていなければ None
+    pub archived_at: Option<DateTime<Utc>>,
+    // 絵文字 1 文字かアイコン名. 省略したら色だけで表示する
+    pub icon: Option<String>,
+}
+
+// GET /labels/tree の要素
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelNode {
+    #[serde(flatten)]
+    pub label: Label,
+    pub children: Vec<LabelNode>,
+}
+
+// 並び順を保ったまま、兄弟の中で同じ順に並ぶ木にする. 親が labels の中にないラベルは根にする
+pub fn build_tree(labels: Vec<Label>) -> Vec<LabelNode> {
+    let ids: Vec<i32> = labels.iter().map(|label| label.id).collect();
+    let mut children: HashMap<Option<i32>, Vec<Label>> = HashMap::new();
+    for label in labels {
+        let parent_id = label.parent_id.filter(|parent_id| ids.contains(parent_id));
+        children.entry(parent_id).or_default().push(label);
+    }
+    fn nodes(
+        parent_id: Option<i32>,
+        children: &mut HashMap<Option<i32>, Vec<Label>>,
+    ) -> Vec<LabelNode> {
+        children
+            .remove(&parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|label| LabelNode {
+                children: nodes(Some(label.id), children),
+                label,
+            })
+            .collect()
+    }
+    nodes(None, &mut children)
+}
+
+// GET /labels のクエリパラメータ
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct LabelQuery {
+    // 省略したら手動で並び替えた順
+    pub sort: Option<LabelSort>,
+    // 省略したら作成日時は古い順、名前は辞書順
+    pub order: Option<SortOrder>,
+    // true ならアーカイブしたラベルだけ、省略したらアーカイブしていないラベルだけ
+    pub archived: Option<bool>,
+    // 名前に含む文字列. 大文字と小文字は区別しない
+    pub q: Option<String>,
+}
+
+// 同じ値のラベルは order と同じ向きの id 順に並べる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelSort {
+    // ラベルには作成日時の列がないので、作成順に振られる id で並べる
+    CreatedAt,
+    Name,
+    // PATCH /labels/reorder で並び替えた順
+    Position,
+}
+
+// DELETE /labels/:id で todo に付いているラベルをどう扱うか
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelDeleteMode {
+    // todo から外して消す
+    #[default]
+    Detach,
+    // 消さずに 409 を返す
+    Forbid,
+}
+
+impl LabelQuery {
+    // ORDER BY 句. 列は LabelSort から選ぶので、クエリパラメータの文字列がそのまま SQL に入ることはない
+    fn order_by(&self) -> String {
+        let order = self.order.unwrap_or(SortOrder::Asc).as_sql();
+        match self.sort {
+            Some(LabelSort::Name) => format!("name {}, id {}", order, order),
+            Some(LabelSort::CreatedAt) => format!("id {}", order),
+            Some(LabelSort::Position) | None => format!("position {}, id {}", order, order),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
@@ -25,6 +204,30 @@ pub struct CreateLabel {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over name length"))]
     name: String,
+    // 省略したら DEFAULT_COLOR
+    #[serde(default)]
+    #[validate(custom = "validate_color")]
+    pub color: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_icon")]
+    pub icon: Option<String>,
+    // 同じ一覧 (個人 or workspace) のラベルの下に入れる
+    #[serde(default)]
+    #[validate(range(min = 1, message = "Invalid label id"))]
+    pub parent_id: Option<i32>,
+    // クライアントからは受け取らず、handler で認証済みユーザーの id をセットする
+    #[serde(skip_deserializing)]
+    pub user_id: i32,
+    // アクティブな workspace を handler でセットする
+    #[serde(skip_deserializing)]
+    pub workspace_id: Option<i32>,
+}
+
+// PATCH /labels/reorder のボディ. 一覧の全てのラベルの id を並べたい順に入れる
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct ReorderLabels {
+    #[validate(length(max = 1000, message = "Over ids length"))]
+    pub ids: Vec<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
@@ -32,88 +235,368 @@ pub struct UpdateLabel {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over name length"))]
     name: Option<String>,
+    #[validate(custom = "validate_color")]
+    color: Option<String>,
+    // 省略したら変更しない. null ならアイコンを外す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[validate(custom = "validate_icon")]
+    icon: Option<Option<String>>,
+    // 省略したら変更しない. null ならトップレベルのラベルに戻す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    parent_id: Option<Option<i32>>,
+}
+
+// null と省略を区別するため、値があれば null でも Some で包む
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// #rrggbb の形だけを受け付ける. 大文字と小文字は区別しない
+pub(super) fn validate_color(color: &str) -> Result<(), ValidationError> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    valid
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Invalid color"))
 }
 
+// 英小文字で始まり英小文字・数字・ハイフンが続くアイコン名 (shopping-cart など) か、絵文字 1 文字
+// 絵文字は肌の色や ZWJ でつないだもの、国旗のように複数のコードポイントからなるものも 1 文字として受け付ける
+pub(super) fn validate_icon(icon: &str) -> Result<(), ValidationError> {
+    let is_name = icon.len() <= 50
+        && icon.starts_with(|c: char| c.is_ascii_lowercase())
+        && icon
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    let is_emoji = icon.chars().count() <= 10
+        && icon.chars().any(is_emoji_char)
+        && icon.chars().all(|c| is_emoji_char(c) || is_emoji_joiner(c));
+    (is_name || is_emoji)
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Invalid icon"))
+}
+
+fn is_emoji_char(c: char) -> bool {
+    matches!(c,
+        '\u{1F000}'..='\u{1FAFF}' // 絵文字・国旗の地域指示子・肌の色
+        | '\u{2300}'..='\u{23FF}' // ⌚ など
+        | '\u{2600}'..='\u{27BF}' // ☀ ✅ など
+        | '\u{2B00}'..='\u{2BFF}' // ⭐ など
+    )
+}
+
+// 単独では絵文字にならず、前後の絵文字とつなげて使うコードポイント
+fn is_emoji_joiner(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200D}' // ZWJ
+        | '\u{FE0F}' // 絵文字で表示する異体字セレクタ
+        | '\u{E0020}'..='\u{E007F}' // サブディビジョンの旗のタグ
+    )
+}
+
+// LabelQuery で絞り込んだ一覧. $1: workspace_id, $2: user_id, $3: archived, $4: q
+const FILTERED_LABELS: &str = r#"
+    SELECT id, name, user_id, workspace_id, color, parent_id, position, archived_at, icon FROM labels
+    WHERE workspace_id IS NOT DISTINCT FROM $1 AND ($1::INTEGER IS NOT NULL OR user_id = $2)
+        AND (archived_at IS NOT NULL) = $3
+        AND ($4::TEXT IS NULL OR name ILIKE '%' || $4 || '%')
+"#;
+
+// 新しいラベルは一覧 (個人 or workspace) の末尾に置く
+const INSERT_LABEL: &str = r#"
+    INSERT INTO labels (name, user_id, workspace_id, color, parent_id, icon, position)
+    VALUES ( $1, $2, $3, $4, $5, $6, (
+        SELECT COALESCE(MAX(position), 0) + 1 FROM labels
+        WHERE workspace_id IS NOT DISTINCT FROM $3 AND ($3::INTEGER IS NOT NULL OR user_id = $2)
+    ) )
+"#;
+
+// 読むだけの find や all などは read_pool に送る. read_pool は replica を指定しなければ pool と同じ
 #[derive(Debug, Clone)]
 pub struct LabelRepositoryForDb {
     pool: PgPool,
+    read_pool: PgPool,
 }
 
 impl LabelRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            read_pool: pool.clone(),
+            pool,
+        }
     }
-}
 
-#[async_trait]
-impl LabelRepository for LabelRepositoryForDb {
-    async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>(
+    pub fn with_read_pool(self, read_pool: PgPool) -> Self {
+        Self { read_pool, ..self }
+    }
+
+    async fn find_in(&self, conn: &mut PgConnection, id: i32) -> anyhow::Result<Label> {
+        let label = sqlx::query_as::<_, Label>(
             r#"
-            select id, name from labels where name = $1
+                SELECT labels.*
+                FROM labels
+                WHERE labels.id=$1
+                "#,
+        )
+        .bind(id)
+        .fetch_one(conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(label)
+    }
+
+    // 親は同じ一覧のラベルで、id のラベル自身やその子孫ではないこと. 作成するときの id は None
+    // 個人のラベルの親は同じ user の個人のラベル
+    async fn check_parent(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Option<i32>,
+        workspace_id: Option<i32>,
+        id: Option<i32>,
+        parent_id: i32,
+    ) -> anyhow::Result<()> {
+        let parent = self.find_in(conn, parent_id).await?;
+        if parent.workspace_id != workspace_id
+            || (workspace_id.is_none() && parent.user_id != user_id)
+        {
+            return Err(RepositoryError::Invalid(format!(
+                "label {} can not be a parent of this label",
+                parent_id
+            ))
+            .into());
+        }
+        let Some(id) = id else {
+            return Ok(());
+        };
+
+        // 親からたどれる祖先に自分自身が含まれていれば循環する
+        let cyclic = sqlx::query_scalar::<_, bool>(
+            r#"
+            WITH RECURSIVE ancestors (id, parent_id) AS (
+                SELECT id, parent_id FROM labels WHERE id = $1
+                UNION
+                SELECT labels.id, labels.parent_id
+                FROM labels INNER JOIN ancestors ON labels.id = ancestors.parent_id
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $2)
             "#,
         )
-        .bind(payload.name.clone())
-        .fetch_optional(&self.pool)
+        .bind(parent_id)
+        .bind(id)
+        .fetch_one(&mut *conn)
         .await?;
+        if cyclic {
+            return Err(RepositoryError::Invalid(format!(
+                "label {} is a descendant of label {}",
+                parent_id, id
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // 一意制約 (labels_user_id_name_idx, labels_workspace_id_name_idx) の違反を、
+    // 同じ一覧で同じ名前の既存のラベルの id の RepositoryError::Duplicate にする
+    // 違反した tx ではもう読めないので、既存のラベルは pool から探す
+    async fn map_duplicate(
+        &self,
+        e: sqlx::Error,
+        user_id: Option<i32>,
+        workspace_id: Option<i32>,
+        name: &str,
+    ) -> anyhow::Error {
+        match e {
+            sqlx::Error::Database(ref db) if db.code().as_deref() == Some("23505") => {
+                let existing = sqlx::query_scalar::<_, i32>(
+                    r#"
+                    SELECT id FROM labels
+                    WHERE workspace_id IS NOT DISTINCT FROM $3 AND ($3::INTEGER IS NOT NULL OR user_id = $1)
+                        AND lower(name) = lower($2)
+                    "#,
+                )
+                .bind(user_id)
+                .bind(name)
+                .bind(workspace_id)
+                .fetch_one(&self.pool)
+                .await;
+                match existing {
+                    Ok(id) => RepositoryError::Duplicate(id).into(),
+                    Err(e) => e.into(),
+                }
+            }
+            _ => e.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl LabelRepository for LabelRepositoryForDb {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        Tx::begin(&self.pool).await
+    }
+
+    #[tracing::instrument(name = "LabelRepository::create", skip_all)]
+    async fn create_in(&self, tx: &mut Tx, payload: CreateLabel) -> anyhow::Result<Label> {
+        let conn = tx.conn()?;
+        if let Some(parent_id) = payload.parent_id {
+            self.check_parent(
+                conn,
+                Some(payload.user_id),
+                payload.workspace_id,
+                None,
+                parent_id,
+            )
+            .await?;
+        }
 
-        if let Some(label) = optional_label {
-            // アプリケーションでバリデーションするなら、
-            // どうして DB 側に制約を入れないのだろうか？？？
-            return Err(RepositoryError::Duplicate(label.id).into());
-            // return Ok(label);
+        // 同じ名前のラベルがあるかを先に確かめると、同時に作成されたときにすり抜けるので一意制約に任せる
+        let sql = format!("{} RETURNING *", INSERT_LABEL);
+        let label = sqlx::query_as::<_, Label>(&sql)
+            .bind(&payload.name)
+            .bind(payload.user_id)
+            .bind(payload.workspace_id)
+            .bind(payload.color.unwrap_or_else(|| DEFAULT_COLOR.to_string()))
+            .bind(payload.parent_id)
+            .bind(&payload.icon)
+            .fetch_one(&mut *conn)
+            .await;
+
+        match label {
+            Ok(label) => Ok(label),
+            Err(e) => Err(self
+                .map_duplicate(
+                    e,
+                    Some(payload.user_id),
+                    payload.workspace_id,
+                    &payload.name,
+                )
+                .await),
+        }
+    }
+
+    #[tracing::instrument(name = "LabelRepository::create_or_get", skip_all)]
+    async fn create_or_get_in(
+        &self,
+        tx: &mut Tx,
+        payload: CreateLabel,
+    ) -> anyhow::Result<(Label, bool)> {
+        let conn = tx.conn()?;
+        if let Some(parent_id) = payload.parent_id {
+            self.check_parent(
+                conn,
+                Some(payload.user_id),
+                payload.workspace_id,
+                None,
+                parent_id,
+            )
+            .await?;
+        }
+
+        // 一意制約は一覧ごとの部分インデックスなので、どちらに当たるかを指定する
+        let target = match payload.workspace_id {
+            None => "(user_id, lower(name)) WHERE workspace_id IS NULL",
+            Some(_) => "(workspace_id, lower(name)) WHERE workspace_id IS NOT NULL",
+        };
+        let sql = format!(
+            "{} ON CONFLICT {} DO NOTHING RETURNING *",
+            INSERT_LABEL, target
+        );
+        let created = sqlx::query_as::<_, Label>(&sql)
+            .bind(&payload.name)
+            .bind(payload.user_id)
+            .bind(payload.workspace_id)
+            .bind(payload.color.unwrap_or_else(|| DEFAULT_COLOR.to_string()))
+            .bind(payload.parent_id)
+            .bind(&payload.icon)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(label) = created {
+            return Ok((label, true));
         }
 
+        // 既にあれば RETURNING が空になるので、同じ一覧の同じ名前のラベルを取り直す
         let label = sqlx::query_as::<_, Label>(
             r#"
-            INSERT INTO labels (name)
-            VALUES ( $1 )
-            RETURNING *
+            SELECT * FROM labels
+            WHERE workspace_id IS NOT DISTINCT FROM $3 AND ($3::INTEGER IS NOT NULL OR user_id = $1)
+                AND lower(name) = lower($2)
             "#,
         )
-        .bind(payload.name)
-        .fetch_one(&self.pool)
+        .bind(payload.user_id)
+        .bind(&payload.name)
+        .bind(payload.workspace_id)
+        .fetch_one(&mut *conn)
         .await?;
 
-        Ok(label)
+        Ok((label, false))
     }
 
-    async fn update(&self, id: i32, payload: UpdateLabel) -> anyhow::Result<Label> {
-        let old_label = self.find(id).await?;
+    #[tracing::instrument(name = "LabelRepository::update", skip_all)]
+    async fn update_in(&self, tx: &mut Tx, id: i32, payload: UpdateLabel) -> anyhow::Result<Label> {
+        let conn = tx.conn()?;
+        // 古い値で上書きしないように tx の中で読む
+        let old_label = self.find_in(conn, id).await?;
+        if let Some(Some(parent_id)) = payload.parent_id {
+            self.check_parent(
+                conn,
+                old_label.user_id,
+                old_label.workspace_id,
+                Some(id),
+                parent_id,
+            )
+            .await?;
+        }
+        let name = payload.name.unwrap_or(old_label.name);
         let updated_one = sqlx::query_as::<_, Label>(
             r#"
-            UPDATE labels SET name=$1
-            WHERE id=$2
+            UPDATE labels SET name=$1, color=$2, parent_id=$3, icon=$4
+            WHERE id=$5
             RETURNING *
             "#,
         )
-        .bind(payload.name.unwrap_or(old_label.name))
+        .bind(&name)
+        .bind(payload.color.unwrap_or(old_label.color))
+        .bind(payload.parent_id.unwrap_or(old_label.parent_id))
+        .bind(payload.icon.unwrap_or(old_label.icon))
         .bind(id)
-        .fetch_one(&self.pool)
-        .await?;
+        .fetch_one(&mut *conn)
+        .await;
 
-        Ok(updated_one)
+        match updated_one {
+            Ok(label) => Ok(label),
+            Err(e) => Err(self
+                .map_duplicate(e, old_label.user_id, old_label.workspace_id, &name)
+                .await),
+        }
     }
 
+    #[tracing::instrument(name = "LabelRepository::find", skip_all)]
     async fn find(&self, id: i32) -> anyhow::Result<Label> {
-        let label = sqlx::query_as::<_, Label>(
-            r#"
-                SELECT labels.*
-                FROM labels
-                WHERE labels.id=$1
-                "#,
-        )
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        let mut conn = self.read_pool.acquire().await?;
+        self.find_in(&mut conn, id).await
+    }
 
-        Ok(label.clone())
+    #[tracing::instrument(name = "LabelRepository::find_for_update", skip_all)]
+    async fn find_for_update_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        let conn = tx.conn()?;
+        sqlx::query("SELECT id FROM labels WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        self.find_in(conn, id).await
     }
 
+    #[tracing::instrument(name = "LabelRepository::find_by_user", skip_all)]
     async fn find_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Label>> {
         let labels = sqlx::query_as::<_, Label>(
             r#"
@@ -123,7 +606,7 @@ impl LabelRepository for LabelRepositoryForDb {
                 "#,
         )
         .bind(user_id)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.read_pool)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => RepositoryError::NotFound(user_id),
@@ -133,35 +616,196 @@ impl LabelRepository for LabelRepositoryForDb {
         Ok(labels)
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
+    #[tracing::instrument(name = "LabelRepository::all", skip_all)]
+    async fn all(&self, scope: Scope, query: &LabelQuery) -> anyhow::Result<Vec<Label>> {
+        let sql = format!("{} ORDER BY {}", FILTERED_LABELS, query.order_by());
+        let labels = sqlx::query_as::<_, Label>(&sql)
+            .bind(scope.workspace_id)
+            .bind(scope.user_id)
+            .bind(query.archived.unwrap_or(false))
+            .bind(query.q.as_deref().map(escape_like))
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        Ok(labels)
+    }
+
+    #[tracing::instrument(name = "LabelRepository::page", skip_all)]
+    async fn page(
+        &self,
+        scope: Scope,
+        query: &LabelQuery,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Label>, i64)> {
+        let sql = format!(
+            "{} ORDER BY {} LIMIT $5 OFFSET $6",
+            FILTERED_LABELS,
+            query.order_by()
+        );
+        let labels = sqlx::query_as::<_, Label>(&sql)
+            .bind(scope.workspace_id)
+            .bind(scope.user_id)
+            .bind(query.archived.unwrap_or(false))
+            .bind(query.q.as_deref().map(escape_like))
+            .bind(page.limit)
+            .bind(page.offset)
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        let sql = format!("SELECT COUNT(*) FROM ({}) filtered", FILTERED_LABELS);
+        let total = sqlx::query_scalar::<_, i64>(&sql)
+            .bind(scope.workspace_id)
+            .bind(scope.user_id)
+            .bind(query.archived.unwrap_or(false))
+            .bind(query.q.as_deref().map(escape_like))
+            .fetch_one(&self.read_pool)
+            .await?;
+
+        Ok((labels, total))
+    }
+
+    #[tracing::instrument(name = "LabelRepository::delete", skip_all)]
+    async fn delete_in(&self, tx: &mut Tx, id: i32, mode: LabelDeleteMode) -> anyhow::Result<()> {
+        // 確かめてから消すまでの間に付けられないよう、ラベルの行をロックする
+        let conn = tx.conn()?;
+        sqlx::query("SELECT id FROM labels WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(RepositoryError::NotFound(id))?;
+        let in_use = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM todo_labels WHERE label_id = $1)
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+        if in_use && mode == LabelDeleteMode::Forbid {
+            return Err(RepositoryError::InUse(id).into());
+        }
+
+        // 外部キーの ON DELETE に任せず、関連付けはここで外す
+        sqlx::query("DELETE FROM todo_labels WHERE label_id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM template_labels WHERE label_id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("UPDATE labels SET parent_id = NULL WHERE parent_id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "LabelRepository::reorder", skip_all)]
+    async fn reorder(
+        &self,
+        workspace_id: Option<i32>,
+        user_id: i32,
+        ids: &[i32],
+    ) -> anyhow::Result<Vec<Label>> {
+        // 並べ替えの途中でラベルが増減しないよう、一覧の行をロックしてから確かめる
+        // アーカイブしたラベルは GET /labels に出ないので並べ替えの対象にしない
+        let mut tx = self.pool.begin().await?;
+        let current = sqlx::query_scalar::<_, i32>(
+            r#"
+            SELECT id FROM labels
+            WHERE workspace_id IS NOT DISTINCT FROM $1 AND ($1::INTEGER IS NOT NULL OR user_id = $2)
+                AND archived_at IS NULL
+            FOR UPDATE
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_all(&mut tx)
+        .await?;
+        check_order(&current, ids)?;
+
+        sqlx::query(
+            r#"
+            UPDATE labels SET position = t.position
+            FROM unnest($1::INTEGER[]) WITH ORDINALITY as t(id, position)
+            WHERE labels.id = t.id
+            "#,
+        )
+        .bind(ids)
+        .execute(&mut tx)
+        .await?;
         let labels = sqlx::query_as::<_, Label>(
             r#"
-            SELECT id, name FROM labels
-            ORDER BY id ASC;
+            SELECT * FROM labels WHERE id = ANY($1) ORDER BY position
             "#,
         )
-        .fetch_all(&self.pool)
+        .bind(ids)
+        .fetch_all(&mut tx)
         .await?;
+        tx.commit().await?;
 
         Ok(labels)
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        sqlx::query(
+    #[tracing::instrument(name = "LabelRepository::archive", skip_all)]
+    async fn archive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        let label = sqlx::query_as::<_, Label>(
             r#"
-            DELETE FROM labels WHERE id = $1
+            UPDATE labels SET archived_at = COALESCE(archived_at, now())
+            WHERE id = $1
+            RETURNING *
             "#,
         )
         .bind(id)
-        .execute(&self.pool)
+        .fetch_one(tx.conn()?)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
             _ => RepositoryError::Unexpected(e.to_string()),
         })?;
 
-        Ok(())
+        Ok(label)
+    }
+
+    #[tracing::instrument(name = "LabelRepository::unarchive", skip_all)]
+    async fn unarchive_in(&self, tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+        let label = sqlx::query_as::<_, Label>(
+            r#"
+            UPDATE labels SET archived_at = NULL
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .fetch_one(tx.conn()?)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(label)
+    }
+}
+
+// ids が current のラベルをちょうど 1 回ずつ含んでいるか
+fn check_order(current: &[i32], ids: &[i32]) -> Result<(), RepositoryError> {
+    let mut current = current.to_vec();
+    current.sort_unstable();
+    let mut sorted = ids.to_vec();
+    sorted.sort_unstable();
+    if current != sorted {
+        return Err(RepositoryError::Invalid(
+            "ids must contain every label of the list exactly once".to_string(),
+        ));
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -178,19 +822,254 @@ mod test {
         let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
         let pool = PgPool::connect(&database_url)
             .await
-            .expect(&format!("cannot connect database: [{}]", database_url));
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
         let repo = LabelRepositoryForDb::new(pool.clone());
         let label_text = "test_label";
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'label_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
 
         // create
         // name が unique 制約である場合、DB クリアを毎回やらないと成立しない
         let label = repo
             .create(CreateLabel {
                 name: label_text.to_string(),
+                color: None,
+                icon: Some("🏠".to_string()),
+                parent_id: None,
+                user_id,
+                workspace_id: None,
             })
             .await
             .expect("[create] returned Err");
         assert_eq!(label.name, label_text);
+        assert_eq!(label.user_id, Some(user_id));
+        assert_eq!(label.color, DEFAULT_COLOR);
+        assert_eq!(label.icon.as_deref(), Some("🏠"));
+
+        // update. icon を省略したら変えない
+        let label = repo
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: None,
+                    color: Some("#e5484d".to_string()),
+                    icon: None,
+                    parent_id: None,
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(
+            (label.name.as_str(), label.color.as_str()),
+            (label_text, "#e5484d")
+        );
+        assert_eq!(label.icon.as_deref(), Some("🏠"));
+        let label = repo
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: None,
+                    color: None,
+                    icon: Some(None),
+                    parent_id: None,
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.icon, None);
+
+        // 名前は user ごとに大文字と小文字を区別せず一意
+        let res = repo
+            .create(CreateLabel::new(label_text.to_uppercase(), user_id))
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == label.id
+        ));
+        let (existing, created) = repo
+            .create_or_get(CreateLabel::new(label_text.to_uppercase(), user_id))
+            .await
+            .expect("[create_or_get] returned Err");
+        assert_eq!((existing, created), (label.clone(), false));
+
+        // find_by_user
+        let labels = repo
+            .find_by_user(user_id)
+            .await
+            .expect("[find_by_user] returned Err");
+        assert_eq!(labels, vec![label.clone()]);
+
+        // workspace のラベルは個人のラベルとは別に、workspace の中で一意
+        let (member_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'label_crud_scenario_member@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        let (workspace_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO workspaces (name, owner_id) VALUES ('label_crud_scenario', $1) RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare workspace data.");
+        let (in_workspace, created) = repo
+            .create_or_get(CreateLabel {
+                workspace_id: Some(workspace_id),
+                ..CreateLabel::new(label_text.to_string(), user_id)
+            })
+            .await
+            .expect("[create_or_get] returned Err");
+        assert!(created);
+        assert_eq!(in_workspace.workspace_id, Some(workspace_id));
+        let (existing, created) = repo
+            .create_or_get(CreateLabel {
+                workspace_id: Some(workspace_id),
+                ..CreateLabel::new(label_text.to_uppercase(), member_id)
+            })
+            .await
+            .expect("[create_or_get] returned Err");
+        assert_eq!((existing, created), (in_workspace.clone(), false));
+        let res = repo
+            .create(CreateLabel {
+                workspace_id: Some(workspace_id),
+                ..CreateLabel::new(label_text.to_uppercase(), member_id)
+            })
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == in_workspace.id
+        ));
+        repo.delete(in_workspace.id, LabelDeleteMode::Forbid)
+            .await
+            .expect("[delete] returned Err");
+        sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(workspace_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up workspace data.");
+
+        // 入れ子. 子孫を親にすると循環するので Invalid
+        let child = repo
+            .create(CreateLabel {
+                parent_id: Some(label.id),
+                ..CreateLabel::new(format!("{}/child", label_text), user_id)
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(child.parent_id, Some(label.id));
+        let rename = UpdateLabel {
+            name: Some(label_text.to_uppercase()),
+            color: None,
+            icon: None,
+            parent_id: None,
+        };
+        let res = repo.update(child.id, rename).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == label.id
+        ));
+        let res = repo
+            .update(label.id, UpdateLabel::parent(Some(child.id)))
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Invalid(_))
+        ));
+        let res = repo
+            .update(child.id, UpdateLabel::parent(Some(child.id)))
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Invalid(_))
+        ));
+
+        // reorder は個人のラベルを全て並べた場合だけ受け付ける
+        let labels = repo
+            .reorder(None, user_id, &[child.id, label.id])
+            .await
+            .expect("[reorder] returned Err");
+        assert_eq!(
+            labels
+                .iter()
+                .map(|label| (label.id, label.position))
+                .collect::<Vec<_>>(),
+            vec![(child.id, 1), (label.id, 2)]
+        );
+        for ids in [vec![label.id], vec![label.id, child.id, child.id]] {
+            let res = repo.reorder(None, user_id, &ids).await;
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Invalid(_))
+            ));
+        }
+
+        // archive. 一覧からは外れ、並べ替えの対象にもならない
+        let archived = repo
+            .archive(child.id)
+            .await
+            .expect("[archive] returned Err");
+        assert!(archived.archived_at.is_some());
+        let again = repo
+            .archive(child.id)
+            .await
+            .expect("[archive] returned Err");
+        assert_eq!(again.archived_at, archived.archived_at);
+        let labels = repo
+            .all(Scope::personal(user_id), &LabelQuery::default())
+            .await
+            .expect("[all] returned Err");
+        assert!(!labels.iter().any(|label| label.id == child.id));
+        let query = LabelQuery {
+            archived: Some(true),
+            ..Default::default()
+        };
+        let labels = repo
+            .all(Scope::personal(user_id), &query)
+            .await
+            .expect("[all] returned Err");
+        assert!(labels.contains(&archived));
+        repo.reorder(None, user_id, &[label.id])
+            .await
+            .expect("[reorder] returned Err");
+        let child = repo
+            .unarchive(child.id)
+            .await
+            .expect("[unarchive] returned Err");
+        assert_eq!(child.archived_at, None);
+
+        // page. q は大文字と小文字を区別しない
+        let query = LabelQuery {
+            sort: Some(LabelSort::Name),
+            q: Some(label_text.to_uppercase()),
+            ..Default::default()
+        };
+        let (labels, total) = repo
+            .page(
+                Scope::personal(user_id),
+                &query,
+                Page {
+                    limit: 1,
+                    offset: 1,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        assert_eq!((labels, total), (vec![child.clone()], 2));
 
         // all
         // let labels = repo.all()
@@ -201,8 +1080,50 @@ mod test {
         // // assert!(labels.len() == 1); // DB クリアする前提がないので今はこれが安定して成立しない
         // assert_eq!(label.name, label_text);
 
-        // delete
-        let _ = repo.delete(label.id).await.expect("[delete] returned Err");
+        // delete. todo に付いていれば Forbid では消さず、Detach なら外して消す
+        let (todo_id,): (i32,) = sqlx::query_as(
+            r#"
+            WITH todo AS (
+                INSERT INTO todos (text, user_id) VALUES ( '[label_crud_scenario] todo', $1 ) RETURNING id
+            )
+            INSERT INTO todo_labels (todo_id, label_id) SELECT id, $2 FROM todo RETURNING todo_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(label.id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+        let res = repo.delete(label.id, LabelDeleteMode::Forbid).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::InUse(_))
+        ));
+        repo.delete(label.id, LabelDeleteMode::Detach)
+            .await
+            .expect("[delete] returned Err");
+        let attached =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM todo_labels WHERE todo_id = $1")
+                .bind(todo_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(attached, 0);
+        sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up todo data.");
+        // 親を削除した子はトップレベルに残る
+        assert_eq!(repo.find(child.id).await.unwrap().parent_id, None);
+        repo.delete(child.id, LabelDeleteMode::Forbid)
+            .await
+            .expect("[delete] returned Err");
+        let res = repo.delete(child.id, LabelDeleteMode::Forbid).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
         // let labels = repo.all().await.expect("[all] returned Err");
         // 他 (Label) のテストが途中で失敗するなど、Label が残っている初期状態で
         // このテストが起動してしまうと、次のアサーションは失敗する
@@ -210,9 +1131,11 @@ mod test {
     }
 }
 
-#[cfg(test)]
-pub mod test_utils {
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
     use crate::repositories::label::CreateLabel;
+    use crate::repositories::todo::memory::TodoRepositoryForMemory;
     use axum::async_trait;
     use std::{
         collections::HashMap,
@@ -222,87 +1145,390 @@ pub mod test_utils {
     use super::*;
 
     impl Label {
-        pub fn new(id: i32, name: String) -> Self {
-            Self { id, name }
+        pub fn new(id: i32, name: String, user_id: i32) -> Self {
+            Self {
+                id,
+                name,
+                user_id: Some(user_id),
+                workspace_id: None,
+                color: DEFAULT_COLOR.to_string(),
+                parent_id: None,
+                position: id,
+                archived_at: None,
+                icon: None,
+            }
+        }
+    }
+
+    impl UpdateLabel {
+        pub fn parent(parent_id: Option<i32>) -> Self {
+            Self {
+                name: None,
+                color: None,
+                icon: None,
+                parent_id: Some(parent_id),
+            }
         }
     }
 
-    #[cfg(test)]
     impl CreateLabel {
-        // pub fn new(name: String) -> Self {
-        //     Self { name: name }
-        // }
+        pub fn new(name: String, user_id: i32) -> Self {
+            Self {
+                name,
+                color: None,
+                icon: None,
+                parent_id: None,
+                user_id,
+                workspace_id: None,
+            }
+        }
     }
 
     type LabelDatas = HashMap<i32, Label>;
 
+    // 削除したラベルの id を使い回さないように、件数ではなく最大の id から採番する
+    fn next_id(store: &LabelDatas) -> i32 {
+        store.keys().max().copied().unwrap_or(0) + 1
+    }
+
     #[derive(Debug, Clone)]
     pub struct LabelRepositoryForMemory {
         store: Arc<RwLock<LabelDatas>>,
+        // 削除するときに todo から外すため、todo のレポジトリと同じ store を持つ
+        todos: Option<TodoRepositoryForMemory>,
     }
 
     impl LabelRepositoryForMemory {
         pub fn new() -> Self {
             LabelRepositoryForMemory {
                 store: Arc::default(),
+                todos: None,
+            }
+        }
+
+        pub fn with_todos(todos: TodoRepositoryForMemory) -> Self {
+            LabelRepositoryForMemory {
+                store: Arc::default(),
+                todos: Some(todos),
+            }
+        }
+
+        pub fn restore(labels: Vec<Label>, todos: TodoRepositoryForMemory) -> Self {
+            let store = labels.into_iter().map(|label| (label.id, label)).collect();
+            LabelRepositoryForMemory {
+                store: Arc::new(RwLock::new(store)),
+                todos: Some(todos),
             }
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<LabelDatas> {
+        // 差分が読みやすいように id 順に並べる
+        pub fn snapshot(&self) -> Vec<Label> {
+            let mut labels: Vec<Label> = self.read_store_ref().values().cloned().collect();
+            labels.sort_by_key(|label| label.id);
+            labels
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, LabelDatas> {
             self.store.write().unwrap()
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<LabelDatas> {
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, LabelDatas> {
             self.store.read().unwrap()
         }
+
+        // DB 実装の check_parent と同じ判定
+        fn check_parent(
+            store: &LabelDatas,
+            user_id: Option<i32>,
+            workspace_id: Option<i32>,
+            id: Option<i32>,
+            parent_id: i32,
+        ) -> anyhow::Result<()> {
+            let parent = store
+                .get(&parent_id)
+                .ok_or(RepositoryError::NotFound(parent_id))?;
+            if parent.workspace_id != workspace_id
+                || (workspace_id.is_none() && parent.user_id != user_id)
+            {
+                return Err(RepositoryError::Invalid(format!(
+                    "label {} can not be a parent of this label",
+                    parent_id
+                ))
+                .into());
+            }
+            let mut current = Some(parent_id);
+            while let Some(ancestor_id) = current {
+                if Some(ancestor_id) == id {
+                    return Err(RepositoryError::Invalid(format!(
+                        "label {} is a descendant of label {}",
+                        parent_id, ancestor_id
+                    ))
+                    .into());
+                }
+                current = store.get(&ancestor_id).and_then(|label| label.parent_id);
+            }
+            Ok(())
+        }
+
+        // DB 実装の一意制約と同じ判定. id のラベル自身とは重ならない
+        fn check_name(
+            store: &LabelDatas,
+            user_id: Option<i32>,
+            workspace_id: Option<i32>,
+            id: Option<i32>,
+            name: &str,
+        ) -> Result<(), RepositoryError> {
+            let duplicate = store.values().find(|label| {
+                let same_list = match workspace_id {
+                    Some(_) => label.workspace_id == workspace_id,
+                    None => {
+                        label.workspace_id.is_none()
+                            && user_id.is_some()
+                            && label.user_id == user_id
+                    }
+                };
+                Some(label.id) != id
+                    && same_list
+                    && label.name.to_lowercase() == name.to_lowercase()
+            });
+            match duplicate {
+                Some(label) => Err(RepositoryError::Duplicate(label.id)),
+                None => Ok(()),
+            }
+        }
     }
 
     #[async_trait]
     impl LabelRepository for LabelRepositoryForMemory {
-        async fn create(&self, payload: CreateLabel) -> anyhow::Result<Label> {
+        async fn begin(&self) -> anyhow::Result<Tx> {
+            Ok(Tx::memory())
+        }
+
+        async fn create_in(&self, _tx: &mut Tx, payload: CreateLabel) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let label = Label::new(id, payload.name.clone());
+            if let Some(parent_id) = payload.parent_id {
+                Self::check_parent(
+                    &store,
+                    Some(payload.user_id),
+                    payload.workspace_id,
+                    None,
+                    parent_id,
+                )?;
+            }
+            Self::check_name(
+                &store,
+                Some(payload.user_id),
+                payload.workspace_id,
+                None,
+                &payload.name,
+            )?;
+            let id = next_id(&store);
+            let position = store
+                .values()
+                .filter(|label| label.workspace_id == payload.workspace_id)
+                .filter(|label| {
+                    payload.workspace_id.is_some() || label.user_id == Some(payload.user_id)
+                })
+                .map(|label| label.position)
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let label = Label {
+                workspace_id: payload.workspace_id,
+                color: payload.color.unwrap_or_else(|| DEFAULT_COLOR.to_string()),
+                icon: payload.icon,
+                parent_id: payload.parent_id,
+                position,
+                ..Label::new(id, payload.name.clone(), payload.user_id)
+            };
             store.insert(id, label.clone());
             Ok(label)
         }
 
+        async fn create_or_get_in(
+            &self,
+            tx: &mut Tx,
+            payload: CreateLabel,
+        ) -> anyhow::Result<(Label, bool)> {
+            let existing = Self::check_name(
+                &self.read_store_ref(),
+                Some(payload.user_id),
+                payload.workspace_id,
+                None,
+                &payload.name,
+            );
+            match existing {
+                Err(RepositoryError::Duplicate(id)) => Ok((self.find(id).await?, false)),
+                _ => Ok((self.create_in(tx, payload).await?, true)),
+            }
+        }
+
         async fn find(&self, id: i32) -> anyhow::Result<Label> {
             let store = self.read_store_ref();
             let label = store.get(&id).ok_or(RepositoryError::NotFound(id))?;
             Ok(label.clone())
         }
 
-        async fn find_by_user(&self, _user_id: i32) -> anyhow::Result<Vec<Label>> {
-            let labels: Vec<Label> = self
+        async fn find_for_update_in(&self, _tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+            self.find(id).await
+        }
+
+        async fn find_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Label>> {
+            let mut labels: Vec<Label> = self
                 .read_store_ref()
                 .values()
-                // .filter(|label| label.user_id == user_id)
+                .filter(|label| label.user_id == Some(user_id))
                 .cloned()
                 .collect();
+            labels.sort_by_key(|label| label.id);
             Ok(labels)
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
-            let store = self.read_store_ref();
-            let labels = Vec::from_iter(store.values().map(|label| label.clone()));
+        async fn all(&self, scope: Scope, query: &LabelQuery) -> anyhow::Result<Vec<Label>> {
+            let mut labels: Vec<Label> = self
+                .read_store_ref()
+                .values()
+                .filter(|label| label.workspace_id == scope.workspace_id)
+                .filter(|label| {
+                    scope.workspace_id.is_some() || label.user_id == Some(scope.user_id)
+                })
+                .filter(|label| label.archived_at.is_some() == query.archived.unwrap_or(false))
+                .filter(|label| {
+                    query
+                        .q
+                        .as_ref()
+                        .is_none_or(|q| label.name.to_lowercase().contains(&q.to_lowercase()))
+                })
+                .cloned()
+                .collect();
+            // DB 実装の ORDER BY と同じ並び順
+            labels.sort_by(|a, b| {
+                let ordering = match query.sort {
+                    Some(LabelSort::Name) => a.name.cmp(&b.name).then(a.id.cmp(&b.id)),
+                    Some(LabelSort::CreatedAt) => a.id.cmp(&b.id),
+                    Some(LabelSort::Position) | None => {
+                        a.position.cmp(&b.position).then(a.id.cmp(&b.id))
+                    }
+                };
+                match query.order.unwrap_or(SortOrder::Asc) {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            });
             Ok(labels)
         }
 
-        async fn update(&self, id: i32, payload: UpdateLabel) -> anyhow::Result<Label> {
+        async fn page(
+            &self,
+            scope: Scope,
+            query: &LabelQuery,
+            page: Page,
+        ) -> anyhow::Result<(Vec<Label>, i64)> {
+            let labels = self.all(scope, query).await?;
+            let total = labels.len() as i64;
+            let labels = labels
+                .into_iter()
+                .skip(page.offset as usize)
+                .take(page.limit as usize)
+                .collect();
+            Ok((labels, total))
+        }
+
+        async fn update_in(
+            &self,
+            _tx: &mut Tx,
+            id: i32,
+            payload: UpdateLabel,
+        ) -> anyhow::Result<Label> {
             let mut store = self.write_store_ref();
+            let label = store.get(&id).ok_or(RepositoryError::NotFound(id))?;
+            let (workspace_id, user_id) = (label.workspace_id, label.user_id);
+            if let Some(Some(parent_id)) = payload.parent_id {
+                Self::check_parent(&store, user_id, workspace_id, Some(id), parent_id)?;
+            }
+            if let Some(name) = &payload.name {
+                Self::check_name(&store, user_id, workspace_id, Some(id), name)?;
+            }
             let label = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            if let Some(parent_id) = payload.parent_id {
+                label.parent_id = parent_id;
+            }
             if let Some(name) = payload.name {
                 label.name = name;
             }
+            if let Some(color) = payload.color {
+                label.color = color;
+            }
+            if let Some(icon) = payload.icon {
+                label.icon = icon;
+            }
             Ok(label.clone())
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn delete_in(
+            &self,
+            _tx: &mut Tx,
+            id: i32,
+            mode: LabelDeleteMode,
+        ) -> anyhow::Result<()> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
+            if !store.contains_key(&id) {
+                return Err(RepositoryError::NotFound(id).into());
+            }
+            if let Some(todos) = &self.todos {
+                if mode == LabelDeleteMode::Forbid && todos.has_label(id) {
+                    return Err(RepositoryError::InUse(id).into());
+                }
+                todos.detach_label(id);
+            }
+            store.remove(&id);
+            for label in store
+                .values_mut()
+                .filter(|label| label.parent_id == Some(id))
+            {
+                label.parent_id = None;
+            }
             Ok(())
         }
+
+        async fn reorder(
+            &self,
+            workspace_id: Option<i32>,
+            user_id: i32,
+            ids: &[i32],
+        ) -> anyhow::Result<Vec<Label>> {
+            let mut store = self.write_store_ref();
+            let current: Vec<i32> = store
+                .values()
+                .filter(|label| label.workspace_id == workspace_id)
+                .filter(|label| workspace_id.is_some() || label.user_id == Some(user_id))
+                .filter(|label| label.archived_at.is_none())
+                .map(|label| label.id)
+                .collect();
+            check_order(&current, ids)?;
+            let mut labels = Vec::with_capacity(ids.len());
+            for (index, id) in ids.iter().enumerate() {
+                if let Some(label) = store.get_mut(id) {
+                    label.position = index as i32 + 1;
+                    labels.push(label.clone());
+                }
+            }
+            Ok(labels)
+        }
+
+        async fn archive_in(&self, _tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+            let mut store = self.write_store_ref();
+            let label = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            label.archived_at.get_or_insert_with(Utc::now);
+            Ok(label.clone())
+        }
+
+        async fn unarchive_in(&self, _tx: &mut Tx, id: i32) -> anyhow::Result<Label> {
+            let mut store = self.write_store_ref();
+            let label = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            label.archived_at = None;
+            Ok(label.clone())
+        }
     }
 
     #[cfg(test)]
@@ -313,24 +1539,95 @@ pub mod test_utils {
         async fn label_crud_scenario() {
             let name = "label name".to_string();
             let id = 1;
-            let expected = Label::new(id, name.clone());
+            let user_id = 1;
+            let expected = Label::new(id, name.clone(), user_id);
 
             let repo = LabelRepositoryForMemory::new();
 
             // create
             let label = repo
-                .create(CreateLabel { name: name })
+                .create(CreateLabel::new(name, user_id))
                 .await
                 .expect("failed create label");
             assert_eq!(expected, label);
 
+            // 名前は user ごとに大文字と小文字を区別せず一意
+            let res = repo
+                .create(CreateLabel::new("LABEL NAME".to_string(), user_id))
+                .await;
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Duplicate(1))
+            ));
+            let other = repo
+                .create(CreateLabel::new("Label Name".to_string(), 2))
+                .await
+                .expect("failed create label");
+            repo.delete(other.id, LabelDeleteMode::Forbid)
+                .await
+                .expect("failed delete label");
+
+            // create_or_get は同じ名前のラベルを返す
+            let (existing, created) = repo
+                .create_or_get(CreateLabel::new("LABEL NAME".to_string(), user_id))
+                .await
+                .expect("failed create or get label");
+            assert_eq!((existing, created), (label.clone(), false));
+
+            // find_by_user
+            let labels = repo
+                .find_by_user(user_id)
+                .await
+                .expect("failed find labels");
+            assert_eq!(vec![label.clone()], labels);
+            let labels = repo.find_by_user(2).await.expect("failed find labels");
+            assert!(labels.is_empty());
+
             // all
-            let labels = repo.all().await.expect("failed get all labels");
-            assert_eq!(vec![label], labels);
+            let labels = repo
+                .all(Scope::personal(user_id), &LabelQuery::default())
+                .await
+                .expect("failed get all labels");
+            assert_eq!(vec![label.clone()], labels);
+            let labels = repo
+                .all(Scope::new(user_id, Some(1)), &LabelQuery::default())
+                .await
+                .expect("failed get all labels");
+            assert!(labels.is_empty());
+            let labels = repo
+                .all(Scope::personal(2), &LabelQuery::default())
+                .await
+                .expect("failed get all labels");
+            assert!(labels.is_empty());
+
+            // archive
+            let archived = repo.archive(id).await.expect("failed archive label");
+            assert!(archived.archived_at.is_some());
+            let labels = repo
+                .all(Scope::personal(user_id), &LabelQuery::default())
+                .await
+                .expect("failed get all labels");
+            assert!(labels.is_empty());
+            let query = LabelQuery {
+                archived: Some(true),
+                ..Default::default()
+            };
+            let labels = repo
+                .all(Scope::personal(user_id), &query)
+                .await
+                .expect("failed get all labels");
+            assert_eq!(vec![archived], labels);
+            let label = repo.unarchive(id).await.expect("failed unarchive label");
+            assert_eq!(expected, label);
 
             // delete
-            repo.delete(id).await.expect("failed delete label");
-            let labels = repo.all().await.expect("failed get all labels");
+            repo.delete(id, LabelDeleteMode::Forbid)
+                .await
+                .expect("failed delete label");
+            let labels = repo
+                .all(Scope::personal(user_id), &LabelQuery::default())
+                .await
+                .expect("failed get all labels");
             assert_eq!(labels.len(), 0);
         }
     }
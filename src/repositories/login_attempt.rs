@@ -0,0 +1,284 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::IpAddr;
+
+#[async_trait]
+pub trait LoginAttemptRepository: std::marker::Send + std::marker::Sync + 'static {
+    async fn record_failure(&self, email: &str, ip: Option<IpAddr>) -> anyhow::Result<()>;
+    // since 以降の失敗時刻を新しい順に返す
+    async fn account_failures(
+        &self,
+        email: &str,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DateTime<Utc>>>;
+    async fn ip_failures(
+        &self,
+        ip: IpAddr,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DateTime<Utc>>>;
+    // ログインに成功したらアカウントの失敗記録を消す. IP 単位の記録は残す
+    async fn clear(&self, email: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginAttemptRepositoryForDb {
+    pool: PgPool,
+}
+
+impl LoginAttemptRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for LoginAttemptRepositoryForDb {
+    #[tracing::instrument(name = "LoginAttemptRepository::record_failure", skip_all)]
+    async fn record_failure(&self, email: &str, ip: Option<IpAddr>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO login_attempts (email, ip)
+            VALUES ( $1, $2 )
+            "#,
+        )
+        .bind(email)
+        .bind(ip.map(|ip| ip.to_string()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "LoginAttemptRepository::account_failures", skip_all)]
+    async fn account_failures(
+        &self,
+        email: &str,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DateTime<Utc>>> {
+        let failures = sqlx::query_scalar::<_, DateTime<Utc>>(
+            r#"
+            SELECT attempted_at FROM login_attempts
+            WHERE email = $1 AND attempted_at > $2
+            ORDER BY attempted_at DESC
+            "#,
+        )
+        .bind(email)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(failures)
+    }
+
+    #[tracing::instrument(name = "LoginAttemptRepository::ip_failures", skip_all)]
+    async fn ip_failures(
+        &self,
+        ip: IpAddr,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<DateTime<Utc>>> {
+        let failures = sqlx::query_scalar::<_, DateTime<Utc>>(
+            r#"
+            SELECT attempted_at FROM login_attempts
+            WHERE ip = $1 AND attempted_at > $2
+            ORDER BY attempted_at DESC
+            "#,
+        )
+        .bind(ip.to_string())
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(failures)
+    }
+
+    #[tracing::instrument(name = "LoginAttemptRepository::clear", skip_all)]
+    async fn clear(&self, email: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM login_attempts WHERE email = $1")
+            .bind(email)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = LoginAttemptRepositoryForDb::new(pool.clone());
+        let email = "login_attempt_crud_scenario@example.com";
+        let ip = IpAddr::from([192, 0, 2, 18]);
+        // 前回のテストの記録を消しておく
+        sqlx::query("DELETE FROM login_attempts WHERE email = $1 OR ip = $2")
+            .bind(email)
+            .bind(ip.to_string())
+            .execute(&pool)
+            .await
+            .expect("failed to clean up login attempts");
+        let since = Utc::now() - Duration::minutes(1);
+
+        // record_failure
+        for _ in 0..2 {
+            repo.record_failure(email, Some(ip))
+                .await
+                .expect("[record_failure] returned Err");
+        }
+        let failures = repo
+            .account_failures(email, since)
+            .await
+            .expect("[account_failures] returned Err");
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0] >= failures[1]);
+        let failures = repo
+            .ip_failures(ip, since)
+            .await
+            .expect("[ip_failures] returned Err");
+        assert_eq!(failures.len(), 2);
+
+        // 期間外の失敗は数えない
+        let failures = repo
+            .account_failures(email, Utc::now() + Duration::minutes(1))
+            .await
+            .expect("[account_failures] returned Err");
+        assert!(failures.is_empty());
+
+        // clear はアカウントの記録だけ消す
+        repo.clear(email).await.expect("[clear] returned Err");
+        assert!(repo
+            .account_failures(email, since)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct LoginAttempt {
+        email: String,
+        ip: Option<IpAddr>,
+        attempted_at: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct LoginAttemptRepositoryForMemory {
+        store: Arc<RwLock<Vec<LoginAttempt>>>,
+    }
+
+    impl LoginAttemptRepositoryForMemory {
+        pub fn new() -> Self {
+            LoginAttemptRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+
+        fn failures(
+            &self,
+            since: DateTime<Utc>,
+            matches: impl Fn(&LoginAttempt) -> bool,
+        ) -> Vec<DateTime<Utc>> {
+            let mut failures: Vec<DateTime<Utc>> = self
+                .store
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|attempt| attempt.attempted_at > since && matches(attempt))
+                .map(|attempt| attempt.attempted_at)
+                .collect();
+            failures.sort_by(|a, b| b.cmp(a));
+            failures
+        }
+    }
+
+    #[async_trait]
+    impl LoginAttemptRepository for LoginAttemptRepositoryForMemory {
+        async fn record_failure(&self, email: &str, ip: Option<IpAddr>) -> anyhow::Result<()> {
+            self.store.write().unwrap().push(LoginAttempt {
+                email: email.to_string(),
+                ip,
+                attempted_at: Utc::now(),
+            });
+            Ok(())
+        }
+
+        async fn account_failures(
+            &self,
+            email: &str,
+            since: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<DateTime<Utc>>> {
+            Ok(self.failures(since, |attempt| attempt.email == email))
+        }
+
+        async fn ip_failures(
+            &self,
+            ip: IpAddr,
+            since: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<DateTime<Utc>>> {
+            Ok(self.failures(since, |attempt| attempt.ip == Some(ip)))
+        }
+
+        async fn clear(&self, email: &str) -> anyhow::Result<()> {
+            self.store
+                .write()
+                .unwrap()
+                .retain(|attempt| attempt.email != email);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use chrono::Duration;
+
+        #[tokio::test]
+        async fn login_attempt_scenario() {
+            let repo = LoginAttemptRepositoryForMemory::new();
+            let ip = IpAddr::from([192, 0, 2, 1]);
+            let since = Utc::now() - Duration::minutes(1);
+            repo.record_failure("a@example.com", Some(ip))
+                .await
+                .unwrap();
+            repo.record_failure("b@example.com", Some(ip))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                repo.account_failures("a@example.com", since)
+                    .await
+                    .unwrap()
+                    .len(),
+                1
+            );
+            assert_eq!(repo.ip_failures(ip, since).await.unwrap().len(), 2);
+
+            repo.clear("a@example.com").await.unwrap();
+            assert!(repo
+                .account_failures("a@example.com", since)
+                .await
+                .unwrap()
+                .is_empty());
+            assert_eq!(repo.ip_failures(ip, since).await.unwrap().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+
+// 取り出した event を他の relay に渡さない時間. 送り終えずに落ちた relay の分はこの後に送り直す
+pub const CLAIM_LEASE_SECS: i64 = 30;
+
+// outbox_events は todos / labels の trigger が書く. ここでは読み出しと後片付けだけを行う
+#[async_trait]
+pub trait OutboxRepository: std::marker::Send + std::marker::Sync + 'static {
+    // まだ送っていない event を古い順に limit 件まで取り出す. 取り出した event は lease の間は他の relay に返さない
+    async fn claim(&self, now: DateTime<Utc>, limit: i64) -> anyhow::Result<Vec<OutboxEvent>>;
+    // 送り終えた event を消す
+    async fn remove(&self, ids: &[i64]) -> anyhow::Result<()>;
+}
+
+// event bus に流す内容. 同じ event が 2 回届くことがあるので、受け取る側は id で重複を除く
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub event: String,
+    pub entity_id: i32,
+    pub user_id: Option<i32>,
+    pub workspace_id: Option<i32>,
+    // テーブルの行をそのまま JSON にしたもの. todo のラベルは含まない
+    pub data: Value,
+    // 更新なら変更前の行. 作成と削除では None
+    pub previous: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxRepositoryForDb {
+    pool: PgPool,
+}
+
+impl OutboxRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for OutboxRepositoryForDb {
+    #[tracing::instrument(name = "OutboxRepository::claim", skip_all)]
+    async fn claim(&self, now: DateTime<Utc>, limit: i64) -> anyhow::Result<Vec<OutboxEvent>> {
+        // 複数の relay が同時に取り出しても、同じ event を二重に取らない
+        let mut events = sqlx::query_as::<_, OutboxEvent>(
+            r#"
+            UPDATE outbox_events SET claimed_until = $2
+            WHERE id IN (
+                SELECT id FROM outbox_events
+                WHERE claimed_until IS NULL OR claimed_until <= $1
+                ORDER BY id
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, event, entity_id, user_id, workspace_id, data, previous, created_at
+            "#,
+        )
+        .bind(now)
+        .bind(now + Duration::seconds(CLAIM_LEASE_SECS))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        events.sort_by_key(|event| event.id);
+
+        Ok(events)
+    }
+
+    #[tracing::instrument(name = "OutboxRepository::remove", skip_all)]
+    async fn remove(&self, ids: &[i64]) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM outbox_events WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn outbox_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = OutboxRepositoryForDb::new(pool.clone());
+        // 前回のテストや他のテストの event を消しておく
+        sqlx::query("DELETE FROM outbox_events")
+            .execute(&pool)
+            .await
+            .expect("failed to clean up outbox events");
+
+        // the trigger records the change in the same transaction
+        let (label_id,): (i32,) =
+            sqlx::query_as("INSERT INTO labels (name) VALUES ('outbox_scenario') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .expect("failed to insert label");
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("UPDATE labels SET name = 'rolled back' WHERE id = $1")
+            .bind(label_id)
+            .execute(&mut tx)
+            .await
+            .unwrap();
+        tx.rollback().await.unwrap();
+        sqlx::query("UPDATE labels SET name = name WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE labels SET name = 'outbox_scenario renamed' WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let events = repo.claim(now, 10).await.expect("[claim] returned Err");
+        let names: Vec<&str> = events.iter().map(|event| event.event.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["label.created", "label.updated", "label.deleted"]
+        );
+        assert_eq!(events[0].entity_id, label_id);
+        assert_eq!(events[0].data["name"], "outbox_scenario");
+        assert_eq!(events[0].previous, None);
+        let previous = events[1].previous.as_ref().expect("no previous row");
+        assert_eq!(previous["name"], "outbox_scenario");
+
+        // claimed events are not handed out again until the lease ends
+        assert!(repo.claim(now, 10).await.unwrap().is_empty());
+        repo.remove(&[events[0].id])
+            .await
+            .expect("[remove] returned Err");
+        let later = now + Duration::seconds(CLAIM_LEASE_SECS);
+        let events = repo.claim(later, 10).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, "label.updated");
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, RwLock,
+    };
+
+    use super::*;
+
+    // lease の期限と一緒に持つ
+    type Entry = (OutboxEvent, Option<DateTime<Utc>>);
+
+    // trigger の代わりに record で event を積む
+    #[derive(Debug, Clone, Default)]
+    pub struct OutboxRepositoryForMemory {
+        store: Arc<RwLock<Vec<Entry>>>,
+        last_id: Arc<AtomicI64>,
+    }
+
+    impl OutboxRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn record(&self, event: &str, entity_id: i32, data: Value) -> OutboxEvent {
+            let event = OutboxEvent {
+                id: self.last_id.fetch_add(1, Ordering::SeqCst) + 1,
+                event: event.to_string(),
+                entity_id,
+                user_id: data["user_id"].as_i64().map(|id| id as i32),
+                workspace_id: data["workspace_id"].as_i64().map(|id| id as i32),
+                data,
+                previous: None,
+                created_at: Utc::now(),
+            };
+            self.store.write().unwrap().push((event.clone(), None));
+            event
+        }
+
+        pub fn pending(&self) -> Vec<OutboxEvent> {
+            let store = self.store.read().unwrap();
+            store.iter().map(|(event, _)| event.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl OutboxRepository for OutboxRepositoryForMemory {
+        async fn claim(&self, now: DateTime<Utc>, limit: i64) -> anyhow::Result<Vec<OutboxEvent>> {
+            let mut store = self.store.write().unwrap();
+            let claimed = store
+                .iter_mut()
+                .filter(|(_, claimed_until)| !claimed_until.is_some_and(|until| until > now))
+                .take(limit as usize)
+                .map(|(event, claimed_until)| {
+                    *claimed_until = Some(now + Duration::seconds(CLAIM_LEASE_SECS));
+                    event.clone()
+                })
+                .collect();
+            Ok(claimed)
+        }
+
+        async fn remove(&self, ids: &[i64]) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            store.retain(|(event, _)| !ids.contains(&event.id));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use super::{cache::RepositoryCache, RepositoryError, Scope, Tx};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use validator::Validate;
+
+// scope の外 (他人のプロジェクトや別 workspace のプロジェクト) に触れようとした場合は RepositoryError::Forbidden を返す
+#[async_trait]
+pub trait ProjectRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 書き込みをまとめるトランザクションを始める. *_in は handler が始めた tx の中で書き込み、監査ログも同じ tx で残す
+    // *_in のない方はテスト用で、その場で tx を始めて commit する
+    async fn begin(&self) -> anyhow::Result<Tx>;
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateProject,
+    ) -> anyhow::Result<Project>;
+    #[cfg(test)]
+    async fn create(&self, scope: Scope, payload: CreateProject) -> anyhow::Result<Project> {
+        let mut tx = self.begin().await?;
+        let result = self.create_in(&mut tx, scope, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Project>;
+    // 古い順
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Project>>;
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateProject,
+    ) -> anyhow::Result<Project>;
+    #[cfg(test)]
+    async fn update(
+        &self,
+        scope: Scope,
+        id: i32,
+        payload: UpdateProject,
+    ) -> anyhow::Result<Project> {
+        let mut tx = self.begin().await?;
+        let result = self.update_in(&mut tx, scope, id, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // プロジェクトの todo は残り、project_id が外れる. todo ごと消す場合は先に handler で消しておく
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()>;
+    #[cfg(test)]
+    async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        let result = self.delete_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Project {
+    pub id: i32,
+    pub name: String,
+    pub user_id: i32,
+    // workspace に属さない個人のプロジェクトは None
+    pub workspace_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Project {
+    // Todo::is_visible_in と同じ判定
+    pub fn is_visible_in(&self, scope: Scope) -> bool {
+        match scope.workspace_id {
+            Some(workspace_id) => self.workspace_id == Some(workspace_id),
+            None => self.workspace_id.is_none() && self.user_id == scope.user_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateProject {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateProject {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectRepositoryForDb {
+    pool: PgPool,
+    // 消したものに付いていた todo / label のキャッシュを捨てる
+    cache: Option<RepositoryCache>,
+}
+
+impl ProjectRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool, cache: None }
+    }
+
+    pub fn with_cache(self, cache: Option<RepositoryCache>) -> Self {
+        Self { cache, ..self }
+    }
+
+    async fn find_in(
+        &self,
+        conn: &mut PgConnection,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<Project> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            SELECT * FROM projects WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+        if !project.is_visible_in(scope) {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+
+        Ok(project)
+    }
+}
+
+#[async_trait]
+impl ProjectRepository for ProjectRepositoryForDb {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        Tx::begin(&self.pool).await
+    }
+
+    #[tracing::instrument(name = "ProjectRepository::create", skip_all)]
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateProject,
+    ) -> anyhow::Result<Project> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            INSERT INTO projects (name, user_id, workspace_id)
+            VALUES ( $1, $2, $3 )
+            RETURNING *
+            "#,
+        )
+        .bind(payload.name)
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_one(tx.conn()?)
+        .await?;
+
+        Ok(project)
+    }
+
+    #[tracing::instrument(name = "ProjectRepository::find", skip_all)]
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Project> {
+        let mut conn = self.pool.acquire().await?;
+        self.find_in(&mut conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "ProjectRepository::all", skip_all)]
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Project>> {
+        let projects = sqlx::query_as::<_, Project>(
+            r#"
+            SELECT * FROM projects
+            WHERE ($2::INTEGER IS NULL AND workspace_id IS NULL AND user_id = $1)
+                OR workspace_id = $2
+            ORDER BY id
+            "#,
+        )
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(projects)
+    }
+
+    #[tracing::instrument(name = "ProjectRepository::update", skip_all)]
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateProject,
+    ) -> anyhow::Result<Project> {
+        let conn = tx.conn()?;
+        let old_project = self.find_in(conn, scope, id).await?;
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects SET name = $1
+            WHERE id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(payload.name.unwrap_or(old_project.name))
+        .bind(id)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(project)
+    }
+
+    #[tracing::instrument(name = "ProjectRepository::delete", skip_all)]
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let conn = tx.conn()?;
+        self.find_in(conn, scope, id).await?;
+        // todos.project_id は ON DELETE SET NULL で外れる
+        sqlx::query("DELETE FROM projects WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all_on_commit(tx);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = ProjectRepositoryForDb::new(pool.clone());
+        let prepare_user = |email: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let (user_id,): (i32,) = sqlx::query_as(
+                    r#"
+                    INSERT INTO users (email, password_hash)
+                    VALUES ( $1, 'hash' )
+                    ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+                    RETURNING id
+                    "#,
+                )
+                .bind(email)
+                .fetch_one(&pool)
+                .await
+                .expect("failed to prepare user data.");
+                user_id
+            }
+        };
+        let user_id = prepare_user("project_crud_scenario@example.com").await;
+        let other_user_id = prepare_user("project_crud_scenario_other@example.com").await;
+        let scope = Scope::new(user_id, None);
+
+        // create
+        let project = repo
+            .create(scope, CreateProject::new("[project_crud_scenario] name"))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(project.user_id, user_id);
+
+        // find / all
+        assert_eq!(repo.find(scope, project.id).await.unwrap(), project);
+        let res = repo.find(Scope::new(other_user_id, None), project.id).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let projects = repo.all(scope).await.expect("[all] returned Err");
+        assert!(projects.contains(&project));
+
+        // update
+        let project = repo
+            .update(
+                scope,
+                project.id,
+                UpdateProject::new("[project_crud_scenario] updated"),
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(project.name, "[project_crud_scenario] updated");
+
+        // delete は todo を残して project_id を外す
+        let (todo_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO todos (text, user_id, project_id)
+            VALUES ( '[project_crud_scenario] text', $1, $2 )
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(project.id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+        repo.delete(scope, project.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repo.find(scope, project.id).await.is_err());
+        let (project_id,): (Option<i32>,) =
+            sqlx::query_as("SELECT project_id FROM todos WHERE id = $1")
+                .bind(todo_id)
+                .fetch_one(&pool)
+                .await
+                .expect("failed to fetch todo");
+        assert_eq!(project_id, None);
+
+        sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up todo");
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    impl CreateProject {
+        pub fn new(name: &str) -> Self {
+            Self {
+                name: name.to_string(),
+            }
+        }
+    }
+
+    impl UpdateProject {
+        pub fn new(name: &str) -> Self {
+            Self {
+                name: Some(name.to_string()),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ProjectRepositoryForMemory {
+        store: Arc<RwLock<Vec<Project>>>,
+    }
+
+    impl ProjectRepositoryForMemory {
+        pub fn new() -> Self {
+            ProjectRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ProjectRepository for ProjectRepositoryForMemory {
+        async fn begin(&self) -> anyhow::Result<Tx> {
+            Ok(Tx::memory())
+        }
+
+        async fn create_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            payload: CreateProject,
+        ) -> anyhow::Result<Project> {
+            let mut store = self.store.write().unwrap();
+            let project = Project {
+                id: store.iter().map(|project| project.id).max().unwrap_or(0) + 1,
+                name: payload.name,
+                user_id: scope.user_id,
+                workspace_id: scope.workspace_id,
+                created_at: Utc::now(),
+            };
+            store.push(project.clone());
+            Ok(project)
+        }
+
+        async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Project> {
+            let store = self.store.read().unwrap();
+            let project = store
+                .iter()
+                .find(|project| project.id == id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            if !project.is_visible_in(scope) {
+                return Err(RepositoryError::Forbidden(id).into());
+            }
+            Ok(project)
+        }
+
+        async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Project>> {
+            let store = self.store.read().unwrap();
+            Ok(store
+                .iter()
+                .filter(|project| project.is_visible_in(scope))
+                .cloned()
+                .collect())
+        }
+
+        async fn update_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            payload: UpdateProject,
+        ) -> anyhow::Result<Project> {
+            self.find(scope, id).await?;
+            let mut store = self.store.write().unwrap();
+            let project = store
+                .iter_mut()
+                .find(|project| project.id == id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            if let Some(name) = payload.name {
+                project.name = name;
+            }
+            Ok(project.clone())
+        }
+
+        async fn delete_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+            self.find(scope, id).await?;
+            self.store
+                .write()
+                .unwrap()
+                .retain(|project| project.id != id);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn project_crud_scenario() {
+            let repo = ProjectRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let project = repo
+                .create(scope, CreateProject::new("project"))
+                .await
+                .expect("failed create project");
+            let workspace_project = repo
+                .create(
+                    Scope::new(1, Some(1)),
+                    CreateProject::new("workspace project"),
+                )
+                .await
+                .expect("failed create project");
+
+            assert_eq!(repo.all(scope).await.unwrap(), vec![project.clone()]);
+            assert!(repo.find(scope, workspace_project.id).await.is_err());
+            assert!(repo.find(Scope::personal(2), project.id).await.is_err());
+
+            let updated = repo
+                .update(scope, project.id, UpdateProject::new("renamed"))
+                .await
+                .expect("failed update project");
+            assert_eq!(updated.name, "renamed");
+
+            repo.delete(scope, project.id)
+                .await
+                .expect("failed delete project");
+            assert!(repo.all(scope).await.unwrap().is_empty());
+        }
+    }
+}
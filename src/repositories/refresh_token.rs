@@ -0,0 +1,402 @@
+use super::RepositoryError;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use sqlx::{FromRow, PgPool};
+
+#[async_trait]
+pub trait RefreshTokenRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 新しい family のトークンを発行する (ログイン時)
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateRefreshToken,
+    ) -> anyhow::Result<RefreshToken>;
+    // token_hash のトークンを失効させ、同じ family で新しいトークンを発行する
+    // 失効済みのトークンが使われた場合は漏洩とみなして family ごと失効させる
+    async fn rotate(
+        &self,
+        token_hash: &str,
+        payload: CreateRefreshToken,
+    ) -> anyhow::Result<RefreshToken>;
+    async fn revoke_all(&self, user_id: i32) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// トークンそのものは handler 側で生成し、ハッシュだけを渡す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateRefreshToken {
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn new_family_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RefreshTokenRepositoryForDb {
+    pool: PgPool,
+}
+
+impl RefreshTokenRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for RefreshTokenRepositoryForDb {
+    #[tracing::instrument(name = "RefreshTokenRepository::create", skip_all)]
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateRefreshToken,
+    ) -> anyhow::Result<RefreshToken> {
+        let token = sqlx::query_as::<_, RefreshToken>(
+            r#"
+            INSERT INTO refresh_tokens (user_id, token_hash, family_id, expires_at)
+            VALUES ( $1, $2, $3, $4 )
+            RETURNING id, user_id, family_id, expires_at, revoked_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(payload.token_hash)
+        .bind(new_family_id())
+        .bind(payload.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    #[tracing::instrument(name = "RefreshTokenRepository::rotate", skip_all)]
+    async fn rotate(
+        &self,
+        token_hash: &str,
+        payload: CreateRefreshToken,
+    ) -> anyhow::Result<RefreshToken> {
+        let mut tx = self.pool.begin().await?;
+
+        // 同じトークンでの同時リクエストを直列化するため行ロックを取る
+        let current = sqlx::query_as::<_, RefreshToken>(
+            r#"
+            SELECT id, user_id, family_id, expires_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(0))?;
+
+        if current.revoked_at.is_some() {
+            sqlx::query(
+                r#"
+                UPDATE refresh_tokens SET revoked_at = now()
+                WHERE family_id = $1 AND revoked_at IS NULL
+                "#,
+            )
+            .bind(current.family_id.clone())
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await?;
+            return Err(RepositoryError::Forbidden(current.id).into());
+        }
+        if current.expires_at <= Utc::now() {
+            return Err(RepositoryError::Forbidden(current.id).into());
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens SET revoked_at = now() WHERE id = $1
+            "#,
+        )
+        .bind(current.id)
+        .execute(&mut tx)
+        .await?;
+
+        let token = sqlx::query_as::<_, RefreshToken>(
+            r#"
+            INSERT INTO refresh_tokens (user_id, token_hash, family_id, expires_at)
+            VALUES ( $1, $2, $3, $4 )
+            RETURNING id, user_id, family_id, expires_at, revoked_at
+            "#,
+        )
+        .bind(current.user_id)
+        .bind(payload.token_hash)
+        .bind(current.family_id)
+        .bind(payload.expires_at)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    #[tracing::instrument(name = "RefreshTokenRepository::revoke_all", skip_all)]
+    async fn revoke_all(&self, user_id: i32) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens SET revoked_at = now()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    fn payload(token_hash: &str) -> CreateRefreshToken {
+        CreateRefreshToken {
+            token_hash: token_hash.to_string(),
+            expires_at: Utc::now() + Duration::days(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = RefreshTokenRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'refresh_token_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        // ハッシュは unique なので前回のテストのデータを消しておく
+        sqlx::query("DELETE FROM refresh_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up refresh tokens");
+
+        // create
+        let first = repo
+            .create(user_id, payload("db-first"))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(first.user_id, user_id);
+
+        // rotate
+        let second = repo
+            .rotate("db-first", payload("db-second"))
+            .await
+            .expect("[rotate] returned Err");
+        assert_eq!(second.family_id, first.family_id);
+
+        // 使用済みトークンの再利用で family ごと失効する
+        assert!(repo.rotate("db-first", payload("db-third")).await.is_err());
+        assert!(repo.rotate("db-second", payload("db-third")).await.is_err());
+
+        // revoke_all
+        repo.create(user_id, payload("db-other-family"))
+            .await
+            .expect("[create] returned Err");
+        repo.revoke_all(user_id)
+            .await
+            .expect("[revoke_all] returned Err");
+        assert!(repo
+            .rotate("db-other-family", payload("db-fourth"))
+            .await
+            .is_err());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockWriteGuard},
+    };
+
+    use super::*;
+
+    // token_hash -> RefreshToken
+    type RefreshTokenDatas = HashMap<String, RefreshToken>;
+
+    #[derive(Debug, Clone)]
+    pub struct RefreshTokenRepositoryForMemory {
+        store: Arc<RwLock<RefreshTokenDatas>>,
+    }
+
+    impl RefreshTokenRepositoryForMemory {
+        pub fn new() -> Self {
+            RefreshTokenRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, RefreshTokenDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn insert(
+            store: &mut RefreshTokenDatas,
+            user_id: i32,
+            family_id: String,
+            payload: CreateRefreshToken,
+        ) -> RefreshToken {
+            let token = RefreshToken {
+                id: (store.len() + 1) as i32,
+                user_id,
+                family_id,
+                expires_at: payload.expires_at,
+                revoked_at: None,
+            };
+            store.insert(payload.token_hash, token.clone());
+            token
+        }
+    }
+
+    #[async_trait]
+    impl RefreshTokenRepository for RefreshTokenRepositoryForMemory {
+        async fn create(
+            &self,
+            user_id: i32,
+            payload: CreateRefreshToken,
+        ) -> anyhow::Result<RefreshToken> {
+            let mut store = self.write_store_ref();
+            Ok(Self::insert(&mut store, user_id, new_family_id(), payload))
+        }
+
+        async fn rotate(
+            &self,
+            token_hash: &str,
+            payload: CreateRefreshToken,
+        ) -> anyhow::Result<RefreshToken> {
+            let mut store = self.write_store_ref();
+            let current = store
+                .get(token_hash)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(0))?;
+
+            if current.revoked_at.is_some() {
+                for token in store.values_mut() {
+                    if token.family_id == current.family_id && token.revoked_at.is_none() {
+                        token.revoked_at = Some(Utc::now());
+                    }
+                }
+                return Err(RepositoryError::Forbidden(current.id).into());
+            }
+            if current.expires_at <= Utc::now() {
+                return Err(RepositoryError::Forbidden(current.id).into());
+            }
+
+            if let Some(token) = store.get_mut(token_hash) {
+                token.revoked_at = Some(Utc::now());
+            }
+            Ok(Self::insert(
+                &mut store,
+                current.user_id,
+                current.family_id,
+                payload,
+            ))
+        }
+
+        async fn revoke_all(&self, user_id: i32) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            for token in store.values_mut() {
+                if token.user_id == user_id && token.revoked_at.is_none() {
+                    token.revoked_at = Some(Utc::now());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use chrono::Duration;
+
+        fn payload(token_hash: &str) -> CreateRefreshToken {
+            CreateRefreshToken {
+                token_hash: token_hash.to_string(),
+                expires_at: Utc::now() + Duration::days(1),
+            }
+        }
+
+        #[tokio::test]
+        async fn refresh_token_rotation_scenario() {
+            let repo = RefreshTokenRepositoryForMemory::new();
+
+            // create
+            let first = repo
+                .create(1, payload("first"))
+                .await
+                .expect("failed create refresh token");
+
+            // rotate
+            let second = repo
+                .rotate("first", payload("second"))
+                .await
+                .expect("failed rotate refresh token");
+            assert_eq!(second.user_id, 1);
+            assert_eq!(second.family_id, first.family_id);
+
+            // 使用済みトークンの再利用は family ごと失効させる
+            assert!(repo.rotate("first", payload("third")).await.is_err());
+            assert!(repo.rotate("second", payload("third")).await.is_err());
+
+            // 期限切れ
+            repo.create(
+                1,
+                CreateRefreshToken {
+                    token_hash: "expired".to_string(),
+                    expires_at: Utc::now() - Duration::days(1),
+                },
+            )
+            .await
+            .expect("failed create refresh token");
+            assert!(repo.rotate("expired", payload("fourth")).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn revoke_all_scenario() {
+            let repo = RefreshTokenRepositoryForMemory::new();
+            repo.create(1, payload("user 1")).await.unwrap();
+            repo.create(2, payload("user 2")).await.unwrap();
+
+            repo.revoke_all(1).await.expect("failed revoke all");
+            assert!(repo.rotate("user 1", payload("next 1")).await.is_err());
+            assert!(repo.rotate("user 2", payload("next 2")).await.is_ok());
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use super::RepositoryError;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use validator::{Validate, ValidationError};
+
+#[async_trait]
+pub trait ReminderRepository: std::marker::Send + std::marker::Sync + 'static {
+    // todo を見られるかどうかは handler 側で確認する
+    async fn create(
+        &self,
+        user_id: i32,
+        todo_id: i32,
+        payload: CreateReminder,
+    ) -> anyhow::Result<Reminder>;
+    // user_id が todo_id に設定した通知を remind_at の早い順に返す
+    async fn all_by_todo(&self, user_id: i32, todo_id: i32) -> anyhow::Result<Vec<Reminder>>;
+    // 他人の通知は消せない. 存在しない場合と同じく RepositoryError::NotFound
+    async fn delete(&self, user_id: i32, todo_id: i32, id: i32) -> anyhow::Result<()>;
+    // remind_at を過ぎた未送信の通知を送信済みにして返す. 同じ通知は一度しか返さない
+    async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueReminder>>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    // サーバーのログに出すだけ
+    #[default]
+    Log,
+    Email,
+    Webhook,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Reminder {
+    pub id: i32,
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub remind_at: DateTime<Utc>,
+    pub channel: Channel,
+    pub url: Option<String>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+// 送信に必要な todo とユーザーの情報を付けたもの
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct DueReminder {
+    pub id: i32,
+    pub todo_id: i32,
+    pub remind_at: DateTime<Utc>,
+    pub channel: Channel,
+    pub url: Option<String>,
+    pub text: String,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[validate(schema(function = "validate_channel"))]
+pub struct CreateReminder {
+    pub remind_at: DateTime<Utc>,
+    #[serde(default)]
+    pub channel: Channel,
+    #[serde(default)]
+    #[validate(url(message = "Invalid url"))]
+    pub url: Option<String>,
+}
+
+// webhook には送信先が必要
+fn validate_channel(payload: &CreateReminder) -> Result<(), ValidationError> {
+    (payload.channel != Channel::Webhook || payload.url.is_some())
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Webhook reminders need a url"))
+}
+
+#[derive(Debug, Clone)]
+pub struct ReminderRepositoryForDb {
+    pool: PgPool,
+}
+
+impl ReminderRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ReminderRepository for ReminderRepositoryForDb {
+    #[tracing::instrument(name = "ReminderRepository::create", skip_all)]
+    async fn create(
+        &self,
+        user_id: i32,
+        todo_id: i32,
+        payload: CreateReminder,
+    ) -> anyhow::Result<Reminder> {
+        let reminder = sqlx::query_as::<_, Reminder>(
+            r#"
+            INSERT INTO reminders (todo_id, user_id, remind_at, channel, url)
+            VALUES ( $1, $2, $3, $4, $5 )
+            RETURNING id, todo_id, user_id, remind_at, channel, url, sent_at
+            "#,
+        )
+        .bind(todo_id)
+        .bind(user_id)
+        .bind(payload.remind_at)
+        .bind(payload.channel)
+        .bind(payload.url)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(reminder)
+    }
+
+    #[tracing::instrument(name = "ReminderRepository::all_by_todo", skip_all)]
+    async fn all_by_todo(&self, user_id: i32, todo_id: i32) -> anyhow::Result<Vec<Reminder>> {
+        let reminders = sqlx::query_as::<_, Reminder>(
+            r#"
+            SELECT id, todo_id, user_id, remind_at, channel, url, sent_at
+            FROM reminders
+            WHERE user_id = $1 AND todo_id = $2
+            ORDER BY remind_at, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(todo_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reminders)
+    }
+
+    #[tracing::instrument(name = "ReminderRepository::delete", skip_all)]
+    async fn delete(&self, user_id: i32, todo_id: i32, id: i32) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM reminders WHERE id = $1 AND user_id = $2 AND todo_id = $3
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(todo_id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "ReminderRepository::take_due", skip_all)]
+    async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueReminder>> {
+        // 複数の worker が動いていても同じ通知を二度送らないよう、送信済みにしてから返す
+        let reminders = sqlx::query_as::<_, DueReminder>(
+            r#"
+            WITH due AS (
+                UPDATE reminders SET sent_at = $1
+                WHERE id IN (
+                    SELECT id FROM reminders
+                    WHERE sent_at IS NULL AND remind_at <= $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, todo_id, user_id, remind_at, channel, url
+            )
+            SELECT due.id, due.todo_id, due.remind_at, due.channel, due.url, todos.text, users.email
+            FROM due
+                INNER JOIN todos ON todos.id = due.todo_id
+                INNER JOIN users ON users.id = due.user_id
+            ORDER BY due.remind_at, due.id
+            "#,
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reminders)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = ReminderRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'reminder_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        let (todo_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO todos (text, user_id)
+            VALUES ( '[reminder_crud_scenario] text', $1 )
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+        let now = Utc::now();
+        let create = |remind_at: DateTime<Utc>| CreateReminder {
+            remind_at,
+            channel: Channel::Email,
+            url: None,
+        };
+
+        // create
+        let due = repo
+            .create(user_id, todo_id, create(now - Duration::minutes(1)))
+            .await
+            .expect("[create] returned Err");
+        let later = repo
+            .create(user_id, todo_id, create(now + Duration::hours(1)))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(due.sent_at, None);
+
+        // all_by_todo
+        let reminders = repo
+            .all_by_todo(user_id, todo_id)
+            .await
+            .expect("[all_by_todo] returned Err");
+        assert_eq!(reminders, vec![due.clone(), later.clone()]);
+
+        // take_due は期限を過ぎたものを一度だけ返す
+        let taken = repo.take_due(now).await.expect("[take_due] returned Err");
+        let taken = taken
+            .iter()
+            .find(|reminder| reminder.id == due.id)
+            .expect("due reminder is not taken");
+        assert_eq!(taken.text, "[reminder_crud_scenario] text");
+        assert_eq!(taken.email, "reminder_crud_scenario@example.com");
+        let taken = repo.take_due(now).await.expect("[take_due] returned Err");
+        assert!(taken
+            .iter()
+            .all(|reminder| reminder.id != due.id && reminder.id != later.id));
+
+        // delete
+        assert!(repo.delete(user_id + 1, todo_id, later.id).await.is_err());
+        repo.delete(user_id, todo_id, later.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repo.delete(user_id, todo_id, later.id).await.is_err());
+
+        // todo を消したら通知も消える
+        sqlx::query("DELETE FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up todo");
+        let reminders = repo
+            .all_by_todo(user_id, todo_id)
+            .await
+            .expect("[all_by_todo] returned Err");
+        assert!(reminders.is_empty());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    pub struct ReminderRepositoryForMemory {
+        store: Arc<RwLock<Vec<Reminder>>>,
+    }
+
+    impl ReminderRepositoryForMemory {
+        pub fn new() -> Self {
+            ReminderRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ReminderRepository for ReminderRepositoryForMemory {
+        async fn create(
+            &self,
+            user_id: i32,
+            todo_id: i32,
+            payload: CreateReminder,
+        ) -> anyhow::Result<Reminder> {
+            let mut store = self.store.write().unwrap();
+            let reminder = Reminder {
+                id: store.iter().map(|reminder| reminder.id).max().unwrap_or(0) + 1,
+                todo_id,
+                user_id,
+                remind_at: payload.remind_at,
+                channel: payload.channel,
+                url: payload.url,
+                sent_at: None,
+            };
+            store.push(reminder.clone());
+            Ok(reminder)
+        }
+
+        async fn all_by_todo(&self, user_id: i32, todo_id: i32) -> anyhow::Result<Vec<Reminder>> {
+            let mut reminders: Vec<Reminder> = self
+                .store
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|reminder| reminder.user_id == user_id && reminder.todo_id == todo_id)
+                .cloned()
+                .collect();
+            reminders.sort_by_key(|reminder| (reminder.remind_at, reminder.id));
+            Ok(reminders)
+        }
+
+        async fn delete(&self, user_id: i32, todo_id: i32, id: i32) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            let len = store.len();
+            store.retain(|reminder| {
+                !(reminder.id == id && reminder.user_id == user_id && reminder.todo_id == todo_id)
+            });
+            if store.len() == len {
+                return Err(RepositoryError::NotFound(id).into());
+            }
+            Ok(())
+        }
+
+        // todo とユーザーは持っていないので text と email は空になる
+        async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueReminder>> {
+            let mut store = self.store.write().unwrap();
+            let mut due = vec![];
+            for reminder in store.iter_mut() {
+                if reminder.sent_at.is_none() && reminder.remind_at <= now {
+                    reminder.sent_at = Some(now);
+                    due.push(DueReminder {
+                        id: reminder.id,
+                        todo_id: reminder.todo_id,
+                        remind_at: reminder.remind_at,
+                        channel: reminder.channel,
+                        url: reminder.url.clone(),
+                        text: String::new(),
+                        email: String::new(),
+                    });
+                }
+            }
+            due.sort_by_key(|reminder| (reminder.remind_at, reminder.id));
+            Ok(due)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use chrono::Duration;
+
+        #[tokio::test]
+        async fn reminder_scenario() {
+            let repo = ReminderRepositoryForMemory::new();
+            let now = Utc::now();
+            let create = |remind_at: DateTime<Utc>| CreateReminder {
+                remind_at,
+                channel: Channel::Log,
+                url: None,
+            };
+            let later = repo
+                .create(1, 1, create(now + Duration::hours(1)))
+                .await
+                .expect("failed create reminder");
+            let due = repo
+                .create(1, 1, create(now - Duration::hours(1)))
+                .await
+                .expect("failed create reminder");
+            repo.create(2, 1, create(now))
+                .await
+                .expect("failed create reminder");
+
+            // 自分の通知だけを早い順に
+            let reminders = repo.all_by_todo(1, 1).await.unwrap();
+            assert_eq!(reminders, vec![due.clone(), later.clone()]);
+
+            // 期限を過ぎたものを一度だけ
+            let taken = repo.take_due(now).await.unwrap();
+            let ids: Vec<i32> = taken.iter().map(|reminder| reminder.id).collect();
+            assert_eq!(ids, vec![due.id, 3]);
+            assert!(repo.take_due(now).await.unwrap().is_empty());
+
+            // 他人の通知は消せない
+            assert!(repo.delete(2, 1, later.id).await.is_err());
+            repo.delete(1, 1, later.id).await.unwrap();
+            assert_eq!(repo.all_by_todo(1, 1).await.unwrap().len(), 1);
+        }
+
+        #[test]
+        fn should_require_url_for_webhook() {
+            let payload = |channel, url: Option<&str>| CreateReminder {
+                remind_at: Utc::now(),
+                channel,
+                url: url.map(str::to_string),
+            };
+            assert!(payload(Channel::Webhook, None).validate().is_err());
+            assert!(payload(Channel::Webhook, Some("not a url"))
+                .validate()
+                .is_err());
+            assert!(payload(Channel::Webhook, Some("https://example.com/hook"))
+                .validate()
+                .is_ok());
+            assert!(payload(Channel::Email, None).validate().is_ok());
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use super::{todo::Priority, RepositoryError, Scope, Tx};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use validator::{Validate, ValidationError};
+
+// scope の外 (他人のテンプレートや別 workspace のテンプレート) に触れようとした場合は RepositoryError::Forbidden を返す
+#[async_trait]
+pub trait TemplateRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 書き込みをまとめるトランザクションを始める. *_in は handler が始めた tx の中で書き込み、監査ログも同じ tx で残す
+    // *_in のない方はテスト用で、その場で tx を始めて commit する
+    async fn begin(&self) -> anyhow::Result<Tx>;
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateTemplate,
+    ) -> anyhow::Result<Template>;
+    #[cfg(test)]
+    async fn create(&self, scope: Scope, payload: CreateTemplate) -> anyhow::Result<Template> {
+        let mut tx = self.begin().await?;
+        let result = self.create_in(&mut tx, scope, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Template>;
+    // 古い順
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Template>>;
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTemplate,
+    ) -> anyhow::Result<Template>;
+    #[cfg(test)]
+    async fn update(
+        &self,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTemplate,
+    ) -> anyhow::Result<Template> {
+        let mut tx = self.begin().await?;
+        let result = self.update_in(&mut tx, scope, id, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // テンプレートから作った todo はそのまま残る
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()>;
+    #[cfg(test)]
+    async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        let result = self.delete_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Template {
+    pub id: i32,
+    pub name: String,
+    // 以下はテンプレートから作る todo の初期値
+    pub text: String,
+    pub description: Option<String>,
+    pub priority: Priority,
+    pub labels: Vec<i32>,
+    // サブタスクにする text. この順で作る
+    pub subtasks: Vec<String>,
+    pub user_id: i32,
+    // workspace に属さない個人のテンプレートは None
+    pub workspace_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Template {
+    // Todo::is_visible_in と同じ判定
+    pub fn is_visible_in(&self, scope: Scope) -> bool {
+        match scope.workspace_id {
+            Some(workspace_id) => self.workspace_id == Some(workspace_id),
+            None => self.workspace_id.is_none() && self.user_id == scope.user_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateTemplate {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: String,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    text: String,
+    #[serde(default)]
+    #[validate(length(max = 10000, message = "Over description length"))]
+    description: Option<String>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    labels: Vec<i32>,
+    #[serde(default)]
+    #[validate(custom = "validate_subtasks")]
+    subtasks: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateTemplate {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: Option<String>,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    text: Option<String>,
+    // 省略したら変更しない. null なら説明を消す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[validate(length(max = 10000, message = "Over description length"))]
+    description: Option<Option<String>>,
+    priority: Option<Priority>,
+    labels: Option<Vec<i32>>,
+    #[validate(custom = "validate_subtasks")]
+    subtasks: Option<Vec<String>>,
+}
+
+// null と省略を区別するため、値があれば null でも Some で包む
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// サブタスクの text も todo の text と同じ長さの制限にする
+fn validate_subtasks(subtasks: &[String]) -> Result<(), ValidationError> {
+    if subtasks.len() > 100 {
+        return Err(ValidationError::new("Too many subtasks"));
+    }
+    if subtasks
+        .iter()
+        .any(|text| text.is_empty() || text.chars().count() > 100)
+    {
+        return Err(ValidationError::new("Invalid subtask text length"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplateRepositoryForDb {
+    pool: PgPool,
+}
+
+impl TemplateRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn find_in(
+        &self,
+        conn: &mut PgConnection,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<Template> {
+        let template =
+            sqlx::query_as::<_, Template>(&format!("{} WHERE templates.id = $1", SELECT_TEMPLATES))
+                .bind(id)
+                .fetch_optional(conn)
+                .await?
+                .ok_or(RepositoryError::NotFound(id))?;
+        if !template.is_visible_in(scope) {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+
+        Ok(template)
+    }
+}
+
+// labels は template_labels から集める
+const SELECT_TEMPLATES: &str = r#"
+    SELECT templates.*,
+        ARRAY(
+            SELECT label_id FROM template_labels
+            WHERE template_id = templates.id
+            ORDER BY label_id
+        ) AS labels
+    FROM templates
+"#;
+
+#[async_trait]
+impl TemplateRepository for TemplateRepositoryForDb {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        Tx::begin(&self.pool).await
+    }
+
+    #[tracing::instrument(name = "TemplateRepository::create", skip_all)]
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateTemplate,
+    ) -> anyhow::Result<Template> {
+        let conn = tx.conn()?;
+
+        let (id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO templates (name, text, description, priority, subtasks, user_id, workspace_id)
+            VALUES ( $1, $2, $3, $4, $5, $6, $7 )
+            RETURNING id
+            "#,
+        )
+        .bind(payload.name)
+        .bind(payload.text)
+        .bind(payload.description)
+        .bind(payload.priority)
+        .bind(payload.subtasks)
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO template_labels (template_id, label_id)
+            SELECT DISTINCT $1, id
+            FROM unnest($2) as t(id)
+            "#,
+        )
+        .bind(id)
+        .bind(payload.labels)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_in(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TemplateRepository::find", skip_all)]
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Template> {
+        let mut conn = self.pool.acquire().await?;
+        self.find_in(&mut conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TemplateRepository::all", skip_all)]
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Template>> {
+        let templates = sqlx::query_as::<_, Template>(&format!(
+            r#"
+            {}
+            WHERE ($2::INTEGER IS NULL AND workspace_id IS NULL AND user_id = $1)
+                OR workspace_id = $2
+            ORDER BY id
+            "#,
+            SELECT_TEMPLATES
+        ))
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(templates)
+    }
+
+    #[tracing::instrument(name = "TemplateRepository::update", skip_all)]
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTemplate,
+    ) -> anyhow::Result<Template> {
+        let conn = tx.conn()?;
+        let old_template = self.find_in(conn, scope, id).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE templates SET name = $1, text = $2, description = $3, priority = $4, subtasks = $5
+            WHERE id = $6
+            "#,
+        )
+        .bind(payload.name.unwrap_or(old_template.name))
+        .bind(payload.text.unwrap_or(old_template.text))
+        .bind(payload.description.unwrap_or(old_template.description))
+        .bind(payload.priority.unwrap_or(old_template.priority))
+        .bind(payload.subtasks.unwrap_or(old_template.subtasks))
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+        if let Some(labels) = payload.labels {
+            sqlx::query("DELETE FROM template_labels WHERE template_id = $1")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO template_labels (template_id, label_id)
+                SELECT DISTINCT $1, id
+                FROM unnest($2) as t(id)
+                "#,
+            )
+            .bind(id)
+            .bind(labels)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        self.find_in(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TemplateRepository::delete", skip_all)]
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let conn = tx.conn()?;
+        self.find_in(conn, scope, id).await?;
+        // template_labels は ON DELETE CASCADE で消える
+        sqlx::query("DELETE FROM templates WHERE id = $1")
+            .bind(id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = TemplateRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'template_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        let (label_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO labels (name, user_id)
+            VALUES ( '[template_crud_scenario] label', $1 )
+            ON CONFLICT (user_id, lower(name)) WHERE workspace_id IS NULL
+            DO UPDATE SET name = EXCLUDED.name
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare label data.");
+        let scope = Scope::new(user_id, None);
+
+        // create
+        let template = repo
+            .create(
+                scope,
+                CreateTemplate::new(
+                    "[template_crud_scenario] name",
+                    vec![label_id],
+                    vec!["first", "second"],
+                ),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(template.labels, vec![label_id]);
+        assert_eq!(template.subtasks, vec!["first", "second"]);
+
+        // find / all
+        assert_eq!(repo.find(scope, template.id).await.unwrap(), template);
+        let res = repo.find(Scope::new(user_id, Some(-1)), template.id).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let templates = repo.all(scope).await.expect("[all] returned Err");
+        assert!(templates.contains(&template));
+
+        // update
+        let template = repo
+            .update(
+                scope,
+                template.id,
+                UpdateTemplate {
+                    priority: Some(Priority::High),
+                    subtasks: Some(vec!["only".to_string()]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(template.priority, Priority::High);
+        assert_eq!(template.subtasks, vec!["only"]);
+        assert_eq!(template.labels, vec![label_id]);
+
+        // ラベルを消すとテンプレートからも外れる
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label_id)
+            .execute(&pool)
+            .await
+            .expect("failed to delete label");
+        let template = repo.find(scope, template.id).await.unwrap();
+        assert!(template.labels.is_empty());
+
+        // delete
+        repo.delete(scope, template.id)
+            .await
+            .expect("[delete] returned Err");
+        let res = repo.find(scope, template.id).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::NotFound(_))
+        ));
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    impl CreateTemplate {
+        pub fn new(text: &str, labels: Vec<i32>, subtasks: Vec<&str>) -> Self {
+            Self {
+                name: text.to_string(),
+                text: text.to_string(),
+                description: None,
+                priority: Priority::default(),
+                labels,
+                subtasks: subtasks.into_iter().map(str::to_string).collect(),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct TemplateRepositoryForMemory {
+        store: Arc<RwLock<Vec<Template>>>,
+    }
+
+    impl TemplateRepositoryForMemory {
+        pub fn new() -> Self {
+            TemplateRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TemplateRepository for TemplateRepositoryForMemory {
+        async fn begin(&self) -> anyhow::Result<Tx> {
+            Ok(Tx::memory())
+        }
+
+        async fn create_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            payload: CreateTemplate,
+        ) -> anyhow::Result<Template> {
+            let mut store = self.store.write().unwrap();
+            let mut labels = payload.labels;
+            labels.sort_unstable();
+            labels.dedup();
+            let template = Template {
+                id: store.iter().map(|template| template.id).max().unwrap_or(0) + 1,
+                name: payload.name,
+                text: payload.text,
+                description: payload.description,
+                priority: payload.priority,
+                labels,
+                subtasks: payload.subtasks,
+                user_id: scope.user_id,
+                workspace_id: scope.workspace_id,
+                created_at: Utc::now(),
+            };
+            store.push(template.clone());
+            Ok(template)
+        }
+
+        async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Template> {
+            let store = self.store.read().unwrap();
+            let template = store
+                .iter()
+                .find(|template| template.id == id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            if !template.is_visible_in(scope) {
+                return Err(RepositoryError::Forbidden(id).into());
+            }
+            Ok(template)
+        }
+
+        async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Template>> {
+            let store = self.store.read().unwrap();
+            Ok(store
+                .iter()
+                .filter(|template| template.is_visible_in(scope))
+                .cloned()
+                .collect())
+        }
+
+        async fn update_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            payload: UpdateTemplate,
+        ) -> anyhow::Result<Template> {
+            self.find(scope, id).await?;
+            let mut store = self.store.write().unwrap();
+            let template = store
+                .iter_mut()
+                .find(|template| template.id == id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            if let Some(name) = payload.name {
+                template.name = name;
+            }
+            if let Some(text) = payload.text {
+                template.text = text;
+            }
+            if let Some(description) = payload.description {
+                template.description = description;
+            }
+            if let Some(priority) = payload.priority {
+                template.priority = priority;
+            }
+            if let Some(mut labels) = payload.labels {
+                labels.sort_unstable();
+                labels.dedup();
+                template.labels = labels;
+            }
+            if let Some(subtasks) = payload.subtasks {
+                template.subtasks = subtasks;
+            }
+            Ok(template.clone())
+        }
+
+        async fn delete_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+            self.find(scope, id).await?;
+            self.store
+                .write()
+                .unwrap()
+                .retain(|template| template.id != id);
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn template_crud_scenario() {
+            let repo = TemplateRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let template = repo
+                .create(
+                    scope,
+                    CreateTemplate::new("template", vec![2, 1, 2], vec!["step"]),
+                )
+                .await
+                .expect("failed create template");
+            assert_eq!(template.labels, vec![1, 2]);
+            assert_eq!(repo.all(scope).await.unwrap(), vec![template.clone()]);
+            assert!(repo.find(Scope::personal(2), template.id).await.is_err());
+
+            let updated = repo
+                .update(
+                    scope,
+                    template.id,
+                    UpdateTemplate {
+                        description: Some(Some("memo".to_string())),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect("failed update template");
+            assert_eq!(updated.description, Some("memo".to_string()));
+            assert_eq!(updated.subtasks, vec!["step"]);
+
+            repo.delete(scope, template.id)
+                .await
+                .expect("failed delete template");
+            assert!(repo.all(scope).await.unwrap().is_empty());
+        }
+
+        #[test]
+        fn validate_subtasks_test() {
+            assert!(CreateTemplate::new("template", vec![], vec!["step"])
+                .validate()
+                .is_ok());
+            assert!(CreateTemplate::new("template", vec![], vec![""])
+                .validate()
+                .is_err());
+            let long = "a".repeat(101);
+            assert!(CreateTemplate::new("template", vec![], vec![long.as_str()])
+                .validate()
+                .is_err());
+        }
+    }
+}
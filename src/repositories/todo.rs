@@ -1,56 +1,1096 @@
 use anyhow::Ok;
 use axum::async_trait;
-use validator::Validate;
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Duration, Months, Utc};
+use pulldown_cmark::escape::escape_html;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{postgres::PgArguments, Arguments, FromRow, PgConnection, PgPool, Row};
+use std::collections::HashMap;
+use validator::{Validate, ValidationError, ValidationErrors};
 
-use super::{label::Label, RepositoryError};
+use super::{
+    attachment::{storage_key, Attachment},
+    escape_like,
+    label::Label,
+    template::Template,
+    Page, PatchOperation, RepositoryError, Scope, SortOrder, Tx,
+};
+use crate::health::HealthCheck;
 
 // Clone, Send, Sync, 'static の多重継承
 // axum でこのレポジトリ機能を共有(?)するために layer という機能を使う。layer を利用するためにこれらを継承する必要がある
 // ここでの「共有」は単一プロセスの中でシングルトン的に扱いたい、という意味合いと勝手に解釈した
 #[async_trait]
-pub trait TodoRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo>;
-    async fn find(&self, id: i32) -> anyhow::Result<Todo>;
-    async fn all(&self) -> anyhow::Result<Vec<Todo>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+pub trait TodoRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 書き込みをまとめるトランザクションを始める. service はこれで始めた tx を *_in に渡し、監査ログも同じ tx で残す
+    // *_in が返す todo は tx の中で読み直したもの. *_in のない方はテスト用で、その場で tx を始めて commit する
+    async fn begin(&self) -> anyhow::Result<Tx>;
+    // scope の外 (他人の todo や別 workspace の todo) に触れようとした場合は RepositoryError::Forbidden を返す
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateTodo,
+    ) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn create(&self, scope: Scope, payload: CreateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.create_in(&mut tx, scope, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 1 つのトランザクションでまとめて作り、payloads と同じ順で返す. 1 件でも失敗したら何も作らない
+    async fn create_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn create_many(
+        &self,
+        scope: Scope,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.begin().await?;
+        let result = self.create_many_in(&mut tx, scope, payloads).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    // 変更前の todo を tx の中で primary から読み、commit まで行をロックする. 見える範囲は find と同じ
+    async fn find_for_update_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    // scope の一覧でピン留めしている、アーカイブしていない todo の数. 共有された todo は数えない
+    // 一覧の持ち主 (workspace か個人のユーザー) の行をロックするので、同じ一覧へのピン留めは commit まで待つ
+    async fn count_pinned_in(&self, tx: &mut Tx, scope: Scope) -> anyhow::Result<i64>;
+    async fn all(&self, scope: Scope, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>>;
+    // all と同じ順で page の範囲だけを返し、filter に一致した件数と一緒に返す
+    async fn page(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)>;
+    // label_id のラベルを付けたアーカイブしていない todo を、絞り込まない all と同じ順で page の範囲だけ返す
+    // todo_labels と join して絞り込み、見える範囲は all と同じ. 件数も一緒に返す
+    async fn page_by_label(
+        &self,
+        scope: Scope,
+        label_id: i32,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)>;
+    // all と同じ順で cursor より後の todo を limit 件まで返す. 続きがあれば最後の todo の位置も返す
+    // offset を使わずに cursor の値で絞り込むので、後ろのページでも遅くならない
+    async fn page_after(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        cursor: Option<&TodoCursor>,
+        limit: i64,
+    ) -> anyhow::Result<(Vec<Todo>, Option<TodoCursor>)>;
+    // text と description の全文検索. アーカイブしていない todo から一致度の高い順に SEARCH_LIMIT 件まで返す
+    // 検索する範囲は all と同じで、個人の一覧なら共有された todo も含む
+    async fn search(&self, scope: Scope, query: &str) -> anyhow::Result<Vec<TodoSearchHit>>;
+    // 管理者向け. 所有者に関係なく全ユーザーの todo を返す
+    async fn all_unscoped(&self) -> anyhow::Result<Vec<Todo>>;
+    // データのエクスポート向け. workspace のものも含めて user_id が作成した todo を返す
+    async fn all_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Todo>>;
+    // 共有された todo は permission に応じて閲覧・更新できるが、削除と再共有はできない
+    // 親子関係が循環する場合や、親が別の所有者の todo の場合は RepositoryError::Invalid を返す
+    // 変わったフィールドは scope のユーザーの変更として履歴に残す. complete_subtasks で完了にしたサブタスクも同じ
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn update(&self, scope: Scope, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.update_in(&mut tx, scope, id, payload).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 更新の履歴. 古い順
+    async fn history(&self, scope: Scope, id: i32) -> anyhow::Result<Vec<TodoRevision>>;
+    // 別の todo の履歴は RepositoryError::NotFound
+    async fn revision(
+        &self,
+        scope: Scope,
+        id: i32,
+        revision_id: i32,
+    ) -> anyhow::Result<TodoRevision>;
+    // 一覧に出る todo を作成・完了した出来事を新しい順に limit 件まで返す (フィード向け). 完了した日時は履歴から拾う
+    async fn activity(&self, scope: Scope, limit: usize) -> anyhow::Result<Vec<TodoActivity>>;
+    // scope の一覧 (個人 or workspace) でラベルごとに未完了と done の todo を数える. アーカイブした todo と共有された todo は数えない
+    // 1 つも付いていないラベルは返さない. DB では最後に refresh_label_usage で数え直した時点の件数
+    async fn label_usage(&self, scope: Scope) -> anyhow::Result<Vec<LabelUsage>>;
+    // label_usage の件数を数え直す. scheduler から定期的に呼ぶ
+    async fn refresh_label_usage(&self) -> anyhow::Result<()>;
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()>;
+    #[cfg(test)]
+    async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        let result = self.delete_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // selection に一致する todo をまとめて done にし、done にする前の todo を返す. 1 つの UPDATE で行い、履歴も残す
+    // Write で共有された todo も対象. done と cancelled の todo はそのまま残し、返す todo にも含めない
+    async fn complete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<Vec<Todo>>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn complete_many(
+        &self,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.begin().await?;
+        let result = self.complete_many_in(&mut tx, scope, selection).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // selection に一致する todo をまとめて削除し、削除した todo を返す. 1 つの DELETE で行う
+    // delete と同じく共有された todo は対象外. 子はトップレベルの todo として残す
+    async fn delete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<DeletedTodos>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn delete_many(
+        &self,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<DeletedTodos> {
+        let mut tx = self.begin().await?;
+        let result = self.delete_many_in(&mut tx, scope, selection).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // ids の todo にまとめて add のラベルを付け、remove のラベルを外す. 1 つのトランザクションで行い、履歴も残す
+    // Write で共有された todo も対象. 見えない todo とアーカイブした todo は飛ばし、ラベルが変わらなかった todo は返さない
+    // add のラベルが scope で使えるかどうかは呼び出し側で確認する
+    async fn relabel_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        ids: &[i32],
+        add: &[Label],
+        remove: &[i32],
+    ) -> anyhow::Result<Vec<RelabeledTodo>>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn relabel_many(
+        &self,
+        scope: Scope,
+        ids: &[i32],
+        add: &[Label],
+        remove: &[i32],
+    ) -> anyhow::Result<Vec<RelabeledTodo>> {
+        let mut tx = self.begin().await?;
+        let result = self
+            .relabel_many_in(&mut tx, scope, ids, add, remove)
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 監査ログに残したスナップショットの状態に戻す. 削除済みなら同じ id で作り直す (undo 向け)
+    // もう存在しない親・プロジェクト・担当者・ラベルは外す. 添付ファイル・リマインダー・共有と、削除で外れたサブタスクは戻さない
+    // 存在する todo を戻した場合は update と同じく履歴に残す
+    // アーカイブ・ピン留め・スヌーズ・並び順は共有されたユーザーは戻せない
+    async fn restore_in(&self, tx: &mut Tx, scope: Scope, snapshot: Todo) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn restore(&self, scope: Scope, snapshot: Todo) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.restore_in(&mut tx, scope, snapshot).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 削除の代わりに一覧から外す. 削除と同じく共有されたユーザーはできない
+    // アーカイブ済みの todo をアーカイブしても日時は変えない
+    async fn archive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn archive(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.archive_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn unarchive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn unarchive(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.unarchive_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // アーカイブと同じく共有されたユーザーはできない. ピン留めできる数の上限は呼び出し側で確認する
+    async fn pin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn pin(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.pin_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn unpin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn unpin(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.unpin_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // until まで既定の一覧に出さない. None ならスヌーズを解除する. ピン留めと同じく共有されたユーザーはできない
+    async fn snooze_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn snooze(
+        &self,
+        scope: Scope,
+        id: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.snooze_in(&mut tx, scope, id, until).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 担当者を設定する. None なら担当を外す. Write で共有されたユーザーもできる
+    // 担当者が workspace のメンバーかどうかは呼び出し側で確認する
+    async fn assign_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        assignee_id: Option<i32>,
+    ) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn assign(
+        &self,
+        scope: Scope,
+        id: i32,
+        assignee_id: Option<i32>,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.assign_in(&mut tx, scope, id, assignee_id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // 同じ一覧 (個人 or workspace) の中で並び順を変える. 共有されたユーザーはできない
+    // before / after に別の一覧の todo を指定した場合は RepositoryError::Invalid を返す
+    async fn move_to_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        target: MoveTodo,
+    ) -> anyhow::Result<Todo>;
+    #[cfg(test)]
+    async fn move_to(&self, scope: Scope, id: i32, target: MoveTodo) -> anyhow::Result<Todo> {
+        let mut tx = self.begin().await?;
+        let result = self.move_to_in(&mut tx, scope, id, target).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // サブタスク (孫以下も含む)・ラベル・添付ファイルのメタデータごと複製する. 共有されたユーザーはできない
+    // 複製はアーカイブされておらず、一覧の末尾に並ぶ. リマインダー・共有・担当者・ピン留め・スヌーズは複製しない
+    async fn duplicate_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<DuplicatedTodo>;
+    #[cfg(test)]
+    #[allow(dead_code)]
+    async fn duplicate(&self, scope: Scope, id: i32) -> anyhow::Result<DuplicatedTodo> {
+        let mut tx = self.begin().await?;
+        let result = self.duplicate_in(&mut tx, scope, id).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    async fn share(&self, scope: Scope, id: i32, payload: ShareTodo) -> anyhow::Result<TodoShare>;
+    // from_user_id の個人の todo を into_user_id に付け替え、付け替えた todo を返す
+    // ゲストユーザーのデータを登録済みのアカウントに引き継ぐときに使う
+    async fn merge(&self, from_user_id: i32, into_user_id: i32) -> anyhow::Result<Vec<Todo>>;
+    // 完了済みの繰り返し todo から次の todo を作り、作った todo を返す. scheduler から定期的に呼ぶ
+    // 一度次の todo を作った todo は、完了を取り消して再度完了にしても対象にならない
+    async fn materialize_recurrences_in(
+        &self,
+        tx: &mut Tx,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>>;
+    #[cfg(test)]
+    async fn materialize_recurrences(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.begin().await?;
+        let result = self.materialize_recurrences_in(&mut tx, now).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+    // before より前にアーカイブした todo を全ユーザー分消し、消した todo を返す. scheduler から定期的に呼ぶ
+    // アーカイブしていないサブタスクは残してトップレベルの todo にする
+    async fn purge_archived_in(
+        &self,
+        tx: &mut Tx,
+        before: DateTime<Utc>,
+    ) -> anyhow::Result<DeletedTodos>;
+    #[cfg(test)]
+    async fn purge_archived(&self, before: DateTime<Utc>) -> anyhow::Result<DeletedTodos> {
+        let mut tx = self.begin().await?;
+        let result = self.purge_archived_in(&mut tx, before).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 }
 
-
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
 pub struct TodoFromRow {
     id: i32,
     text: String,
-    completed: bool,
+    description: Option<String>,
+    status: Status,
+    user_id: i32,
+    workspace_id: Option<i32>,
+    due_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    priority: Priority,
+    parent_id: Option<i32>,
+    recurrence_freq: Option<Frequency>,
+    recurrence_interval: i32,
+    recurrence_until: Option<DateTime<Utc>>,
+    archived_at: Option<DateTime<Utc>>,
+    position: i64,
+    project_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    assignee_id: Option<i32>,
+    is_pinned: bool,
+    snoozed_until: Option<DateTime<Utc>>,
 }
 
 // 以下の Todo に関連する構造体は derive Clone しないと axum の「共有状態」として利用できなくなる(?)
-#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, FromRow)]
 pub struct TodoWithLabelFromRow {
     id: i32,
     text: String,
-    completed: bool,
+    description: Option<String>,
+    status: Status,
+    user_id: i32,
+    workspace_id: Option<i32>,
+    due_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    priority: Priority,
+    parent_id: Option<i32>,
+    recurrence_freq: Option<Frequency>,
+    recurrence_interval: i32,
+    recurrence_until: Option<DateTime<Utc>>,
+    archived_at: Option<DateTime<Utc>>,
+    position: i64,
+    project_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    assignee_id: Option<i32>,
+    is_pinned: bool,
+    snoozed_until: Option<DateTime<Utc>>,
     label_id: Option<i32>,
     label_name: Option<String>,
+    label_user_id: Option<i32>,
+    label_workspace_id: Option<i32>,
+    label_color: Option<String>,
+    label_parent_id: Option<i32>,
+    label_position: Option<i32>,
+    label_archived_at: Option<DateTime<Utc>>,
+    label_icon: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Todo {
     pub id: i32,
     pub text: String,
-    pub completed: bool,
+    // markdown
+    pub description: Option<String>,
+    pub status: Status,
+    pub user_id: i32,
+    pub workspace_id: Option<i32>,
+    pub due_at: Option<DateTime<Utc>>,
+    // 開始日時. 未来の todo は開始するまで既定の一覧に出さない. due_at より後にはできない
+    pub starts_at: Option<DateTime<Utc>>,
+    pub priority: Priority,
+    // サブタスクなら親の todo の id
+    pub parent_id: Option<i32>,
+    pub recurrence: Option<Recurrence>,
+    // アーカイブした日時. アーカイブした todo は一覧に出さない
+    pub archived_at: Option<DateTime<Utc>>,
+    // 手動での並び順. 小さいほど前
+    pub position: i64,
+    pub project_id: Option<i32>,
     pub labels: Vec<Label>,
+    pub created_at: DateTime<Utc>,
+    // 担当者のユーザー id. workspace の todo ならメンバー、個人の todo なら作成者だけを設定できる
+    pub assignee_id: Option<i32>,
+    // ピン留めした todo は sort を省略した一覧で先頭に並ぶ
+    pub is_pinned: bool,
+    // スヌーズした todo はこの日時を過ぎるまで既定の一覧に出さない. 過ぎても値は残る
+    pub snoozed_until: Option<DateTime<Utc>>,
 }
 
-fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<Todo> {
-    let mut rows = rows.iter();
-    let mut result: Vec<Todo> = vec![];
-    'outer: while let Some(row) = rows.next() {
-        let mut todos = result.iter_mut();
+// 宣言順がそのまま優先度の低い順になる
+// DB には check 制約付きの TEXT で保存する
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    // PRIORITY_RANK と同じ値
+    fn rank(self) -> i32 {
+        self as i32 + 1
+    }
+}
+
+// 優先度を並び替えに使う数値にする SQL
+const PRIORITY_RANK: &str =
+    "CASE todos.priority WHEN 'urgent' THEN 4 WHEN 'high' THEN 3 WHEN 'medium' THEN 2 ELSE 1 END";
+
+// かんばんの列. DB には check 制約付きの TEXT で保存する
+// done と cancelled はそれ以上作業しない状態で、期限切れにならず、繰り返しの次の todo を作る
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    Backlog,
+    InProgress,
+    Done,
+    Cancelled,
+}
+
+impl Status {
+    // 許可する状態の遷移. 同じ状態への遷移は何もしないので許可する
+    // done は作業中に差し戻せるが、cancelled は backlog からやり直す
+    pub fn can_transition_to(self, next: Status) -> bool {
+        use Status::*;
+        self == next
+            || matches!(
+                (self, next),
+                (Backlog, InProgress | Done | Cancelled)
+                    | (InProgress, Backlog | Done | Cancelled)
+                    | (Done, Backlog | InProgress)
+                    | (Cancelled, Backlog)
+            )
+    }
+}
+
+// 繰り返しのルール. DB には recurrence_* の列に分けて保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    pub freq: Frequency,
+    // freq の何回分ごとに繰り返すか. 省略したら 1
+    #[serde(default = "default_interval")]
+    pub interval: i32,
+    // この日時より後の todo は作らない
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+fn default_interval() -> i32 {
+    1
+}
+
+impl Recurrence {
+    fn from_row(
+        freq: Option<Frequency>,
+        interval: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> Option<Self> {
+        freq.map(|freq| Self {
+            freq,
+            interval,
+            until,
+        })
+    }
+
+    // 次の todo の期限. 期限のない todo は now を起点にする
+    // 遅れて完了した場合も期限切れの todo を作らないよう、now より後になるまで進める
+    // until を過ぎるなら繰り返しは終わりなので None
+    fn next_due(&self, due_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = due_at.unwrap_or(now);
+        loop {
+            next = match self.freq {
+                Frequency::Daily => {
+                    next.checked_add_signed(Duration::days(self.interval.into()))?
+                }
+                Frequency::Weekly => {
+                    next.checked_add_signed(Duration::weeks(self.interval.into()))?
+                }
+                Frequency::Monthly => next.checked_add_months(Months::new(self.interval as u32))?,
+            };
+            if next > now {
+                break;
+            }
+        }
+        match self.until {
+            Some(until) if next > until => None,
+            _ => Some(next),
+        }
+    }
+}
+
+// 繰り返しで作る次の todo の開始日時. 期限との間隔を保ち、期限のない todo は引き継がない
+fn next_start(
+    starts_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+    next_due: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    Some(next_due - (due_at? - starts_at?))
+}
+
+// 開始日時は期限より後にできない
+pub(super) fn is_valid_schedule(
+    starts_at: Option<DateTime<Utc>>,
+    due_at: Option<DateTime<Utc>>,
+) -> bool {
+    match (starts_at, due_at) {
+        (Some(starts_at), Some(due_at)) => starts_at <= due_at,
+        _ => true,
+    }
+}
+
+pub(super) fn validate_recurrence(recurrence: &Recurrence) -> Result<(), ValidationError> {
+    (recurrence.interval >= 1)
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Invalid recurrence interval"))
+}
+
+impl Todo {
+    // scope から見える todo かどうか
+    // workspace の todo はメンバー全員から、個人の todo は作成者からだけ見える
+    pub fn is_visible_in(&self, scope: Scope) -> bool {
+        match scope.workspace_id {
+            Some(workspace_id) => self.workspace_id == Some(workspace_id),
+            None => self.workspace_id.is_none() && self.user_id == scope.user_id,
+        }
+    }
+}
+
+// GET /todos のクエリパラメータ
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct TodoFilter {
+    // 期限がこの日時より前の todo だけを返す
+    pub due_before: Option<DateTime<Utc>>,
+    // true なら期限切れの todo だけを、false なら期限切れでない todo だけを返す
+    pub overdue: Option<bool>,
+    // 指定した todo の直下のサブタスクだけを返す
+    pub parent_id: Option<i32>,
+    // true ならアーカイブした todo だけを返す. 省略したらアーカイブしていない todo だけを返す
+    pub archived: Option<bool>,
+    // かんばんの列ごとに取得する
+    pub status: Option<Status>,
+    // プロジェクトの todo だけを返す
+    pub project_id: Option<i32>,
+    // true なら done の todo だけを、false なら done 以外の todo だけを返す
+    pub completed: Option<bool>,
+    // ラベルを付けた todo だけを返す
+    pub label_id: Option<i32>,
+    // true なら label_id の子孫のラベルを付けた todo も返す
+    pub sublabels: Option<bool>,
+    // 作成日時がこの日時より後の todo だけを返す
+    pub created_after: Option<DateTime<Utc>>,
+    // text に含む (大文字と小文字は区別しない) todo だけを返す
+    pub text_contains: Option<String>,
+    // 担当者で絞り込む. me なら自分が担当の todo だけを返す
+    pub assignee: Option<Assignee>,
+    // true ならピン留めした todo だけを、false ならピン留めしていない todo だけを返す
+    pub pinned: Option<bool>,
+    // true ならスヌーズ中の todo だけを返す. 省略したらスヌーズ中でない todo だけを返す
+    pub snoozed: Option<bool>,
+    // true なら開始日時が未来の todo だけを返す. 省略したら開始済みか開始日時のない todo だけを返す
+    pub upcoming: Option<bool>,
+    // 省略したらピン留めした todo を先に、新しい順
+    pub sort: Option<TodoSort>,
+    // 省略したら sort ごとの向き
+    pub order: Option<SortOrder>,
+}
+
+// assignee=me または assignee=<ユーザー id>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignee {
+    Me,
+    User(i32),
+}
+
+impl Assignee {
+    fn user_id(self, scope: Scope) -> i32 {
+        match self {
+            Assignee::Me => scope.user_id,
+            Assignee::User(user_id) => user_id,
+        }
+    }
+}
+
+// クエリパラメータは文字列で届くので、me 以外は数値として読む
+impl<'de> Deserialize<'de> for Assignee {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value == "me" {
+            return std::result::Result::Ok(Assignee::Me);
+        }
+        value
+            .parse()
+            .map(Assignee::User)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// 同じ値の todo は order と同じ向きの id 順に並べる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoSort {
+    // 優先度の高い順
+    Priority,
+    // 手動で並び替えた順
+    Position,
+    // 新しい順
+    CreatedAt,
+    // text の辞書順
+    Text,
+    // 期限の近い順. 期限のない todo は order に関わらず最後
+    DueAt,
+}
+
+impl TodoSort {
+    fn default_order(self) -> SortOrder {
+        match self {
+            TodoSort::Priority | TodoSort::CreatedAt => SortOrder::Desc,
+            TodoSort::Position | TodoSort::Text | TodoSort::DueAt => SortOrder::Asc,
+        }
+    }
+}
+
+// scope と TodoFilter で絞り込む SQL. 引数は TodoFilter::arguments の順
+const FILTERED_TODOS: &str = r#"
+    SELECT todos.* FROM todos
+    WHERE (
+            ($2::INTEGER IS NULL AND (
+                (todos.workspace_id IS NULL AND todos.user_id = $1)
+                OR todos.id IN (SELECT todo_id FROM todo_shares WHERE user_id = $1)
+            ))
+            OR todos.workspace_id = $2
+        )
+        AND ($3::TIMESTAMPTZ IS NULL OR todos.due_at < $3)
+        AND ($4::BOOLEAN IS NULL
+            OR COALESCE(todos.due_at < now() AND todos.status NOT IN ('done', 'cancelled'), false) = $4)
+        AND ($5::INTEGER IS NULL OR todos.parent_id = $5)
+        AND (todos.archived_at IS NOT NULL) = $6
+        AND ($7::TEXT IS NULL OR todos.status = $7)
+        AND ($8::INTEGER IS NULL OR todos.project_id = $8)
+        AND ($9::BOOLEAN IS NULL OR (todos.status = 'done') = $9)
+        AND ($10::INTEGER IS NULL
+            OR todos.id IN (
+                SELECT todo_id FROM todo_labels
+                WHERE label_id = $10
+                    OR ($17 AND label_id IN (
+                        WITH RECURSIVE subtree (id) AS (
+                            SELECT id FROM labels WHERE parent_id = $10
+                            UNION
+                            SELECT labels.id FROM labels INNER JOIN subtree ON labels.parent_id = subtree.id
+                        )
+                        SELECT id FROM subtree
+                    ))
+            ))
+        AND ($11::TIMESTAMPTZ IS NULL OR todos.created_at > $11)
+        AND ($12::TEXT IS NULL OR todos.text ILIKE '%' || $12 || '%')
+        AND ($13::INTEGER IS NULL OR todos.assignee_id = $13)
+        AND ($14::BOOLEAN IS NULL OR todos.is_pinned = $14)
+        AND COALESCE(todos.snoozed_until > now(), false) = $15
+        AND COALESCE(todos.starts_at > now(), false) = $16
+"#;
+
+impl TodoFilter {
+    // FILTERED_TODOS の $1 から $17
+    fn arguments(&self, scope: Scope) -> PgArguments {
+        let mut arguments = PgArguments::default();
+        arguments.add(scope.user_id);
+        arguments.add(scope.workspace_id);
+        arguments.add(self.due_before);
+        arguments.add(self.overdue);
+        arguments.add(self.parent_id);
+        arguments.add(self.archived.unwrap_or(false));
+        arguments.add(self.status);
+        arguments.add(self.project_id);
+        arguments.add(self.completed);
+        arguments.add(self.label_id);
+        arguments.add(self.created_after);
+        arguments.add(self.text_contains.as_deref().map(escape_like));
+        arguments.add(self.assignee.map(|assignee| assignee.user_id(scope)));
+        arguments.add(self.pinned);
+        arguments.add(self.snoozed.unwrap_or(false));
+        arguments.add(self.upcoming.unwrap_or(false));
+        arguments.add(self.sublabels.unwrap_or(false));
+        arguments
+    }
+
+    // 省略したら sort ごとの向き. sort も省略したら新しい順
+    fn direction(&self) -> SortOrder {
+        self.order
+            .unwrap_or(self.sort.map_or(SortOrder::Desc, TodoSort::default_order))
+    }
+
+    // ORDER BY 句. 列は TodoSort から選ぶので、クエリパラメータの文字列がそのまま SQL に入ることはない
+    // sort を省略したときは order に関わらずピン留めした todo を先に並べる
+    fn order_by(&self) -> String {
+        let order = self.direction().as_sql();
+        let Some(sort) = self.sort else {
+            return format!("todos.is_pinned DESC, todos.id {}", order);
+        };
+        let column = match sort {
+            TodoSort::Priority => PRIORITY_RANK,
+            TodoSort::Position => "todos.position",
+            TodoSort::CreatedAt => "todos.created_at",
+            TodoSort::Text => "todos.text",
+            TodoSort::DueAt => "todos.due_at",
+        };
+        format!("{} {} NULLS LAST, todos.id {}", column, order, order)
+    }
+}
 
-        while let Some(todo) = todos.next() {
+// 1 回の一括操作で指定できる id の数
+const MAX_SELECTION_IDS: u64 = 1000;
+
+// POST /todos/complete と DELETE /todos の対象. { "ids": [1, 2] } か { "filter": { "overdue": true } } のどちらか
+// filter は GET /todos と同じ条件で、archived を指定しなければアーカイブした todo は対象にならない
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoSelection {
+    Ids(Vec<i32>),
+    Filter(TodoFilter),
+}
+
+impl Validate for TodoSelection {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if let TodoSelection::Ids(ids) = self {
+            if ids.is_empty() || ids.len() as u64 > MAX_SELECTION_IDS {
+                let mut error = ValidationError::new("length");
+                error.message = Some("Over ids length".into());
+                errors.add("ids", error);
+            }
+        }
+        if errors.is_empty() {
+            return std::result::Result::Ok(());
+        }
+        Err(errors)
+    }
+}
+
+// POST /todos/labels のボディ. todo_ids の todo に add のラベルを付け、remove のラベルを外す
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Validate)]
+#[validate(schema(function = "validate_relabel"))]
+pub struct RelabelTodos {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 1000, message = "Over todo_ids length"))]
+    pub todo_ids: Vec<i32>,
+    #[serde(default)]
+    #[validate(length(max = 100, message = "Over add length"))]
+    pub add: Vec<i32>,
+    #[serde(default)]
+    #[validate(length(max = 100, message = "Over remove length"))]
+    pub remove: Vec<i32>,
+}
+
+// add と remove のどちらかは必要で、同じラベルを両方に入れることはできない
+fn validate_relabel(payload: &RelabelTodos) -> Result<(), ValidationError> {
+    if payload.add.is_empty() && payload.remove.is_empty() {
+        return Err(ValidationError::new("add or remove is required"));
+    }
+    if payload
+        .add
+        .iter()
+        .any(|label_id| payload.remove.contains(label_id))
+    {
+        return Err(ValidationError::new(
+            "Can not add and remove the same label",
+        ));
+    }
+    std::result::Result::Ok(())
+}
+
+// scope から見えるアーカイブしていない todo のうち、$3 のラベルを付けた todo
+// スヌーズ中や開始前の todo も含める
+const LABELED_TODOS: &str = r#"
+    SELECT todos.* FROM todos
+        INNER JOIN todo_labels ON todo_labels.todo_id = todos.id AND todo_labels.label_id = $3
+    WHERE (
+            ($2::INTEGER IS NULL AND (
+                (todos.workspace_id IS NULL AND todos.user_id = $1)
+                OR todos.id IN (SELECT todo_id FROM todo_shares WHERE user_id = $1)
+            ))
+            OR todos.workspace_id = $2
+        )
+        AND todos.archived_at IS NULL
+"#;
+
+impl TodoSelection {
+    // FILTERED_TODOS の $1 から $17 と、ids で絞り込む $18
+    fn arguments(&self, scope: Scope) -> PgArguments {
+        let (filter, ids) = match self {
+            TodoSelection::Ids(ids) => (TodoFilter::default(), Some(ids.clone())),
+            TodoSelection::Filter(filter) => (filter.clone(), None),
+        };
+        let mut arguments = filter.arguments(scope);
+        arguments.add(ids);
+        arguments
+    }
+}
+
+// FILTERED_TODOS の todo のうち、共有されたのではなく scope の中にある todo
+const OWNED_IN_SCOPE: &str = r#"
+    (($2::INTEGER IS NULL AND todos.workspace_id IS NULL AND todos.user_id = $1)
+        OR todos.workspace_id = $2)
+"#;
+
+// cursor ページングで最後に返した todo の位置. 並び替えに使った値と id を持つ
+// クライアントには中身を見せず、encode した文字列として渡す
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TodoCursor {
+    key: CursorKey,
+    order: SortOrder,
+    id: i32,
+}
+
+// TodoSort ごとの並び替えの値. sort を省略したときはピン留めかどうかと id で並べる
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "sort", content = "value")]
+enum CursorKey {
+    Pinned(bool),
+    Priority(i32),
+    Position(i64),
+    CreatedAt(DateTime<Utc>),
+    Text(String),
+    DueAt(Option<DateTime<Utc>>),
+}
+
+impl TodoCursor {
+    // filter の並び順で todo の直後から続ける cursor
+    pub fn after(filter: &TodoFilter, todo: &Todo) -> Self {
+        let key = match filter.sort {
+            None => CursorKey::Pinned(todo.is_pinned),
+            Some(TodoSort::Priority) => CursorKey::Priority(todo.priority.rank()),
+            Some(TodoSort::Position) => CursorKey::Position(todo.position),
+            Some(TodoSort::CreatedAt) => CursorKey::CreatedAt(todo.created_at),
+            Some(TodoSort::Text) => CursorKey::Text(todo.text.clone()),
+            Some(TodoSort::DueAt) => CursorKey::DueAt(todo.due_at),
+        };
+        Self {
+            key,
+            order: filter.direction(),
+            id: todo.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_vec(self)
+            .unwrap_or_default()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // 壊れた cursor や、filter と並び順が違う cursor は RepositoryError::Invalid
+    pub fn decode(filter: &TodoFilter, cursor: &str) -> Result<Self, RepositoryError> {
+        let invalid = || RepositoryError::Invalid(format!("invalid cursor: {}", cursor));
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| {
+                cursor
+                    .get(i..i + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let cursor: Self = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        let same_sort = matches!(
+            (filter.sort, &cursor.key),
+            (None, CursorKey::Pinned(_))
+                | (Some(TodoSort::Priority), CursorKey::Priority(_))
+                | (Some(TodoSort::Position), CursorKey::Position(_))
+                | (Some(TodoSort::CreatedAt), CursorKey::CreatedAt(_))
+                | (Some(TodoSort::Text), CursorKey::Text(_))
+                | (Some(TodoSort::DueAt), CursorKey::DueAt(_))
+        );
+        if !same_sort || cursor.order != filter.direction() {
+            return Err(invalid());
+        }
+        std::result::Result::Ok(cursor)
+    }
+
+    // cursor より後に並ぶ todo だけを残す WHERE 句. $19 に id、$20 に並び替えの値を追加する
+    fn keyset(&self, arguments: &mut PgArguments) -> String {
+        let cmp = match self.order {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        };
+        arguments.add(self.id);
+        let column = match &self.key {
+            // ピン留めした todo は向きに関わらず先
+            CursorKey::Pinned(pinned) => {
+                arguments.add(*pinned);
+                return format!(
+                    "(todos.is_pinned < $20 OR (todos.is_pinned = $20 AND todos.id {} $19))",
+                    cmp
+                );
+            }
+            CursorKey::Priority(rank) => {
+                arguments.add(*rank);
+                PRIORITY_RANK
+            }
+            CursorKey::Position(position) => {
+                arguments.add(*position);
+                "todos.position"
+            }
+            CursorKey::CreatedAt(created_at) => {
+                arguments.add(*created_at);
+                "todos.created_at"
+            }
+            CursorKey::Text(text) => {
+                arguments.add(text.clone());
+                "todos.text"
+            }
+            // 期限のない todo は向きに関わらず最後に並ぶ
+            CursorKey::DueAt(due_at) => {
+                arguments.add(*due_at);
+                return format!(
+                    r#"CASE WHEN $20::TIMESTAMPTZ IS NULL THEN todos.due_at IS NULL AND todos.id {cmp} $19
+                    ELSE todos.due_at IS NULL OR (todos.due_at, todos.id) {cmp} ($20, $19) END"#,
+                    cmp = cmp
+                );
+            }
+        };
+        format!("({}, todos.id) {} ($20, $19)", column, cmp)
+    }
+}
+
+// limit + 1 件まで取った todos から limit 件を返し、残りがあれば続きの cursor も返す
+fn next_page(
+    filter: &TodoFilter,
+    mut todos: Vec<Todo>,
+    limit: i64,
+) -> (Vec<Todo>, Option<TodoCursor>) {
+    if todos.len() as i64 <= limit {
+        return (todos, None);
+    }
+    todos.truncate(limit as usize);
+    let cursor = todos.last().map(|todo| TodoCursor::after(filter, todo));
+    (todos, cursor)
+}
+
+// 新しい todo や移動した todo の前後に空ける間隔
+const POSITION_GAP: i64 = 1024;
+
+// 移動先. index, before, after のどれか 1 つを指定する
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[validate(schema(function = "validate_move"))]
+pub struct MoveTodo {
+    // 並び順で何番目に置くか (0 始まり). 一覧の長さより大きければ末尾に置く
+    pub index: Option<usize>,
+    // この todo の直前に置く
+    pub before: Option<i32>,
+    // この todo の直後に置く
+    pub after: Option<i32>,
+}
+
+fn validate_move(payload: &MoveTodo) -> Result<(), ValidationError> {
+    let targets = [
+        payload.index.is_some(),
+        payload.before.is_some(),
+        payload.after.is_some(),
+    ];
+    (targets.iter().filter(|target| **target).count() == 1)
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Specify one of index, before and after"))
+}
+
+impl MoveTodo {
+    // siblings は移動する todo を除いた一覧の (id, position) を並び順に並べたもの
+    fn index_in(&self, siblings: &[(i32, i64)]) -> anyhow::Result<usize> {
+        let find = |target_id: i32| {
+            siblings
+                .iter()
+                .position(|(id, _)| *id == target_id)
+                .ok_or_else(|| {
+                    RepositoryError::Invalid(format!("todo {} is not in the same list", target_id))
+                })
+        };
+        match (self.index, self.before, self.after) {
+            (Some(index), _, _) => Ok(index.min(siblings.len())),
+            (_, Some(before), _) => Ok(find(before)?),
+            (_, _, Some(after)) => Ok(find(after)? + 1),
+            _ => Err(RepositoryError::Invalid("no target".to_string()).into()),
+        }
+    }
+}
+
+// id を siblings の index 番目に置くために更新する (id, position) を返す
+// 前後の間に空きがあれば id だけを間に入れ、なければ一覧全体の position を振り直す
+fn plan_move(siblings: &[(i32, i64)], id: i32, index: usize) -> Vec<(i32, i64)> {
+    let prev = index.checked_sub(1).map(|i| siblings[i].1);
+    let next = siblings.get(index).map(|(_, position)| *position);
+    let position = match (prev, next) {
+        (None, None) => Some(POSITION_GAP),
+        (Some(prev), None) => prev.checked_add(POSITION_GAP),
+        (None, Some(next)) => (next > 1).then_some(next / 2),
+        (Some(prev), Some(next)) => (next - prev > 1).then_some(prev + (next - prev) / 2),
+    };
+    if let Some(position) = position {
+        return vec![(id, position)];
+    }
+
+    let mut ids: Vec<i32> = siblings.iter().map(|(id, _)| *id).collect();
+    ids.insert(index, id);
+    ids.into_iter()
+        .zip(1..)
+        .map(|(id, i)| (id, i * POSITION_GAP))
+        .collect()
+}
+
+pub(super) fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<Todo> {
+    let mut result: Vec<Todo> = vec![];
+    'outer: for row in rows.iter() {
+        for todo in result.iter_mut() {
             // todo:label の N:N 関係を第一正規形展開したものを受けとるので、
             // rows の中で同じ ID を持つ Todo は存在し得る
             // Todo 的には自身 (Todo) に紐づく Label を配列でまとめて保持する定義なので、
@@ -61,25 +1101,39 @@ fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<Todo> {
                 todo.labels.push(Label {
                     id: row.label_id.unwrap(),
                     name: row.label_name.clone().unwrap(),
+                    user_id: row.label_user_id,
+                    workspace_id: row.label_workspace_id,
+                    color: row.label_color.clone().unwrap(),
+                    parent_id: row.label_parent_id,
+                    position: row.label_position.unwrap(),
+                    archived_at: row.label_archived_at,
+                    icon: row.label_icon.clone(),
                 });
                 continue 'outer;
             }
         }
-        
+
         // 手前の while を抜けているので、この時点では
         // 今の outer ループで扱っている row の Todo ID は
         // 今の Vec<Todo> の中に存在してない Todo である、と言える
         // なので、このコメント以下でやるべき仕事は新しい Todo を作って push すること。
         // Todo の Todo ID は row が持っているそれ。
-        // 
+        //
         // 実際に DB に入ってるデータとそのクエリ方法の想定として
         // 交差テーブルを使っての outer join を行うので、
         // row.label_id は Optional 型となることに注意。
         // ラベルの有無に関わらず join していくクエリを書くので、label_id は Optional となる。
-        let labels = if row.label_id.is_some() {
+        let labels = if let Some(label_id) = row.label_id {
             vec![Label {
-                id: row.label_id.unwrap(),
+                id: label_id,
                 name: row.label_name.clone().unwrap(),
+                user_id: row.label_user_id,
+                workspace_id: row.label_workspace_id,
+                color: row.label_color.clone().unwrap(),
+                parent_id: row.label_parent_id,
+                position: row.label_position.unwrap(),
+                archived_at: row.label_archived_at,
+                icon: row.label_icon.clone(),
             }]
         } else {
             vec![]
@@ -88,436 +1142,5265 @@ fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<Todo> {
         result.push(Todo {
             id: row.id,
             text: row.text.clone(),
-            completed: row.completed,
+            description: row.description.clone(),
+            status: row.status,
+            user_id: row.user_id,
+            workspace_id: row.workspace_id,
+            due_at: row.due_at,
+            starts_at: row.starts_at,
+            priority: row.priority,
+            parent_id: row.parent_id,
+            recurrence: Recurrence::from_row(
+                row.recurrence_freq,
+                row.recurrence_interval,
+                row.recurrence_until,
+            ),
+            archived_at: row.archived_at,
+            position: row.position,
+            project_id: row.project_id,
             labels,
+            created_at: row.created_at,
+            assignee_id: row.assignee_id,
+            is_pinned: row.is_pinned,
+            snoozed_until: row.snoozed_until,
         });
     }
     result
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[validate(schema(function = "validate_create"))]
 pub struct CreateTodo {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "Over text length"))]
     text: String,
+    #[serde(default)]
+    #[validate(length(max = 10000, message = "Over description length"))]
+    description: Option<String>,
     labels: Vec<i32>,
+    #[serde(default)]
+    due_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    starts_at: Option<DateTime<Utc>>,
+    // 省略したら medium. 定義外の値は JSON のパースで弾かれる
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    parent_id: Option<i32>,
+    #[serde(default)]
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<Recurrence>,
+    // プロジェクトが scope の中にあるかどうかは handler で確認する
+    #[serde(default)]
+    pub project_id: Option<i32>,
+}
+
+impl CreateTodo {
+    // テンプレートの初期値で作る todo. サブタスクは from_subtask で別に作る
+    pub fn from_template(template: &Template) -> Self {
+        Self {
+            text: template.text.clone(),
+            description: template.description.clone(),
+            labels: template.labels.clone(),
+            due_at: None,
+            starts_at: None,
+            priority: template.priority,
+            parent_id: None,
+            recurrence: None,
+            project_id: None,
+        }
+    }
+
+    pub fn from_subtask(parent_id: i32, text: String) -> Self {
+        Self {
+            text,
+            description: None,
+            labels: vec![],
+            due_at: None,
+            starts_at: None,
+            priority: Priority::default(),
+            parent_id: Some(parent_id),
+            recurrence: None,
+            project_id: None,
+        }
+    }
 }
 
+fn validate_create(payload: &CreateTodo) -> Result<(), ValidationError> {
+    is_valid_schedule(payload.starts_at, payload.due_at)
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("starts_at must not be after due_at"))
+}
+
+// POST /todos/bulk のリクエスト. CreateTodo の配列をそのまま受け取る
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[serde(transparent)]
+pub struct CreateTodos {
+    #[validate]
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over todos length"))]
+    pub todos: Vec<CreateTodo>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
 pub struct UpdateTodo {
     #[validate(length(min = 1, message = "Can not be empty"))]
     #[validate(length(max = 100, message = "over text length"))]
     text: Option<String>,
-    completed: Option<bool>,
+    // 省略したら変更しない. null なら説明を消す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[validate(length(max = 10000, message = "Over description length"))]
+    description: Option<Option<String>>,
+    // 遷移できるかどうかは handler で確認する
+    pub status: Option<Status>,
     labels: Option<Vec<i32>>,
+    // 省略したら変更しない. null なら期限を外す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    due_at: Option<Option<DateTime<Utc>>>,
+    // 省略したら変更しない. null なら開始日時を外す. 変更後の due_at より後なら RepositoryError::Invalid
+    #[serde(default, deserialize_with = "deserialize_some")]
+    starts_at: Option<Option<DateTime<Utc>>>,
+    priority: Option<Priority>,
+    // 省略したら変更しない. null ならトップレベルの todo に戻す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    parent_id: Option<Option<i32>>,
+    // 省略したら変更しない. null なら繰り返しをやめる
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<Option<Recurrence>>,
+    // status を done にするとき、終わっていないサブタスクもすべて done にする
+    #[serde(default)]
+    complete_subtasks: bool,
+    // 省略したら変更しない. null ならプロジェクトから外す
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub project_id: Option<Option<i32>>,
 }
 
-#[derive(Debug, Clone)]
-pub struct TodoRepositoryForDb {
-    pool: PgPool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct TodoRevision {
+    pub id: i32,
+    pub todo_id: i32,
+    // ユーザーが削除されたら None
+    pub actor_id: Option<i32>,
+    // 変わったフィールドごとの { "before": .., "after": .. }
+    pub changes: Value,
+    pub created_at: DateTime<Utc>,
 }
 
-impl TodoRepositoryForDb {
-    pub fn new (pool: PgPool) -> Self {
-        TodoRepositoryForDb { pool }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRow)]
+pub struct LabelUsage {
+    pub label_id: i32,
+    // backlog と in_progress
+    pub open: i64,
+    pub completed: i64,
 }
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
-        let tx = self.pool.begin().await?;
+// 同じ日時なら後の出来事が先に並ぶように、起きる順に宣言する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoActivityKind {
+    Created,
+    Completed,
+}
 
-        let row = sqlx::query_as::<_, TodoFromRow>(
-            r#"
-            INSERT INTO todos (text, completed)
-            VALUES ($1, false)
-            RETURNING *
-            "#
-        ).bind(payload.text.clone())
-        .fetch_one(&self.pool)
-        .await?;
-        
-        // この SQL 文は、bind した配列を展開したら例えばこうなる
-        // INSERT INTO todo_labels (todo_id, label_id)
-        // SELECT 1, id
-        // FROM unnest(array[1, 2, 3]) as t(id) 
-        sqlx::query(
-            r#"
-            INSERT INTO todo_labels (todo_id, label_id)
-            SELECT $1, id
-            FROM unnest($2) as t(id);
-            "#
-        )
-        .bind(row.id)
-        .bind(payload.labels)
-        .execute(&self.pool)
-        .await?;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoActivity {
+    pub kind: TodoActivityKind,
+    pub at: DateTime<Utc>,
+    pub todo: Todo,
+}
 
-        tx.commit().await?;
+// 作成した日時と、done にした日時 (todo の id ごと) から新しい順の出来事を作る
+fn collect_activity(
+    todos: Vec<Todo>,
+    completions: Vec<(i32, DateTime<Utc>)>,
+    limit: usize,
+) -> Vec<TodoActivity> {
+    let todos: HashMap<i32, Todo> = todos.into_iter().map(|todo| (todo.id, todo)).collect();
+    let created = todos.values().map(|todo| TodoActivity {
+        kind: TodoActivityKind::Created,
+        at: todo.created_at,
+        todo: todo.clone(),
+    });
+    let completed = completions.into_iter().filter_map(|(todo_id, at)| {
+        todos.get(&todo_id).map(|todo| TodoActivity {
+            kind: TodoActivityKind::Completed,
+            at,
+            todo: todo.clone(),
+        })
+    });
+    let mut activity: Vec<TodoActivity> = created.chain(completed).collect();
+    activity.sort_by_key(|a| std::cmp::Reverse((a.at, a.kind, a.todo.id)));
+    activity.truncate(limit);
+    activity
+}
+
+// 履歴で比べるフィールド. labels は id だけを比べる
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TodoFields {
+    text: String,
+    description: Option<String>,
+    status: Status,
+    due_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    priority: Priority,
+    parent_id: Option<i32>,
+    recurrence: Option<Recurrence>,
+    project_id: Option<i32>,
+    labels: Vec<i32>,
+}
 
-        let todo = self.find(row.id).await?;
-        Ok(todo)
+impl TodoFields {
+    fn of(todo: &Todo) -> Self {
+        let mut labels: Vec<i32> = todo.labels.iter().map(|label| label.id).collect();
+        labels.sort_unstable();
+        Self {
+            text: todo.text.clone(),
+            description: todo.description.clone(),
+            status: todo.status,
+            due_at: todo.due_at,
+            starts_at: todo.starts_at,
+            priority: todo.priority,
+            parent_id: todo.parent_id,
+            recurrence: todo.recurrence,
+            project_id: todo.project_id,
+            labels,
+        }
     }
 
-    async fn find(&self, id: i32) ->  anyhow::Result<Todo> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"
-            SELECT todos.*, labels.id label_id, labels.name label_name
-            FROM todos
-            LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
-            LEFT OUTER JOIN labels on labels.id = tl.label_id
-            WHERE todos.id=$1
-            "#  
-        ).
-        bind(id)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+    // payload を適用した後の値
+    fn apply(&self, payload: &UpdateTodo) -> Self {
+        let labels = match &payload.labels {
+            Some(labels) => {
+                let mut labels = labels.clone();
+                labels.sort_unstable();
+                labels.dedup();
+                labels
+            }
+            None => self.labels.clone(),
+        };
+        Self {
+            text: payload.text.clone().unwrap_or_else(|| self.text.clone()),
+            description: payload
+                .description
+                .clone()
+                .unwrap_or_else(|| self.description.clone()),
+            status: payload.status.unwrap_or(self.status),
+            due_at: payload.due_at.unwrap_or(self.due_at),
+            starts_at: payload.starts_at.unwrap_or(self.starts_at),
+            priority: payload.priority.unwrap_or(self.priority),
+            parent_id: payload.parent_id.unwrap_or(self.parent_id),
+            recurrence: payload.recurrence.unwrap_or(self.recurrence),
+            project_id: payload.project_id.unwrap_or(self.project_id),
+            labels,
+        }
+    }
+
+    // 値が変わったフィールドだけを TodoRevision::changes の形にする
+    fn diff(&self, after: &Self) -> Map<String, Value> {
+        let (Value::Object(before), Value::Object(after)) = (json!(self), json!(after)) else {
+            return Map::new();
+        };
+        before
+            .into_iter()
+            .filter(|(field, value)| after.get(field) != Some(value))
+            .map(|(field, value)| {
+                let change = json!({ "before": value, "after": after[&field] });
+                (field, change)
+            })
+            .collect()
+    }
+}
+
+// 検索結果. snippet は一致した語を <mark> で囲んだ HTML で、それ以外はエスケープ済み
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TodoSearchHit {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+// 検索結果の最大件数
+const SEARCH_LIMIT: i64 = 50;
+// snippet で一致した語の前後に置く区切り. 私用領域の文字なので普通の text には現れない
+const MARK_START: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+
+// 区切りで囲んだ部分を <mark> にし、それ以外は HTML としてエスケープする
+fn mark_snippet(raw: &str) -> String {
+    let mut snippet = String::new();
+    // String への書き込みは失敗しない
+    let _ = escape_html(&mut snippet, raw);
+    snippet
+        .replace(MARK_START, "<mark>")
+        .replace(MARK_END, "</mark>")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatedTodo {
+    pub todo: Todo,
+    // 複製した添付ファイルと、その中身のコピー元の storage_key. 中身のコピーは handler で行う
+    pub attachments: Vec<(String, Attachment)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeletedTodos {
+    pub todos: Vec<Todo>,
+    // 一緒に消えた添付ファイルの中身の storage_key. 中身の削除は handler で行う
+    pub storage_keys: Vec<String>,
+}
+
+// relabel_many でラベルが変わった todo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelabeledTodo {
+    pub before: Todo,
+    pub after: Todo,
+}
+
+impl Todo {
+    // remove のラベルを外し、まだ付いていない add のラベルを後ろに付ける
+    fn relabeled(&self, add: &[Label], remove: &[i32]) -> Todo {
+        let mut labels: Vec<Label> = self
+            .labels
+            .iter()
+            .filter(|label| !remove.contains(&label.id))
+            .cloned()
+            .collect();
+        for label in add {
+            if !labels.iter().any(|known| known.id == label.id) {
+                labels.push(label.clone());
+            }
+        }
+        Todo {
+            labels,
+            ..self.clone()
+        }
+    }
+}
+
+impl UpdateTodo {
+    // revision の変更を取り消す更新. 変わったフィールドを before の値に戻す
+    pub fn revert(revision: &TodoRevision) -> anyhow::Result<Self> {
+        let before: Map<String, Value> = revision
+            .changes
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(field, change)| Some((field.clone(), change.get("before")?.clone())))
+            .collect();
+        Ok(serde_json::from_value(Value::Object(before))?)
+    }
+
+    // RFC 7396 の JSON Merge Patch を before に当てる更新. 省略したフィールドは変更せず、null なら外す
+    // 外せないフィールドに null を指定したり、当てた結果が不正な値になる場合は RepositoryError::Invalid
+    pub fn merge(before: &Todo, patch: Map<String, Value>) -> anyhow::Result<Self> {
+        let mut patch = patch;
+        for field in REQUIRED_FIELDS {
+            if patch.get(field).is_some_and(Value::is_null) {
+                return Err(
+                    RepositoryError::Invalid(format!("{} can not be removed", field)).into(),
+                );
+            }
+        }
+        // オブジェクトのフィールドは、中のフィールドごとにマージする
+        if let Some(recurrence) = patch
+            .get_mut("recurrence")
+            .filter(|value| value.is_object())
+        {
+            let mut target = serde_json::to_value(before.recurrence)?;
+            merge_patch(&mut target, recurrence.take());
+            *recurrence = target;
+        }
+        let payload: Self = serde_json::from_value(Value::Object(patch))
+            .map_err(|e| RepositoryError::Invalid(e.to_string()))?;
+        payload
+            .validate()
+            .map_err(|e| RepositoryError::Invalid(e.to_string()))?;
+        Ok(payload)
+    }
+
+    // RFC 6902 の JSON Patch を before に当てる更新. 変更できるのは PATCH_FIELDS と
+    // /labels/{index}・/labels/-・/recurrence/{field} だけで、それ以外の path は RepositoryError::Invalid
+    // 途中の操作が 1 つでも失敗したら何も変更しない
+    pub fn apply(before: &Todo, operations: Vec<PatchOperation>) -> anyhow::Result<Self> {
+        let mut document: Map<String, Value> = match serde_json::to_value(before)? {
+            Value::Object(todo) => todo
+                .into_iter()
+                .filter(|(field, _)| PATCH_FIELDS.contains(&field.as_str()))
+                .collect(),
+            _ => Map::new(),
+        };
+        // labels はラベルの id の配列として扱う
+        let labels = before.labels.iter().map(|label| json!(label.id)).collect();
+        document.insert("labels".to_string(), Value::Array(labels));
+        let original = document.clone();
+
+        for operation in operations {
+            apply_operation(&mut document, operation)?;
+        }
+        let changed = document
+            .into_iter()
+            .filter(|(field, value)| original.get(field) != Some(value))
+            .collect();
+        Self::merge(before, changed)
+    }
+}
+
+fn apply_operation(
+    document: &mut Map<String, Value>,
+    operation: PatchOperation,
+) -> anyhow::Result<()> {
+    let tokens = operation.tokens()?;
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    let invalid = |message: String| RepositoryError::Invalid(message).into();
+    match (tokens.as_slice(), operation) {
+        ([field], PatchOperation::Remove { .. }) if REQUIRED_FIELDS.contains(field) => {
+            Err(invalid(format!("{} can not be removed", field)))
+        }
+        // 外せるフィールドの remove は null にする
+        ([field], PatchOperation::Remove { .. }) if PATCH_FIELDS.contains(field) => {
+            document.insert(field.to_string(), Value::Null);
+            Ok(())
+        }
+        ([field], PatchOperation::Add { value, .. } | PatchOperation::Replace { value, .. })
+            if PATCH_FIELDS.contains(field) =>
+        {
+            document.insert(field.to_string(), value);
+            Ok(())
+        }
+        (["labels", index], operation) => {
+            let Some(Value::Array(labels)) = document.get_mut("labels") else {
+                return Err(invalid("labels is not an array".to_string()));
+            };
+            let len = labels.len();
+            let position = match *index {
+                "-" => Some(len),
+                index if index == "0" || !index.starts_with('0') => index.parse::<usize>().ok(),
+                _ => None,
+            };
+            match (position, operation) {
+                (Some(position), PatchOperation::Add { value, .. }) if position <= len => {
+                    labels.insert(position, value);
+                }
+                (Some(position), PatchOperation::Replace { value, .. }) if position < len => {
+                    labels[position] = value;
+                }
+                (Some(position), PatchOperation::Remove { .. }) if position < len => {
+                    labels.remove(position);
+                }
+                _ => return Err(invalid(format!("no label at /labels/{}", index))),
+            }
+            Ok(())
+        }
+        (["recurrence", field], operation) if RECURRENCE_FIELDS.contains(field) => {
+            let Some(Value::Object(recurrence)) = document.get_mut("recurrence") else {
+                return Err(invalid("recurrence is not set".to_string()));
+            };
+            let value = match operation {
+                PatchOperation::Add { value, .. } | PatchOperation::Replace { value, .. } => value,
+                PatchOperation::Remove { .. } => Value::Null,
+            };
+            recurrence.insert(field.to_string(), value);
+            Ok(())
+        }
+        (_, operation) => Err(invalid(format!("{:?} is not allowed", operation))),
+    }
+}
+
+// RFC 7396 の MergePatch(target, patch). オブジェクト同士はキーごとにマージし、null のキーは消す
+fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+// PATCH で外せない (null にできない) フィールド
+const REQUIRED_FIELDS: [&str; 4] = ["text", "status", "labels", "priority"];
+// JSON Patch で変更できるフィールド. UpdateTodo のフィールドと同じ
+const PATCH_FIELDS: [&str; 10] = [
+    "text",
+    "description",
+    "status",
+    "labels",
+    "due_at",
+    "starts_at",
+    "priority",
+    "parent_id",
+    "recurrence",
+    "project_id",
+];
+// JSON Patch で /recurrence/{field} として変更できるフィールド
+const RECURRENCE_FIELDS: [&str; 3] = ["freq", "interval", "until"];
+
+// null と省略を区別するため、値があれば null でも Some で包む
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// 共有されたユーザーに許す操作. Write は Read を含む
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "share_permission", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SharePermission {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct ShareTodo {
+    #[validate(range(min = 1, message = "Invalid user id"))]
+    pub user_id: i32,
+    pub permission: SharePermission,
+}
+
+// PATCH /todos/:id/assign のボディ. null なら担当を外す
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct AssignTodo {
+    #[validate(range(min = 1, message = "Invalid user id"))]
+    pub assignee_id: Option<i32>,
+}
+
+// POST /todos/:id/snooze のボディ. until か minutes のどちらか 1 つを指定する
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+#[validate(schema(function = "validate_snooze"))]
+pub struct SnoozeTodo {
+    // この日時までスヌーズする. 過去の日時は指定できない
+    pub until: Option<DateTime<Utc>>,
+    // 今から何分スヌーズするか. 最長で 1 年
+    #[validate(range(min = 1, max = 525600, message = "Invalid minutes"))]
+    pub minutes: Option<i64>,
+}
+
+fn validate_snooze(payload: &SnoozeTodo) -> Result<(), ValidationError> {
+    match (payload.until, payload.minutes) {
+        (Some(until), None) if until > Utc::now() => std::result::Result::Ok(()),
+        (Some(_), None) => Err(ValidationError::new("Can not snooze until the past")),
+        (None, Some(_)) => std::result::Result::Ok(()),
+        _ => Err(ValidationError::new("Specify one of until and minutes")),
+    }
+}
+
+impl SnoozeTodo {
+    // validate 済みなら until か minutes のどちらかがある
+    pub fn until(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.until
+            .unwrap_or_else(|| now + Duration::minutes(self.minutes.unwrap_or_default()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
+pub struct TodoShare {
+    pub todo_id: i32,
+    pub user_id: i32,
+    pub permission: SharePermission,
+}
+
+// 読むだけの find や all などは read_pool に、書き込みと書き込んだ直後の読み直しは pool に送る
+// read_pool は replica を指定しなければ pool と同じ
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForDb {
+    pool: PgPool,
+    read_pool: PgPool,
+}
+
+impl TodoRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        TodoRepositoryForDb {
+            read_pool: pool.clone(),
+            pool,
+        }
+    }
+
+    pub fn with_read_pool(self, read_pool: PgPool) -> Self {
+        Self { read_pool, ..self }
+    }
+
+    async fn find_with_permission(
+        &self,
+        scope: Scope,
+        id: i32,
+        required: Option<SharePermission>,
+    ) -> anyhow::Result<Todo> {
+        let mut conn = self.pool.acquire().await?;
+        self.find_with_permission_in(&mut conn, scope, id, required)
+            .await
+    }
+
+    // 書き込んだ todo を返すときは書き込んだ tx の中で読み直す. replica は遅れていることがあり、
+    // commit 前の変更は tx の中からしか見えない
+    async fn find_written(
+        &self,
+        conn: &mut PgConnection,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<Todo> {
+        self.find_with_permission_in(conn, scope, id, Some(SharePermission::Read))
+            .await
+    }
+
+    // scope の外の todo でも、required 以上の権限で共有されていれば返す
+    // required が None の操作 (削除・再共有) は共有では許さない
+    async fn find_with_permission_in(
+        &self,
+        conn: &mut PgConnection,
+        scope: Scope,
+        id: i32,
+        required: Option<SharePermission>,
+    ) -> anyhow::Result<Todo> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id label_id, labels.name label_name, labels.user_id label_user_id, labels.workspace_id label_workspace_id, labels.color label_color, labels.parent_id label_parent_id, labels.position label_position, labels.archived_at label_archived_at, labels.icon label_icon
+            FROM todos
+            LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+            LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.id=$1 AND todos.user_id IS NOT NULL
+            "#  
+        ).
+        bind(id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
             _ => RepositoryError::Unexpected(e.to_string()),
         })?;
 
         let todos = fold_entities(items);
         let todo = todos.first().ok_or(RepositoryError::NotFound(id))?;
-        Ok(todo.clone())
+        if todo.is_visible_in(scope) {
+            return Ok(todo.clone());
+        }
+        if let Some(required) = required {
+            let permission = sqlx::query_scalar::<_, SharePermission>(
+                r#"
+                SELECT permission FROM todo_shares WHERE todo_id = $1 AND user_id = $2
+                "#,
+            )
+            .bind(id)
+            .bind(scope.user_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+            if permission.is_some_and(|permission| permission >= required) {
+                return Ok(todo.clone());
+            }
+        }
+        Err(RepositoryError::Forbidden(id).into())
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Todo>> {
-        let todos = sqlx::query_as::<_, TodoWithLabelFromRow>(
+    // parent_id を owner の todo の親にできるか確認する. id は更新時の todo 自身
+    async fn check_parent(
+        &self,
+        conn: &mut PgConnection,
+        scope: Scope,
+        owner: Scope,
+        id: Option<i32>,
+        parent_id: i32,
+    ) -> anyhow::Result<()> {
+        let parent = self
+            .find_with_permission_in(conn, scope, parent_id, Some(SharePermission::Read))
+            .await?;
+        if !parent.is_visible_in(owner) {
+            return Err(RepositoryError::Invalid(format!(
+                "todo {} can not be a parent of this todo",
+                parent_id
+            ))
+            .into());
+        }
+        let Some(id) = id else {
+            return Ok(());
+        };
+
+        // 親からたどれる祖先に自分自身が含まれていれば循環する
+        let cyclic = sqlx::query_scalar::<_, bool>(
             r#"
-            SELECT todos.*, labels.id as label_id, labels.name as label_name
-            FROM todos
-                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
-                LEFT OUTER JOIN labels on labels.id = tl.label_id
-            ORDER BY todos.id DESC
-            "#
-        ).fetch_all(&self.pool)
+            WITH RECURSIVE ancestors (id, parent_id) AS (
+                SELECT id, parent_id FROM todos WHERE id = $1
+                UNION
+                SELECT todos.id, todos.parent_id
+                FROM todos INNER JOIN ancestors ON todos.id = ancestors.parent_id
+            )
+            SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $2)
+            "#,
+        )
+        .bind(parent_id)
+        .bind(id)
+        .fetch_one(&mut *conn)
         .await?;
+        if cyclic {
+            return Err(RepositoryError::Invalid(format!(
+                "todo {} is a subtask of todo {}",
+                parent_id, id
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
 
-        Ok(fold_entities(todos))
+#[async_trait]
+impl HealthCheck for TodoRepositoryForDb {
+    async fn check(&self) -> anyhow::Result<()> {
+        sqlx::query("select 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForDb {
+    async fn begin(&self) -> anyhow::Result<Tx> {
+        Tx::begin(&self.pool).await
     }
 
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
-        let tx = self.pool.begin().await?;
-        
-        let old_todo = self.find(id).await?;
-        sqlx::query_as::<_, TodoFromRow>(
+    #[tracing::instrument(name = "TodoRepository::create", skip_all)]
+    async fn create_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payload: CreateTodo,
+    ) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        if let Some(parent_id) = payload.parent_id {
+            self.check_parent(conn, scope, scope, None, parent_id)
+                .await?;
+        }
+
+        let row = sqlx::query_as::<_, TodoFromRow>(
             r#"
-            UPDATE todos SET text=$1, completed=$2
-            WHERE id=$3
+            INSERT INTO todos (text, user_id, workspace_id, due_at, priority, parent_id,
+                recurrence_freq, recurrence_interval, recurrence_until, description, project_id,
+                starts_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             RETURNING *
-            "#
+            "#,
         )
-        .bind(payload.text.unwrap_or(old_todo.text))
-        .bind(payload.completed.unwrap_or(old_todo.completed))
-        .bind(id)
-        .fetch_one(&self.pool)
+        .bind(payload.text.clone())
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .bind(payload.due_at)
+        .bind(payload.priority)
+        .bind(payload.parent_id)
+        .bind(payload.recurrence.map(|recurrence| recurrence.freq))
+        .bind(
+            payload
+                .recurrence
+                .map_or(1, |recurrence| recurrence.interval),
+        )
+        .bind(payload.recurrence.and_then(|recurrence| recurrence.until))
+        .bind(payload.description)
+        .bind(payload.project_id)
+        .bind(payload.starts_at)
+        .fetch_one(&mut *conn)
         .await?;
 
-        // payload が labels を持っているなら交差テーブル todo_labels を更新
-        if let Some(labels) = payload.labels {
-            // いったん削除
-            sqlx::query(
-                r#"
-                DELETE FROM todo_labels WHERE todo_id = $1
-                "#
-            )
-            .bind(id)
-            .execute(&self.pool)
-            .await?;
+        // この SQL 文は、bind した配列を展開したら例えばこうなる
+        // INSERT INTO todo_labels (todo_id, label_id)
+        // SELECT 1, id
+        // FROM unnest(array[1, 2, 3]) as t(id)
+        sqlx::query(
+            r#"
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT $1, id
+            FROM unnest($2) as t(id);
+            "#,
+        )
+        .bind(row.id)
+        .bind(payload.labels)
+        .execute(&mut *conn)
+        .await?;
 
-            // 新しい label ids を insert
-            sqlx::query(
-                r#"
-                INSERT INTO todo_labels (todo_id, label_id)
-                SELECT $1, id as label_id
-                FROM unnest($2) as t(id);
-                "#
-            )
-            .bind(id)
-            .bind(labels)
-            .execute(&self.pool)
-            .await?;
-        }
+        self.find_written(conn, scope, row.id).await
+    }
 
-        tx.commit().await?;
-        let todo = self.find(id).await?;
+    #[tracing::instrument(name = "TodoRepository::create_many", skip_all)]
+    async fn create_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let conn = tx.conn()?;
+        for parent_id in payloads.iter().filter_map(|payload| payload.parent_id) {
+            self.check_parent(conn, scope, scope, None, parent_id)
+                .await?;
+        }
+        if payloads.is_empty() {
+            return Ok(vec![]);
+        }
 
-        Ok(todo)
-    }
+        // 1 回の INSERT で全件を入れる. 1 件につき 12 個の引数を使う
+        let mut arguments = PgArguments::default();
+        let mut values = vec![];
+        for (i, payload) in payloads.iter().enumerate() {
+            let placeholders: Vec<String> = (1..=12).map(|j| format!("${}", i * 12 + j)).collect();
+            values.push(format!("({})", placeholders.join(", ")));
+            arguments.add(payload.text.clone());
+            arguments.add(scope.user_id);
+            arguments.add(scope.workspace_id);
+            arguments.add(payload.due_at);
+            arguments.add(payload.priority);
+            arguments.add(payload.parent_id);
+            arguments.add(payload.recurrence.map(|recurrence| recurrence.freq));
+            arguments.add(
+                payload
+                    .recurrence
+                    .map_or(1, |recurrence| recurrence.interval),
+            );
+            arguments.add(payload.recurrence.and_then(|recurrence| recurrence.until));
+            arguments.add(payload.description.clone());
+            arguments.add(payload.project_id);
+            arguments.add(payload.starts_at);
+        }
+        let sql = format!(
+            r#"
+            INSERT INTO todos (text, user_id, workspace_id, due_at, priority, parent_id,
+                recurrence_freq, recurrence_interval, recurrence_until, description, project_id,
+                starts_at)
+            VALUES {}
+            RETURNING id
+            "#,
+            values.join(", ")
+        );
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        let tx = self.pool.begin().await?;
+        // id は VALUES の順に採番されるので、昇順に並べれば payloads の順になる
+        let mut ids = sqlx::query_scalar_with::<_, i32, _>(&sql, arguments)
+            .fetch_all(&mut *conn)
+            .await?;
+        ids.sort();
 
-        // 中間テーブルの関係を外す
+        let (todo_ids, label_ids): (Vec<i32>, Vec<i32>) = ids
+            .iter()
+            .zip(&payloads)
+            .flat_map(|(id, payload)| payload.labels.iter().map(move |label_id| (*id, *label_id)))
+            .unzip();
         sqlx::query(
             r#"
-            DELETE FROM todo_labels WHERE todo_id = $1
-            "#
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT * FROM unnest($1::INTEGER[], $2::INTEGER[])
+            "#,
         )
-        .bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+        .bind(todo_ids)
+        .bind(label_ids)
+        .execute(&mut *conn)
+        .await?;
 
-        // todo の削除
-        sqlx::query(
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
             r#"
-            DELETE FROM todos WHERE id = $1
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.id = ANY($1)
+            ORDER BY todos.id
             "#
-        ).bind(id)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string())
-        })?;
+        )
+        .bind(&ids)
+        .fetch_all(&mut *conn)
+        .await?;
 
-        tx.commit().await?;
-        
-        Ok(())
+        Ok(fold_entities(rows))
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use dotenv::dotenv;
-    use sqlx::PgPool;
-    use std::env;
+    #[tracing::instrument(name = "TodoRepository::find", skip_all)]
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut conn = self.read_pool.acquire().await?;
+        self.find_with_permission_in(&mut conn, scope, id, Some(SharePermission::Read))
+            .await
+    }
 
-    #[cfg(feature = "database-test")]
-    #[tokio::test]
-    async fn crud_scenario() {
-        dotenv().ok();
-        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-        let pool = PgPool::connect(&database_url)
+    #[tracing::instrument(name = "TodoRepository::find_for_update", skip_all)]
+    async fn find_for_update_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        // ラベルは outer join なので、todo の行だけを先にロックする
+        sqlx::query("SELECT id FROM todos WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        self.find_with_permission_in(conn, scope, id, Some(SharePermission::Read))
             .await
-            .expect(&format!("failed to connect database: [{}]", database_url));
-        
-        // label data prepare
-        // Note: Label レポジトリのテストデータと同じ名前だと2回目以降のテストが通らない.
-        //        このことから、複数スレッド or 複数クライアントを想定したシナリオが漏れている
-        //        脆弱なテストと言えるのではないか?
-        let label_name = String::from("test label");
-        let optional_label = sqlx::query_as::<_, Label>(
+    }
+
+    #[tracing::instrument(name = "TodoRepository::count_pinned", skip_all)]
+    async fn count_pinned_in(&self, tx: &mut Tx, scope: Scope) -> anyhow::Result<i64> {
+        let conn = tx.conn()?;
+        // todo の INSERT が持ち主の行に取る FOR KEY SHARE とはぶつからない強さでロックする
+        let lock = match scope.workspace_id {
+            Some(workspace_id) => {
+                sqlx::query("SELECT id FROM workspaces WHERE id = $1 FOR NO KEY UPDATE")
+                    .bind(workspace_id)
+            }
+            None => sqlx::query("SELECT id FROM users WHERE id = $1 FOR NO KEY UPDATE")
+                .bind(scope.user_id),
+        };
+        lock.execute(&mut *conn).await?;
+        let pinned = sqlx::query_scalar::<_, i64>(
             r#"
-            SELECT * FROM labels WHERE name = $1
-            "#
+            SELECT COUNT(*) FROM todos
+            WHERE is_pinned AND archived_at IS NULL
+                AND (
+                    ($2::INTEGER IS NULL AND workspace_id IS NULL AND user_id = $1)
+                    OR workspace_id = $2
+                )
+            "#,
         )
-        .bind(label_name.clone())
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(pinned)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::all", skip_all)]
+    async fn all(&self, scope: Scope, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+        let sql = format!(
+            r#"
+            WITH filtered AS ({})
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM filtered todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY {}
+            "#,
+            FILTERED_TODOS,
+            filter.order_by()
+        );
+        let todos =
+            sqlx::query_as_with::<_, TodoWithLabelFromRow, _>(&sql, filter.arguments(scope))
+                .fetch_all(&self.read_pool)
+                .await?;
+
+        Ok(fold_entities(todos))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::page", skip_all)]
+    async fn page(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)> {
+        // ラベルを join すると todo 1 件が複数行になるので、先に todo だけでページを切り出す
+        let sql = format!(
+            r#"
+            WITH filtered AS ({}),
+            paged AS (SELECT * FROM filtered todos ORDER BY {} LIMIT $18 OFFSET $19)
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM paged todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY {}
+            "#,
+            FILTERED_TODOS,
+            filter.order_by(),
+            filter.order_by()
+        );
+        let mut arguments = filter.arguments(scope);
+        arguments.add(page.limit);
+        arguments.add(page.offset);
+        let todos = sqlx::query_as_with::<_, TodoWithLabelFromRow, _>(&sql, arguments)
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        let sql = format!("SELECT COUNT(*) FROM ({}) filtered", FILTERED_TODOS);
+        let total = sqlx::query_scalar_with::<_, i64, _>(&sql, filter.arguments(scope))
+            .fetch_one(&self.read_pool)
+            .await?;
+
+        Ok((fold_entities(todos), total))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::page_by_label", skip_all)]
+    async fn page_by_label(
+        &self,
+        scope: Scope,
+        label_id: i32,
+        page: Page,
+    ) -> anyhow::Result<(Vec<Todo>, i64)> {
+        let order_by = TodoFilter::default().order_by();
+        let sql = format!(
+            r#"
+            WITH labeled AS ({}),
+            paged AS (SELECT * FROM labeled todos ORDER BY {} LIMIT $4 OFFSET $5)
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM paged todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY {}
+            "#,
+            LABELED_TODOS, order_by, order_by
+        );
+        let todos = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .bind(label_id)
+            .bind(page.limit)
+            .bind(page.offset)
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        let sql = format!("SELECT COUNT(*) FROM ({}) labeled", LABELED_TODOS);
+        let total = sqlx::query_scalar::<_, i64>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .bind(label_id)
+            .fetch_one(&self.read_pool)
+            .await?;
+
+        Ok((fold_entities(todos), total))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::page_after", skip_all)]
+    async fn page_after(
+        &self,
+        scope: Scope,
+        filter: &TodoFilter,
+        cursor: Option<&TodoCursor>,
+        limit: i64,
+    ) -> anyhow::Result<(Vec<Todo>, Option<TodoCursor>)> {
+        // 続きがあるか分かるように 1 件多く取る
+        let mut arguments = filter.arguments(scope);
+        arguments.add(limit + 1);
+        let keyset = cursor.map_or("TRUE".to_string(), |cursor| cursor.keyset(&mut arguments));
+        let sql = format!(
+            r#"
+            WITH filtered AS ({}),
+            paged AS (SELECT * FROM filtered todos WHERE {} ORDER BY {} LIMIT $18)
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM paged todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY {}
+            "#,
+            FILTERED_TODOS,
+            keyset,
+            filter.order_by(),
+            filter.order_by()
+        );
+        let todos = sqlx::query_as_with::<_, TodoWithLabelFromRow, _>(&sql, arguments)
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        Ok(next_page(filter, fold_entities(todos), limit))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::search", skip_all)]
+    async fn search(&self, scope: Scope, query: &str) -> anyhow::Result<Vec<TodoSearchHit>> {
+        let options = format!(
+            "StartSel={}, StopSel={}, MaxWords=20, MinWords=5",
+            MARK_START, MARK_END
+        );
+        let hits = sqlx::query_as::<_, (i32, f32, String)>(
+            r#"
+            SELECT todos.id, ts_rank(todos.search_vector, query) AS rank,
+                ts_headline('simple', concat_ws(E'\n', todos.text, todos.description), query, $4)
+            FROM todos, websearch_to_tsquery('simple', $3) query
+            WHERE (
+                    ($2::INTEGER IS NULL AND (
+                        (todos.workspace_id IS NULL AND todos.user_id = $1)
+                        OR todos.id IN (SELECT todo_id FROM todo_shares WHERE user_id = $1)
+                    ))
+                    OR todos.workspace_id = $2
+                )
+                AND todos.archived_at IS NULL
+                AND todos.search_vector @@ query
+            ORDER BY rank DESC, todos.id DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .bind(query)
+        .bind(options)
+        .bind(SEARCH_LIMIT)
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        // ラベルは一致した todo の分だけまとめて取得する
+        let ids: Vec<i32> = hits.iter().map(|(id, _, _)| *id).collect();
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.id = ANY($1)
+            "#
+        )
+        .bind(&ids)
+        .fetch_all(&self.read_pool)
+        .await?;
+        let todos: HashMap<i32, Todo> = fold_entities(rows)
+            .into_iter()
+            .map(|todo| (todo.id, todo))
+            .collect();
+
+        Ok(hits
+            .into_iter()
+            .filter_map(|(id, rank, snippet)| {
+                Some(TodoSearchHit {
+                    todo: todos.get(&id)?.clone(),
+                    rank,
+                    snippet: mark_snippet(&snippet),
+                })
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(name = "TodoRepository::all_unscoped", skip_all)]
+    async fn all_unscoped(&self) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.user_id IS NOT NULL
+            ORDER BY todos.id DESC
+            "#
+        )
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        Ok(fold_entities(todos))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::all_by_user", skip_all)]
+    async fn all_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Todo>> {
+        let todos = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.user_id = $1
+            ORDER BY todos.id DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        Ok(fold_entities(todos))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::update", skip_all)]
+    async fn update_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        payload: UpdateTodo,
+    ) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        let old_todo = self
+            .find_with_permission_in(conn, scope, id, Some(SharePermission::Write))
+            .await?;
+        if let Some(Some(parent_id)) = payload.parent_id {
+            let owner = Scope::new(old_todo.user_id, old_todo.workspace_id);
+            self.check_parent(conn, scope, owner, Some(id), parent_id)
+                .await?;
+        }
+        let before = TodoFields::of(&old_todo);
+        let after = before.apply(&payload);
+        if !is_valid_schedule(after.starts_at, after.due_at) {
+            return Err(
+                RepositoryError::Invalid("starts_at must not be after due_at".to_string()).into(),
+            );
+        }
+
+        // 更新と履歴の記録は同じ tx で行う
+        sqlx::query(
+            r#"
+            UPDATE todos SET text=$1, status=$2, due_at=$3, priority=$4, parent_id=$5,
+                recurrence_freq=$6, recurrence_interval=$7, recurrence_until=$8, description=$9,
+                project_id=$10, starts_at=$11
+            WHERE id=$12
+            "#,
+        )
+        .bind(&after.text)
+        .bind(after.status)
+        .bind(after.due_at)
+        .bind(after.priority)
+        .bind(after.parent_id)
+        .bind(after.recurrence.map(|recurrence| recurrence.freq))
+        .bind(after.recurrence.map_or(1, |recurrence| recurrence.interval))
+        .bind(after.recurrence.and_then(|recurrence| recurrence.until))
+        .bind(&after.description)
+        .bind(after.project_id)
+        .bind(after.starts_at)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        // payload が labels を持っているなら交差テーブル todo_labels を更新
+        if payload.labels.is_some() {
+            // いったん削除
+            sqlx::query(
+                r#"
+                DELETE FROM todo_labels WHERE todo_id = $1
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+            // 新しい label ids を insert
+            sqlx::query(
+                r#"
+                INSERT INTO todo_labels (todo_id, label_id)
+                SELECT $1, id as label_id
+                FROM unnest($2) as t(id);
+                "#,
+            )
+            .bind(id)
+            .bind(&after.labels)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        // 完了にしたサブタスクの id と元の status
+        let mut completed: Vec<(i32, Status)> = vec![];
+        if payload.status == Some(Status::Done) && payload.complete_subtasks {
+            completed = sqlx::query_as::<_, (i32, Status)>(
+                r#"
+                WITH RECURSIVE descendants (id) AS (
+                    SELECT id FROM todos WHERE parent_id = $1
+                    UNION
+                    SELECT todos.id FROM todos INNER JOIN descendants ON todos.parent_id = descendants.id
+                ), targets AS (
+                    SELECT id, status FROM todos
+                    WHERE id IN (SELECT id FROM descendants) AND status NOT IN ('done', 'cancelled')
+                )
+                UPDATE todos SET status = 'done'
+                FROM targets WHERE todos.id = targets.id
+                RETURNING todos.id, targets.status
+                "#
+            )
+            .bind(id)
+            .fetch_all(&mut *conn)
+            .await?;
+        }
+
+        let mut revisions = vec![(id, before.diff(&after))];
+        for (subtask_id, status) in completed {
+            let change = json!({ "before": status, "after": Status::Done });
+            revisions.push((subtask_id, Map::from_iter([("status".to_string(), change)])));
+        }
+        for (todo_id, changes) in revisions {
+            if changes.is_empty() {
+                continue;
+            }
+            sqlx::query(
+                r#"
+                INSERT INTO todo_revisions (todo_id, actor_id, changes)
+                VALUES ( $1, $2, $3 )
+                "#,
+            )
+            .bind(todo_id)
+            .bind(scope.user_id)
+            .bind(Value::Object(changes))
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::history", skip_all)]
+    async fn history(&self, scope: Scope, id: i32) -> anyhow::Result<Vec<TodoRevision>> {
+        self.find(scope, id).await?;
+        let revisions = sqlx::query_as::<_, TodoRevision>(
+            r#"
+            SELECT * FROM todo_revisions WHERE todo_id = $1 ORDER BY id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        Ok(revisions)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::revision", skip_all)]
+    async fn revision(
+        &self,
+        scope: Scope,
+        id: i32,
+        revision_id: i32,
+    ) -> anyhow::Result<TodoRevision> {
+        self.find(scope, id).await?;
+        let revision = sqlx::query_as::<_, TodoRevision>(
+            r#"
+            SELECT * FROM todo_revisions WHERE id = $1 AND todo_id = $2
+            "#,
+        )
+        .bind(revision_id)
+        .bind(id)
+        .fetch_optional(&self.read_pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(revision_id))?;
+
+        Ok(revision)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::activity", skip_all)]
+    async fn activity(&self, scope: Scope, limit: usize) -> anyhow::Result<Vec<TodoActivity>> {
+        let todos = self.all(scope, &TodoFilter::default()).await?;
+        let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        let completions = sqlx::query_as::<_, (i32, DateTime<Utc>)>(
+            r#"
+            SELECT todo_id, created_at FROM todo_revisions
+            WHERE todo_id = ANY($1) AND changes -> 'status' ->> 'after' = 'done'
+            ORDER BY created_at DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(&ids)
+        .bind(limit as i64)
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        Ok(collect_activity(todos, completions, limit))
+    }
+
+    #[tracing::instrument(name = "TodoRepository::label_usage", skip_all)]
+    async fn label_usage(&self, scope: Scope) -> anyhow::Result<Vec<LabelUsage>> {
+        let usage = sqlx::query_as::<_, LabelUsage>(
+            r#"
+            SELECT label_id, SUM(open)::BIGINT AS open, SUM(completed)::BIGINT AS completed
+            FROM label_usage
+            WHERE ($2::INTEGER IS NULL AND workspace_id IS NULL AND user_id = $1)
+                OR workspace_id = $2
+            GROUP BY label_id
+            ORDER BY label_id
+            "#,
+        )
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    // CONCURRENTLY なので、数え直している間も前の件数を読める
+    #[tracing::instrument(name = "TodoRepository::refresh_label_usage", skip_all)]
+    async fn refresh_label_usage(&self) -> anyhow::Result<()> {
+        sqlx::query("REFRESH MATERIALIZED VIEW CONCURRENTLY label_usage")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "TodoRepository::restore", skip_all)]
+    async fn restore_in(&self, tx: &mut Tx, scope: Scope, snapshot: Todo) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        let id = snapshot.id;
+        let exists =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM todos WHERE id = $1)")
+                .bind(id)
+                .fetch_one(&mut *conn)
+                .await?;
+        let old_todo = match exists {
+            true => Some(
+                self.find_with_permission_in(conn, scope, id, Some(SharePermission::Write))
+                    .await?,
+            ),
+            false => None,
+        };
+        let owner = match &old_todo {
+            Some(old_todo) => {
+                let moved = old_todo.archived_at != snapshot.archived_at
+                    || old_todo.is_pinned != snapshot.is_pinned
+                    || old_todo.snoozed_until != snapshot.snoozed_until
+                    || old_todo.position != snapshot.position;
+                if moved && !old_todo.is_visible_in(scope) {
+                    return Err(RepositoryError::Forbidden(id).into());
+                }
+                Scope::new(old_todo.user_id, old_todo.workspace_id)
+            }
+            None => {
+                if !snapshot.is_visible_in(scope) {
+                    return Err(RepositoryError::Forbidden(id).into());
+                }
+                Scope::new(snapshot.user_id, snapshot.workspace_id)
+            }
+        };
+
+        // もう存在しない親・プロジェクト・担当者・ラベルを外す
+        let mut parent_id = None;
+        if let Some(snapshot_parent_id) = snapshot.parent_id {
+            let parent_exists =
+                sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM todos WHERE id = $1)")
+                    .bind(snapshot_parent_id)
+                    .fetch_one(&mut *conn)
+                    .await?;
+            if parent_exists {
+                self.check_parent(
+                    conn,
+                    scope,
+                    owner,
+                    old_todo.as_ref().map(|todo| todo.id),
+                    snapshot_parent_id,
+                )
+                .await?;
+                parent_id = Some(snapshot_parent_id);
+            }
+        }
+        let project_id = sqlx::query_scalar::<_, i32>("SELECT id FROM projects WHERE id = $1")
+            .bind(snapshot.project_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let assignee_id = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE id = $1")
+            .bind(snapshot.assignee_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let label_ids: Vec<i32> = snapshot.labels.iter().map(|label| label.id).collect();
+        let label_ids = sqlx::query_scalar::<_, i32>("SELECT id FROM labels WHERE id = ANY($1)")
+            .bind(label_ids)
+            .fetch_all(&mut *conn)
+            .await?;
+        let restored = Todo {
+            user_id: owner.user_id,
+            workspace_id: owner.workspace_id,
+            parent_id,
+            project_id,
+            assignee_id,
+            labels: snapshot
+                .labels
+                .into_iter()
+                .filter(|label| label_ids.contains(&label.id))
+                .collect(),
+            ..snapshot
+        };
+
+        // 所有者は作り直すときだけ設定し、存在する todo の所有者は変えない
+        sqlx::query(
+            r#"
+            INSERT INTO todos (id, text, description, status, user_id, workspace_id, due_at, priority,
+                parent_id, recurrence_freq, recurrence_interval, recurrence_until, archived_at, position,
+                project_id, created_at, assignee_id, is_pinned, snoozed_until, starts_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18,
+                $19, $20)
+            ON CONFLICT (id) DO UPDATE SET text = EXCLUDED.text, description = EXCLUDED.description,
+                status = EXCLUDED.status, due_at = EXCLUDED.due_at, priority = EXCLUDED.priority,
+                parent_id = EXCLUDED.parent_id, recurrence_freq = EXCLUDED.recurrence_freq,
+                recurrence_interval = EXCLUDED.recurrence_interval,
+                recurrence_until = EXCLUDED.recurrence_until, archived_at = EXCLUDED.archived_at,
+                position = EXCLUDED.position, project_id = EXCLUDED.project_id,
+                assignee_id = EXCLUDED.assignee_id, is_pinned = EXCLUDED.is_pinned,
+                snoozed_until = EXCLUDED.snoozed_until, starts_at = EXCLUDED.starts_at
+            "#
+        )
+        .bind(id)
+        .bind(&restored.text)
+        .bind(&restored.description)
+        .bind(restored.status)
+        .bind(restored.user_id)
+        .bind(restored.workspace_id)
+        .bind(restored.due_at)
+        .bind(restored.priority)
+        .bind(restored.parent_id)
+        .bind(restored.recurrence.map(|recurrence| recurrence.freq))
+        .bind(restored.recurrence.map_or(1, |recurrence| recurrence.interval))
+        .bind(restored.recurrence.and_then(|recurrence| recurrence.until))
+        .bind(restored.archived_at)
+        .bind(restored.position)
+        .bind(restored.project_id)
+        .bind(restored.created_at)
+        .bind(restored.assignee_id)
+        .bind(restored.is_pinned)
+        .bind(restored.snoozed_until)
+        .bind(restored.starts_at)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query("DELETE FROM todo_labels WHERE todo_id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT $1, id
+            FROM unnest($2) as t(id);
+            "#,
+        )
+        .bind(id)
+        .bind(&label_ids)
+        .execute(&mut *conn)
+        .await?;
+
+        if let Some(old_todo) = &old_todo {
+            let changes = TodoFields::of(old_todo).diff(&TodoFields::of(&restored));
+            if !changes.is_empty() {
+                sqlx::query(
+                    r#"
+                    INSERT INTO todo_revisions (todo_id, actor_id, changes)
+                    VALUES ( $1, $2, $3 )
+                    "#,
+                )
+                .bind(id)
+                .bind(scope.user_id)
+                .bind(Value::Object(changes))
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::delete", skip_all)]
+    async fn delete_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let conn = tx.conn()?;
+        // 所有者の確認. 存在しない or scope 外の todo ならここでエラーになる
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        // 中間テーブルの関係を外す
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels WHERE todo_id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        // todo の削除
+        sqlx::query(
+            r#"
+            DELETE FROM todos WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "TodoRepository::complete_many", skip_all)]
+    async fn complete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<Vec<Todo>> {
+        // targets は UPDATE の前の値なので、そのまま更新前の todo として返せる
+        let sql = format!(
+            r#"
+            WITH filtered AS ({}), targets AS (
+                SELECT todos.* FROM filtered todos
+                WHERE ({} OR todos.id IN (
+                        SELECT todo_id FROM todo_shares WHERE user_id = $1 AND permission = 'write'
+                    ))
+                    AND todos.status NOT IN ('done', 'cancelled')
+                    AND ($18::INTEGER[] IS NULL OR todos.id = ANY($18))
+            ), completed AS (
+                UPDATE todos SET status = 'done'
+                FROM targets WHERE todos.id = targets.id
+                RETURNING todos.id
+            )
+            SELECT targets.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM targets
+                INNER JOIN completed ON completed.id = targets.id
+                LEFT OUTER JOIN todo_labels tl on targets.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY targets.id
+            "#,
+            FILTERED_TODOS, OWNED_IN_SCOPE
+        );
+
+        let conn = tx.conn()?;
+        let rows =
+            sqlx::query_as_with::<_, TodoWithLabelFromRow, _>(&sql, selection.arguments(scope))
+                .fetch_all(&mut *conn)
+                .await?;
+        let todos = fold_entities(rows);
+
+        for todo in &todos {
+            let change = json!({ "before": todo.status, "after": Status::Done });
+            sqlx::query(
+                r#"
+                INSERT INTO todo_revisions (todo_id, actor_id, changes)
+                VALUES ( $1, $2, $3 )
+                "#,
+            )
+            .bind(todo.id)
+            .bind(scope.user_id)
+            .bind(json!({ "status": change }))
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(todos)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::delete_many", skip_all)]
+    async fn delete_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<DeletedTodos> {
+        // 同じ文の中では削除前の状態が見えるので、消したラベルの関係や添付ファイルも targets から引ける
+        let sql = format!(
+            r#"
+            WITH filtered AS ({}), targets AS (
+                SELECT todos.* FROM filtered todos
+                WHERE {} AND ($18::INTEGER[] IS NULL OR todos.id = ANY($18))
+            ), unlinked AS (
+                DELETE FROM todo_labels WHERE todo_id IN (SELECT id FROM targets)
+            ), deleted AS (
+                DELETE FROM todos WHERE id IN (SELECT id FROM targets)
+                RETURNING id
+            )
+            SELECT targets.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon,
+                ARRAY(SELECT storage_key FROM attachments WHERE todo_id = targets.id) as storage_keys
+            FROM targets
+                INNER JOIN deleted ON deleted.id = targets.id
+                LEFT OUTER JOIN todo_labels tl on targets.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY targets.id
+            "#,
+            FILTERED_TODOS, OWNED_IN_SCOPE
+        );
+        let rows = sqlx::query_with(&sql, selection.arguments(scope))
+            .fetch_all(tx.conn()?)
+            .await?;
+
+        let mut items = Vec::with_capacity(rows.len());
+        let mut storage_keys = vec![];
+        for row in &rows {
+            items.push(TodoWithLabelFromRow::from_row(row)?);
+            storage_keys.extend(row.try_get::<Vec<String>, _>("storage_keys")?);
+        }
+        // ラベルの数だけ同じ行が返るので重複を除く
+        storage_keys.sort();
+        storage_keys.dedup();
+
+        Ok(DeletedTodos {
+            todos: fold_entities(items),
+            storage_keys,
+        })
+    }
+
+    #[tracing::instrument(name = "TodoRepository::relabel_many", skip_all)]
+    async fn relabel_many_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        ids: &[i32],
+        add: &[Label],
+        remove: &[i32],
+    ) -> anyhow::Result<Vec<RelabeledTodo>> {
+        let conn = tx.conn()?;
+        let sql = format!(
+            r#"
+            SELECT todos.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon
+            FROM todos
+                LEFT OUTER JOIN todo_labels tl on todos.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            WHERE todos.id = ANY($3) AND todos.archived_at IS NULL
+                AND ({} OR ($2::INTEGER IS NULL AND todos.id IN (
+                    SELECT todo_id FROM todo_shares WHERE user_id = $1 AND permission = 'write'
+                )))
+            ORDER BY todos.id
+            "#,
+            OWNED_IN_SCOPE
+        );
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(scope.user_id)
+            .bind(scope.workspace_id)
+            .bind(ids)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        // 変わらない todo は更新も履歴もいらない
+        let mut relabeled = vec![];
+        for before in fold_entities(rows) {
+            let after = before.relabeled(add, remove);
+            let changes = TodoFields::of(&before).diff(&TodoFields::of(&after));
+            if !changes.is_empty() {
+                relabeled.push((RelabeledTodo { before, after }, changes));
+            }
+        }
+        let todo_ids: Vec<i32> = relabeled.iter().map(|(todo, _)| todo.before.id).collect();
+        let label_ids: Vec<i32> = add.iter().map(|label| label.id).collect();
+
+        sqlx::query("DELETE FROM todo_labels WHERE todo_id = ANY($1) AND label_id = ANY($2)")
+            .bind(&todo_ids)
+            .bind(remove)
+            .execute(&mut *conn)
+            .await?;
+        // todo_labels には一意制約がないので、付いていない組み合わせだけを入れる
+        sqlx::query(
+            r#"
+            INSERT INTO todo_labels (todo_id, label_id)
+            SELECT t.todo_id, l.label_id
+            FROM unnest($1::INTEGER[]) as t(todo_id) CROSS JOIN unnest($2::INTEGER[]) as l(label_id)
+            WHERE NOT EXISTS (
+                SELECT 1 FROM todo_labels WHERE todo_id = t.todo_id AND label_id = l.label_id
+            )
+            "#,
+        )
+        .bind(&todo_ids)
+        .bind(&label_ids)
+        .execute(&mut *conn)
+        .await?;
+
+        for (todo, changes) in &relabeled {
+            sqlx::query(
+                r#"
+                INSERT INTO todo_revisions (todo_id, actor_id, changes)
+                VALUES ( $1, $2, $3 )
+                "#,
+            )
+            .bind(todo.before.id)
+            .bind(scope.user_id)
+            .bind(Value::Object(changes.clone()))
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(relabeled.into_iter().map(|(todo, _)| todo).collect())
+    }
+
+    #[tracing::instrument(name = "TodoRepository::archive", skip_all)]
+    async fn archive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET archived_at = COALESCE(archived_at, now()) WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::unarchive", skip_all)]
+    async fn unarchive_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET archived_at = NULL WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::pin", skip_all)]
+    async fn pin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET is_pinned = true WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::unpin", skip_all)]
+    async fn unpin_in(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET is_pinned = false WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::snooze", skip_all)]
+    async fn snooze_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET snoozed_until = $2 WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(until)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::assign", skip_all)]
+    async fn assign_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        assignee_id: Option<i32>,
+    ) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, Some(SharePermission::Write))
+            .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE todos SET assignee_id = $2 WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(assignee_id)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::move_to", skip_all)]
+    async fn move_to_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+        target: MoveTodo,
+    ) -> anyhow::Result<Todo> {
+        let conn = tx.conn()?;
+        let todo = self.find_with_permission_in(conn, scope, id, None).await?;
+
+        // 同じ一覧の並び替えが同時に走っても position が重ならないよう、一覧の行をまとめてロックする
+        let rows = sqlx::query_as::<_, (i32, i64)>(
+            r#"
+            SELECT id, position FROM todos
+            WHERE ($2::INTEGER IS NULL AND workspace_id IS NULL AND user_id = $1)
+                OR workspace_id = $2
+            ORDER BY position, id
+            FOR UPDATE
+            "#,
+        )
+        .bind(todo.user_id)
+        .bind(todo.workspace_id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let siblings: Vec<(i32, i64)> = rows
+            .into_iter()
+            .filter(|(row_id, _)| *row_id != id)
+            .collect();
+        let index = target.index_in(&siblings)?;
+        let (ids, positions): (Vec<i32>, Vec<i64>) =
+            plan_move(&siblings, id, index).into_iter().unzip();
+        sqlx::query(
+            r#"
+            UPDATE todos SET position = t.position
+            FROM unnest($1::INTEGER[], $2::BIGINT[]) AS t(id, position)
+            WHERE todos.id = t.id
+            "#,
+        )
+        .bind(ids)
+        .bind(positions)
+        .execute(&mut *conn)
+        .await?;
+
+        self.find_written(conn, scope, id).await
+    }
+
+    #[tracing::instrument(name = "TodoRepository::duplicate", skip_all)]
+    async fn duplicate_in(
+        &self,
+        tx: &mut Tx,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<DuplicatedTodo> {
+        let conn = tx.conn()?;
+        self.find_with_permission_in(conn, scope, id, None).await?;
+
+        // 親を先に複製するため、深さの浅い順に並べる
+        let tree = sqlx::query_as::<_, (i32, Option<i32>)>(
+            r#"
+            WITH RECURSIVE tree (id, parent_id, depth) AS (
+                SELECT id, parent_id, 0 FROM todos WHERE id = $1
+                UNION ALL
+                SELECT todos.id, todos.parent_id, tree.depth + 1
+                FROM todos INNER JOIN tree ON todos.parent_id = tree.id
+            )
+            SELECT id, parent_id FROM tree
+            ORDER BY depth, id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        // 元の id -> 複製の id
+        let mut copies: HashMap<i32, i32> = HashMap::new();
+        let mut attachments = vec![];
+        for (source_id, parent_id) in tree {
+            // 複製した todo 自身は元と同じ親の下に置く
+            let parent_id = match source_id == id {
+                true => parent_id,
+                false => parent_id.and_then(|parent_id| copies.get(&parent_id).copied()),
+            };
+            let copy_id = sqlx::query_scalar::<_, i32>(
+                r#"
+                INSERT INTO todos (text, description, status, user_id, workspace_id, due_at, priority,
+                    parent_id, recurrence_freq, recurrence_interval, recurrence_until, project_id,
+                    starts_at)
+                SELECT text, description, status, $3, workspace_id, due_at, priority,
+                    $2, recurrence_freq, recurrence_interval, recurrence_until, project_id, starts_at
+                FROM todos WHERE id = $1
+                RETURNING id
+                "#
+            )
+            .bind(source_id)
+            .bind(parent_id)
+            .bind(scope.user_id)
+            .fetch_one(&mut *conn)
+            .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO todo_labels (todo_id, label_id)
+                SELECT $1, label_id FROM todo_labels WHERE todo_id = $2
+                "#,
+            )
+            .bind(copy_id)
+            .bind(source_id)
+            .execute(&mut *conn)
+            .await?;
+
+            // storage_key は一意なので、複製には新しい key を振る
+            let sources = sqlx::query_as::<_, Attachment>(
+                r#"
+                SELECT * FROM attachments WHERE todo_id = $1 ORDER BY id
+                "#,
+            )
+            .bind(source_id)
+            .fetch_all(&mut *conn)
+            .await?;
+            for source in sources {
+                let attachment = sqlx::query_as::<_, Attachment>(
+                    r#"
+                    INSERT INTO attachments (todo_id, user_id, file_name, content_type, size, storage_key)
+                    VALUES ( $1, $2, $3, $4, $5, $6 )
+                    RETURNING *
+                    "#
+                )
+                .bind(copy_id)
+                .bind(source.user_id)
+                .bind(&source.file_name)
+                .bind(&source.content_type)
+                .bind(source.size)
+                .bind(storage_key(copy_id))
+                .fetch_one(&mut *conn)
+                .await?;
+                attachments.push((source.storage_key, attachment));
+            }
+            copies.insert(source_id, copy_id);
+        }
+
+        let todo = self.find_written(conn, scope, copies[&id]).await?;
+        Ok(DuplicatedTodo { todo, attachments })
+    }
+
+    #[tracing::instrument(name = "TodoRepository::share", skip_all)]
+    async fn share(&self, scope: Scope, id: i32, payload: ShareTodo) -> anyhow::Result<TodoShare> {
+        self.find_with_permission(scope, id, None).await?;
+
+        let share = sqlx::query_as::<_, TodoShare>(
+            r#"
+            INSERT INTO todo_shares (todo_id, user_id, permission)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (todo_id, user_id) DO UPDATE SET permission = EXCLUDED.permission
+            RETURNING todo_id, user_id, permission
+            "#,
+        )
+        .bind(id)
+        .bind(payload.user_id)
+        .bind(payload.permission)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            // 存在しないユーザーへの共有 (外部キー違反)
+            sqlx::Error::Database(ref db) if db.code().as_deref() == Some("23503") => {
+                RepositoryError::NotFound(payload.user_id)
+            }
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(share)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::merge", skip_all)]
+    async fn merge(&self, from_user_id: i32, into_user_id: i32) -> anyhow::Result<Vec<Todo>> {
+        let mut tx = self.pool.begin().await?;
+
+        // todo に付いている label も一緒に付け替えないと、引き継いだ todo から見えなくなる
+        let ids = sqlx::query_scalar::<_, i32>(
+            r#"
+            UPDATE todos SET user_id = $2
+            WHERE user_id = $1 AND workspace_id IS NULL
+            RETURNING id
+            "#,
+        )
+        .bind(from_user_id)
+        .bind(into_user_id)
+        .fetch_all(&mut tx)
+        .await?;
+        // 個人のラベルの名前は user ごとに一意なので、引き継ぐ側に同じ名前の個人のラベルがあればそちらに付け替えて消す
+        let duplicates = sqlx::query_as::<_, (i32, i32)>(
+            r#"
+            SELECT labels.id, existing.id FROM labels
+                INNER JOIN labels existing
+                    ON existing.user_id = $2 AND existing.workspace_id IS NULL
+                        AND lower(existing.name) = lower(labels.name)
+            WHERE labels.user_id = $1 AND labels.workspace_id IS NULL
+            "#,
+        )
+        .bind(from_user_id)
+        .bind(into_user_id)
+        .fetch_all(&mut tx)
+        .await?;
+        let (from_label_ids, into_label_ids): (Vec<i32>, Vec<i32>) = duplicates.into_iter().unzip();
+        sqlx::query(
+            r#"
+            UPDATE todo_labels SET label_id = t.into_id
+            FROM unnest($1::INTEGER[], $2::INTEGER[]) as t(from_id, into_id)
+            WHERE todo_labels.label_id = t.from_id
+            "#,
+        )
+        .bind(&from_label_ids)
+        .bind(&into_label_ids)
+        .execute(&mut tx)
+        .await?;
+        // 両方のラベルが付いていた todo は同じラベルが 2 回付くので 1 つにする
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels a USING todo_labels b
+            WHERE a.label_id = ANY($1) AND a.todo_id = b.todo_id AND a.label_id = b.label_id AND a.id > b.id
+            "#
+        )
+        .bind(&into_label_ids)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO template_labels (template_id, label_id)
+            SELECT template_labels.template_id, t.into_id
+            FROM template_labels
+                INNER JOIN unnest($1::INTEGER[], $2::INTEGER[]) as t(from_id, into_id)
+                    ON template_labels.label_id = t.from_id
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&from_label_ids)
+        .bind(&into_label_ids)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM labels WHERE id = ANY($1)")
+            .bind(&from_label_ids)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE labels SET user_id = $2
+            WHERE user_id = $1 AND workspace_id IS NULL
+            "#,
+        )
+        .bind(from_user_id)
+        .bind(into_user_id)
+        .execute(&mut tx)
+        .await?;
+        // 自分の todo になったものは共有しておく必要がない
+        sqlx::query(
+            r#"
+            DELETE FROM todo_shares
+            WHERE user_id = $1 AND todo_id = ANY($2)
+            "#,
+        )
+        .bind(into_user_id)
+        .bind(&ids)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        let todos = self
+            .all(Scope::new(into_user_id, None), &TodoFilter::default())
+            .await?
+            .into_iter()
+            .filter(|todo| ids.contains(&todo.id))
+            .collect();
+        Ok(todos)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::materialize_recurrences", skip_all)]
+    async fn materialize_recurrences_in(
+        &self,
+        tx: &mut Tx,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let conn = tx.conn()?;
+
+        // 複数のプロセスで scheduler が動いていても、同じ todo から二度作らないようロックする
+        let rows = sqlx::query_as::<_, TodoFromRow>(
+            r#"
+            SELECT * FROM todos
+            WHERE recurrence_freq IS NOT NULL AND status IN ('done', 'cancelled') AND recurred_at IS NULL
+                AND user_id IS NOT NULL
+            ORDER BY id
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut created = vec![];
+        for row in rows {
+            let recurrence = Recurrence::from_row(
+                row.recurrence_freq,
+                row.recurrence_interval,
+                row.recurrence_until,
+            );
+            // until を過ぎていれば次の todo は作らず、印だけ付ける
+            if let Some(due_at) =
+                recurrence.and_then(|recurrence| recurrence.next_due(row.due_at, now))
+            {
+                let id = sqlx::query_scalar::<_, i32>(
+                    r#"
+                    INSERT INTO todos (text, user_id, workspace_id, due_at, priority, parent_id,
+                        recurrence_freq, recurrence_interval, recurrence_until, description, project_id,
+                        assignee_id, starts_at)
+                    SELECT text, user_id, workspace_id, $2, priority, parent_id,
+                        recurrence_freq, recurrence_interval, recurrence_until, description, project_id,
+                        assignee_id, $3
+                    FROM todos WHERE id = $1
+                    RETURNING id
+                    "#
+                )
+                .bind(row.id)
+                .bind(due_at)
+                .bind(next_start(row.starts_at, row.due_at, due_at))
+                .fetch_one(&mut *conn)
+                .await?;
+                sqlx::query(
+                    r#"
+                    INSERT INTO todo_labels (todo_id, label_id)
+                    SELECT $1, label_id FROM todo_labels WHERE todo_id = $2
+                    "#,
+                )
+                .bind(id)
+                .bind(row.id)
+                .execute(&mut *conn)
+                .await?;
+                created.push((Scope::new(row.user_id, row.workspace_id), id));
+            }
+            sqlx::query(
+                r#"
+                UPDATE todos SET recurred_at = $2 WHERE id = $1
+                "#,
+            )
+            .bind(row.id)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        let mut todos = vec![];
+        for (scope, id) in created {
+            todos.push(self.find_written(conn, scope, id).await?);
+        }
+        Ok(todos)
+    }
+
+    #[tracing::instrument(name = "TodoRepository::purge_archived", skip_all)]
+    async fn purge_archived_in(
+        &self,
+        tx: &mut Tx,
+        before: DateTime<Utc>,
+    ) -> anyhow::Result<DeletedTodos> {
+        // delete_many と同じく、消したラベルの関係や添付ファイルも targets から引ける
+        let rows = sqlx::query(
+            r#"
+            WITH targets AS (
+                SELECT * FROM todos WHERE archived_at < $1
+            ), unlinked AS (
+                DELETE FROM todo_labels WHERE todo_id IN (SELECT id FROM targets)
+            ), deleted AS (
+                DELETE FROM todos WHERE id IN (SELECT id FROM targets)
+                RETURNING id
+            )
+            SELECT targets.*, labels.id as label_id, labels.name as label_name, labels.user_id as label_user_id, labels.workspace_id as label_workspace_id, labels.color as label_color, labels.parent_id as label_parent_id, labels.position as label_position, labels.archived_at as label_archived_at, labels.icon as label_icon,
+                ARRAY(SELECT storage_key FROM attachments WHERE todo_id = targets.id) as storage_keys
+            FROM targets
+                INNER JOIN deleted ON deleted.id = targets.id
+                LEFT OUTER JOIN todo_labels tl on targets.id = tl.todo_id
+                LEFT OUTER JOIN labels on labels.id = tl.label_id
+            ORDER BY targets.id
+            "#,
+        )
+        .bind(before)
+        .fetch_all(tx.conn()?)
+        .await?;
+
+        let mut items = Vec::with_capacity(rows.len());
+        let mut storage_keys = vec![];
+        for row in &rows {
+            items.push(TodoWithLabelFromRow::from_row(row)?);
+            storage_keys.extend(row.try_get::<Vec<String>, _>("storage_keys")?);
+        }
+        storage_keys.sort();
+        storage_keys.dedup();
+
+        Ok(DeletedTodos {
+            todos: fold_entities(items),
+            storage_keys,
+        })
+    }
+}
+
+// どのテストも DB を使うので、database-test なしでは準備用の関数も使われない
+#[cfg(test)]
+#[cfg_attr(not(feature = "database-test"), allow(unused_imports, dead_code))]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+
+        // label data prepare
+        // Note: Label レポジトリのテストデータと同じ名前だと2回目以降のテストが通らない.
+        //        このことから、複数スレッド or 複数クライアントを想定したシナリオが漏れている
+        //        脆弱なテストと言えるのではないか?
+        let label_name = String::from("test label");
+        let optional_label = sqlx::query_as::<_, Label>(
+            r#"
+            SELECT * FROM labels WHERE name = $1
+            "#,
+        )
+        .bind(label_name.clone())
         .fetch_optional(&pool)
         .await
-        .expect("failed to prepare label data.");
+        .expect("failed to prepare label data.");
+
+        let label_1 = if let Some(label) = optional_label {
+            // DB にラベルが入ってるならそれを使う
+            label
+        } else {
+            // DB に label_name と同名のラベルが存在しないなら作成
+            let label = sqlx::query_as::<_, Label>(
+                r#"
+                INSERT INTO labels ( name )
+                VALUES ( $1 )
+                RETURNING *
+                "#,
+            )
+            .bind(label_name)
+            .fetch_one(&pool)
+            .await
+            .expect("failed to insert label data.");
+            label
+        };
+        // Memo: この時点では、DB にラベル "test label" が存在する
+
+        // user data prepare
+        let user_id = prepare_user(&pool, "todo_crud_scenario@example.com").await;
+        let other_user_id = prepare_user(&pool, "todo_crud_scenario_other@example.com").await;
+
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let todo_text = "[crud_scenario] text";
+
+        // create
+        let created = repo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo::new(todo_text.to_string(), vec![label_1.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(created.text, todo_text);
+        assert_eq!(created.status, Status::Backlog);
+        assert_eq!(created.user_id, user_id);
+        assert_eq!(*created.labels.first().unwrap(), label_1);
+
+        // find
+        let todo = repo
+            .find(Scope::personal(user_id), created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(todo, created);
+
+        // 他のユーザーからは見えない
+        let res = repo.find(Scope::personal(other_user_id), created.id).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let todos = repo
+            .all(Scope::personal(other_user_id), &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().all(|todo| todo.id != created.id));
+
+        // share
+        let share = repo
+            .share(
+                Scope::personal(user_id),
+                created.id,
+                ShareTodo {
+                    user_id: other_user_id,
+                    permission: SharePermission::Read,
+                },
+            )
+            .await
+            .expect("[share] returned Err");
+        assert_eq!(share.permission, SharePermission::Read);
+        let todo = repo
+            .find(Scope::personal(other_user_id), created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(todo, created);
+        assert!(repo
+            .share(
+                Scope::personal(other_user_id),
+                created.id,
+                ShareTodo {
+                    user_id: other_user_id,
+                    permission: SharePermission::Write,
+                }
+            )
+            .await
+            .is_err());
+
+        // all_unscoped
+        let todos = repo
+            .all_unscoped()
+            .await
+            .expect("[all_unscoped] returned Err");
+        assert!(todos.iter().any(|todo| todo.id == created.id));
+
+        // all_by_user は共有された todo を含まない
+        let todos = repo
+            .all_by_user(user_id)
+            .await
+            .expect("[all_by_user] returned Err");
+        assert!(todos.iter().any(|todo| todo.id == created.id));
+        let todos = repo
+            .all_by_user(other_user_id)
+            .await
+            .expect("[all_by_user] returned Err");
+        assert!(todos.iter().all(|todo| todo.id != created.id));
+
+        // all
+        let todos = repo
+            .all(Scope::personal(user_id), &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        // assert_eq!(todos, vec![todo]);
+        let todo = todos.first().unwrap();
+        assert_eq!(created, *todo);
+
+        // update
+        let update_text = "[crud_scenario] updated text";
+        let todo = repo
+            .update(
+                Scope::personal(user_id),
+                todo.id,
+                UpdateTodo {
+                    text: Some(update_text.to_string()),
+                    description: Some(Some("**memo**".to_string())),
+                    status: Some(Status::Done),
+                    labels: Some(vec![]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(created.id, todo.id);
+        assert_eq!(todo.text, update_text);
+        assert_eq!(todo.description.as_deref(), Some("**memo**"));
+        assert!(todo.labels.is_empty());
+
+        // archive / unarchive
+        let archived = repo
+            .archive(Scope::personal(user_id), todo.id)
+            .await
+            .expect("[archive] returned Err");
+        assert!(archived.archived_at.is_some());
+        assert!(repo
+            .archive(Scope::personal(other_user_id), todo.id)
+            .await
+            .is_err());
+        let todos = repo
+            .all(Scope::personal(user_id), &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().all(|todo| todo.id != archived.id));
+        let filter = TodoFilter {
+            archived: Some(true),
+            ..Default::default()
+        };
+        let todos = repo
+            .all(Scope::personal(user_id), &filter)
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().any(|todo| todo.id == archived.id));
+        let todo = repo
+            .unarchive(Scope::personal(user_id), todo.id)
+            .await
+            .expect("[unarchive] returned Err");
+        assert!(todo.archived_at.is_none());
+
+        // move_to
+        let first = repo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo::new("[crud_scenario] first".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert!(first.position > todo.position);
+        let moved = repo
+            .move_to(
+                Scope::personal(user_id),
+                first.id,
+                MoveTodo {
+                    before: Some(todo.id),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[move_to] returned Err");
+        assert!(
+            moved.position
+                < repo
+                    .find(Scope::personal(user_id), todo.id)
+                    .await
+                    .unwrap()
+                    .position
+        );
+        assert!(repo
+            .move_to(
+                Scope::personal(other_user_id),
+                first.id,
+                MoveTodo {
+                    index: Some(0),
+                    ..Default::default()
+                }
+            )
+            .await
+            .is_err());
+        repo.delete(Scope::personal(user_id), first.id)
+            .await
+            .expect("[delete] returned Err");
+
+        // due_at と期限切れの絞り込み
+        let due_at = chrono::Utc::now() - chrono::Duration::days(1);
+        let overdue = repo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo {
+                    due_at: Some(due_at),
+                    ..CreateTodo::new("[crud_scenario] overdue".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert!(overdue.due_at.is_some());
+        assert_eq!(overdue.priority, Priority::Medium);
+        let filter = TodoFilter {
+            overdue: Some(true),
+            ..Default::default()
+        };
+        let todos = repo
+            .all(Scope::personal(user_id), &filter)
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().any(|todo| todo.id == overdue.id));
+        assert!(todos.iter().all(|todo| todo.id != created.id));
+        let filter = TodoFilter {
+            due_before: Some(due_at),
+            ..Default::default()
+        };
+        let todos = repo
+            .all(Scope::personal(user_id), &filter)
+            .await
+            .expect("[all] returned Err");
+        assert!(todos.iter().all(|todo| todo.id != overdue.id));
+
+        // サブタスクと循環の検出
+        let child = repo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo {
+                    parent_id: Some(overdue.id),
+                    ..CreateTodo::new("[crud_scenario] child".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(child.parent_id, Some(overdue.id));
+        let res = repo
+            .update(
+                Scope::personal(user_id),
+                overdue.id,
+                UpdateTodo {
+                    parent_id: Some(Some(child.id)),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Invalid(_))
+        ));
+        assert!(repo
+            .create(
+                Scope::personal(other_user_id),
+                CreateTodo {
+                    parent_id: Some(overdue.id),
+                    ..CreateTodo::new("[crud_scenario] other child".to_string(), vec![])
+                }
+            )
+            .await
+            .is_err());
+        repo.update(
+            Scope::personal(user_id),
+            overdue.id,
+            UpdateTodo {
+                status: Some(Status::Done),
+                complete_subtasks: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+        let filter = TodoFilter {
+            parent_id: Some(overdue.id),
+            ..Default::default()
+        };
+        let children = repo
+            .all(Scope::personal(user_id), &filter)
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].status, Status::Done);
+        repo.delete(Scope::personal(user_id), child.id)
+            .await
+            .expect("[delete] returned Err");
+
+        // 繰り返し
+        let recurrence = Recurrence {
+            freq: Frequency::Weekly,
+            interval: 1,
+            until: None,
+        };
+        let recurring = repo
+            .create(
+                Scope::personal(user_id),
+                CreateTodo {
+                    due_at: Some(due_at),
+                    recurrence: Some(recurrence),
+                    ..CreateTodo::new("[crud_scenario] weekly".to_string(), vec![label_1.id])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(recurring.recurrence, Some(recurrence));
+        repo.update(
+            Scope::personal(user_id),
+            recurring.id,
+            UpdateTodo {
+                status: Some(Status::Done),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+        let now = chrono::Utc::now();
+        let materialized = repo
+            .materialize_recurrences(now)
+            .await
+            .expect("[materialize_recurrences] returned Err");
+        let next = materialized
+            .iter()
+            .find(|todo| todo.text == recurring.text)
+            .expect("next todo is not created");
+        assert_eq!(
+            next.due_at,
+            recurring
+                .due_at
+                .map(|due_at| due_at + chrono::Duration::weeks(1))
+        );
+        assert_eq!(next.labels, recurring.labels);
+        assert_eq!(next.status, Status::Backlog);
+        let materialized = repo
+            .materialize_recurrences(now)
+            .await
+            .expect("[materialize_recurrences] returned Err");
+        assert!(materialized.iter().all(|todo| todo.text != recurring.text));
+        for id in [recurring.id, next.id] {
+            repo.delete(Scope::personal(user_id), id)
+                .await
+                .expect("[delete] returned Err");
+        }
+
+        // priority
+        let urgent = repo
+            .update(
+                Scope::personal(user_id),
+                overdue.id,
+                UpdateTodo {
+                    priority: Some(Priority::Urgent),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(urgent.priority, Priority::Urgent);
+        let filter = TodoFilter {
+            sort: Some(TodoSort::Priority),
+            ..Default::default()
+        };
+        let todos = repo
+            .all(Scope::personal(user_id), &filter)
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(todos.first().map(|todo| todo.id), Some(urgent.id));
+        repo.delete(Scope::personal(user_id), overdue.id)
+            .await
+            .expect("[delete] returned Err");
+
+        // delete
+        assert!(repo
+            .delete(Scope::personal(other_user_id), todo.id)
+            .await
+            .is_err());
+        repo.delete(Scope::personal(user_id), todo.id)
+            .await
+            .expect("[delete] returned Err");
+        let res = repo.find(Scope::personal(user_id), created.id).await;
+        assert!(res.is_err());
+
+        let todo_rows = sqlx::query(
+            r#"
+            SELECT * FROM todos where id = $1
+            "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("[delete] todo_labels fetch error");
+        assert!(todo_rows.is_empty());
+
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM todo_labels WHERE todo_id = $1
+            "#,
+        )
+        .bind(todo.id)
+        .fetch_all(&pool)
+        .await
+        .expect("[delete] todo_labels fect error");
+        assert!(rows.is_empty());
+
+        // merge. 同じ名前のラベルは引き継ぐ側のラベルにまとめる
+        let other_label =
+            prepare_label(&pool, other_user_id, "[crud_scenario] merged label", None).await;
+        let label = prepare_label(&pool, user_id, "[crud_scenario] MERGED label", None).await;
+        let created = repo
+            .create(
+                Scope::personal(other_user_id),
+                CreateTodo::new(
+                    "[crud_scenario] merged text".to_string(),
+                    vec![other_label.id],
+                ),
+            )
+            .await
+            .expect("[create] returned Err");
+        let merged = repo
+            .merge(other_user_id, user_id)
+            .await
+            .expect("[merge] returned Err");
+        assert!(merged
+            .iter()
+            .any(|todo| todo.id == created.id && todo.user_id == user_id));
+        let todo = repo
+            .find(Scope::personal(user_id), created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(todo.user_id, user_id);
+        assert_eq!(todo.labels, vec![label]);
+        assert!(repo
+            .find(Scope::personal(other_user_id), created.id)
+            .await
+            .is_err());
+        repo.delete(Scope::personal(user_id), created.id)
+            .await
+            .expect("[delete] returned Err");
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn duplicate_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "duplicate_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+        let label = prepare_label(&pool, user_id, "[duplicate_scenario] label", None).await;
+
+        let parent = repo
+            .create(
+                scope,
+                CreateTodo::new("[duplicate_scenario] parent".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let child = repo
+            .create(
+                scope,
+                CreateTodo::from_subtask(parent.id, "[duplicate_scenario] child".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+        let (storage_key,): (String,) = sqlx::query_as(
+            r#"
+            INSERT INTO attachments (todo_id, user_id, file_name, content_type, size, storage_key)
+            VALUES ( $1, $2, 'memo.txt', 'text/plain', 4, $3 )
+            RETURNING storage_key
+            "#,
+        )
+        .bind(child.id)
+        .bind(user_id)
+        .bind(super::storage_key(child.id))
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare attachment data.");
+
+        let duplicated = repo
+            .duplicate(scope, parent.id)
+            .await
+            .expect("[duplicate] returned Err");
+        assert_ne!(duplicated.todo.id, parent.id);
+        assert_eq!(duplicated.todo.text, parent.text);
+        assert_eq!(duplicated.todo.labels, vec![label.clone()]);
+        assert!(duplicated.todo.position > child.position);
+        let filter = TodoFilter {
+            parent_id: Some(duplicated.todo.id),
+            ..Default::default()
+        };
+        let children = repo.all(scope, &filter).await.expect("[all] returned Err");
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].text, child.text);
+        let [(source_key, attachment)] = duplicated.attachments.as_slice() else {
+            panic!(
+                "attachments are not duplicated: {:?}",
+                duplicated.attachments
+            );
+        };
+        assert_eq!(source_key, &storage_key);
+        assert_eq!(attachment.todo_id, children[0].id);
+        assert_eq!(attachment.file_name, "memo.txt");
+        assert_ne!(attachment.storage_key, storage_key);
+
+        // 他人の todo は複製できない
+        let other_user_id = prepare_user(&pool, "duplicate_scenario_other@example.com").await;
+        assert!(repo
+            .duplicate(Scope::personal(other_user_id), parent.id)
+            .await
+            .is_err());
+
+        for id in [children[0].id, duplicated.todo.id, child.id, parent.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label.id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up label");
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn revision_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "revision_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let parent = repo
+            .create(
+                scope,
+                CreateTodo::new("[revision_scenario] parent".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let child = repo
+            .create(
+                scope,
+                CreateTodo::from_subtask(parent.id, "[revision_scenario] child".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+        repo.update(
+            scope,
+            parent.id,
+            UpdateTodo {
+                text: Some("[revision_scenario] renamed".to_string()),
+                status: Some(Status::Done),
+                complete_subtasks: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+
+        let revisions = repo
+            .history(scope, parent.id)
+            .await
+            .expect("[history] returned Err");
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].actor_id, Some(user_id));
+        assert_eq!(
+            revisions[0].changes,
+            json!({
+                "text": { "before": "[revision_scenario] parent", "after": "[revision_scenario] renamed" },
+                "status": { "before": "backlog", "after": "done" },
+            })
+        );
+        // complete_subtasks で完了にしたサブタスクにも履歴が残る
+        let child_revisions = repo
+            .history(scope, child.id)
+            .await
+            .expect("[history] returned Err");
+        assert_eq!(
+            child_revisions
+                .iter()
+                .map(|revision| &revision.changes)
+                .collect::<Vec<_>>(),
+            vec![&json!({ "status": { "before": "backlog", "after": "done" } })]
+        );
+        assert!(repo
+            .revision(scope, child.id, revisions[0].id)
+            .await
+            .is_err());
+
+        let revision = repo
+            .revision(scope, parent.id, revisions[0].id)
+            .await
+            .expect("[revision] returned Err");
+        let payload = UpdateTodo::revert(&revision).expect("[revert] returned Err");
+        let todo = repo
+            .update(scope, parent.id, payload)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.text, parent.text);
+        assert_eq!(todo.status, Status::Backlog);
+        assert_eq!(repo.history(scope, parent.id).await.unwrap().len(), 2);
+
+        let other_user_id = prepare_user(&pool, "revision_scenario_other@example.com").await;
+        assert!(repo
+            .history(Scope::personal(other_user_id), parent.id)
+            .await
+            .is_err());
+
+        // 差し戻しても完了した出来事は残る
+        let activity = repo
+            .activity(scope, 10)
+            .await
+            .expect("[activity] returned Err");
+        let events: Vec<(i32, TodoActivityKind)> = activity
+            .iter()
+            .map(|activity| (activity.todo.id, activity.kind))
+            .collect();
+        for id in [parent.id, child.id] {
+            assert!(events.contains(&(id, TodoActivityKind::Created)));
+            assert!(events.contains(&(id, TodoActivityKind::Completed)));
+        }
+        assert_eq!(repo.activity(scope, 1).await.unwrap().len(), 1);
+
+        for id in [child.id, parent.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn filter_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "filter_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+        let label = prepare_label(&pool, user_id, "[filter_scenario] label", None).await;
+
+        let labeled = repo
+            .create(
+                scope,
+                CreateTodo::new("[filter_scenario] 100% labeled".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let done = repo
+            .create(
+                scope,
+                CreateTodo::new("[filter_scenario] done".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        repo.update(
+            scope,
+            done.id,
+            UpdateTodo {
+                status: Some(Status::Done),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+
+        let ids = |todos: Vec<Todo>| todos.iter().map(|todo| todo.id).collect::<Vec<_>>();
+        let filter = TodoFilter {
+            label_id: Some(label.id),
+            ..Default::default()
+        };
+        let todos = repo.all(scope, &filter).await.expect("[all] returned Err");
+        assert_eq!(todos, vec![labeled.clone()]);
+        // sublabels なら孫のラベルを付けた todo も返す
+        let child = prepare_label(
+            &pool,
+            user_id,
+            "[filter_scenario] label/child",
+            Some(label.id),
+        )
+        .await;
+        let grandchild = prepare_label(
+            &pool,
+            user_id,
+            "[filter_scenario] label/child/grandchild",
+            Some(child.id),
+        )
+        .await
+        .id;
+        let nested = repo
+            .create(
+                scope,
+                CreateTodo::new("[filter_scenario] nested".to_string(), vec![grandchild]),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(
+            ids(repo.all(scope, &filter).await.unwrap()),
+            vec![labeled.id]
+        );
+        let filter = TodoFilter {
+            label_id: Some(label.id),
+            sublabels: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(repo.all(scope, &filter).await.unwrap()),
+            vec![nested.id, labeled.id]
+        );
+        repo.delete(scope, nested.id)
+            .await
+            .expect("[delete] returned Err");
+        // page_by_label は todo_labels と join して絞り込む
+        let (todos, total) = repo
+            .page_by_label(
+                scope,
+                label.id,
+                Page {
+                    limit: 10,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page_by_label] returned Err");
+        assert_eq!((ids(todos), total), (vec![labeled.id], 1));
+        let (todos, total) = repo
+            .page_by_label(
+                scope,
+                label.id,
+                Page {
+                    limit: 10,
+                    offset: 1,
+                },
+            )
+            .await
+            .expect("[page_by_label] returned Err");
+        assert_eq!((ids(todos), total), (vec![], 1));
+        let filter = TodoFilter {
+            completed: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(ids(repo.all(scope, &filter).await.unwrap()), vec![done.id]);
+        // % は LIKE のワイルドカードではなく文字として扱う
+        let filter = TodoFilter {
+            text_contains: Some("0% LAB".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(repo.all(scope, &filter).await.unwrap()),
+            vec![labeled.id]
+        );
+        let filter = TodoFilter {
+            text_contains: Some("%".to_string()),
+            completed: Some(true),
+            ..Default::default()
+        };
+        assert!(repo.all(scope, &filter).await.unwrap().is_empty());
+        let filter = TodoFilter {
+            created_after: Some(labeled.created_at),
+            ..Default::default()
+        };
+        assert_eq!(ids(repo.all(scope, &filter).await.unwrap()), vec![done.id]);
+
+        // 並び順は ORDER BY で指定する
+        let filter = TodoFilter {
+            sort: Some(TodoSort::Text),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(repo.all(scope, &filter).await.unwrap()),
+            vec![labeled.id, done.id]
+        );
+        let filter = TodoFilter {
+            sort: Some(TodoSort::Text),
+            order: Some(SortOrder::Desc),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(repo.all(scope, &filter).await.unwrap()),
+            vec![done.id, labeled.id]
+        );
+
+        // page は絞り込み後の件数を返す
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 1,
+                    offset: 1,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        assert_eq!((ids(todos), total), (vec![labeled.id], 2));
+        let filter = TodoFilter {
+            completed: Some(true),
+            ..Default::default()
+        };
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 10,
+                    offset: 1,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        assert_eq!((ids(todos), total), (vec![], 1));
+
+        // ラベルごとの件数は GROUP BY で数える
+        let update = UpdateTodo {
+            labels: Some(vec![label.id]),
+            ..Default::default()
+        };
+        repo.update(scope, done.id, update)
+            .await
+            .expect("[update] returned Err");
+        repo.refresh_label_usage()
+            .await
+            .expect("[refresh_label_usage] returned Err");
+        let usage = repo
+            .label_usage(scope)
+            .await
+            .expect("[label_usage] returned Err");
+        assert_eq!(
+            usage,
+            vec![LabelUsage {
+                label_id: label.id,
+                open: 1,
+                completed: 1,
+            }]
+        );
+
+        for id in [labeled.id, done.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label.id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up label");
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn create_many_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "create_many_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+        let label = prepare_label(&pool, user_id, "[create_many_scenario] label", None).await;
+
+        let todos = repo
+            .create_many(
+                scope,
+                vec![
+                    CreateTodo::new("[create_many_scenario] first".to_string(), vec![label.id]),
+                    CreateTodo::new("[create_many_scenario] second".to_string(), vec![]),
+                    CreateTodo::new("[create_many_scenario] third".to_string(), vec![label.id]),
+                ],
+            )
+            .await
+            .expect("[create_many] returned Err");
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "[create_many_scenario] first",
+                "[create_many_scenario] second",
+                "[create_many_scenario] third",
+            ]
+        );
+        assert_eq!(todos[0].labels, vec![label.clone()]);
+        assert!(todos[1].labels.is_empty());
+        assert_eq!(todos[2].labels, vec![label.clone()]);
+        for todo in &todos {
+            assert_eq!(repo.find(scope, todo.id).await.unwrap(), *todo);
+        }
+
+        // 存在しないラベルがあれば 1 件も作らない
+        let res = repo
+            .create_many(
+                scope,
+                vec![
+                    CreateTodo::new("[create_many_scenario] rolled back".to_string(), vec![]),
+                    CreateTodo::new(
+                        "[create_many_scenario] rolled back".to_string(),
+                        vec![i32::MAX],
+                    ),
+                ],
+            )
+            .await;
+        assert!(res.is_err());
+        let filter = TodoFilter {
+            text_contains: Some("rolled back".to_string()),
+            ..Default::default()
+        };
+        assert!(repo.all(scope, &filter).await.unwrap().is_empty());
+
+        for todo in todos {
+            repo.delete(scope, todo.id)
+                .await
+                .expect("[delete] returned Err");
+        }
+        sqlx::query("DELETE FROM labels WHERE id = $1")
+            .bind(label.id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up label");
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn cursor_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "cursor_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let now = Utc::now();
+        let mut created = vec![];
+        for (text, due_at, priority) in [
+            ("b", Some(now + Duration::days(2)), Priority::High),
+            ("a", None, Priority::Low),
+            ("c", Some(now + Duration::days(1)), Priority::High),
+            ("a", None, Priority::Urgent),
+        ] {
+            let todo = repo
+                .create(
+                    scope,
+                    CreateTodo {
+                        due_at,
+                        priority,
+                        ..CreateTodo::new(format!("[cursor_scenario] {}", text), vec![])
+                    },
+                )
+                .await
+                .expect("[create] returned Err");
+            created.push(todo.id);
+        }
+
+        // cursor を辿ると all と同じ順に全件を 1 回ずつ返す
+        for (sort, order) in [
+            (None, None),
+            (Some(TodoSort::Text), None),
+            (Some(TodoSort::Priority), None),
+            (Some(TodoSort::DueAt), None),
+            (Some(TodoSort::DueAt), Some(SortOrder::Desc)),
+        ] {
+            let filter = TodoFilter {
+                sort,
+                order,
+                ..Default::default()
+            };
+            let expected: Vec<i32> = repo
+                .all(scope, &filter)
+                .await
+                .expect("[all] returned Err")
+                .iter()
+                .map(|todo| todo.id)
+                .collect();
+            let mut ids = vec![];
+            let mut cursor = None;
+            loop {
+                let (todos, next) = repo
+                    .page_after(scope, &filter, cursor.as_ref(), 3)
+                    .await
+                    .expect("[page_after] returned Err");
+                ids.extend(todos.iter().map(|todo| todo.id));
+                match next {
+                    Some(next) => {
+                        let encoded = next.encode();
+                        cursor = Some(TodoCursor::decode(&filter, &encoded).unwrap());
+                    }
+                    None => break,
+                }
+            }
+            assert_eq!(ids, expected, "{:?} {:?}", sort, order);
+        }
+
+        for id in created {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn search_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "search_scenario@example.com").await;
+        let other_user_id = prepare_user(&pool, "search_scenario_other@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let described = repo
+            .create(
+                scope,
+                CreateTodo {
+                    description: Some("compare quokka <prices>".to_string()),
+                    ..CreateTodo::new("[search_scenario] write report".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        let titled = repo
+            .create(
+                scope,
+                CreateTodo::new("[search_scenario] feed the Quokka".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let archived = repo
+            .create(
+                scope,
+                CreateTodo::new("[search_scenario] quokka photos".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        repo.archive(scope, archived.id)
+            .await
+            .expect("[archive] returned Err");
+
+        // text の一致が上位に来る. アーカイブした todo は対象外
+        let hits = repo
+            .search(scope, "quokka")
+            .await
+            .expect("[search] returned Err");
+        let ids: Vec<i32> = hits.iter().map(|hit| hit.todo.id).collect();
+        assert_eq!(ids, vec![titled.id, described.id]);
+        assert!(hits[0].rank > hits[1].rank);
+        assert!(hits[0].snippet.contains("<mark>Quokka</mark>"));
+        assert!(hits[1].snippet.contains("<mark>quokka</mark>"));
+        assert!(!hits[1].snippet.contains("<prices>"));
+
+        let hits = repo
+            .search(scope, "quokka -feed")
+            .await
+            .expect("[search] returned Err");
+        assert_eq!(
+            hits.iter().map(|hit| hit.todo.id).collect::<Vec<_>>(),
+            vec![described.id]
+        );
+        let hits = repo
+            .search(Scope::personal(other_user_id), "quokka")
+            .await
+            .expect("[search] returned Err");
+        assert!(hits.is_empty());
+
+        for id in [described.id, titled.id, archived.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn restore_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "restore_scenario@example.com").await;
+        let other_user_id = prepare_user(&pool, "restore_scenario_other@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let parent = repo
+            .create(
+                scope,
+                CreateTodo::new("[restore_scenario] parent".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let child = repo
+            .create(
+                scope,
+                CreateTodo::from_subtask(parent.id, "[restore_scenario] child".to_string()),
+            )
+            .await
+            .expect("[create] returned Err");
+        let snapshot = repo
+            .archive(scope, child.id)
+            .await
+            .expect("[archive] returned Err");
+
+        // 削除した todo は同じ id で作り直す
+        repo.delete(scope, child.id)
+            .await
+            .expect("[delete] returned Err");
+        let res = repo
+            .restore(Scope::personal(other_user_id), snapshot.clone())
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let todo = repo
+            .restore(scope, snapshot.clone())
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(todo, snapshot);
+        assert!(repo.history(scope, child.id).await.unwrap().is_empty());
+
+        // 存在する todo を戻すと履歴に残る. もう存在しない親は外す
+        repo.update(
+            scope,
+            child.id,
+            UpdateTodo {
+                text: Some("[restore_scenario] renamed".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+        repo.delete(scope, parent.id)
+            .await
+            .expect("[delete] returned Err");
+        let todo = repo
+            .restore(scope, snapshot.clone())
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(todo.text, snapshot.text);
+        assert_eq!(todo.parent_id, None);
+        assert_eq!(todo.archived_at, snapshot.archived_at);
+        assert_eq!(repo.history(scope, child.id).await.unwrap().len(), 2);
 
-        let label_1 = if let Some(label) = optional_label {
-            // DB にラベルが入ってるならそれを使う
-            label
-        } else {
-            // DB に label_name と同名のラベルが存在しないなら作成
-            let label = sqlx::query_as::<_, Label>(
-                r#"
-                INSERT INTO labels ( name )
-                VALUES ( $1 )
-                RETURNING *
-                "#
+        repo.delete(scope, child.id)
+            .await
+            .expect("[delete] returned Err");
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn assign_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "assign_scenario@example.com").await;
+        let other_user_id = prepare_user(&pool, "assign_scenario_other@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let assigned = repo
+            .create(
+                scope,
+                CreateTodo::new("[assign_scenario] assigned".to_string(), vec![]),
             )
-            .bind(label_name)
-            .fetch_one(&pool)
             .await
-            .expect("failed to insert label data.");
-            label
+            .expect("[create] returned Err");
+        let unassigned = repo
+            .create(
+                scope,
+                CreateTodo::new("[assign_scenario] unassigned".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let todo = repo
+            .assign(scope, assigned.id, Some(user_id))
+            .await
+            .expect("[assign] returned Err");
+        assert_eq!(todo.assignee_id, Some(user_id));
+
+        // me は scope のユーザーになる. page と page_after の引数の位置もずれていないか確かめる
+        let filter = TodoFilter {
+            assignee: Some(Assignee::Me),
+            ..Default::default()
         };
-        // Memo: この時点では、DB にラベル "test label" が存在する
+        let todos = repo.all(scope, &filter).await.expect("[all] returned Err");
+        assert_eq!(todos, vec![todo.clone()]);
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 10,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        assert_eq!((todos, total), (vec![todo.clone()], 1));
+        let (todos, next) = repo
+            .page_after(scope, &filter, None, 10)
+            .await
+            .expect("[page_after] returned Err");
+        assert_eq!((todos, next), (vec![todo.clone()], None));
+        let filter = TodoFilter {
+            assignee: Some(Assignee::User(other_user_id)),
+            ..Default::default()
+        };
+        assert!(repo.all(scope, &filter).await.unwrap().is_empty());
+
+        // 読み取りだけ共有されたユーザーは担当者を変えられない
+        repo.share(
+            scope,
+            assigned.id,
+            ShareTodo {
+                user_id: other_user_id,
+                permission: SharePermission::Read,
+            },
+        )
+        .await
+        .expect("[share] returned Err");
+        let res = repo
+            .assign(Scope::personal(other_user_id), assigned.id, None)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+
+        let todo = repo
+            .assign(scope, assigned.id, None)
+            .await
+            .expect("[assign] returned Err");
+        assert_eq!(todo.assignee_id, None);
+
+        for id in [assigned.id, unassigned.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
 
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn pin_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
         let repo = TodoRepositoryForDb::new(pool.clone());
-        let todo_text = "[crud_scenario] text";
+        let user_id = prepare_user(&pool, "pin_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let mut ids = vec![];
+        for text in ["first", "second", "third"] {
+            let todo = repo
+                .create(
+                    scope,
+                    CreateTodo::new(format!("[pin_scenario] {}", text), vec![]),
+                )
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let todo = repo.pin(scope, ids[0]).await.expect("[pin] returned Err");
+        assert!(todo.is_pinned);
+
+        // sort を省略すると向きに関わらずピン留めした todo が先
+        let filter = TodoFilter::default();
+        let todos = repo.all(scope, &filter).await.expect("[all] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(got, vec![ids[0], ids[2], ids[1]]);
+        let filter = TodoFilter {
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 2,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!((got, total), (vec![ids[0], ids[1]], 3));
+
+        // cursor はピン留めした todo の後から続ける
+        let (_, cursor) = repo
+            .page_after(scope, &filter, None, 1)
+            .await
+            .expect("[page_after] returned Err");
+        let (todos, _) = repo
+            .page_after(scope, &filter, cursor.as_ref(), 2)
+            .await
+            .expect("[page_after] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(got, vec![ids[1], ids[2]]);
+
+        let filter = TodoFilter {
+            pinned: Some(true),
+            ..Default::default()
+        };
+        let (_, pinned) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 0,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        assert_eq!(pinned, 1);
+
+        // 数えている間は同じ一覧を数えられず、ピン留めを commit してから続く
+        let mut tx = repo.begin().await.expect("[begin] returned Err");
+        let counted = repo
+            .count_pinned_in(&mut tx, scope)
+            .await
+            .expect("[count_pinned] returned Err");
+        assert_eq!(counted, 1);
+        let waiting = {
+            let repo = repo.clone();
+            tokio::spawn(async move {
+                let mut tx = repo.begin().await?;
+                let counted = repo.count_pinned_in(&mut tx, scope).await?;
+                tx.commit().await?;
+                anyhow::Ok(counted)
+            })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!waiting.is_finished());
+        repo.pin_in(&mut tx, scope, ids[1])
+            .await
+            .expect("[pin] returned Err");
+        tx.commit().await.expect("[commit] returned Err");
+        let counted = waiting.await.unwrap().expect("[count_pinned] returned Err");
+        assert_eq!(counted, 2);
+        repo.unpin(scope, ids[1])
+            .await
+            .expect("[unpin] returned Err");
+
+        let todo = repo
+            .unpin(scope, ids[0])
+            .await
+            .expect("[unpin] returned Err");
+        assert!(!todo.is_pinned);
+
+        for id in ids {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn snooze_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "snooze_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let mut ids = vec![];
+        for text in ["first", "second", "third"] {
+            let todo = repo
+                .create(
+                    scope,
+                    CreateTodo::new(format!("[snooze_scenario] {}", text), vec![]),
+                )
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        let until = Utc::now() + Duration::hours(1);
+        let todo = repo
+            .snooze(scope, ids[1], Some(until))
+            .await
+            .expect("[snooze] returned Err");
+        assert!(todo.snoozed_until.is_some());
+        // 過ぎたスヌーズは一覧に戻る
+        repo.snooze(scope, ids[2], Some(Utc::now() - Duration::hours(1)))
+            .await
+            .expect("[snooze] returned Err");
+
+        let filter = TodoFilter::default();
+        let todos = repo.all(scope, &filter).await.expect("[all] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(got, vec![ids[2], ids[0]]);
+        let (todos, cursor) = repo
+            .page_after(scope, &filter, None, 1)
+            .await
+            .expect("[page_after] returned Err");
+        assert_eq!(todos[0].id, ids[2]);
+        let (todos, _) = repo
+            .page_after(scope, &filter, cursor.as_ref(), 2)
+            .await
+            .expect("[page_after] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(got, vec![ids[0]]);
+
+        let filter = TodoFilter {
+            snoozed: Some(true),
+            ..Default::default()
+        };
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 10,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!((got, total), (vec![ids[1]], 1));
+
+        let todo = repo
+            .snooze(scope, ids[1], None)
+            .await
+            .expect("[snooze] returned Err");
+        assert_eq!(todo.snoozed_until, None);
+        let todos = repo
+            .all(scope, &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(todos.len(), 3);
+
+        for id in ids {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn starts_at_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "starts_at_scenario@example.com").await;
+        let scope = Scope::personal(user_id);
+
+        let now = Utc::now();
+        let started = repo
+            .create(
+                scope,
+                CreateTodo {
+                    starts_at: Some(now - Duration::hours(1)),
+                    ..CreateTodo::new("[starts_at_scenario] started".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        let upcoming = repo
+            .create(
+                scope,
+                CreateTodo {
+                    starts_at: Some(now + Duration::days(1)),
+                    due_at: Some(now + Duration::days(2)),
+                    ..CreateTodo::new("[starts_at_scenario] upcoming".to_string(), vec![])
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert!(upcoming.starts_at.is_some());
+
+        let todos = repo
+            .all(scope, &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(got, vec![started.id]);
+        let filter = TodoFilter {
+            upcoming: Some(true),
+            ..Default::default()
+        };
+        let (todos, total) = repo
+            .page(
+                scope,
+                &filter,
+                Page {
+                    limit: 10,
+                    offset: 0,
+                },
+            )
+            .await
+            .expect("[page] returned Err");
+        let got: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!((got, total), (vec![upcoming.id], 1));
+
+        // 期限を開始日時より前にはできない
+        let update: UpdateTodo =
+            serde_json::from_str(&format!(r#"{{ "due_at": "{}" }}"#, now.to_rfc3339())).unwrap();
+        let res = repo.update(scope, upcoming.id, update).await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Invalid(_))
+        ));
+
+        // 開始日時を外すと既定の一覧に出る. 履歴にも残る
+        let update: UpdateTodo = serde_json::from_str(r#"{ "starts_at": null }"#).unwrap();
+        let todo = repo
+            .update(scope, upcoming.id, update)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(todo.starts_at, None);
+        let history = repo
+            .history(scope, upcoming.id)
+            .await
+            .expect("[history] returned Err");
+        assert!(history.last().unwrap().changes.get("starts_at").is_some());
+        let todos = repo
+            .all(scope, &TodoFilter::default())
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(todos.len(), 2);
+
+        for id in [started.id, upcoming.id] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn bulk_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("failed to connect database: [{}]", database_url));
+        let repo = TodoRepositoryForDb::new(pool.clone());
+        let user_id = prepare_user(&pool, "bulk_scenario@example.com").await;
+        let other_user_id = prepare_user(&pool, "bulk_scenario_other@example.com").await;
+        let scope = Scope::personal(user_id);
+        let other_scope = Scope::personal(other_user_id);
+        // 前回のテストの todo を消しておく
+        for scope in [scope, other_scope] {
+            repo.delete_many(scope, &TodoSelection::Filter(TodoFilter::default()))
+                .await
+                .expect("[delete_many] returned Err");
+        }
+
+        let mut ids = vec![];
+        for text in ["first", "second", "cancelled"] {
+            let todo = repo
+                .create(
+                    scope,
+                    CreateTodo::new(format!("[bulk_scenario] {}", text), vec![]),
+                )
+                .await
+                .expect("[create] returned Err");
+            ids.push(todo.id);
+        }
+        repo.update(
+            scope,
+            ids[2],
+            UpdateTodo {
+                status: Some(Status::Cancelled),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("[update] returned Err");
+        let shared = repo
+            .create(
+                other_scope,
+                CreateTodo::new("[bulk_scenario] shared".to_string(), vec![]),
+            )
+            .await
+            .expect("[create] returned Err");
+        repo.share(
+            other_scope,
+            shared.id,
+            ShareTodo {
+                user_id,
+                permission: SharePermission::Write,
+            },
+        )
+        .await
+        .expect("[share] returned Err");
+        sqlx::query(
+            r#"
+            INSERT INTO attachments (todo_id, user_id, file_name, content_type, size, storage_key)
+            VALUES ( $1, $2, 'memo.txt', 'text/plain', 4, $3 )
+            "#,
+        )
+        .bind(ids[0])
+        .bind(user_id)
+        .bind(format!("todos/{}/bulk_scenario", ids[0]))
+        .execute(&pool)
+        .await
+        .expect("failed to prepare attachment");
 
-        // create
-        let created = repo
-            .create(CreateTodo::new(
-                todo_text.to_string(),
-                vec![label_1.id],
-            ))
+        // Write で共有された todo もラベルを付け外しできる. 付いているラベルは重ねて付けない
+        let label = prepare_label(&pool, user_id, "[bulk_scenario] label", None).await;
+        let add = vec![label.clone()];
+        let relabeled = repo
+            .relabel_many(scope, &[ids[0], shared.id], &add, &[])
             .await
-            .expect("[create] returned Err");
-        assert_eq!(created.text, todo_text);
-        assert!(!created.completed);
-        assert_eq!(*created.labels.first().unwrap(), label_1);
-
-        // find
-        let todo = repo.find(created.id).await.expect("[find] returned Err");
-        assert_eq!(todo, created);
+            .expect("[relabel_many] returned Err");
+        assert_eq!(
+            relabeled
+                .iter()
+                .map(|todo| todo.after.id)
+                .collect::<Vec<_>>(),
+            vec![ids[0], shared.id]
+        );
+        assert_eq!(
+            repo.find(scope, ids[0]).await.unwrap().labels,
+            vec![label.clone()]
+        );
+        let relabeled = repo
+            .relabel_many(scope, &[ids[0]], &add, &[])
+            .await
+            .expect("[relabel_many] returned Err");
+        assert!(relabeled.is_empty());
+        assert_eq!(repo.find(scope, ids[0]).await.unwrap().labels.len(), 1);
+        let relabeled = repo
+            .relabel_many(scope, &[shared.id], &[], &[label.id])
+            .await
+            .expect("[relabel_many] returned Err");
+        assert_eq!(relabeled[0].before.labels, vec![label]);
+        assert!(repo.find(scope, shared.id).await.unwrap().labels.is_empty());
 
-        // all
-        let todos = repo.all().await.expect("[all] returned Err");
-        // assert_eq!(todos, vec![todo]);
-        let todo = todos.first().unwrap();
-        assert_eq!(created, *todo);
+        // Write で共有された todo も完了にできる. cancelled の todo はそのまま
+        let completed = repo
+            .complete_many(scope, &TodoSelection::Ids(vec![ids[1], ids[2], shared.id]))
+            .await
+            .expect("[complete_many] returned Err");
+        assert_eq!(
+            completed
+                .iter()
+                .map(|todo| (todo.id, todo.status))
+                .collect::<Vec<_>>(),
+            vec![(ids[1], Status::Backlog), (shared.id, Status::Backlog)]
+        );
+        assert_eq!(repo.find(scope, ids[1]).await.unwrap().status, Status::Done);
+        assert_eq!(
+            repo.find(scope, ids[2]).await.unwrap().status,
+            Status::Cancelled
+        );
+        let revisions = repo.history(other_scope, shared.id).await.unwrap();
+        assert_eq!(revisions[0].actor_id, Some(user_id));
+        let completed = repo
+            .complete_many(
+                scope,
+                &TodoSelection::Filter(TodoFilter {
+                    completed: Some(false),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .expect("[complete_many] returned Err");
+        assert_eq!(
+            completed.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![ids[0]]
+        );
 
-        // update
-        let update_text = "[crud_scenario] updated text";
-        let todo = repo
-            .update(
-                todo.id,
-                UpdateTodo {
-                    text: Some(update_text.to_string()),
+        // 共有された todo は削除できない
+        let deleted = repo
+            .delete_many(
+                scope,
+                &TodoSelection::Filter(TodoFilter {
                     completed: Some(true),
-                    labels: Some(vec![]),
-                },
+                    ..Default::default()
+                }),
             )
             .await
-            .expect("[update] returned Err");
-        assert_eq!(created.id, todo.id);
-        assert_eq!(todo.text, update_text);
-        assert!(todo.labels.len() == 0);
+            .expect("[delete_many] returned Err");
+        assert_eq!(
+            deleted.todos.iter().map(|todo| todo.id).collect::<Vec<_>>(),
+            vec![ids[0], ids[1]]
+        );
+        assert_eq!(
+            deleted.storage_keys,
+            vec![format!("todos/{}/bulk_scenario", ids[0])]
+        );
+        assert!(repo.find(scope, ids[0]).await.is_err());
+        assert!(repo.find(other_scope, shared.id).await.is_ok());
 
-        // delete
-        let _ = repo
-            .delete(todo.id)
-            .await
-            .expect("[delete] returned Err");
-        let res = repo.find(created.id).await;
-        assert!(res.is_err());
+        for (scope, id) in [(scope, ids[2]), (other_scope, shared.id)] {
+            repo.delete(scope, id).await.expect("[delete] returned Err");
+        }
+    }
 
-        let todo_rows = sqlx::query(
+    // email に対応するユーザーの id を返す. 存在しなければ作る
+    async fn prepare_user(pool: &PgPool, email: &str) -> i32 {
+        let (id,): (i32,) = sqlx::query_as(
             r#"
-            SELECT * FROM todos where id = $1
-            "#
+            INSERT INTO users (email, password_hash)
+            VALUES ( $1, 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
         )
-        .bind(todo.id)
-        .fetch_all(&pool)
+        .bind(email)
+        .fetch_one(pool)
         .await
-        .expect("[delete] todo_labels fetch error");
-        assert!(todo_rows.len() == 0);
+        .expect("failed to prepare user data.");
+        id
+    }
 
-        let rows = sqlx::query(
+    // ラベル名は user ごとに一意なので、前回のテストで作ったラベルは todo から外して使い回す
+    async fn prepare_label(
+        pool: &PgPool,
+        user_id: i32,
+        name: &str,
+        parent_id: Option<i32>,
+    ) -> Label {
+        let label = sqlx::query_as::<_, Label>(
             r#"
-            SELECT * FROM todo_labels WHERE todo_id = $1
-            "#
+            INSERT INTO labels (name, user_id, parent_id) VALUES ( $1, $2, $3 )
+            ON CONFLICT (user_id, lower(name)) WHERE workspace_id IS NULL
+            DO UPDATE SET parent_id = EXCLUDED.parent_id
+            RETURNING *
+            "#,
         )
-        .bind(todo.id)
-        .fetch_all(&pool)
+        .bind(name)
+        .bind(user_id)
+        .bind(parent_id)
+        .fetch_one(pool)
         .await
-        .expect("[delete] todo_labels fect error");
-        assert!(rows.len() == 0);
+        .expect("failed to prepare label data.");
+        sqlx::query("DELETE FROM todo_labels WHERE label_id = $1")
+            .bind(label.id)
+            .execute(pool)
+            .await
+            .expect("failed to prepare label data.");
+        label
     }
 }
 
-#[cfg(test)]
-pub mod test_utils {
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use super::*;
     use anyhow::Context;
     use axum::async_trait;
     use std::{
-        collections::HashMap,
-        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard}
+        cmp::Ordering,
+        collections::{BTreeMap, HashSet},
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
     };
-    use super::*;
 
     impl Todo {
-        pub fn new(id: i32, text: String) -> Self {
+        pub fn new(id: i32, user_id: i32, text: String) -> Self {
             Self {
                 id,
                 text,
-                completed: false,
+                description: None,
+                status: Status::Backlog,
+                user_id,
+                workspace_id: None,
+                due_at: None,
+                starts_at: None,
+                priority: Priority::Medium,
+                parent_id: None,
+                recurrence: None,
+                archived_at: None,
+                position: id as i64 * POSITION_GAP,
+                project_id: None,
                 labels: vec![],
+                created_at: Utc::now(),
+                assignee_id: None,
+                is_pinned: false,
+                snoozed_until: None,
+            }
+        }
+
+        // 未完了のまま期限を過ぎているか
+        fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+            !self.status.is_closed() && self.due_at.is_some_and(|due_at| due_at < now)
+        }
+    }
+
+    // haystack の中の needle を区切りで囲み、一致した数と一緒に返す. needle は小文字にしておく
+    fn mark_matches(haystack: &str, needle: &str) -> (String, usize) {
+        let lower = haystack.to_ascii_lowercase();
+        let mut marked = String::new();
+        let mut count = 0;
+        let mut rest = 0;
+        for (start, _) in lower.match_indices(needle) {
+            let end = start + needle.len();
+            marked.push_str(&haystack[rest..start]);
+            marked.push(MARK_START);
+            marked.push_str(&haystack[start..end]);
+            marked.push(MARK_END);
+            rest = end;
+            count += 1;
+        }
+        marked.push_str(&haystack[rest..]);
+        (marked, count)
+    }
+
+    impl Status {
+        // DB 実装の status IN ('done', 'cancelled') と同じ判定
+        fn is_closed(self) -> bool {
+            matches!(self, Status::Done | Status::Cancelled)
+        }
+    }
+
+    impl TodoFilter {
+        // DB 実装の WHERE 句と同じ判定
+        fn matches(&self, todo: &Todo, scope: Scope, now: DateTime<Utc>) -> bool {
+            let due_before = self
+                .due_before
+                .is_none_or(|due_before| todo.due_at.is_some_and(|due_at| due_at < due_before));
+            let overdue = self
+                .overdue
+                .is_none_or(|overdue| todo.is_overdue(now) == overdue);
+            let parent = self
+                .parent_id
+                .is_none_or(|parent_id| todo.parent_id == Some(parent_id));
+            let archived = todo.archived_at.is_some() == self.archived.unwrap_or(false);
+            let status = self.status.is_none_or(|status| todo.status == status);
+            let project = self
+                .project_id
+                .is_none_or(|project_id| todo.project_id == Some(project_id));
+            let completed = self
+                .completed
+                .is_none_or(|completed| (todo.status == Status::Done) == completed);
+            // メモリ実装はラベルの一覧を持たないので、子孫のうち直下の子ラベルまでを対象にする
+            let sublabels = self.sublabels.unwrap_or(false);
+            let label = self.label_id.is_none_or(|label_id| {
+                todo.labels.iter().any(|label| {
+                    label.id == label_id || (sublabels && label.parent_id == Some(label_id))
+                })
+            });
+            let created_after = self
+                .created_after
+                .is_none_or(|created_after| todo.created_at > created_after);
+            let text = self
+                .text_contains
+                .as_ref()
+                .is_none_or(|text| todo.text.to_lowercase().contains(&text.to_lowercase()));
+            let assignee = self
+                .assignee
+                .is_none_or(|assignee| todo.assignee_id == Some(assignee.user_id(scope)));
+            let pinned = self.pinned.is_none_or(|pinned| todo.is_pinned == pinned);
+            let snoozed = todo
+                .snoozed_until
+                .is_some_and(|snoozed_until| snoozed_until > now)
+                == self.snoozed.unwrap_or(false);
+            let upcoming = todo.starts_at.is_some_and(|starts_at| starts_at > now)
+                == self.upcoming.unwrap_or(false);
+            due_before
+                && overdue
+                && parent
+                && archived
+                && status
+                && project
+                && completed
+                && label
+                && created_after
+                && text
+                && assignee
+                && pinned
+                && snoozed
+                && upcoming
+        }
+
+        // DB 実装の ORDER BY と同じ並び順
+        fn compare(&self, a: &Todo, b: &Todo) -> Ordering {
+            let directed = |ordering: Ordering| match self.direction() {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+            let ordering = match self.sort {
+                Some(TodoSort::Priority) => directed(a.priority.cmp(&b.priority)),
+                Some(TodoSort::Position) => directed(a.position.cmp(&b.position)),
+                Some(TodoSort::CreatedAt) => directed(a.created_at.cmp(&b.created_at)),
+                Some(TodoSort::Text) => directed(a.text.cmp(&b.text)),
+                // 期限のない todo は向きに関わらず最後
+                Some(TodoSort::DueAt) => match (a.due_at, b.due_at) {
+                    (Some(a), Some(b)) => directed(a.cmp(&b)),
+                    (a, b) => a.is_none().cmp(&b.is_none()),
+                },
+                // ピン留めした todo は向きに関わらず先
+                None => b.is_pinned.cmp(&a.is_pinned),
+            };
+            ordering.then_with(|| directed(a.id.cmp(&b.id)))
+        }
+    }
+
+    impl TodoCursor {
+        // DB 実装の keyset と同じ判定. todo が cursor より後に並ぶなら true
+        fn is_before(&self, todo: &Todo) -> bool {
+            let directed = |ordering: Ordering| match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+            let other = match &self.key {
+                CursorKey::Pinned(_) => CursorKey::Pinned(todo.is_pinned),
+                CursorKey::Priority(_) => CursorKey::Priority(todo.priority.rank()),
+                CursorKey::Position(_) => CursorKey::Position(todo.position),
+                CursorKey::CreatedAt(_) => CursorKey::CreatedAt(todo.created_at),
+                CursorKey::Text(_) => CursorKey::Text(todo.text.clone()),
+                CursorKey::DueAt(_) => CursorKey::DueAt(todo.due_at),
+            };
+            let ordering = match (&self.key, &other) {
+                // ピン留めした todo は向きに関わらず先
+                (CursorKey::Pinned(a), CursorKey::Pinned(b)) => b.cmp(a),
+                // 期限のない todo は向きに関わらず最後
+                (CursorKey::DueAt(a), CursorKey::DueAt(b)) if a.is_none() || b.is_none() => {
+                    a.is_none().cmp(&b.is_none())
+                }
+                (a, b) => directed(a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+            };
+            ordering.then_with(|| directed(self.id.cmp(&todo.id))) == Ordering::Less
+        }
+    }
+
+    impl TodoSelection {
+        // DB 実装の targets と同じ判定 (権限の確認を除く)
+        fn matches(&self, todo: &Todo, scope: Scope, now: DateTime<Utc>) -> bool {
+            match self {
+                TodoSelection::Ids(ids) => {
+                    ids.contains(&todo.id) && TodoFilter::default().matches(todo, scope, now)
+                }
+                TodoSelection::Filter(filter) => filter.matches(todo, scope, now),
             }
         }
     }
 
-    #[cfg(test)]
     impl CreateTodo {
         pub fn new(text: String, labels: Vec<i32>) -> Self {
             Self {
-                text: text,
+                text,
+                description: None,
                 labels,
+                due_at: None,
+                starts_at: None,
+                priority: Priority::Medium,
+                parent_id: None,
+                recurrence: None,
+                project_id: None,
             }
         }
     }
 
     type TodoDatas = HashMap<i32, Todo>;
 
+    // 削除した todo の id を使い回さないように、件数ではなく最大の id から採番する
+    fn next_id(store: &TodoDatas) -> i32 {
+        store.keys().max().copied().unwrap_or(0) + 1
+    }
+    // (todo_id, user_id) -> permission
+    type ShareDatas = HashMap<(i32, i32), SharePermission>;
+
     #[derive(Debug, Clone)]
     pub struct TodoRepositoryForMemory {
         // 複数スレッドからのアクセスを想定し Arc<RwLock<>> でスレッドセーフにする
         // 不変参照の場合は複数スレッドで共有できるが、可変参照の場合はスレッドを1つに制限する
         store: Arc<RwLock<TodoDatas>>,
+        shares: Arc<RwLock<ShareDatas>>,
+        // DB の recurred_at に相当する. 次の todo を作成済みの todo の id
+        recurred: Arc<RwLock<HashSet<i32>>>,
+        revisions: Arc<RwLock<Vec<TodoRevision>>>,
+    }
+
+    // memory バックエンドを再起動しても todo を残すために JSON で書き出す中身
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct TodoSnapshot {
+        todos: Vec<Todo>,
+        // (todo_id, user_id, permission)
+        shares: Vec<(i32, i32, SharePermission)>,
+        recurred: Vec<i32>,
+        revisions: Vec<TodoRevision>,
     }
 
     impl TodoRepositoryForMemory {
         pub fn new() -> Self {
             TodoRepositoryForMemory {
                 store: Arc::default(),
+                shares: Arc::default(),
+                recurred: Arc::default(),
+                revisions: Arc::default(),
+            }
+        }
+
+        pub fn restore(snapshot: TodoSnapshot) -> Self {
+            let store = snapshot
+                .todos
+                .into_iter()
+                .map(|todo| (todo.id, todo))
+                .collect();
+            let shares = snapshot
+                .shares
+                .into_iter()
+                .map(|(todo_id, user_id, permission)| ((todo_id, user_id), permission))
+                .collect();
+            TodoRepositoryForMemory {
+                store: Arc::new(RwLock::new(store)),
+                shares: Arc::new(RwLock::new(shares)),
+                recurred: Arc::new(RwLock::new(snapshot.recurred.into_iter().collect())),
+                revisions: Arc::new(RwLock::new(snapshot.revisions)),
+            }
+        }
+
+        // 差分が読みやすいように id 順に並べる
+        pub fn snapshot(&self) -> TodoSnapshot {
+            let mut todos: Vec<Todo> = self.read_store_ref().values().cloned().collect();
+            todos.sort_by_key(|todo| todo.id);
+            let mut shares: Vec<_> = self
+                .shares
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(&(todo_id, user_id), &permission)| (todo_id, user_id, permission))
+                .collect();
+            shares.sort();
+            let mut recurred: Vec<i32> = self.recurred.read().unwrap().iter().copied().collect();
+            recurred.sort();
+            TodoSnapshot {
+                todos,
+                shares,
+                recurred,
+                revisions: self.revisions.read().unwrap().clone(),
+            }
+        }
+
+        fn record_revision(&self, scope: Scope, todo_id: i32, changes: Map<String, Value>) {
+            if changes.is_empty() {
+                return;
+            }
+            let mut revisions = self.revisions.write().unwrap();
+            let revision = TodoRevision {
+                id: revisions.len() as i32 + 1,
+                todo_id,
+                actor_id: Some(scope.user_id),
+                changes: Value::Object(changes),
+                created_at: Utc::now(),
+            };
+            revisions.push(revision);
+        }
+
+        // DB 実装の find_with_permission と同じ判定
+        fn check_permission(
+            &self,
+            scope: Scope,
+            todo: &Todo,
+            required: Option<SharePermission>,
+        ) -> anyhow::Result<()> {
+            if todo.is_visible_in(scope) {
+                return Ok(());
+            }
+            let permission = self
+                .shares
+                .read()
+                .unwrap()
+                .get(&(todo.id, scope.user_id))
+                .copied();
+            match (permission, required) {
+                (Some(permission), Some(required)) if permission >= required => Ok(()),
+                _ => Err(RepositoryError::Forbidden(todo.id).into()),
+            }
+        }
+
+        // DB 実装の check_parent と同じ判定
+        fn check_parent(
+            &self,
+            store: &TodoDatas,
+            scope: Scope,
+            owner: Scope,
+            id: Option<i32>,
+            parent_id: i32,
+        ) -> anyhow::Result<()> {
+            let parent = store
+                .get(&parent_id)
+                .ok_or(RepositoryError::NotFound(parent_id))?;
+            self.check_permission(scope, parent, Some(SharePermission::Read))?;
+            if !parent.is_visible_in(owner) {
+                return Err(RepositoryError::Invalid(format!(
+                    "todo {} can not be a parent of this todo",
+                    parent_id
+                ))
+                .into());
+            }
+            let mut ancestor = Some(parent_id);
+            while let Some(ancestor_id) = ancestor {
+                if Some(ancestor_id) == id {
+                    return Err(RepositoryError::Invalid(format!(
+                        "todo {} is a subtask of todo {}",
+                        parent_id, ancestor_id
+                    ))
+                    .into());
+                }
+                ancestor = store.get(&ancestor_id).and_then(|todo| todo.parent_id);
+            }
+            Ok(())
+        }
+
+        fn is_shared_with(&self, todo_id: i32, user_id: i32) -> bool {
+            self.shares
+                .read()
+                .unwrap()
+                .contains_key(&(todo_id, user_id))
+        }
+
+        // DB 実装の FILTERED_TODOS と同じく、個人の一覧には共有された todo も含める
+        fn is_listed_in(&self, todo: &Todo, scope: Scope) -> bool {
+            todo.is_visible_in(scope)
+                || (scope.workspace_id.is_none() && self.is_shared_with(todo.id, scope.user_id))
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, TodoDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, TodoDatas> {
+            self.store.read().unwrap()
+        }
+
+        // ラベルの削除向け. label_id のラベルが付いた todo があるか
+        pub fn has_label(&self, label_id: i32) -> bool {
+            self.read_store_ref()
+                .values()
+                .any(|todo| todo.labels.iter().any(|label| label.id == label_id))
+        }
+
+        // DB 実装で todo_labels の行を消すのと同じく、全ての todo から label_id のラベルを外す
+        pub fn detach_label(&self, label_id: i32) {
+            for todo in self.write_store_ref().values_mut() {
+                todo.labels.retain(|label| label.id != label_id);
+            }
+        }
+    }
+
+    // メモリ上なので常に使える
+    #[async_trait]
+    impl HealthCheck for TodoRepositoryForMemory {
+        async fn check(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TodoRepository for TodoRepositoryForMemory {
+        async fn begin(&self) -> anyhow::Result<Tx> {
+            Ok(Tx::memory())
+        }
+
+        async fn create_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            payload: CreateTodo,
+        ) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            if let Some(parent_id) = payload.parent_id {
+                self.check_parent(&store, scope, scope, None, parent_id)?;
+            }
+            let id = next_id(&store);
+            let todo = Todo {
+                workspace_id: scope.workspace_id,
+                due_at: payload.due_at,
+                starts_at: payload.starts_at,
+                priority: payload.priority,
+                parent_id: payload.parent_id,
+                recurrence: payload.recurrence,
+                description: payload.description,
+                project_id: payload.project_id,
+                ..Todo::new(id, scope.user_id, payload.text.clone())
+            };
+            store.insert(id, todo.clone());
+            Ok(todo)
+        }
+
+        async fn create_many_in(
+            &self,
+            tx: &mut Tx,
+            scope: Scope,
+            payloads: Vec<CreateTodo>,
+        ) -> anyhow::Result<Vec<Todo>> {
+            // 途中で失敗して一部だけ作られないよう、先に親を確認しておく
+            {
+                let store = self.read_store_ref();
+                for parent_id in payloads.iter().filter_map(|payload| payload.parent_id) {
+                    self.check_parent(&store, scope, scope, None, parent_id)?;
+                }
+            }
+            let mut todos = vec![];
+            for payload in payloads {
+                todos.push(self.create_in(tx, scope, payload).await?);
+            }
+            Ok(todos)
+        }
+
+        async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+            let store = self.read_store_ref();
+            let todo = store
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, &todo, Some(SharePermission::Read))?;
+            Ok(todo)
+        }
+
+        async fn find_for_update_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+        ) -> anyhow::Result<Todo> {
+            self.find(scope, id).await
+        }
+
+        async fn count_pinned_in(&self, _tx: &mut Tx, scope: Scope) -> anyhow::Result<i64> {
+            let pinned = self
+                .read_store_ref()
+                .values()
+                .filter(|todo| {
+                    todo.is_pinned && todo.archived_at.is_none() && todo.is_visible_in(scope)
+                })
+                .count();
+            Ok(pinned as i64)
+        }
+
+        // Note: find() の実装に Box を使うパターン. clone の回数が増えるならヒープの利用を検討する
+        // fn find(&self, id: i32) -> Option<Box<Todo>> {
+        //     let store = self.read_store_ref();
+        //     let todo = store.get(&id);
+        //     let todo = Box::new(todo.clone());
+        //     Some(Todo)
+        // }
+
+        async fn update_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            payload: UpdateTodo,
+        ) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, Some(SharePermission::Write))?;
+            if let Some(Some(parent_id)) = payload.parent_id {
+                let owner = Scope::new(todo.user_id, todo.workspace_id);
+                self.check_parent(&store, scope, owner, Some(id), parent_id)?;
+            }
+            let before = TodoFields::of(todo);
+            let text = payload.text.unwrap_or(todo.text.clone());
+            let status = payload.status.unwrap_or(todo.status);
+            let todo = Todo {
+                id,
+                text,
+                description: payload.description.unwrap_or(todo.description.clone()),
+                status,
+                user_id: todo.user_id,
+                workspace_id: todo.workspace_id,
+                due_at: payload.due_at.unwrap_or(todo.due_at),
+                starts_at: payload.starts_at.unwrap_or(todo.starts_at),
+                priority: payload.priority.unwrap_or(todo.priority),
+                parent_id: payload.parent_id.unwrap_or(todo.parent_id),
+                recurrence: payload.recurrence.unwrap_or(todo.recurrence),
+                archived_at: todo.archived_at,
+                position: todo.position,
+                project_id: payload.project_id.unwrap_or(todo.project_id),
+                labels: vec![],
+                created_at: todo.created_at,
+                assignee_id: todo.assignee_id,
+                is_pinned: todo.is_pinned,
+                snoozed_until: todo.snoozed_until,
+            };
+            if !is_valid_schedule(todo.starts_at, todo.due_at) {
+                return Err(RepositoryError::Invalid(
+                    "starts_at must not be after due_at".to_string(),
+                )
+                .into());
+            }
+            store.insert(id, todo.clone()).unwrap();
+            self.record_revision(scope, id, before.diff(&TodoFields::of(&todo)));
+            if payload.status == Some(Status::Done) && payload.complete_subtasks {
+                let mut parents = vec![id];
+                while let Some(parent_id) = parents.pop() {
+                    for child in store
+                        .values_mut()
+                        .filter(|child| child.parent_id == Some(parent_id))
+                    {
+                        if !child.status.is_closed() {
+                            let change = json!({ "before": child.status, "after": Status::Done });
+                            self.record_revision(
+                                scope,
+                                child.id,
+                                Map::from_iter([("status".to_string(), change)]),
+                            );
+                            child.status = Status::Done;
+                        }
+                        parents.push(child.id);
+                    }
+                }
+            }
+            Ok(todo)
+        }
+
+        async fn history(&self, scope: Scope, id: i32) -> anyhow::Result<Vec<TodoRevision>> {
+            self.find(scope, id).await?;
+            let revisions = self.revisions.read().unwrap();
+            Ok(revisions
+                .iter()
+                .filter(|revision| revision.todo_id == id)
+                .cloned()
+                .collect())
+        }
+
+        async fn revision(
+            &self,
+            scope: Scope,
+            id: i32,
+            revision_id: i32,
+        ) -> anyhow::Result<TodoRevision> {
+            self.find(scope, id).await?;
+            let revisions = self.revisions.read().unwrap();
+            let revision = revisions
+                .iter()
+                .find(|revision| revision.id == revision_id && revision.todo_id == id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(revision_id))?;
+            Ok(revision)
+        }
+
+        async fn activity(&self, scope: Scope, limit: usize) -> anyhow::Result<Vec<TodoActivity>> {
+            let todos = self.all(scope, &TodoFilter::default()).await?;
+            let completions = self
+                .revisions
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|revision| revision.changes["status"]["after"] == "done")
+                .map(|revision| (revision.todo_id, revision.created_at))
+                .collect();
+            Ok(collect_activity(todos, completions, limit))
+        }
+
+        async fn label_usage(&self, scope: Scope) -> anyhow::Result<Vec<LabelUsage>> {
+            let store = self.read_store_ref();
+            let mut usage: BTreeMap<i32, LabelUsage> = BTreeMap::new();
+            let todos = store
+                .values()
+                .filter(|todo| todo.is_visible_in(scope) && todo.archived_at.is_none());
+            for todo in todos {
+                for label in &todo.labels {
+                    let counts = usage.entry(label.id).or_insert(LabelUsage {
+                        label_id: label.id,
+                        open: 0,
+                        completed: 0,
+                    });
+                    match todo.status {
+                        Status::Done => counts.completed += 1,
+                        Status::Cancelled => {}
+                        _ => counts.open += 1,
+                    }
+                }
+            }
+            Ok(usage.into_values().collect())
+        }
+
+        // label_usage は呼ばれるたびに数えるので、数え直すものはない
+        async fn refresh_label_usage(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn restore_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            snapshot: Todo,
+        ) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let id = snapshot.id;
+            let old_todo = store.get(&id).cloned();
+            let owner = match &old_todo {
+                Some(old_todo) => {
+                    self.check_permission(scope, old_todo, Some(SharePermission::Write))?;
+                    let moved = old_todo.archived_at != snapshot.archived_at
+                        || old_todo.is_pinned != snapshot.is_pinned
+                        || old_todo.snoozed_until != snapshot.snoozed_until
+                        || old_todo.position != snapshot.position;
+                    if moved && !old_todo.is_visible_in(scope) {
+                        return Err(RepositoryError::Forbidden(id).into());
+                    }
+                    Scope::new(old_todo.user_id, old_todo.workspace_id)
+                }
+                None => {
+                    if !snapshot.is_visible_in(scope) {
+                        return Err(RepositoryError::Forbidden(id).into());
+                    }
+                    Scope::new(snapshot.user_id, snapshot.workspace_id)
+                }
+            };
+            // プロジェクトとラベルは別のレポジトリが持っているので、親だけ存在を確認する
+            let parent_id = snapshot
+                .parent_id
+                .filter(|parent_id| store.contains_key(parent_id));
+            if let Some(parent_id) = parent_id {
+                self.check_parent(
+                    &store,
+                    scope,
+                    owner,
+                    old_todo.as_ref().map(|todo| todo.id),
+                    parent_id,
+                )?;
+            }
+            let todo = Todo {
+                user_id: owner.user_id,
+                workspace_id: owner.workspace_id,
+                parent_id,
+                ..snapshot
+            };
+            store.insert(id, todo.clone());
+            if let Some(old_todo) = &old_todo {
+                self.record_revision(
+                    scope,
+                    id,
+                    TodoFields::of(old_todo).diff(&TodoFields::of(&todo)),
+                );
+            }
+            Ok(todo)
+        }
+
+        async fn all(&self, scope: Scope, filter: &TodoFilter) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let now = Utc::now();
+            let mut todos = Vec::from_iter(
+                store
+                    .values()
+                    .filter(|todo| self.is_listed_in(todo, scope))
+                    .filter(|todo| filter.matches(todo, scope, now))
+                    .cloned(),
+            );
+            todos.sort_by(|a, b| filter.compare(a, b));
+            Ok(todos)
+        }
+
+        async fn page(
+            &self,
+            scope: Scope,
+            filter: &TodoFilter,
+            page: Page,
+        ) -> anyhow::Result<(Vec<Todo>, i64)> {
+            let todos = self.all(scope, filter).await?;
+            let total = todos.len() as i64;
+            let todos = todos
+                .into_iter()
+                .skip(page.offset as usize)
+                .take(page.limit as usize)
+                .collect();
+            Ok((todos, total))
+        }
+
+        async fn page_by_label(
+            &self,
+            scope: Scope,
+            label_id: i32,
+            page: Page,
+        ) -> anyhow::Result<(Vec<Todo>, i64)> {
+            let store = self.read_store_ref();
+            let filter = TodoFilter::default();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| self.is_listed_in(todo, scope) && todo.archived_at.is_none())
+                .filter(|todo| todo.labels.iter().any(|label| label.id == label_id))
+                .cloned()
+                .collect();
+            todos.sort_by(|a, b| filter.compare(a, b));
+            let total = todos.len() as i64;
+            let todos = todos
+                .into_iter()
+                .skip(page.offset as usize)
+                .take(page.limit as usize)
+                .collect();
+            Ok((todos, total))
+        }
+
+        async fn page_after(
+            &self,
+            scope: Scope,
+            filter: &TodoFilter,
+            cursor: Option<&TodoCursor>,
+            limit: i64,
+        ) -> anyhow::Result<(Vec<Todo>, Option<TodoCursor>)> {
+            let todos = self
+                .all(scope, filter)
+                .await?
+                .into_iter()
+                .filter(|todo| cursor.is_none_or(|cursor| cursor.is_before(todo)))
+                .take(limit as usize + 1)
+                .collect();
+            Ok(next_page(filter, todos, limit))
+        }
+
+        async fn search(&self, scope: Scope, query: &str) -> anyhow::Result<Vec<TodoSearchHit>> {
+            // 全文検索の代わりに、大文字と小文字 (ASCII のみ) を区別しない部分一致で探す
+            let needle = query.trim().to_ascii_lowercase();
+            if needle.is_empty() {
+                return Ok(vec![]);
+            }
+            let todos = self.all(scope, &TodoFilter::default()).await?;
+            let mut hits: Vec<TodoSearchHit> = todos
+                .into_iter()
+                .filter_map(|todo| {
+                    let (text, text_count) = mark_matches(&todo.text, &needle);
+                    let (description, description_count) = todo
+                        .description
+                        .as_deref()
+                        .map(|description| mark_matches(description, &needle))
+                        .unzip();
+                    let description_count = description_count.unwrap_or(0);
+                    if text_count + description_count == 0 {
+                        return None;
+                    }
+                    // DB 実装の重み A / B と同じく text の一致を重くする
+                    let rank = text_count as f32 + description_count as f32 * 0.4;
+                    let snippet = match description {
+                        Some(description) => format!("{}\n{}", text, description),
+                        None => text,
+                    };
+                    Some(TodoSearchHit {
+                        todo,
+                        rank,
+                        snippet: mark_snippet(&snippet),
+                    })
+                })
+                .collect();
+            hits.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(b.todo.id.cmp(&a.todo.id)));
+            hits.truncate(SEARCH_LIMIT as usize);
+            Ok(hits)
+        }
+
+        async fn all_unscoped(&self) -> anyhow::Result<Vec<Todo>> {
+            let store = self.read_store_ref();
+            let mut todos = Vec::from_iter(store.values().cloned());
+            todos.sort_by_key(|todo| std::cmp::Reverse(todo.id));
+            Ok(todos)
+        }
+
+        async fn all_by_user(&self, user_id: i32) -> anyhow::Result<Vec<Todo>> {
+            let mut todos = self.all_unscoped().await?;
+            todos.retain(|todo| todo.user_id == user_id);
+            Ok(todos)
+        }
+
+        async fn delete_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            let todo = store.get(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            store.remove(&id);
+            self.shares
+                .write()
+                .unwrap()
+                .retain(|(todo_id, _), _| *todo_id != id);
+            // DB の ON DELETE SET NULL と同じく、子はトップレベルの todo として残す
+            for child in store
+                .values_mut()
+                .filter(|child| child.parent_id == Some(id))
+            {
+                child.parent_id = None;
+            }
+            Ok(())
+        }
+
+        async fn complete_many_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            selection: &TodoSelection,
+        ) -> anyhow::Result<Vec<Todo>> {
+            let mut store = self.write_store_ref();
+            let now = Utc::now();
+            let mut targets: Vec<Todo> = store
+                .values()
+                .filter(|todo| {
+                    self.is_listed_in(todo, scope) && selection.matches(todo, scope, now)
+                })
+                .filter(|todo| {
+                    self.check_permission(scope, todo, Some(SharePermission::Write))
+                        .is_ok()
+                })
+                .filter(|todo| !matches!(todo.status, Status::Done | Status::Cancelled))
+                .cloned()
+                .collect();
+            targets.sort_by_key(|todo| todo.id);
+            for before in &targets {
+                if let Some(todo) = store.get_mut(&before.id) {
+                    todo.status = Status::Done;
+                }
+                let change = json!({ "before": before.status, "after": Status::Done });
+                self.record_revision(
+                    scope,
+                    before.id,
+                    Map::from_iter([("status".to_string(), change)]),
+                );
+            }
+            Ok(targets)
+        }
+
+        async fn delete_many_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            selection: &TodoSelection,
+        ) -> anyhow::Result<DeletedTodos> {
+            let mut store = self.write_store_ref();
+            let now = Utc::now();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| todo.is_visible_in(scope) && selection.matches(todo, scope, now))
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            store.retain(|id, _| !ids.contains(id));
+            self.shares
+                .write()
+                .unwrap()
+                .retain(|(todo_id, _), _| !ids.contains(todo_id));
+            for child in store.values_mut() {
+                if child
+                    .parent_id
+                    .is_some_and(|parent_id| ids.contains(&parent_id))
+                {
+                    child.parent_id = None;
+                }
+            }
+            // 添付ファイルのメタデータは別の repository にあるので、中身の key は返せない
+            Ok(DeletedTodos {
+                todos,
+                storage_keys: vec![],
+            })
+        }
+
+        async fn relabel_many_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            ids: &[i32],
+            add: &[Label],
+            remove: &[i32],
+        ) -> anyhow::Result<Vec<RelabeledTodo>> {
+            let mut store = self.write_store_ref();
+            let mut targets: Vec<Todo> = store
+                .values()
+                .filter(|todo| ids.contains(&todo.id) && todo.archived_at.is_none())
+                .filter(|todo| {
+                    self.is_listed_in(todo, scope)
+                        && self
+                            .check_permission(scope, todo, Some(SharePermission::Write))
+                            .is_ok()
+                })
+                .cloned()
+                .collect();
+            targets.sort_by_key(|todo| todo.id);
+            let mut relabeled = vec![];
+            for before in targets {
+                let after = before.relabeled(add, remove);
+                let changes = TodoFields::of(&before).diff(&TodoFields::of(&after));
+                if changes.is_empty() {
+                    continue;
+                }
+                store.insert(after.id, after.clone());
+                self.record_revision(scope, after.id, changes);
+                relabeled.push(RelabeledTodo { before, after });
             }
+            Ok(relabeled)
+        }
+
+        async fn archive_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            todo.archived_at = todo.archived_at.or(Some(Utc::now()));
+            Ok(todo.clone())
+        }
+
+        async fn unarchive_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            todo.archived_at = None;
+            Ok(todo.clone())
         }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<TodoDatas> {
-            self.store.write().unwrap()
+        async fn pin_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            todo.is_pinned = true;
+            Ok(todo.clone())
         }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<TodoDatas> {
-            self.store.read().unwrap()
+        async fn unpin_in(&self, _tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            todo.is_pinned = false;
+            Ok(todo.clone())
         }
-    }
 
-    #[async_trait]
-    impl TodoRepository for TodoRepositoryForMemory {
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<Todo> {
+        async fn snooze_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            until: Option<DateTime<Utc>>,
+        ) -> anyhow::Result<Todo> {
             let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let todo = Todo::new(id, payload.text.clone());
-            store.insert(id, todo.clone());
-            Ok(todo)
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            todo.snoozed_until = until;
+            Ok(todo.clone())
         }
 
-        async fn find(&self, id: i32) -> anyhow::Result<Todo> {
-            let store = self.read_store_ref();
-            let todo = store
-                .get(&id)
-                .map(|todo| todo.clone())
-                .ok_or(RepositoryError::NotFound(id))?;
-            Ok(todo)
+        async fn assign_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            assignee_id: Option<i32>,
+        ) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, Some(SharePermission::Write))?;
+            todo.assignee_id = assignee_id;
+            Ok(todo.clone())
         }
 
-        // Note: find() の実装に Box を使うパターン. clone の回数が増えるならヒープの利用を検討する
-        // fn find(&self, id: i32) -> Option<Box<Todo>> {
-        //     let store = self.read_store_ref();
-        //     let todo = store.get(&id);
-        //     let todo = Box::new(todo.clone());
-        //     Some(Todo)
-        // }
+        async fn move_to_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+            target: MoveTodo,
+        ) -> anyhow::Result<Todo> {
+            let mut store = self.write_store_ref();
+            let todo = store.get(&id).ok_or(RepositoryError::NotFound(id))?.clone();
+            self.check_permission(scope, &todo, None)?;
+            let owner = Scope::new(todo.user_id, todo.workspace_id);
+            let mut siblings: Vec<(i32, i64)> = store
+                .values()
+                .filter(|sibling| sibling.id != id && sibling.is_visible_in(owner))
+                .map(|sibling| (sibling.id, sibling.position))
+                .collect();
+            siblings.sort_by_key(|(id, position)| (*position, *id));
+            let index = target.index_in(&siblings)?;
+            for (id, position) in plan_move(&siblings, id, index) {
+                if let Some(todo) = store.get_mut(&id) {
+                    todo.position = position;
+                }
+            }
+            Ok(store[&id].clone())
+        }
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<Todo> {
+        async fn duplicate_in(
+            &self,
+            _tx: &mut Tx,
+            scope: Scope,
+            id: i32,
+        ) -> anyhow::Result<DuplicatedTodo> {
             let mut store = self.write_store_ref();
-            let todo = store
-                .get(&id)
-                .context(RepositoryError::NotFound(id))?;
-            let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed);
-            let todo = Todo {
-                id,
-                text,
-                completed,
-                labels: vec![],
-            };
-            store.insert(id, todo.clone()).unwrap();
-            Ok(todo)
+            let todo = store.get(&id).ok_or(RepositoryError::NotFound(id))?.clone();
+            self.check_permission(scope, &todo, None)?;
+            // DB 実装と同じく、深さの浅い順に並べる
+            let mut sources = vec![todo];
+            let mut i = 0;
+            while i < sources.len() {
+                let mut children: Vec<Todo> = store
+                    .values()
+                    .filter(|child| child.parent_id == Some(sources[i].id))
+                    .cloned()
+                    .collect();
+                children.sort_by_key(|child| child.id);
+                sources.extend(children);
+                i += 1;
+            }
+
+            let mut copies: HashMap<i32, i32> = HashMap::new();
+            for source in sources {
+                let copy_id = next_id(&store);
+                let parent_id = match source.id == id {
+                    true => source.parent_id,
+                    false => source
+                        .parent_id
+                        .and_then(|parent_id| copies.get(&parent_id).copied()),
+                };
+                let copy = Todo {
+                    id: copy_id,
+                    user_id: scope.user_id,
+                    parent_id,
+                    archived_at: None,
+                    position: copy_id as i64 * POSITION_GAP,
+                    created_at: Utc::now(),
+                    assignee_id: None,
+                    is_pinned: false,
+                    snoozed_until: None,
+                    ..source
+                };
+                copies.insert(source.id, copy_id);
+                store.insert(copy_id, copy);
+            }
+            let todo = store[&copies[&id]].clone();
+            // 添付ファイルのメタデータは AttachmentRepositoryForMemory が持っているので複製できない
+            Ok(DuplicatedTodo {
+                todo,
+                attachments: vec![],
+            })
         }
 
-        async fn all(&self) -> anyhow::Result<Vec<Todo>> {
+        async fn share(
+            &self,
+            scope: Scope,
+            id: i32,
+            payload: ShareTodo,
+        ) -> anyhow::Result<TodoShare> {
             let store = self.read_store_ref();
-            let todos = Vec::from_iter(store.values().map(|todo| todo.clone()));
-            Ok(todos)
+            let todo = store.get(&id).ok_or(RepositoryError::NotFound(id))?;
+            self.check_permission(scope, todo, None)?;
+            self.shares
+                .write()
+                .unwrap()
+                .insert((id, payload.user_id), payload.permission);
+            Ok(TodoShare {
+                todo_id: id,
+                user_id: payload.user_id,
+                permission: payload.permission,
+            })
         }
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
+        async fn merge(&self, from_user_id: i32, into_user_id: i32) -> anyhow::Result<Vec<Todo>> {
             let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
-            Ok(())
+            let mut merged = vec![];
+            for todo in store.values_mut() {
+                if todo.user_id == from_user_id && todo.workspace_id.is_none() {
+                    todo.user_id = into_user_id;
+                    merged.push(todo.clone());
+                }
+            }
+            self.shares
+                .write()
+                .unwrap()
+                .retain(|(todo_id, user_id), _| {
+                    *user_id != into_user_id || merged.iter().all(|todo| todo.id != *todo_id)
+                });
+            merged.sort_by_key(|todo| std::cmp::Reverse(todo.id));
+            Ok(merged)
+        }
+
+        async fn materialize_recurrences_in(
+            &self,
+            _tx: &mut Tx,
+            now: DateTime<Utc>,
+        ) -> anyhow::Result<Vec<Todo>> {
+            let mut store = self.write_store_ref();
+            let mut recurred = self.recurred.write().unwrap();
+            let mut pending = Vec::from_iter(
+                store
+                    .values()
+                    .filter(|todo| {
+                        todo.recurrence.is_some()
+                            && todo.status.is_closed()
+                            && !recurred.contains(&todo.id)
+                    })
+                    .cloned(),
+            );
+            pending.sort_by_key(|todo| todo.id);
+
+            let mut created = vec![];
+            for todo in pending {
+                recurred.insert(todo.id);
+                let Some(due_at) = todo
+                    .recurrence
+                    .and_then(|recurrence| recurrence.next_due(todo.due_at, now))
+                else {
+                    continue;
+                };
+                let id = next_id(&store);
+                let next = Todo {
+                    id,
+                    status: Status::Backlog,
+                    due_at: Some(due_at),
+                    starts_at: next_start(todo.starts_at, todo.due_at, due_at),
+                    archived_at: None,
+                    position: id as i64 * POSITION_GAP,
+                    created_at: now,
+                    is_pinned: false,
+                    snoozed_until: None,
+                    ..todo
+                };
+                store.insert(id, next.clone());
+                created.push(next);
+            }
+            Ok(created)
+        }
+
+        async fn purge_archived_in(
+            &self,
+            _tx: &mut Tx,
+            before: DateTime<Utc>,
+        ) -> anyhow::Result<DeletedTodos> {
+            let mut store = self.write_store_ref();
+            let mut todos: Vec<Todo> = store
+                .values()
+                .filter(|todo| {
+                    todo.archived_at
+                        .is_some_and(|archived_at| archived_at < before)
+                })
+                .cloned()
+                .collect();
+            todos.sort_by_key(|todo| todo.id);
+            let ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+            store.retain(|id, _| !ids.contains(id));
+            self.shares
+                .write()
+                .unwrap()
+                .retain(|(todo_id, _), _| !ids.contains(todo_id));
+            for child in store.values_mut() {
+                if child
+                    .parent_id
+                    .is_some_and(|parent_id| ids.contains(&parent_id))
+                {
+                    child.parent_id = None;
+                }
+            }
+            Ok(DeletedTodos {
+                todos,
+                storage_keys: vec![],
+            })
         }
     }
 
@@ -530,50 +6413,85 @@ pub mod test_utils {
             let label_1 = Label {
                 id: 1,
                 name: String::from("label 1"),
+                user_id: Some(1),
+                workspace_id: None,
+                color: String::from("#4f86f7"),
+                parent_id: None,
+                position: 1,
+                archived_at: None,
+                icon: None,
             };
             let label_2 = Label {
                 id: 2,
                 name: String::from("label 2"),
+                user_id: Some(1),
+                workspace_id: None,
+                color: String::from("#e5484d"),
+                parent_id: Some(1),
+                position: 2,
+                archived_at: None,
+                icon: Some(String::from("🏠")),
             };
             let rows = vec![
                 TodoWithLabelFromRow {
                     id: 1,
                     text: String::from("todo 1"),
-                    completed: false,
+                    status: Status::Backlog,
+                    user_id: 1,
                     label_id: Some(label_1.id),
                     label_name: Some(label_1.name.clone()),
+                    label_user_id: label_1.user_id,
+                    label_workspace_id: label_1.workspace_id,
+                    label_color: Some(label_1.color.clone()),
+                    label_position: Some(label_1.position),
+                    position: 1024,
+                    ..Default::default()
                 },
                 TodoWithLabelFromRow {
                     id: 1,
                     text: String::from("todo 1"),
-                    completed: false,
+                    status: Status::Backlog,
+                    user_id: 1,
                     label_id: Some(label_2.id),
                     label_name: Some(label_2.name.clone()),
+                    label_user_id: label_2.user_id,
+                    label_workspace_id: label_2.workspace_id,
+                    label_color: Some(label_2.color.clone()),
+                    label_parent_id: label_2.parent_id,
+                    label_position: Some(label_2.position),
+                    label_icon: label_2.icon.clone(),
+                    position: 1024,
+                    ..Default::default()
                 },
                 TodoWithLabelFromRow {
                     id: 2,
                     text: String::from("todo 2"),
-                    completed: false,
+                    status: Status::Backlog,
+                    user_id: 1,
                     label_id: Some(label_1.id),
                     label_name: Some(label_1.name.clone()),
+                    label_user_id: label_1.user_id,
+                    label_workspace_id: label_1.workspace_id,
+                    label_color: Some(label_1.color.clone()),
+                    label_position: Some(label_1.position),
+                    position: 2048,
+                    ..Default::default()
                 },
             ];
-    
+
             let res = fold_entities(rows);
             assert_eq!(
                 res,
                 vec![
                     Todo {
-                        id: 1,
-                        text: String::from("todo 1"),
-                        completed: false,
                         labels: vec![label_1.clone(), label_2.clone()],
+                        created_at: DateTime::default(),
+                        ..Todo::new(1, 1, String::from("todo 1"))
                     },
                     Todo {
-                        id: 2,
-                        text: String::from("todo 2"),
-                        completed: false,
                         labels: vec![label_1.clone()],
+                        created_at: DateTime::default(),
+                        ..Todo::new(2, 1, String::from("todo 2"))
                     },
                 ]
             )
@@ -583,48 +6501,757 @@ pub mod test_utils {
         async fn todo_crud_scenario() {
             let text = "todo text".to_string();
             let id = 1;
-            let expected = Todo::new(id, text.clone());
+            let user_id = 1;
+            let scope = Scope::personal(user_id);
+            let other = Scope::personal(2);
+            let expected = Todo::new(id, user_id, text.clone());
 
             // create
             let labels = vec![];
             let repo = TodoRepositoryForMemory::new();
             let todo = repo
-                .create(CreateTodo::new(text, labels))
+                .create(scope, CreateTodo::new(text, labels))
                 .await
                 .expect("failed create todo");
+            let expected = Todo {
+                created_at: todo.created_at,
+                ..expected
+            };
             assert_eq!(expected, todo);
 
             // find
-            let todo = repo.find(todo.id).await.unwrap();
+            let todo = repo.find(scope, todo.id).await.unwrap();
             assert_eq!(expected, todo);
+            assert!(repo.find(other, todo.id).await.is_err());
 
             // all
-            let todos = repo.all().await.expect("fialed get all todo");
+            let todos = repo
+                .all(scope, &TodoFilter::default())
+                .await
+                .expect("fialed get all todo");
             assert_eq!(vec![expected], todos);
+            let todos = repo
+                .all(other, &TodoFilter::default())
+                .await
+                .expect("fialed get all todo");
+            assert!(todos.is_empty());
+            let todos = repo.all_unscoped().await.expect("fialed get all todo");
+            assert_eq!(todos.len(), 1);
 
             // update
             let text = "update todo".to_string();
-            let todo = repo.update(
-                1,
-                UpdateTodo {
-                    text: Some(text.clone()),
-                    completed: Some(true),
-                    labels: Some(vec![]),
-                }
-            ).await.expect("failed update todo");
+            let todo = repo
+                .update(
+                    scope,
+                    1,
+                    UpdateTodo {
+                        text: Some(text.clone()),
+                        status: Some(Status::Done),
+                        labels: Some(vec![]),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect("failed update todo");
             assert_eq!(
                 Todo {
-                    id,
-                    text,
-                    completed: true,
-                    labels: vec![],
+                    status: Status::Done,
+                    created_at: todo.created_at,
+                    ..Todo::new(id, user_id, text)
                 },
                 todo
             );
 
             // delete
-            let res = repo.delete(id).await;
+            assert!(repo.delete(other, id).await.is_err());
+            let res = repo.delete(scope, id).await;
             assert!(res.is_ok())
         }
+
+        #[tokio::test]
+        async fn workspace_scope_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let personal = repo
+                .create(
+                    Scope::personal(1),
+                    CreateTodo::new("personal".to_string(), vec![]),
+                )
+                .await
+                .expect("failed create todo");
+            let shared = repo
+                .create(
+                    Scope::new(1, Some(10)),
+                    CreateTodo::new("shared".to_string(), vec![]),
+                )
+                .await
+                .expect("failed create todo");
+            assert_eq!(shared.workspace_id, Some(10));
+
+            // 同じ workspace のメンバーからは見えるが、個人の todo は見えない
+            let member = Scope::new(2, Some(10));
+            let todos = repo
+                .all(member, &TodoFilter::default())
+                .await
+                .expect("failed get all todo");
+            assert_eq!(vec![shared.clone()], todos);
+            assert!(repo.find(member, personal.id).await.is_err());
+
+            // 個人の一覧には workspace の todo は出てこない
+            let todos = repo
+                .all(Scope::personal(1), &TodoFilter::default())
+                .await
+                .expect("failed get all todo");
+            assert_eq!(vec![personal], todos);
+
+            // 別の workspace からは触れない
+            let outsider = Scope::new(1, Some(20));
+            assert!(repo.find(outsider, shared.id).await.is_err());
+            assert!(repo.delete(outsider, shared.id).await.is_err());
+            assert!(repo.delete(member, shared.id).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn share_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let owner = Scope::personal(1);
+            let reader = Scope::personal(2);
+            let writer = Scope::personal(3);
+            let todo = repo
+                .create(owner, CreateTodo::new("shared todo".to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+            let update = || UpdateTodo {
+                text: Some("updated".to_string()),
+                status: None,
+                labels: None,
+                ..Default::default()
+            };
+
+            // 共有前は見えない
+            assert!(repo.find(reader, todo.id).await.is_err());
+
+            for (user_id, permission) in [(2, SharePermission::Read), (3, SharePermission::Write)] {
+                repo.share(
+                    owner,
+                    todo.id,
+                    ShareTodo {
+                        user_id,
+                        permission,
+                    },
+                )
+                .await
+                .expect("failed share todo");
+            }
+            // 共有された側は再共有できない
+            assert!(repo
+                .share(
+                    writer,
+                    todo.id,
+                    ShareTodo {
+                        user_id: 4,
+                        permission: SharePermission::Write
+                    }
+                )
+                .await
+                .is_err());
+
+            // read は閲覧だけ、write は更新まで. どちらも削除はできない
+            assert_eq!(repo.find(reader, todo.id).await.unwrap(), todo);
+            assert_eq!(
+                repo.all(reader, &TodoFilter::default()).await.unwrap(),
+                vec![todo.clone()]
+            );
+            assert!(repo.update(reader, todo.id, update()).await.is_err());
+            let updated = repo
+                .update(writer, todo.id, update())
+                .await
+                .expect("failed update todo");
+            assert_eq!(updated.text, "updated");
+            assert_eq!(updated.user_id, 1);
+            assert!(repo.delete(reader, todo.id).await.is_err());
+            assert!(repo.delete(writer, todo.id).await.is_err());
+            assert!(repo.delete(owner, todo.id).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn due_filter_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let now = Utc::now();
+            let create = |text: &str, due_at: Option<DateTime<Utc>>| CreateTodo {
+                due_at,
+                ..CreateTodo::new(text.to_string(), vec![])
+            };
+            let overdue = repo
+                .create(
+                    scope,
+                    create("overdue", Some(now - chrono::Duration::days(1))),
+                )
+                .await
+                .unwrap();
+            let upcoming = repo
+                .create(
+                    scope,
+                    create("upcoming", Some(now + chrono::Duration::days(1))),
+                )
+                .await
+                .unwrap();
+            let no_due = repo.create(scope, create("no due", None)).await.unwrap();
+            let ids = |todos: Vec<Todo>| {
+                let mut ids: Vec<i32> = todos.iter().map(|todo| todo.id).collect();
+                ids.sort();
+                ids
+            };
+
+            let filter = TodoFilter {
+                overdue: Some(true),
+                ..Default::default()
+            };
+            assert_eq!(
+                ids(repo.all(scope, &filter).await.unwrap()),
+                vec![overdue.id]
+            );
+            let filter = TodoFilter {
+                overdue: Some(false),
+                ..Default::default()
+            };
+            assert_eq!(
+                ids(repo.all(scope, &filter).await.unwrap()),
+                vec![upcoming.id, no_due.id]
+            );
+            let filter = TodoFilter {
+                due_before: Some(now + chrono::Duration::days(2)),
+                ..Default::default()
+            };
+            assert_eq!(
+                ids(repo.all(scope, &filter).await.unwrap()),
+                vec![overdue.id, upcoming.id]
+            );
+
+            // 完了した todo は期限切れとみなさない
+            let update = UpdateTodo {
+                status: Some(Status::Done),
+                ..Default::default()
+            };
+            repo.update(scope, overdue.id, update).await.unwrap();
+            let filter = TodoFilter {
+                overdue: Some(true),
+                ..Default::default()
+            };
+            assert!(repo.all(scope, &filter).await.unwrap().is_empty());
+
+            // null で期限を外せる
+            let update: UpdateTodo = serde_json::from_str(r#"{ "due_at": null }"#).unwrap();
+            let todo = repo.update(scope, upcoming.id, update).await.unwrap();
+            assert_eq!(todo.due_at, None);
+        }
+
+        #[tokio::test]
+        async fn priority_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            for priority in [
+                Priority::High,
+                Priority::Low,
+                Priority::Urgent,
+                Priority::High,
+            ] {
+                repo.create(
+                    scope,
+                    CreateTodo {
+                        priority,
+                        ..CreateTodo::new(format!("{:?}", priority), vec![])
+                    },
+                )
+                .await
+                .expect("failed create todo");
+            }
+
+            // 優先度の高い順、同じ優先度なら新しい順
+            let filter = TodoFilter {
+                sort: Some(TodoSort::Priority),
+                ..Default::default()
+            };
+            let ids: Vec<i32> = repo
+                .all(scope, &filter)
+                .await
+                .unwrap()
+                .iter()
+                .map(|todo| todo.id)
+                .collect();
+            assert_eq!(ids, vec![3, 4, 1, 2]);
+
+            let update = UpdateTodo {
+                priority: Some(Priority::Urgent),
+                ..Default::default()
+            };
+            let todo = repo.update(scope, 2, update).await.unwrap();
+            assert_eq!(todo.priority, Priority::Urgent);
+        }
+
+        #[tokio::test]
+        async fn subtask_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let create = |text: &str, parent_id: Option<i32>| CreateTodo {
+                parent_id,
+                ..CreateTodo::new(text.to_string(), vec![])
+            };
+            let parent = repo.create(scope, create("parent", None)).await.unwrap();
+            let child = repo
+                .create(scope, create("child", Some(parent.id)))
+                .await
+                .unwrap();
+            let grandchild = repo
+                .create(scope, create("grandchild", Some(child.id)))
+                .await
+                .unwrap();
+            assert_eq!(grandchild.parent_id, Some(child.id));
+
+            // 他人の todo の下には作れない
+            assert!(repo
+                .create(Scope::personal(2), create("other", Some(parent.id)))
+                .await
+                .is_err());
+
+            // 循環する親子関係は作れない
+            let reparent = |parent_id: i32| UpdateTodo {
+                parent_id: Some(Some(parent_id)),
+                ..Default::default()
+            };
+            for parent_id in [parent.id, child.id, grandchild.id] {
+                let res = repo.update(scope, parent.id, reparent(parent_id)).await;
+                assert!(matches!(
+                    res.unwrap_err().downcast_ref::<RepositoryError>(),
+                    Some(RepositoryError::Invalid(_))
+                ));
+            }
+
+            // 直下のサブタスク
+            let filter = TodoFilter {
+                parent_id: Some(parent.id),
+                ..Default::default()
+            };
+            assert_eq!(repo.all(scope, &filter).await.unwrap(), vec![child.clone()]);
+
+            // 親と一緒にサブタスクもすべて完了にする
+            let complete = UpdateTodo {
+                status: Some(Status::Done),
+                complete_subtasks: true,
+                ..Default::default()
+            };
+            repo.update(scope, parent.id, complete).await.unwrap();
+            assert_eq!(
+                repo.find(scope, child.id).await.unwrap().status,
+                Status::Done
+            );
+            assert_eq!(
+                repo.find(scope, grandchild.id).await.unwrap().status,
+                Status::Done
+            );
+
+            // 親を削除しても子は残る
+            repo.delete(scope, child.id).await.unwrap();
+            assert_eq!(
+                repo.find(scope, grandchild.id).await.unwrap().parent_id,
+                None
+            );
+        }
+
+        #[test]
+        fn next_due_test() {
+            let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+            let now = at("2022-12-26T00:00:00Z");
+            let recurrence = |freq, interval, until| Recurrence {
+                freq,
+                interval,
+                until,
+            };
+
+            assert_eq!(
+                recurrence(Frequency::Weekly, 2, None)
+                    .next_due(Some(at("2022-12-25T09:00:00Z")), now),
+                Some(at("2023-01-08T09:00:00Z"))
+            );
+            // 月末は翌月の末日に丸める
+            assert_eq!(
+                recurrence(Frequency::Monthly, 1, None)
+                    .next_due(Some(at("2023-01-31T09:00:00Z")), now),
+                Some(at("2023-02-28T09:00:00Z"))
+            );
+            // 遅れて完了したら now より後になるまで進める
+            assert_eq!(
+                recurrence(Frequency::Daily, 1, None)
+                    .next_due(Some(at("2022-12-20T09:00:00Z")), now),
+                Some(at("2022-12-26T09:00:00Z"))
+            );
+            // 期限がなければ now から
+            assert_eq!(
+                recurrence(Frequency::Daily, 3, None).next_due(None, now),
+                Some(at("2022-12-29T00:00:00Z"))
+            );
+            // until を過ぎたら終わり
+            let until = Some(at("2022-12-31T00:00:00Z"));
+            assert_eq!(
+                recurrence(Frequency::Weekly, 1, until)
+                    .next_due(Some(at("2022-12-25T09:00:00Z")), now),
+                None
+            );
+        }
+
+        #[test]
+        fn status_transition_test() {
+            use Status::*;
+            assert!(Backlog.can_transition_to(InProgress));
+            assert!(InProgress.can_transition_to(Done));
+            assert!(Done.can_transition_to(InProgress));
+            assert!(Cancelled.can_transition_to(Backlog));
+            assert!(Cancelled.can_transition_to(Cancelled));
+            assert!(!Cancelled.can_transition_to(Done));
+            assert!(!Done.can_transition_to(Cancelled));
+            assert!(Done.is_closed() && Cancelled.is_closed());
+            assert!(!Backlog.is_closed() && !InProgress.is_closed());
+        }
+
+        #[test]
+        fn plan_move_test() {
+            let siblings = [(1, 1024), (2, 2048), (3, 2049)];
+            // 空きがあれば移動する todo だけを更新する
+            assert_eq!(plan_move(&siblings, 4, 0), vec![(4, 512)]);
+            assert_eq!(plan_move(&siblings, 4, 1), vec![(4, 1536)]);
+            assert_eq!(plan_move(&siblings, 4, 3), vec![(4, 3073)]);
+            assert_eq!(plan_move(&[], 4, 0), vec![(4, 1024)]);
+            // 空きがなければ全体を振り直す
+            assert_eq!(
+                plan_move(&siblings, 4, 2),
+                vec![(1, 1024), (2, 2048), (4, 3072), (3, 4096)]
+            );
+            assert_eq!(plan_move(&[(1, 1)], 4, 0), vec![(4, 1024), (1, 2048)]);
+        }
+
+        #[test]
+        fn merge_patch_test() {
+            let merge = |before: &Todo, patch: Value| {
+                UpdateTodo::merge(before, patch.as_object().unwrap().clone())
+            };
+            let before = Todo {
+                recurrence: Some(Recurrence {
+                    freq: Frequency::Weekly,
+                    interval: 1,
+                    until: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+                }),
+                ..Todo::new(1, 1, "todo".to_string())
+            };
+            // 省略したフィールドは変更せず、null なら外す
+            let payload = merge(&before, json!({ "text": "merged", "due_at": null })).unwrap();
+            assert_eq!(payload.text, Some("merged".to_string()));
+            assert_eq!(payload.due_at, Some(None));
+            assert_eq!(payload.description, None);
+            // オブジェクトは中のフィールドごとにマージする
+            let payload = merge(
+                &before,
+                json!({ "recurrence": { "interval": 2, "until": null } }),
+            );
+            assert_eq!(
+                payload.unwrap().recurrence,
+                Some(Some(Recurrence {
+                    freq: Frequency::Weekly,
+                    interval: 2,
+                    until: None,
+                }))
+            );
+            let payload = merge(&before, json!({ "recurrence": null })).unwrap();
+            assert_eq!(payload.recurrence, Some(None));
+            // 外せないフィールドや、マージした結果が不正なら Invalid
+            for patch in [
+                json!({ "text": null }),
+                json!({ "labels": null }),
+                json!({ "text": "" }),
+                json!({ "priority": "urgent!" }),
+            ] {
+                let e = merge(&before, patch).unwrap_err();
+                assert!(matches!(
+                    e.downcast_ref(),
+                    Some(RepositoryError::Invalid(_))
+                ));
+            }
+            let e = merge(
+                &Todo::new(2, 1, "todo".to_string()),
+                json!({ "recurrence": {} }),
+            );
+            assert!(e.is_err());
+        }
+
+        #[test]
+        fn json_patch_test() {
+            let apply = |before: &Todo, operations: Value| {
+                UpdateTodo::apply(before, serde_json::from_value(operations).unwrap())
+            };
+            let before = Todo {
+                labels: vec![
+                    Label::new(1, "a".to_string(), 1),
+                    Label::new(2, "b".to_string(), 1),
+                ],
+                due_at: Some("2030-01-01T00:00:00Z".parse().unwrap()),
+                recurrence: Some(Recurrence {
+                    freq: Frequency::Weekly,
+                    interval: 1,
+                    until: None,
+                }),
+                ..Todo::new(1, 1, "todo".to_string())
+            };
+            // 変更したフィールドだけを更新する
+            let payload = apply(
+                &before,
+                json!([
+                    { "op": "replace", "path": "/text", "value": "patched" },
+                    { "op": "remove", "path": "/due_at" },
+                    { "op": "add", "path": "/labels/-", "value": 3 },
+                    { "op": "remove", "path": "/labels/0" },
+                    { "op": "replace", "path": "/recurrence/interval", "value": 2 },
+                ]),
+            )
+            .unwrap();
+            assert_eq!(payload.text, Some("patched".to_string()));
+            assert_eq!(payload.due_at, Some(None));
+            assert_eq!(payload.labels, Some(vec![2, 3]));
+            assert_eq!(payload.description, None);
+            assert_eq!(payload.recurrence.flatten().map(|r| r.interval), Some(2));
+            let payload = apply(
+                &before,
+                json!([{ "op": "replace", "path": "/text", "value": "todo" }]),
+            );
+            assert_eq!(payload.unwrap().text, None);
+            // 許可していない path、範囲外の添字、外せないフィールドや、当てた結果が不正なら Invalid
+            for operations in [
+                json!([{ "op": "replace", "path": "/id", "value": 2 }]),
+                json!([{ "op": "replace", "path": "/user_id", "value": 2 }]),
+                json!([{ "op": "add", "path": "/text/0", "value": "x" }]),
+                json!([{ "op": "remove", "path": "/labels/2" }]),
+                json!([{ "op": "add", "path": "/labels/01", "value": 3 }]),
+                json!([{ "op": "remove", "path": "/text" }]),
+                json!([{ "op": "replace", "path": "/recurrence/count", "value": 1 }]),
+                json!([{ "op": "replace", "path": "/text", "value": "" }]),
+                json!([{ "op": "replace", "path": "text", "value": "x" }]),
+            ] {
+                let e = apply(&before, operations.clone()).unwrap_err();
+                assert!(
+                    matches!(e.downcast_ref(), Some(RepositoryError::Invalid(_))),
+                    "{}",
+                    operations
+                );
+            }
+            let e = apply(
+                &Todo::new(2, 1, "todo".to_string()),
+                json!([{ "op": "replace", "path": "/recurrence/interval", "value": 2 }]),
+            );
+            assert!(e.is_err());
+            // move / copy / test は受け付けない
+            let operation = json!({ "op": "move", "from": "/text", "path": "/description" });
+            assert!(serde_json::from_value::<PatchOperation>(operation).is_err());
+        }
+
+        #[tokio::test]
+        async fn move_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            for text in ["a", "b", "c"] {
+                repo.create(scope, CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .unwrap();
+            }
+            let other = repo
+                .create(
+                    Scope::personal(2),
+                    CreateTodo::new("other".to_string(), vec![]),
+                )
+                .await
+                .unwrap();
+            let texts = || async {
+                let filter = TodoFilter {
+                    sort: Some(TodoSort::Position),
+                    ..Default::default()
+                };
+                let todos = repo.all(scope, &filter).await.unwrap();
+                todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>()
+            };
+            assert_eq!(texts().await, vec!["a", "b", "c"]);
+
+            let move_to = |index, before, after| MoveTodo {
+                index,
+                before,
+                after,
+            };
+            repo.move_to(scope, 3, move_to(Some(0), None, None))
+                .await
+                .unwrap();
+            assert_eq!(texts().await, vec!["c", "a", "b"]);
+            repo.move_to(scope, 3, move_to(None, None, Some(2)))
+                .await
+                .unwrap();
+            assert_eq!(texts().await, vec!["a", "b", "c"]);
+            repo.move_to(scope, 1, move_to(None, Some(3), None))
+                .await
+                .unwrap();
+            assert_eq!(texts().await, vec!["b", "a", "c"]);
+            // 末尾より後ろは末尾
+            repo.move_to(scope, 2, move_to(Some(10), None, None))
+                .await
+                .unwrap();
+            assert_eq!(texts().await, vec!["a", "c", "b"]);
+
+            // 別の一覧の todo は基準にできないし、他人の todo は動かせない
+            let res = repo
+                .move_to(scope, 1, move_to(None, Some(other.id), None))
+                .await;
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<RepositoryError>(),
+                Some(RepositoryError::Invalid(_))
+            ));
+            assert!(repo
+                .move_to(scope, other.id, move_to(Some(0), None, None))
+                .await
+                .is_err());
+        }
+
+        #[tokio::test]
+        async fn recurrence_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let now = Utc::now();
+            let recurrence = Recurrence {
+                freq: Frequency::Daily,
+                interval: 1,
+                until: None,
+            };
+            let todo = repo
+                .create(
+                    scope,
+                    CreateTodo {
+                        due_at: Some(now - Duration::hours(1)),
+                        starts_at: Some(now - Duration::hours(3)),
+                        recurrence: Some(recurrence),
+                        ..CreateTodo::new("daily".to_string(), vec![])
+                    },
+                )
+                .await
+                .unwrap();
+            let once = repo
+                .create(scope, CreateTodo::new("once".to_string(), vec![]))
+                .await
+                .unwrap();
+
+            // 完了するまでは作らない
+            assert!(repo.materialize_recurrences(now).await.unwrap().is_empty());
+
+            let complete = || UpdateTodo {
+                status: Some(Status::Done),
+                ..Default::default()
+            };
+            repo.update(scope, todo.id, complete()).await.unwrap();
+            repo.update(scope, once.id, complete()).await.unwrap();
+            let created = repo.materialize_recurrences(now).await.unwrap();
+            assert_eq!(created.len(), 1);
+            let next = &created[0];
+            assert_eq!(next.text, todo.text);
+            assert_eq!(next.status, Status::Backlog);
+            assert_eq!(next.due_at, Some(now + Duration::hours(23)));
+            // 開始日時は期限との間隔を保つ
+            assert_eq!(next.starts_at, Some(now + Duration::hours(21)));
+            assert_eq!(next.recurrence, Some(recurrence));
+            assert_eq!(repo.find(scope, next.id).await.unwrap(), *next);
+
+            // 同じ todo からは一度だけ
+            assert!(repo.materialize_recurrences(now).await.unwrap().is_empty());
+
+            // until を過ぎたら作らない
+            let last = UpdateTodo {
+                status: Some(Status::Done),
+                recurrence: Some(Some(Recurrence {
+                    until: Some(now),
+                    ..recurrence
+                })),
+                ..Default::default()
+            };
+            repo.update(scope, next.id, last).await.unwrap();
+            assert!(repo.materialize_recurrences(now).await.unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn purge_archived_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let create = |text: &str| repo.create(scope, CreateTodo::new(text.to_string(), vec![]));
+            let parent = create("parent").await.unwrap();
+            let child = repo
+                .create(
+                    scope,
+                    CreateTodo {
+                        parent_id: Some(parent.id),
+                        ..CreateTodo::new("child".to_string(), vec![])
+                    },
+                )
+                .await
+                .unwrap();
+            let open = create("open").await.unwrap();
+            repo.archive(scope, parent.id).await.unwrap();
+
+            // アーカイブした時刻が before より前のものだけ
+            let archived_at = repo
+                .find(scope, parent.id)
+                .await
+                .unwrap()
+                .archived_at
+                .unwrap();
+            assert!(repo
+                .purge_archived(archived_at)
+                .await
+                .unwrap()
+                .todos
+                .is_empty());
+            let purged = repo
+                .purge_archived(archived_at + Duration::seconds(1))
+                .await
+                .unwrap();
+            assert_eq!(purged.todos.len(), 1);
+            assert_eq!(purged.todos[0].id, parent.id);
+            assert!(repo.find(scope, parent.id).await.is_err());
+            assert_eq!(repo.find(scope, child.id).await.unwrap().parent_id, None);
+            assert_eq!(repo.find(scope, open.id).await.unwrap(), open);
+        }
+
+        #[tokio::test]
+        async fn merge_scenario() {
+            let repo = TodoRepositoryForMemory::new();
+            let guest = Scope::personal(1);
+            let user = Scope::personal(2);
+            let todo = repo
+                .create(guest, CreateTodo::new("guest todo".to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+            repo.create(
+                Scope::new(1, Some(1)),
+                CreateTodo::new("workspace todo".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+
+            // 個人の todo だけが引き継がれる
+            let merged = repo.merge(1, 2).await.expect("failed merge todos");
+            let expected = Todo {
+                created_at: todo.created_at,
+                ..Todo::new(todo.id, 2, "guest todo".to_string())
+            };
+            assert_eq!(merged, vec![expected]);
+            assert_eq!(
+                repo.all(user, &TodoFilter::default()).await.unwrap(),
+                merged
+            );
+            assert!(repo
+                .all(guest, &TodoFilter::default())
+                .await
+                .unwrap()
+                .is_empty());
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1025 @@
+use super::{cache::RepositoryCache, RepositoryError};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+#[async_trait]
+pub trait UserRepository: std::marker::Send + std::marker::Sync + 'static {
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User>;
+    async fn find(&self, id: i32) -> anyhow::Result<User>;
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User>;
+    // 外部 IdP のアカウントに紐づくユーザーを返す
+    // 未登録なら同じ email のユーザーに紐づけるか、新しくユーザーを作る
+    async fn find_or_create_by_identity(&self, identity: ExternalIdentity) -> anyhow::Result<User>;
+    // 端末のトークンで識別するゲストユーザー. メールアドレスは仮のものを割り当てる
+    async fn create_guest(&self, device_token_hash: String) -> anyhow::Result<User>;
+    async fn find_guest(&self, device_token_hash: &str) -> anyhow::Result<User>;
+    // 管理者向け
+    async fn all(&self) -> anyhow::Result<Vec<User>>;
+    async fn set_disabled(&self, id: i32, disabled: bool) -> anyhow::Result<User>;
+    async fn require_password_reset(&self, id: i32) -> anyhow::Result<User>;
+    // パスワードを変更し、パスワード変更の強制を解除する
+    async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<User>;
+    // 購読用トークンの版を上げ、それまでに発行したトークンを全て使えなくする
+    async fn rotate_feed_token(&self, id: i32) -> anyhow::Result<User>;
+    // ユーザーとそのユーザーのデータを 1 つのトランザクションですべて削除する
+    // 一緒に消えた添付ファイルの中身の storage_key を返す. 中身の削除は handler で行う
+    async fn delete(&self, id: i32) -> anyhow::Result<Vec<String>>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Member,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub email: String,
+    pub role: Role,
+    // ハッシュ値とはいえレスポンスに含める理由はないので serialize しない
+    // 外部 IdP 経由で作られたユーザーは None
+    #[serde(skip_serializing)]
+    pub password_hash: Option<String>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub password_reset_required: bool,
+    pub guest: bool,
+    // 購読用トークンに載せる版. トークンと同じく秘密にしておく
+    #[serde(skip_serializing)]
+    pub feed_token_version: i32,
+}
+
+impl User {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+}
+
+// パスワードは handler 側でハッシュ化してから渡す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateUser {
+    pub email: String,
+    pub password_hash: String,
+}
+
+// IdP から取得したアカウント情報. email は IdP 側で検証済みのものだけを扱う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForDb {
+    pool: PgPool,
+    // 消したものに付いていた todo / label のキャッシュを捨てる
+    cache: Option<RepositoryCache>,
+}
+
+impl UserRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool, cache: None }
+    }
+
+    pub fn with_cache(self, cache: Option<RepositoryCache>) -> Self {
+        Self { cache, ..self }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForDb {
+    #[tracing::instrument(name = "UserRepository::create", skip_all)]
+    async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+        let optional_user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users WHERE email = $1
+            "#,
+        )
+        .bind(payload.email.clone())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(user) = optional_user {
+            return Err(RepositoryError::Duplicate(user.id).into());
+        }
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( $1, $2 )
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(payload.email)
+        .bind(payload.password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::find", skip_all)]
+    async fn find(&self, id: i32) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::find_by_email", skip_all)]
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users WHERE email = $1
+            "#,
+        )
+        .bind(email)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            // email で引いているので id は分からない
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(0),
+            _ => RepositoryError::Unexpected(e.to_string()),
+        })?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::find_or_create_by_identity", skip_all)]
+    async fn find_or_create_by_identity(&self, identity: ExternalIdentity) -> anyhow::Result<User> {
+        let mut tx = self.pool.begin().await?;
+
+        let linked_user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT users.id, users.email, users.role, users.password_hash, users.disabled_at, users.password_reset_required,
+                users.guest, users.feed_token_version
+            FROM users
+            INNER JOIN identities ON identities.user_id = users.id
+            WHERE identities.provider = $1 AND identities.subject = $2
+            "#,
+        )
+        .bind(identity.provider.clone())
+        .bind(identity.subject.clone())
+        .fetch_optional(&mut tx)
+        .await?;
+        if let Some(user) = linked_user {
+            return Ok(user);
+        }
+
+        // 同じ email のユーザーがいればそのユーザーに紐づける
+        let optional_user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users WHERE email = $1
+            "#,
+        )
+        .bind(identity.email.clone())
+        .fetch_optional(&mut tx)
+        .await?;
+        let user = match optional_user {
+            Some(user) => user,
+            None => {
+                sqlx::query_as::<_, User>(
+                    r#"
+                    INSERT INTO users (email)
+                    VALUES ( $1 )
+                    RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+                    "#,
+                )
+                .bind(identity.email)
+                .fetch_one(&mut tx)
+                .await?
+            }
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO identities (user_id, provider, subject)
+            VALUES ( $1, $2, $3 )
+            "#,
+        )
+        .bind(user.id)
+        .bind(identity.provider)
+        .bind(identity.subject)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::create_guest", skip_all)]
+    async fn create_guest(&self, device_token_hash: String) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (email, guest, device_token_hash)
+            VALUES ( 'guest-' || md5(random()::text) || '@guest.invalid', true, $1 )
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(device_token_hash)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::find_guest", skip_all)]
+    async fn find_guest(&self, device_token_hash: &str) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users
+            WHERE device_token_hash = $1 AND guest
+            "#,
+        )
+        .bind(device_token_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(0))?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::all", skip_all)]
+    async fn all(&self) -> anyhow::Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version FROM users
+            ORDER BY id ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    #[tracing::instrument(name = "UserRepository::set_disabled", skip_all)]
+    async fn set_disabled(&self, id: i32, disabled: bool) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET disabled_at = CASE WHEN $2 THEN COALESCE(disabled_at, now()) END
+            WHERE id = $1
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(id)
+        .bind(disabled)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::require_password_reset", skip_all)]
+    async fn require_password_reset(&self, id: i32) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET password_reset_required = true
+            WHERE id = $1
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::update_password", skip_all)]
+    async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET password_hash = $2, password_reset_required = false
+            WHERE id = $1
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(id)
+        .bind(password_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::rotate_feed_token", skip_all)]
+    async fn rotate_feed_token(&self, id: i32) -> anyhow::Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET feed_token_version = feed_token_version + 1
+            WHERE id = $1
+            RETURNING id, email, role, password_hash, disabled_at, password_reset_required, guest, feed_token_version
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(name = "UserRepository::delete", skip_all)]
+    async fn delete(&self, id: i32) -> anyhow::Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+
+        // 添付ファイルの行は todo と一緒に消えるので、中身の key を先に控えておく
+        let storage_keys = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT attachments.storage_key FROM attachments
+            INNER JOIN todos ON todos.id = attachments.todo_id
+            WHERE todos.user_id = $1
+                OR todos.workspace_id IN (SELECT id FROM workspaces WHERE owner_id = $1)
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&mut tx)
+        .await?;
+
+        // 所有している workspace は中の todo / label ごと消す
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels
+            WHERE todo_id IN (
+                    SELECT todos.id FROM todos
+                    INNER JOIN workspaces ON workspaces.id = todos.workspace_id
+                    WHERE workspaces.owner_id = $1
+                )
+                OR label_id IN (
+                    SELECT labels.id FROM labels
+                    INNER JOIN workspaces ON workspaces.id = labels.workspace_id
+                    WHERE workspaces.owner_id = $1
+                )
+                OR todo_id IN (SELECT id FROM todos WHERE user_id = $1)
+                OR label_id IN (SELECT id FROM labels WHERE user_id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM todos
+            WHERE user_id = $1
+                OR workspace_id IN (SELECT id FROM workspaces WHERE owner_id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            "DELETE FROM projects WHERE workspace_id IN (SELECT id FROM workspaces WHERE owner_id = $1)",
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            "DELETE FROM templates WHERE workspace_id IN (SELECT id FROM workspaces WHERE owner_id = $1)",
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM labels
+            WHERE user_id = $1
+                OR workspace_id IN (SELECT id FROM workspaces WHERE owner_id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM workspaces WHERE owner_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+        // 個人データを残さないよう、本人の操作の記録やログインの失敗記録も消す
+        sqlx::query("DELETE FROM audit_events WHERE actor_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM login_attempts
+            WHERE email = (SELECT email FROM users WHERE id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM invitations
+            WHERE email = (SELECT email FROM users WHERE id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+        // identities, refresh_tokens, memberships などは ON DELETE CASCADE で消える
+        let res = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        tx.commit().await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all().await;
+        }
+        Ok(storage_keys)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = UserRepositoryForDb::new(pool.clone());
+        let email = "crud_scenario@example.com";
+
+        // 前回のテストで残ったユーザーがいると create が Duplicate になるので掃除しておく
+        sqlx::query("DELETE FROM users WHERE email = $1")
+            .bind(email)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up users");
+
+        // create
+        let user = repo
+            .create(CreateUser {
+                email: email.to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(user.email, email);
+        assert_eq!(user.role, Role::Member);
+
+        // duplicate
+        let res = repo
+            .create(CreateUser {
+                email: email.to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await;
+        assert!(res.is_err());
+
+        // find
+        let found = repo.find(user.id).await.expect("[find] returned Err");
+        assert_eq!(found, user);
+
+        // find_by_email
+        let found = repo
+            .find_by_email(email)
+            .await
+            .expect("[find_by_email] returned Err");
+        assert_eq!(found, user);
+
+        // find_or_create_by_identity
+        // 同じ email のユーザーに紐づき、2回目以降は identities から引かれる
+        let identity = ExternalIdentity {
+            provider: "github".to_string(),
+            subject: format!("crud_scenario-{}", user.id),
+            email: email.to_string(),
+        };
+        let linked = repo
+            .find_or_create_by_identity(identity.clone())
+            .await
+            .expect("[find_or_create_by_identity] returned Err");
+        assert_eq!(linked, user);
+        let linked = repo
+            .find_or_create_by_identity(identity)
+            .await
+            .expect("[find_or_create_by_identity] returned Err");
+        assert_eq!(linked, user);
+
+        // set_disabled
+        let disabled = repo
+            .set_disabled(user.id, true)
+            .await
+            .expect("[set_disabled] returned Err");
+        assert!(disabled.is_disabled());
+        let enabled = repo
+            .set_disabled(user.id, false)
+            .await
+            .expect("[set_disabled] returned Err");
+        assert!(!enabled.is_disabled());
+
+        // require_password_reset / update_password
+        let reset = repo
+            .require_password_reset(user.id)
+            .await
+            .expect("[require_password_reset] returned Err");
+        assert!(reset.password_reset_required);
+        let updated = repo
+            .update_password(user.id, "new hash".to_string())
+            .await
+            .expect("[update_password] returned Err");
+        assert!(!updated.password_reset_required);
+        assert_eq!(updated.password_hash, Some("new hash".to_string()));
+
+        // rotate_feed_token
+        let rotated = repo
+            .rotate_feed_token(user.id)
+            .await
+            .expect("[rotate_feed_token] returned Err");
+        assert_eq!(rotated.feed_token_version, updated.feed_token_version + 1);
+
+        // all
+        let users = repo.all().await.expect("[all] returned Err");
+        assert!(users.contains(&rotated));
+
+        // delete はユーザーの todo もまとめて消し、添付ファイルの中身の key を返す
+        let (todo_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO todos (text, user_id) VALUES ('todo of deleted user', $1) RETURNING id",
+        )
+        .bind(user.id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+        let storage_key = format!("crud_scenario/{}", todo_id);
+        sqlx::query(
+            r#"
+            INSERT INTO attachments (todo_id, user_id, file_name, content_type, size, storage_key)
+            VALUES ($1, $2, 'memo.txt', 'text/plain', 4, $3)
+            "#,
+        )
+        .bind(todo_id)
+        .bind(user.id)
+        .bind(&storage_key)
+        .execute(&pool)
+        .await
+        .expect("failed to prepare attachment data.");
+        let storage_keys = repo.delete(user.id).await.expect("[delete] returned Err");
+        assert_eq!(storage_keys, vec![storage_key]);
+        assert!(repo.find(user.id).await.is_err());
+        let rows = sqlx::query("SELECT * FROM todos WHERE user_id = $1")
+            .bind(user.id)
+            .fetch_all(&pool)
+            .await
+            .expect("[delete] todos fetch error");
+        assert!(rows.is_empty());
+        assert!(repo.delete(user.id).await.is_err());
+
+        // create_guest / find_guest
+        sqlx::query("DELETE FROM users WHERE device_token_hash = 'db-guest-device-token'")
+            .execute(&pool)
+            .await
+            .expect("failed to clean up guests");
+        let guest = repo
+            .create_guest("db-guest-device-token".to_string())
+            .await
+            .expect("[create_guest] returned Err");
+        assert!(guest.guest);
+        assert_eq!(guest.password_hash, None);
+        let found = repo
+            .find_guest("db-guest-device-token")
+            .await
+            .expect("[find_guest] returned Err");
+        assert_eq!(found, guest);
+        repo.delete(guest.id).await.expect("[delete] returned Err");
+    }
+
+    #[tokio::test]
+    async fn delete_workspace_owner_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = UserRepositoryForDb::new(pool.clone());
+        let email = "delete_workspace_owner_scenario@example.com";
+        sqlx::query("DELETE FROM users WHERE email = $1")
+            .bind(email)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up users");
+        let user = repo
+            .create(CreateUser {
+                email: email.to_string(),
+                password_hash: "hash".to_string(),
+            })
+            .await
+            .expect("[create] returned Err");
+
+        // 所有している workspace は、中のプロジェクトやテンプレートごと消える
+        let (workspace_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO workspaces (name, owner_id) VALUES ('owned', $1) RETURNING id",
+        )
+        .bind(user.id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare workspace data.");
+        sqlx::query("INSERT INTO projects (name, user_id, workspace_id) VALUES ('board', $1, $2)")
+            .bind(user.id)
+            .bind(workspace_id)
+            .execute(&pool)
+            .await
+            .expect("failed to prepare project data.");
+        sqlx::query(
+            "INSERT INTO templates (name, text, user_id, workspace_id) VALUES ('weekly', 'review', $1, $2)",
+        )
+        .bind(user.id)
+        .bind(workspace_id)
+        .execute(&pool)
+        .await
+        .expect("failed to prepare template data.");
+
+        repo.delete(user.id).await.expect("[delete] returned Err");
+        let rows = sqlx::query("SELECT * FROM workspaces WHERE id = $1")
+            .bind(workspace_id)
+            .fetch_all(&pool)
+            .await
+            .expect("[delete] workspaces fetch error");
+        assert!(rows.is_empty());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    use super::*;
+
+    type UserDatas = HashMap<i32, User>;
+    // (provider, subject) -> user id
+    type IdentityDatas = HashMap<(String, String), i32>;
+
+    // device_token_hash -> user id
+    type DeviceTokenDatas = HashMap<String, i32>;
+
+    // ユーザーは削除されることがあるので、件数ではなく最大の id から採番する
+    fn next_id(store: &UserDatas) -> i32 {
+        store.keys().max().copied().unwrap_or(0) + 1
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct UserRepositoryForMemory {
+        store: Arc<RwLock<UserDatas>>,
+        identities: Arc<RwLock<IdentityDatas>>,
+        device_tokens: Arc<RwLock<DeviceTokenDatas>>,
+    }
+
+    impl UserRepositoryForMemory {
+        pub fn new() -> Self {
+            UserRepositoryForMemory {
+                store: Arc::default(),
+                identities: Arc::default(),
+                device_tokens: Arc::default(),
+            }
+        }
+
+        // 決まった id とロールのユーザーを置く. handler のテストはこの id でトークンを発行する
+        #[cfg(test)]
+        pub fn insert(&self, id: i32, role: Role) -> User {
+            let user = User {
+                id,
+                email: format!("user{}@example.com", id),
+                role,
+                password_hash: None,
+                disabled_at: None,
+                password_reset_required: false,
+                guest: false,
+                feed_token_version: 0,
+            };
+            self.write_store_ref().insert(id, user.clone());
+            user
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, UserDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, UserDatas> {
+            self.store.read().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for UserRepositoryForMemory {
+        async fn create(&self, payload: CreateUser) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            if let Some(user) = store.values().find(|user| user.email == payload.email) {
+                return Err(RepositoryError::Duplicate(user.id).into());
+            }
+            let id = next_id(&store);
+            let user = User {
+                id,
+                email: payload.email,
+                role: Role::Member,
+                password_hash: Some(payload.password_hash),
+                disabled_at: None,
+                password_reset_required: false,
+                guest: false,
+                feed_token_version: 0,
+            };
+            store.insert(id, user.clone());
+            Ok(user)
+        }
+
+        async fn find(&self, id: i32) -> anyhow::Result<User> {
+            let store = self.read_store_ref();
+            let user = store.get(&id).ok_or(RepositoryError::NotFound(id))?;
+            Ok(user.clone())
+        }
+
+        async fn find_by_email(&self, email: &str) -> anyhow::Result<User> {
+            let store = self.read_store_ref();
+            let user = store
+                .values()
+                .find(|user| user.email == email)
+                .ok_or(RepositoryError::NotFound(0))?;
+            Ok(user.clone())
+        }
+
+        async fn find_or_create_by_identity(
+            &self,
+            identity: ExternalIdentity,
+        ) -> anyhow::Result<User> {
+            let key = (identity.provider, identity.subject);
+            let mut identities = self.identities.write().unwrap();
+            let mut store = self.write_store_ref();
+            if let Some(user) = identities.get(&key).and_then(|id| store.get(id)) {
+                return Ok(user.clone());
+            }
+
+            let user = match store.values().find(|user| user.email == identity.email) {
+                Some(user) => user.clone(),
+                None => {
+                    let id = next_id(&store);
+                    let user = User {
+                        id,
+                        email: identity.email,
+                        role: Role::Member,
+                        password_hash: None,
+                        disabled_at: None,
+                        password_reset_required: false,
+                        guest: false,
+                        feed_token_version: 0,
+                    };
+                    store.insert(id, user.clone());
+                    user
+                }
+            };
+            identities.insert(key, user.id);
+            Ok(user)
+        }
+
+        async fn create_guest(&self, device_token_hash: String) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            let id = next_id(&store);
+            let user = User {
+                id,
+                email: format!("guest-{}@guest.invalid", id),
+                role: Role::Member,
+                password_hash: None,
+                disabled_at: None,
+                password_reset_required: false,
+                guest: true,
+                feed_token_version: 0,
+            };
+            store.insert(id, user.clone());
+            self.device_tokens
+                .write()
+                .unwrap()
+                .insert(device_token_hash, id);
+            Ok(user)
+        }
+
+        async fn find_guest(&self, device_token_hash: &str) -> anyhow::Result<User> {
+            let device_tokens = self.device_tokens.read().unwrap();
+            let user = device_tokens
+                .get(device_token_hash)
+                .and_then(|id| self.read_store_ref().get(id).cloned())
+                .ok_or(RepositoryError::NotFound(0))?;
+            Ok(user)
+        }
+
+        async fn all(&self) -> anyhow::Result<Vec<User>> {
+            let mut users: Vec<User> = self.read_store_ref().values().cloned().collect();
+            users.sort_by_key(|user| user.id);
+            Ok(users)
+        }
+
+        async fn set_disabled(&self, id: i32, disabled: bool) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            let user = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            user.disabled_at = match (disabled, user.disabled_at) {
+                (true, Some(disabled_at)) => Some(disabled_at),
+                (true, None) => Some(Utc::now()),
+                (false, _) => None,
+            };
+            Ok(user.clone())
+        }
+
+        async fn require_password_reset(&self, id: i32) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            let user = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            user.password_reset_required = true;
+            Ok(user.clone())
+        }
+
+        async fn update_password(&self, id: i32, password_hash: String) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            let user = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            user.password_hash = Some(password_hash);
+            user.password_reset_required = false;
+            Ok(user.clone())
+        }
+
+        async fn rotate_feed_token(&self, id: i32) -> anyhow::Result<User> {
+            let mut store = self.write_store_ref();
+            let user = store.get_mut(&id).ok_or(RepositoryError::NotFound(id))?;
+            user.feed_token_version += 1;
+            Ok(user.clone())
+        }
+
+        async fn delete(&self, id: i32) -> anyhow::Result<Vec<String>> {
+            self.write_store_ref()
+                .remove(&id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            self.identities
+                .write()
+                .unwrap()
+                .retain(|_, user_id| *user_id != id);
+            self.device_tokens
+                .write()
+                .unwrap()
+                .retain(|_, user_id| *user_id != id);
+            Ok(vec![])
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn user_crud_scenario() {
+            let repo = UserRepositoryForMemory::new();
+            let payload = CreateUser {
+                email: "user@example.com".to_string(),
+                password_hash: "hash".to_string(),
+            };
+
+            // create
+            let user = repo
+                .create(payload.clone())
+                .await
+                .expect("failed create user");
+            assert_eq!(user.id, 1);
+            assert_eq!(user.role, Role::Member);
+            assert!(repo.create(payload).await.is_err());
+
+            // find
+            let found = repo.find(user.id).await.expect("failed find user");
+            assert_eq!(found, user);
+
+            // find_by_email
+            let found = repo
+                .find_by_email("user@example.com")
+                .await
+                .expect("failed find user by email");
+            assert_eq!(found, user);
+            assert!(repo.find_by_email("none@example.com").await.is_err());
+        }
+
+        #[tokio::test]
+        async fn guest_scenario() {
+            let repo = UserRepositoryForMemory::new();
+            let guest = repo
+                .create_guest("device token hash".to_string())
+                .await
+                .expect("failed create guest");
+            assert!(guest.guest);
+            assert_eq!(guest.password_hash, None);
+            assert_eq!(repo.find_guest("device token hash").await.unwrap(), guest);
+            assert!(repo.find_guest("unknown").await.is_err());
+
+            repo.delete(guest.id).await.expect("failed delete guest");
+            assert!(repo.find_guest("device token hash").await.is_err());
+        }
+
+        #[tokio::test]
+        async fn user_management_scenario() {
+            let repo = UserRepositoryForMemory::new();
+            let user = repo
+                .create(CreateUser {
+                    email: "user@example.com".to_string(),
+                    password_hash: "hash".to_string(),
+                })
+                .await
+                .expect("failed create user");
+
+            // set_disabled
+            let disabled = repo.set_disabled(user.id, true).await.unwrap();
+            assert!(disabled.is_disabled());
+            assert!(!repo
+                .set_disabled(user.id, false)
+                .await
+                .unwrap()
+                .is_disabled());
+
+            // require_password_reset / update_password
+            assert!(
+                repo.require_password_reset(user.id)
+                    .await
+                    .unwrap()
+                    .password_reset_required
+            );
+            let updated = repo
+                .update_password(user.id, "new hash".to_string())
+                .await
+                .unwrap();
+            assert!(!updated.password_reset_required);
+
+            // all / delete
+            assert_eq!(repo.all().await.unwrap(), vec![updated]);
+            repo.delete(user.id).await.expect("failed delete user");
+            assert!(repo.all().await.unwrap().is_empty());
+            assert!(repo.delete(user.id).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn find_or_create_by_identity_scenario() {
+            let repo = UserRepositoryForMemory::new();
+            let user = repo
+                .create(CreateUser {
+                    email: "user@example.com".to_string(),
+                    password_hash: "hash".to_string(),
+                })
+                .await
+                .expect("failed create user");
+
+            // 同じ email のユーザーに紐づく
+            let identity = ExternalIdentity {
+                provider: "google".to_string(),
+                subject: "google-user".to_string(),
+                email: "user@example.com".to_string(),
+            };
+            let linked = repo
+                .find_or_create_by_identity(identity.clone())
+                .await
+                .expect("failed link identity");
+            assert_eq!(linked, user);
+
+            // 知らない email なら新しいユーザーができる
+            let created = repo
+                .find_or_create_by_identity(ExternalIdentity {
+                    provider: "github".to_string(),
+                    subject: "github-user".to_string(),
+                    email: "new@example.com".to_string(),
+                })
+                .await
+                .expect("failed create user by identity");
+            assert_ne!(created.id, user.id);
+            assert_eq!(created.password_hash, None);
+
+            // 2回目は紐づけ済みのユーザーが返る
+            let found = repo
+                .find_or_create_by_identity(identity)
+                .await
+                .expect("failed find user by identity");
+            assert_eq!(found, user);
+        }
+    }
+}
@@ -0,0 +1,872 @@
+use super::{outbox::OutboxEvent, RepositoryError, Scope};
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use validator::{Validate, ValidationError};
+
+// 購読できる event. outbox_events の event と同じ名前
+pub const EVENTS: [&str; 6] = [
+    "todo.created",
+    "todo.updated",
+    "todo.deleted",
+    "label.created",
+    "label.updated",
+    "label.deleted",
+];
+// 一度に取り出す配送の数
+const TAKE_LIMIT: i64 = 100;
+// 取り出した配送は送信中として、この時間が経つまで他の worker に渡さない
+const LEASE_MINUTES: i64 = 10;
+
+// 他人の webhook に触れようとした場合は RepositoryError::Forbidden を返す
+#[async_trait]
+pub trait WebhookRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 登録より前の変更は送らない
+    async fn create(
+        &self,
+        scope: Scope,
+        payload: CreateWebhook,
+        secret: String,
+    ) -> anyhow::Result<Webhook>;
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Webhook>;
+    // scope で自分が登録したものを古い順に返す
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Webhook>>;
+    async fn update(
+        &self,
+        scope: Scope,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook>;
+    // 未配送のものも一緒に消える
+    async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()>;
+    // outbox の relay が渡した todo / label の変更を、登録後に変更を購読している webhook の配送待ちにする
+    // 同じ event を何度渡しても配送は 1 つ. 新しく展開した配送の数を返す
+    async fn enqueue(&self, events: &[OutboxEvent]) -> anyhow::Result<u64>;
+    // 送信時刻の来た配送の attempts を増やして返す. 送信中の配送は返さない
+    async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueDelivery>>;
+    async fn mark_delivered(&self, id: i32, now: DateTime<Utc>) -> anyhow::Result<()>;
+    // retry_at が None なら再試行を諦める
+    async fn mark_failed(
+        &self,
+        id: i32,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Webhook {
+    pub id: i32,
+    pub user_id: i32,
+    pub workspace_id: Option<i32>,
+    pub url: String,
+    // 登録したときのレスポンスでだけ返す
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    fn is_visible_in(&self, scope: Scope) -> bool {
+        self.user_id == scope.user_id && self.workspace_id == scope.workspace_id
+    }
+}
+
+// 送信に必要な webhook の情報を付けた配送
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct DueDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub payload: Value,
+    // 今回の送信も含めた回数
+    pub attempts: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateWebhook {
+    #[validate(url(message = "Invalid url"))]
+    pub url: String,
+    #[serde(default)]
+    #[validate(custom = "validate_events")]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateWebhook {
+    #[validate(url(message = "Invalid url"))]
+    pub url: Option<String>,
+    #[validate(custom = "validate_events")]
+    pub events: Option<Vec<String>>,
+}
+
+fn validate_events(events: &[String]) -> Result<(), ValidationError> {
+    events
+        .iter()
+        .all(|event| EVENTS.contains(&event.as_str()))
+        .then_some(())
+        .ok_or_else(|| ValidationError::new("Unknown event"))
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRepositoryForDb {
+    pool: PgPool,
+}
+
+impl WebhookRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for WebhookRepositoryForDb {
+    #[tracing::instrument(name = "WebhookRepository::create", skip_all)]
+    async fn create(
+        &self,
+        scope: Scope,
+        payload: CreateWebhook,
+        secret: String,
+    ) -> anyhow::Result<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            INSERT INTO webhooks (user_id, workspace_id, url, secret, events)
+            VALUES ( $1, $2, $3, $4, $5 )
+            RETURNING id, user_id, workspace_id, url, secret, events, created_at
+            "#,
+        )
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .bind(payload.url)
+        .bind(secret)
+        .bind(payload.events)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::find", skip_all)]
+    async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT id, user_id, workspace_id, url, secret, events, created_at
+            FROM webhooks WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+        if !webhook.is_visible_in(scope) {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+
+        Ok(webhook)
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::all", skip_all)]
+    async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            r#"
+            SELECT id, user_id, workspace_id, url, secret, events, created_at
+            FROM webhooks
+            WHERE user_id = $1 AND workspace_id IS NOT DISTINCT FROM $2
+            ORDER BY id
+            "#,
+        )
+        .bind(scope.user_id)
+        .bind(scope.workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::update", skip_all)]
+    async fn update(
+        &self,
+        scope: Scope,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> anyhow::Result<Webhook> {
+        let old_webhook = self.find(scope, id).await?;
+        let webhook = sqlx::query_as::<_, Webhook>(
+            r#"
+            UPDATE webhooks SET url = $1, events = $2
+            WHERE id = $3
+            RETURNING id, user_id, workspace_id, url, secret, events, created_at
+            "#,
+        )
+        .bind(payload.url.unwrap_or(old_webhook.url))
+        .bind(payload.events.unwrap_or(old_webhook.events))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::delete", skip_all)]
+    async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()> {
+        self.find(scope, id).await?;
+        sqlx::query("DELETE FROM webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::enqueue", skip_all)]
+    async fn enqueue(&self, events: &[OutboxEvent]) -> anyhow::Result<u64> {
+        // 送り直された event は配送の一意制約で除く. workspace から抜けたユーザーの webhook には送らない
+        let result = sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (webhook_id, outbox_event_id, event, payload)
+            SELECT webhooks.id, events.id, events.event, jsonb_build_object(
+                'id', events.id,
+                'event', events.event,
+                'occurred_at', events.created_at,
+                'data', events.data,
+                'previous', events.previous
+            )
+            FROM jsonb_to_recordset($1) AS events (
+                id BIGINT, event TEXT, user_id INTEGER, workspace_id INTEGER, data JSONB,
+                previous JSONB, created_at TIMESTAMPTZ
+            )
+                INNER JOIN webhooks ON webhooks.created_at <= events.created_at
+            WHERE (cardinality(webhooks.events) = 0 OR events.event = ANY(webhooks.events))
+                AND CASE
+                    WHEN webhooks.workspace_id IS NULL THEN events.workspace_id IS NULL
+                        AND events.user_id = webhooks.user_id
+                    ELSE events.workspace_id = webhooks.workspace_id
+                        AND EXISTS (
+                            SELECT 1 FROM memberships
+                            WHERE memberships.workspace_id = webhooks.workspace_id
+                                AND memberships.user_id = webhooks.user_id
+                        )
+                END
+            ORDER BY events.id, webhooks.id
+            ON CONFLICT (webhook_id, outbox_event_id) DO NOTHING
+            "#,
+        )
+        .bind(serde_json::to_value(events)?)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::take_due", skip_all)]
+    async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueDelivery>> {
+        // 送信中に worker が落ちても、lease が切れたら別の worker が送り直す
+        let deliveries = sqlx::query_as::<_, DueDelivery>(
+            r#"
+            WITH due AS (
+                UPDATE webhook_deliveries
+                SET attempts = attempts + 1, next_attempt_at = $2
+                WHERE id IN (
+                    SELECT id FROM webhook_deliveries
+                    WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= $1
+                    ORDER BY next_attempt_at, id
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, webhook_id, event, payload, attempts
+            )
+            SELECT due.id, due.webhook_id, webhooks.url, webhooks.secret, due.event, due.payload,
+                due.attempts
+            FROM due
+                INNER JOIN webhooks ON webhooks.id = due.webhook_id
+            ORDER BY due.id
+            "#,
+        )
+        .bind(now)
+        .bind(now + Duration::minutes(LEASE_MINUTES))
+        .bind(TAKE_LIMIT)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::mark_delivered", skip_all)]
+    async fn mark_delivered(&self, id: i32, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries SET delivered_at = $1, last_error = NULL
+            WHERE id = $2
+            "#,
+        )
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "WebhookRepository::mark_failed", skip_all)]
+    async fn mark_failed(
+        &self,
+        id: i32,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET last_error = $1,
+                next_attempt_at = COALESCE($2, next_attempt_at),
+                failed_at = CASE WHEN $2::TIMESTAMPTZ IS NULL THEN $3 END
+            WHERE id = $4
+            "#,
+        )
+        .bind(error)
+        .bind(retry_at)
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(id).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use std::env;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = WebhookRepositoryForDb::new(pool.clone());
+        let (user_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( 'webhook_crud_scenario@example.com', 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare user data.");
+        sqlx::query("DELETE FROM webhooks WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .expect("failed to clean up webhooks");
+        let scope = Scope::new(user_id, None);
+        let event = |id: i64, event: &str, user_id: i32, created_at| OutboxEvent {
+            id,
+            event: event.to_string(),
+            entity_id: 1,
+            user_id: Some(user_id),
+            workspace_id: None,
+            data: json!({ "id": 1, "user_id": user_id, "workspace_id": null, "text": "after" }),
+            previous: Some(json!({ "id": 1, "text": "before" })),
+            created_at,
+        };
+        // 登録前の変更は送らない
+        let before_registration =
+            event(1, "todo.created", user_id, Utc::now() - Duration::hours(1));
+
+        // create
+        let all = repo
+            .create(
+                scope,
+                CreateWebhook {
+                    url: "https://example.com/all".to_string(),
+                    events: vec![],
+                },
+                "secret".to_string(),
+            )
+            .await
+            .expect("[create] returned Err");
+        let labels = repo
+            .create(
+                scope,
+                CreateWebhook {
+                    url: "https://example.com/labels".to_string(),
+                    events: vec!["todo.created".to_string()],
+                },
+                "secret".to_string(),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(all.secret, "secret");
+
+        // find / all
+        assert_eq!(repo.find(scope, all.id).await.unwrap(), all);
+        assert!(repo
+            .find(Scope::new(user_id + 1, None), all.id)
+            .await
+            .is_err());
+        assert!(repo
+            .find(Scope::new(user_id, Some(1)), all.id)
+            .await
+            .is_err());
+        let webhooks = repo.all(scope).await.expect("[all] returned Err");
+        assert_eq!(webhooks, vec![all.clone(), labels.clone()]);
+
+        // update
+        let labels = repo
+            .update(
+                scope,
+                labels.id,
+                UpdateWebhook {
+                    url: None,
+                    events: Some(vec!["label.deleted".to_string()]),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(labels.url, "https://example.com/labels");
+        assert_eq!(labels.events, vec!["label.deleted"]);
+
+        // enqueue は購読している event だけ、自分の scope の変更だけを展開する
+        // outbox の id はテストごとに重ならないよう、webhook の id から作る
+        let base = i64::from(all.id) * 10;
+        let now = Utc::now();
+        let events = vec![
+            OutboxEvent {
+                id: base,
+                ..before_registration
+            },
+            event(base + 1, "todo.updated", user_id, now),
+            event(base + 2, "todo.updated", user_id + 1, now),
+        ];
+        assert_eq!(
+            repo.enqueue(&events).await.expect("[enqueue] returned Err"),
+            1
+        );
+        // relay が送り直しても二重にしない
+        assert_eq!(
+            repo.enqueue(&events).await.expect("[enqueue] returned Err"),
+            0
+        );
+
+        // take_due
+        let now = Utc::now();
+        let deliveries = repo.take_due(now).await.expect("[take_due] returned Err");
+        let deliveries: Vec<DueDelivery> = deliveries
+            .into_iter()
+            .filter(|delivery| delivery.webhook_id == all.id || delivery.webhook_id == labels.id)
+            .collect();
+        assert_eq!(deliveries.len(), 1);
+        let delivery = &deliveries[0];
+        assert_eq!(delivery.webhook_id, all.id);
+        assert_eq!(delivery.url, "https://example.com/all");
+        assert_eq!(delivery.secret, "secret");
+        assert_eq!(delivery.event, "todo.updated");
+        assert_eq!(delivery.payload["id"], base + 1);
+        assert_eq!(delivery.payload["data"]["text"], "after");
+        assert_eq!(delivery.payload["previous"]["text"], "before");
+        assert_eq!(delivery.attempts, 1);
+        // 送信中のものは返さない
+        let taken = repo.take_due(now).await.expect("[take_due] returned Err");
+        assert!(taken.iter().all(|taken| taken.id != delivery.id));
+
+        // mark_failed で再試行の時刻が来たらもう一度返す
+        repo.mark_failed(delivery.id, "503", Some(now + Duration::minutes(1)), now)
+            .await
+            .expect("[mark_failed] returned Err");
+        let taken = repo.take_due(now).await.expect("[take_due] returned Err");
+        assert!(taken.iter().all(|taken| taken.id != delivery.id));
+        let taken = repo
+            .take_due(now + Duration::minutes(1))
+            .await
+            .expect("[take_due] returned Err");
+        let retried = taken
+            .iter()
+            .find(|taken| taken.id == delivery.id)
+            .expect("failed delivery is not retried");
+        assert_eq!(retried.attempts, 2);
+
+        // mark_delivered したものは返さない
+        repo.mark_delivered(delivery.id, now)
+            .await
+            .expect("[mark_delivered] returned Err");
+        let taken = repo
+            .take_due(now + Duration::hours(1))
+            .await
+            .expect("[take_due] returned Err");
+        assert!(taken.iter().all(|taken| taken.id != delivery.id));
+
+        // delete
+        assert!(repo
+            .delete(Scope::new(user_id + 1, None), all.id)
+            .await
+            .is_err());
+        repo.delete(scope, all.id)
+            .await
+            .expect("[delete] returned Err");
+        repo.delete(scope, labels.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repo.find(scope, all.id).await.is_err());
+        assert!(repo.all(scope).await.unwrap().is_empty());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Delivery {
+        pub id: i32,
+        pub webhook_id: i32,
+        pub outbox_event_id: i64,
+        pub event: String,
+        pub payload: Value,
+        pub attempts: i32,
+        pub next_attempt_at: DateTime<Utc>,
+        pub delivered_at: Option<DateTime<Utc>>,
+        pub failed_at: Option<DateTime<Utc>>,
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Store {
+        webhooks: Vec<Webhook>,
+        deliveries: Vec<Delivery>,
+    }
+
+    // workspace のメンバーかどうかは確認しない
+    #[derive(Debug, Clone, Default)]
+    pub struct WebhookRepositoryForMemory {
+        store: Arc<RwLock<Store>>,
+    }
+
+    impl WebhookRepositoryForMemory {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn deliveries(&self) -> Vec<Delivery> {
+            self.store.read().unwrap().deliveries.clone()
+        }
+    }
+
+    #[async_trait]
+    impl WebhookRepository for WebhookRepositoryForMemory {
+        async fn create(
+            &self,
+            scope: Scope,
+            payload: CreateWebhook,
+            secret: String,
+        ) -> anyhow::Result<Webhook> {
+            let mut store = self.store.write().unwrap();
+            let webhook = Webhook {
+                id: store
+                    .webhooks
+                    .iter()
+                    .map(|webhook| webhook.id)
+                    .max()
+                    .unwrap_or(0)
+                    + 1,
+                user_id: scope.user_id,
+                workspace_id: scope.workspace_id,
+                url: payload.url,
+                secret,
+                events: payload.events,
+                created_at: Utc::now(),
+            };
+            store.webhooks.push(webhook.clone());
+            Ok(webhook)
+        }
+
+        async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Webhook> {
+            let store = self.store.read().unwrap();
+            let webhook = store
+                .webhooks
+                .iter()
+                .find(|webhook| webhook.id == id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            if !webhook.is_visible_in(scope) {
+                return Err(RepositoryError::Forbidden(id).into());
+            }
+            Ok(webhook.clone())
+        }
+
+        async fn all(&self, scope: Scope) -> anyhow::Result<Vec<Webhook>> {
+            Ok(self
+                .store
+                .read()
+                .unwrap()
+                .webhooks
+                .iter()
+                .filter(|webhook| webhook.is_visible_in(scope))
+                .cloned()
+                .collect())
+        }
+
+        async fn update(
+            &self,
+            scope: Scope,
+            id: i32,
+            payload: UpdateWebhook,
+        ) -> anyhow::Result<Webhook> {
+            let old_webhook = self.find(scope, id).await?;
+            let webhook = Webhook {
+                url: payload.url.unwrap_or(old_webhook.url),
+                events: payload.events.unwrap_or(old_webhook.events),
+                ..old_webhook
+            };
+            let mut store = self.store.write().unwrap();
+            if let Some(stored) = store.webhooks.iter_mut().find(|webhook| webhook.id == id) {
+                *stored = webhook.clone();
+            }
+            Ok(webhook)
+        }
+
+        async fn delete(&self, scope: Scope, id: i32) -> anyhow::Result<()> {
+            self.find(scope, id).await?;
+            let mut store = self.store.write().unwrap();
+            store.webhooks.retain(|webhook| webhook.id != id);
+            store
+                .deliveries
+                .retain(|delivery| delivery.webhook_id != id);
+            Ok(())
+        }
+
+        async fn enqueue(&self, events: &[OutboxEvent]) -> anyhow::Result<u64> {
+            let mut store = self.store.write().unwrap();
+            let Store {
+                webhooks,
+                deliveries,
+            } = &mut *store;
+            let mut enqueued = 0;
+            for event in events.iter() {
+                for webhook in webhooks.iter() {
+                    let in_scope = match webhook.workspace_id {
+                        Some(_) => event.workspace_id == webhook.workspace_id,
+                        None => {
+                            event.workspace_id.is_none() && event.user_id == Some(webhook.user_id)
+                        }
+                    };
+                    let subscribed =
+                        webhook.events.is_empty() || webhook.events.contains(&event.event);
+                    let enqueued_before = deliveries.iter().any(|delivery| {
+                        delivery.webhook_id == webhook.id && delivery.outbox_event_id == event.id
+                    });
+                    if webhook.created_at > event.created_at
+                        || !in_scope
+                        || !subscribed
+                        || enqueued_before
+                    {
+                        continue;
+                    }
+                    deliveries.push(Delivery {
+                        id: deliveries.len() as i32 + 1,
+                        webhook_id: webhook.id,
+                        outbox_event_id: event.id,
+                        event: event.event.clone(),
+                        payload: serde_json::json!({
+                            "id": event.id,
+                            "event": event.event,
+                            "occurred_at": event.created_at,
+                            "data": event.data,
+                            "previous": event.previous,
+                        }),
+                        attempts: 0,
+                        next_attempt_at: event.created_at,
+                        delivered_at: None,
+                        failed_at: None,
+                        last_error: None,
+                    });
+                    enqueued += 1;
+                }
+            }
+            Ok(enqueued)
+        }
+
+        async fn take_due(&self, now: DateTime<Utc>) -> anyhow::Result<Vec<DueDelivery>> {
+            let mut store = self.store.write().unwrap();
+            let Store {
+                webhooks,
+                deliveries,
+            } = &mut *store;
+            let mut due = vec![];
+            for delivery in deliveries.iter_mut() {
+                if delivery.delivered_at.is_some()
+                    || delivery.failed_at.is_some()
+                    || delivery.next_attempt_at > now
+                {
+                    continue;
+                }
+                let Some(webhook) = webhooks
+                    .iter()
+                    .find(|webhook| webhook.id == delivery.webhook_id)
+                else {
+                    continue;
+                };
+                delivery.attempts += 1;
+                delivery.next_attempt_at = now + Duration::minutes(LEASE_MINUTES);
+                due.push(DueDelivery {
+                    id: delivery.id,
+                    webhook_id: webhook.id,
+                    url: webhook.url.clone(),
+                    secret: webhook.secret.clone(),
+                    event: delivery.event.clone(),
+                    payload: delivery.payload.clone(),
+                    attempts: delivery.attempts,
+                });
+            }
+            Ok(due)
+        }
+
+        async fn mark_delivered(&self, id: i32, now: DateTime<Utc>) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            let delivery = store
+                .deliveries
+                .iter_mut()
+                .find(|delivery| delivery.id == id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            delivery.delivered_at = Some(now);
+            delivery.last_error = None;
+            Ok(())
+        }
+
+        async fn mark_failed(
+            &self,
+            id: i32,
+            error: &str,
+            retry_at: Option<DateTime<Utc>>,
+            now: DateTime<Utc>,
+        ) -> anyhow::Result<()> {
+            let mut store = self.store.write().unwrap();
+            let delivery = store
+                .deliveries
+                .iter_mut()
+                .find(|delivery| delivery.id == id)
+                .ok_or(RepositoryError::NotFound(id))?;
+            delivery.last_error = Some(error.to_string());
+            match retry_at {
+                Some(retry_at) => delivery.next_attempt_at = retry_at,
+                None => delivery.failed_at = Some(now),
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use serde_json::json;
+
+        #[tokio::test]
+        async fn webhook_scenario() {
+            let repo = WebhookRepositoryForMemory::new();
+            let scope = Scope::personal(1);
+            let event = |id: i64, event: &str, user_id: i32| OutboxEvent {
+                id,
+                event: event.to_string(),
+                entity_id: 1,
+                user_id: Some(user_id),
+                workspace_id: None,
+                data: json!({ "id": 1, "user_id": user_id, "workspace_id": null }),
+                previous: None,
+                created_at: Utc::now(),
+            };
+            let before_registration = OutboxEvent {
+                created_at: Utc::now() - Duration::seconds(1),
+                ..event(1, "todo.created", 1)
+            };
+            let webhook = repo
+                .create(
+                    scope,
+                    CreateWebhook {
+                        url: "https://example.com/hook".to_string(),
+                        events: vec!["todo.created".to_string()],
+                    },
+                    "secret".to_string(),
+                )
+                .await
+                .expect("failed create webhook");
+
+            // 他人の webhook は見られない
+            assert_eq!(repo.all(scope).await.unwrap(), vec![webhook.clone()]);
+            assert!(repo.all(Scope::personal(2)).await.unwrap().is_empty());
+            assert!(repo.find(Scope::personal(2), webhook.id).await.is_err());
+
+            // 登録後の、自分の scope で購読している変更だけ. 同じ event は二重にしない
+            let events = [
+                before_registration,
+                event(2, "todo.created", 1),
+                event(3, "todo.created", 2),
+                event(4, "label.created", 1),
+            ];
+            assert_eq!(repo.enqueue(&events).await.unwrap(), 1);
+            assert_eq!(repo.enqueue(&events).await.unwrap(), 0);
+
+            let now = Utc::now();
+            let due = repo.take_due(now).await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].event, "todo.created");
+            assert_eq!(due[0].payload["id"], 2);
+            assert_eq!(due[0].attempts, 1);
+            assert!(repo.take_due(now).await.unwrap().is_empty());
+
+            repo.mark_failed(due[0].id, "timeout", None, now)
+                .await
+                .unwrap();
+            assert!(repo
+                .take_due(now + Duration::days(1))
+                .await
+                .unwrap()
+                .is_empty());
+            assert_eq!(repo.deliveries()[0].last_error.as_deref(), Some("timeout"));
+
+            repo.delete(scope, webhook.id).await.unwrap();
+            assert!(repo.deliveries().is_empty());
+        }
+
+        #[test]
+        fn should_validate_events() {
+            let payload = |events: &[&str]| CreateWebhook {
+                url: "https://example.com/hook".to_string(),
+                events: events.iter().map(|event| event.to_string()).collect(),
+            };
+            assert!(payload(&[]).validate().is_ok());
+            assert!(payload(&["todo.created", "label.deleted"])
+                .validate()
+                .is_ok());
+            assert!(payload(&["todo.archived"]).validate().is_err());
+            assert!(CreateWebhook {
+                url: "not a url".to_string(),
+                ..payload(&[])
+            }
+            .validate()
+            .is_err());
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use super::{cache::RepositoryCache, RepositoryError};
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use validator::Validate;
+
+#[async_trait]
+pub trait WorkspaceRepository: std::marker::Send + std::marker::Sync + 'static {
+    // 作成したユーザーが owner として最初のメンバーになる
+    async fn create(&self, user_id: i32, payload: CreateWorkspace) -> anyhow::Result<Workspace>;
+    // メンバーでない workspace は RepositoryError::Forbidden を返す
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace>;
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>>;
+    // 変更・削除は owner だけができる
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWorkspace,
+    ) -> anyhow::Result<Workspace>;
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()>;
+    async fn find_membership(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Membership>;
+    // 既にメンバーなら今の membership をそのまま返す
+    async fn add_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Membership>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "membership_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum MembershipRole {
+    Owner,
+    Member,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+    pub owner_id: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct Membership {
+    pub workspace_id: i32,
+    pub user_id: i32,
+    pub role: MembershipRole,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct CreateWorkspace {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
+pub struct UpdateWorkspace {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over name length"))]
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkspaceRepositoryForDb {
+    pool: PgPool,
+    // 消したものに付いていた todo / label のキャッシュを捨てる
+    cache: Option<RepositoryCache>,
+}
+
+impl WorkspaceRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool, cache: None }
+    }
+
+    pub fn with_cache(self, cache: Option<RepositoryCache>) -> Self {
+        Self { cache, ..self }
+    }
+
+    // owner 以外の変更を弾く
+    async fn find_owned(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace> {
+        let workspace = self.find(user_id, id).await?;
+        if workspace.owner_id != user_id {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+        Ok(workspace)
+    }
+}
+
+#[async_trait]
+impl WorkspaceRepository for WorkspaceRepositoryForDb {
+    #[tracing::instrument(name = "WorkspaceRepository::create", skip_all)]
+    async fn create(&self, user_id: i32, payload: CreateWorkspace) -> anyhow::Result<Workspace> {
+        let mut tx = self.pool.begin().await?;
+
+        let workspace = sqlx::query_as::<_, Workspace>(
+            r#"
+            INSERT INTO workspaces (name, owner_id)
+            VALUES ( $1, $2 )
+            RETURNING id, name, owner_id
+            "#,
+        )
+        .bind(payload.name)
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO memberships (workspace_id, user_id, role)
+            VALUES ( $1, $2, 'owner' )
+            "#,
+        )
+        .bind(workspace.id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(workspace)
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::find", skip_all)]
+    async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace> {
+        let workspace = sqlx::query_as::<_, Workspace>(
+            r#"
+            SELECT id, name, owner_id FROM workspaces WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(id))?;
+        self.find_membership(id, user_id)
+            .await
+            .map_err(|_| RepositoryError::Forbidden(id))?;
+
+        Ok(workspace)
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::all", skip_all)]
+    async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>> {
+        let workspaces = sqlx::query_as::<_, Workspace>(
+            r#"
+            SELECT workspaces.id, workspaces.name, workspaces.owner_id
+            FROM workspaces
+                INNER JOIN memberships ON memberships.workspace_id = workspaces.id
+            WHERE memberships.user_id = $1
+            ORDER BY workspaces.id ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(workspaces)
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::update", skip_all)]
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWorkspace,
+    ) -> anyhow::Result<Workspace> {
+        let old_workspace = self.find_owned(user_id, id).await?;
+        let workspace = sqlx::query_as::<_, Workspace>(
+            r#"
+            UPDATE workspaces SET name = $1
+            WHERE id = $2
+            RETURNING id, name, owner_id
+            "#,
+        )
+        .bind(payload.name.unwrap_or(old_workspace.name))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(workspace)
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::delete", skip_all)]
+    async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+        self.find_owned(user_id, id).await?;
+        let mut tx = self.pool.begin().await?;
+
+        // workspace の todo / label も一緒に消す. memberships は ON DELETE CASCADE で消える
+        sqlx::query(
+            r#"
+            DELETE FROM todo_labels
+            WHERE todo_id IN (SELECT id FROM todos WHERE workspace_id = $1)
+                OR label_id IN (SELECT id FROM labels WHERE workspace_id = $1)
+            "#,
+        )
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("DELETE FROM todos WHERE workspace_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM projects WHERE workspace_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM templates WHERE workspace_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM labels WHERE workspace_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        if let Some(cache) = &self.cache {
+            cache.invalidate_all().await;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::find_membership", skip_all)]
+    async fn find_membership(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Membership> {
+        let membership = sqlx::query_as::<_, Membership>(
+            r#"
+            SELECT workspace_id, user_id, role FROM memberships
+            WHERE workspace_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(workspace_id))?;
+
+        Ok(membership)
+    }
+
+    #[tracing::instrument(name = "WorkspaceRepository::add_member", skip_all)]
+    async fn add_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Membership> {
+        sqlx::query(
+            r#"
+            INSERT INTO memberships (workspace_id, user_id, role)
+            VALUES ( $1, $2, 'member' )
+            ON CONFLICT (workspace_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        self.find_membership(workspace_id, user_id).await
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use super::*;
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+    use std::env;
+
+    async fn prepare_user(pool: &PgPool, email: &str) -> i32 {
+        let (id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (email, password_hash)
+            VALUES ( $1, 'hash' )
+            ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
+            "#,
+        )
+        .bind(email)
+        .fetch_one(pool)
+        .await
+        .expect("failed to prepare user data.");
+        id
+    }
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = env::var("DATABASE_URL").expect("undefined env: [DATABASE_URL]");
+        let pool = PgPool::connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("cannot connect database: [{}]", database_url));
+        let repo = WorkspaceRepositoryForDb::new(pool.clone());
+        let owner_id = prepare_user(&pool, "workspace_crud_scenario@example.com").await;
+        let other_id = prepare_user(&pool, "workspace_crud_scenario_other@example.com").await;
+
+        // create
+        let workspace = repo
+            .create(owner_id, CreateWorkspace::new("workspace".to_string()))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(workspace.owner_id, owner_id);
+        let membership = repo
+            .find_membership(workspace.id, owner_id)
+            .await
+            .expect("[find_membership] returned Err");
+        assert_eq!(membership.role, MembershipRole::Owner);
+
+        // find / all
+        assert_eq!(
+            repo.find(owner_id, workspace.id)
+                .await
+                .expect("[find] returned Err"),
+            workspace
+        );
+        assert!(repo.find(other_id, workspace.id).await.is_err());
+        repo.add_member(workspace.id, other_id)
+            .await
+            .expect("[add_member] returned Err");
+        assert!(repo.find(other_id, workspace.id).await.is_ok());
+        let workspaces = repo.all(owner_id).await.expect("[all] returned Err");
+        assert!(workspaces.contains(&workspace));
+
+        // update
+        assert!(repo
+            .update(other_id, workspace.id, UpdateWorkspace::new("other"))
+            .await
+            .is_err());
+        let updated = repo
+            .update(owner_id, workspace.id, UpdateWorkspace::new("updated"))
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(updated.name, "updated");
+
+        // delete で workspace の todo も消える
+        let (todo_id,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO todos (text, user_id, workspace_id)
+            VALUES ( 'workspace todo', $1, $2 )
+            RETURNING id
+            "#,
+        )
+        .bind(owner_id)
+        .bind(workspace.id)
+        .fetch_one(&pool)
+        .await
+        .expect("failed to prepare todo data.");
+        repo.delete(owner_id, workspace.id)
+            .await
+            .expect("[delete] returned Err");
+        let rows = sqlx::query("SELECT * FROM todos WHERE id = $1")
+            .bind(todo_id)
+            .fetch_all(&pool)
+            .await
+            .expect("[delete] todos fetch error");
+        assert!(rows.is_empty());
+        assert!(repo.find_membership(workspace.id, owner_id).await.is_err());
+    }
+}
+
+#[cfg(any(test, feature = "memory"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod memory {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    use super::*;
+
+    impl CreateWorkspace {
+        pub fn new(name: String) -> Self {
+            Self { name }
+        }
+    }
+
+    impl UpdateWorkspace {
+        pub fn new(name: &str) -> Self {
+            Self {
+                name: Some(name.to_string()),
+            }
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct WorkspaceDatas {
+        workspaces: HashMap<i32, Workspace>,
+        memberships: Vec<Membership>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct WorkspaceRepositoryForMemory {
+        store: Arc<RwLock<WorkspaceDatas>>,
+    }
+
+    impl WorkspaceRepositoryForMemory {
+        pub fn new() -> Self {
+            WorkspaceRepositoryForMemory {
+                store: Arc::default(),
+            }
+        }
+
+        fn write_store_ref(&self) -> RwLockWriteGuard<'_, WorkspaceDatas> {
+            self.store.write().unwrap()
+        }
+
+        fn read_store_ref(&self) -> RwLockReadGuard<'_, WorkspaceDatas> {
+            self.store.read().unwrap()
+        }
+    }
+
+    impl WorkspaceDatas {
+        fn is_member(&self, workspace_id: i32, user_id: i32) -> bool {
+            self.memberships
+                .iter()
+                .any(|m| m.workspace_id == workspace_id && m.user_id == user_id)
+        }
+
+        fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace> {
+            let workspace = self
+                .workspaces
+                .get(&id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(id))?;
+            if !self.is_member(id, user_id) {
+                return Err(RepositoryError::Forbidden(id).into());
+            }
+            Ok(workspace)
+        }
+
+        fn find_owned(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace> {
+            let workspace = self.find(user_id, id)?;
+            if workspace.owner_id != user_id {
+                return Err(RepositoryError::Forbidden(id).into());
+            }
+            Ok(workspace)
+        }
+    }
+
+    #[async_trait]
+    impl WorkspaceRepository for WorkspaceRepositoryForMemory {
+        async fn create(
+            &self,
+            user_id: i32,
+            payload: CreateWorkspace,
+        ) -> anyhow::Result<Workspace> {
+            let mut store = self.write_store_ref();
+            let id = store.workspaces.keys().max().copied().unwrap_or(0) + 1;
+            let workspace = Workspace {
+                id,
+                name: payload.name,
+                owner_id: user_id,
+            };
+            store.workspaces.insert(id, workspace.clone());
+            store.memberships.push(Membership {
+                workspace_id: id,
+                user_id,
+                role: MembershipRole::Owner,
+            });
+            Ok(workspace)
+        }
+
+        async fn find(&self, user_id: i32, id: i32) -> anyhow::Result<Workspace> {
+            self.read_store_ref().find(user_id, id)
+        }
+
+        async fn all(&self, user_id: i32) -> anyhow::Result<Vec<Workspace>> {
+            let store = self.read_store_ref();
+            let mut workspaces: Vec<Workspace> = store
+                .workspaces
+                .values()
+                .filter(|workspace| store.is_member(workspace.id, user_id))
+                .cloned()
+                .collect();
+            workspaces.sort_by_key(|workspace| workspace.id);
+            Ok(workspaces)
+        }
+
+        async fn update(
+            &self,
+            user_id: i32,
+            id: i32,
+            payload: UpdateWorkspace,
+        ) -> anyhow::Result<Workspace> {
+            let mut store = self.write_store_ref();
+            let mut workspace = store.find_owned(user_id, id)?;
+            if let Some(name) = payload.name {
+                workspace.name = name;
+            }
+            store.workspaces.insert(id, workspace.clone());
+            Ok(workspace)
+        }
+
+        async fn delete(&self, user_id: i32, id: i32) -> anyhow::Result<()> {
+            let mut store = self.write_store_ref();
+            store.find_owned(user_id, id)?;
+            store.workspaces.remove(&id);
+            store.memberships.retain(|m| m.workspace_id != id);
+            Ok(())
+        }
+
+        async fn find_membership(
+            &self,
+            workspace_id: i32,
+            user_id: i32,
+        ) -> anyhow::Result<Membership> {
+            let store = self.read_store_ref();
+            let membership = store
+                .memberships
+                .iter()
+                .find(|m| m.workspace_id == workspace_id && m.user_id == user_id)
+                .cloned()
+                .ok_or(RepositoryError::NotFound(workspace_id))?;
+            Ok(membership)
+        }
+
+        async fn add_member(&self, workspace_id: i32, user_id: i32) -> anyhow::Result<Membership> {
+            let mut store = self.write_store_ref();
+            if !store.workspaces.contains_key(&workspace_id) {
+                return Err(RepositoryError::NotFound(workspace_id).into());
+            }
+            if let Some(membership) = store
+                .memberships
+                .iter()
+                .find(|m| m.workspace_id == workspace_id && m.user_id == user_id)
+            {
+                return Ok(membership.clone());
+            }
+            let membership = Membership {
+                workspace_id,
+                user_id,
+                role: MembershipRole::Member,
+            };
+            store.memberships.push(membership.clone());
+            Ok(membership)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[tokio::test]
+        async fn workspace_crud_scenario() {
+            let repo = WorkspaceRepositoryForMemory::new();
+            let owner_id = 1;
+            let member_id = 2;
+
+            // create
+            let workspace = repo
+                .create(owner_id, CreateWorkspace::new("workspace".to_string()))
+                .await
+                .expect("failed create workspace");
+            assert_eq!(
+                Workspace {
+                    id: 1,
+                    name: "workspace".to_string(),
+                    owner_id,
+                },
+                workspace
+            );
+
+            // メンバー以外からは見えない
+            assert!(repo.find(member_id, workspace.id).await.is_err());
+            assert!(repo.all(member_id).await.unwrap().is_empty());
+            let membership = repo
+                .add_member(workspace.id, member_id)
+                .await
+                .expect("failed add member");
+            assert_eq!(membership.role, MembershipRole::Member);
+            // 二重に追加しても owner が member に変わったりしない
+            let membership = repo.add_member(workspace.id, owner_id).await.unwrap();
+            assert_eq!(membership.role, MembershipRole::Owner);
+            assert_eq!(repo.find(member_id, workspace.id).await.unwrap(), workspace);
+            assert_eq!(repo.all(member_id).await.unwrap(), vec![workspace.clone()]);
+
+            // update / delete は owner だけ
+            assert!(repo
+                .update(member_id, workspace.id, UpdateWorkspace::new("member"))
+                .await
+                .is_err());
+            let workspace = repo
+                .update(owner_id, workspace.id, UpdateWorkspace::new("updated"))
+                .await
+                .expect("failed update workspace");
+            assert_eq!(workspace.name, "updated");
+            assert!(repo.delete(member_id, workspace.id).await.is_err());
+            repo.delete(owner_id, workspace.id)
+                .await
+                .expect("failed delete workspace");
+            assert!(repo.find_membership(workspace.id, member_id).await.is_err());
+        }
+    }
+}
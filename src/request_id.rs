@@ -0,0 +1,83 @@
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use rand::{distributions::Alphanumeric, Rng};
+
+// 問い合わせのときにこの値を聞けば、ログからそのリクエストを探せる
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// これより長い値や、表示できない文字を含む値は受け取らずに作り直す
+const MAX_REQUEST_ID_LEN: usize = 128;
+const GENERATED_LEN: usize = 26;
+
+// リクエストの extensions に入れる. 後ろの middleware や handler はここから読む
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    // ロードバランサーやゲートウェイが付けた値があればそのまま使う
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| is_valid(value))
+            .map(|value| Self(value.to_string()))
+            .unwrap_or_else(Self::generate)
+    }
+
+    pub fn generate() -> Self {
+        let id = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_LEN)
+            .map(char::from)
+            .collect();
+        Self(id)
+    }
+}
+
+fn is_valid(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// 全てのレスポンスに X-Request-Id を付ける. エラーのレスポンスも同じ
+// trace_request より外側に置くので、リクエストの span にも記録される
+pub async fn assign_request_id<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let request_id = RequestId::from_request(&req);
+    req.extensions_mut().insert(request_id.clone());
+    let mut res = next.run(req).await;
+    if res.status().is_server_error() {
+        tracing::error!(request_id = %request_id.0, "request failed with {}", res.status());
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_keep_valid_request_ids() {
+        let request = |value: &str| {
+            Request::builder()
+                .header(REQUEST_ID_HEADER, value)
+                .body(())
+                .unwrap()
+        };
+        let id = RequestId::from_request(&request("req-123"));
+        assert_eq!(id, RequestId("req-123".to_string()));
+
+        for invalid in ["", "with space", &"a".repeat(MAX_REQUEST_ID_LEN + 1)] {
+            let id = RequestId::from_request(&request(invalid));
+            assert_eq!(id.0.len(), GENERATED_LEN, "{}", invalid);
+        }
+        let id = RequestId::from_request(&Request::new(()));
+        assert_eq!(id.0.len(), GENERATED_LEN);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::request_id::RequestId;
+use axum::{
+    body::{boxed, Empty, Full, HttpBody},
+    extract::{Extension, OriginalUri},
+    http::{
+        header::{ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN},
+        HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+// create_app のルートと、それぞれで受け付けるメソッド. HEAD と OPTIONS はここに書かなくても受け付ける
+// axum は layer を重ねたルートの 405 に Allow を付けないので、この表から組み立てる
+pub const ROUTES: [(&str, &[&str]); 76] = [
+    ("/", &["GET"]),
+    ("/healthz", &["GET"]),
+    ("/readyz", &["GET"]),
+    ("/auth/register", &["POST"]),
+    ("/auth/login", &["POST"]),
+    ("/auth/refresh", &["POST"]),
+    ("/auth/guest", &["POST"]),
+    ("/auth/password", &["POST"]),
+    ("/auth/:provider", &["GET"]),
+    ("/auth/:provider/callback", &["GET"]),
+    ("/todos/calendar.ics", &["GET"]),
+    ("/todos/feed.atom", &["GET"]),
+    ("/batch", &["POST"]),
+    ("/auth/me", &["GET"]),
+    ("/auth/logout-all", &["POST"]),
+    ("/auth/claim", &["POST"]),
+    ("/me", &["DELETE"]),
+    ("/me/export", &["GET"]),
+    ("/me/feeds", &["GET", "DELETE"]),
+    ("/export", &["GET"]),
+    ("/import", &["POST"]),
+    ("/todos", &["GET", "POST", "DELETE"]),
+    ("/todos/:id", &["GET", "PATCH", "DELETE"]),
+    ("/todos/bulk", &["POST"]),
+    ("/todos/complete", &["POST"]),
+    ("/todos/labels", &["POST"]),
+    ("/todos/search", &["GET"]),
+    ("/todos/undo", &["POST"]),
+    ("/todos/from-template/:id", &["POST"]),
+    ("/todos/:id/archive", &["POST"]),
+    ("/todos/:id/unarchive", &["POST"]),
+    ("/todos/:id/pin", &["POST"]),
+    ("/todos/:id/unpin", &["POST"]),
+    ("/todos/:id/snooze", &["POST"]),
+    ("/todos/:id/unsnooze", &["POST"]),
+    ("/todos/:id/move", &["PATCH"]),
+    ("/todos/:id/assign", &["PATCH"]),
+    ("/todos/:id/duplicate", &["POST"]),
+    ("/todos/:id/history", &["GET"]),
+    ("/todos/:id/revert/:revision_id", &["POST"]),
+    ("/todos/:id/share", &["POST"]),
+    ("/todos/:id/subtasks", &["GET"]),
+    ("/todos/:id/reminders", &["GET", "POST"]),
+    ("/todos/:id/reminders/:reminder_id", &["DELETE"]),
+    ("/todos/:id/attachments", &["GET", "POST"]),
+    ("/todos/:id/attachments/:attachment_id", &["GET", "DELETE"]),
+    ("/projects", &["GET", "POST"]),
+    ("/projects/:id", &["GET", "PATCH", "DELETE"]),
+    ("/projects/:id/todos", &["GET"]),
+    ("/templates", &["GET", "POST"]),
+    ("/templates/:id", &["GET", "PATCH", "DELETE"]),
+    ("/labels", &["GET", "POST"]),
+    ("/labels/:id", &["GET", "PATCH", "DELETE"]),
+    ("/labels/user/:user_id", &["GET"]),
+    ("/labels/stats", &["GET"]),
+    ("/labels/tree", &["GET"]),
+    ("/labels/reorder", &["PATCH"]),
+    ("/labels/:id/todos", &["GET"]),
+    ("/labels/:id/archive", &["POST"]),
+    ("/labels/:id/unarchive", &["POST"]),
+    ("/admin/todos", &["GET"]),
+    ("/admin/labels/:id", &["DELETE"]),
+    ("/admin/users", &["GET"]),
+    ("/admin/users/:id", &["DELETE"]),
+    ("/admin/users/:id/disable", &["POST"]),
+    ("/admin/users/:id/enable", &["POST"]),
+    ("/admin/users/:id/password-reset", &["POST"]),
+    ("/audit", &["GET"]),
+    ("/webhooks", &["GET", "POST"]),
+    ("/webhooks/:id", &["GET", "PATCH", "DELETE"]),
+    ("/workspaces", &["GET", "POST"]),
+    ("/workspaces/:workspace_id", &["GET", "PATCH", "DELETE"]),
+    ("/workspaces/:workspace_id/todos", &["GET", "POST"]),
+    ("/workspaces/:workspace_id/labels", &["GET", "POST"]),
+    ("/workspaces/:workspace_id/invitations", &["POST"]),
+    ("/invitations/:token/accept", &["POST"]),
+];
+
+// path に一致する ROUTES のルートと、そのルートで受け付けるメソッド. ルートがなければ None
+// /v1, /v2 の付いたパスも付いていないパスも同じルートとして扱う
+pub fn matched_route(path: &str) -> Option<(&'static str, &'static [&'static str])> {
+    let path = ["/v1", "/v2"]
+        .iter()
+        .filter_map(|prefix| path.strip_prefix(prefix))
+        .find(|rest| rest.len() > 1 && rest.starts_with('/'))
+        .unwrap_or(path);
+    ROUTES
+        .iter()
+        .filter_map(|(route, methods)| Some((specificity(route, path)?, (*route, *methods))))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, route)| route)
+}
+
+// path で受け付けるメソッドを Allow ヘッダーの形で返す. ルートがなければ None
+pub fn allowed_methods(path: &str) -> Option<String> {
+    let (_, methods) = matched_route(path)?;
+    let mut allow = Vec::new();
+    for method in methods.iter() {
+        allow.push(*method);
+        if *method == "GET" {
+            allow.push("HEAD");
+        }
+    }
+    allow.push("OPTIONS");
+    Some(allow.join(", "))
+}
+
+// route が path に一致すれば、セグメントごとに固定の文字列で一致したかを返す
+// axum と同じく /todos/search は /todos/:id より優先する
+fn specificity(route: &str, path: &str) -> Option<Vec<bool>> {
+    let route: Vec<&str> = route.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    if route.len() != path.len() {
+        return None;
+    }
+    route
+        .iter()
+        .zip(path.iter())
+        .map(|(route, path)| match route.strip_prefix(':') {
+            Some(_) if !path.is_empty() => Some(false),
+            Some(_) => None,
+            None if route == path => Some(true),
+            None => None,
+        })
+        .collect()
+}
+
+// GET のルートへの HEAD には本文を除いて正しい Content-Length を返し、
+// CORS の preflight ではない OPTIONS には受け付けるメソッドを Allow で返す
+pub async fn answer_head_and_options<B>(req: Request<B>, next: Next<B>) -> Response {
+    let method = req.method().clone();
+    if method == Method::OPTIONS && !is_preflight(&req) {
+        return match allowed_methods(req.uri().path()) {
+            Some(allow) => (StatusCode::NO_CONTENT, [(ALLOW, allow)]).into_response(),
+            None => {
+                let request_id = req.extensions().get::<RequestId>().cloned();
+                no_route(&method, req.uri().path(), request_id).into_response()
+            }
+        };
+    }
+    let res = next.run(req).await;
+    if method != Method::HEAD {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    if !parts.headers.contains_key(CONTENT_LENGTH) {
+        // ストリームの本文は長さがわからないので読み切って数える
+        let length = match body.size_hint().exact() {
+            Some(length) => length,
+            None => match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes.len() as u64,
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            },
+        };
+        parts
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    Response::from_parts(parts, boxed(Empty::new()))
+}
+
+// ルートがない、またはメソッドを受け付けないときに返す本文
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteError {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+    // X-Request-Id と同じ値. 問い合わせのときに本文だけ貼られても探せるように入れる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl RouteError {
+    pub fn new(status: StatusCode, message: String, request_id: Option<RequestId>) -> Self {
+        Self {
+            status: status.as_u16(),
+            error: status.canonical_reason().unwrap_or_default().to_string(),
+            message,
+            request_id: request_id.map(|request_id| request_id.0),
+        }
+    }
+}
+
+fn no_route(
+    method: &Method,
+    path: &str,
+    request_id: Option<RequestId>,
+) -> (StatusCode, Json<RouteError>) {
+    let message = format!("no route for {} {}", method, path);
+    let error = RouteError::new(StatusCode::NOT_FOUND, message, request_id);
+    (StatusCode::NOT_FOUND, Json(error))
+}
+
+// どのルートにも一致しなかったリクエストへの 404
+// バージョンを付けずに呼ばれた場合も、書き換える前のパスを返す
+pub async fn not_found(
+    method: Method,
+    OriginalUri(uri): OriginalUri,
+    request_id: Option<Extension<RequestId>>,
+) -> impl IntoResponse {
+    no_route(
+        &method,
+        uri.path(),
+        request_id.map(|Extension(request_id)| request_id),
+    )
+}
+
+// axum が返す空の 405 を、受け付けるメソッドの Allow ヘッダーを付けた JSON にする
+// CORS などの layer が付けたヘッダーはそのまま残す
+pub async fn describe_method_not_allowed<B>(req: Request<B>, next: Next<B>) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let res = next.run(req).await;
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+
+    let message = format!("{} is not allowed for {}", method, path);
+    let error = RouteError::new(StatusCode::METHOD_NOT_ALLOWED, message, request_id);
+    let Ok(body) = serde_json::to_vec(&error) else {
+        return res;
+    };
+    let (mut parts, _) = res.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Some(allow) = allowed_methods(&path).and_then(|allow| allow.parse().ok()) {
+        parts.headers.insert(ALLOW, allow);
+    }
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+// preflight は CorsLayer に任せる
+fn is_preflight<B>(req: &Request<B>) -> bool {
+    req.headers().contains_key(ORIGIN) && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_list_allowed_methods() {
+        assert_eq!(
+            allowed_methods("/v1/todos").as_deref(),
+            Some("GET, HEAD, POST, DELETE, OPTIONS")
+        );
+        assert_eq!(
+            allowed_methods("/todos/1").as_deref(),
+            Some("GET, HEAD, PATCH, DELETE, OPTIONS")
+        );
+        // 固定のパスは :id より優先する
+        assert_eq!(
+            allowed_methods("/v2/todos/bulk").as_deref(),
+            Some("POST, OPTIONS")
+        );
+        assert_eq!(
+            allowed_methods("/auth/register").as_deref(),
+            Some("POST, OPTIONS")
+        );
+        assert_eq!(
+            allowed_methods("/auth/github").as_deref(),
+            Some("GET, HEAD, OPTIONS")
+        );
+        assert_eq!(allowed_methods("/").as_deref(), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(allowed_methods("/v1"), None);
+        assert_eq!(allowed_methods("/v1/"), None);
+        assert_eq!(allowed_methods("/v1/nothing"), None);
+        assert_eq!(allowed_methods("/todos/"), None);
+        assert_eq!(allowed_methods("/v3/todos"), None);
+    }
+}
@@ -0,0 +1,705 @@
+use crate::blob_store::BlobStore;
+use crate::config::JobsConfig;
+use crate::cron::CronSchedule;
+use crate::events::EventPublisher;
+use crate::handlers::attachment::remove_blobs;
+use crate::notifier::Notifier;
+use crate::repositories::{
+    audit::{AuditAction, AuditEntity, AuditRepository},
+    idempotency::IdempotencyRepository,
+    outbox::OutboxRepository,
+    reminder::ReminderRepository,
+    todo::{DeletedTodos, Todo, TodoRepository},
+    webhook::WebhookRepository,
+};
+use crate::services::audit::record_job_event;
+use crate::webhook::{self, WebhookSender};
+use chrono::Utc;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+
+// 一定間隔で実行するバックグラウンドタスク
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    interval: Duration,
+}
+
+impl Scheduler {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+
+    pub fn from_config(config: &JobsConfig) -> Self {
+        Self::new(Duration::from_secs(config.interval_secs))
+    }
+
+    // 完了した繰り返し todo から次の todo を作り続ける
+    pub fn spawn_recurrences<T: TodoRepository, A: AuditRepository>(
+        &self,
+        repo: T,
+        audit: A,
+    ) -> JoinHandle<()> {
+        let mut ticker = tokio::time::interval(self.interval);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                materialize_recurrences(&repo, &audit).await;
+            }
+        })
+    }
+
+    // 期限の来た通知を送り続ける
+    pub fn spawn_reminders<R: ReminderRepository, N: Notifier>(
+        &self,
+        repo: R,
+        notifier: N,
+    ) -> JoinHandle<()> {
+        let mut ticker = tokio::time::interval(self.interval);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                dispatch_reminders(&repo, &notifier).await;
+            }
+        })
+    }
+
+    // 配送待ちにした todo / label の変更を webhook に送り続ける
+    pub fn spawn_webhooks<W: WebhookRepository, S: WebhookSender>(
+        &self,
+        repo: W,
+        sender: S,
+    ) -> JoinHandle<()> {
+        let mut ticker = tokio::time::interval(self.interval);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                deliver_webhooks(&repo, &sender).await;
+            }
+        })
+    }
+
+    // outbox に溜まった todo / label の変更を event bus に流し、webhook の配送待ちにし続ける
+    pub fn spawn_outbox_relay<O: OutboxRepository, P: EventPublisher, W: WebhookRepository>(
+        &self,
+        repo: O,
+        bus: P,
+        webhooks: W,
+    ) -> JoinHandle<()> {
+        let mut ticker = tokio::time::interval(self.interval);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                // 溜まっていれば間隔を待たずに続けて流す
+                while relay_outbox(&repo, &bus, &webhooks).await == OUTBOX_BATCH_SIZE as usize {}
+            }
+        })
+    }
+}
+
+// relay が 1 回に取り出す event の数
+const OUTBOX_BATCH_SIZE: i64 = 100;
+
+// 間隔ではなく決まった時刻に動かす保守の job. 式を書いていない job は動かさない
+#[derive(Debug, Clone)]
+pub struct MaintenanceJobs {
+    purge_archived_todos: Option<CronSchedule>,
+    archived_retention: chrono::Duration,
+    purge_idempotency_keys: Option<CronSchedule>,
+    refresh_label_stats: Option<CronSchedule>,
+}
+
+impl MaintenanceJobs {
+    pub fn from_config(config: &JobsConfig) -> Self {
+        Self {
+            purge_archived_todos: JobsConfig::schedule(&config.purge_archived_todos),
+            archived_retention: chrono::Duration::days(config.archived_retention_days.into()),
+            purge_idempotency_keys: JobsConfig::schedule(&config.purge_idempotency_keys),
+            refresh_label_stats: JobsConfig::schedule(&config.refresh_label_stats),
+        }
+    }
+
+    pub fn spawn<T: TodoRepository, A: AuditRepository, I: IdempotencyRepository>(
+        &self,
+        todo: T,
+        audit: A,
+        idempotency: I,
+        blob_store: Arc<dyn BlobStore>,
+    ) -> Vec<JoinHandle<()>> {
+        let mut handles = vec![];
+        let todo = Arc::new(todo);
+        if let Some(schedule) = self.purge_archived_todos.clone() {
+            let todo = todo.clone();
+            let audit = Arc::new(audit);
+            let retention = self.archived_retention;
+            handles.push(spawn_cron("purge_archived_todos", schedule, move || {
+                let todo = todo.clone();
+                let audit = audit.clone();
+                let blob_store = blob_store.clone();
+                async move {
+                    purge_archived_todos(
+                        todo.as_ref(),
+                        audit.as_ref(),
+                        blob_store.as_ref(),
+                        retention,
+                    )
+                    .await;
+                }
+            }));
+        }
+        if let Some(schedule) = self.purge_idempotency_keys.clone() {
+            let idempotency = Arc::new(idempotency);
+            handles.push(spawn_cron("purge_idempotency_keys", schedule, move || {
+                let idempotency = idempotency.clone();
+                async move {
+                    purge_idempotency_keys(idempotency.as_ref()).await;
+                }
+            }));
+        }
+        if let Some(schedule) = self.refresh_label_stats.clone() {
+            handles.push(spawn_cron("refresh_label_stats", schedule, move || {
+                let todo = todo.clone();
+                async move {
+                    refresh_label_stats(todo.as_ref()).await;
+                }
+            }));
+        }
+        handles
+    }
+}
+
+// schedule の時刻が来るたびに job を動かす. job が長引いて時刻を過ぎた回は飛ばす
+pub fn spawn_cron<F, Fut>(name: &'static str, schedule: CronSchedule, job: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        loop {
+            let now = Utc::now();
+            let Some(next) = schedule.next_after(now) else {
+                tracing::warn!("{} is never scheduled, stopping", name);
+                return;
+            };
+            tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+            tracing::debug!("running {}", name);
+            job().await;
+        }
+    })
+}
+
+// 失敗しても次の周期で再試行されるので、ログに残すだけにする
+pub async fn materialize_recurrences<T: TodoRepository, A: AuditRepository>(
+    repo: &T,
+    audit: &A,
+) -> usize {
+    match materialize_with_audit(repo, audit).await {
+        Ok(todos) => {
+            if !todos.is_empty() {
+                tracing::info!("created {} recurring todos", todos.len());
+            }
+            todos.len()
+        }
+        Err(e) => {
+            tracing::error!("failed to materialize recurring todos: {}", e);
+            0
+        }
+    }
+}
+
+// 作った todo は監査ログにも同じ tx で残す. 記録できなければ作らない
+async fn materialize_with_audit<T: TodoRepository, A: AuditRepository>(
+    repo: &T,
+    audit: &A,
+) -> anyhow::Result<Vec<Todo>> {
+    let mut tx = repo.begin().await?;
+    let todos = repo.materialize_recurrences_in(&mut tx, Utc::now()).await?;
+    for todo in &todos {
+        record_job_event(
+            audit,
+            &mut tx,
+            AuditAction::Create,
+            AuditEntity::Todo,
+            todo.id,
+            None,
+            Some(todo),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(todos)
+}
+
+// 消せなかった記録は次の周期で消す
+pub async fn purge_idempotency_keys<I: IdempotencyRepository>(repo: &I) -> u64 {
+    match repo.purge_expired(Utc::now()).await {
+        Ok(purged) => purged,
+        Err(e) => {
+            tracing::error!("failed to purge expired idempotency keys: {}", e);
+            0
+        }
+    }
+}
+
+// 数え直せなかったら、次の回まで前の件数のまま返す
+pub async fn refresh_label_stats<T: TodoRepository>(repo: &T) -> bool {
+    match repo.refresh_label_usage().await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("failed to refresh label stats: {}", e);
+            false
+        }
+    }
+}
+
+// 添付ファイルの中身も消す. 消せなかった中身はログに残すだけにする
+pub async fn purge_archived_todos<T: TodoRepository, A: AuditRepository>(
+    repo: &T,
+    audit: &A,
+    blob_store: &dyn BlobStore,
+    retention: chrono::Duration,
+) -> usize {
+    match purge_with_audit(repo, audit, retention).await {
+        Ok(purged) => {
+            remove_blobs(blob_store, &purged.storage_keys).await;
+            if !purged.todos.is_empty() {
+                tracing::info!("purged {} archived todos", purged.todos.len());
+            }
+            purged.todos.len()
+        }
+        Err(e) => {
+            tracing::error!("failed to purge archived todos: {}", e);
+            0
+        }
+    }
+}
+
+// 消した todo は監査ログにも同じ tx で残す. 中身は commit したあとに呼び出し側で消す
+async fn purge_with_audit<T: TodoRepository, A: AuditRepository>(
+    repo: &T,
+    audit: &A,
+    retention: chrono::Duration,
+) -> anyhow::Result<DeletedTodos> {
+    let mut tx = repo.begin().await?;
+    let purged = repo
+        .purge_archived_in(&mut tx, Utc::now() - retention)
+        .await?;
+    for todo in &purged.todos {
+        record_job_event(
+            audit,
+            &mut tx,
+            AuditAction::Delete,
+            AuditEntity::Todo,
+            todo.id,
+            Some(todo),
+            None,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(purged)
+}
+
+// 通知は取り出した時点で送信済みになるので、送信に失敗しても再送はしない
+pub async fn dispatch_reminders<R: ReminderRepository, N: Notifier>(
+    repo: &R,
+    notifier: &N,
+) -> usize {
+    let reminders = match repo.take_due(Utc::now()).await {
+        Ok(reminders) => reminders,
+        Err(e) => {
+            tracing::error!("failed to take due reminders: {}", e);
+            return 0;
+        }
+    };
+    let mut sent = 0;
+    for reminder in reminders.iter() {
+        match notifier.notify(reminder).await {
+            Ok(()) => sent += 1,
+            Err(e) => tracing::error!("failed to send reminder {}: {}", reminder.id, e),
+        }
+    }
+    sent
+}
+
+// 送信に失敗した配送は間隔を空けて再送し、何度も失敗したら諦める
+pub async fn deliver_webhooks<W: WebhookRepository, S: WebhookSender>(
+    repo: &W,
+    sender: &S,
+) -> usize {
+    let deliveries = match repo.take_due(Utc::now()).await {
+        Ok(deliveries) => deliveries,
+        Err(e) => {
+            tracing::error!("failed to take due webhook deliveries: {}", e);
+            return 0;
+        }
+    };
+    let mut delivered = 0;
+    for delivery in deliveries.iter() {
+        let now = Utc::now();
+        let result = match sender.send(delivery).await {
+            Ok(()) => {
+                delivered += 1;
+                repo.mark_delivered(delivery.id, now).await
+            }
+            Err(e) => {
+                let retry_at = webhook::retry_at(delivery.attempts, now);
+                if retry_at.is_none() {
+                    tracing::warn!("gave up webhook delivery {}: {}", delivery.id, e);
+                }
+                repo.mark_failed(delivery.id, &e.to_string(), retry_at, now)
+                    .await
+            }
+        };
+        // 記録できなかった配送は lease が切れたあとにもう一度送られる
+        if let Err(e) = result {
+            tracing::error!("failed to record webhook delivery {}: {}", delivery.id, e);
+        }
+    }
+    delivered
+}
+
+// 古い順に流し、失敗したらそこで止める. 流せなかった event は lease が切れた後に順番を保って送り直す
+// 流した event は webhook の配送待ちにしてから消す. 配送待ちにできなかった event と、消せなかった event はもう一度流れる
+pub async fn relay_outbox<O: OutboxRepository, P: EventPublisher, W: WebhookRepository>(
+    repo: &O,
+    bus: &P,
+    webhooks: &W,
+) -> usize {
+    let events = match repo.claim(Utc::now(), OUTBOX_BATCH_SIZE).await {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::error!("failed to claim outbox events: {}", e);
+            return 0;
+        }
+    };
+    let mut published = vec![];
+    for event in events.iter() {
+        if let Err(e) = bus.publish(event).await {
+            tracing::error!("failed to publish outbox event {}: {}", event.id, e);
+            break;
+        }
+        published.push(event.id);
+    }
+    if published.is_empty() {
+        return 0;
+    }
+    if let Err(e) = webhooks.enqueue(&events[..published.len()]).await {
+        tracing::error!("failed to enqueue webhook deliveries: {}", e);
+        return 0;
+    }
+    if let Err(e) = repo.remove(&published).await {
+        tracing::error!("failed to remove published outbox events: {}", e);
+    }
+    published.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blob_store::memory::BlobStoreForMemory;
+    use crate::events::LocalPublisher;
+    use crate::repositories::audit::memory::AuditRepositoryForMemory;
+    use crate::repositories::idempotency::memory::IdempotencyRepositoryForMemory;
+    use crate::repositories::outbox::{memory::OutboxRepositoryForMemory, OutboxEvent};
+    use crate::repositories::reminder::{
+        memory::ReminderRepositoryForMemory, Channel, CreateReminder, DueReminder,
+    };
+    use crate::repositories::todo::{memory::TodoRepositoryForMemory, CreateTodo, Status};
+    use crate::repositories::webhook::{
+        memory::WebhookRepositoryForMemory, CreateWebhook, DueDelivery,
+    };
+    use crate::repositories::Scope;
+    use axum::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    // 送った通知を記録する. webhook の通知だけ失敗させる
+    #[derive(Debug, Clone, Default)]
+    struct RecordingNotifier {
+        sent: Arc<Mutex<Vec<i32>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+            if reminder.channel == Channel::Webhook {
+                anyhow::bail!("webhook is down");
+            }
+            self.sent.lock().unwrap().push(reminder.id);
+            Ok(())
+        }
+    }
+
+    // 送った配送を記録する. 送信先の url が down なら失敗させる
+    #[derive(Debug, Clone, Default)]
+    struct RecordingSender {
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl WebhookSender for RecordingSender {
+        async fn send(&self, delivery: &DueDelivery) -> anyhow::Result<()> {
+            if delivery.url.contains("down") {
+                anyhow::bail!("503 Service Unavailable");
+            }
+            self.sent.lock().unwrap().push(delivery.event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_materialize_recurrences_in_background() {
+        let repo = TodoRepositoryForMemory::new();
+        let scope = Scope::personal(1);
+        let payload = serde_json::from_str::<CreateTodo>(
+            r#"{ "text": "daily", "labels": [], "recurrence": { "freq": "daily" } }"#,
+        )
+        .unwrap();
+        let todo = repo.create(scope, payload).await.unwrap();
+        let complete = serde_json::from_str(r#"{ "status": "done" }"#).unwrap();
+        repo.update(scope, todo.id, complete).await.unwrap();
+
+        let audit = AuditRepositoryForMemory::new();
+        let handle = Scheduler::new(Duration::from_millis(10))
+            .spawn_recurrences(repo.clone(), audit.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.abort();
+
+        let todos = repo.all_unscoped().await.unwrap();
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[0].status, Status::Backlog);
+        assert_eq!(materialize_recurrences(&repo, &audit).await, 0);
+        // the created todo is recorded without an actor
+        let events = audit.all().await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, AuditAction::Create);
+        assert_eq!(events[0].actor_id, None);
+    }
+
+    #[tokio::test]
+    async fn should_purge_expired_idempotency_keys() {
+        let repo = IdempotencyRepositoryForMemory::new();
+        let now = Utc::now();
+        repo.reserve(1, "expired", "a", now - chrono::Duration::seconds(1))
+            .await
+            .unwrap();
+        repo.reserve(1, "alive", "a", now + chrono::Duration::hours(1))
+            .await
+            .unwrap();
+
+        let config = JobsConfig {
+            purge_idempotency_keys: "* * * * * *".to_string(),
+            refresh_label_stats: String::new(),
+            ..Default::default()
+        };
+        let handles = MaintenanceJobs::from_config(&config).spawn(
+            TodoRepositoryForMemory::new(),
+            AuditRepositoryForMemory::new(),
+            repo.clone(),
+            Arc::new(BlobStoreForMemory::new()),
+        );
+        assert_eq!(handles.len(), 1);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        handles[0].abort();
+
+        assert_eq!(purge_idempotency_keys(&repo).await, 0);
+        let existing = repo
+            .reserve(1, "alive", "b", now + chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert!(existing.is_some());
+    }
+
+    // 2 回目以降の publish を失敗させる
+    #[derive(Debug, Clone, Default)]
+    struct FlakyPublisher {
+        published: Arc<Mutex<Vec<i64>>>,
+    }
+
+    #[async_trait]
+    impl EventPublisher for FlakyPublisher {
+        async fn publish(&self, event: &OutboxEvent) -> anyhow::Result<()> {
+            let mut published = self.published.lock().unwrap();
+            if !published.is_empty() {
+                anyhow::bail!("connection reset");
+            }
+            published.push(event.id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_relay_outbox_events_in_order() {
+        let repo = OutboxRepositoryForMemory::new();
+        let bus = LocalPublisher::new(16);
+        let mut receiver = bus.subscribe();
+        let webhooks = WebhookRepositoryForMemory::new();
+        let payload = CreateWebhook {
+            url: "https://example.com/hook".to_string(),
+            events: vec![],
+        };
+        webhooks
+            .create(Scope::personal(1), payload, "secret".to_string())
+            .await
+            .unwrap();
+        let created = repo.record(
+            "todo.created",
+            1,
+            serde_json::json!({ "id": 1, "user_id": 1 }),
+        );
+        let updated = repo.record(
+            "todo.updated",
+            1,
+            serde_json::json!({ "id": 1, "user_id": 1 }),
+        );
+
+        let handle = Scheduler::new(Duration::from_millis(10)).spawn_outbox_relay(
+            repo.clone(),
+            bus.clone(),
+            webhooks.clone(),
+        );
+        assert_eq!(receiver.recv().await.unwrap(), created);
+        assert_eq!(receiver.recv().await.unwrap(), updated);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.abort();
+        assert!(repo.pending().is_empty());
+        // the same events are queued for the webhook, one delivery per event
+        let delivered: Vec<i64> = webhooks
+            .deliveries()
+            .iter()
+            .map(|delivery| delivery.outbox_event_id)
+            .collect();
+        assert_eq!(delivered, vec![created.id, updated.id]);
+
+        // a failed publish keeps the event and everything after it
+        let label = serde_json::json!({ "id": 2, "user_id": 1 });
+        let first = repo.record("label.created", 2, label.clone());
+        repo.record("label.updated", 2, label);
+        let bus = FlakyPublisher::default();
+        assert_eq!(relay_outbox(&repo, &bus, &webhooks).await, 1);
+        assert_eq!(*bus.published.lock().unwrap(), vec![first.id]);
+        let pending = repo.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].event, "label.updated");
+        // and it is not handed out again until the lease ends
+        assert_eq!(
+            relay_outbox(&repo, &LocalPublisher::new(16), &webhooks).await,
+            0
+        );
+        assert_eq!(webhooks.deliveries().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_purge_archived_todos_after_the_retention() {
+        let repo = TodoRepositoryForMemory::new();
+        let blob_store = BlobStoreForMemory::new();
+        let scope = Scope::personal(1);
+        let payload =
+            serde_json::from_str::<CreateTodo>(r#"{ "text": "old", "labels": [] }"#).unwrap();
+        let todo = repo.create(scope, payload).await.unwrap();
+        repo.archive(scope, todo.id).await.unwrap();
+
+        let audit = AuditRepositoryForMemory::new();
+        let retention = chrono::Duration::days(30);
+        assert_eq!(
+            purge_archived_todos(&repo, &audit, &blob_store, retention).await,
+            0
+        );
+        assert_eq!(
+            purge_archived_todos(&repo, &audit, &blob_store, chrono::Duration::zero()).await,
+            1
+        );
+        assert!(repo.all_unscoped().await.unwrap().is_empty());
+        let events = audit.all().await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, AuditAction::Delete);
+        assert_eq!(events[0].entity_id, todo.id);
+    }
+
+    #[tokio::test]
+    async fn should_dispatch_due_reminders() {
+        let repo = ReminderRepositoryForMemory::new();
+        let notifier = RecordingNotifier::default();
+        let now = chrono::Utc::now();
+        let create = |remind_at, channel, url: Option<&str>| CreateReminder {
+            remind_at,
+            channel,
+            url: url.map(str::to_string),
+        };
+        let due = repo
+            .create(1, 1, create(now, Channel::Email, None))
+            .await
+            .unwrap();
+        repo.create(
+            1,
+            1,
+            create(now, Channel::Webhook, Some("https://example.com/hook")),
+        )
+        .await
+        .unwrap();
+        repo.create(
+            1,
+            1,
+            create(now + chrono::Duration::hours(1), Channel::Log, None),
+        )
+        .await
+        .unwrap();
+
+        let handle = Scheduler::new(Duration::from_millis(10))
+            .spawn_reminders(repo.clone(), notifier.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.abort();
+
+        // 失敗した webhook は再送しない. まだ期限の来ていない通知は送らない
+        assert_eq!(*notifier.sent.lock().unwrap(), vec![due.id]);
+        assert_eq!(dispatch_reminders(&repo, &notifier).await, 0);
+        let reminders = repo.all_by_todo(1, 1).await.unwrap();
+        assert_eq!(
+            reminders
+                .iter()
+                .filter(|reminder| reminder.sent_at.is_some())
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn should_deliver_webhooks_with_retries() {
+        let repo = WebhookRepositoryForMemory::new();
+        let sender = RecordingSender::default();
+        let scope = Scope::personal(1);
+        for url in ["https://example.com/hook", "https://down.example.com/hook"] {
+            let payload = CreateWebhook {
+                url: url.to_string(),
+                events: vec![],
+            };
+            repo.create(scope, payload, "secret".to_string())
+                .await
+                .unwrap();
+        }
+        let event = OutboxRepositoryForMemory::new().record(
+            "todo.created",
+            1,
+            serde_json::json!({ "id": 1, "user_id": 1, "workspace_id": null }),
+        );
+        assert_eq!(repo.enqueue(&[event]).await.unwrap(), 2);
+
+        let handle =
+            Scheduler::new(Duration::from_millis(10)).spawn_webhooks(repo.clone(), sender.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        handle.abort();
+
+        // 失敗した配送は間隔を空けて再送する
+        assert_eq!(*sender.sent.lock().unwrap(), vec!["todo.created"]);
+        assert_eq!(deliver_webhooks(&repo, &sender).await, 0);
+        let deliveries = repo.deliveries();
+        assert!(deliveries[0].delivered_at.is_some());
+        let failed = &deliveries[1];
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(
+            failed.last_error.as_deref(),
+            Some("503 Service Unavailable")
+        );
+        assert!(failed.next_attempt_at > Utc::now() + chrono::Duration::seconds(20));
+        assert_eq!(failed.failed_at, None);
+    }
+}
@@ -0,0 +1,368 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, env, sync::Arc};
+
+// 設定の値を秘密情報の保存先から取得する. 設定には平文の代わりに <scheme>:<path>#<key> と書く
+//   vault:secret/data/todos#database_url   Vault の KV. key はシークレットの中のフィールド
+//   aws-sm:prod/todos#jwt_secret           AWS Secrets Manager. #key を書けば JSON の中のフィールド
+#[async_trait]
+pub trait SecretProvider: std::marker::Send + std::marker::Sync + 'static {
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String>;
+}
+
+// 組み込みの保存先と、それを有効にする環境変数
+const KNOWN_SCHEMES: [(&str, &str); 2] = [("vault", "VAULT_ADDR"), ("aws-sm", "AWS_REGION")];
+
+// scheme ごとの保存先. 設定されていない保存先を参照したらエラーにする
+#[derive(Clone, Default)]
+pub struct SecretProviders {
+    providers: HashMap<String, Arc<dyn SecretProvider>>,
+}
+
+impl SecretProviders {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut providers = Self::default();
+        if let Some(vault) = VaultSecretProvider::from_env() {
+            providers = providers.with("vault", vault?);
+        }
+        if let Some(aws) = AwsSecretsManagerProvider::from_env() {
+            providers = providers.with("aws-sm", aws?);
+        }
+        Ok(providers)
+    }
+
+    pub fn with(mut self, scheme: &str, provider: impl SecretProvider) -> Self {
+        self.providers
+            .insert(scheme.to_string(), Arc::new(provider));
+        self
+    }
+
+    // 秘密情報の参照でなければ None. postgres:// のような値はそのまま使う
+    pub async fn resolve(&self, value: &str) -> Option<anyhow::Result<String>> {
+        let (scheme, rest) = value.split_once(':')?;
+        let (path, key) = match rest.split_once('#') {
+            Some((path, key)) => (path, Some(key)),
+            None => (rest, None),
+        };
+        match self.providers.get(scheme) {
+            Some(provider) => Some(provider.fetch(path, key).await),
+            None => {
+                let (_, variable) = KNOWN_SCHEMES.iter().find(|(known, _)| *known == scheme)?;
+                Some(Err(anyhow::anyhow!(
+                    "{} is not configured, set {}",
+                    scheme,
+                    variable
+                )))
+            }
+        }
+    }
+}
+
+// Vault の KV (v1 と v2 のどちらでもよい) から読む. トークンの更新はしないので、起動時に読むだけに使う
+#[derive(Debug, Clone)]
+pub struct VaultSecretProvider {
+    addr: String,
+    token: String,
+    namespace: Option<String>,
+    client: reqwest::Client,
+}
+
+impl VaultSecretProvider {
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        let addr = env::var("VAULT_ADDR")
+            .ok()
+            .filter(|addr| !addr.is_empty())?;
+        Some((|| {
+            let token =
+                env::var("VAULT_TOKEN").map_err(|_| anyhow::anyhow!("undefined [VAULT_TOKEN]"))?;
+            let client = reqwest::Client::builder()
+                .user_agent(env!("CARGO_PKG_NAME"))
+                .build()?;
+            Ok(Self {
+                addr: addr.trim_end_matches('/').to_string(),
+                token,
+                namespace: env::var("VAULT_NAMESPACE").ok().filter(|ns| !ns.is_empty()),
+                client,
+            })
+        })())
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultSecretProvider {
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let key = key.ok_or_else(|| anyhow::anyhow!("vault secrets need a #key"))?;
+        let mut req = self
+            .client
+            .get(format!("{}/v1/{}", self.addr, path.trim_start_matches('/')))
+            .header("X-Vault-Token", &self.token);
+        if let Some(namespace) = &self.namespace {
+            req = req.header("X-Vault-Namespace", namespace);
+        }
+        let body: Value = req.send().await?.error_for_status()?.json().await?;
+        vault_field(&body, key)
+    }
+}
+
+// KV v2 は data.data に、v1 は data にフィールドが入っている
+fn vault_field(body: &Value, key: &str) -> anyhow::Result<String> {
+    let data = &body["data"];
+    let fields = if data["data"].is_object() && data["metadata"].is_object() {
+        &data["data"]
+    } else {
+        data
+    };
+    fields[key]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("no string field [{}] in the secret", key))
+}
+
+#[derive(Debug, Clone)]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+// AWS Secrets Manager の GetSecretValue を呼ぶ. 認証情報は AWS_ACCESS_KEY_ID などの環境変数から読む
+#[derive(Debug, Clone)]
+pub struct AwsSecretsManagerProvider {
+    region: String,
+    endpoint: String,
+    credentials: AwsCredentials,
+    client: reqwest::Client,
+}
+
+impl AwsSecretsManagerProvider {
+    pub fn from_env() -> Option<anyhow::Result<Self>> {
+        let region = env::var("AWS_REGION")
+            .ok()
+            .filter(|region| !region.is_empty())?;
+        let read = |name: &str| env::var(name).map_err(|_| anyhow::anyhow!("undefined [{}]", name));
+        Some((|| {
+            let credentials = AwsCredentials {
+                access_key_id: read("AWS_ACCESS_KEY_ID")?,
+                secret_access_key: read("AWS_SECRET_ACCESS_KEY")?,
+                session_token: env::var("AWS_SESSION_TOKEN")
+                    .ok()
+                    .filter(|token| !token.is_empty()),
+            };
+            let client = reqwest::Client::builder()
+                .user_agent(env!("CARGO_PKG_NAME"))
+                .build()?;
+            Ok(Self {
+                endpoint: format!("secretsmanager.{}.amazonaws.com", region),
+                region,
+                credentials,
+                client,
+            })
+        })())
+    }
+}
+
+#[async_trait]
+impl SecretProvider for AwsSecretsManagerProvider {
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let body = json!({ "SecretId": path }).to_string();
+        let mut headers = vec![
+            (
+                "content-type".to_string(),
+                "application/x-amz-json-1.1".to_string(),
+            ),
+            ("host".to_string(), self.endpoint.clone()),
+            (
+                "x-amz-target".to_string(),
+                "secretsmanager.GetSecretValue".to_string(),
+            ),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let request = SignedRequest {
+            method: "POST",
+            path: "/",
+            headers,
+            body: body.as_bytes(),
+        };
+        let (amz_date, authorization) = request.sign(
+            &self.credentials,
+            &self.region,
+            "secretsmanager",
+            Utc::now(),
+        );
+
+        let mut req = self
+            .client
+            .post(format!("https://{}/", self.endpoint))
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+            .body(body.clone());
+        for (name, value) in &request.headers {
+            if name != "host" {
+                req = req.header(name.as_str(), value.as_str());
+            }
+        }
+        let res: Value = req.send().await?.error_for_status()?.json().await?;
+        let secret = res["SecretString"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("the secret has no SecretString"))?;
+        match key {
+            None => Ok(secret.to_string()),
+            Some(key) => serde_json::from_str::<Value>(secret)?[key]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("no string field [{}] in the secret", key)),
+        }
+    }
+}
+
+// Signature Version 4 で署名するリクエスト. headers は小文字の名前で、host を含める
+struct SignedRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    // X-Amz-Date と Authorization の値を返す
+    fn sign(
+        &self,
+        credentials: &AwsCredentials,
+        region: &str,
+        service: &str,
+        now: DateTime<Utc>,
+    ) -> (String, String) {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let mut headers = self.headers.clone();
+        headers.push(("x-amz-date".to_string(), amz_date.clone()));
+        headers.sort();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{:x}",
+            self.method,
+            self.path,
+            canonical_headers,
+            signed_headers,
+            Sha256::digest(self.body)
+        );
+
+        let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amz_date,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = [date.as_str(), region, service, "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+                |key, part| hmac_sha256(&key, part.as_bytes()),
+            );
+        let signature: String = hmac_sha256(&key, string_to_sign.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        );
+        (amz_date, authorization)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    struct StaticSecrets;
+
+    #[async_trait]
+    impl SecretProvider for StaticSecrets {
+        async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+            match (path, key) {
+                ("todos", Some("jwt_secret")) => Ok("from-the-store".to_string()),
+                _ => Err(anyhow::anyhow!("no secret {}", path)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_resolve_references_only() {
+        let providers = SecretProviders::default().with("test", StaticSecrets);
+        assert_eq!(
+            providers
+                .resolve("test:todos#jwt_secret")
+                .await
+                .unwrap()
+                .unwrap(),
+            "from-the-store"
+        );
+        assert!(providers.resolve("test:missing").await.unwrap().is_err());
+        // plain values are not references
+        assert!(providers.resolve("postgres://db/todos").await.is_none());
+        assert!(providers.resolve("change-me").await.is_none());
+        // a known store that is not configured
+        let error = providers
+            .resolve("vault:secret/todos#url")
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "vault is not configured, set VAULT_ADDR");
+    }
+
+    #[test]
+    fn should_read_vault_fields() {
+        let v2 = json!({"data": {"data": {"url": "postgres://v2"}, "metadata": {"version": 3}}});
+        assert_eq!(vault_field(&v2, "url").unwrap(), "postgres://v2");
+        let v1 = json!({"data": {"url": "postgres://v1"}});
+        assert_eq!(vault_field(&v1, "url").unwrap(), "postgres://v1");
+        assert!(vault_field(&v1, "password").is_err());
+    }
+
+    // get-vanilla from the AWS Signature Version 4 test suite
+    #[test]
+    fn should_sign_like_aws() {
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let request = SignedRequest {
+            method: "GET",
+            path: "/",
+            headers: vec![("host".to_string(), "example.amazonaws.com".to_string())],
+            body: b"",
+        };
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let (amz_date, authorization) = request.sign(&credentials, "us-east-1", "service", now);
+        assert_eq!(amz_date, "20150830T123600Z");
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+}
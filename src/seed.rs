@@ -0,0 +1,251 @@
+use crate::auth::hash_password;
+use crate::repositories::{
+    label::{CreateLabel, Label, LabelRepository},
+    todo::{CreateTodo, Todo, TodoRepository},
+    user::{CreateUser, User, UserRepository},
+    RepositoryError, Scope,
+};
+use chrono::{Duration, Utc};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_json::json;
+
+// ローカルや検証環境で画面を触るためのユーザー. 本番で流さないこと
+// 2 人目以降は demo2@example.com, demo3@example.com, ... で、パスワードは全員同じ
+pub const SEED_EMAIL: &str = "demo@example.com";
+pub const SEED_PASSWORD: &str = "demo-password";
+
+const LABEL_NAMES: [&str; 10] = [
+    "work", "home", "errands", "health", "finance", "travel", "reading", "shopping", "family",
+    "ideas",
+];
+const LABEL_COLORS: [&str; 6] = [
+    "#4f86f7", "#e5533d", "#f2a93b", "#3fb37f", "#8e6cd8", "#7a8691",
+];
+const VERBS: [&str; 12] = [
+    "Buy", "Call", "Email", "Fix", "Plan", "Review", "Book", "Clean", "Write", "Renew", "Pay",
+    "Organize",
+];
+const OBJECTS: [&str; 14] = [
+    "groceries",
+    "the dentist",
+    "the quarterly report",
+    "the bike tire",
+    "the birthday party",
+    "flight tickets",
+    "the garage",
+    "a blog post",
+    "the passport",
+    "car insurance",
+    "the rent",
+    "the photo album",
+    "the team offsite",
+    "the electricity bill",
+];
+const DETAILS: [&str; 6] = [
+    "Check the notes from last week first.",
+    "Ask for a receipt.",
+    "Before the end of the month if possible.",
+    "Compare at least two options.",
+    "Share the result with the family.",
+    "Keep it short.",
+];
+const PRIORITIES: [&str; 4] = ["low", "medium", "high", "urgent"];
+
+// 作る件数. labels と todos はユーザーごとの件数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedOptions {
+    pub users: usize,
+    pub labels: usize,
+    pub todos: usize,
+    // 指定すれば毎回同じ内容になる. テストでは固定する
+    pub rng_seed: Option<u64>,
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        Self {
+            users: 1,
+            labels: 3,
+            todos: 20,
+            rng_seed: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Seeded {
+    pub users: Vec<User>,
+    pub labels: Vec<Label>,
+    pub todos: Vec<Todo>,
+}
+
+pub fn seed_email(index: usize) -> String {
+    match index {
+        0 => SEED_EMAIL.to_string(),
+        _ => format!("demo{}@example.com", index + 1),
+    }
+}
+
+// repository のトレイト越しに作るので、DB でもメモリでも、テストの準備にも使える
+// 何度流してもよい. ユーザーとラベルは既にあればそれを使い、todo は毎回足す
+pub async fn seed(
+    user: &dyn UserRepository,
+    label: &dyn LabelRepository,
+    todo: &dyn TodoRepository,
+    options: &SeedOptions,
+) -> anyhow::Result<Seeded> {
+    let mut rng = match options.rng_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut seeded = Seeded {
+        users: Vec::with_capacity(options.users),
+        labels: Vec::new(),
+        todos: Vec::new(),
+    };
+    // ハッシュの計算は遅いので 1 回だけにする
+    let mut password_hash = None;
+    for index in 0..options.users {
+        let email = seed_email(index);
+        let user = match user.find_by_email(&email).await {
+            Ok(user) => user,
+            Err(e) if matches!(e.downcast_ref(), Some(RepositoryError::NotFound(_))) => {
+                let password_hash = match &password_hash {
+                    Some(password_hash) => password_hash,
+                    None => password_hash.insert(hash_password(SEED_PASSWORD)?),
+                };
+                let payload = CreateUser {
+                    email,
+                    password_hash: password_hash.clone(),
+                };
+                user.create(payload).await?
+            }
+            Err(e) => return Err(e),
+        };
+
+        // ユーザーごとのラベルと todo は 1 つの tx で作る
+        let mut tx = todo.begin().await?;
+        let mut labels = Vec::with_capacity(options.labels);
+        for i in 0..options.labels {
+            let mut payload: CreateLabel = serde_json::from_value(json!({
+                "name": label_name(i),
+                "color": LABEL_COLORS[i % LABEL_COLORS.len()],
+            }))?;
+            payload.user_id = user.id;
+            let (label, _) = label.create_or_get_in(&mut tx, payload).await?;
+            labels.push(label);
+        }
+
+        let payloads = (0..options.todos)
+            .map(|_| fake_todo(&mut rng, &labels))
+            .collect::<Result<Vec<CreateTodo>, _>>()?;
+        let todos = todo
+            .create_many_in(&mut tx, Scope::personal(user.id), payloads)
+            .await?;
+        tx.commit().await?;
+
+        seeded.users.push(user);
+        seeded.labels.extend(labels);
+        seeded.todos.extend(todos);
+    }
+    Ok(seeded)
+}
+
+// 用意した名前を使い切ったら番号を付けて回す
+fn label_name(index: usize) -> String {
+    let name = LABEL_NAMES[index % LABEL_NAMES.len()];
+    match index / LABEL_NAMES.len() {
+        0 => name.to_string(),
+        round => format!("{} {}", name, round + 1),
+    }
+}
+
+// 期限は 1 週間前から 1 か月先まで. 期限なし、説明なし、ラベルなしのものも混ぜる
+fn fake_todo(rng: &mut StdRng, labels: &[Label]) -> Result<CreateTodo, serde_json::Error> {
+    let text = format!(
+        "{} {}",
+        VERBS.choose(rng).unwrap(),
+        OBJECTS.choose(rng).unwrap()
+    );
+    let description = rng.gen_bool(0.3).then(|| DETAILS.choose(rng).unwrap());
+    let due_at = rng
+        .gen_bool(0.6)
+        .then(|| Utc::now() + Duration::hours(rng.gen_range(-7 * 24..30 * 24)));
+    let count = rng.gen_range(0..=labels.len().min(2));
+    let label_ids: Vec<i32> = labels
+        .choose_multiple(rng, count)
+        .map(|label| label.id)
+        .collect();
+    serde_json::from_value(json!({
+        "text": text,
+        "description": description,
+        "labels": label_ids,
+        "due_at": due_at,
+        "priority": PRIORITIES.choose(rng).unwrap(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        label::memory::LabelRepositoryForMemory, todo::memory::TodoRepositoryForMemory,
+        todo::TodoFilter, user::memory::UserRepositoryForMemory,
+    };
+
+    #[tokio::test]
+    async fn should_seed_again_without_duplicates() {
+        let user = UserRepositoryForMemory::new();
+        let label = LabelRepositoryForMemory::new();
+        let todo = TodoRepositoryForMemory::new();
+        let options = SeedOptions {
+            users: 2,
+            labels: 12,
+            todos: 5,
+            rng_seed: Some(1),
+        };
+
+        let seeded = seed(&user, &label, &todo, &options).await.unwrap();
+        assert_eq!(seeded.users.len(), 2);
+        assert_eq!(seeded.labels.len(), 24);
+        assert_eq!(seeded.todos.len(), 10);
+        assert_eq!(seeded.labels[11].name, "home 2");
+        let ids: Vec<i32> = seeded.labels.iter().map(|label| label.id).collect();
+        for todo in &seeded.todos {
+            assert!(todo.labels.len() <= 2);
+            assert!(todo.labels.iter().all(|label| ids.contains(&label.id)));
+        }
+
+        let seeded_again = seed(&user, &label, &todo, &options).await.unwrap();
+        assert_eq!(seeded_again.users, seeded.users);
+        assert_eq!(seeded_again.labels, seeded.labels);
+        assert_eq!(user.all().await.unwrap().len(), 2);
+        let demo = user.find_by_email("demo2@example.com").await.unwrap();
+        assert_eq!(label.find_by_user(demo.id).await.unwrap().len(), 12);
+        let todos = todo
+            .all(Scope::personal(demo.id), &TodoFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(todos.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn should_generate_the_same_todos_from_the_same_seed() {
+        let generate = || async {
+            let options = SeedOptions {
+                rng_seed: Some(42),
+                ..Default::default()
+            };
+            let user = UserRepositoryForMemory::new();
+            let label = LabelRepositoryForMemory::new();
+            let todo = TodoRepositoryForMemory::new();
+            let seeded = seed(&user, &label, &todo, &options).await.unwrap();
+            seeded
+                .todos
+                .into_iter()
+                .map(|todo| (todo.text, todo.priority, todo.labels.len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate().await, generate().await);
+    }
+}
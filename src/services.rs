@@ -0,0 +1,29 @@
+pub mod audit;
+pub mod label;
+pub mod todo;
+
+use crate::repositories::todo::Status;
+use thiserror::Error;
+
+// repository のエラーに加えて service のルールに反したときに返すエラー
+// handler では error_status でステータスコードに変換する
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("status cannot change from {0:?} to {1:?}")]
+    InvalidTransition(Status, Status),
+    #[error("project {0} cannot be used here")]
+    UnavailableProject(i32),
+    #[error("label {0} cannot be attached here")]
+    UnavailableLabel(i32),
+    #[error("user {0} cannot be assigned to this todo")]
+    UnassignableUser(i32),
+    #[error("at most {0} todos can be pinned")]
+    TooManyPinned(i64),
+    #[error("no change to undo")]
+    NothingToUndo,
+    #[error("todo {0} has changed since")]
+    UndoConflict(i32),
+    // If-Match に一致しない
+    #[error("todo {0} does not match the precondition")]
+    PreconditionFailed(i32),
+}
@@ -0,0 +1,114 @@
+use crate::repositories::audit::{
+    AuditAction, AuditEntity, AuditEvent, AuditRepository, CreateAuditEvent,
+};
+use crate::repositories::Tx;
+use serde::Serialize;
+
+// todo / label を変更した service や handler から、変更と同じ tx で呼ぶ
+// 記録できなければ変更ごと rollback させるため、エラーはそのまま返す
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all)]
+pub async fn record_event<T: Serialize>(
+    repo: &dyn AuditRepository,
+    tx: &mut Tx,
+    actor_id: i32,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i32,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    record(
+        repo,
+        tx,
+        Some(actor_id),
+        action,
+        entity,
+        entity_id,
+        None,
+        before,
+        after,
+    )
+    .await
+}
+
+// scheduler のように、ユーザーの操作ではない変更を記録する. actor_id は残らない
+#[tracing::instrument(skip_all)]
+pub async fn record_job_event<T: Serialize>(
+    repo: &dyn AuditRepository,
+    tx: &mut Tx,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i32,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    record(
+        repo, tx, None, action, entity, entity_id, None, before, after,
+    )
+    .await
+}
+
+// undo で undone の変更を取り消したときに呼ぶ. 対象は undone と同じ
+#[tracing::instrument(skip_all)]
+pub async fn record_undo_event<T: Serialize>(
+    repo: &dyn AuditRepository,
+    tx: &mut Tx,
+    actor_id: i32,
+    action: AuditAction,
+    undone: &AuditEvent,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    record(
+        repo,
+        tx,
+        Some(actor_id),
+        action,
+        undone.entity,
+        undone.entity_id,
+        Some(undone.id),
+        before,
+        after,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record<T: Serialize>(
+    repo: &dyn AuditRepository,
+    tx: &mut Tx,
+    actor_id: Option<i32>,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i32,
+    undo_of: Option<i32>,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> anyhow::Result<()> {
+    let to_json = |value: Option<&T>| value.and_then(|value| serde_json::to_value(value).ok());
+    repo.record(
+        tx,
+        CreateAuditEvent {
+            actor_id,
+            action,
+            entity,
+            entity_id,
+            before: to_json(before),
+            after: to_json(after),
+            undo_of,
+        },
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!(
+            "failed to record audit event ({:?} {:?} {}): {}",
+            action,
+            entity,
+            entity_id,
+            e
+        );
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,402 @@
+use super::{audit::record_event, ServiceError};
+use crate::auth::CurrentUser;
+use crate::repositories::audit::{AuditAction, AuditEntity, AuditRepository};
+use crate::repositories::label::{
+    CreateLabel, Label, LabelDeleteMode, LabelRepository, UpdateLabel,
+};
+use crate::repositories::{RepositoryError, Scope, Tx};
+use crate::state::AppState;
+
+// ラベルの一意性や workspace の境界、削除できる人などのルールをまとめたもの
+// workspace_id はアクティブな workspace. None なら個人のラベルを扱う
+pub struct LabelService<'a> {
+    label: &'a dyn LabelRepository,
+    audit: &'a dyn AuditRepository,
+}
+
+impl<'a> LabelService<'a> {
+    pub fn new(label: &'a dyn LabelRepository, audit: &'a dyn AuditRepository) -> Self {
+        Self { label, audit }
+    }
+
+    pub fn from_state(state: &'a AppState) -> Self {
+        Self::new(state.label.as_ref(), state.audit.as_ref())
+    }
+
+    // 名前は一覧 (個人 or workspace) ごとに一意. 同じ名前のラベルがあれば RepositoryError::Duplicate
+    pub async fn create(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        mut payload: CreateLabel,
+    ) -> anyhow::Result<Label> {
+        payload.user_id = user.id;
+        payload.workspace_id = workspace_id;
+        let mut tx = self.label.begin().await?;
+        let label = self.label.create_in(&mut tx, payload).await?;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Create,
+            label.id,
+            None,
+            Some(&label),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(label)
+    }
+
+    // 同じ名前のラベルがあればそれを返す. 作成したかどうかも返す
+    pub async fn create_or_get(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        mut payload: CreateLabel,
+    ) -> anyhow::Result<(Label, bool)> {
+        payload.user_id = user.id;
+        payload.workspace_id = workspace_id;
+        let mut tx = self.label.begin().await?;
+        let (label, created) = self.label.create_or_get_in(&mut tx, payload).await?;
+        if created {
+            self.record(
+                &mut tx,
+                user,
+                AuditAction::Create,
+                label.id,
+                None,
+                Some(&label),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok((label, created))
+    }
+
+    // workspace のラベルはその workspace をアクティブにしているときだけ、個人のラベルは持ち主だけが触れる
+    pub async fn find(&self, scope: Scope, id: i32) -> anyhow::Result<Label> {
+        let label = self.label.find(id).await?;
+        if !in_scope(&label, scope) {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+        Ok(label)
+    }
+
+    // todo に付けるラベルを取得する. 別の一覧のラベルと、アーカイブしたラベルは付けられない
+    pub async fn attachable(&self, scope: Scope, ids: &[i32]) -> anyhow::Result<Vec<Label>> {
+        let mut labels = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let label = self.label.find(id).await?;
+            if !in_scope(&label, scope) || label.archived_at.is_some() {
+                return Err(ServiceError::UnavailableLabel(id).into());
+            }
+            labels.push(label);
+        }
+        Ok(labels)
+    }
+
+    pub async fn update(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        id: i32,
+        payload: UpdateLabel,
+    ) -> anyhow::Result<Label> {
+        let mut tx = self.label.begin().await?;
+        let before = self
+            .find_for_update(&mut tx, Scope::new(user.id, workspace_id), id)
+            .await?;
+        let label = self.label.update_in(&mut tx, id, payload).await?;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Update,
+            id,
+            Some(&before),
+            Some(&label),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(label)
+    }
+
+    // 一覧から外すだけで、todo に付いたラベルはそのまま残る
+    pub async fn archive(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        id: i32,
+    ) -> anyhow::Result<Label> {
+        let mut tx = self.label.begin().await?;
+        let before = self
+            .find_for_update(&mut tx, Scope::new(user.id, workspace_id), id)
+            .await?;
+        let label = self.label.archive_in(&mut tx, id).await?;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Update,
+            id,
+            Some(&before),
+            Some(&label),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(label)
+    }
+
+    pub async fn unarchive(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        id: i32,
+    ) -> anyhow::Result<Label> {
+        let mut tx = self.label.begin().await?;
+        let before = self
+            .find_for_update(&mut tx, Scope::new(user.id, workspace_id), id)
+            .await?;
+        let label = self.label.unarchive_in(&mut tx, id).await?;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Update,
+            id,
+            Some(&before),
+            Some(&label),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(label)
+    }
+
+    // 自分のラベルしか消せない. 所有者のいない共有ラベルや他人のラベルは管理者だけが消せる
+    pub async fn delete(
+        &self,
+        user: CurrentUser,
+        workspace_id: Option<i32>,
+        id: i32,
+        mode: LabelDeleteMode,
+    ) -> anyhow::Result<()> {
+        // 管理者は他人の個人のラベルも消せるので、find の持ち主の確認は通さない
+        let mut tx = self.label.begin().await?;
+        let label = self.label.find_for_update_in(&mut tx, id).await?;
+        if label.workspace_id.is_some() && label.workspace_id != workspace_id {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+        if label.user_id != Some(user.id) && !user.is_admin() {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+        self.label.delete_in(&mut tx, id, mode).await?;
+        self.record(&mut tx, user, AuditAction::Delete, id, Some(&label), None)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // find と同じ確認をして、変更前のラベルを tx の中でロックして読む
+    async fn find_for_update(&self, tx: &mut Tx, scope: Scope, id: i32) -> anyhow::Result<Label> {
+        let label = self.label.find_for_update_in(tx, id).await?;
+        if !in_scope(&label, scope) {
+            return Err(RepositoryError::Forbidden(id).into());
+        }
+        Ok(label)
+    }
+
+    async fn record(
+        &self,
+        tx: &mut Tx,
+        user: CurrentUser,
+        action: AuditAction,
+        id: i32,
+        before: Option<&Label>,
+        after: Option<&Label>,
+    ) -> anyhow::Result<()> {
+        record_event(
+            self.audit,
+            tx,
+            user.id,
+            action,
+            AuditEntity::Label,
+            id,
+            before,
+            after,
+        )
+        .await
+    }
+}
+
+// scope の一覧 (個人 or workspace) のラベルかどうか
+fn in_scope(label: &Label, scope: Scope) -> bool {
+    match scope.workspace_id {
+        Some(_) => label.workspace_id == scope.workspace_id,
+        None => label.workspace_id.is_none() && label.user_id == Some(scope.user_id),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        audit::memory::AuditRepositoryForMemory, label::memory::LabelRepositoryForMemory,
+        user::Role,
+    };
+
+    fn user(id: i32) -> CurrentUser {
+        CurrentUser {
+            id,
+            role: Role::Member,
+        }
+    }
+
+    fn payload(name: &str) -> CreateLabel {
+        CreateLabel::new(name.to_string(), 0)
+    }
+
+    #[tokio::test]
+    async fn should_keep_names_unique_per_list() {
+        let label = LabelRepositoryForMemory::new();
+        let audit = AuditRepositoryForMemory::new();
+        let service = LabelService::new(&label, &audit);
+
+        let created = service
+            .create(user(1), None, payload("Work"))
+            .await
+            .unwrap();
+        assert_eq!(created.user_id, Some(1));
+        let error = service
+            .create(user(1), None, payload("work"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == created.id
+        ));
+        let (existing, was_created) = service
+            .create_or_get(user(1), None, payload("work"))
+            .await
+            .unwrap();
+        assert_eq!((existing, was_created), (created.clone(), false));
+        // a workspace keeps its own names, shared by all of its members
+        let (in_workspace, was_created) = service
+            .create_or_get(user(1), Some(7), payload("work"))
+            .await
+            .unwrap();
+        assert!(was_created);
+        assert_eq!(in_workspace.workspace_id, Some(7));
+        let (existing, was_created) = service
+            .create_or_get(user(2), Some(7), payload("Work"))
+            .await
+            .unwrap();
+        assert_eq!((existing, was_created), (in_workspace.clone(), false));
+        let error = service
+            .create(user(2), Some(7), payload("WORK"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Duplicate(id)) if *id == in_workspace.id
+        ));
+        // only the creates are recorded
+        assert_eq!(audit.all().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_guard_workspaces_and_owners() {
+        let label = LabelRepositoryForMemory::new();
+        let audit = AuditRepositoryForMemory::new();
+        let service = LabelService::new(&label, &audit);
+        let shared = service
+            .create(user(1), Some(7), payload("Team"))
+            .await
+            .unwrap();
+        let personal = service
+            .create(user(1), None, payload("Mine"))
+            .await
+            .unwrap();
+
+        let error = service
+            .find(Scope::personal(1), shared.id)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        service
+            .find(Scope::new(2, Some(7)), shared.id)
+            .await
+            .unwrap();
+
+        // personal labels belong to their owner alone
+        let error = service
+            .find(Scope::personal(2), personal.id)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let error = service
+            .update(user(2), None, personal.id, UpdateLabel::parent(None))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let error = service
+            .archive(user(2), None, personal.id)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        let error = service
+            .attachable(Scope::personal(2), &[personal.id])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::UnavailableLabel(id)) if *id == personal.id
+        ));
+        assert_eq!(
+            service
+                .attachable(Scope::personal(1), &[personal.id])
+                .await
+                .unwrap(),
+            vec![personal.clone()]
+        );
+
+        service.archive(user(1), None, personal.id).await.unwrap();
+        let error = service
+            .attachable(Scope::personal(1), &[personal.id])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::UnavailableLabel(id)) if *id == personal.id
+        ));
+        assert_eq!(
+            service
+                .attachable(Scope::new(2, Some(7)), &[shared.id])
+                .await
+                .unwrap(),
+            vec![shared.clone()]
+        );
+
+        let error = service
+            .delete(user(2), Some(7), shared.id, LabelDeleteMode::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RepositoryError>(),
+            Some(RepositoryError::Forbidden(_))
+        ));
+        service
+            .delete(user(1), Some(7), shared.id, LabelDeleteMode::default())
+            .await
+            .unwrap();
+        assert!(label.find(shared.id).await.is_err());
+    }
+}
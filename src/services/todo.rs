@@ -0,0 +1,792 @@
+use super::audit::{record_event, record_undo_event};
+use super::{label::LabelService, ServiceError};
+use crate::auth::CurrentUser;
+use crate::blob_store::BlobStore;
+use crate::handlers::attachment::remove_blobs;
+use crate::repositories::attachment::AttachmentRepository;
+use crate::repositories::audit::{AuditAction, AuditEntity, AuditEvent, AuditRepository};
+use crate::repositories::label::LabelRepository;
+use crate::repositories::project::ProjectRepository;
+use crate::repositories::todo::{
+    CreateTodo, MoveTodo, RelabelTodos, Status, Todo, TodoRepository, TodoSelection, UpdateTodo,
+};
+use crate::repositories::workspace::WorkspaceRepository;
+use crate::repositories::{RepositoryError, Scope, Tx};
+use crate::state::AppState;
+use chrono::{DateTime, Duration, Utc};
+
+// POST /todos/undo で取り消せるのは、この時間内の変更だけ
+const UNDO_WINDOW_SECONDS: i64 = 60;
+// 1 つの一覧 (個人 or workspace) でピン留めできる数. アーカイブした todo は数えない
+const MAX_PINNED_TODOS: i64 = 10;
+
+// todo の作成と更新のルールをまとめたもの. 監査ログもここで残す
+// 変更と監査ログは 1 つの tx で書き、どちらかが失敗したら両方とも残らない
+// HTTP に関すること (If-Match やステータスコード) は handler に残す
+pub struct TodoService<'a> {
+    todo: &'a dyn TodoRepository,
+    audit: &'a dyn AuditRepository,
+    project: &'a dyn ProjectRepository,
+    label: &'a dyn LabelRepository,
+    workspace: &'a dyn WorkspaceRepository,
+    attachment: &'a dyn AttachmentRepository,
+    blob_store: &'a dyn BlobStore,
+}
+
+impl<'a> TodoService<'a> {
+    pub fn new(
+        todo: &'a dyn TodoRepository,
+        audit: &'a dyn AuditRepository,
+        project: &'a dyn ProjectRepository,
+        label: &'a dyn LabelRepository,
+        workspace: &'a dyn WorkspaceRepository,
+        attachment: &'a dyn AttachmentRepository,
+        blob_store: &'a dyn BlobStore,
+    ) -> Self {
+        Self {
+            todo,
+            audit,
+            project,
+            label,
+            workspace,
+            attachment,
+            blob_store,
+        }
+    }
+
+    pub fn from_state(state: &'a AppState) -> Self {
+        Self::new(
+            state.todo.as_ref(),
+            state.audit.as_ref(),
+            state.project.as_ref(),
+            state.label.as_ref(),
+            state.workspace.as_ref(),
+            state.attachment.as_ref(),
+            state.blob_store.as_ref(),
+        )
+    }
+
+    pub async fn create(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        payload: CreateTodo,
+    ) -> anyhow::Result<Todo> {
+        if let Some(project_id) = payload.project_id {
+            self.check_project(scope, project_id).await?;
+        }
+        let mut tx = self.todo.begin().await?;
+        let todo = self.todo.create_in(&mut tx, scope, payload).await?;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Create,
+            todo.id,
+            None,
+            Some(&todo),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(todo)
+    }
+
+    // 全件を 1 つのトランザクションで作る. 1 件でも作れなければ何も作らない
+    pub async fn create_many(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        payloads: Vec<CreateTodo>,
+    ) -> anyhow::Result<Vec<Todo>> {
+        let mut project_ids: Vec<i32> =
+            payloads.iter().filter_map(|todo| todo.project_id).collect();
+        project_ids.sort();
+        project_ids.dedup();
+        for project_id in project_ids {
+            self.check_project(scope, project_id).await?;
+        }
+        let mut tx = self.todo.begin().await?;
+        let todos = self.todo.create_many_in(&mut tx, scope, payloads).await?;
+        for todo in &todos {
+            self.record(
+                &mut tx,
+                user,
+                AuditAction::Create,
+                todo.id,
+                None,
+                Some(todo),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(todos)
+    }
+
+    // prepare には tx の中でロックして読んだ変更前の todo を渡し、それを元に変更内容を作らせる
+    // If-Match の確認や patch の適用は呼び出し側が prepare の中で行う
+    pub async fn update(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+        prepare: impl FnOnce(&Todo) -> anyhow::Result<UpdateTodo>,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let payload = prepare(&before)?;
+        // 共有された todo を編集する場合も、入れられるのは所有者のプロジェクトだけ
+        if let Some(Some(project_id)) = payload.project_id {
+            let owner = Scope::new(before.user_id, before.workspace_id);
+            self.check_project(owner, project_id).await?;
+        }
+        if let Some(status) = payload.status {
+            if !before.status.can_transition_to(status) {
+                return Err(ServiceError::InvalidTransition(before.status, status).into());
+            }
+        }
+        let todo = self.todo.update_in(&mut tx, scope, id, payload).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    // revision で変わったフィールドを変更前の値に戻す. 戻したこと自体も新しい履歴になる
+    pub async fn revert(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+        revision_id: i32,
+    ) -> anyhow::Result<Todo> {
+        let revision = self.todo.revision(scope, id, revision_id).await?;
+        let payload = UpdateTodo::revert(&revision).map_err(|e| {
+            tracing::error!("failed to read todo revision {}: {}", revision_id, e);
+            RepositoryError::Unexpected(e.to_string())
+        })?;
+        self.update(user, scope, id, |_| Ok(payload)).await
+    }
+
+    pub async fn delete(&self, user: CurrentUser, scope: Scope, id: i32) -> anyhow::Result<()> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let storage_keys = self.storage_keys(id).await?;
+        self.todo.delete_in(&mut tx, scope, id).await?;
+        self.record(&mut tx, user, AuditAction::Delete, id, Some(&before), None)
+            .await?;
+        tx.commit().await?;
+        remove_blobs(self.blob_store, &storage_keys).await;
+        Ok(())
+    }
+
+    // 一括で完了にした todo も、1 件ずつ監査ログに残して undo できるようにする. 完了にした件数を返す
+    pub async fn complete_many(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<usize> {
+        let mut tx = self.todo.begin().await?;
+        let completed = self
+            .todo
+            .complete_many_in(&mut tx, scope, selection)
+            .await?;
+        for before in &completed {
+            let after = Todo {
+                status: Status::Done,
+                ..before.clone()
+            };
+            self.record(
+                &mut tx,
+                user,
+                AuditAction::Update,
+                before.id,
+                Some(before),
+                Some(&after),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(completed.len())
+    }
+
+    // 削除した件数を返す
+    pub async fn delete_many(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        selection: &TodoSelection,
+    ) -> anyhow::Result<usize> {
+        let mut tx = self.todo.begin().await?;
+        let deleted = self.todo.delete_many_in(&mut tx, scope, selection).await?;
+        for before in &deleted.todos {
+            self.record(
+                &mut tx,
+                user,
+                AuditAction::Delete,
+                before.id,
+                Some(before),
+                None,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        remove_blobs(self.blob_store, &deleted.storage_keys).await;
+        Ok(deleted.todos.len())
+    }
+
+    // 付けるラベルは今の一覧のものだけ. ラベルが変わった件数を返す
+    pub async fn relabel_many(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        payload: &RelabelTodos,
+    ) -> anyhow::Result<usize> {
+        let add = LabelService::new(self.label, self.audit)
+            .attachable(scope, &payload.add)
+            .await?;
+        let mut tx = self.todo.begin().await?;
+        let relabeled = self
+            .todo
+            .relabel_many_in(&mut tx, scope, &payload.todo_ids, &add, &payload.remove)
+            .await?;
+        for todo in &relabeled {
+            self.record(
+                &mut tx,
+                user,
+                AuditAction::Update,
+                todo.before.id,
+                Some(&todo.before),
+                Some(&todo.after),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(relabeled.len())
+    }
+
+    // 今の一覧で自分が最後にした todo の変更を取り消す. 続けて呼ぶとその前の変更を取り消す
+    // 作成は削除し、更新と削除は変更前の状態に戻す. 変更の後に他の操作が入っていたら ServiceError::UndoConflict
+    // 取り消した記録と、取り消した後の todo を返す. 作成を取り消した場合の todo は None
+    pub async fn undo(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+    ) -> anyhow::Result<(AuditEvent, Option<Todo>)> {
+        let since = Utc::now() - Duration::seconds(UNDO_WINDOW_SECONDS);
+        let events = self
+            .audit
+            .undoable(user.id, AuditEntity::Todo, since)
+            .await?;
+        // 記録した todo の最後の状態で、別の workspace での変更を除く
+        let (event, snapshot) = events
+            .into_iter()
+            .find_map(|event| {
+                let snapshot = event.after.clone().or_else(|| event.before.clone())?;
+                let snapshot: Todo = serde_json::from_value(snapshot).ok()?;
+                (snapshot.workspace_id == scope.workspace_id).then_some((event, snapshot))
+            })
+            .ok_or(ServiceError::NothingToUndo)?;
+        // 記録があるのに todo が見つからないのは、他の操作で消えたとき
+        let mut tx = self.todo.begin().await?;
+        let current = match self
+            .todo
+            .find_for_update_in(&mut tx, scope, event.entity_id)
+            .await
+        {
+            Ok(todo) => Some(todo),
+            Err(e) => match e.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::NotFound(_)) => None,
+                _ => return Err(e),
+            },
+        };
+        let conflict = || ServiceError::UndoConflict(event.entity_id);
+
+        let mut storage_keys = vec![];
+        let todo = match event.action {
+            AuditAction::Create => {
+                let current = current.filter(|current| is_unchanged(current, &snapshot));
+                let current = current.ok_or_else(conflict)?;
+                storage_keys = self.storage_keys(current.id).await?;
+                self.todo.delete_in(&mut tx, scope, current.id).await?;
+                self.record_undo(
+                    &mut tx,
+                    user,
+                    AuditAction::Delete,
+                    &event,
+                    Some(&current),
+                    None,
+                )
+                .await?;
+                None
+            }
+            AuditAction::Update => {
+                let current = current.filter(|current| is_unchanged(current, &snapshot));
+                let current = current.ok_or_else(conflict)?;
+                let before: Todo = event
+                    .before
+                    .clone()
+                    .and_then(|before| serde_json::from_value(before).ok())
+                    .ok_or_else(|| {
+                        RepositoryError::Unexpected(format!(
+                            "audit event {} has no todo to restore",
+                            event.id
+                        ))
+                    })?;
+                let todo = self.todo.restore_in(&mut tx, scope, before).await?;
+                self.record_undo(
+                    &mut tx,
+                    user,
+                    AuditAction::Update,
+                    &event,
+                    Some(&current),
+                    Some(&todo),
+                )
+                .await?;
+                Some(todo)
+            }
+            AuditAction::Delete => {
+                if current.is_some() {
+                    return Err(conflict().into());
+                }
+                let todo = self.todo.restore_in(&mut tx, scope, snapshot).await?;
+                self.record_undo(
+                    &mut tx,
+                    user,
+                    AuditAction::Create,
+                    &event,
+                    None,
+                    Some(&todo),
+                )
+                .await?;
+                Some(todo)
+            }
+        };
+        tx.commit().await?;
+        remove_blobs(self.blob_store, &storage_keys).await;
+        Ok((event, todo))
+    }
+
+    pub async fn archive(&self, user: CurrentUser, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let todo = self.todo.archive_in(&mut tx, scope, id).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    pub async fn unarchive(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let todo = self.todo.unarchive_in(&mut tx, scope, id).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    // 1 つの一覧でピン留めできるのは MAX_PINNED_TODOS 件まで. 超えるなら ServiceError::TooManyPinned
+    // 数えてからピン留めするまで一覧をロックしておくので、同時にピン留めしても上限を超えない
+    pub async fn pin(&self, user: CurrentUser, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        if !before.is_pinned {
+            let owner = Scope::new(before.user_id, before.workspace_id);
+            let pinned = self.todo.count_pinned_in(&mut tx, owner).await?;
+            if pinned >= MAX_PINNED_TODOS {
+                return Err(ServiceError::TooManyPinned(MAX_PINNED_TODOS).into());
+            }
+        }
+        let todo = self.todo.pin_in(&mut tx, scope, id).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    pub async fn unpin(&self, user: CurrentUser, scope: Scope, id: i32) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let todo = self.todo.unpin_in(&mut tx, scope, id).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    // until が None ならスヌーズを解除する
+    pub async fn snooze(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+        until: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let todo = self.todo.snooze_in(&mut tx, scope, id, until).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    // workspace の todo はメンバーにだけ、個人の todo は作成者にだけ割り当てられる
+    pub async fn assign(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+        assignee_id: Option<i32>,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        if let Some(assignee_id) = assignee_id {
+            let assignable = match before.workspace_id {
+                Some(workspace_id) => self
+                    .workspace
+                    .find_membership(workspace_id, assignee_id)
+                    .await
+                    .is_ok(),
+                None => assignee_id == before.user_id,
+            };
+            if !assignable {
+                return Err(ServiceError::UnassignableUser(assignee_id).into());
+            }
+        }
+        let todo = self.todo.assign_in(&mut tx, scope, id, assignee_id).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    pub async fn move_to(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+        target: MoveTodo,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let before = self.todo.find_for_update_in(&mut tx, scope, id).await?;
+        let todo = self.todo.move_to_in(&mut tx, scope, id, target).await?;
+        self.commit_update(tx, user, &before, todo).await
+    }
+
+    // 添付ファイルの中身は DB の外にあるので、複製を commit したあとにコピーする
+    // コピーできなかった添付ファイルは複製から外す
+    pub async fn duplicate(
+        &self,
+        user: CurrentUser,
+        scope: Scope,
+        id: i32,
+    ) -> anyhow::Result<Todo> {
+        let mut tx = self.todo.begin().await?;
+        let duplicated = self.todo.duplicate_in(&mut tx, scope, id).await?;
+        let todo = duplicated.todo;
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Create,
+            todo.id,
+            None,
+            Some(&todo),
+        )
+        .await?;
+        tx.commit().await?;
+        for (source_key, attachment) in duplicated.attachments {
+            let copied = match self.blob_store.get(&source_key).await {
+                Ok(bytes) => {
+                    self.blob_store
+                        .put(&attachment.storage_key, &attachment.content_type, bytes)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = copied {
+                tracing::error!("failed to copy attachment {}: {}", source_key, e);
+                self.attachment
+                    .delete(attachment.todo_id, attachment.id)
+                    .await
+                    .ok();
+            }
+        }
+        Ok(todo)
+    }
+
+    // 添付ファイルのメタデータは todo と一緒に消えるので、中身の key を先に控えておく
+    // 中身は commit したあとに消す
+    async fn storage_keys(&self, id: i32) -> anyhow::Result<Vec<String>> {
+        let storage_keys = self
+            .attachment
+            .all_by_todo(id)
+            .await?
+            .into_iter()
+            .map(|attachment| attachment.storage_key)
+            .collect();
+        Ok(storage_keys)
+    }
+
+    // 1 件の更新を監査ログに残して commit する
+    async fn commit_update(
+        &self,
+        mut tx: Tx,
+        user: CurrentUser,
+        before: &Todo,
+        todo: Todo,
+    ) -> anyhow::Result<Todo> {
+        self.record(
+            &mut tx,
+            user,
+            AuditAction::Update,
+            todo.id,
+            Some(before),
+            Some(&todo),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(todo)
+    }
+
+    // 他人や別の workspace のプロジェクトは存在していても使えない
+    async fn check_project(&self, owner: Scope, project_id: i32) -> anyhow::Result<()> {
+        match self.project.find(owner, project_id).await {
+            Ok(_) => Ok(()),
+            Err(e) => match e.downcast_ref::<RepositoryError>() {
+                Some(RepositoryError::Forbidden(_)) => {
+                    Err(ServiceError::UnavailableProject(project_id).into())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    async fn record(
+        &self,
+        tx: &mut Tx,
+        user: CurrentUser,
+        action: AuditAction,
+        id: i32,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+    ) -> anyhow::Result<()> {
+        record_event(
+            self.audit,
+            tx,
+            user.id,
+            action,
+            AuditEntity::Todo,
+            id,
+            before,
+            after,
+        )
+        .await
+    }
+
+    async fn record_undo(
+        &self,
+        tx: &mut Tx,
+        user: CurrentUser,
+        action: AuditAction,
+        undone: &AuditEvent,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+    ) -> anyhow::Result<()> {
+        record_undo_event(self.audit, tx, user.id, action, undone, before, after).await
+    }
+}
+
+// 変更した後に他の操作が入っていないか. ラベルの並び順は問わない
+fn is_unchanged(current: &Todo, snapshot: &Todo) -> bool {
+    let label_ids = |todo: &Todo| {
+        let mut ids: Vec<i32> = todo.labels.iter().map(|label| label.id).collect();
+        ids.sort_unstable();
+        ids
+    };
+    let without_labels = |todo: &Todo| Todo {
+        labels: vec![],
+        ..todo.clone()
+    };
+    without_labels(current) == without_labels(snapshot) && label_ids(current) == label_ids(snapshot)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blob_store::memory::BlobStoreForMemory;
+    use crate::repositories::{
+        attachment::memory::AttachmentRepositoryForMemory,
+        audit::memory::AuditRepositoryForMemory,
+        label::memory::LabelRepositoryForMemory,
+        project::{memory::ProjectRepositoryForMemory, CreateProject},
+        todo::memory::TodoRepositoryForMemory,
+        user::Role,
+        workspace::memory::WorkspaceRepositoryForMemory,
+    };
+    use serde_json::json;
+
+    struct Repos {
+        todo: TodoRepositoryForMemory,
+        audit: AuditRepositoryForMemory,
+        project: ProjectRepositoryForMemory,
+        label: LabelRepositoryForMemory,
+        workspace: WorkspaceRepositoryForMemory,
+        attachment: AttachmentRepositoryForMemory,
+        blob_store: BlobStoreForMemory,
+    }
+
+    impl Repos {
+        fn new() -> Self {
+            Self {
+                todo: TodoRepositoryForMemory::new(),
+                audit: AuditRepositoryForMemory::new(),
+                project: ProjectRepositoryForMemory::new(),
+                label: LabelRepositoryForMemory::new(),
+                workspace: WorkspaceRepositoryForMemory::new(),
+                attachment: AttachmentRepositoryForMemory::new(),
+                blob_store: BlobStoreForMemory::new(),
+            }
+        }
+
+        fn service(&self) -> TodoService<'_> {
+            TodoService::new(
+                &self.todo,
+                &self.audit,
+                &self.project,
+                &self.label,
+                &self.workspace,
+                &self.attachment,
+                &self.blob_store,
+            )
+        }
+    }
+
+    fn user(id: i32) -> CurrentUser {
+        CurrentUser {
+            id,
+            role: Role::Member,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_check_projects_and_record_events() {
+        let repos = Repos::new();
+        let (audit, project) = (&repos.audit, &repos.project);
+        let service = repos.service();
+        let others: CreateProject = serde_json::from_value(json!({"name": "others"})).unwrap();
+        let others = project.create(Scope::personal(2), others).await.unwrap();
+
+        let mut payload = CreateTodo::new("write docs".to_string(), vec![]);
+        payload.project_id = Some(others.id);
+        let error = service
+            .create(user(1), Scope::personal(1), payload)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::UnavailableProject(id)) if *id == others.id
+        ));
+        assert!(audit.all().await.unwrap().is_empty());
+
+        let created = service
+            .create_many(
+                user(1),
+                Scope::personal(1),
+                vec![
+                    CreateTodo::new("first".to_string(), vec![]),
+                    CreateTodo::new("second".to_string(), vec![]),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(created.len(), 2);
+        let events = audit.all().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|event| event.action == AuditAction::Create));
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_transitions() {
+        let repos = Repos::new();
+        let (todo, audit) = (&repos.todo, &repos.audit);
+        let service = repos.service();
+        let scope = Scope::personal(1);
+        let created = service
+            .create(user(1), scope, CreateTodo::new("ship".to_string(), vec![]))
+            .await
+            .unwrap();
+
+        let done: UpdateTodo = serde_json::from_value(json!({"status": "done"})).unwrap();
+        let before = service
+            .update(user(1), scope, created.id, |_| Ok(done))
+            .await
+            .unwrap();
+        assert_eq!(before.status, Status::Done);
+        let cancelled: UpdateTodo = serde_json::from_value(json!({"status": "cancelled"})).unwrap();
+        let error = service
+            .update(user(1), scope, before.id, |_| Ok(cancelled))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::InvalidTransition(
+                Status::Done,
+                Status::Cancelled
+            ))
+        ));
+        assert_eq!(
+            todo.find(scope, created.id).await.unwrap().status,
+            Status::Done
+        );
+        // create and the first update only
+        assert_eq!(audit.all().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn should_limit_pins_and_check_assignees() {
+        let repos = Repos::new();
+        let service = repos.service();
+        let scope = Scope::personal(1);
+        let mut ids = vec![];
+        for i in 0..=MAX_PINNED_TODOS {
+            let payload = CreateTodo::new(format!("todo {}", i), vec![]);
+            ids.push(service.create(user(1), scope, payload).await.unwrap().id);
+        }
+        for &id in &ids[1..] {
+            assert!(service.pin(user(1), scope, id).await.unwrap().is_pinned);
+        }
+        let error = service.pin(user(1), scope, ids[0]).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::TooManyPinned(MAX_PINNED_TODOS))
+        ));
+        // pinning an already pinned todo does not count twice
+        service.pin(user(1), scope, ids[1]).await.unwrap();
+
+        let error = service
+            .assign(user(1), scope, ids[0], Some(2))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::UnassignableUser(2))
+        ));
+        let todo = service.assign(user(1), scope, ids[0], Some(1)).await;
+        assert_eq!(todo.unwrap().assignee_id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn should_undo_the_last_change() {
+        let repos = Repos::new();
+        let service = repos.service();
+        let scope = Scope::personal(1);
+        let error = service.undo(user(1), scope).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::NothingToUndo)
+        ));
+
+        let payload = CreateTodo::new("undo me".to_string(), vec![]);
+        let created = service.create(user(1), scope, payload).await.unwrap();
+        service.delete(user(1), scope, created.id).await.unwrap();
+        let (event, todo) = service.undo(user(1), scope).await.unwrap();
+        assert_eq!(event.action, AuditAction::Delete);
+        assert_eq!(todo.unwrap().text, "undo me");
+
+        // the restored todo is archived by someone else before the next undo
+        repos.todo.archive(scope, created.id).await.unwrap();
+        let error = service.undo(user(1), scope).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ServiceError>(),
+            Some(ServiceError::UndoConflict(id)) if *id == created.id
+        ));
+    }
+}
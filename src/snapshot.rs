@@ -0,0 +1,114 @@
+use crate::repositories::{
+    label::{memory::LabelRepositoryForMemory, Label},
+    todo::memory::{TodoRepositoryForMemory, TodoSnapshot},
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// REPO_BACKEND=memory で残すのは todo とラベルだけ. ユーザーなどは再起動すると消える
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MemorySnapshot {
+    todos: TodoSnapshot,
+    labels: Vec<Label>,
+}
+
+// path が空、またはファイルがまだなければ空の状態から始める
+pub fn load_snapshot(
+    path: &str,
+) -> anyhow::Result<(TodoRepositoryForMemory, LabelRepositoryForMemory)> {
+    let snapshot = match std::fs::read(path) {
+        _ if path.is_empty() => MemorySnapshot::default(),
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow::anyhow!("invalid snapshot [{}]: {}", path, e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => MemorySnapshot::default(),
+        Err(e) => return Err(anyhow::anyhow!("cannot read snapshot [{}]: {}", path, e)),
+    };
+    let todo = TodoRepositoryForMemory::restore(snapshot.todos);
+    let label = LabelRepositoryForMemory::restore(snapshot.labels, todo.clone());
+    Ok((todo, label))
+}
+
+// 書いている途中で止まっても前回の snapshot が壊れないように、一時ファイルに書いてから置き換える
+pub fn save_snapshot(
+    path: &str,
+    todo: &TodoRepositoryForMemory,
+    label: &LabelRepositoryForMemory,
+) -> anyhow::Result<()> {
+    let snapshot = MemorySnapshot {
+        todos: todo.snapshot(),
+        labels: label.snapshot(),
+    };
+    let bytes = serde_json::to_vec_pretty(&snapshot)?;
+    let temporary = format!("{}.tmp", path);
+    if let Some(dir) = Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("cannot create [{}]: {}", dir.display(), e))?;
+    }
+    std::fs::write(&temporary, bytes)
+        .and_then(|_| std::fs::rename(&temporary, path))
+        .map_err(|e| anyhow::anyhow!("cannot write snapshot [{}]: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repositories::{
+        label::{CreateLabel, LabelRepository},
+        todo::{CreateTodo, SharePermission, ShareTodo, TodoRepository},
+        Scope,
+    };
+
+    #[tokio::test]
+    async fn should_restore_saved_todos_and_labels() {
+        let dir = std::env::temp_dir().join(format!("rust-web-snapshot-{}", std::process::id()));
+        let path = dir.join("memory.json").display().to_string();
+        let (todo, label) = load_snapshot(&path).unwrap();
+        let scope = Scope::personal(1);
+        let created_label = label
+            .create(CreateLabel::new("work".to_string(), 1))
+            .await
+            .unwrap();
+        let created = todo
+            .create(
+                scope,
+                CreateTodo::new("write docs".to_string(), vec![created_label.id]),
+            )
+            .await
+            .unwrap();
+        todo.share(
+            scope,
+            created.id,
+            ShareTodo {
+                user_id: 2,
+                permission: SharePermission::Write,
+            },
+        )
+        .await
+        .unwrap();
+        save_snapshot(&path, &todo, &label).unwrap();
+
+        let (todo, label) = load_snapshot(&path).unwrap();
+        assert_eq!(todo.find(scope, created.id).await.unwrap(), created);
+        assert_eq!(label.find(created_label.id).await.unwrap(), created_label);
+        // shares survive the restart as well
+        todo.find(Scope::personal(2), created.id).await.unwrap();
+        // ids keep counting from the restored ones
+        let next = todo
+            .create(scope, CreateTodo::new("next".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(next.id, created.id + 1);
+
+        std::fs::write(&path, "{").unwrap();
+        let error = load_snapshot(&path).err().unwrap();
+        assert!(
+            error.to_string().starts_with("invalid snapshot"),
+            "{}",
+            error
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+use crate::blob_store::BlobStore;
+use crate::health::HealthCheck;
+use crate::repositories::{
+    attachment::AttachmentRepository, audit::AuditRepository, backup::BackupRepository,
+    idempotency::IdempotencyRepository, invitation::InvitationRepository, label::LabelRepository,
+    login_attempt::LoginAttemptRepository, project::ProjectRepository,
+    refresh_token::RefreshTokenRepository, reminder::ReminderRepository,
+    template::TemplateRepository, todo::TodoRepository, user::UserRepository,
+    webhook::WebhookRepository, workspace::WorkspaceRepository,
+};
+use std::sync::Arc;
+
+// handler が使う repository をまとめたもの. Extension で全てのリクエストに渡す
+// repository を足すときはここにフィールドを足せばよく、既存の handler の型は変わらない
+#[derive(Clone)]
+pub struct AppState {
+    pub todo: Arc<dyn TodoRepository>,
+    pub label: Arc<dyn LabelRepository>,
+    pub user: Arc<dyn UserRepository>,
+    pub refresh_token: Arc<dyn RefreshTokenRepository>,
+    pub workspace: Arc<dyn WorkspaceRepository>,
+    pub invitation: Arc<dyn InvitationRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+    pub login_attempt: Arc<dyn LoginAttemptRepository>,
+    pub reminder: Arc<dyn ReminderRepository>,
+    pub attachment: Arc<dyn AttachmentRepository>,
+    pub blob_store: Arc<dyn BlobStore>,
+    pub project: Arc<dyn ProjectRepository>,
+    pub template: Arc<dyn TemplateRepository>,
+    pub idempotency: Arc<dyn IdempotencyRepository>,
+    pub backup: Arc<dyn BackupRepository>,
+    pub webhook: Arc<dyn WebhookRepository>,
+    // GET /readyz で確認する依存先と、checks に出す名前
+    pub health_checks: Vec<(&'static str, Arc<dyn HealthCheck>)>,
+}
@@ -0,0 +1,385 @@
+use crate::config::TelemetryConfig;
+use crate::forwarded::ClientInfo;
+use crate::request_id::RequestId;
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+use serde_json::{json, Value};
+use std::{
+    env,
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Instrument, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+// W3C Trace Context. ゲートウェイが付けてくるので、同じ trace の子として span を作る
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+// 送りきれない span はここで捨てる. 送信先が落ちていてもメモリを使い切らない
+const QUEUE_CAPACITY: usize = 4096;
+// 1 回の送信にまとめる span の数
+const MAX_BATCH: usize = 512;
+// 最初の span が届いてから送信するまで待つ時間. この間に終わった span をまとめて送る
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// traceparent の trace-id と parent-id. どちらも小文字の 16 進数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    // 00-{trace-id 32 桁}-{parent-id 16 桁}-{flags 2 桁}. 全て 0 の id は無効
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_span_id = parts.next()?;
+        let flags = parts.next()?;
+        // 00 より後の版は後ろに項目が増えることがあるので、00 のときだけ余りを許さない
+        if version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        let is_hex = |id: &str, len: usize| {
+            id.len() == len
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        };
+        let is_id = |id: &str, len: usize| is_hex(id, len) && id.bytes().any(|b| b != b'0');
+        if !is_hex(version, 2)
+            || !is_id(trace_id, 32)
+            || !is_id(parent_span_id, 16)
+            || !is_hex(flags, 2)
+        {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_span_id: Some(parent_span_id.to_string()),
+        })
+    }
+
+    // traceparent が無いか壊れていれば、新しい trace を始める
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        req.headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+            .unwrap_or_else(|| Self {
+                trace_id: random_id(16),
+                parent_span_id: None,
+            })
+    }
+}
+
+fn random_id(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+// リクエストごとの span. handler や repository の span はこの子になる
+// trace_id をログにも出すので、ゲートウェイのトレースとログを突き合わせられる
+pub async fn trace_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let context = TraceContext::from_request(&req);
+    let span_id = random_id(8);
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|request_id| request_id.0.clone())
+        .unwrap_or_default();
+    let client_ip = req
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client| client.ip)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", req.method(), req.uri().path()),
+        otel.kind = "server",
+        http.method = %req.method(),
+        http.target = %req.uri(),
+        http.status_code = tracing::field::Empty,
+        trace_id = %context.trace_id,
+        span_id = %span_id,
+        parent_span_id = context.parent_span_id.as_deref().unwrap_or_default(),
+        request_id = %request_id,
+        client.address = %client_ip,
+    );
+    let mut res = next.run(req).instrument(span.clone()).await;
+    span.record("http.status_code", res.status().as_u16());
+    // 下流が同じ trace に繋げられるように、このリクエストの span を親にして返す
+    let traceparent = format!("00-{}-{}-01", context.trace_id, span_id);
+    if let Ok(value) = HeaderValue::from_str(&traceparent) {
+        res.headers_mut().insert(TRACEPARENT_HEADER, value);
+    }
+    res
+}
+
+// telemetry.otlp_endpoint に OTLP/HTTP (JSON) で送る. 未設定なら何も送らない
+#[derive(Debug, Clone)]
+pub struct OtlpLayer {
+    sender: mpsc::Sender<SpanData>,
+}
+
+impl OtlpLayer {
+    // 送信するタスクを起動するので、tokio のランタイムの中で呼ぶ
+    pub fn from_config(config: &TelemetryConfig) -> Option<Self> {
+        if config.otlp_endpoint.is_empty() {
+            return None;
+        }
+        let service_name = config.service_name.clone();
+        let url = format!("{}/v1/traces", config.otlp_endpoint.trim_end_matches('/'));
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .ok()?;
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(export(client, url, service_name, receiver));
+        Some(Self { sender })
+    }
+}
+
+async fn export(
+    client: reqwest::Client,
+    url: String,
+    service_name: String,
+    mut receiver: mpsc::Receiver<SpanData>,
+) {
+    while let Some(first) = receiver.recv().await {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(span) => batch.push(span),
+                Err(_) => break,
+            }
+        }
+        // 送信の失敗をここでログに出すと、その span がまた送られるので eprintln にする
+        let body = export_request(&service_name, &batch);
+        let res = client.post(&url).json(&body).send().await;
+        if let Err(e) = res.and_then(|res| res.error_for_status()) {
+            eprintln!("cannot export spans to {}: {}", url, e);
+        }
+    }
+}
+
+// 終わった span. 属性は文字列にして持つ
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: u8,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct FieldVisitor(Vec<(String, String)>);
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldVisitor::default();
+        attrs.record(&mut fields);
+        let field = |name: &str| {
+            fields
+                .0
+                .iter()
+                .find(|(key, value)| key == name && !value.is_empty())
+                .map(|(_, value)| value.clone())
+        };
+        // 親が無ければ trace_request が traceparent から読んだ値を使う. span_id も trace_request が決める
+        let (trace_id, parent_span_id) = match span.parent() {
+            Some(parent) => match parent.extensions().get::<SpanData>() {
+                Some(parent) => (parent.trace_id.clone(), Some(parent.span_id.clone())),
+                None => (random_id(16), None),
+            },
+            None => (
+                field("trace_id").unwrap_or_else(|| random_id(16)),
+                field("parent_span_id"),
+            ),
+        };
+        let now = SystemTime::now();
+        let data = SpanData {
+            trace_id,
+            span_id: field("span_id").unwrap_or_else(|| random_id(8)),
+            parent_span_id,
+            name: field("otel.name").unwrap_or_else(|| attrs.metadata().name().to_string()),
+            // SPAN_KIND_SERVER = 2, SPAN_KIND_INTERNAL = 1
+            kind: if field("otel.kind").as_deref() == Some("server") {
+                2
+            } else {
+                1
+            },
+            start: now,
+            end: now,
+            attributes: fields.0,
+        };
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldVisitor::default();
+        values.record(&mut fields);
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            data.attributes.extend(fields.0);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(mut data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        data.end = SystemTime::now();
+        // 溢れたら捨てる. リクエストの処理を待たせない
+        let _ = self.sender.try_send(data);
+    }
+}
+
+// OTLP の ExportTraceServiceRequest を JSON にしたもの. id は 16 進数、時刻は文字列の nano 秒
+pub fn export_request(service_name: &str, spans: &[SpanData]) -> Value {
+    let nanos = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string()
+    };
+    let string_attribute =
+        |key: &str, value: &str| json!({ "key": key, "value": { "stringValue": value } });
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let attributes: Vec<Value> = span
+                .attributes
+                .iter()
+                .filter(|(key, _)| {
+                    !key.starts_with("otel.")
+                        && !["trace_id", "span_id", "parent_span_id"].contains(&key.as_str())
+                })
+                .map(|(key, value)| string_attribute(key, value))
+                .collect();
+            let mut value = json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "name": span.name,
+                "kind": span.kind,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": attributes,
+            });
+            if let Some(parent_span_id) = &span.parent_span_id {
+                value["parentSpanId"] = json!(parent_span_id);
+            }
+            value
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [string_attribute("service.name", service_name)] },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_traceparent() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+        }
+        // 後の版は後ろに項目が増えても読める
+        assert!(TraceContext::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn should_build_otlp_request() {
+        let start = UNIX_EPOCH + Duration::from_secs(1);
+        let span = SpanData {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+            span_id: "00f067aa0ba902b7".to_string(),
+            parent_span_id: None,
+            name: "GET /v1/todos".to_string(),
+            kind: 2,
+            start,
+            end: start + Duration::from_millis(5),
+            attributes: vec![
+                ("otel.name".to_string(), "GET /v1/todos".to_string()),
+                ("http.status_code".to_string(), "200".to_string()),
+            ],
+        };
+        let body = export_request("rust-web", &[span]);
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "rust-web"
+        );
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["startTimeUnixNano"], "1000000000");
+        assert_eq!(span["endTimeUnixNano"], "1005000000");
+        assert!(span.get("parentSpanId").is_none());
+        assert_eq!(span["attributes"].as_array().unwrap().len(), 1);
+        assert_eq!(span["attributes"][0]["key"], "http.status_code");
+    }
+}
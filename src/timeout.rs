@@ -0,0 +1,96 @@
+use crate::config::ServerConfig;
+use crate::request_id::RequestId;
+use crate::routes::RouteError;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::Route,
+    Json,
+};
+use std::{convert::Infallible, future::Future, time::Duration};
+use tower::{Layer, Service, ServiceBuilder};
+use tower_http::timeout::TimeoutLayer;
+
+// handler が応答を返すまでの上限. None なら待ち続ける
+pub fn request_timeout(config: &ServerConfig) -> Option<Duration> {
+    (config.request_timeout_secs > 0).then(|| Duration::from_secs(config.request_timeout_secs))
+}
+
+// 時間内に応答がなければ 504 を返す. 時間は tower-http の TimeoutLayer が測る.
+// handler の future はそこで drop するので、実行中の repository の呼び出しも一緒に打ち切られる.
+// DB 側のクエリは statement_timeout で止まる
+// 本文は handler が読むので、遅いアップロードもこの時間に含まれる
+pub fn timeout(
+    duration: Duration,
+) -> impl Layer<
+    Route,
+    Service = impl Service<
+        Request<Body>,
+        Response = Response,
+        Error = Infallible,
+        Future = impl Future<Output = Result<Response, Infallible>> + Send + 'static,
+    > + Clone
+                  + Send
+                  + 'static,
+> + Clone {
+    ServiceBuilder::new()
+        .layer(middleware::from_fn(move |req, next| {
+            describe_timeout(duration, req, next)
+        }))
+        .layer(TimeoutLayer::new(duration))
+        .into_inner()
+}
+
+// TimeoutLayer は空の 408 を返すので、これまでどおり 504 の JSON にする
+// 408 はクライアントの送信が遅いときの status で、ここでは待ちきれなかったのはサーバー側
+async fn describe_timeout(duration: Duration, req: Request<Body>, next: Next<Body>) -> Response {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let route = format!("{} {}", req.method(), req.uri().path());
+    let res = next.run(req).await;
+    if res.status() != StatusCode::REQUEST_TIMEOUT {
+        return res;
+    }
+    tracing::warn!("{} did not finish within {:?}", route, duration);
+    let message = format!(
+        "request did not finish within {} seconds",
+        duration.as_secs()
+    );
+    let error = RouteError::new(StatusCode::GATEWAY_TIMEOUT, message, request_id);
+    (StatusCode::GATEWAY_TIMEOUT, Json(error)).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn should_give_up_on_slow_handlers() {
+        let app = |limit: Duration| {
+            Router::new()
+                .route(
+                    "/slow",
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        "done"
+                    }),
+                )
+                .layer(timeout(limit))
+        };
+        let req = || Request::builder().uri("/slow").body(Body::empty()).unwrap();
+
+        let res = app(Duration::from_millis(20)).oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let res = app(Duration::from_secs(5)).oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let config = ServerConfig {
+            request_timeout_secs: 0,
+            ..ServerConfig::default()
+        };
+        assert_eq!(request_timeout(&config), None);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::config::TlsConfig;
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use std::{
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{rustls::ServerConfig, server::TlsStream, TlsAcceptor};
+
+// ハンドシェイクが終わるのを待つ時間. 途中で止まったクライアントに接続を握られないようにする
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// ハンドシェイクが終わって hyper に渡す前の接続をいくつまで待たせるか
+const PENDING_CONNECTIONS: usize = 128;
+
+// PEM の証明書チェーンと秘密鍵から rustls の設定を作る. 鍵は PKCS#8, RSA, EC のどれでもよい
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> anyhow::Result<ServerConfig> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| anyhow::anyhow!("cannot read [{}]: {}", path.display(), e))
+    };
+    let certs: Vec<_> = rustls_pemfile::certs(&mut read(cert_path)?.as_slice())?
+        .into_iter()
+        .map(tokio_rustls::rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in [{}]", cert_path.display());
+    }
+    let key = rustls_pemfile::read_all(&mut read(key_path)?.as_slice())?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(tokio_rustls::rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("no private key in [{}]", key_path.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// 証明書の更新に追従するため、新しい接続は毎回その時点の設定でハンドシェイクする
+#[derive(Clone)]
+pub struct ReloadableTlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl ReloadableTlsConfig {
+    pub fn load(config: &TlsConfig) -> anyhow::Result<Self> {
+        let cert_path = PathBuf::from(&config.cert_path);
+        let key_path = PathBuf::from(&config.key_path);
+        let current = load_server_config(&cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            current: Arc::new(RwLock::new(Arc::new(current))),
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        };
+        Some((modified(&self.cert_path)?, modified(&self.key_path)?))
+    }
+
+    // interval ごとに更新日時を見て、変わっていれば読み直す
+    // 読めない間は古い証明書のまま続ける. 証明書と鍵を順に書き換える途中でも止まらない
+    pub fn spawn_reload(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut last = this.modified();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let modified = this.modified();
+                if modified.is_none() || modified == last {
+                    continue;
+                }
+                match load_server_config(&this.cert_path, &this.key_path) {
+                    Ok(config) => {
+                        *this.current.write().unwrap() = Arc::new(config);
+                        last = modified;
+                        tracing::info!("reloaded TLS certificate [{}]", this.cert_path.display());
+                    }
+                    Err(e) => tracing::warn!("cannot reload TLS certificate: {}", e),
+                }
+            }
+        })
+    }
+}
+
+// ハンドシェイクが終わった接続. 接続元のアドレスは ConnectInfo で handler に渡す
+pub struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    remote_addr: SocketAddr,
+}
+
+impl Connected<&TlsConnection> for SocketAddr {
+    fn connect_info(target: &TlsConnection) -> Self {
+        target.remote_addr
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+// axum::Server::builder に渡す. 受け付けた接続ごとに別のタスクでハンドシェイクするので、
+// 遅いクライアントがいても他の接続の受け付けは止まらない
+pub struct TlsAcceptorIncoming {
+    receiver: mpsc::Receiver<TlsConnection>,
+}
+
+impl TlsAcceptorIncoming {
+    pub async fn bind(addr: SocketAddr, tls: ReloadableTlsConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let (sender, receiver) = mpsc::channel(PENDING_CONNECTIONS);
+        tokio::spawn(async move {
+            loop {
+                let (stream, remote_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("cannot accept connection: {}", e);
+                        continue;
+                    }
+                };
+                // サーバーが止まったら受け付けもやめる
+                if sender.is_closed() {
+                    break;
+                }
+                let acceptor = tls.acceptor();
+                let connections = sender.clone();
+                tokio::spawn(async move {
+                    let handshake =
+                        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+                    match handshake.await {
+                        Ok(Ok(stream)) => {
+                            let _ = connections
+                                .send(TlsConnection {
+                                    stream,
+                                    remote_addr,
+                                })
+                                .await;
+                        }
+                        Ok(Err(e)) => {
+                            tracing::debug!("TLS handshake failed from {}: {}", remote_addr, e)
+                        }
+                        Err(_) => tracing::debug!("TLS handshake timed out from {}", remote_addr),
+                    }
+                });
+            }
+        });
+        Ok(Self { receiver })
+    }
+}
+
+impl Accept for TlsAcceptorIncoming {
+    type Conn = TlsConnection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|connection| connection.map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_report_missing_files() {
+        let error = load_server_config(Path::new("missing.crt"), Path::new("missing.key"))
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().starts_with("cannot read [missing.crt]"),
+            "{}",
+            error
+        );
+
+        let dir = std::env::temp_dir().join(format!("rust-web-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "").unwrap();
+        let error = load_server_config(&empty, &empty).err().unwrap();
+        assert!(
+            error.to_string().starts_with("no certificate in"),
+            "{}",
+            error
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use crate::repositories::webhook::DueDelivery;
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// 受け取る側は `{timestamp}.{body}` の HMAC-SHA256 を secret で計算して署名を検証する
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+// 再送しても変わらないので、受け取る側で重複を除くのに使える
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+// この回数失敗したら再試行を諦める
+const MAX_ATTEMPTS: i32 = 8;
+// 1 回目の失敗から次の送信までの間隔. 失敗するたびに倍にする
+const BASE_BACKOFF_SECS: i64 = 30;
+
+// 配送を送信する. テストでは送信先を差し替える
+#[async_trait]
+pub trait WebhookSender: std::marker::Send + std::marker::Sync + 'static {
+    async fn send(&self, delivery: &DueDelivery) -> anyhow::Result<()>;
+}
+
+// 2xx 以外のレスポンスは失敗として再試行する
+#[derive(Debug, Clone)]
+pub struct HttpWebhookSender {
+    client: reqwest::Client,
+}
+
+impl HttpWebhookSender {
+    pub fn new() -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(env!("CARGO_PKG_NAME"))
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl WebhookSender for HttpWebhookSender {
+    async fn send(&self, delivery: &DueDelivery) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(&delivery.payload)?;
+        let timestamp = Utc::now().timestamp();
+        self.client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &body))
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(EVENT_HEADER, &delivery.event)
+            .header(DELIVERY_HEADER, delivery.id)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+// attempts 回失敗した配送を次に送る時刻. 諦める場合は None
+pub fn retry_at(attempts: i32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+    let backoff = BASE_BACKOFF_SECS << (attempts - 1).clamp(0, MAX_ATTEMPTS);
+    Some(now + Duration::seconds(backoff))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_sign_payload() {
+        let body = br#"{"event":"todo.created"}"#;
+        assert_eq!(
+            sign("secret", 1672531200, body),
+            "sha256=203ac634e2a899160182e225e283008767a9acb1fb646bec79f1268078ff0e58"
+        );
+        // 時刻が違えば署名も変わるので、古いリクエストを使い回せない
+        assert_ne!(
+            sign("secret", 1672531201, body),
+            sign("secret", 1672531200, body)
+        );
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let now = Utc::now();
+        assert_eq!(retry_at(1, now), Some(now + Duration::seconds(30)));
+        assert_eq!(retry_at(2, now), Some(now + Duration::seconds(60)));
+        assert_eq!(retry_at(3, now), Some(now + Duration::seconds(120)));
+        assert_eq!(
+            retry_at(MAX_ATTEMPTS - 1, now),
+            Some(now + Duration::seconds(30 * 64))
+        );
+        assert_eq!(retry_at(MAX_ATTEMPTS, now), None);
+    }
+}